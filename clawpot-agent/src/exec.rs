@@ -1,6 +1,8 @@
 use crate::proto::{ExecRequest, ExecResponse};
 use anyhow::Result;
+use std::process::{Output, Stdio};
 use std::time::Duration;
+use tokio::io::AsyncWriteExt;
 use tokio::process::Command;
 use tracing::{debug, warn};
 
@@ -20,7 +22,10 @@ pub async fn run_command(req: ExecRequest) -> Result<ExecResponse> {
         cmd.env(key, value);
     }
 
-    let output = tokio::time::timeout(EXEC_TIMEOUT, cmd.output()).await;
+    let output = match req.stdin {
+        Some(input) => tokio::time::timeout(EXEC_TIMEOUT, output_with_stdin(cmd, input)).await,
+        None => tokio::time::timeout(EXEC_TIMEOUT, cmd.output()).await,
+    };
 
     match output {
         Ok(Ok(output)) => Ok(ExecResponse {
@@ -50,3 +55,23 @@ pub async fn run_command(req: ExecRequest) -> Result<ExecResponse> {
         }
     }
 }
+
+/// Run `cmd` with `input` on its stdin, closing it once written.
+async fn output_with_stdin(mut cmd: Command, input: Vec<u8>) -> std::io::Result<Output> {
+    let mut child = cmd
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let stdin = child.stdin.take();
+    // Written while output is collected, so a command that fills its output
+    // pipe before reading all of its input can't deadlock
+    let write = async move {
+        if let Some(mut stdin) = stdin {
+            // A command that exits without reading its input is not an error
+            let _ = stdin.write_all(&input).await;
+        }
+    };
+    let ((), output) = tokio::join!(write, child.wait_with_output());
+    output
+}
@@ -1,20 +1,139 @@
 use crate::proto::{exec_stream_input, exec_stream_output, ExecStreamInput, ExecStreamOutput};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::process::Command;
-use tokio::sync::mpsc;
+use tokio::sync::{mpsc, Notify};
+use tokio::time::Instant;
+use tokio_stream::{Stream, StreamExt};
 use tonic::Status;
-use tracing::{debug, error, info};
+use tracing::{debug, error, info, warn};
 
 const CHUNK_SIZE: usize = 4096;
 
+/// Caps the stdout and stderr bytes sent over one session.
+struct OutputLimit {
+    max: Option<u64>,
+    sent: AtomicU64,
+    /// Notified once the cap is reached
+    reached: Notify,
+}
+
+impl OutputLimit {
+    fn new(max: Option<u64>) -> Self {
+        Self {
+            max,
+            sent: AtomicU64::new(0),
+            reached: Notify::new(),
+        }
+    }
+
+    /// How much of an `n` byte chunk may still be sent. Anything less than
+    /// `n` means the cap has been reached.
+    fn take(&self, n: usize) -> usize {
+        let Some(max) = self.max else {
+            return n;
+        };
+        let before = self.sent.fetch_add(n as u64, Ordering::SeqCst);
+        let allowed = max.saturating_sub(before).min(n as u64) as usize;
+        if allowed < n {
+            self.reached.notify_one();
+        }
+        allowed
+    }
+}
+
+/// Send `reader`'s output to the client in chunks, stopping at EOF, when the
+/// client goes away, or when `limit` is reached.
+async fn forward_output(
+    mut reader: impl AsyncRead + Unpin,
+    tx: mpsc::Sender<Result<ExecStreamOutput, Status>>,
+    limit: Arc<OutputLimit>,
+    wrap: fn(Vec<u8>) -> exec_stream_output::Output,
+) {
+    let mut buf = vec![0u8; CHUNK_SIZE];
+    loop {
+        let n = match reader.read(&mut buf).await {
+            Ok(n) if n > 0 => n,
+            Ok(_) | Err(_) => break,
+        };
+        let allowed = limit.take(n);
+        if allowed > 0 {
+            let msg = ExecStreamOutput {
+                output: Some(wrap(buf[..allowed].to_vec())),
+            };
+            if tx.send(Ok(msg)).await.is_err() {
+                break;
+            }
+        }
+        if allowed < n {
+            break;
+        }
+    }
+}
+
+/// Write the client's stdin frames to the command until the client closes
+/// stdin or the stream ends. A failed write is reported to the client once
+/// as `StdinError`; stdin sent after that is discarded.
+async fn forward_stdin<W: AsyncWrite + Unpin>(
+    mut input: impl Stream<Item = Result<ExecStreamInput, Status>> + Unpin,
+    mut stdin: Option<W>,
+    tx: mpsc::Sender<Result<ExecStreamOutput, Status>>,
+) {
+    while let Some(Ok(msg)) = input.next().await {
+        match msg.input {
+            Some(exec_stream_input::Input::StdinData(data)) => {
+                let Some(writer) = stdin.as_mut() else {
+                    continue;
+                };
+                if let Err(e) = writer.write_all(&data).await {
+                    let reason = if e.kind() == std::io::ErrorKind::BrokenPipe {
+                        "the command closed its stdin".to_string()
+                    } else {
+                        format!("failed to write to stdin: {e}")
+                    };
+                    debug!("Stream exec stdin: {}", reason);
+                    stdin = None;
+                    let msg = ExecStreamOutput {
+                        output: Some(exec_stream_output::Output::StdinError(reason)),
+                    };
+                    if tx.send(Ok(msg)).await.is_err() {
+                        break;
+                    }
+                }
+            }
+            Some(exec_stream_input::Input::CloseStdin(true)) => {
+                // Drop stdin to signal EOF
+                break;
+            }
+            _ => break,
+        }
+    }
+}
+
+/// Resolves at `deadline`, or never if there is none.
+async fn sleep_until(deadline: Option<Instant>) {
+    match deadline {
+        Some(deadline) => tokio::time::sleep_until(deadline).await,
+        None => std::future::pending().await,
+    }
+}
+
+/// Run the command from the first message of `input_stream`, forwarding the
+/// rest as its stdin and its output to `tx`.
+///
+/// All output frames share `tx` and every sender is finished before the
+/// exit code is sent, so `ExitCode` is always the last frame and the client
+/// has all of the output once it sees it.
 #[allow(clippy::too_many_lines)]
 pub async fn run_stream(
-    mut input_stream: tonic::Streaming<ExecStreamInput>,
+    mut input_stream: impl Stream<Item = Result<ExecStreamInput, Status>> + Send + Unpin + 'static,
     tx: mpsc::Sender<Result<ExecStreamOutput, Status>>,
 ) {
     // First message must be a start command
-    let start_req = match input_stream.message().await {
-        Ok(Some(msg)) => {
+    let start_req = match input_stream.next().await {
+        Some(Ok(msg)) => {
             if let Some(exec_stream_input::Input::Start(req)) = msg.input {
                 req
             } else {
@@ -26,8 +145,8 @@ pub async fn run_stream(
                 return;
             }
         }
-        Ok(None) => return,
-        Err(e) => {
+        None => return,
+        Some(Err(e)) => {
             let _ = tx.send(Err(e)).await;
             return;
         }
@@ -35,6 +154,19 @@ pub async fn run_stream(
 
     debug!("Stream exec: {} {:?}", start_req.command, start_req.args);
 
+    let max_duration = start_req
+        .max_duration_secs
+        .filter(|&secs| secs > 0)
+        .map(|secs| Duration::from_secs(secs.into()));
+    let deadline = max_duration.map(|limit| Instant::now() + limit);
+    let duration_exceeded = || {
+        format!(
+            "session exceeded {} seconds",
+            max_duration.unwrap_or_default().as_secs()
+        )
+    };
+    let limit = Arc::new(OutputLimit::new(start_req.max_output_bytes));
+
     // Spawn process
     let mut cmd = Command::new(&start_req.command);
     cmd.args(&start_req.args)
@@ -62,86 +194,78 @@ pub async fn run_stream(
         }
     };
 
-    let mut child_stdin = child.stdin.take();
-    let mut child_stdout = child.stdout.take().unwrap();
-    let mut child_stderr = child.stderr.take().unwrap();
+    let child_stdin = child.stdin.take();
+    let child_stdout = child.stdout.take().unwrap();
+    let child_stderr = child.stderr.take().unwrap();
 
     // Task: forward client stdin to process stdin
-    let stdin_handle = tokio::spawn(async move {
-        while let Ok(Some(msg)) = input_stream.message().await {
-            match msg.input {
-                Some(exec_stream_input::Input::StdinData(data)) => {
-                    if let Some(ref mut stdin) = child_stdin {
-                        if stdin.write_all(&data).await.is_err() {
-                            break;
-                        }
-                    }
-                }
-                Some(exec_stream_input::Input::CloseStdin(true)) => {
-                    // Drop stdin to signal EOF
-                    child_stdin.take();
-                    break;
-                }
-                _ => break,
-            }
-        }
-        // Drop stdin on stream end
-        drop(child_stdin);
-    });
-
-    // Task: read stdout and send to client
-    let tx_stdout = tx.clone();
-    let stdout_handle = tokio::spawn(async move {
-        let mut buf = vec![0u8; CHUNK_SIZE];
-        loop {
-            match child_stdout.read(&mut buf).await {
-                Ok(n) if n > 0 => {
-                    let msg = ExecStreamOutput {
-                        output: Some(exec_stream_output::Output::StdoutData(buf[..n].to_vec())),
-                    };
-                    if tx_stdout.send(Ok(msg)).await.is_err() {
-                        break;
-                    }
-                }
-                Ok(_) | Err(_) => break,
-            }
-        }
-    });
-
-    // Task: read stderr and send to client
-    let tx_stderr = tx.clone();
-    let stderr_handle = tokio::spawn(async move {
-        let mut buf = vec![0u8; CHUNK_SIZE];
-        loop {
-            match child_stderr.read(&mut buf).await {
-                Ok(n) if n > 0 => {
-                    let msg = ExecStreamOutput {
-                        output: Some(exec_stream_output::Output::StderrData(buf[..n].to_vec())),
-                    };
-                    if tx_stderr.send(Ok(msg)).await.is_err() {
-                        break;
-                    }
-                }
-                Ok(_) | Err(_) => break,
-            }
-        }
-    });
+    let stdin_handle = tokio::spawn(forward_stdin(input_stream, child_stdin, tx.clone()));
 
-    // Wait for stdout/stderr to finish
-    let _ = stdout_handle.await;
-    let _ = stderr_handle.await;
+    // Tasks: read stdout and stderr and send to client
+    let stdout_handle = tokio::spawn(forward_output(
+        child_stdout,
+        tx.clone(),
+        limit.clone(),
+        exec_stream_output::Output::StdoutData,
+    ));
+    let stderr_handle = tokio::spawn(forward_output(
+        child_stderr,
+        tx.clone(),
+        limit.clone(),
+        exec_stream_output::Output::StderrData,
+    ));
+    let stdout_abort = stdout_handle.abort_handle();
+    let stderr_abort = stderr_handle.abort_handle();
+    let readers = async { tokio::join!(stdout_handle, stderr_handle) };
+    tokio::pin!(readers);
 
-    // Wait for process to exit
-    let exit_code = match child.wait().await {
-        Ok(status) => status.code().unwrap_or(-1),
-        Err(e) => {
-            error!("Failed to wait for process: {}", e);
-            -1
-        }
+    // Wait for stdout/stderr to finish, unless a session limit is hit first.
+    // The cap is checked first, as the reader that hit it also finishes
+    let mut stopped = tokio::select! {
+        biased;
+        () = limit.reached.notified() => Some(format!(
+            "output exceeded {} bytes",
+            limit.max.unwrap_or_default()
+        )),
+        _ = &mut readers => None,
+        () = sleep_until(deadline) => Some(duration_exceeded()),
     };
 
-    // Cancel stdin forwarder
+    let mut exit_code = -1;
+    if stopped.is_some() {
+        stdout_abort.abort();
+        stderr_abort.abort();
+        // Wait for the readers to stop so none of their output follows ours
+        let _ = readers.await;
+    } else {
+        // The readers finish once the command closes its output, which need
+        // not be when it exits, so the deadline also bounds this wait
+        tokio::select! {
+            status = child.wait() => match status {
+                Ok(status) => exit_code = status.code().unwrap_or(-1),
+                Err(e) => error!("Failed to wait for process: {}", e),
+            },
+            () = sleep_until(deadline) => stopped = Some(duration_exceeded()),
+        }
+    }
+
+    if let Some(reason) = stopped {
+        warn!("Stream exec killed: {}", reason);
+        if let Err(e) = child.kill().await {
+            error!("Failed to kill process: {}", e);
+        }
+        let _ = tx
+            .send(Ok(ExecStreamOutput {
+                output: Some(exec_stream_output::Output::StderrData(
+                    format!("Command killed: {reason}\n").into_bytes(),
+                )),
+            }))
+            .await;
+    }
+
+    // Cancel stdin forwarder, which may otherwise still report a stdin error
     stdin_handle.abort();
+    let _ = stdin_handle.await;
 
     info!("Stream exec finished with exit code {}", exit_code);
 
@@ -152,3 +276,162 @@ pub async fn run_stream(
         }))
         .await;
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stdin_frame(data: &[u8]) -> Result<ExecStreamInput, Status> {
+        Ok(ExecStreamInput {
+            input: Some(exec_stream_input::Input::StdinData(data.to_vec())),
+        })
+    }
+
+    #[tokio::test]
+    async fn test_closed_stdin_is_reported() {
+        // `true` exits without reading, closing its end of the pipe
+        let mut child = Command::new("true")
+            .stdin(std::process::Stdio::piped())
+            .spawn()
+            .unwrap();
+        let stdin = child.stdin.take();
+        child.wait().await.unwrap();
+
+        let input = tokio_stream::iter([stdin_frame(b"first\n"), stdin_frame(b"second\n")]);
+        let (tx, mut rx) = mpsc::channel(4);
+        forward_stdin(input, stdin, tx).await;
+
+        let msg = rx.recv().await.unwrap().unwrap();
+        assert!(matches!(
+            msg.output,
+            Some(exec_stream_output::Output::StdinError(_))
+        ));
+        // Reported once, not again for the second frame
+        assert!(rx.recv().await.is_none());
+    }
+
+    /// Run `sh -c script` through `run_stream`, returning its stdout, its
+    /// stderr and the exit code.
+    async fn run_script(
+        script: &str,
+        limits: crate::proto::ExecRequest,
+    ) -> (Vec<u8>, Vec<u8>, Option<i32>) {
+        run_script_with_input(script, limits, tokio_stream::empty()).await
+    }
+
+    /// Like [`run_script`], with `stdin` sent after the start frame.
+    async fn run_script_with_input(
+        script: &str,
+        limits: crate::proto::ExecRequest,
+        stdin: impl Stream<Item = Result<ExecStreamInput, Status>> + Send + Unpin + 'static,
+    ) -> (Vec<u8>, Vec<u8>, Option<i32>) {
+        let start = ExecStreamInput {
+            input: Some(exec_stream_input::Input::Start(crate::proto::ExecRequest {
+                command: "sh".to_string(),
+                args: vec!["-c".to_string(), script.to_string()],
+                ..limits
+            })),
+        };
+        let (tx, mut rx) = mpsc::channel(16);
+        tokio::spawn(run_stream(tokio_stream::iter([Ok(start)]).chain(stdin), tx));
+
+        let (mut stdout, mut stderr, mut exit_code) = (Vec::new(), Vec::new(), None);
+        while let Some(msg) = rx.recv().await {
+            assert_eq!(exit_code, None, "output after the exit code");
+            match msg.unwrap().output {
+                Some(exec_stream_output::Output::StdoutData(data)) => stdout.extend(data),
+                Some(exec_stream_output::Output::StderrData(data)) => stderr.extend(data),
+                Some(exec_stream_output::Output::ExitCode(code)) => exit_code = Some(code),
+                _ => {}
+            }
+        }
+        (stdout, stderr, exit_code)
+    }
+
+    #[tokio::test]
+    async fn test_stdin_is_forwarded() {
+        let close = Ok(ExecStreamInput {
+            input: Some(exec_stream_input::Input::CloseStdin(true)),
+        });
+        // The stream stays open, so only CloseStdin can end `cat`'s input
+        let stdin = tokio_stream::iter([stdin_frame(b"hello "), stdin_frame(b"world\n"), close])
+            .chain(tokio_stream::pending());
+        let (stdout, _, exit_code) =
+            run_script_with_input("cat; echo done", Default::default(), stdin).await;
+        assert_eq!(stdout, b"hello world\ndone\n");
+        assert_eq!(exit_code, Some(0));
+    }
+
+    #[tokio::test]
+    async fn test_exit_code_follows_all_output() {
+        let (stdout, stderr, exit_code) = run_script(
+            "head -c 200000 /dev/zero; head -c 100000 /dev/zero >&2; exit 7",
+            Default::default(),
+        )
+        .await;
+        assert_eq!(
+            (stdout.len(), stderr.len(), exit_code),
+            (200_000, 100_000, Some(7))
+        );
+    }
+
+    #[tokio::test]
+    async fn test_output_limit_kills_command() {
+        let (stdout, stderr, exit_code) = run_script(
+            "head -c 100000 /dev/zero; sleep 30",
+            crate::proto::ExecRequest {
+                max_output_bytes: Some(1000),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(stdout.len(), 1000);
+        assert_eq!(stderr, b"Command killed: output exceeded 1000 bytes\n");
+        assert_eq!(exit_code, Some(-1));
+    }
+
+    #[tokio::test]
+    async fn test_duration_limit_kills_command() {
+        let (_, stderr, exit_code) = run_script(
+            "sleep 30",
+            crate::proto::ExecRequest {
+                max_duration_secs: Some(1),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert_eq!(stderr, b"Command killed: session exceeded 1 seconds\n");
+        assert_eq!(exit_code, Some(-1));
+    }
+
+    #[tokio::test]
+    async fn test_duration_limit_covers_command_without_output() {
+        // Closing stdout and stderr ends the readers long before the exit
+        let started = Instant::now();
+        let (_, stderr, exit_code) = run_script(
+            "exec >/dev/null 2>&1; exec sleep 30",
+            crate::proto::ExecRequest {
+                max_duration_secs: Some(1),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert!(started.elapsed() < Duration::from_secs(10));
+        assert_eq!(stderr, b"Command killed: session exceeded 1 seconds\n");
+        assert_eq!(exit_code, Some(-1));
+    }
+
+    #[tokio::test]
+    async fn test_zero_duration_is_no_limit() {
+        let (_, stderr, exit_code) = run_script(
+            "sleep 1; exit 3",
+            crate::proto::ExecRequest {
+                max_duration_secs: Some(0),
+                ..Default::default()
+            },
+        )
+        .await;
+        assert!(stderr.is_empty());
+        assert_eq!(exit_code, Some(3));
+    }
+}
@@ -0,0 +1,38 @@
+use anyhow::{bail, Result};
+use clawpot_common::proto::{
+    clawpot_service_client::ClawpotServiceClient, exec_vm_stream_output, AttachExecRequest,
+};
+use std::io::Write;
+use tonic::transport::Channel;
+
+/// Print a running exec's output until it exits, then exit with its code
+pub async fn execute(client: &mut ClawpotServiceClient<Channel>, session_id: String) -> Result<()> {
+    let mut output = client
+        .attach_exec(AttachExecRequest { session_id })
+        .await?
+        .into_inner();
+
+    let mut exit_code = None;
+    while let Some(msg) = output.message().await? {
+        match msg.output {
+            Some(exec_vm_stream_output::Output::StdoutData(data)) => {
+                let mut stdout = std::io::stdout();
+                stdout.write_all(&data)?;
+                stdout.flush()?;
+            }
+            Some(exec_vm_stream_output::Output::StderrData(data)) => {
+                std::io::stderr().write_all(&data)?;
+            }
+            Some(exec_vm_stream_output::Output::ExitCode(code)) => exit_code = Some(code),
+            Some(exec_vm_stream_output::Output::StdinError(reason)) => {
+                eprintln!("clawpot: the command's stdin is no longer forwarded: {reason}");
+            }
+            Some(exec_vm_stream_output::Output::SessionId(_)) | None => {}
+        }
+    }
+
+    let Some(exit_code) = exit_code else {
+        bail!("Exec stream ended without an exit code");
+    };
+    std::process::exit(exit_code);
+}
@@ -0,0 +1,257 @@
+use super::selftest::{create_vm, delete_vm, http_request, wait_for_agent};
+use anyhow::{bail, Result};
+use clawpot_common::proto::clawpot_service_client::ClawpotServiceClient;
+use serde::Serialize;
+use std::time::{Duration, Instant};
+use tabled::{Table, Tabled};
+use tokio::task::JoinSet;
+use tonic::transport::Channel;
+
+/// Latency distribution for a set of timed operations, in milliseconds.
+#[derive(Debug, Serialize)]
+struct LatencyStats {
+    count: usize,
+    min_ms: f64,
+    mean_ms: f64,
+    p50_ms: f64,
+    p90_ms: f64,
+    p99_ms: f64,
+    max_ms: f64,
+}
+
+impl LatencyStats {
+    #[allow(clippy::cast_precision_loss)]
+    fn from_samples(samples: &[Duration]) -> Option<Self> {
+        if samples.is_empty() {
+            return None;
+        }
+
+        let mut ms: Vec<f64> = samples.iter().map(|d| d.as_secs_f64() * 1000.0).collect();
+        ms.sort_by(f64::total_cmp);
+
+        Some(Self {
+            count: ms.len(),
+            min_ms: ms[0],
+            mean_ms: ms.iter().sum::<f64>() / ms.len() as f64,
+            p50_ms: percentile(&ms, 50.0),
+            p90_ms: percentile(&ms, 90.0),
+            p99_ms: percentile(&ms, 99.0),
+            max_ms: ms[ms.len() - 1],
+        })
+    }
+}
+
+/// Nearest-rank percentile of an already sorted, non-empty slice.
+#[allow(clippy::cast_precision_loss)]
+fn percentile(sorted: &[f64], pct: f64) -> f64 {
+    let rank = (pct / 100.0 * sorted.len() as f64).ceil() as usize;
+    sorted[rank.clamp(1, sorted.len()) - 1]
+}
+
+#[derive(Debug, Serialize)]
+struct BenchReport {
+    vms: usize,
+    requests_per_vm: usize,
+    url: String,
+    failed_boots: usize,
+    failed_requests: usize,
+    boot: Option<LatencyStats>,
+    request: Option<LatencyStats>,
+    request_wall_secs: f64,
+    throughput_rps: f64,
+}
+
+#[derive(Tabled)]
+struct StatsRow {
+    #[tabled(rename = "Metric")]
+    metric: &'static str,
+    #[tabled(rename = "Count")]
+    count: usize,
+    #[tabled(rename = "Min (ms)")]
+    min: String,
+    #[tabled(rename = "Mean (ms)")]
+    mean: String,
+    #[tabled(rename = "p50 (ms)")]
+    p50: String,
+    #[tabled(rename = "p90 (ms)")]
+    p90: String,
+    #[tabled(rename = "p99 (ms)")]
+    p99: String,
+    #[tabled(rename = "Max (ms)")]
+    max: String,
+}
+
+impl StatsRow {
+    fn new(metric: &'static str, stats: &LatencyStats) -> Self {
+        Self {
+            metric,
+            count: stats.count,
+            min: format!("{:.1}", stats.min_ms),
+            mean: format!("{:.1}", stats.mean_ms),
+            p50: format!("{:.1}", stats.p50_ms),
+            p90: format!("{:.1}", stats.p90_ms),
+            p99: format!("{:.1}", stats.p99_ms),
+            max: format!("{:.1}", stats.max_ms),
+        }
+    }
+}
+
+#[allow(clippy::cast_precision_loss)]
+pub async fn execute(
+    client: &mut ClawpotServiceClient<Channel>,
+    vms: usize,
+    requests: usize,
+    url: &str,
+    json: bool,
+) -> Result<()> {
+    if vms == 0 {
+        bail!("--vms must be at least 1");
+    }
+
+    // Progress goes to stderr so --json output stays machine-readable
+    eprintln!("Booting {vms} VM(s)...");
+
+    // Boot sequentially so each boot time is measured without contention
+    let mut vm_ids = Vec::new();
+    let mut boot_times = Vec::new();
+    let mut failed_boots = 0;
+    for i in 1..=vms {
+        let start = Instant::now();
+        match create_vm(client).await {
+            Ok(vm_id) => {
+                let ready = wait_for_agent(client, &vm_id).await;
+                vm_ids.push(vm_id);
+                match ready {
+                    Ok(_) => {
+                        boot_times.push(start.elapsed());
+                        eprintln!("  VM {i}/{vms} ready in {} ms", start.elapsed().as_millis());
+                    }
+                    Err(e) => {
+                        failed_boots += 1;
+                        eprintln!("  VM {i}/{vms} agent not ready: {e:#}");
+                    }
+                }
+            }
+            Err(e) => {
+                failed_boots += 1;
+                eprintln!("  VM {i}/{vms} failed to create: {e:#}");
+            }
+        }
+    }
+
+    // Run each VM's requests concurrently with the other VMs
+    eprintln!("Running {requests} request(s) per VM against {url}...");
+    let request_start = Instant::now();
+    let mut tasks = JoinSet::new();
+    for vm_id in vm_ids.clone() {
+        let mut client = client.clone();
+        let url = url.to_string();
+        tasks.spawn(async move {
+            let mut latencies = Vec::new();
+            let mut failures = 0;
+            for _ in 0..requests {
+                let start = Instant::now();
+                match http_request(&mut client, &vm_id, &url).await {
+                    Ok(_) => latencies.push(start.elapsed()),
+                    Err(_) => failures += 1,
+                }
+            }
+            (latencies, failures)
+        });
+    }
+
+    let mut request_times = Vec::new();
+    let mut failed_requests = 0;
+    while let Some(result) = tasks.join_next().await {
+        let (latencies, failures) = result?;
+        request_times.extend(latencies);
+        failed_requests += failures;
+    }
+    let request_wall = request_start.elapsed();
+
+    // Always clean up, even if some steps failed
+    eprintln!("Deleting {} VM(s)...", vm_ids.len());
+    for vm_id in &vm_ids {
+        if let Err(e) = delete_vm(client, vm_id).await {
+            eprintln!("  Failed to delete VM {vm_id}: {e:#}");
+        }
+    }
+
+    let report = BenchReport {
+        vms,
+        requests_per_vm: requests,
+        url: url.to_string(),
+        failed_boots,
+        failed_requests,
+        boot: LatencyStats::from_samples(&boot_times),
+        request: LatencyStats::from_samples(&request_times),
+        request_wall_secs: request_wall.as_secs_f64(),
+        throughput_rps: if request_wall.is_zero() {
+            0.0
+        } else {
+            request_times.len() as f64 / request_wall.as_secs_f64()
+        },
+    };
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        print_summary(&report);
+    }
+
+    Ok(())
+}
+
+fn print_summary(report: &BenchReport) {
+    let rows: Vec<StatsRow> = [("VM boot", &report.boot), ("Request", &report.request)]
+        .into_iter()
+        .filter_map(|(metric, stats)| stats.as_ref().map(|s| StatsRow::new(metric, s)))
+        .collect();
+
+    println!();
+    if rows.is_empty() {
+        println!("No successful measurements");
+    } else {
+        println!("{}", Table::new(rows));
+    }
+
+    println!(
+        "\nThroughput: {:.2} req/s ({} requests in {:.1}s)",
+        report.throughput_rps,
+        report.request.as_ref().map_or(0, |s| s.count),
+        report.request_wall_secs
+    );
+    if report.failed_boots > 0 || report.failed_requests > 0 {
+        println!(
+            "Failures:   {} boot(s), {} request(s)",
+            report.failed_boots, report.failed_requests
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_percentile_nearest_rank() {
+        let sorted: Vec<f64> = (1..=100).map(f64::from).collect();
+        assert!((percentile(&sorted, 50.0) - 50.0).abs() < f64::EPSILON);
+        assert!((percentile(&sorted, 90.0) - 90.0).abs() < f64::EPSILON);
+        assert!((percentile(&sorted, 99.0) - 99.0).abs() < f64::EPSILON);
+        assert!((percentile(&[7.0], 99.0) - 7.0).abs() < f64::EPSILON);
+    }
+
+    #[test]
+    fn test_latency_stats() {
+        assert!(LatencyStats::from_samples(&[]).is_none());
+
+        let samples: Vec<Duration> = [30, 10, 20].map(Duration::from_millis).to_vec();
+        let stats = LatencyStats::from_samples(&samples).unwrap();
+        assert_eq!(stats.count, 3);
+        assert!((stats.min_ms - 10.0).abs() < 1e-9);
+        assert!((stats.max_ms - 30.0).abs() < 1e-9);
+        assert!((stats.mean_ms - 20.0).abs() < 1e-9);
+        assert!((stats.p50_ms - 20.0).abs() < 1e-9);
+    }
+}
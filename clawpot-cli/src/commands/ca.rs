@@ -0,0 +1,126 @@
+use anyhow::{bail, Context, Result};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// Bundle most TLS clients in the guest read (written by scripts/setup-rootfs.sh)
+pub const DEFAULT_TRUST_STORE: &str = "/etc/ssl/certs/ca-certificates.crt";
+
+/// Default CA certificate path based on CLAWPOT_ROOT. This is the file the
+/// server's `CertificateAuthority` loads and serves as `ca_cert_pem()`.
+fn default_ca_path() -> PathBuf {
+    let root = std::env::var("CLAWPOT_ROOT").unwrap_or_else(|_| "/workspaces/clawpot".to_string());
+    Path::new(&root).join("ca/ca.crt")
+}
+
+pub fn execute_check(rootfs: &str, trust_store: &str, ca: Option<&str>) -> Result<()> {
+    let ca_path = ca.map_or_else(default_ca_path, PathBuf::from);
+    let ca_pem = std::fs::read_to_string(&ca_path)
+        .with_context(|| format!("Failed to read CA certificate {}", ca_path.display()))?;
+    let Some(ca_cert) = pem_blocks(&ca_pem).into_iter().next() else {
+        bail!("No PEM certificate found in {}", ca_path.display());
+    };
+
+    let bundle = read_rootfs_file(Path::new(rootfs), trust_store)?;
+
+    println!("CA certificate: {}", ca_path.display());
+    println!("Trust store:    {rootfs}:{trust_store}");
+    if !pem_blocks(&bundle).contains(&ca_cert) {
+        println!("\n✗ FAIL: the clawpot CA is not in the trust store");
+        println!("  HTTPS interception will fail inside VMs booted from this image.");
+        println!("  Re-run scripts/setup-rootfs.sh to inject the current CA.");
+        bail!("CA check failed");
+    }
+
+    println!("\n✓ PASS: the clawpot CA is trusted by this rootfs");
+    Ok(())
+}
+
+/// Read `path` from inside `rootfs`, which is either a directory (an
+/// extracted or mounted root filesystem) or an ext4 image. Images are read
+/// with `debugfs`, so no mount or root privileges are needed.
+fn read_rootfs_file(rootfs: &Path, path: &str) -> Result<String> {
+    if rootfs.is_dir() {
+        let full = rootfs.join(path.trim_start_matches('/'));
+        return std::fs::read_to_string(&full)
+            .with_context(|| format!("Failed to read {}", full.display()));
+    }
+    if !rootfs.exists() {
+        bail!("Rootfs {} does not exist", rootfs.display());
+    }
+
+    let output = Command::new("debugfs")
+        .arg("-R")
+        .arg(format!("cat {path}"))
+        .arg(rootfs)
+        .output()
+        .context("Failed to run debugfs (install e2fsprogs, or pass a mounted rootfs directory)")?;
+    if !output.status.success() {
+        bail!(
+            "debugfs failed on {}: {}",
+            rootfs.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    // debugfs reports a missing file on stderr but still exits successfully
+    if output.stdout.is_empty() {
+        bail!(
+            "{path} not found in {} ({})",
+            rootfs.display(),
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Base64 bodies of the PEM certificates in `text`, with whitespace removed
+/// so line wrapping and line endings don't affect comparison.
+fn pem_blocks(text: &str) -> Vec<String> {
+    const BEGIN: &str = "-----BEGIN CERTIFICATE-----";
+    const END: &str = "-----END CERTIFICATE-----";
+
+    let mut blocks = Vec::new();
+    let mut rest = text;
+    while let Some(start) = rest.find(BEGIN) {
+        let body = &rest[start + BEGIN.len()..];
+        let Some(end) = body.find(END) else {
+            break;
+        };
+        blocks.push(body[..end].split_whitespace().collect());
+        rest = &body[end + END.len()..];
+    }
+    blocks
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pem_blocks() {
+        let bundle =
+            "# Some CA\n-----BEGIN CERTIFICATE-----\nAAAA\nBBBB\n-----END CERTIFICATE-----\n\
+                      -----BEGIN CERTIFICATE-----\r\nCCCC\r\n-----END CERTIFICATE-----\r\n";
+        assert_eq!(pem_blocks(bundle), vec!["AAAABBBB", "CCCC"]);
+
+        let ca = "-----BEGIN CERTIFICATE-----\nCCCC\n-----END CERTIFICATE-----\n";
+        assert!(pem_blocks(bundle).contains(&pem_blocks(ca)[0]));
+
+        assert!(pem_blocks("-----BEGIN CERTIFICATE-----\nDDDD\n").is_empty());
+        assert!(pem_blocks("").is_empty());
+    }
+
+    #[test]
+    fn test_read_rootfs_dir() {
+        let dir = std::env::temp_dir().join(format!("clawpot-rootfs-{}", std::process::id()));
+        std::fs::create_dir_all(dir.join("etc/ssl/certs")).unwrap();
+        std::fs::write(dir.join("etc/ssl/certs/ca-certificates.crt"), "bundle").unwrap();
+
+        assert_eq!(
+            read_rootfs_file(&dir, DEFAULT_TRUST_STORE).unwrap(),
+            "bundle"
+        );
+        assert!(read_rootfs_file(&dir, "/etc/missing.crt").is_err());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,15 @@
+use anyhow::Result;
+use clawpot_common::proto::{clawpot_service_client::ClawpotServiceClient, StreamConsoleRequest};
+use tonic::transport::Channel;
+
+/// Follow the console until the VM stops or the user interrupts
+pub async fn execute(client: &mut ClawpotServiceClient<Channel>, vm_id: String) -> Result<()> {
+    let mut stream = client
+        .stream_console(StreamConsoleRequest { vm_id })
+        .await?
+        .into_inner();
+    while let Some(msg) = stream.message().await? {
+        println!("{}", msg.line);
+    }
+    Ok(())
+}
@@ -1,15 +1,63 @@
-use anyhow::Result;
-use clawpot_common::proto::{clawpot_service_client::ClawpotServiceClient, CreateVmRequest};
+use anyhow::{bail, Result};
+use clawpot_common::proto::{
+    clawpot_service_client::ClawpotServiceClient, CreateVmRequest, DataDrive,
+};
 use tonic::transport::Channel;
 
+/// Parse a `--drive PATH[:ro]` argument.
+fn parse_drive(raw: &str) -> Result<DataDrive> {
+    let (host_path, read_only) = match raw.strip_suffix(":ro") {
+        Some(path) => (path, true),
+        None => (raw.strip_suffix(":rw").unwrap_or(raw), false),
+    };
+    if host_path.is_empty() {
+        bail!("Invalid --drive '{raw}': expected PATH[:ro]");
+    }
+    Ok(DataDrive {
+        host_path: host_path.to_string(),
+        read_only,
+    })
+}
+
+/// Parse a `--label KEY=VALUE` argument.
+pub fn parse_label(raw: &str) -> Result<(String, String), String> {
+    match raw.split_once('=') {
+        Some((key, value)) if !key.trim().is_empty() => {
+            Ok((key.trim().to_string(), value.trim().to_string()))
+        }
+        _ => Err(format!("expected <key>=<value>, got '{raw}'")),
+    }
+}
+
 pub async fn execute(
     client: &mut ClawpotServiceClient<Channel>,
     vcpus: Option<u32>,
     memory: Option<u32>,
+    rx_limit: Option<u64>,
+    tx_limit: Option<u64>,
+    name: Option<String>,
+    ip: Option<String>,
+    drives: &[String],
+    balloon: Option<u32>,
+    labels: Vec<(String, String)>,
+    from_pool: bool,
 ) -> Result<()> {
+    let data_drives = drives
+        .iter()
+        .map(|d| parse_drive(d))
+        .collect::<Result<Vec<_>>>()?;
+
     let request = CreateVmRequest {
         vcpu_count: vcpus,
         mem_size_mib: memory,
+        rx_bytes_per_sec: rx_limit,
+        tx_bytes_per_sec: tx_limit,
+        name: name.clone(),
+        ip_address: ip,
+        data_drives,
+        balloon_mib: balloon,
+        labels: labels.into_iter().collect(),
+        from_pool: from_pool.then_some(true),
     };
 
     println!("Creating VM...");
@@ -19,8 +67,44 @@ pub async fn execute(
 
     println!("\n✓ VM created successfully!");
     println!("  VM ID:      {}", vm_info.vm_id);
+    if let Some(name) = name {
+        println!("  Name:       {name}");
+    }
     println!("  IP Address: {}", vm_info.ip_address);
     println!("  Socket:     {}", vm_info.socket_path);
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_drive() {
+        let drive = parse_drive("/data/set.ext4:ro").unwrap();
+        assert_eq!(drive.host_path, "/data/set.ext4");
+        assert!(drive.read_only);
+
+        let drive = parse_drive("/data/scratch.ext4").unwrap();
+        assert_eq!(drive.host_path, "/data/scratch.ext4");
+        assert!(!drive.read_only);
+
+        assert!(!parse_drive("/data/scratch.ext4:rw").unwrap().read_only);
+        assert!(parse_drive(":ro").is_err());
+    }
+
+    #[test]
+    fn test_parse_label() {
+        assert_eq!(
+            parse_label("tier=frontend").unwrap(),
+            ("tier".to_string(), "frontend".to_string())
+        );
+        assert_eq!(
+            parse_label("empty=").unwrap(),
+            ("empty".to_string(), String::new())
+        );
+        assert!(parse_label("=value").is_err());
+        assert!(parse_label("novalue").is_err());
+    }
+}
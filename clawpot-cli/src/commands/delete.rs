@@ -18,5 +18,12 @@ pub async fn execute(client: &mut ClawpotServiceClient<Channel>, vm_id: String)
         println!("\n✗ Failed to delete VM");
     }
 
+    if !result.leaked.is_empty() {
+        println!("\nResources left behind:");
+        for resource in &result.leaked {
+            println!("  {resource}");
+        }
+    }
+
     Ok(())
 }
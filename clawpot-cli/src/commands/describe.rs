@@ -0,0 +1,81 @@
+use super::list::state_name;
+use super::logs::format_data_summary;
+use anyhow::Result;
+use clawpot_common::proto::{clawpot_service_client::ClawpotServiceClient, DescribeVmRequest};
+use tonic::transport::Channel;
+
+pub async fn execute(
+    client: &mut ClawpotServiceClient<Channel>,
+    vm_id: String,
+    events: u32,
+) -> Result<()> {
+    let response = client
+        .describe_vm(DescribeVmRequest {
+            vm_id,
+            event_limit: Some(events),
+        })
+        .await?
+        .into_inner();
+    let vm = response.vm.unwrap_or_default();
+
+    println!("VM ID:         {}", vm.vm_id);
+    if !vm.name.is_empty() {
+        println!("Name:          {}", vm.name);
+    }
+    println!("State:         {}", state_name(vm.state));
+    let created = chrono::DateTime::from_timestamp(vm.created_at, 0)
+        .map_or_else(|| vm.created_at.to_string(), |t| t.to_rfc3339());
+    println!("Created:       {created}");
+    if !response.last_activity.is_empty() {
+        println!("Last activity: {}", response.last_activity);
+    }
+    println!("vCPUs:         {}", vm.vcpu_count);
+    let balloon = if response.balloon { " (balloon)" } else { "" };
+    println!("Memory:        {} MiB{balloon}", vm.mem_size_mib);
+    println!("IP address:    {}", vm.ip_address);
+    println!("TAP device:    {}", response.tap_name);
+    println!("Socket:        {}", vm.socket_path);
+    println!(
+        "vsock:         {} (CID {})",
+        response.vsock_path, response.guest_cid
+    );
+    if response.kernel_path.is_empty() {
+        println!("Boot config:   none (restored from a snapshot)");
+    } else {
+        println!("Kernel:        {}", response.kernel_path);
+        println!("Rootfs:        {}", response.rootfs_path);
+        println!("Boot args:     {}", response.boot_args);
+    }
+    if !vm.labels.is_empty() {
+        println!("Labels:");
+        let mut labels: Vec<_> = vm.labels.iter().collect();
+        labels.sort();
+        for (key, value) in labels {
+            println!("  {key}={value}");
+        }
+    }
+
+    if response.recent_events.is_empty() {
+        println!("\nNo recorded events");
+        return Ok(());
+    }
+    println!("\nRecent events (newest first):");
+    for event in &response.recent_events {
+        let data = serde_json::from_str(&event.data).unwrap_or_default();
+        let duration = event
+            .duration_ms
+            .map_or_else(String::new, |d| format!(" ({d}ms)"));
+        let outcome = match event.success {
+            Some(true) => " OK",
+            Some(false) => " FAIL",
+            None => "",
+        };
+        println!(
+            "  {} {}{duration}{outcome} {}",
+            event.timestamp,
+            event.event_type,
+            format_data_summary(&event.event_type, &data)
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,18 @@
+use anyhow::Result;
+use clawpot_common::proto::{clawpot_service_client::ClawpotServiceClient, SetDrainRequest};
+use tonic::transport::Channel;
+
+pub async fn execute(client: &mut ClawpotServiceClient<Channel>, enabled: bool) -> Result<()> {
+    let response = client
+        .set_drain(SetDrainRequest { enabled })
+        .await?
+        .into_inner();
+
+    if enabled {
+        println!("✓ Draining: new VMs are rejected");
+        println!("  {} VM(s) still running", response.vm_count);
+    } else {
+        println!("✓ Accepting new VMs");
+    }
+    Ok(())
+}
@@ -1,18 +1,54 @@
 use anyhow::{bail, Result};
-use clawpot_common::proto::{clawpot_service_client::ClawpotServiceClient, ExecVmRequest};
+use clawpot_common::proto::{
+    clawpot_service_client::ClawpotServiceClient, exec_vm_stream_input, exec_vm_stream_output,
+    ExecVmRequest, ExecVmStreamInput, ExecVmStreamStart,
+};
 use std::collections::HashMap;
-use std::io::Write;
+use std::io::{IsTerminal, Write};
+use tokio::io::{AsyncRead, AsyncReadExt};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
 use tonic::transport::Channel;
 
+/// Size of the stdin chunks forwarded in interactive mode
+const STDIN_CHUNK_SIZE: usize = 8192;
+
+fn split_command(command: &[String]) -> Result<(String, Vec<String>)> {
+    match command.split_first() {
+        Some((first, rest)) => Ok((first.clone(), rest.to_vec())),
+        None => bail!("No command specified"),
+    }
+}
+
+/// Input for a unary exec: piped or redirected input is sent along unless
+/// `forward` is off; a terminal is never read.
+async fn read_stdin<R: AsyncRead + Unpin>(
+    mut input: R,
+    is_terminal: bool,
+    forward: bool,
+) -> Result<Option<Vec<u8>>> {
+    if !forward || is_terminal {
+        return Ok(None);
+    }
+    let mut buf = Vec::new();
+    input.read_to_end(&mut buf).await?;
+    Ok(Some(buf))
+}
+
 pub async fn execute(
     client: &mut ClawpotServiceClient<Channel>,
     vm_id: String,
     command: Vec<String>,
+    forward_stdin: bool,
 ) -> Result<()> {
-    let (cmd, args) = match command.split_first() {
-        Some((first, rest)) => (first.clone(), rest.to_vec()),
-        None => bail!("No command specified"),
-    };
+    let (cmd, args) = split_command(&command)?;
+
+    let stdin = read_stdin(
+        tokio::io::stdin(),
+        std::io::stdin().is_terminal(),
+        forward_stdin,
+    )
+    .await?;
 
     let request = ExecVmRequest {
         vm_id,
@@ -20,6 +56,7 @@ pub async fn execute(
         args,
         env: HashMap::new(),
         working_dir: String::new(),
+        stdin,
     };
 
     let response = client.exec_vm(request).await?.into_inner();
@@ -29,3 +66,99 @@ pub async fn execute(
 
     std::process::exit(response.exit_code);
 }
+
+/// Run a command over the streaming API, forwarding this terminal's stdin to
+/// it (unless `forward_stdin` is off) and printing output as it arrives.
+pub async fn execute_interactive(
+    client: &mut ClawpotServiceClient<Channel>,
+    vm_id: String,
+    command: Vec<String>,
+    forward_stdin: bool,
+) -> Result<()> {
+    let (cmd, args) = split_command(&command)?;
+
+    let (tx, rx) = mpsc::channel(16);
+    let start = ExecVmStreamStart {
+        vm_id,
+        command: cmd,
+        args,
+        ..Default::default()
+    };
+    tx.send(stream_input(exec_vm_stream_input::Input::Start(start)))
+        .await?;
+
+    tokio::spawn(async move {
+        if forward_stdin {
+            let mut stdin = tokio::io::stdin();
+            let mut buf = vec![0u8; STDIN_CHUNK_SIZE];
+            loop {
+                let input = match stdin.read(&mut buf).await {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => exec_vm_stream_input::Input::StdinData(buf[..n].to_vec()),
+                };
+                if tx.send(stream_input(input)).await.is_err() {
+                    return;
+                }
+            }
+        }
+        let _ = tx
+            .send(stream_input(exec_vm_stream_input::Input::CloseStdin(true)))
+            .await;
+    });
+
+    let mut output = client
+        .exec_vm_stream(ReceiverStream::new(rx))
+        .await?
+        .into_inner();
+
+    let mut exit_code = None;
+    while let Some(msg) = output.message().await? {
+        match msg.output {
+            Some(exec_vm_stream_output::Output::StdoutData(data)) => {
+                let mut stdout = std::io::stdout();
+                stdout.write_all(&data)?;
+                stdout.flush()?;
+            }
+            Some(exec_vm_stream_output::Output::StderrData(data)) => {
+                std::io::stderr().write_all(&data)?;
+            }
+            Some(exec_vm_stream_output::Output::ExitCode(code)) => exit_code = Some(code),
+            Some(exec_vm_stream_output::Output::StdinError(reason)) => {
+                eprintln!("clawpot: stdin is no longer forwarded: {reason}");
+            }
+            // Only worth mentioning to someone at a terminal
+            Some(exec_vm_stream_output::Output::SessionId(id)) => {
+                if std::io::stderr().is_terminal() {
+                    eprintln!("clawpot: others can watch with `clawpot attach {id}`");
+                }
+            }
+            None => {}
+        }
+    }
+
+    let Some(exit_code) = exit_code else {
+        bail!("Exec stream ended without an exit code");
+    };
+    std::process::exit(exit_code);
+}
+
+fn stream_input(input: exec_vm_stream_input::Input) -> ExecVmStreamInput {
+    ExecVmStreamInput { input: Some(input) }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_read_stdin() {
+        let piped: &[u8] = b"piped input\n";
+        assert_eq!(
+            read_stdin(piped, false, true).await.unwrap(),
+            Some(b"piped input\n".to_vec())
+        );
+        // A terminal is never read, and --no-stdin leaves a pipe alone
+        assert_eq!(read_stdin(piped, true, true).await.unwrap(), None);
+        assert_eq!(read_stdin(piped, false, false).await.unwrap(), None);
+    }
+}
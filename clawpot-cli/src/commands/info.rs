@@ -0,0 +1,41 @@
+use anyhow::Result;
+use clawpot_common::proto::{clawpot_service_client::ClawpotServiceClient, GetServerInfoRequest};
+use tonic::transport::Channel;
+
+#[allow(clippy::cast_precision_loss)]
+pub async fn execute(client: &mut ClawpotServiceClient<Channel>) -> Result<()> {
+    let info = client
+        .get_server_info(GetServerInfoRequest {})
+        .await?
+        .into_inner();
+
+    println!("Version:    {}", info.version);
+    println!("Server ID:  {}", info.server_id);
+    println!("Session ID: {}", info.session_id);
+    if info.draining {
+        println!("Draining:   yes (new VMs are rejected)");
+    }
+    if info.body_store_degraded {
+        println!(
+            "Body store: degraded (inline-only) — {}",
+            info.body_store_error
+        );
+    } else {
+        println!("Body store: ok");
+    }
+    println!(
+        "            {} file(s), {:.1} MiB on disk",
+        info.body_store_files,
+        info.body_store_bytes as f64 / (1024.0 * 1024.0)
+    );
+    if !info.config.is_empty() {
+        println!("Config:");
+        let mut config: Vec<_> = info.config.iter().collect();
+        config.sort();
+        for (name, value) in config {
+            println!("  {name}={value}");
+        }
+    }
+
+    Ok(())
+}
@@ -1,7 +1,9 @@
+use super::create::parse_label;
 use anyhow::Result;
 use clawpot_common::proto::{
-    clawpot_service_client::ClawpotServiceClient, ListVmsRequest, VmState,
+    clawpot_service_client::ClawpotServiceClient, IpPoolStatus, ListVmsRequest, VmState,
 };
+use std::collections::HashMap;
 use tabled::{Table, Tabled};
 use tonic::transport::Channel;
 
@@ -9,6 +11,8 @@ use tonic::transport::Channel;
 struct VmRow {
     #[tabled(rename = "VM ID")]
     vm_id: String,
+    #[tabled(rename = "Name")]
+    name: String,
     #[tabled(rename = "State")]
     state: String,
     #[tabled(rename = "IP Address")]
@@ -17,39 +21,81 @@ struct VmRow {
     vcpus: u32,
     #[tabled(rename = "Memory (MiB)")]
     memory: u32,
+    #[tabled(rename = "Labels")]
+    labels: String,
 }
 
-pub async fn execute(client: &mut ClawpotServiceClient<Channel>) -> Result<()> {
-    let request = ListVmsRequest {};
+/// Parse a `--selector key=value[,key=value...]` argument.
+pub fn parse_selector(raw: &str) -> Result<HashMap<String, String>, String> {
+    raw.split(',')
+        .filter(|pair| !pair.trim().is_empty())
+        .map(parse_label)
+        .collect()
+}
+
+/// Labels as `key=value` pairs in key order, or `-` if there are none
+fn format_labels(labels: &HashMap<String, String>) -> String {
+    if labels.is_empty() {
+        return "-".to_string();
+    }
+    let mut pairs: Vec<String> = labels.iter().map(|(k, v)| format!("{k}={v}")).collect();
+    pairs.sort();
+    pairs.join(",")
+}
+
+/// Display name of a protobuf `VmState` value
+pub fn state_name(state: i32) -> &'static str {
+    match VmState::try_from(state) {
+        Ok(VmState::Unspecified) => "Unspecified",
+        Ok(VmState::Starting) => "Starting",
+        Ok(VmState::Running) => "Running",
+        Ok(VmState::Paused) => "Paused",
+        Ok(VmState::Stopping) => "Stopping",
+        Ok(VmState::Stopped) => "Stopped",
+        Ok(VmState::Error) => "Error",
+        Err(_) => "Unknown",
+    }
+}
+
+pub async fn execute(
+    client: &mut ClawpotServiceClient<Channel>,
+    selector: HashMap<String, String>,
+) -> Result<()> {
+    let filtered = !selector.is_empty();
+    let request = ListVmsRequest {
+        label_selector: selector,
+    };
 
-    let response = client.list_v_ms(request).await?;
-    let vms = response.into_inner().vms;
+    let response = client.list_v_ms(request).await?.into_inner();
+    let vms = response.vms;
+    let pool_line = response.ip_pool.as_ref().map(pool_summary);
 
     if vms.is_empty() {
-        println!("No VMs running");
+        if filtered {
+            println!("No VMs match the selector");
+        } else {
+            println!("No VMs running");
+        }
+        if let Some(line) = pool_line {
+            println!("{line}");
+        }
         return Ok(());
     }
 
     let rows: Vec<VmRow> = vms
         .into_iter()
-        .map(|vm| {
-            let state_str = match VmState::try_from(vm.state) {
-                Ok(VmState::Unspecified) => "Unspecified",
-                Ok(VmState::Starting) => "Starting",
-                Ok(VmState::Running) => "Running",
-                Ok(VmState::Stopping) => "Stopping",
-                Ok(VmState::Stopped) => "Stopped",
-                Ok(VmState::Error) => "Error",
-                Err(_) => "Unknown",
-            };
-
-            VmRow {
-                vm_id: vm.vm_id,
-                state: state_str.to_string(),
-                ip_address: vm.ip_address,
-                vcpus: vm.vcpu_count,
-                memory: vm.mem_size_mib,
-            }
+        .map(|vm| VmRow {
+            vm_id: vm.vm_id,
+            name: if vm.name.is_empty() {
+                "-".to_string()
+            } else {
+                vm.name
+            },
+            state: state_name(vm.state).to_string(),
+            ip_address: vm.ip_address,
+            vcpus: vm.vcpu_count,
+            memory: vm.mem_size_mib,
+            labels: format_labels(&vm.labels),
         })
         .collect();
 
@@ -57,6 +103,53 @@ pub async fn execute(client: &mut ClawpotServiceClient<Channel>) -> Result<()> {
     let table = Table::new(rows).to_string();
     println!("{table}");
     println!("\nTotal: {count} VM(s)");
+    if let Some(line) = pool_line {
+        println!("{line}");
+    }
 
     Ok(())
 }
+
+/// One-line summary of IP pool usage, with a warning once fewer than 10%
+/// of addresses remain.
+fn pool_summary(pool: &IpPoolStatus) -> String {
+    let line = format!(
+        "IP pool: {} of {} in use, {} available",
+        pool.allocated, pool.total, pool.available
+    );
+    if pool.available * 10 < pool.total {
+        format!("{line} (warning: pool nearly exhausted)")
+    } else {
+        line
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pool_summary() {
+        let pool = |allocated| IpPoolStatus {
+            total: 253,
+            allocated,
+            available: 253 - allocated,
+        };
+        assert_eq!(
+            pool_summary(&pool(3)),
+            "IP pool: 3 of 253 in use, 250 available"
+        );
+        assert!(pool_summary(&pool(250)).ends_with("(warning: pool nearly exhausted)"));
+    }
+
+    #[test]
+    fn test_parse_selector() {
+        let selector = parse_selector("tier=frontend, env=prod").unwrap();
+        assert_eq!(selector.len(), 2);
+        assert_eq!(selector["env"], "prod");
+        assert!(parse_selector("tier").is_err());
+
+        assert_eq!(format_labels(&selector), "env=prod,tier=frontend");
+        assert_eq!(format_labels(&HashMap::new()), "-");
+    }
+}
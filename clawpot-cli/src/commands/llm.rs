@@ -0,0 +1,101 @@
+use anyhow::{bail, Context, Result};
+use clawpot_common::llm_keys;
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use tabled::{Table, Tabled};
+
+#[derive(Tabled)]
+struct KeyRow {
+    #[tabled(rename = "Provider")]
+    provider: &'static str,
+    #[tabled(rename = "Key Set")]
+    key_set: &'static str,
+}
+
+/// Default key file path based on CLAWPOT_ROOT.
+fn default_keys_path() -> PathBuf {
+    let root = std::env::var("CLAWPOT_ROOT").unwrap_or_else(|_| "/workspaces/clawpot".to_string());
+    llm_keys::keys_path(&Path::new(&root).join("data"))
+}
+
+fn keys_path(file: Option<&str>) -> PathBuf {
+    file.map_or_else(default_keys_path, PathBuf::from)
+}
+
+pub fn execute_set(file: Option<&str>, provider: &str, key: Option<&str>) -> Result<()> {
+    llm_keys::validate_provider(provider)?;
+
+    // Reading from stdin keeps the key out of shell history
+    let key = match key {
+        Some(key) => key.to_string(),
+        None => {
+            let mut line = String::new();
+            std::io::stdin()
+                .lock()
+                .read_line(&mut line)
+                .context("Failed to read key from stdin")?;
+            line
+        }
+    };
+    let key = key.trim();
+    if key.is_empty() {
+        bail!("Key must not be empty");
+    }
+
+    let path = keys_path(file);
+    let mut keys = llm_keys::load(&path)?;
+    keys.insert(provider.to_string(), key.to_string());
+    llm_keys::save(&path, &keys)?;
+
+    println!("✓ Stored {provider} key in {}", path.display());
+    println!("  Restart clawpot-server to use it");
+    Ok(())
+}
+
+pub fn execute_list(file: Option<&str>) -> Result<()> {
+    let path = keys_path(file);
+    let keys = llm_keys::load(&path)?;
+
+    let rows: Vec<KeyRow> = llm_keys::PROVIDER_NAMES
+        .iter()
+        .map(|&provider| KeyRow {
+            provider,
+            key_set: if keys.contains_key(provider) {
+                "yes"
+            } else {
+                "no"
+            },
+        })
+        .collect();
+
+    println!("{}", Table::new(rows));
+    println!(
+        "\nKey file: {}\nEnvironment variables on the server take precedence over stored keys.",
+        path.display()
+    );
+    Ok(())
+}
+
+pub fn execute_clear(file: Option<&str>, provider: Option<&str>) -> Result<()> {
+    let path = keys_path(file);
+    let mut keys = llm_keys::load(&path)?;
+
+    match provider {
+        Some(provider) => {
+            llm_keys::validate_provider(provider)?;
+            if keys.remove(provider).is_none() {
+                println!("No stored key for {provider}");
+                return Ok(());
+            }
+            println!("✓ Cleared {provider} key");
+        }
+        None => {
+            keys.clear();
+            println!("✓ Cleared all stored keys");
+        }
+    }
+
+    llm_keys::save(&path, &keys)?;
+    println!("  Restart clawpot-server to apply the change");
+    Ok(())
+}
@@ -1,7 +1,11 @@
 use anyhow::{Context, Result};
 use rusqlite::Connection;
 use serde::{Deserialize, Serialize};
+use std::collections::hash_map::RandomState;
+use std::collections::{HashMap, HashSet};
 use std::fmt::Write as _;
+use std::hash::BuildHasher;
+use std::net::{IpAddr, SocketAddr};
 use std::path::Path;
 
 /// Summary of a session.
@@ -12,25 +16,26 @@ struct SessionInfo {
     stopped_at: Option<String>,
     server_version: String,
     event_count: i64,
+    server_id: Option<String>,
 }
 
 /// A single event row.
 #[derive(Debug, Serialize, Deserialize)]
 #[allow(clippy::struct_field_names)]
-struct Event {
-    id: i64,
-    session_id: String,
-    timestamp: String,
-    category: String,
-    event_type: String,
-    vm_id: Option<String>,
-    correlation_id: Option<String>,
-    duration_ms: Option<i64>,
-    success: Option<bool>,
-    data: serde_json::Value,
+pub(super) struct Event {
+    pub(super) id: i64,
+    pub(super) session_id: String,
+    pub(super) timestamp: String,
+    pub(super) category: String,
+    pub(super) event_type: String,
+    pub(super) vm_id: Option<String>,
+    pub(super) correlation_id: Option<String>,
+    pub(super) duration_ms: Option<i64>,
+    pub(super) success: Option<bool>,
+    pub(super) data: serde_json::Value,
 }
 
-fn open_db(path: &str) -> Result<Connection> {
+pub(super) fn open_db(path: &str) -> Result<Connection> {
     // Open in read-write mode so we can recover WAL data if the -wal file
     // exists but hasn't been checkpointed (read-only can't create -shm).
     let conn =
@@ -40,13 +45,32 @@ fn open_db(path: &str) -> Result<Connection> {
     Ok(conn)
 }
 
+/// Whether the sessions table has a `server_id` column (older databases predate it).
+fn sessions_have_server_id(conn: &Connection) -> Result<bool> {
+    let mut stmt = conn.prepare("PRAGMA table_info(sessions)")?;
+    let names = stmt.query_map([], |row| row.get::<_, String>(1))?;
+    for name in names {
+        if name? == "server_id" {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
 fn list_sessions(conn: &Connection) -> Result<Vec<SessionInfo>> {
-    let mut stmt = conn.prepare(
+    // The DB is opened query-only, so select NULL rather than migrating old schemas.
+    let server_id_col = if sessions_have_server_id(conn)? {
+        "s.server_id"
+    } else {
+        "NULL"
+    };
+    let mut stmt = conn.prepare(&format!(
         "SELECT s.id, s.started_at, s.stopped_at, s.server_version,
-                (SELECT COUNT(*) FROM events e WHERE e.session_id = s.id) as event_count
+                (SELECT COUNT(*) FROM events e WHERE e.session_id = s.id) as event_count,
+                {server_id_col}
          FROM sessions s
-         ORDER BY s.started_at DESC",
-    )?;
+         ORDER BY s.started_at DESC"
+    ))?;
 
     let rows = stmt.query_map([], |row| {
         Ok(SessionInfo {
@@ -55,6 +79,7 @@ fn list_sessions(conn: &Connection) -> Result<Vec<SessionInfo>> {
             stopped_at: row.get(2)?,
             server_version: row.get(3)?,
             event_count: row.get(4)?,
+            server_id: row.get(5)?,
         })
     })?;
 
@@ -65,14 +90,69 @@ fn list_sessions(conn: &Connection) -> Result<Vec<SessionInfo>> {
     Ok(sessions)
 }
 
-fn query_events(
-    conn: &Connection,
-    session_id: Option<&str>,
-    vm_id: Option<&str>,
-    category: Option<&str>,
-    event_type: Option<&str>,
-    limit: Option<i64>,
-) -> Result<Vec<Event>> {
+/// Whether the database has the `event_headers` table (older databases predate it).
+fn has_event_headers(conn: &Connection) -> Result<bool> {
+    let count: i64 = conn.query_row(
+        "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name = 'event_headers'",
+        [],
+        |row| row.get(0),
+    )?;
+    Ok(count > 0)
+}
+
+/// Parse a `--header name=value` filter.
+pub fn parse_header_filter(raw: &str) -> Result<(String, String), String> {
+    match raw.split_once('=') {
+        Some((name, value)) if !name.trim().is_empty() => {
+            Ok((name.trim().to_ascii_lowercase(), value.to_string()))
+        }
+        _ => Err(format!("expected <name>=<value>, got '{raw}'")),
+    }
+}
+
+/// Filters for [`query_events`]; unset fields match every event.
+#[derive(Default)]
+pub struct EventQuery<'a> {
+    pub session_id: Option<&'a str>,
+    pub vm_id: Option<&'a str>,
+    pub category: Option<&'a str>,
+    pub event_type: Option<&'a str>,
+    /// Events of one operation, e.g. a single VM create or proxied request
+    pub correlation_id: Option<&'a str>,
+    /// Indexed header (name, value) the event must carry
+    pub header: Option<(&'a str, &'a str)>,
+    /// With `vm_id`, also match traffic not attributed to any VM
+    pub include_unknown: bool,
+    /// Only match traffic not attributed to any VM
+    pub only_unknown: bool,
+    pub limit: Option<i64>,
+}
+
+/// Matches events from sources that aren't registered VMs. Older servers
+/// stored these with the literal vm_id "unknown".
+const UNATTRIBUTED_SQL: &str = "(json_extract(data, '$.unattributed') = 1 OR vm_id = 'unknown')";
+
+/// Whether an event came from a source that isn't a registered VM.
+fn is_unattributed(event: &Event) -> bool {
+    event
+        .data
+        .get("unattributed")
+        .and_then(serde_json::Value::as_bool)
+        == Some(true)
+}
+
+pub(super) fn query_events(conn: &Connection, query: &EventQuery) -> Result<Vec<Event>> {
+    let EventQuery {
+        session_id,
+        vm_id,
+        category,
+        event_type,
+        correlation_id,
+        header,
+        include_unknown,
+        only_unknown,
+        limit,
+    } = *query;
     let mut sql = String::from(
         "SELECT id, session_id, timestamp, category, event_type, vm_id,
                 correlation_id, duration_ms, success, data
@@ -85,9 +165,20 @@ fn query_events(
         params.push(Box::new(sid.to_string()));
     }
     if let Some(vid) = vm_id {
-        let _ = write!(sql, " AND vm_id = ?{}", params.len() + 1);
+        if include_unknown {
+            let _ = write!(
+                sql,
+                " AND (vm_id = ?{} OR {UNATTRIBUTED_SQL})",
+                params.len() + 1
+            );
+        } else {
+            let _ = write!(sql, " AND vm_id = ?{}", params.len() + 1);
+        }
         params.push(Box::new(vid.to_string()));
     }
+    if only_unknown {
+        let _ = write!(sql, " AND {UNATTRIBUTED_SQL}");
+    }
     if let Some(cat) = category {
         let _ = write!(sql, " AND category = ?{}", params.len() + 1);
         params.push(Box::new(cat.to_string()));
@@ -96,6 +187,24 @@ fn query_events(
         let _ = write!(sql, " AND event_type = ?{}", params.len() + 1);
         params.push(Box::new(et.to_string()));
     }
+    if let Some(corr) = correlation_id {
+        let _ = write!(sql, " AND correlation_id = ?{}", params.len() + 1);
+        params.push(Box::new(corr.to_string()));
+    }
+    if let Some((name, value)) = header {
+        // Headers are only indexed when the server sets CLAWPOT_INDEXED_HEADERS
+        if !has_event_headers(conn)? {
+            return Ok(Vec::new());
+        }
+        let _ = write!(
+            sql,
+            " AND id IN (SELECT event_id FROM event_headers WHERE name = ?{} AND value = ?{})",
+            params.len() + 1,
+            params.len() + 2
+        );
+        params.push(Box::new(name.to_string()));
+        params.push(Box::new(value.to_string()));
+    }
 
     sql.push_str(" ORDER BY timestamp ASC, id ASC");
 
@@ -132,63 +241,165 @@ fn query_events(
 }
 
 /// Default DB path based on CLAWPOT_ROOT.
-fn default_db_path() -> String {
+pub(super) fn default_db_path() -> String {
     let root = std::env::var("CLAWPOT_ROOT").unwrap_or_else(|_| "/workspaces/clawpot".to_string());
     format!("{root}/data/events.db")
 }
 
-pub fn execute_sessions(db_path: Option<&str>) -> Result<()> {
-    let path = db_path.map_or_else(default_db_path, String::from);
+/// Database files selected by `--db`. Directories expand to the `*.db` files
+/// directly inside them, e.g. a folder of archived databases; with no
+/// `--db`, the default database is used.
+fn resolve_db_paths(db_paths: &[String]) -> Result<Vec<String>> {
+    if db_paths.is_empty() {
+        return Ok(vec![default_db_path()]);
+    }
+    let mut paths = Vec::new();
+    for path in db_paths {
+        if !Path::new(path).is_dir() {
+            paths.push(path.clone());
+            continue;
+        }
+        let mut found = Vec::new();
+        for entry in std::fs::read_dir(path).with_context(|| format!("Failed to read {path}"))? {
+            let entry_path = entry?.path();
+            if entry_path.is_file() && entry_path.extension().is_some_and(|ext| ext == "db") {
+                found.push(entry_path.to_string_lossy().into_owned());
+            }
+        }
+        found.sort();
+        paths.extend(found);
+    }
+    Ok(paths)
+}
+
+/// Open every selected database that exists. Missing files among several are
+/// skipped with a warning; the result is empty if none of them exist.
+fn open_dbs(db_paths: &[String]) -> Result<Vec<Connection>> {
+    let paths = resolve_db_paths(db_paths)?;
+    let mut conns = Vec::new();
+    for path in &paths {
+        if Path::new(path).exists() {
+            conns.push(open_db(path)?);
+        } else if paths.len() > 1 {
+            eprintln!("Skipping missing events database {path}");
+        }
+    }
+    Ok(conns)
+}
+
+fn no_db_message(db_paths: &[String]) -> String {
+    let paths = if db_paths.is_empty() {
+        default_db_path()
+    } else {
+        db_paths.join(", ")
+    };
+    format!("No events database found at {paths}")
+}
+
+/// Sessions from every database, newest first. A session split across
+/// databases (e.g. partly archived) is listed once with its events summed.
+fn list_sessions_all(conns: &[Connection]) -> Result<Vec<SessionInfo>> {
+    let mut sessions: Vec<SessionInfo> = Vec::new();
+    let mut index: HashMap<String, usize> = HashMap::new();
+    for conn in conns {
+        for s in list_sessions(conn)? {
+            match index.get(&s.id) {
+                Some(&i) => {
+                    let merged = &mut sessions[i];
+                    merged.event_count += s.event_count;
+                    merged.stopped_at = merged.stopped_at.take().or(s.stopped_at);
+                    merged.server_id = merged.server_id.take().or(s.server_id);
+                }
+                None => {
+                    index.insert(s.id.clone(), sessions.len());
+                    sessions.push(s);
+                }
+            }
+        }
+    }
+    sessions.sort_by(|a, b| b.started_at.cmp(&a.started_at));
+    Ok(sessions)
+}
+
+/// Run `query` against every database and merge the matches in timestamp
+/// order. Each database is queried with its own schema checks, so older
+/// databases that lack newer columns or tables still contribute what they
+/// have. Like the session event counts from [`list_sessions_all`], events
+/// aren't deduplicated: event IDs are only unique within one database, and
+/// archives don't overlap.
+fn query_events_all(conns: &[Connection], query: &EventQuery) -> Result<Vec<Event>> {
+    let mut events = Vec::new();
+    for conn in conns {
+        events.extend(query_events(conn, query)?);
+    }
+    // Stable, so ties keep each database's id order
+    events.sort_by(|a, b| a.timestamp.cmp(&b.timestamp));
+    if let Some(limit) = query.limit.and_then(|l| usize::try_from(l).ok()) {
+        events.truncate(limit);
+    }
+    Ok(events)
+}
 
-    if !Path::new(&path).exists() {
-        println!("No events database found at {path}");
+pub fn execute_sessions(db_paths: &[String]) -> Result<()> {
+    let conns = open_dbs(db_paths)?;
+    if conns.is_empty() {
+        println!("{}", no_db_message(db_paths));
         return Ok(());
     }
 
-    let conn = open_db(&path)?;
-    let sessions = list_sessions(&conn)?;
+    let sessions = list_sessions_all(&conns)?;
 
     if sessions.is_empty() {
         println!("No sessions found.");
         return Ok(());
     }
 
-    println!(
-        "{:<38} {:<26} {:<26} {:<10} {:>6}",
-        "SESSION ID", "STARTED", "STOPPED", "VERSION", "EVENTS"
-    );
-    println!("{}", "-".repeat(110));
-    for s in &sessions {
+    for (i, (server_id, group)) in group_by_server(&sessions).iter().enumerate() {
+        if i > 0 {
+            println!();
+        }
+        println!("Server {}", server_id.unwrap_or("(unknown)"));
         println!(
             "{:<38} {:<26} {:<26} {:<10} {:>6}",
-            s.id,
-            &s.started_at,
-            s.stopped_at.as_deref().unwrap_or("(running)"),
-            &s.server_version,
-            s.event_count,
+            "SESSION ID", "STARTED", "STOPPED", "VERSION", "EVENTS"
         );
+        println!("{}", "-".repeat(110));
+        for s in group {
+            println!(
+                "{:<38} {:<26} {:<26} {:<10} {:>6}",
+                s.id,
+                &s.started_at,
+                s.stopped_at.as_deref().unwrap_or("(running)"),
+                &s.server_version,
+                s.event_count,
+            );
+        }
     }
 
     Ok(())
 }
 
-pub fn execute_show(
-    db_path: Option<&str>,
-    session_id: Option<&str>,
-    vm_id: Option<&str>,
-    category: Option<&str>,
-    event_type: Option<&str>,
-    limit: Option<i64>,
-) -> Result<()> {
-    let path = db_path.map_or_else(default_db_path, String::from);
+/// Group sessions by server ID, keeping servers in order of their most recent session.
+fn group_by_server(sessions: &[SessionInfo]) -> Vec<(Option<&str>, Vec<&SessionInfo>)> {
+    let mut groups: Vec<(Option<&str>, Vec<&SessionInfo>)> = Vec::new();
+    for s in sessions {
+        let key = s.server_id.as_deref();
+        match groups.iter_mut().find(|(id, _)| *id == key) {
+            Some((_, group)) => group.push(s),
+            None => groups.push((key, vec![s])),
+        }
+    }
+    groups
+}
 
-    if !Path::new(&path).exists() {
-        println!("No events database found at {path}");
+pub fn execute_show(db_paths: &[String], query: &EventQuery) -> Result<()> {
+    let conns = open_dbs(db_paths)?;
+    if conns.is_empty() {
+        println!("{}", no_db_message(db_paths));
         return Ok(());
     }
 
-    let conn = open_db(&path)?;
-    let events = query_events(&conn, session_id, vm_id, category, event_type, limit)?;
+    let events = query_events_all(&conns, query)?;
 
     if events.is_empty() {
         println!("No events found.");
@@ -206,7 +417,9 @@ pub fn execute_show(
             &e.timestamp,
             &e.category,
             &e.event_type,
-            e.vm_id.as_deref().unwrap_or("-"),
+            e.vm_id
+                .as_deref()
+                .unwrap_or(if is_unattributed(e) { "unknown" } else { "-" }),
             e.duration_ms
                 .map_or_else(|| "-".to_string(), |d| d.to_string()),
             match e.success {
@@ -221,15 +434,31 @@ pub fn execute_show(
     Ok(())
 }
 
-pub fn execute_export(db_path: Option<&str>, session_id: Option<&str>, format: &str) -> Result<()> {
-    let path = db_path.map_or_else(default_db_path, String::from);
-
-    if !Path::new(&path).exists() {
-        anyhow::bail!("No events database found at {path}");
+pub fn execute_export(
+    db_paths: &[String],
+    session_id: Option<&str>,
+    format: &str,
+    anonymize: bool,
+) -> Result<()> {
+    let conns = open_dbs(db_paths)?;
+    if conns.is_empty() {
+        anyhow::bail!("{}", no_db_message(db_paths));
     }
 
-    let conn = open_db(&path)?;
-    let events = query_events(&conn, session_id, None, None, None, None)?;
+    let mut events = query_events_all(
+        &conns,
+        &EventQuery {
+            session_id,
+            ..EventQuery::default()
+        },
+    )?;
+
+    if anonymize {
+        let anonymizer = Anonymizer::new(&events);
+        for event in &mut events {
+            anonymizer.anonymize_event(event);
+        }
+    }
 
     match format {
         "json" => {
@@ -246,20 +475,177 @@ pub fn execute_export(db_path: Option<&str>, session_id: Option<&str>, format: &
     Ok(())
 }
 
+/// Replaces IP addresses, VM IDs and VM names in exported events with hashes.
+///
+/// Hashes are keyed with a random seed chosen per export, so the same value
+/// always maps to the same hash within one export (keeping correlations
+/// intact) but can't be reversed or matched across exports.
+struct Anonymizer {
+    state: RandomState,
+    vm_ids: HashSet<String>,
+}
+
+impl Anonymizer {
+    fn new(events: &[Event]) -> Self {
+        let mut vm_ids: HashSet<String> = events.iter().filter_map(|e| e.vm_id.clone()).collect();
+        for event in events {
+            collect_vm_ids(&event.data, &mut vm_ids);
+        }
+        Self {
+            state: RandomState::new(),
+            vm_ids,
+        }
+    }
+
+    fn hash(&self, prefix: &str, value: &str) -> String {
+        format!(
+            "{prefix}-{:012x}",
+            self.state.hash_one(value) & 0xffff_ffff_ffff
+        )
+    }
+
+    fn anonymize_event(&self, event: &mut Event) {
+        if let Some(vm_id) = &event.vm_id {
+            event.vm_id = Some(self.anonymize_str(vm_id));
+        }
+        self.anonymize_value(&mut event.data);
+    }
+
+    fn anonymize_value(&self, value: &mut serde_json::Value) {
+        match value {
+            serde_json::Value::String(s) => *s = self.anonymize_str(s),
+            serde_json::Value::Array(items) => {
+                for item in items {
+                    self.anonymize_value(item);
+                }
+            }
+            serde_json::Value::Object(map) => {
+                for (key, item) in map {
+                    match item {
+                        // Friendly names are user-chosen, so hash them whole
+                        serde_json::Value::String(name) if key == "vm_name" => {
+                            *name = self.hash("name", name);
+                        }
+                        _ => self.anonymize_value(item),
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// Anonymize VM IDs and IP addresses anywhere inside a string, including
+    /// URLs, log messages and JSON that was stored as a string.
+    fn anonymize_str(&self, s: &str) -> String {
+        let mut out = s.to_string();
+        for vm_id in &self.vm_ids {
+            if out.contains(vm_id.as_str()) {
+                out = out.replace(vm_id.as_str(), &self.hash("vm", vm_id));
+            }
+        }
+        self.replace_ips(&out)
+    }
+
+    fn replace_ips(&self, s: &str) -> String {
+        let bytes = s.as_bytes();
+        let mut out = String::with_capacity(s.len());
+        let mut copied = 0;
+        let mut i = 0;
+
+        while i < bytes.len() {
+            // Only consider candidates that start and end on a word boundary,
+            // so things like `std::backtrace` aren't mistaken for IPv6
+            let starts_word = i == 0 || !is_word_byte(bytes[i - 1]);
+            if !is_ip_byte(bytes[i]) || !starts_word {
+                i += 1;
+                continue;
+            }
+
+            let start = i;
+            while i < bytes.len() && is_ip_byte(bytes[i]) {
+                i += 1;
+            }
+            if i < bytes.len() && is_word_byte(bytes[i]) {
+                continue;
+            }
+
+            if let Some((len, replacement)) = self.anonymize_ip_token(&s[start..i]) {
+                out.push_str(&s[copied..start]);
+                out.push_str(&replacement);
+                copied = start + len;
+            }
+        }
+
+        out.push_str(&s[copied..]);
+        out
+    }
+
+    /// Anonymize an IP or IPv4 `ip:port` at the start of `token`, ignoring
+    /// trailing punctuation. Returns the length consumed and its replacement.
+    fn anonymize_ip_token(&self, token: &str) -> Option<(usize, String)> {
+        let mut candidate = token;
+        loop {
+            if let Ok(ip) = candidate.parse::<IpAddr>() {
+                return Some((candidate.len(), self.hash("ip", &ip.to_string())));
+            }
+            if let Ok(addr) = candidate.parse::<SocketAddr>() {
+                let ip = self.hash("ip", &addr.ip().to_string());
+                return Some((candidate.len(), format!("{ip}:{}", addr.port())));
+            }
+            candidate = candidate.strip_suffix(['.', ':'])?;
+        }
+    }
+}
+
+/// Collect VM IDs recorded in `vm_id` fields nested inside event data.
+fn collect_vm_ids(value: &serde_json::Value, vm_ids: &mut HashSet<String>) {
+    match value {
+        serde_json::Value::Array(items) => {
+            for item in items {
+                collect_vm_ids(item, vm_ids);
+            }
+        }
+        serde_json::Value::Object(map) => {
+            for (key, item) in map {
+                match item {
+                    serde_json::Value::String(id) if key == "vm_id" && !id.is_empty() => {
+                        vm_ids.insert(id.clone());
+                    }
+                    _ => collect_vm_ids(item, vm_ids),
+                }
+            }
+        }
+        _ => {}
+    }
+}
+
+fn is_ip_byte(b: u8) -> bool {
+    b.is_ascii_hexdigit() || b == b'.' || b == b':'
+}
+
+fn is_word_byte(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || b == b'_'
+}
+
 pub fn execute_timeline(
-    db_path: Option<&str>,
+    db_paths: &[String],
     session_id: Option<&str>,
     vm_id: Option<&str>,
 ) -> Result<()> {
-    let path = db_path.map_or_else(default_db_path, String::from);
-
-    if !Path::new(&path).exists() {
-        println!("No events database found at {path}");
+    let conns = open_dbs(db_paths)?;
+    if conns.is_empty() {
+        println!("{}", no_db_message(db_paths));
         return Ok(());
     }
 
-    let conn = open_db(&path)?;
-    let events = query_events(&conn, session_id, vm_id, None, None, None)?;
+    let events = query_events_all(
+        &conns,
+        &EventQuery {
+            session_id,
+            vm_id,
+            ..EventQuery::default()
+        },
+    )?;
 
     if events.is_empty() {
         println!("No events found.");
@@ -268,10 +654,7 @@ pub fn execute_timeline(
 
     for e in &events {
         // Format: timestamp [category] event_type (vm_id) data_summary
-        let vm_part = e
-            .vm_id
-            .as_deref()
-            .map_or_else(String::new, |v| format!(" vm={v}"));
+        let vm_part = format_vm_part(e.vm_id.as_deref(), &e.data);
         let duration_part = e
             .duration_ms
             .map_or_else(String::new, |d| format!(" ({d}ms)"));
@@ -299,7 +682,18 @@ pub fn execute_timeline(
     Ok(())
 }
 
-fn format_data_summary(event_type: &str, data: &serde_json::Value) -> String {
+/// Format the VM column of a timeline line, preferring the VM's friendly
+/// name (recorded on some events as `vm_name`) over its bare UUID.
+fn format_vm_part(vm_id: Option<&str>, data: &serde_json::Value) -> String {
+    let name = data.get("vm_name").and_then(|v| v.as_str());
+    match (vm_id, name) {
+        (Some(id), Some(name)) => format!(" vm={name} ({id})"),
+        (Some(id), None) => format!(" vm={id}"),
+        (None, _) => String::new(),
+    }
+}
+
+pub(super) fn format_data_summary(event_type: &str, data: &serde_json::Value) -> String {
     match event_type {
         "log" => data
             .get("message")
@@ -329,6 +723,35 @@ fn format_data_summary(event_type: &str, data: &serde_json::Value) -> String {
                 .unwrap_or("?");
             format!("{name} {qtype}")
         }
+        "network.dns.response" => {
+            let rcode = data
+                .get("rcode")
+                .and_then(serde_json::Value::as_i64)
+                .map_or_else(|| "?".to_string(), |r| r.to_string());
+            // Upstream answers list their records; refused and overridden
+            // answers carry a plain `answers` string instead
+            let answers = match data.get("records").and_then(|v| v.as_array()) {
+                Some(records) => records
+                    .iter()
+                    .map(|r| {
+                        let rtype = r.get("type").and_then(|v| v.as_str()).unwrap_or("?");
+                        let value = r.get("data").and_then(|v| v.as_str()).unwrap_or("?");
+                        format!("{rtype} {value}")
+                    })
+                    .collect::<Vec<_>>()
+                    .join(", "),
+                None => data
+                    .get("answers")
+                    .and_then(|v| v.as_str())
+                    .unwrap_or("")
+                    .to_string(),
+            };
+            if answers.is_empty() {
+                format!("rcode={rcode}")
+            } else {
+                format!("rcode={rcode} {answers}")
+            }
+        }
         "vm.create.ip_allocated" => data
             .get("ip_address")
             .and_then(|v| v.as_str())
@@ -370,8 +793,20 @@ fn format_data_summary(event_type: &str, data: &serde_json::Value) -> String {
             let output = data
                 .get("output_tokens")
                 .and_then(serde_json::Value::as_u64);
+            // Estimated counts are marked with a leading '~'
+            let mark = |key: &str| {
+                if data.get(key).and_then(serde_json::Value::as_bool) == Some(true) {
+                    "~"
+                } else {
+                    ""
+                }
+            };
             let tokens = match (input, output) {
-                (Some(i), Some(o)) => format!(" tokens={i}+{o}"),
+                (Some(i), Some(o)) => format!(
+                    " tokens={}{i}+{}{o}",
+                    mark("input_tokens_estimated"),
+                    mark("output_tokens_estimated")
+                ),
                 _ => String::new(),
             };
             let status = data
@@ -391,3 +826,306 @@ fn format_data_summary(event_type: &str, data: &serde_json::Value) -> String {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn event(vm_id: Option<&str>, data: serde_json::Value) -> Event {
+        Event {
+            id: 1,
+            session_id: "session".to_string(),
+            timestamp: "2026-01-01T12:34:56.789Z".to_string(),
+            category: "network".to_string(),
+            event_type: "network.http.request".to_string(),
+            vm_id: vm_id.map(String::from),
+            correlation_id: None,
+            duration_ms: None,
+            success: None,
+            data,
+        }
+    }
+
+    #[test]
+    fn test_anonymize_replaces_ips_and_vm_ids() {
+        let vm_id = "6f1c2a9e-0000-4000-8000-000000000001";
+        let mut events = vec![
+            event(
+                Some(vm_id),
+                json!({
+                    "url": "http://192.168.100.2:8080/path",
+                    "nested": { "ip_address": "192.168.100.2", "peers": ["fe80::1"] },
+                }),
+            ),
+            event(
+                None,
+                json!({ "message": format!("VM {vm_id} at 192.168.100.2.") }),
+            ),
+        ];
+
+        let anonymizer = Anonymizer::new(&events);
+        for e in &mut events {
+            anonymizer.anonymize_event(e);
+        }
+
+        let ip = anonymizer.hash("ip", "192.168.100.2");
+        let vm = anonymizer.hash("vm", vm_id);
+        assert_eq!(events[0].vm_id.as_deref(), Some(vm.as_str()));
+        assert_eq!(events[0].data["url"], format!("http://{ip}:8080/path"));
+        assert_eq!(events[0].data["nested"]["ip_address"], ip);
+        assert_eq!(
+            events[0].data["nested"]["peers"][0],
+            anonymizer.hash("ip", "fe80::1")
+        );
+        assert_eq!(events[1].data["message"], format!("VM {vm} at {ip}."));
+    }
+
+    #[test]
+    fn test_anonymize_hashes_vm_names() {
+        let mut e = event(
+            Some("vm"),
+            json!({ "vm_name": "build-vm-3", "query_name": "b" }),
+        );
+        let anonymizer = Anonymizer::new(std::slice::from_ref(&e));
+        anonymizer.anonymize_event(&mut e);
+        assert_eq!(e.data["vm_name"], anonymizer.hash("name", "build-vm-3"));
+        assert_eq!(e.data["query_name"], "b");
+    }
+
+    #[test]
+    fn test_format_vm_part() {
+        let named = json!({ "vm_name": "build-vm-3" });
+        assert_eq!(format_vm_part(Some("abc"), &named), " vm=build-vm-3 (abc)");
+        assert_eq!(format_vm_part(Some("abc"), &json!({})), " vm=abc");
+        assert_eq!(format_vm_part(None, &named), "");
+    }
+
+    #[test]
+    fn test_anonymize_leaves_non_ips_alone() {
+        let anonymizer = Anonymizer::new(&[]);
+        for s in [
+            "12:34:56.789",
+            "version 0.1.0",
+            "std::backtrace",
+            "aa:bb:cc:dd:ee:ff",
+            "2026-01-01T12:34:56Z",
+        ] {
+            assert_eq!(anonymizer.anonymize_str(s), s);
+        }
+    }
+
+    #[test]
+    fn test_format_llm_summaries() {
+        let request = json!({
+            "provider": "anthropic",
+            "endpoint": "messages",
+            "model": "claude-sonnet-4",
+            "message_count": 3,
+            "streaming": true,
+        });
+        assert_eq!(
+            format_data_summary("llm.request", &request),
+            "anthropic/messages model=claude-sonnet-4 messages=3 streaming=true"
+        );
+
+        let response = json!({
+            "provider": "anthropic",
+            "endpoint": "messages",
+            "model": "claude-sonnet-4",
+            "input_tokens": 150,
+            "output_tokens": 85,
+            "output_tokens_estimated": true,
+            "status_code": 200,
+        });
+        assert_eq!(
+            format_data_summary("llm.response", &response),
+            "anthropic/messages model=claude-sonnet-4 tokens=150+~85 status=200"
+        );
+    }
+
+    #[test]
+    fn test_format_dns_response_summary() {
+        let response = json!({
+            "rcode": 0,
+            "records": [
+                { "name": "example.com", "type": "CNAME", "ttl": 60, "data": "cdn.example.com" },
+                { "name": "cdn.example.com", "type": "A", "ttl": 30, "data": "10.0.0.1" },
+            ],
+        });
+        assert_eq!(
+            format_data_summary("network.dns.response", &response),
+            "rcode=0 CNAME cdn.example.com, A 10.0.0.1"
+        );
+        let refused = json!({ "rcode": 5, "answers": "REFUSED" });
+        assert_eq!(
+            format_data_summary("network.dns.response", &refused),
+            "rcode=5 REFUSED"
+        );
+    }
+
+    #[test]
+    fn test_unknown_filters() {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(
+            "CREATE TABLE events (
+                id INTEGER PRIMARY KEY, session_id TEXT, timestamp TEXT, category TEXT,
+                event_type TEXT, vm_id TEXT, correlation_id TEXT, duration_ms INTEGER,
+                success INTEGER, data TEXT
+            );
+            INSERT INTO events (session_id, timestamp, category, event_type, vm_id, data) VALUES
+                ('s', '1', 'network', 'network.http.request', 'vm-1', '{}'),
+                ('s', '2', 'network', 'network.http.request', NULL, '{\"unattributed\":true}'),
+                ('s', '3', 'network', 'network.http.request', 'unknown', '{}'),
+                ('s', '4', 'server', 'log', NULL, '{}');",
+        )
+        .unwrap();
+        let timestamps = |query: &EventQuery| -> Vec<String> {
+            query_events(&conn, query)
+                .unwrap()
+                .into_iter()
+                .map(|e| e.timestamp)
+                .collect()
+        };
+
+        let vm = EventQuery {
+            vm_id: Some("vm-1"),
+            ..EventQuery::default()
+        };
+        assert_eq!(timestamps(&vm), ["1"]);
+        let with_unknown = EventQuery {
+            include_unknown: true,
+            ..vm
+        };
+        assert_eq!(timestamps(&with_unknown), ["1", "2", "3"]);
+        let only_unknown = EventQuery {
+            only_unknown: true,
+            ..EventQuery::default()
+        };
+        assert_eq!(timestamps(&only_unknown), ["2", "3"]);
+    }
+
+    /// An events database with the given `(timestamp, session_id)` events.
+    /// `legacy` leaves out `sessions.server_id` and the `event_headers`
+    /// table, like databases from older servers.
+    fn events_db(legacy: bool, events: &[(&str, &str)]) -> Connection {
+        let conn = Connection::open_in_memory().unwrap();
+        let server_id = if legacy { "" } else { ", server_id TEXT" };
+        conn.execute_batch(&format!(
+            "CREATE TABLE sessions (
+                id TEXT PRIMARY KEY, started_at TEXT, stopped_at TEXT,
+                server_version TEXT{server_id}
+            );
+            CREATE TABLE events (
+                id INTEGER PRIMARY KEY, session_id TEXT, timestamp TEXT, category TEXT,
+                event_type TEXT, vm_id TEXT, correlation_id TEXT, duration_ms INTEGER,
+                success INTEGER, data TEXT
+            );"
+        ))
+        .unwrap();
+        if !legacy {
+            conn.execute_batch(
+                "CREATE TABLE event_headers (event_id INTEGER, name TEXT, value TEXT);",
+            )
+            .unwrap();
+        }
+        for (timestamp, session) in events {
+            conn.execute(
+                "INSERT OR IGNORE INTO sessions (id, started_at, server_version) VALUES (?1, ?2, '0.1.0')",
+                [session, timestamp],
+            )
+            .unwrap();
+            conn.execute(
+                "INSERT INTO events (session_id, timestamp, category, event_type, data)
+                 VALUES (?1, ?2, 'server', 'log', '{}')",
+                [session, timestamp],
+            )
+            .unwrap();
+        }
+        conn
+    }
+
+    #[test]
+    fn test_query_across_databases() {
+        let conns = [
+            events_db(true, &[("1", "old"), ("3", "old")]),
+            events_db(false, &[("2", "new"), ("4", "new")]),
+        ];
+        let timestamps = |query: &EventQuery| -> Vec<String> {
+            query_events_all(&conns, query)
+                .unwrap()
+                .into_iter()
+                .map(|e| e.timestamp)
+                .collect()
+        };
+
+        assert_eq!(timestamps(&EventQuery::default()), ["1", "2", "3", "4"]);
+        let limited = EventQuery {
+            limit: Some(3),
+            ..EventQuery::default()
+        };
+        assert_eq!(timestamps(&limited), ["1", "2", "3"]);
+        // The legacy database has no header index, so only the other one is searched
+        let header = EventQuery {
+            header: Some(("user-agent", "curl")),
+            ..EventQuery::default()
+        };
+        assert!(timestamps(&header).is_empty());
+
+        let sessions = list_sessions_all(&conns).unwrap();
+        let ids: Vec<&str> = sessions.iter().map(|s| s.id.as_str()).collect();
+        assert_eq!(ids, ["new", "old"]);
+        assert!(sessions.iter().all(|s| s.event_count == 2));
+    }
+
+    #[test]
+    fn test_session_split_across_databases() {
+        let conns = [
+            events_db(true, &[("1", "s")]),
+            events_db(false, &[("1", "s"), ("2", "s")]),
+        ];
+        let sessions = list_sessions_all(&conns).unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].event_count, 3);
+
+        // Both databases number their events from 1; each event is listed,
+        // matching the count
+        let events = query_events_all(&conns, &EventQuery::default()).unwrap();
+        let timestamps: Vec<_> = events.iter().map(|e| e.timestamp.as_str()).collect();
+        assert_eq!(timestamps, ["1", "1", "2"]);
+    }
+
+    #[test]
+    fn test_resolve_db_paths_expands_directories() {
+        let dir = tempfile::tempdir().unwrap();
+        for name in ["b.db", "a.db", "notes.txt"] {
+            std::fs::write(dir.path().join(name), b"").unwrap();
+        }
+        let dir_str = dir.path().to_string_lossy().into_owned();
+
+        let paths = resolve_db_paths(&[dir_str.clone(), "/tmp/other.db".to_string()]).unwrap();
+        assert_eq!(
+            paths,
+            [
+                format!("{dir_str}/a.db"),
+                format!("{dir_str}/b.db"),
+                "/tmp/other.db".to_string()
+            ]
+        );
+        assert_eq!(resolve_db_paths(&[]).unwrap(), [default_db_path()]);
+    }
+
+    #[test]
+    fn test_parse_header_filter() {
+        assert_eq!(
+            parse_header_filter("User-Agent=curl/8.5.0"),
+            Ok(("user-agent".to_string(), "curl/8.5.0".to_string()))
+        );
+        assert_eq!(
+            parse_header_filter("x-token=a=b"),
+            Ok(("x-token".to_string(), "a=b".to_string()))
+        );
+        assert!(parse_header_filter("user-agent").is_err());
+        assert!(parse_header_filter("=curl").is_err());
+    }
+}
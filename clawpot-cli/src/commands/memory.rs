@@ -0,0 +1,16 @@
+use anyhow::Result;
+use clawpot_common::proto::{clawpot_service_client::ClawpotServiceClient, UpdateVmMemoryRequest};
+use tonic::transport::Channel;
+
+pub async fn execute(
+    client: &mut ClawpotServiceClient<Channel>,
+    vm_id: String,
+    balloon_mib: u32,
+) -> Result<()> {
+    println!("Resizing memory balloon of VM {vm_id} to {balloon_mib} MiB...");
+    client
+        .update_vm_memory(UpdateVmMemoryRequest { vm_id, balloon_mib })
+        .await?;
+    println!("\n✓ Balloon resized");
+    Ok(())
+}
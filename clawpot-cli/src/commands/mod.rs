@@ -1,5 +1,23 @@
+pub mod attach;
+pub mod bench;
+pub mod ca;
+pub mod console;
 pub mod create;
 pub mod delete;
+pub mod describe;
+pub mod drain;
 pub mod exec;
+pub mod info;
 pub mod list;
+pub mod llm;
 pub mod logs;
+pub mod memory;
+pub mod net_rules;
+pub mod orphans;
+pub mod pause;
+pub mod requests;
+pub mod reset;
+pub mod restart;
+pub mod run;
+pub mod selftest;
+pub mod status;
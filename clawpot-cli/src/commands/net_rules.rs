@@ -0,0 +1,38 @@
+use anyhow::Result;
+use clawpot_common::proto::{clawpot_service_client::ClawpotServiceClient, ListNetRulesRequest};
+use tabled::{Table, Tabled};
+use tonic::transport::Channel;
+
+#[derive(Tabled)]
+struct RuleRow {
+    #[tabled(rename = "Table")]
+    table: String,
+    #[tabled(rename = "Chain")]
+    chain: String,
+    #[tabled(rename = "Rule")]
+    rule: String,
+}
+
+pub async fn execute(client: &mut ClawpotServiceClient<Channel>) -> Result<()> {
+    let response = client.list_net_rules(ListNetRulesRequest {}).await?;
+    let rules = response.into_inner().rules;
+
+    if rules.is_empty() {
+        println!("No clawpot iptables rules installed");
+        return Ok(());
+    }
+
+    let count = rules.len();
+    let rows: Vec<RuleRow> = rules
+        .into_iter()
+        .map(|r| RuleRow {
+            table: r.table,
+            chain: r.chain,
+            rule: r.rule,
+        })
+        .collect();
+    println!("{}", Table::new(rows));
+    println!("\nTotal: {count} rule(s)");
+
+    Ok(())
+}
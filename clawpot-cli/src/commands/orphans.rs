@@ -0,0 +1,73 @@
+use anyhow::Result;
+use clawpot_common::proto::{
+    clawpot_service_client::ClawpotServiceClient, CleanOrphansRequest, ListOrphansRequest,
+    OrphanKind, OrphanResource,
+};
+use tabled::{Table, Tabled};
+use tonic::transport::Channel;
+
+#[derive(Tabled)]
+struct OrphanRow {
+    #[tabled(rename = "Kind")]
+    kind: String,
+    #[tabled(rename = "Name")]
+    name: String,
+}
+
+impl From<OrphanResource> for OrphanRow {
+    fn from(orphan: OrphanResource) -> Self {
+        let kind = match OrphanKind::try_from(orphan.kind) {
+            Ok(OrphanKind::TapDevice) => "TAP device",
+            Ok(OrphanKind::Socket) => "Socket",
+            Ok(OrphanKind::Unspecified) | Err(_) => "Unknown",
+        };
+        Self {
+            kind: kind.to_string(),
+            name: orphan.name,
+        }
+    }
+}
+
+pub async fn execute_list(client: &mut ClawpotServiceClient<Channel>) -> Result<()> {
+    let response = client.list_orphans(ListOrphansRequest {}).await?;
+    let orphans = response.into_inner().orphans;
+
+    if orphans.is_empty() {
+        println!("No orphaned resources found");
+        return Ok(());
+    }
+
+    let count = orphans.len();
+    let rows: Vec<OrphanRow> = orphans.into_iter().map(OrphanRow::from).collect();
+    println!("{}", Table::new(rows));
+    println!("\nTotal: {count} orphaned resource(s)");
+
+    Ok(())
+}
+
+pub async fn execute_clean(client: &mut ClawpotServiceClient<Channel>) -> Result<()> {
+    println!("Cleaning orphaned resources...");
+
+    let response = client.clean_orphans(CleanOrphansRequest {}).await?;
+    let result = response.into_inner();
+
+    if result.cleaned.is_empty() && result.failed.is_empty() {
+        println!("\nNo orphaned resources found");
+        return Ok(());
+    }
+
+    for orphan in &result.cleaned {
+        println!("✓ Removed {}", orphan.name);
+    }
+    for orphan in &result.failed {
+        println!("✗ Failed to remove {}", orphan.name);
+    }
+
+    println!(
+        "\nCleaned {} resource(s), {} failed",
+        result.cleaned.len(),
+        result.failed.len()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,25 @@
+use anyhow::Result;
+use clawpot_common::proto::{
+    clawpot_service_client::ClawpotServiceClient, PauseVmRequest, ResumeVmRequest,
+};
+use tonic::transport::Channel;
+
+pub async fn execute_pause(
+    client: &mut ClawpotServiceClient<Channel>,
+    vm_id: String,
+) -> Result<()> {
+    println!("Pausing VM {vm_id}...");
+    client.pause_vm(PauseVmRequest { vm_id }).await?;
+    println!("\n✓ VM paused");
+    Ok(())
+}
+
+pub async fn execute_resume(
+    client: &mut ClawpotServiceClient<Channel>,
+    vm_id: String,
+) -> Result<()> {
+    println!("Resuming VM {vm_id}...");
+    client.resume_vm(ResumeVmRequest { vm_id }).await?;
+    println!("\n✓ VM resumed");
+    Ok(())
+}
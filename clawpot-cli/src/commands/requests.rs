@@ -0,0 +1,251 @@
+use super::logs::{self, Event};
+use anyhow::{bail, Result};
+use base64::prelude::{Engine as _, BASE64_STANDARD};
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::path::Path;
+
+/// Export proxied HTTP requests from the events database as a HAR file on stdout.
+pub fn execute_export(
+    db_path: Option<&str>,
+    session_id: Option<&str>,
+    vm_id: Option<&str>,
+    format: &str,
+) -> Result<()> {
+    if format != "har" {
+        bail!("Unsupported export format '{format}' (expected: har)");
+    }
+
+    let path = db_path.map_or_else(logs::default_db_path, String::from);
+    if !Path::new(&path).exists() {
+        bail!("No events database found at {path}");
+    }
+
+    let conn = logs::open_db(&path)?;
+    let events = logs::query_events(
+        &conn,
+        &logs::EventQuery {
+            session_id,
+            vm_id,
+            category: Some("network"),
+            ..logs::EventQuery::default()
+        },
+    )?;
+
+    println!("{}", serde_json::to_string_pretty(&build_har(&events))?);
+    Ok(())
+}
+
+/// Build a HAR 1.2 log from `network.http.request` and `network.http.response`
+/// events, paired by correlation ID. Requests that never got a response
+/// (e.g. still in flight when the database was read) are left out.
+fn build_har(events: &[Event]) -> Value {
+    let responses: HashMap<&str, &Event> = events
+        .iter()
+        .filter(|e| e.event_type == "network.http.response")
+        .filter_map(|e| Some((e.correlation_id.as_deref()?, e)))
+        .collect();
+
+    let entries: Vec<Value> = events
+        .iter()
+        .filter(|e| e.event_type == "network.http.request")
+        .filter_map(|req| {
+            let resp = responses.get(req.correlation_id.as_deref()?)?;
+            Some(har_entry(req, resp))
+        })
+        .collect();
+
+    json!({
+        "log": {
+            "version": "1.2",
+            "creator": { "name": "clawpot", "version": env!("CARGO_PKG_VERSION") },
+            "entries": entries,
+        }
+    })
+}
+
+fn har_entry(req: &Event, resp: &Event) -> Value {
+    let url = req.data["url"].as_str().unwrap_or_default();
+    let req_headers = parse_headers(&req.data["headers"]);
+    let resp_headers = parse_headers(&resp.data["resp_headers"]);
+    let time = resp.duration_ms.unwrap_or(0);
+
+    let mut request = json!({
+        "method": req.data["method"].as_str().unwrap_or_default(),
+        "url": url,
+        "httpVersion": "HTTP/1.1",
+        "cookies": [],
+        "headers": har_headers(&req_headers),
+        "queryString": query_string(url),
+        "headersSize": -1,
+        "bodySize": req.data["req_body_size"].as_i64().unwrap_or(-1),
+    });
+    if let Some(body) = read_body(&req.data["req_body_path"]) {
+        request["postData"] = json!({
+            "mimeType": header(&req_headers, "content-type").unwrap_or_default(),
+            "text": String::from_utf8_lossy(&body),
+        });
+    }
+
+    let body_size = resp.data["resp_body_size"].as_i64().unwrap_or(-1);
+    let mut content = json!({
+        "size": body_size.max(0),
+        "mimeType": header(&resp_headers, "content-type").unwrap_or_default(),
+    });
+    if let Some(body) = read_body(&resp.data["resp_body_path"]) {
+        match String::from_utf8(body) {
+            Ok(text) => content["text"] = json!(text),
+            Err(e) => {
+                content["text"] = json!(BASE64_STANDARD.encode(e.as_bytes()));
+                content["encoding"] = json!("base64");
+            }
+        }
+    }
+
+    json!({
+        "startedDateTime": req.timestamp,
+        "time": time,
+        "request": request,
+        "response": {
+            "status": resp.data["status_code"].as_u64().unwrap_or(0),
+            "statusText": "",
+            "httpVersion": "HTTP/1.1",
+            "cookies": [],
+            "headers": har_headers(&resp_headers),
+            "content": content,
+            "redirectURL": header(&resp_headers, "location").unwrap_or_default(),
+            "headersSize": -1,
+            "bodySize": body_size,
+        },
+        "cache": {},
+        "timings": { "send": 0, "wait": time, "receive": 0 },
+        "_vmId": req.vm_id,
+    })
+}
+
+/// Headers are stored as a JSON-encoded object; return them sorted by name.
+fn parse_headers(raw: &Value) -> Vec<(String, String)> {
+    let mut headers: Vec<(String, String)> = raw
+        .as_str()
+        .and_then(|s| serde_json::from_str::<HashMap<String, String>>(s).ok())
+        .unwrap_or_default()
+        .into_iter()
+        .collect();
+    headers.sort();
+    headers
+}
+
+fn header<'a>(headers: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    headers
+        .iter()
+        .find(|(n, _)| n.eq_ignore_ascii_case(name))
+        .map(|(_, v)| v.as_str())
+}
+
+fn har_headers(headers: &[(String, String)]) -> Vec<Value> {
+    headers
+        .iter()
+        .map(|(name, value)| json!({ "name": name, "value": value }))
+        .collect()
+}
+
+fn query_string(url: &str) -> Vec<Value> {
+    let Some((_, query)) = url.split_once('?') else {
+        return Vec::new();
+    };
+    let query = query.split('#').next().unwrap_or_default();
+    query
+        .split('&')
+        .filter(|pair| !pair.is_empty())
+        .map(|pair| {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            json!({ "name": name, "value": value })
+        })
+        .collect()
+}
+
+/// Bodies over the inline threshold are kept in the body store; smaller ones
+/// aren't persisted, so only their size is exported.
+fn read_body(path: &Value) -> Option<Vec<u8>> {
+    std::fs::read(path.as_str()?).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(event_type: &str, corr: &str, duration_ms: Option<i64>, data: Value) -> Event {
+        Event {
+            id: 1,
+            session_id: "session".to_string(),
+            timestamp: "2026-01-01T12:34:56.789Z".to_string(),
+            category: "network".to_string(),
+            event_type: event_type.to_string(),
+            vm_id: Some("vm-1".to_string()),
+            correlation_id: Some(corr.to_string()),
+            duration_ms,
+            success: None,
+            data,
+        }
+    }
+
+    #[test]
+    fn test_build_har() {
+        let body_path =
+            std::env::temp_dir().join(format!("clawpot-har-{}.bin", std::process::id()));
+        std::fs::write(&body_path, [0xff, 0x00, 0x01]).unwrap();
+
+        let events = vec![
+            event(
+                "network.http.request",
+                "a",
+                None,
+                json!({
+                    "method": "GET",
+                    "url": "http://example.com/search?q=rust&page=2",
+                    "headers": r#"{"user-agent":"curl/8.5.0","host":"example.com"}"#,
+                    "req_body_size": 0,
+                }),
+            ),
+            event(
+                "network.http.response",
+                "a",
+                Some(42),
+                json!({
+                    "status_code": 200,
+                    "resp_body_size": 3,
+                    "resp_body_path": body_path.to_str().unwrap(),
+                    "resp_headers": r#"{"content-type":"application/octet-stream"}"#,
+                }),
+            ),
+            // No response yet
+            event(
+                "network.http.request",
+                "b",
+                None,
+                json!({ "method": "POST", "url": "http://example.com/" }),
+            ),
+        ];
+
+        let har = build_har(&events);
+        std::fs::remove_file(&body_path).unwrap();
+
+        let entries = har["log"]["entries"].as_array().unwrap();
+        assert_eq!(entries.len(), 1);
+        let entry = &entries[0];
+        assert_eq!(entry["time"], 42);
+        assert_eq!(entry["request"]["method"], "GET");
+        assert_eq!(entry["request"]["headers"][0]["name"], "host");
+        assert_eq!(
+            entry["request"]["queryString"],
+            json!([{ "name": "q", "value": "rust" }, { "name": "page", "value": "2" }])
+        );
+        assert_eq!(entry["response"]["status"], 200);
+        assert_eq!(
+            entry["response"]["content"]["mimeType"],
+            "application/octet-stream"
+        );
+        assert_eq!(entry["response"]["content"]["encoding"], "base64");
+        assert_eq!(entry["response"]["content"]["text"], "/wAB");
+    }
+}
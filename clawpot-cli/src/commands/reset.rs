@@ -0,0 +1,17 @@
+use anyhow::Result;
+use clawpot_common::proto::{clawpot_service_client::ClawpotServiceClient, ResetVmRequest};
+use tonic::transport::Channel;
+
+pub async fn execute(client: &mut ClawpotServiceClient<Channel>, vm_id: String) -> Result<()> {
+    println!("Resetting VM {vm_id}...");
+    let response = client
+        .reset_vm(ResetVmRequest { vm_id })
+        .await?
+        .into_inner();
+    if response.returned_to_pool {
+        println!("\n✓ VM cleaned up and returned to the pool");
+    } else {
+        println!("\n✓ VM deleted instead of pooled: {}", response.reason);
+    }
+    Ok(())
+}
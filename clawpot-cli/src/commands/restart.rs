@@ -0,0 +1,16 @@
+use anyhow::Result;
+use clawpot_common::proto::{clawpot_service_client::ClawpotServiceClient, RebootVmRequest};
+use tonic::transport::Channel;
+
+pub async fn execute(client: &mut ClawpotServiceClient<Channel>, vm_id: String) -> Result<()> {
+    println!("Rebooting VM {vm_id}...");
+    let response = client
+        .reboot_vm(RebootVmRequest { vm_id })
+        .await?
+        .into_inner();
+    println!("\n✓ VM rebooted");
+    if !response.agent_ready {
+        println!("  Warning: the guest agent did not come back; exec may fail");
+    }
+    Ok(())
+}
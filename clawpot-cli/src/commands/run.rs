@@ -0,0 +1,82 @@
+use anyhow::{Context, Result};
+use clawpot_common::proto::{clawpot_service_client::ClawpotServiceClient, ExecVmRequest};
+use std::collections::HashMap;
+use std::io::Write;
+use std::path::Path;
+use tonic::transport::Channel;
+
+/// Shell run in the guest: saves the script from stdin to a temp file, runs
+/// it with the interpreter given as `$1` and removes it again, keeping the
+/// script's exit code.
+const RUN_WRAPPER: &str = r#"script=$(mktemp) || exit 1
+trap 'rm -f "$script"' EXIT
+cat > "$script" && chmod +x "$script" || exit 1
+interpreter=$1
+shift
+"$interpreter" "$script" "$@""#;
+
+/// `sh -c` arguments running a script with `interpreter` and `args`
+fn wrapper_args(interpreter: &str, args: Vec<String>) -> Vec<String> {
+    let mut wrapper = vec![
+        "-c".to_string(),
+        RUN_WRAPPER.to_string(),
+        "clawpot-run".to_string(),
+        interpreter.to_string(),
+    ];
+    wrapper.extend(args);
+    wrapper
+}
+
+/// Upload a local script and run it in the VM in a single exec.
+pub async fn execute(
+    client: &mut ClawpotServiceClient<Channel>,
+    vm_id: String,
+    script: &Path,
+    interpreter: &str,
+    args: Vec<String>,
+) -> Result<()> {
+    let body = std::fs::read(script)
+        .with_context(|| format!("Failed to read script {}", script.display()))?;
+
+    let request = ExecVmRequest {
+        vm_id,
+        command: "sh".to_string(),
+        args: wrapper_args(interpreter, args),
+        env: HashMap::new(),
+        working_dir: String::new(),
+        stdin: Some(body),
+    };
+
+    let response = client.exec_vm(request).await?.into_inner();
+
+    std::io::stdout().write_all(&response.stdout)?;
+    std::io::stderr().write_all(&response.stderr)?;
+
+    std::process::exit(response.exit_code);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_wrapper_runs_script_with_args() {
+        let args = wrapper_args("sh", vec!["one".to_string(), "two words".to_string()]);
+        let output = std::process::Command::new("sh")
+            .args(&args)
+            .stdin(std::process::Stdio::piped())
+            .stdout(std::process::Stdio::piped())
+            .spawn()
+            .and_then(|mut child| {
+                child
+                    .stdin
+                    .take()
+                    .unwrap()
+                    .write_all(b"echo \"$#:$2\"\nexit 3\n")?;
+                child.wait_with_output()
+            })
+            .unwrap();
+        assert_eq!(output.stdout, b"2:two words\n");
+        assert_eq!(output.status.code(), Some(3));
+    }
+}
@@ -0,0 +1,246 @@
+use anyhow::{bail, Context, Result};
+use clawpot_common::proto::{
+    clawpot_service_client::ClawpotServiceClient, CreateVmRequest, DeleteVmRequest, ExecVmRequest,
+    ExecVmResponse,
+};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+use tonic::transport::Channel;
+
+/// How long to wait for the guest agent to answer after the VM is created
+const AGENT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// Outcome of a single self-test step.
+struct StepResult {
+    name: &'static str,
+    passed: bool,
+    detail: String,
+}
+
+/// Collects step results and prints each one as it completes.
+#[derive(Default)]
+struct Report {
+    steps: Vec<StepResult>,
+}
+
+impl Report {
+    /// Record a step outcome. Returns whether it passed.
+    fn record(&mut self, name: &'static str, start: Instant, outcome: Result<String>) -> bool {
+        let elapsed = start.elapsed();
+        let (passed, detail) = match outcome {
+            Ok(detail) => (true, detail),
+            Err(e) => (false, format!("{e:#}")),
+        };
+
+        println!(
+            "{} {:<22} {:>8} ms  {}",
+            if passed { "✓" } else { "✗" },
+            name,
+            elapsed.as_millis(),
+            detail
+        );
+
+        self.steps.push(StepResult {
+            name,
+            passed,
+            detail,
+        });
+        passed
+    }
+
+    /// Record a step that was not run because an earlier step failed.
+    fn skip(&mut self, name: &'static str) {
+        println!("- {name:<22} (skipped)");
+        self.steps.push(StepResult {
+            name,
+            passed: false,
+            detail: "skipped".to_string(),
+        });
+    }
+}
+
+pub async fn execute(client: &mut ClawpotServiceClient<Channel>, url: &str) -> Result<()> {
+    println!("Running clawpot self-test...\n");
+
+    let total = Instant::now();
+    let mut report = Report::default();
+
+    let start = Instant::now();
+    let vm_id = match create_vm(client).await {
+        Ok(vm_id) => {
+            report.record("create VM", start, Ok(vm_id.clone()));
+            Some(vm_id)
+        }
+        Err(e) => {
+            report.record("create VM", start, Err(e));
+            None
+        }
+    };
+
+    if let Some(vm_id) = &vm_id {
+        let start = Instant::now();
+        let agent_ready =
+            report.record("wait for agent", start, wait_for_agent(client, vm_id).await);
+
+        if agent_ready {
+            let start = Instant::now();
+            report.record("exec echo", start, exec_echo(client, vm_id).await);
+
+            let start = Instant::now();
+            report.record(
+                "HTTP via proxy",
+                start,
+                http_request(client, vm_id, url).await,
+            );
+        } else {
+            report.skip("exec echo");
+            report.skip("HTTP via proxy");
+        }
+
+        // Always clean up the VM, even if earlier steps failed
+        let start = Instant::now();
+        report.record("delete VM", start, delete_vm(client, vm_id).await);
+    } else {
+        for name in ["wait for agent", "exec echo", "HTTP via proxy", "delete VM"] {
+            report.skip(name);
+        }
+    }
+
+    let passed = report.steps.iter().filter(|s| s.passed).count();
+    println!(
+        "\n{} of {} steps passed in {:.1}s",
+        passed,
+        report.steps.len(),
+        total.elapsed().as_secs_f64()
+    );
+
+    if let Some(first) = report.steps.iter().find(|s| !s.passed) {
+        bail!("Self-test failed at '{}': {}", first.name, first.detail);
+    }
+
+    println!("\n✓ Self-test passed");
+    Ok(())
+}
+
+pub(super) async fn create_vm(client: &mut ClawpotServiceClient<Channel>) -> Result<String> {
+    let response = client
+        .create_vm(CreateVmRequest::default())
+        .await
+        .context("CreateVM failed")?;
+    Ok(response.into_inner().vm_id)
+}
+
+/// Poll the agent with a no-op command until it answers or the timeout expires.
+pub(super) async fn wait_for_agent(
+    client: &mut ClawpotServiceClient<Channel>,
+    vm_id: &str,
+) -> Result<String> {
+    let deadline = Instant::now() + AGENT_TIMEOUT;
+    let mut attempts = 0;
+
+    loop {
+        attempts += 1;
+        match exec(client, vm_id, "true", &[]).await {
+            Ok(resp) if resp.exit_code == 0 => {
+                return Ok(format!("ready after {attempts} attempt(s)"));
+            }
+            Ok(resp) => bail!("agent ran `true` but it exited with {}", resp.exit_code),
+            Err(e) if Instant::now() >= deadline => {
+                return Err(e.context(format!(
+                    "agent not ready after {}s",
+                    AGENT_TIMEOUT.as_secs()
+                )));
+            }
+            Err(_) => tokio::time::sleep(Duration::from_secs(1)).await,
+        }
+    }
+}
+
+async fn exec_echo(client: &mut ClawpotServiceClient<Channel>, vm_id: &str) -> Result<String> {
+    let resp = exec(client, vm_id, "echo", &["hello"]).await?;
+    let stdout = String::from_utf8_lossy(&resp.stdout);
+    if resp.exit_code != 0 || stdout.trim() != "hello" {
+        bail!(
+            "expected 'hello', got exit code {} and output {:?}",
+            resp.exit_code,
+            stdout.trim()
+        );
+    }
+    Ok("hello".to_string())
+}
+
+/// Fetch a URL from inside the guest, exercising DNS and the HTTP proxy.
+pub(super) async fn http_request(
+    client: &mut ClawpotServiceClient<Channel>,
+    vm_id: &str,
+    url: &str,
+) -> Result<String> {
+    let resp = exec(
+        client,
+        vm_id,
+        "curl",
+        &[
+            "-4",
+            "-sS",
+            "--max-time",
+            "15",
+            "-o",
+            "/dev/null",
+            "-w",
+            "%{http_code}",
+            url,
+        ],
+    )
+    .await?;
+
+    let status = String::from_utf8_lossy(&resp.stdout).trim().to_string();
+    if resp.exit_code != 0 {
+        bail!(
+            "curl exited with {}: {}",
+            resp.exit_code,
+            String::from_utf8_lossy(&resp.stderr).trim()
+        );
+    }
+    match status.parse::<u16>() {
+        Ok(code) if code < 400 => Ok(format!("{url} -> HTTP {code}")),
+        _ => bail!("{url} returned HTTP {status}"),
+    }
+}
+
+pub(super) async fn delete_vm(
+    client: &mut ClawpotServiceClient<Channel>,
+    vm_id: &str,
+) -> Result<String> {
+    let resp = client
+        .delete_vm(DeleteVmRequest {
+            vm_id: vm_id.to_string(),
+        })
+        .await
+        .context("DeleteVM failed")?
+        .into_inner();
+
+    if !resp.success {
+        bail!("resources left behind: {}", resp.leaked.join(", "));
+    }
+    Ok(String::new())
+}
+
+async fn exec(
+    client: &mut ClawpotServiceClient<Channel>,
+    vm_id: &str,
+    command: &str,
+    args: &[&str],
+) -> Result<ExecVmResponse> {
+    let response = client
+        .exec_vm(ExecVmRequest {
+            vm_id: vm_id.to_string(),
+            command: command.to_string(),
+            args: args.iter().map(ToString::to_string).collect(),
+            env: HashMap::new(),
+            working_dir: String::new(),
+            stdin: None,
+        })
+        .await
+        .with_context(|| format!("ExecVM `{command}` failed"))?;
+    Ok(response.into_inner())
+}
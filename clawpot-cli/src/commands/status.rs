@@ -0,0 +1,22 @@
+use super::list::state_name;
+use anyhow::Result;
+use clawpot_common::proto::{clawpot_service_client::ClawpotServiceClient, GetVmStatusRequest};
+use tonic::transport::Channel;
+
+pub async fn execute(client: &mut ClawpotServiceClient<Channel>, vm_id: String) -> Result<()> {
+    let status = client
+        .get_vm_status(GetVmStatusRequest { vm_id })
+        .await?
+        .into_inner();
+
+    let firecracker_state = if status.firecracker_state.is_empty() {
+        "unreachable"
+    } else {
+        &status.firecracker_state
+    };
+    println!("State:             {}", state_name(status.state));
+    println!("Lifecycle state:   {}", state_name(status.lifecycle_state));
+    println!("Firecracker state: {firecracker_state}");
+    println!("Uptime:            {}s", status.uptime_secs);
+    Ok(())
+}
@@ -1,16 +1,17 @@
 mod commands;
+mod transport;
 
 use anyhow::Result;
 use clap::{Parser, Subcommand};
 use clawpot_common::proto::clawpot_service_client::ClawpotServiceClient;
-use tonic::transport::Channel;
+use std::collections::HashMap;
 
 #[derive(Parser)]
 #[command(name = "clawpot")]
 #[command(version = "0.1.0")]
 #[command(about = "Clawpot multi-VM orchestration CLI", long_about = None)]
 struct Cli {
-    /// Server address
+    /// Server address: an http:// URL, or unix:///path for the server's Unix socket
     #[arg(long, default_value = "http://127.0.0.1:50051", global = true)]
     server: String,
 
@@ -22,6 +23,11 @@ struct Cli {
 enum Commands {
     /// Create a new VM
     Create {
+        /// Take an idle pre-booted VM from the server's pool if one is
+        /// ready (only --name and --label can be combined with it)
+        #[arg(long, conflicts_with_all = ["vcpus", "memory", "rx_limit", "tx_limit", "ip", "drives", "balloon"])]
+        from_pool: bool,
+
         /// Number of vCPUs (default: 1)
         #[arg(long)]
         vcpus: Option<u32>,
@@ -29,48 +35,333 @@ enum Commands {
         /// Memory in MiB (default: 256)
         #[arg(long)]
         memory: Option<u32>,
+
+        /// Limit traffic into the VM, in bytes per second (default: unlimited)
+        #[arg(long)]
+        rx_limit: Option<u64>,
+
+        /// Limit traffic out of the VM, in bytes per second (default: unlimited)
+        #[arg(long)]
+        tx_limit: Option<u64>,
+
+        /// Friendly name shown in logs (lowercase letters, digits and hyphens)
+        #[arg(long)]
+        name: Option<String>,
+
+        /// Pin the VM to this IP address (default: next free address)
+        #[arg(long)]
+        ip: Option<String>,
+
+        /// Attach a data drive from a path on the server host; append `:ro`
+        /// for read-only. Repeat for more drives
+        #[arg(long = "drive", value_name = "PATH[:ro]")]
+        drives: Vec<String>,
+
+        /// Attach a memory balloon inflated to this many MiB, so memory can
+        /// be reclaimed later with `clawpot memory` (default: no balloon)
+        #[arg(long, value_name = "MIB")]
+        balloon: Option<u32>,
+
+        /// Label the VM for `clawpot list --selector`. Repeat for more labels
+        #[arg(long = "label", value_name = "KEY=VALUE", value_parser = commands::create::parse_label)]
+        labels: Vec<(String, String)>,
     },
 
     /// Delete a VM
     Delete {
-        /// VM ID to delete
+        /// VM ID or name to delete
+        vm_id: String,
+    },
+
+    /// Clean up a VM taken with `create --from-pool` and return it to the
+    /// pool (best-effort; the root filesystem is not restored)
+    Reset {
+        /// VM ID or name to reset
         vm_id: String,
     },
 
     /// List all VMs
-    List,
+    List {
+        /// Only show VMs with all of these labels, e.g. `tier=frontend,env=prod`
+        #[arg(long, value_parser = commands::list::parse_selector)]
+        selector: Option<HashMap<String, String>>,
+    },
+
+    /// Show a VM's state as reported by Firecracker
+    Status {
+        /// VM ID or name
+        vm_id: String,
+    },
+
+    /// Show everything the server knows about a VM, with its recent events
+    Describe {
+        /// VM ID or name
+        vm_id: String,
+
+        /// Recent events to show
+        #[arg(long, default_value_t = 10)]
+        events: u32,
+    },
+
+    /// Print a VM's serial console output as it arrives
+    Console {
+        /// VM ID or name
+        vm_id: String,
+    },
+
+    /// Pause a running VM
+    Pause {
+        /// VM ID or name to pause
+        vm_id: String,
+    },
+
+    /// Resume a paused VM
+    Resume {
+        /// VM ID or name to resume
+        vm_id: String,
+    },
+
+    /// Reboot a VM in place, keeping its ID, IP address and TAP device
+    Restart {
+        /// VM ID or name to reboot
+        vm_id: String,
+    },
+
+    /// Resize a VM's memory balloon (the guest keeps its memory minus the balloon)
+    Memory {
+        /// VM ID or name
+        vm_id: String,
+
+        /// Target balloon size in MiB
+        balloon_mib: u32,
+    },
 
     /// Execute a command in a VM
     Exec {
-        /// VM ID
+        /// VM ID or name
         vm_id: String,
 
+        /// Stream stdin to the command and print output as it arrives
+        #[arg(short, long)]
+        interactive: bool,
+
+        /// Don't read stdin; the command gets empty input (for use in
+        /// `while read` loops and scripts)
+        #[arg(short, long)]
+        no_stdin: bool,
+
         /// Command and arguments to execute
         #[arg(last = true)]
         command: Vec<String>,
     },
 
+    /// Watch the output of another client's `exec --interactive` (read-only)
+    Attach {
+        /// Session ID printed by `exec --interactive`
+        session_id: String,
+    },
+
+    /// Upload a local script to a VM and run it, printing its output
+    Run {
+        /// VM ID or name
+        vm_id: String,
+
+        /// Script file to run
+        script: std::path::PathBuf,
+
+        /// Program the script is run with inside the VM
+        #[arg(long, default_value = "sh")]
+        interpreter: String,
+
+        /// Arguments passed to the script
+        #[arg(last = true)]
+        args: Vec<String>,
+    },
+
     /// Query event logs from the events database
     Logs {
         #[command(subcommand)]
         action: LogsAction,
     },
+
+    /// Run a smoke test of the full VM lifecycle against the server
+    Selftest {
+        /// URL fetched from inside the VM to exercise the proxy
+        #[arg(long, default_value = "http://example.com")]
+        url: String,
+    },
+
+    /// Benchmark VM boot time and proxied request latency
+    Bench {
+        /// Number of VMs to create
+        #[arg(long, default_value_t = 1)]
+        vms: usize,
+
+        /// Number of proxied requests to run in each VM
+        #[arg(long, default_value_t = 10)]
+        requests: usize,
+
+        /// URL fetched from inside each VM
+        #[arg(long, default_value = "http://example.com")]
+        url: String,
+
+        /// Print the results as JSON
+        #[arg(long)]
+        json: bool,
+    },
+
+    /// Show the iptables rules clawpot has installed on the host
+    NetRules,
+
+    /// Show server version, identity and degraded subsystems
+    Info,
+
+    /// Find and clean up TAP devices and sockets leaked by past VMs
+    Orphans {
+        #[command(subcommand)]
+        action: OrphansAction,
+    },
+
+    /// Stop or resume accepting new VMs, e.g. before replacing the server
+    Drain {
+        #[command(subcommand)]
+        action: DrainAction,
+    },
+
+    /// Export HTTP requests captured by the proxy
+    Requests {
+        #[command(subcommand)]
+        action: RequestsAction,
+    },
+
+    /// Manage server-side LLM settings
+    Llm {
+        #[command(subcommand)]
+        action: LlmAction,
+    },
+
+    /// Inspect the clawpot certificate authority
+    Ca {
+        #[command(subcommand)]
+        action: CaAction,
+    },
 }
 
 #[derive(Subcommand)]
-enum LogsAction {
-    /// List all server sessions
-    Sessions {
+enum CaAction {
+    /// Check that a rootfs trusts the current CA (needed for HTTPS interception)
+    Check {
+        /// Path to an ext4 rootfs image, or a mounted/extracted rootfs directory
+        rootfs: String,
+
+        /// Trust store path inside the rootfs
+        #[arg(long, default_value = commands::ca::DEFAULT_TRUST_STORE)]
+        trust_store: String,
+
+        /// CA certificate to look for (default: $CLAWPOT_ROOT/ca/ca.crt)
+        #[arg(long)]
+        ca: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum LlmAction {
+    /// Manage provider API keys stored in the data directory
+    Keys {
+        #[command(subcommand)]
+        action: LlmKeysAction,
+    },
+}
+
+#[derive(Subcommand)]
+enum LlmKeysAction {
+    /// Store a provider's API key
+    Set {
+        /// Provider name (anthropic, openai)
+        provider: String,
+
+        /// API key; read from stdin if omitted
+        key: Option<String>,
+
+        /// Path to the key file
+        #[arg(long)]
+        file: Option<String>,
+    },
+
+    /// Show which providers have a stored key (never the key itself)
+    List {
+        /// Path to the key file
+        #[arg(long)]
+        file: Option<String>,
+    },
+
+    /// Remove a provider's stored key, or all keys if no provider is given
+    Clear {
+        /// Provider name (anthropic, openai)
+        provider: Option<String>,
+
+        /// Path to the key file
+        #[arg(long)]
+        file: Option<String>,
+    },
+}
+
+#[derive(Subcommand)]
+enum OrphansAction {
+    /// List orphaned resources
+    List,
+
+    /// Remove all orphaned resources
+    Clean,
+}
+
+#[derive(Subcommand)]
+enum DrainAction {
+    /// Reject new VMs; existing VMs keep running
+    On,
+
+    /// Accept new VMs again
+    Off,
+}
+
+#[derive(Subcommand)]
+enum RequestsAction {
+    /// Write requests and responses as an HTTP Archive (HAR) to stdout
+    Export {
         /// Path to the events database
         #[arg(long)]
         db: Option<String>,
+
+        /// Filter by session ID
+        #[arg(long)]
+        session: Option<String>,
+
+        /// Filter by VM ID
+        #[arg(long)]
+        vm: Option<String>,
+
+        /// Output format: har (default)
+        #[arg(long, default_value = "har")]
+        format: String,
+    },
+}
+
+#[derive(Subcommand)]
+enum LogsAction {
+    /// List all server sessions
+    Sessions {
+        /// Events databases to read; repeatable, accepts several paths and
+        /// directories of `*.db` files (e.g. archived databases)
+        #[arg(long, num_args = 1..)]
+        db: Vec<String>,
     },
 
     /// Show events (filtered)
     Show {
-        /// Path to the events database
-        #[arg(long)]
-        db: Option<String>,
+        /// Events databases to read; repeatable, accepts several paths and
+        /// directories of `*.db` files (e.g. archived databases)
+        #[arg(long, num_args = 1..)]
+        db: Vec<String>,
 
         /// Filter by session ID
         #[arg(long)]
@@ -88,6 +379,23 @@ enum LogsAction {
         #[arg(long, name = "type")]
         event_type: Option<String>,
 
+        /// Filter by correlation ID, e.g. every step of one VM create
+        #[arg(long)]
+        correlation: Option<String>,
+
+        /// With --vm, also show traffic not attributed to any VM
+        #[arg(long, requires = "vm")]
+        include_unknown: bool,
+
+        /// Only show traffic from sources that aren't registered VMs
+        #[arg(long, conflicts_with_all = ["vm", "include_unknown"])]
+        only_unknown: bool,
+
+        /// Filter by an indexed HTTP header, e.g. `user-agent=curl/8.5.0`
+        /// (the server must list it in CLAWPOT_INDEXED_HEADERS)
+        #[arg(long, value_parser = commands::logs::parse_header_filter)]
+        header: Option<(String, String)>,
+
         /// Limit number of results
         #[arg(long)]
         limit: Option<i64>,
@@ -95,9 +403,10 @@ enum LogsAction {
 
     /// Export events as JSONL or JSON
     Export {
-        /// Path to the events database
-        #[arg(long)]
-        db: Option<String>,
+        /// Events databases to read; repeatable, accepts several paths and
+        /// directories of `*.db` files (e.g. archived databases)
+        #[arg(long, num_args = 1..)]
+        db: Vec<String>,
 
         /// Filter by session ID
         #[arg(long)]
@@ -106,13 +415,18 @@ enum LogsAction {
         /// Output format: jsonl (default) or json
         #[arg(long, default_value = "jsonl")]
         format: String,
+
+        /// Replace IP addresses and VM IDs with hashes that are stable within this export
+        #[arg(long)]
+        anonymize: bool,
     },
 
     /// Show a human-readable chronological timeline
     Timeline {
-        /// Path to the events database
-        #[arg(long)]
-        db: Option<String>,
+        /// Events databases to read; repeatable, accepts several paths and
+        /// directories of `*.db` files (e.g. archived databases)
+        #[arg(long, num_args = 1..)]
+        db: Vec<String>,
 
         /// Filter by session ID
         #[arg(long)]
@@ -131,53 +445,212 @@ async fn main() -> Result<()> {
     // Handle logs command without gRPC connection
     if let Commands::Logs { action } = &cli.command {
         return match action {
-            LogsAction::Sessions { db } => commands::logs::execute_sessions(db.as_deref()),
+            LogsAction::Sessions { db } => commands::logs::execute_sessions(db),
             LogsAction::Show {
                 db,
                 session,
                 vm,
                 category,
                 event_type,
+                correlation,
+                include_unknown,
+                only_unknown,
+                header,
                 limit,
             } => commands::logs::execute_show(
-                db.as_deref(),
-                session.as_deref(),
-                vm.as_deref(),
-                category.as_deref(),
-                event_type.as_deref(),
-                *limit,
+                db,
+                &commands::logs::EventQuery {
+                    session_id: session.as_deref(),
+                    vm_id: vm.as_deref(),
+                    category: category.as_deref(),
+                    event_type: event_type.as_deref(),
+                    correlation_id: correlation.as_deref(),
+                    header: header.as_ref().map(|(n, v)| (n.as_str(), v.as_str())),
+                    include_unknown: *include_unknown,
+                    only_unknown: *only_unknown,
+                    limit: *limit,
+                },
             ),
             LogsAction::Export {
                 db,
                 session,
                 format,
-            } => commands::logs::execute_export(db.as_deref(), session.as_deref(), format),
+                anonymize,
+            } => commands::logs::execute_export(db, session.as_deref(), format, *anonymize),
             LogsAction::Timeline { db, session, vm } => {
-                commands::logs::execute_timeline(db.as_deref(), session.as_deref(), vm.as_deref())
+                commands::logs::execute_timeline(db, session.as_deref(), vm.as_deref())
+            }
+        };
+    }
+
+    // Handle request export without gRPC connection
+    if let Commands::Requests {
+        action:
+            RequestsAction::Export {
+                db,
+                session,
+                vm,
+                format,
+            },
+    } = &cli.command
+    {
+        return commands::requests::execute_export(
+            db.as_deref(),
+            session.as_deref(),
+            vm.as_deref(),
+            format,
+        );
+    }
+
+    // Handle LLM key management without gRPC connection
+    if let Commands::Llm {
+        action: LlmAction::Keys { action },
+    } = &cli.command
+    {
+        return match action {
+            LlmKeysAction::Set {
+                provider,
+                key,
+                file,
+            } => commands::llm::execute_set(file.as_deref(), provider, key.as_deref()),
+            LlmKeysAction::List { file } => commands::llm::execute_list(file.as_deref()),
+            LlmKeysAction::Clear { provider, file } => {
+                commands::llm::execute_clear(file.as_deref(), provider.as_deref())
             }
         };
     }
 
+    if let Commands::Ca {
+        action:
+            CaAction::Check {
+                rootfs,
+                trust_store,
+                ca,
+            },
+    } = &cli.command
+    {
+        return commands::ca::execute_check(rootfs, trust_store, ca.as_deref());
+    }
+
     // Connect to gRPC server
-    let channel = Channel::from_shared(cli.server.clone())?.connect().await?;
+    let channel = transport::connect(&cli.server).await?;
 
-    let mut client = ClawpotServiceClient::new(channel);
+    let mut client = ClawpotServiceClient::new(channel)
+        .max_decoding_message_size(clawpot_common::grpc::max_message_size_from_env());
+    if let Some(encoding) = clawpot_common::grpc::compression_from_env() {
+        client = client.send_compressed(encoding).accept_compressed(encoding);
+    }
 
     // Execute command
     match cli.command {
-        Commands::Create { vcpus, memory } => {
-            commands::create::execute(&mut client, vcpus, memory).await?;
+        Commands::Create {
+            from_pool,
+            vcpus,
+            memory,
+            rx_limit,
+            tx_limit,
+            name,
+            ip,
+            drives,
+            balloon,
+            labels,
+        } => {
+            commands::create::execute(
+                &mut client,
+                vcpus,
+                memory,
+                rx_limit,
+                tx_limit,
+                name,
+                ip,
+                &drives,
+                balloon,
+                labels,
+                from_pool,
+            )
+            .await?;
         }
         Commands::Delete { vm_id } => {
             commands::delete::execute(&mut client, vm_id).await?;
         }
-        Commands::List => {
-            commands::list::execute(&mut client).await?;
+        Commands::Reset { vm_id } => {
+            commands::reset::execute(&mut client, vm_id).await?;
+        }
+        Commands::List { selector } => {
+            commands::list::execute(&mut client, selector.unwrap_or_default()).await?;
+        }
+        Commands::Status { vm_id } => {
+            commands::status::execute(&mut client, vm_id).await?;
+        }
+        Commands::Describe { vm_id, events } => {
+            commands::describe::execute(&mut client, vm_id, events).await?;
+        }
+        Commands::Console { vm_id } => {
+            commands::console::execute(&mut client, vm_id).await?;
+        }
+        Commands::Pause { vm_id } => {
+            commands::pause::execute_pause(&mut client, vm_id).await?;
+        }
+        Commands::Resume { vm_id } => {
+            commands::pause::execute_resume(&mut client, vm_id).await?;
+        }
+        Commands::Restart { vm_id } => {
+            commands::restart::execute(&mut client, vm_id).await?;
+        }
+        Commands::Memory { vm_id, balloon_mib } => {
+            commands::memory::execute(&mut client, vm_id, balloon_mib).await?;
+        }
+        Commands::Exec {
+            vm_id,
+            interactive,
+            no_stdin,
+            command,
+        } => {
+            if interactive {
+                commands::exec::execute_interactive(&mut client, vm_id, command, !no_stdin).await?;
+            } else {
+                commands::exec::execute(&mut client, vm_id, command, !no_stdin).await?;
+            }
+        }
+        Commands::Attach { session_id } => {
+            commands::attach::execute(&mut client, session_id).await?;
+        }
+        Commands::Run {
+            vm_id,
+            script,
+            interpreter,
+            args,
+        } => {
+            commands::run::execute(&mut client, vm_id, &script, &interpreter, args).await?;
+        }
+        Commands::Selftest { url } => {
+            commands::selftest::execute(&mut client, &url).await?;
+        }
+        Commands::Bench {
+            vms,
+            requests,
+            url,
+            json,
+        } => {
+            commands::bench::execute(&mut client, vms, requests, &url, json).await?;
+        }
+        Commands::NetRules => {
+            commands::net_rules::execute(&mut client).await?;
+        }
+        Commands::Info => {
+            commands::info::execute(&mut client).await?;
         }
-        Commands::Exec { vm_id, command } => {
-            commands::exec::execute(&mut client, vm_id, command).await?;
+        Commands::Orphans { action } => match action {
+            OrphansAction::List => commands::orphans::execute_list(&mut client).await?,
+            OrphansAction::Clean => commands::orphans::execute_clean(&mut client).await?,
+        },
+        Commands::Drain { action } => {
+            commands::drain::execute(&mut client, matches!(action, DrainAction::On)).await?;
         }
-        Commands::Logs { .. } => unreachable!(),
+        Commands::Logs { .. }
+        | Commands::Requests { .. }
+        | Commands::Llm { .. }
+        | Commands::Ca { .. } => unreachable!(),
     }
 
     Ok(())
@@ -0,0 +1,54 @@
+use anyhow::{Context, Result};
+use std::path::PathBuf;
+use tokio::net::UnixStream;
+use tonic::transport::{Channel, Endpoint, Uri};
+use tower::service_fn;
+
+/// Prefix selecting the server's Unix socket (`CLAWPOT_LISTEN_UDS`) over TCP
+const UNIX_SCHEME: &str = "unix://";
+
+/// Socket path of a `unix://<path>` server address, or `None` for TCP addresses.
+fn unix_socket_path(server: &str) -> Option<PathBuf> {
+    server
+        .strip_prefix(UNIX_SCHEME)
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+}
+
+/// Open a channel to the server at `server`, either an `http://host:port`
+/// URL or `unix:///path/to/socket`.
+pub async fn connect(server: &str) -> Result<Channel> {
+    let Some(path) = unix_socket_path(server) else {
+        return Channel::from_shared(server.to_string())?
+            .connect()
+            .await
+            .with_context(|| format!("Failed to connect to {server}"));
+    };
+
+    Endpoint::try_from("http://[::]:50051")? // dummy URI, not actually used
+        .connect_with_connector(service_fn(move |_: Uri| {
+            let path = path.clone();
+            async move {
+                Ok::<_, std::io::Error>(hyper_util::rt::TokioIo::new(
+                    UnixStream::connect(path).await?,
+                ))
+            }
+        }))
+        .await
+        .with_context(|| format!("Failed to connect to {server}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unix_socket_path() {
+        assert_eq!(
+            unix_socket_path("unix:///run/clawpot/grpc.sock"),
+            Some(PathBuf::from("/run/clawpot/grpc.sock"))
+        );
+        assert_eq!(unix_socket_path("unix://"), None);
+        assert_eq!(unix_socket_path("http://127.0.0.1:50051"), None);
+    }
+}
@@ -1,6 +1,8 @@
 use crate::firecracker::models::{
-    BootSource, Drive, EntropyDevice, ErrorResponse, InstanceActionInfo, InstanceInfo,
-    MachineConfig, NetworkInterface, VsockDevice,
+    BalloonDevice, BalloonUpdate, BootSource, Drive, DriveUpdate, EntropyDevice, ErrorResponse,
+    FullVmConfig, InstanceActionInfo, InstanceInfo, MachineConfig, MemBackend, NetworkInterface,
+    NetworkOverride, SnapshotCreateParams, SnapshotLoadParams, SnapshotType, VmRunState,
+    VmStateUpdate, VsockDevice, VsockOverride,
 };
 use anyhow::{anyhow, Context, Result};
 use http_body_util::{BodyExt, Full};
@@ -9,9 +11,10 @@ use hyper::{Method, Request};
 use hyper_util::client::legacy::Client;
 use hyper_util::rt::TokioExecutor;
 use hyperlocal::{UnixConnector, Uri};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
 /// HTTP client for communicating with Firecracker API over Unix socket
+#[derive(Clone)]
 pub struct FirecrackerClient {
     socket_path: PathBuf,
     client: Client<UnixConnector, Full<Bytes>>,
@@ -42,11 +45,22 @@ impl FirecrackerClient {
     /// Make a PUT request to the Firecracker API
     #[tracing::instrument(name = "firecracker.put", skip(self, body), fields(path = %path))]
     async fn put<T: serde::Serialize>(&self, path: &str, body: &T) -> Result<()> {
+        self.send(Method::PUT, path, body).await
+    }
+
+    /// Make a PATCH request to the Firecracker API
+    #[tracing::instrument(name = "firecracker.patch", skip(self, body), fields(path = %path))]
+    async fn patch<T: serde::Serialize>(&self, path: &str, body: &T) -> Result<()> {
+        self.send(Method::PATCH, path, body).await
+    }
+
+    /// Send a JSON body with `method` and check the response status
+    async fn send<T: serde::Serialize>(&self, method: Method, path: &str, body: &T) -> Result<()> {
         let uri = self.build_uri(path)?;
         let json = serde_json::to_string(body).context("Failed to serialize request body")?;
 
         let request = Request::builder()
-            .method(Method::PUT)
+            .method(method)
             .uri(uri)
             .header("Content-Type", "application/json")
             .body(Full::new(Bytes::from(json)))
@@ -140,6 +154,17 @@ impl FirecrackerClient {
         self.put(&path, &drive).await.context("Failed to set drive")
     }
 
+    /// Point a drive of a booted or restored VM at another backing file
+    pub async fn update_drive_path(&self, drive_id: &str, path_on_host: &str) -> Result<()> {
+        let update = DriveUpdate {
+            drive_id: drive_id.to_string(),
+            path_on_host: path_on_host.to_string(),
+        };
+        self.patch(&format!("/drives/{drive_id}"), &update)
+            .await
+            .with_context(|| format!("Failed to update drive {drive_id}"))
+    }
+
     /// Set the machine configuration (CPU and memory)
     pub async fn set_machine_config(&self, config: MachineConfig) -> Result<()> {
         self.put("/machine-config", &config)
@@ -168,6 +193,13 @@ impl FirecrackerClient {
         self.get("/").await.context("Failed to get instance info")
     }
 
+    /// Get the VM's current configuration, including what a snapshot restored
+    pub async fn get_vm_config(&self) -> Result<FullVmConfig> {
+        self.get("/vm/config")
+            .await
+            .context("Failed to get VM configuration")
+    }
+
     /// Set a network interface configuration
     pub async fn set_network_interface(&self, iface: NetworkInterface) -> Result<()> {
         let path = format!("/network-interfaces/{}", iface.iface_id);
@@ -189,4 +221,100 @@ impl FirecrackerClient {
             .await
             .context("Failed to set entropy device")
     }
+
+    /// Attach a memory balloon device (before boot)
+    pub async fn set_balloon(&self, balloon: BalloonDevice) -> Result<()> {
+        self.put("/balloon", &balloon)
+            .await
+            .context("Failed to set balloon device")
+    }
+
+    /// Inflate or deflate the balloon of a booted VM to `target_mib`
+    pub async fn update_balloon(&self, target_mib: u32) -> Result<()> {
+        self.patch(
+            "/balloon",
+            &BalloonUpdate {
+                amount_mib: target_mib,
+            },
+        )
+        .await
+        .with_context(|| format!("Failed to resize balloon to {target_mib} MiB"))
+    }
+
+    /// Pause or resume a booted VM
+    pub async fn set_vm_state(&self, state: VmRunState) -> Result<()> {
+        self.patch("/vm", &VmStateUpdate { state })
+            .await
+            .with_context(|| format!("Failed to set VM state to {state:?}"))
+    }
+
+    /// Pause a running VM
+    pub async fn pause(&self) -> Result<()> {
+        self.set_vm_state(VmRunState::Paused)
+            .await
+            .context("Failed to pause VM")
+    }
+
+    /// Resume a paused VM
+    pub async fn resume(&self) -> Result<()> {
+        self.set_vm_state(VmRunState::Resumed)
+            .await
+            .context("Failed to resume VM")
+    }
+
+    /// Pause the VM and write a snapshot of its state and memory.
+    ///
+    /// The VM is left paused; call [`Self::resume`] to continue running it.
+    pub async fn create_snapshot(
+        &self,
+        snapshot_path: &Path,
+        mem_path: &Path,
+        snapshot_type: SnapshotType,
+    ) -> Result<()> {
+        let params = SnapshotCreateParams {
+            snapshot_type,
+            snapshot_path: snapshot_path_str(snapshot_path)?,
+            mem_file_path: snapshot_path_str(mem_path)?,
+        };
+
+        self.pause().await?;
+        self.put("/snapshot/create", &params)
+            .await
+            .context("Failed to create snapshot")
+    }
+
+    /// Load a snapshot into a freshly started Firecracker process.
+    ///
+    /// This must be the first API call made; the VM must not have been
+    /// configured or booted. `network_overrides` swaps the TAP devices the
+    /// snapshot was taken with for new ones; the interfaces themselves (IDs,
+    /// guest MACs) always come from the snapshot. `vsock_override` does the
+    /// same for the host socket of the vsock device.
+    pub async fn load_snapshot(
+        &self,
+        snapshot_path: &Path,
+        mem_path: &Path,
+        resume_vm: bool,
+        network_overrides: Vec<NetworkOverride>,
+        vsock_override: Option<VsockOverride>,
+    ) -> Result<()> {
+        let params = SnapshotLoadParams {
+            snapshot_path: snapshot_path_str(snapshot_path)?,
+            mem_backend: MemBackend::file(snapshot_path_str(mem_path)?),
+            enable_diff_snapshots: false,
+            resume_vm,
+            network_overrides,
+            vsock_override,
+        };
+
+        self.put("/snapshot/load", &params)
+            .await
+            .context("Failed to load snapshot")
+    }
+}
+
+fn snapshot_path_str(path: &Path) -> Result<String> {
+    path.to_str()
+        .map(String::from)
+        .ok_or_else(|| anyhow!("Invalid snapshot path {}", path.display()))
 }
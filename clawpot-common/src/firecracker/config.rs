@@ -1,4 +1,209 @@
-use std::path::PathBuf;
+use crate::firecracker::models::{BalloonDevice, IoEngine, RateLimiter};
+use std::net::IpAddr;
+use std::path::{Path, PathBuf};
+
+/// Lowest usable guest CID (0-2 are reserved for the hypervisor, local and host)
+pub const MIN_GUEST_CID: u32 = 3;
+
+/// Kernel command line used unless overridden with [`VmConfig::with_boot_args`]
+pub const DEFAULT_BOOT_ARGS: &str = "console=ttyS0 reboot=k panic=1 pci=off";
+
+/// Smallest memory size a VM can be configured with
+pub const MIN_MEM_SIZE_MIB: u32 = 128;
+
+/// Environment variable selecting how VMs use the shared rootfs image
+pub const ROOTFS_MODE_ENV: &str = "CLAWPOT_ROOTFS_MODE";
+
+/// How a VM's root drive relates to the shared rootfs image
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum RootfsMode {
+    /// Boot read-write from the shared image; guest writes persist into it
+    #[default]
+    Shared,
+    /// Boot from the shared image with the drive attached read-only
+    ReadOnly,
+    /// Boot read-write from a private per-VM copy of the image
+    CopyOnWrite,
+}
+
+impl RootfsMode {
+    /// Parse `shared`, `read-only` (or `ro`) or `copy-on-write` (or `cow`).
+    pub fn parse(value: &str) -> Option<Self> {
+        match value.trim().to_ascii_lowercase().as_str() {
+            "shared" => Some(Self::Shared),
+            "read-only" | "readonly" | "ro" => Some(Self::ReadOnly),
+            "copy-on-write" | "cow" => Some(Self::CopyOnWrite),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Shared => "shared",
+            Self::ReadOnly => "read-only",
+            Self::CopyOnWrite => "copy-on-write",
+        }
+    }
+
+    /// Whether Firecracker should attach the root drive read-only
+    pub fn is_read_only(self) -> bool {
+        self == Self::ReadOnly
+    }
+
+    /// Image the VM identified by `vm_key` boots from: `image` itself, or a
+    /// per-VM copy at `/tmp/fc-<vm_key>-rootfs.ext4` in `CopyOnWrite` mode.
+    pub fn path_on_host(self, image: &Path, vm_key: &str) -> PathBuf {
+        match self {
+            Self::Shared | Self::ReadOnly => image.to_path_buf(),
+            Self::CopyOnWrite => PathBuf::from(format!("/tmp/fc-{vm_key}-rootfs.ext4")),
+        }
+    }
+}
+
+/// Reasons a [`VmConfig`] can fail validation
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum ConfigError {
+    #[error("Kernel image not found: {}", .0.display())]
+    MissingKernel(PathBuf),
+    #[error("Rootfs image not found: {}", .0.display())]
+    MissingRootfs(PathBuf),
+    #[error("vCPU count must be at least 1 (got {0})")]
+    InvalidVcpu(u8),
+    #[error("Memory size must be at least {MIN_MEM_SIZE_MIB} MiB (got {0})")]
+    InvalidMemory(u32),
+    #[error("Guest CID must be at least {MIN_GUEST_CID} (got {0})")]
+    InvalidGuestCid(u32),
+    #[error("Data drive not found: {}", .0.display())]
+    MissingDrive(PathBuf),
+    #[error("Drive ID '{0}' is used more than once")]
+    DuplicateDriveId(String),
+    #[error("Balloon size {balloon_mib} MiB exceeds the VM's {mem_size_mib} MiB of memory")]
+    BalloonTooLarge { balloon_mib: u32, mem_size_mib: u32 },
+}
+
+impl ConfigError {
+    /// Whether the error was caused by caller-supplied values rather than host setup
+    pub fn is_invalid_argument(&self) -> bool {
+        matches!(
+            self,
+            Self::InvalidVcpu(_)
+                | Self::InvalidMemory(_)
+                | Self::MissingDrive(_)
+                | Self::DuplicateDriveId(_)
+                | Self::BalloonTooLarge { .. }
+        )
+    }
+}
+
+/// One kernel parameter: `key=value`, or a bare flag such as `quiet`
+#[derive(Debug, Clone, PartialEq, Eq)]
+struct BootArg {
+    key: String,
+    value: Option<String>,
+}
+
+impl BootArg {
+    fn parse(token: &str) -> Self {
+        match token.split_once('=') {
+            Some((key, value)) => Self {
+                key: key.to_string(),
+                value: Some(value.to_string()),
+            },
+            None => Self {
+                key: token.to_string(),
+                value: None,
+            },
+        }
+    }
+}
+
+/// A kernel command line kept as separate parameters, so settings added by
+/// different [`VmConfig`] builders can't overwrite each other. Renders the
+/// parameters in the order they were added, separated by single spaces.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BootArgs {
+    args: Vec<BootArg>,
+}
+
+impl BootArgs {
+    /// Split a command line such as `console=ttyS0 reboot=k quiet` into parameters.
+    pub fn parse(raw: &str) -> Self {
+        Self {
+            args: raw.split_whitespace().map(BootArg::parse).collect(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.args.is_empty()
+    }
+
+    /// Value of the last `key=value` parameter for `key`
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.args
+            .iter()
+            .rev()
+            .find(|arg| arg.key == key)
+            .and_then(|arg| arg.value.as_deref())
+    }
+
+    /// Set `key=value`. An earlier `key` keeps its place and takes the new
+    /// value, and any repeats of it are dropped; otherwise it is appended.
+    pub fn set(&mut self, key: &str, value: &str) {
+        let mut found = false;
+        self.args.retain_mut(|arg| {
+            if arg.key != key {
+                return true;
+            }
+            if found {
+                return false;
+            }
+            found = true;
+            arg.value = Some(value.to_string());
+            true
+        });
+        if !found {
+            self.args.push(BootArg {
+                key: key.to_string(),
+                value: Some(value.to_string()),
+            });
+        }
+    }
+
+    /// Append parameters parsed from `raw`, keeping any earlier ones with
+    /// the same keys (parameters such as `console=` may be repeated).
+    pub fn append(&mut self, raw: &str) {
+        self.args.extend(raw.split_whitespace().map(BootArg::parse));
+    }
+}
+
+impl std::fmt::Display for BootArgs {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, arg) in self.args.iter().enumerate() {
+            if i > 0 {
+                f.write_str(" ")?;
+            }
+            f.write_str(&arg.key)?;
+            if let Some(value) = &arg.value {
+                write!(f, "={value}")?;
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Drive ID Firecracker uses for the root filesystem
+pub const ROOT_DRIVE_ID: &str = "rootfs";
+
+/// A block device attached after the root drive (e.g. a scratch disk or dataset)
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ExtraDrive {
+    /// Unique identifier for the drive
+    pub drive_id: String,
+    /// Path to the image or block device on the host
+    pub host_path: PathBuf,
+    /// Whether the guest may only read the drive
+    pub read_only: bool,
+}
 
 /// VM configuration builder for Firecracker
 #[derive(Debug, Clone)]
@@ -11,8 +216,13 @@ pub struct VmConfig {
     pub vcpu_count: u8,
     /// Memory size in MiB (default: 256)
     pub mem_size_mib: u32,
-    /// Boot arguments for the kernel
-    pub boot_args: String,
+    /// Base boot arguments set by `new` or `with_boot_args`
+    base_boot_args: BootArgs,
+    /// Settings such as `ip=` made by `with_network` and `with_ipv6_disabled`,
+    /// applied over the base arguments
+    managed_boot_args: BootArgs,
+    /// Parameters added by `with_extra_boot_args`, appended last
+    extra_boot_args: BootArgs,
     /// TAP device name for networking
     pub tap_device: Option<String>,
     /// IP address for the VM
@@ -21,6 +231,20 @@ pub struct VmConfig {
     pub guest_cid: Option<u32>,
     /// Path to the host-side vsock Unix Domain Socket
     pub vsock_uds_path: Option<String>,
+    /// Rate limiter for the root drive
+    pub drive_rate_limiter: Option<RateLimiter>,
+    /// I/O engine for the root drive
+    pub drive_io_engine: Option<IoEngine>,
+    /// Rate limiter for traffic received by the guest
+    pub net_rx_rate_limiter: Option<RateLimiter>,
+    /// Rate limiter for traffic sent by the guest
+    pub net_tx_rate_limiter: Option<RateLimiter>,
+    /// How the root drive uses the rootfs image (default: shared)
+    pub rootfs_mode: RootfsMode,
+    /// Data drives attached after the root drive, in order
+    pub extra_drives: Vec<ExtraDrive>,
+    /// Memory balloon, for reclaiming guest memory after boot
+    pub balloon: Option<BalloonDevice>,
 }
 
 impl VmConfig {
@@ -36,11 +260,20 @@ impl VmConfig {
             rootfs_path,
             vcpu_count: 1,
             mem_size_mib: 256,
-            boot_args: "console=ttyS0 reboot=k panic=1 pci=off".to_string(),
+            base_boot_args: BootArgs::parse(DEFAULT_BOOT_ARGS),
+            managed_boot_args: BootArgs::default(),
+            extra_boot_args: BootArgs::default(),
             tap_device: None,
             ip_address: None,
             guest_cid: None,
             vsock_uds_path: None,
+            drive_rate_limiter: None,
+            drive_io_engine: None,
+            net_rx_rate_limiter: None,
+            net_tx_rate_limiter: None,
+            rootfs_mode: RootfsMode::Shared,
+            extra_drives: Vec::new(),
+            balloon: None,
         }
     }
 
@@ -58,23 +291,60 @@ impl VmConfig {
         self
     }
 
-    /// Set custom boot arguments
+    /// Set custom base boot arguments
+    ///
+    /// Network, IPv6 and extra arguments still apply, whichever order the
+    /// builders are called in.
     #[must_use]
     pub fn with_boot_args(mut self, args: String) -> Self {
-        self.boot_args = args;
+        self.base_boot_args = BootArgs::parse(&args);
+        self
+    }
+
+    /// Append kernel parameters (e.g. `init=/sbin/myinit quiet`) to the final
+    /// boot arguments without replacing the base or network settings
+    #[must_use]
+    pub fn with_extra_boot_args(mut self, args: &str) -> Self {
+        self.extra_boot_args.append(args);
+        self
+    }
+
+    /// Turn off IPv6 in the guest kernel (`ipv6.disable=1`)
+    #[must_use]
+    pub fn with_ipv6_disabled(mut self) -> Self {
+        self.managed_boot_args.set("ipv6.disable", "1");
         self
     }
 
-    /// Configure networking with TAP device and IP address
-    /// Automatically updates boot args to include IP configuration
+    /// Kernel command line: the base arguments with the network and IPv6
+    /// settings applied (replacing any the base set itself), then the extra
+    /// arguments
+    pub fn boot_args(&self) -> BootArgs {
+        let mut args = self.base_boot_args.clone();
+        for managed in &self.managed_boot_args.args {
+            args.set(&managed.key, managed.value.as_deref().unwrap_or_default());
+        }
+        args.args.extend(self.extra_boot_args.args.iter().cloned());
+        args
+    }
+
+    /// Configure networking with TAP device, IP address, gateway and netmask
+    /// Automatically adds the IP configuration to the boot args
     #[must_use]
-    pub fn with_network(mut self, tap_device: String, ip_address: String) -> Self {
+    pub fn with_network(
+        mut self,
+        tap_device: String,
+        ip_address: String,
+        gateway: IpAddr,
+        netmask: IpAddr,
+    ) -> Self {
         self.tap_device = Some(tap_device);
 
-        // Update boot args to include IP configuration
         // Format: ip=<client-ip>::<gw-ip>:<netmask>::<device>:<autoconf>
-        let ip_config = format!("ip={ip_address}::192.168.100.1:255.255.255.0::eth0:off");
-        self.boot_args = format!("console=ttyS0 reboot=k panic=1 pci=off {ip_config}");
+        self.managed_boot_args.set(
+            "ip",
+            &format!("{ip_address}::{gateway}:{netmask}::eth0:off"),
+        );
         self.ip_address = Some(ip_address);
 
         self
@@ -88,32 +358,135 @@ impl VmConfig {
         self
     }
 
+    /// Throttle root drive I/O with a Firecracker rate limiter
+    #[must_use]
+    pub fn with_drive_rate_limiter(mut self, rate_limiter: RateLimiter) -> Self {
+        self.drive_rate_limiter = Some(rate_limiter);
+        self
+    }
+
+    /// Set the I/O engine used for the root drive
+    #[must_use]
+    pub fn with_io_engine(mut self, io_engine: IoEngine) -> Self {
+        self.drive_io_engine = Some(io_engine);
+        self
+    }
+
+    /// Throttle the network interface with Firecracker rate limiters
+    ///
+    /// `rx` applies to traffic received by the guest and `tx` to traffic it
+    /// sends. `None` leaves that direction unlimited.
+    #[must_use]
+    pub fn with_network_rate_limiters(
+        mut self,
+        rx: Option<RateLimiter>,
+        tx: Option<RateLimiter>,
+    ) -> Self {
+        self.net_rx_rate_limiter = rx;
+        self.net_tx_rate_limiter = tx;
+        self
+    }
+
+    /// Set how the root drive uses the rootfs image
+    ///
+    /// In `CopyOnWrite` mode `rootfs_path` should already point at the VM's
+    /// private copy; see [`RootfsMode::path_on_host`].
+    #[must_use]
+    pub fn with_rootfs_mode(mut self, mode: RootfsMode) -> Self {
+        self.rootfs_mode = mode;
+        self
+    }
+
+    /// Attach an additional drive after the root drive
+    #[must_use]
+    pub fn with_extra_drive(
+        mut self,
+        drive_id: String,
+        host_path: PathBuf,
+        read_only: bool,
+    ) -> Self {
+        self.extra_drives.push(ExtraDrive {
+            drive_id,
+            host_path,
+            read_only,
+        });
+        self
+    }
+
+    /// Attach a memory balloon inflated to `amount_mib` at boot
+    ///
+    /// The balloon can be resized at runtime with
+    /// [`FirecrackerClient::update_balloon`](crate::firecracker::FirecrackerClient::update_balloon).
+    /// `stats_polling_s` of 0 disables balloon statistics.
+    #[must_use]
+    pub fn with_balloon(
+        mut self,
+        amount_mib: u32,
+        deflate_on_oom: bool,
+        stats_polling_s: u32,
+    ) -> Self {
+        self.balloon = Some(BalloonDevice {
+            amount_mib,
+            deflate_on_oom,
+            stats_polling_interval_s: stats_polling_s,
+        });
+        self
+    }
+
     /// Validate the configuration
-    pub fn validate(&self) -> anyhow::Result<()> {
+    ///
+    /// Request-derived settings (vCPUs, memory) are checked before host paths
+    /// so callers can tell a bad request apart from a misconfigured server.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        // Validate vCPU count
+        if self.vcpu_count == 0 {
+            return Err(ConfigError::InvalidVcpu(self.vcpu_count));
+        }
+
+        // Validate memory
+        if self.mem_size_mib < MIN_MEM_SIZE_MIB {
+            return Err(ConfigError::InvalidMemory(self.mem_size_mib));
+        }
+
+        // The balloon can't take more memory than the guest has
+        if let Some(balloon) = &self.balloon {
+            if balloon.amount_mib > self.mem_size_mib {
+                return Err(ConfigError::BalloonTooLarge {
+                    balloon_mib: balloon.amount_mib,
+                    mem_size_mib: self.mem_size_mib,
+                });
+            }
+        }
+
+        // Validate vsock CID
+        if let Some(cid) = self.guest_cid {
+            if cid < MIN_GUEST_CID {
+                return Err(ConfigError::InvalidGuestCid(cid));
+            }
+        }
+
+        // Drive IDs must be unique, including the root drive's
+        let mut drive_ids = vec![ROOT_DRIVE_ID];
+        for drive in &self.extra_drives {
+            if drive_ids.contains(&drive.drive_id.as_str()) {
+                return Err(ConfigError::DuplicateDriveId(drive.drive_id.clone()));
+            }
+            drive_ids.push(&drive.drive_id);
+        }
+
         // Check kernel path exists
         if !self.kernel_path.exists() {
-            return Err(anyhow::anyhow!(
-                "Kernel image not found: {}",
-                self.kernel_path.display()
-            ));
+            return Err(ConfigError::MissingKernel(self.kernel_path.clone()));
         }
 
         // Check rootfs path exists
         if !self.rootfs_path.exists() {
-            return Err(anyhow::anyhow!(
-                "Rootfs image not found: {}",
-                self.rootfs_path.display()
-            ));
+            return Err(ConfigError::MissingRootfs(self.rootfs_path.clone()));
         }
 
-        // Validate vCPU count
-        if self.vcpu_count == 0 {
-            return Err(anyhow::anyhow!("vCPU count must be at least 1"));
-        }
-
-        // Validate memory
-        if self.mem_size_mib < 128 {
-            return Err(anyhow::anyhow!("Memory size must be at least 128 MiB"));
+        // Check data drive paths exist
+        if let Some(drive) = self.extra_drives.iter().find(|d| !d.host_path.exists()) {
+            return Err(ConfigError::MissingDrive(drive.host_path.clone()));
         }
 
         Ok(())
@@ -123,6 +496,7 @@ impl VmConfig {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::firecracker::models::TokenBucket;
 
     #[test]
     fn test_default_config() {
@@ -130,7 +504,123 @@ mod tests {
 
         assert_eq!(config.vcpu_count, 1);
         assert_eq!(config.mem_size_mib, 256);
-        assert_eq!(config.boot_args, "console=ttyS0 reboot=k panic=1 pci=off");
+        assert_eq!(
+            config.boot_args().to_string(),
+            "console=ttyS0 reboot=k panic=1 pci=off"
+        );
+    }
+
+    #[test]
+    fn test_network_boot_args() {
+        let config = VmConfig::new(PathBuf::from("/tmp/kernel"), PathBuf::from("/tmp/rootfs"))
+            .with_network(
+                "tap-test".to_string(),
+                "10.200.4.2".to_string(),
+                "10.200.4.1".parse().unwrap(),
+                "255.255.252.0".parse().unwrap(),
+            );
+
+        assert_eq!(
+            config.boot_args().to_string(),
+            "console=ttyS0 reboot=k panic=1 pci=off ip=10.200.4.2::10.200.4.1:255.255.252.0::eth0:off"
+        );
+    }
+
+    #[test]
+    fn test_extra_boot_args_compose_with_network() {
+        let network = |config: VmConfig| {
+            config.with_network(
+                "tap-test".to_string(),
+                "192.168.100.2".to_string(),
+                "192.168.100.1".parse().unwrap(),
+                "255.255.255.0".parse().unwrap(),
+            )
+        };
+        let base = VmConfig::new(PathBuf::from("/tmp/kernel"), PathBuf::from("/tmp/rootfs"));
+
+        let config = network(base.clone()).with_extra_boot_args("quiet");
+        let boot_args = config.boot_args().to_string();
+        assert!(boot_args.contains("ip=192.168.100.2::"));
+        assert!(boot_args.ends_with(" quiet"));
+
+        // Call order doesn't matter
+        let reordered = network(base.clone().with_extra_boot_args("quiet"));
+        assert_eq!(reordered.boot_args(), config.boot_args());
+
+        // Custom base args survive with_network
+        let custom = network(base.with_boot_args("console=ttyS0 init=/bin/myinit".to_string()))
+            .with_extra_boot_args("systemd.unified_cgroup_hierarchy=0");
+        assert_eq!(
+            custom.boot_args().to_string(),
+            "console=ttyS0 init=/bin/myinit ip=192.168.100.2::192.168.100.1:255.255.255.0::eth0:off \
+             systemd.unified_cgroup_hierarchy=0"
+        );
+    }
+
+    #[test]
+    fn test_boot_args_composition_order() {
+        let network = |config: VmConfig| {
+            config.with_network(
+                "tap-test".to_string(),
+                "192.168.100.2".to_string(),
+                "192.168.100.1".parse().unwrap(),
+                "255.255.255.0".parse().unwrap(),
+            )
+        };
+        let base = VmConfig::new(PathBuf::from("/tmp/kernel"), PathBuf::from("/tmp/rootfs"));
+        let expected =
+            "console=ttyS0 console=tty0 ip=192.168.100.2::192.168.100.1:255.255.255.0::eth0:off \
+                        ipv6.disable=1 quiet init=/bin/sh";
+
+        // Every order of the builders gives the same command line
+        let a = network(base.clone())
+            .with_ipv6_disabled()
+            .with_boot_args("console=ttyS0 console=tty0".to_string())
+            .with_extra_boot_args("quiet")
+            .with_extra_boot_args("init=/bin/sh");
+        let b = network(
+            base.with_extra_boot_args("quiet init=/bin/sh")
+                .with_boot_args("console=ttyS0 console=tty0".to_string()),
+        )
+        .with_ipv6_disabled();
+        assert_eq!(a.boot_args().to_string(), expected);
+        assert_eq!(b.boot_args().to_string(), expected);
+
+        // An ip= in custom base args is replaced in place, not repeated
+        let custom = network(
+            VmConfig::new(PathBuf::from("/tmp/kernel"), PathBuf::from("/tmp/rootfs"))
+                .with_boot_args("ip=dhcp console=ttyS0".to_string()),
+        );
+        assert_eq!(
+            custom.boot_args().to_string(),
+            "ip=192.168.100.2::192.168.100.1:255.255.255.0::eth0:off console=ttyS0"
+        );
+        assert_eq!(
+            custom.boot_args().get("ip"),
+            Some("192.168.100.2::192.168.100.1:255.255.255.0::eth0:off")
+        );
+    }
+
+    #[test]
+    fn test_boot_args_set_and_append() {
+        let mut args = BootArgs::parse("  console=ttyS0 quiet  loglevel=3 loglevel=4 ");
+        assert_eq!(
+            args.to_string(),
+            "console=ttyS0 quiet loglevel=3 loglevel=4"
+        );
+
+        // set keeps the first position and drops repeats
+        args.set("loglevel", "7");
+        assert_eq!(args.to_string(), "console=ttyS0 quiet loglevel=7");
+        // append keeps repeats, which some parameters rely on
+        args.append("console=tty0 nokaslr");
+        assert_eq!(
+            args.to_string(),
+            "console=ttyS0 quiet loglevel=7 console=tty0 nokaslr"
+        );
+        assert_eq!(args.get("console"), Some("tty0"));
+        assert_eq!(args.get("quiet"), None);
+        assert!(BootArgs::parse(" ").is_empty());
     }
 
     #[test]
@@ -141,5 +631,120 @@ mod tests {
 
         assert_eq!(config.vcpu_count, 4);
         assert_eq!(config.mem_size_mib, 1024);
+        assert!(config.drive_rate_limiter.is_none());
+        assert!(config.drive_io_engine.is_none());
+    }
+
+    #[test]
+    fn test_drive_options() {
+        let limiter = RateLimiter {
+            bandwidth: None,
+            ops: Some(TokenBucket::per_second(500)),
+        };
+        let config = VmConfig::new(PathBuf::from("/tmp/kernel"), PathBuf::from("/tmp/rootfs"))
+            .with_drive_rate_limiter(limiter.clone())
+            .with_io_engine(IoEngine::Async);
+
+        assert_eq!(config.drive_rate_limiter, Some(limiter));
+        assert_eq!(config.drive_io_engine, Some(IoEngine::Async));
+    }
+
+    #[test]
+    fn test_validate_rejects_bad_request_values() {
+        let config = VmConfig::new(PathBuf::from("/tmp/kernel"), PathBuf::from("/tmp/rootfs"));
+
+        let err = config.clone().with_vcpus(0).validate().unwrap_err();
+        assert_eq!(err, ConfigError::InvalidVcpu(0));
+        assert!(err.is_invalid_argument());
+
+        let err = config.clone().with_memory(64).validate().unwrap_err();
+        assert_eq!(err, ConfigError::InvalidMemory(64));
+        assert!(err.is_invalid_argument());
+
+        let err = config.with_balloon(512, true, 0).validate().unwrap_err();
+        assert_eq!(
+            err,
+            ConfigError::BalloonTooLarge {
+                balloon_mib: 512,
+                mem_size_mib: 256
+            }
+        );
+        assert!(err.is_invalid_argument());
+    }
+
+    #[test]
+    fn test_validate_extra_drives() {
+        let config = VmConfig::new(PathBuf::from("/tmp/kernel"), PathBuf::from("/tmp/rootfs"))
+            .with_extra_drive("data0".to_string(), PathBuf::from("/tmp/a"), false);
+
+        let err = config
+            .clone()
+            .with_extra_drive("data0".to_string(), PathBuf::from("/tmp/b"), true)
+            .validate()
+            .unwrap_err();
+        assert_eq!(err, ConfigError::DuplicateDriveId("data0".to_string()));
+        assert!(err.is_invalid_argument());
+
+        let err = config
+            .with_extra_drive(ROOT_DRIVE_ID.to_string(), PathBuf::from("/tmp/b"), true)
+            .validate()
+            .unwrap_err();
+        assert_eq!(
+            err,
+            ConfigError::DuplicateDriveId(ROOT_DRIVE_ID.to_string())
+        );
+
+        let dir = std::env::temp_dir().join(format!("clawpot-config-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join("kernel"), b"").unwrap();
+        std::fs::write(dir.join("rootfs"), b"").unwrap();
+        let err = VmConfig::new(dir.join("kernel"), dir.join("rootfs"))
+            .with_extra_drive("data0".to_string(), dir.join("missing"), false)
+            .validate()
+            .unwrap_err();
+        assert_eq!(err, ConfigError::MissingDrive(dir.join("missing")));
+        assert!(err.is_invalid_argument());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_validate_missing_kernel_is_not_client_error() {
+        let config = VmConfig::new(
+            PathBuf::from("/nonexistent/kernel"),
+            PathBuf::from("/nonexistent/rootfs"),
+        );
+
+        let err = config.validate().unwrap_err();
+        assert_eq!(
+            err,
+            ConfigError::MissingKernel(PathBuf::from("/nonexistent/kernel"))
+        );
+        assert!(!err.is_invalid_argument());
+    }
+
+    #[test]
+    fn test_rootfs_mode() {
+        assert_eq!(RootfsMode::parse("RO"), Some(RootfsMode::ReadOnly));
+        assert_eq!(RootfsMode::parse("cow"), Some(RootfsMode::CopyOnWrite));
+        assert_eq!(RootfsMode::parse("overlay"), None);
+        assert!(RootfsMode::ReadOnly.is_read_only());
+        assert!(!RootfsMode::CopyOnWrite.is_read_only());
+
+        let image = Path::new("/assets/rootfs/ubuntu.ext4");
+        assert_eq!(RootfsMode::Shared.path_on_host(image, "a"), image);
+        assert_eq!(RootfsMode::ReadOnly.path_on_host(image, "a"), image);
+
+        let a = RootfsMode::CopyOnWrite.path_on_host(image, "a");
+        let b = RootfsMode::CopyOnWrite.path_on_host(image, "b");
+        assert_ne!(a, b);
+        assert_ne!(a, image);
+        assert_eq!(a, PathBuf::from("/tmp/fc-a-rootfs.ext4"));
+
+        let config = VmConfig::new(PathBuf::from("/tmp/kernel"), PathBuf::from("/tmp/rootfs"));
+        assert_eq!(config.rootfs_mode, RootfsMode::Shared);
+        assert_eq!(
+            config.with_rootfs_mode(RootfsMode::ReadOnly).rootfs_mode,
+            RootfsMode::ReadOnly
+        );
     }
 }
@@ -3,5 +3,5 @@ pub mod config;
 pub mod models;
 
 pub use client::FirecrackerClient;
-pub use config::VmConfig;
+pub use config::{BootArgs, ConfigError, RootfsMode, VmConfig};
 pub use models::*;
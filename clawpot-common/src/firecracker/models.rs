@@ -20,6 +20,65 @@ pub struct Drive {
     pub is_root_device: bool,
     /// Whether the drive is read-only
     pub is_read_only: bool,
+    /// Optional I/O rate limiter
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limiter: Option<RateLimiter>,
+    /// Optional block device I/O engine (defaults to Sync in Firecracker)
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub io_engine: Option<IoEngine>,
+}
+
+/// Block device I/O engine
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum IoEngine {
+    /// Synchronous I/O (Firecracker default)
+    Sync,
+    /// io_uring based asynchronous I/O (requires host kernel 5.10+)
+    Async,
+}
+
+/// Token bucket used by Firecracker rate limiters
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenBucket {
+    /// Total number of tokens the bucket can hold
+    pub size: u64,
+    /// Initial burst allowance that does not refill
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub one_time_burst: Option<u64>,
+    /// Time in milliseconds for the bucket to refill completely
+    pub refill_time: u64,
+}
+
+impl TokenBucket {
+    /// Bucket that allows `rate` tokens per second
+    pub fn per_second(rate: u64) -> Self {
+        Self {
+            size: rate,
+            one_time_burst: None,
+            refill_time: 1000,
+        }
+    }
+}
+
+/// Rate limiter with optional bandwidth (bytes) and operations buckets
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RateLimiter {
+    /// Bandwidth limit in bytes
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub bandwidth: Option<TokenBucket>,
+    /// Operations limit
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub ops: Option<TokenBucket>,
+}
+
+impl RateLimiter {
+    /// Limiter that caps bandwidth at `rate` bytes per second
+    pub fn bytes_per_second(rate: u64) -> Self {
+        Self {
+            bandwidth: Some(TokenBucket::per_second(rate)),
+            ops: None,
+        }
+    }
 }
 
 /// Machine configuration (CPU and memory)
@@ -96,8 +155,310 @@ pub struct NetworkInterface {
     /// Optional guest MAC address
     #[serde(skip_serializing_if = "Option::is_none")]
     pub guest_mac: Option<String>,
+    /// Optional rate limiter for traffic received by the guest
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rx_rate_limiter: Option<RateLimiter>,
+    /// Optional rate limiter for traffic sent by the guest
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tx_rate_limiter: Option<RateLimiter>,
 }
 
 /// Entropy device configuration (virtio-rng)
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct EntropyDevice {}
+
+/// Memory balloon device configuration (`PUT /balloon`)
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BalloonDevice {
+    /// Target balloon size; this much guest memory is returned to the host
+    pub amount_mib: u32,
+    /// Let the guest deflate the balloon instead of running out of memory
+    pub deflate_on_oom: bool,
+    /// How often the guest reports memory statistics (0 disables them)
+    #[serde(default)]
+    pub stats_polling_interval_s: u32,
+}
+
+/// Body of `PATCH /balloon`, resizing the balloon of a booted VM
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct BalloonUpdate {
+    pub amount_mib: u32,
+}
+
+/// Requested run state of a booted VM (`PATCH /vm`)
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum VmRunState {
+    Paused,
+    Resumed,
+}
+
+/// Body of `PATCH /vm`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VmStateUpdate {
+    pub state: VmRunState,
+}
+
+/// Kind of snapshot to take
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SnapshotType {
+    /// All guest memory
+    Full,
+    /// Only memory dirtied since the last snapshot (needs dirty page tracking)
+    Diff,
+}
+
+/// Body of `PUT /snapshot/create`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotCreateParams {
+    pub snapshot_type: SnapshotType,
+    /// Path the VM state file is written to
+    pub snapshot_path: String,
+    /// Path the guest memory file is written to
+    pub mem_file_path: String,
+}
+
+/// Where guest memory is loaded from when restoring a snapshot
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MemBackend {
+    /// Backend kind; clawpot always uses "File"
+    pub backend_type: String,
+    /// Path of the guest memory file
+    pub backend_path: String,
+}
+
+impl MemBackend {
+    /// Memory backed by a snapshot memory file
+    pub fn file(path: String) -> Self {
+        Self {
+            backend_type: "File".to_string(),
+            backend_path: path,
+        }
+    }
+}
+
+/// Subset of `GET /vm/config`, the configuration of a configured or
+/// restored VM
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct FullVmConfig {
+    #[serde(rename = "machine-config", default)]
+    pub machine_config: Option<MachineConfig>,
+    #[serde(default)]
+    pub vsock: Option<VsockDevice>,
+    #[serde(default)]
+    pub balloon: Option<BalloonDevice>,
+    #[serde(default)]
+    pub drives: Vec<Drive>,
+}
+
+/// Body of `PATCH /drives/{drive_id}`, pointing a drive of a booted (or
+/// restored) VM at another backing file
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DriveUpdate {
+    pub drive_id: String,
+    pub path_on_host: String,
+}
+
+/// Replaces the host TAP device of a snapshotted network interface
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct NetworkOverride {
+    pub iface_id: String,
+    pub host_dev_name: String,
+}
+
+/// Replaces the host socket of a snapshotted vsock device. The guest CID is
+/// guest state and always comes from the snapshot.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct VsockOverride {
+    pub uds_path: String,
+}
+
+/// Body of `PUT /snapshot/load`
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SnapshotLoadParams {
+    /// Path of the VM state file
+    pub snapshot_path: String,
+    pub mem_backend: MemBackend,
+    /// Track dirty pages so diff snapshots can be taken later
+    pub enable_diff_snapshots: bool,
+    /// Resume the VM once loaded instead of leaving it paused
+    pub resume_vm: bool,
+    /// TAP devices to use instead of the ones recorded in the snapshot
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub network_overrides: Vec<NetworkOverride>,
+    /// Host socket to use for the vsock device instead of the snapshot's
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub vsock_override: Option<VsockOverride>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn root_drive() -> Drive {
+        Drive {
+            drive_id: "rootfs".to_string(),
+            path_on_host: "/tmp/rootfs.ext4".to_string(),
+            is_root_device: true,
+            is_read_only: false,
+            rate_limiter: None,
+            io_engine: None,
+        }
+    }
+
+    #[test]
+    fn test_drive_without_extras_serializes_unchanged() {
+        let json = serde_json::to_value(root_drive()).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({
+                "drive_id": "rootfs",
+                "path_on_host": "/tmp/rootfs.ext4",
+                "is_root_device": true,
+                "is_read_only": false
+            })
+        );
+    }
+
+    #[test]
+    fn test_drive_with_rate_limiter_and_io_engine() {
+        let drive = Drive {
+            rate_limiter: Some(RateLimiter {
+                bandwidth: Some(TokenBucket::per_second(10 * 1024 * 1024)),
+                ops: None,
+            }),
+            io_engine: Some(IoEngine::Async),
+            ..root_drive()
+        };
+
+        let json = serde_json::to_value(drive).unwrap();
+        assert_eq!(json["io_engine"], "Async");
+        assert_eq!(
+            json["rate_limiter"],
+            serde_json::json!({
+                "bandwidth": { "size": 10_485_760, "refill_time": 1000 }
+            })
+        );
+    }
+
+    #[test]
+    fn test_snapshot_create_body() {
+        let params = SnapshotCreateParams {
+            snapshot_type: SnapshotType::Full,
+            snapshot_path: "/tmp/snap/vm.snap".to_string(),
+            mem_file_path: "/tmp/snap/vm.mem".to_string(),
+        };
+        assert_eq!(
+            serde_json::to_value(params).unwrap(),
+            serde_json::json!({
+                "snapshot_type": "Full",
+                "snapshot_path": "/tmp/snap/vm.snap",
+                "mem_file_path": "/tmp/snap/vm.mem"
+            })
+        );
+
+        let pause = VmStateUpdate {
+            state: VmRunState::Paused,
+        };
+        assert_eq!(
+            serde_json::to_value(pause).unwrap(),
+            serde_json::json!({ "state": "Paused" })
+        );
+    }
+
+    #[test]
+    fn test_network_interface_rate_limiters() {
+        let iface = NetworkInterface {
+            iface_id: "eth0".to_string(),
+            host_dev_name: "tap-test".to_string(),
+            guest_mac: None,
+            rx_rate_limiter: None,
+            tx_rate_limiter: Some(RateLimiter {
+                bandwidth: Some(TokenBucket::per_second(1_000_000)),
+                ops: None,
+            }),
+        };
+
+        let json = serde_json::to_value(iface).unwrap();
+        assert!(json.get("rx_rate_limiter").is_none());
+        assert_eq!(json["tx_rate_limiter"]["bandwidth"]["size"], 1_000_000);
+    }
+
+    #[test]
+    fn test_snapshot_load_body() {
+        let mut params = SnapshotLoadParams {
+            snapshot_path: "/tmp/snap/vm.snap".to_string(),
+            mem_backend: MemBackend::file("/tmp/snap/vm.mem".to_string()),
+            enable_diff_snapshots: false,
+            resume_vm: true,
+            network_overrides: Vec::new(),
+            vsock_override: None,
+        };
+        assert_eq!(
+            serde_json::to_value(&params).unwrap(),
+            serde_json::json!({
+                "snapshot_path": "/tmp/snap/vm.snap",
+                "mem_backend": { "backend_type": "File", "backend_path": "/tmp/snap/vm.mem" },
+                "enable_diff_snapshots": false,
+                "resume_vm": true
+            })
+        );
+
+        params.network_overrides.push(NetworkOverride {
+            iface_id: "eth0".to_string(),
+            host_dev_name: "tap7".to_string(),
+        });
+        assert_eq!(
+            serde_json::to_value(&params).unwrap()["network_overrides"],
+            serde_json::json!([{ "iface_id": "eth0", "host_dev_name": "tap7" }])
+        );
+        params.vsock_override = Some(VsockOverride {
+            uds_path: "/tmp/fc-new-vsock.sock".to_string(),
+        });
+        assert_eq!(
+            serde_json::to_value(&params).unwrap()["vsock_override"],
+            serde_json::json!({ "uds_path": "/tmp/fc-new-vsock.sock" })
+        );
+    }
+
+    #[test]
+    fn test_balloon_bodies() {
+        let balloon = BalloonDevice {
+            amount_mib: 64,
+            deflate_on_oom: true,
+            stats_polling_interval_s: 5,
+        };
+        assert_eq!(
+            serde_json::to_value(&balloon).unwrap(),
+            serde_json::json!({
+                "amount_mib": 64,
+                "deflate_on_oom": true,
+                "stats_polling_interval_s": 5
+            })
+        );
+        assert_eq!(
+            serde_json::to_value(BalloonUpdate { amount_mib: 128 }).unwrap(),
+            serde_json::json!({ "amount_mib": 128 })
+        );
+    }
+
+    #[test]
+    fn test_vm_config_with_balloon() {
+        let config: FullVmConfig = serde_json::from_str(
+            r#"{"machine-config": {"vcpu_count": 2, "mem_size_mib": 512},
+                "balloon": {"amount_mib": 0, "deflate_on_oom": true}}"#,
+        )
+        .unwrap();
+        assert_eq!(config.balloon.unwrap().stats_polling_interval_s, 0);
+        assert!(config.vsock.is_none());
+        assert!(config.drives.is_empty());
+
+        let config: FullVmConfig = serde_json::from_str(
+            r#"{"drives": [{"drive_id": "rootfs", "path_on_host": "/tmp/fc-a-rootfs.ext4",
+                "is_root_device": true, "is_read_only": false, "cache_type": "Unsafe",
+                "io_engine": "Sync"}]}"#,
+        )
+        .unwrap();
+        assert_eq!(config.drives[0].path_on_host, "/tmp/fc-a-rootfs.ext4");
+        assert!(config.drives[0].is_root_device);
+    }
+}
@@ -0,0 +1,108 @@
+use prost::Message;
+use tonic::codec::CompressionEncoding;
+use tonic::Status;
+
+/// Default maximum gRPC message size (matches tonic's built-in decode limit)
+pub const DEFAULT_MAX_MESSAGE_SIZE: usize = 4 * 1024 * 1024;
+
+/// Maximum gRPC message size in bytes.
+///
+/// Reads `CLAWPOT_GRPC_MAX_MESSAGE_SIZE`, falling back to
+/// [`DEFAULT_MAX_MESSAGE_SIZE`] when unset or unparsable.
+pub fn max_message_size_from_env() -> usize {
+    std::env::var("CLAWPOT_GRPC_MAX_MESSAGE_SIZE")
+        .ok()
+        .and_then(|v| v.trim().parse().ok())
+        .filter(|&size| size > 0)
+        .unwrap_or(DEFAULT_MAX_MESSAGE_SIZE)
+}
+
+/// Compression to use on the gRPC transport, if any.
+///
+/// Reads `CLAWPOT_GRPC_COMPRESSION`; only `gzip` is supported. Compression is
+/// off by default to avoid surprising latency changes.
+pub fn compression_from_env() -> Option<CompressionEncoding> {
+    parse_compression(&std::env::var("CLAWPOT_GRPC_COMPRESSION").unwrap_or_default())
+}
+
+pub fn parse_compression(value: &str) -> Option<CompressionEncoding> {
+    match value.trim().to_ascii_lowercase().as_str() {
+        "gzip" => Some(CompressionEncoding::Gzip),
+        _ => None,
+    }
+}
+
+/// Check that an encoded message fits within `limit` bytes.
+///
+/// Returns `Status::resource_exhausted` with a human-readable explanation
+/// instead of letting tonic fail the call with an opaque decode error.
+pub fn ensure_message_fits<M: Message>(
+    message: &M,
+    limit: usize,
+    what: &str,
+) -> Result<(), Status> {
+    let size = message.encoded_len();
+    if size > limit {
+        return Err(Status::resource_exhausted(format!(
+            "{what} is too large for a single gRPC message ({size} bytes, limit {limit} bytes); \
+             use the streaming API or raise CLAWPOT_GRPC_MAX_MESSAGE_SIZE"
+        )));
+    }
+    Ok(())
+}
+
+/// Whether a status indicates a message exceeded the configured size limit.
+pub fn is_message_too_large(status: &Status) -> bool {
+    matches!(
+        status.code(),
+        tonic::Code::OutOfRange | tonic::Code::ResourceExhausted
+    ) && status.message().contains("too large")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::proto::ExecVmResponse;
+
+    fn response_with_stdout(len: usize) -> ExecVmResponse {
+        ExecVmResponse {
+            exit_code: 0,
+            stdout: vec![b'x'; len],
+            stderr: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn test_message_near_limit_fits() {
+        let resp = response_with_stdout(1024);
+        let limit = resp.encoded_len();
+        assert!(ensure_message_fits(&resp, limit, "Exec output").is_ok());
+    }
+
+    #[test]
+    fn test_message_over_limit_rejected() {
+        let resp = response_with_stdout(1024);
+        let limit = resp.encoded_len() - 1;
+        let status = ensure_message_fits(&resp, limit, "Exec output").unwrap_err();
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+        assert!(status.message().contains("streaming API"));
+        assert!(is_message_too_large(&status));
+    }
+
+    #[test]
+    fn test_parse_compression() {
+        assert_eq!(parse_compression("gzip"), Some(CompressionEncoding::Gzip));
+        assert_eq!(parse_compression(" GZIP "), Some(CompressionEncoding::Gzip));
+        assert_eq!(parse_compression(""), None);
+        assert_eq!(parse_compression("none"), None);
+    }
+
+    #[test]
+    fn test_decode_error_detected() {
+        let status = Status::out_of_range(
+            "Error, decoded message length too large: found 5000000 bytes, the limit is: 4194304 bytes",
+        );
+        assert!(is_message_too_large(&status));
+        assert!(!is_message_too_large(&Status::internal("boom")));
+    }
+}
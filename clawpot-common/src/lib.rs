@@ -1,5 +1,7 @@
 pub mod agent_proto;
 pub mod firecracker;
+pub mod grpc;
+pub mod llm_keys;
 pub mod network_auth_proto;
 pub mod proto;
 pub mod types;
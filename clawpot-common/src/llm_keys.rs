@@ -0,0 +1,90 @@
+use anyhow::{bail, Context, Result};
+use std::collections::BTreeMap;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+/// Providers that accept a server-managed key
+pub const PROVIDER_NAMES: &[&str] = &["anthropic", "openai", "azure_openai"];
+
+/// File name of the key store inside the data directory. Written by
+/// `clawpot llm keys` and read by the server at startup; keys set through
+/// environment variables take precedence over it.
+pub const KEYS_FILE_NAME: &str = "llm_keys.json";
+
+/// Path of the key store inside `data_dir`.
+pub fn keys_path(data_dir: &Path) -> PathBuf {
+    data_dir.join(KEYS_FILE_NAME)
+}
+
+/// Check that `provider` is one clawpot can inject keys for.
+pub fn validate_provider(provider: &str) -> Result<()> {
+    if !PROVIDER_NAMES.contains(&provider) {
+        bail!(
+            "Unknown LLM provider '{provider}' (expected one of: {})",
+            PROVIDER_NAMES.join(", ")
+        );
+    }
+    Ok(())
+}
+
+/// Load keys from `path`, mapping provider name to key. A missing file is empty.
+pub fn load(path: &Path) -> Result<BTreeMap<String, String>> {
+    match fs::read(path) {
+        Ok(data) => serde_json::from_slice(&data)
+            .with_context(|| format!("Failed to parse LLM key file {}", path.display())),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(BTreeMap::new()),
+        Err(e) => Err(e).with_context(|| format!("Failed to read LLM key file {}", path.display())),
+    }
+}
+
+/// Atomically replace the key file at `path`, readable only by its owner.
+pub fn save(path: &Path, keys: &BTreeMap<String, String>) -> Result<()> {
+    if let Some(dir) = path.parent() {
+        fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create directory {}", dir.display()))?;
+    }
+
+    let tmp = path.with_extension("json.tmp");
+    let _ = fs::remove_file(&tmp);
+    let mut file = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(0o600)
+        .open(&tmp)
+        .with_context(|| format!("Failed to create {}", tmp.display()))?;
+    file.write_all(&serde_json::to_vec_pretty(keys)?)
+        .and_then(|()| file.sync_all())
+        .with_context(|| format!("Failed to write {}", tmp.display()))?;
+
+    fs::rename(&tmp, path).with_context(|| format!("Failed to replace {}", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+
+    #[test]
+    fn test_save_and_load() {
+        let dir = std::env::temp_dir().join(format!("clawpot-llm-keys-{}", uuid::Uuid::new_v4()));
+        let path = keys_path(&dir);
+        assert!(load(&path).unwrap().is_empty());
+
+        let keys = BTreeMap::from([("anthropic".to_string(), "sk-ant-test".to_string())]);
+        save(&path, &keys).unwrap();
+
+        assert_eq!(load(&path).unwrap(), keys);
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_validate_provider() {
+        assert!(validate_provider("openai").is_ok());
+        assert!(validate_provider("gemini").is_err());
+    }
+}
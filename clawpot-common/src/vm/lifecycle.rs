@@ -10,6 +10,8 @@ pub enum VmState {
     Starting,
     /// VM is running
     Running,
+    /// VM is paused; its vCPUs are stopped but it keeps its memory and devices
+    Paused,
     /// VM is in the process of stopping
     Stopping,
     /// VM has been stopped
@@ -24,6 +26,7 @@ impl std::fmt::Display for VmState {
             VmState::NotStarted => write!(f, "Not Started"),
             VmState::Starting => write!(f, "Starting"),
             VmState::Running => write!(f, "Running"),
+            VmState::Paused => write!(f, "Paused"),
             VmState::Stopping => write!(f, "Stopping"),
             VmState::Stopped => write!(f, "Stopped"),
             VmState::Error => write!(f, "Error"),
@@ -61,8 +64,11 @@ impl VmLifecycle {
                     VmState::Starting,
                     VmState::Running | VmState::Stopping | VmState::Stopped
                 )
-                | (VmState::Running, VmState::Stopping)
+                | (VmState::Running, VmState::Stopping | VmState::Paused)
+                | (VmState::Paused, VmState::Running | VmState::Stopping)
                 | (VmState::Stopping, VmState::Stopped)
+                // A failed VM can still be torn down, e.g. to reboot it
+                | (VmState::Error, VmState::Stopping | VmState::Stopped)
         ) || self.state == new_state;
 
         if !is_valid {
@@ -119,6 +125,31 @@ mod tests {
         assert!(lifecycle.transition_to(VmState::Running).is_err());
     }
 
+    #[test]
+    fn test_pause_and_resume() {
+        let mut lifecycle = VmLifecycle::new();
+        lifecycle.transition_to(VmState::Starting).unwrap();
+        lifecycle.transition_to(VmState::Running).unwrap();
+
+        lifecycle.transition_to(VmState::Paused).unwrap();
+        assert_eq!(lifecycle.current_state(), VmState::Paused);
+        lifecycle.transition_to(VmState::Running).unwrap();
+        assert_eq!(lifecycle.current_state(), VmState::Running);
+
+        // A paused VM can be stopped without resuming it first
+        lifecycle.transition_to(VmState::Paused).unwrap();
+        lifecycle.transition_to(VmState::Stopping).unwrap();
+    }
+
+    #[test]
+    fn test_cannot_pause_before_running() {
+        let mut lifecycle = VmLifecycle::new();
+        assert!(lifecycle.transition_to(VmState::Paused).is_err());
+
+        lifecycle.transition_to(VmState::Starting).unwrap();
+        assert!(lifecycle.transition_to(VmState::Paused).is_err());
+    }
+
     #[test]
     fn test_error_state_always_valid() {
         let mut lifecycle = VmLifecycle::new();
@@ -127,4 +158,14 @@ mod tests {
         lifecycle.transition_to(VmState::Error).unwrap();
         assert_eq!(lifecycle.current_state(), VmState::Error);
     }
+
+    #[test]
+    fn test_failed_vm_can_be_stopped() {
+        let mut lifecycle = VmLifecycle::new();
+        lifecycle.transition_to(VmState::Error).unwrap();
+
+        lifecycle.transition_to(VmState::Stopping).unwrap();
+        lifecycle.transition_to(VmState::Stopped).unwrap();
+        lifecycle.transition_to(VmState::Starting).unwrap();
+    }
 }
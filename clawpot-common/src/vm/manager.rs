@@ -1,17 +1,130 @@
-use crate::firecracker::{BootSource, Drive, FirecrackerClient, MachineConfig, VmConfig};
+use crate::firecracker::config::ROOT_DRIVE_ID;
+use crate::firecracker::{
+    BootSource, Drive, FirecrackerClient, FullVmConfig, MachineConfig, NetworkOverride, RootfsMode,
+    SnapshotType, VmConfig, VsockOverride,
+};
 use crate::vm::lifecycle::{VmLifecycle, VmState};
 use anyhow::{anyhow, Context, Result};
-use std::path::PathBuf;
-use std::process::{Child, Command};
+use std::path::{Path, PathBuf};
+use std::process::{Child, Command, Stdio};
 use std::time::Duration;
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, BufReader};
+use tokio::sync::broadcast;
+use tokio::task::JoinHandle;
 use tracing::{debug, info, warn};
 
+/// Serial console lines buffered for slow [`VmManager::console_stream`]
+/// subscribers before they start missing lines
+const CONSOLE_BUFFER_LINES: usize = 256;
+
+/// Longest serial console line forwarded in one piece; longer lines (or
+/// output that never ends a line) are split
+const MAX_CONSOLE_LINE_BYTES: u64 = 4096;
+
+/// Resolve the rootfs a VM identified by `vm_key` boots from, creating its
+/// private copy of `image` first in `CopyOnWrite` mode.
+///
+/// The copy uses `cp --reflink=auto`, so it is near-instant on filesystems
+/// that support reflinks and a full copy elsewhere. It runs as a child
+/// process so a slow full copy doesn't block the runtime.
+pub async fn prepare_rootfs(image: &Path, mode: RootfsMode, vm_key: &str) -> Result<PathBuf> {
+    let path = mode.path_on_host(image, vm_key);
+    if mode != RootfsMode::CopyOnWrite {
+        return Ok(path);
+    }
+
+    debug!("Copying rootfs {} to {}", image.display(), path.display());
+    let status = tokio::process::Command::new("cp")
+        .arg("--reflink=auto")
+        .arg(image)
+        .arg(&path)
+        .status()
+        .await
+        .context("Failed to run cp")?;
+    if !status.success() {
+        let _ = std::fs::remove_file(&path);
+        return Err(anyhow!(
+            "Failed to copy rootfs {} to {} ({status})",
+            image.display(),
+            path.display()
+        ));
+    }
+    Ok(path)
+}
+
+/// Files making up a VM snapshot
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SnapshotPaths {
+    /// VM state (device and vCPU state)
+    pub snapshot_path: PathBuf,
+    /// Guest memory
+    pub mem_path: PathBuf,
+}
+
+impl SnapshotPaths {
+    /// Standard file names for a snapshot stored in `dir`
+    pub fn in_dir(dir: &Path) -> Self {
+        Self {
+            snapshot_path: dir.join("vm.snap"),
+            mem_path: dir.join("vm.mem"),
+        }
+    }
+}
+
+/// Host-side resources a restored VM uses in place of the ones recorded in
+/// its snapshot, so it doesn't share them with the VM it was taken from
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RestoreOverrides {
+    /// TAP device behind `eth0`
+    pub tap_device: Option<String>,
+    /// Host socket of the vsock device
+    pub vsock_uds_path: Option<String>,
+    /// Give the VM a private copy of its root drive, made with
+    /// [`prepare_rootfs`] under this key
+    pub rootfs_copy_key: Option<String>,
+}
+
+/// Drives to configure for `config`, in the order they are attached: the root
+/// drive first, then each extra drive.
+fn drives(config: &VmConfig) -> Result<Vec<Drive>> {
+    let path_str = |path: &Path| {
+        path.to_str()
+            .map(String::from)
+            .ok_or_else(|| anyhow!("Invalid drive path {}", path.display()))
+    };
+
+    let mut drives = vec![Drive {
+        drive_id: ROOT_DRIVE_ID.to_string(),
+        path_on_host: path_str(&config.rootfs_path)?,
+        is_root_device: true,
+        is_read_only: config.rootfs_mode.is_read_only(),
+        rate_limiter: config.drive_rate_limiter.clone(),
+        io_engine: config.drive_io_engine,
+    }];
+    for extra in &config.extra_drives {
+        drives.push(Drive {
+            drive_id: extra.drive_id.clone(),
+            path_on_host: path_str(&extra.host_path)?,
+            is_root_device: false,
+            is_read_only: extra.read_only,
+            rate_limiter: None,
+            io_engine: config.drive_io_engine,
+        });
+    }
+    Ok(drives)
+}
+
 /// High-level VM manager that orchestrates Firecracker process and configuration
 pub struct VmManager {
     socket_path: PathBuf,
+    serial_log_path: Option<PathBuf>,
     firecracker_process: Option<Child>,
     client: FirecrackerClient,
     lifecycle: VmLifecycle,
+    /// Serial console lines, kept across restarts so subscribers stay attached
+    console: broadcast::Sender<String>,
+    /// Task reading Firecracker's stdout into `console`
+    console_reader: Option<JoinHandle<()>>,
 }
 
 impl VmManager {
@@ -20,12 +133,53 @@ impl VmManager {
         let client = FirecrackerClient::new(&socket_path);
         Self {
             socket_path,
+            serial_log_path: None,
             firecracker_process: None,
             client,
             lifecycle: VmLifecycle::new(),
+            console: broadcast::channel(CONSOLE_BUFFER_LINES).0,
+            console_reader: None,
         }
     }
 
+    /// Write Firecracker's output (including the guest serial console) to a file
+    #[must_use]
+    pub fn with_serial_log(mut self, path: PathBuf) -> Self {
+        self.serial_log_path = Some(path);
+        self
+    }
+
+    /// Path to the Firecracker API socket
+    pub fn socket_path(&self) -> &Path {
+        &self.socket_path
+    }
+
+    /// Path to the serial console log, if one was configured
+    pub fn serial_log_path(&self) -> Option<&Path> {
+        self.serial_log_path.as_deref()
+    }
+
+    /// Live serial console lines, or `None` if no Firecracker process is
+    /// running or its output goes to the serial log file instead.
+    ///
+    /// Only lines printed after subscribing are received.
+    pub fn console_stream(&self) -> Option<broadcast::Receiver<String>> {
+        self.console_reader
+            .as_ref()
+            .map(|_| self.console.subscribe())
+    }
+
+    /// Whether a Firecracker process has been spawned for this VM
+    pub fn has_process(&self) -> bool {
+        self.firecracker_process.is_some()
+    }
+
+    /// Mark the VM as failed, leaving the Firecracker process running for inspection
+    pub fn mark_failed(&mut self) {
+        // Transitions to Error are always valid
+        let _ = self.lifecycle.transition_to(VmState::Error);
+    }
+
     /// Get the current lifecycle state
     pub fn state(&self) -> VmState {
         self.lifecycle.current_state()
@@ -52,24 +206,7 @@ impl VmManager {
             .transition_to(VmState::Starting)
             .context("Failed to transition to Starting state")?;
 
-        // Clean up any existing socket
-        if self.socket_path.exists() {
-            warn!(
-                "Socket file already exists at {}, removing it",
-                self.socket_path.display()
-            );
-            std::fs::remove_file(&self.socket_path)
-                .context("Failed to remove existing socket file")?;
-        }
-
-        // Start Firecracker process
-        self.start_firecracker_process()
-            .context("Failed to start Firecracker process")?;
-
-        // Wait for socket to be ready
-        self.wait_for_socket()
-            .await
-            .context("Socket did not become ready")?;
+        self.launch_firecracker().await?;
 
         // Configure VM via API
         self.configure_vm(config)
@@ -93,6 +230,136 @@ impl VmManager {
         Ok(())
     }
 
+    /// Restore a VM from a snapshot instead of booting it.
+    ///
+    /// A fresh Firecracker process is started on this manager's socket and
+    /// the snapshot is loaded and resumed; no boot configuration is applied.
+    /// Devices come back as they were when the snapshot was taken, except for
+    /// the host resources replaced by `overrides`. Guest state, such as its
+    /// IP configuration and vsock CID, is always the snapshot's.
+    #[tracing::instrument(
+        name = "vm.restore",
+        skip(self, snapshot),
+        fields(
+            socket_path = %self.socket_path.display(),
+            snapshot_path = %snapshot.snapshot_path.display(),
+        )
+    )]
+    pub async fn restore(
+        &mut self,
+        snapshot: &SnapshotPaths,
+        overrides: &RestoreOverrides,
+    ) -> Result<()> {
+        info!("Restoring Firecracker VM from snapshot...");
+
+        for path in [&snapshot.snapshot_path, &snapshot.mem_path] {
+            if !path.exists() {
+                return Err(anyhow!("Snapshot file {} does not exist", path.display()));
+            }
+        }
+
+        self.lifecycle
+            .transition_to(VmState::Starting)
+            .context("Failed to transition to Starting state")?;
+
+        self.launch_firecracker().await?;
+
+        let network_overrides = overrides
+            .tap_device
+            .iter()
+            .map(|tap| NetworkOverride {
+                iface_id: "eth0".to_string(),
+                host_dev_name: tap.clone(),
+            })
+            .collect();
+        let vsock_override = overrides
+            .vsock_uds_path
+            .as_ref()
+            .map(|uds_path| VsockOverride {
+                uds_path: uds_path.clone(),
+            });
+        // Loaded paused so the root drive can be swapped before the guest
+        // writes to it
+        self.client
+            .load_snapshot(
+                &snapshot.snapshot_path,
+                &snapshot.mem_path,
+                false,
+                network_overrides,
+                vsock_override,
+            )
+            .await?;
+
+        if let Some(key) = &overrides.rootfs_copy_key {
+            self.use_rootfs_copy(key).await?;
+        }
+        self.client
+            .resume()
+            .await
+            .context("Failed to resume restored VM")?;
+
+        self.lifecycle
+            .transition_to(VmState::Running)
+            .context("Failed to transition to Running state")?;
+
+        info!("VM restored successfully!");
+
+        Ok(())
+    }
+
+    /// Point the root drive of a restored, still paused VM at a private copy
+    /// of the image it was snapshotted with.
+    async fn use_rootfs_copy(&self, key: &str) -> Result<()> {
+        let config = self.client.get_vm_config().await?;
+        let root = config
+            .drives
+            .iter()
+            .find(|drive| drive.is_root_device)
+            .ok_or_else(|| anyhow!("Snapshot has no root drive"))?;
+        let copy =
+            prepare_rootfs(Path::new(&root.path_on_host), RootfsMode::CopyOnWrite, key).await?;
+        let copy_str = copy
+            .to_str()
+            .ok_or_else(|| anyhow!("Invalid drive path {}", copy.display()))?;
+        if let Err(e) = self
+            .client
+            .update_drive_path(&root.drive_id, copy_str)
+            .await
+        {
+            let _ = std::fs::remove_file(&copy);
+            return Err(e);
+        }
+        Ok(())
+    }
+
+    /// Configuration Firecracker reports for the VM, e.g. the machine size
+    /// and vsock device recorded in a restored snapshot
+    pub async fn vm_config(&self) -> Result<FullVmConfig> {
+        self.client.get_vm_config().await
+    }
+
+    /// Spawn Firecracker on a clean socket and wait for its API to come up
+    async fn launch_firecracker(&mut self) -> Result<()> {
+        // Clean up any existing socket
+        if self.socket_path.exists() {
+            warn!(
+                "Socket file already exists at {}, removing it",
+                self.socket_path.display()
+            );
+            std::fs::remove_file(&self.socket_path)
+                .context("Failed to remove existing socket file")?;
+        }
+
+        // Start Firecracker process
+        self.start_firecracker_process()
+            .context("Failed to start Firecracker process")?;
+
+        // Wait for socket to be ready
+        self.wait_for_socket()
+            .await
+            .context("Socket did not become ready")
+    }
+
     /// Start the Firecracker process
     fn start_firecracker_process(&mut self) -> Result<()> {
         info!(
@@ -100,15 +367,37 @@ impl VmManager {
             self.socket_path.display()
         );
 
-        let child = Command::new("firecracker")
+        let (stdout, stderr) = match &self.serial_log_path {
+            Some(path) => {
+                let log = std::fs::File::create(path)
+                    .with_context(|| format!("Failed to create serial log {}", path.display()))?;
+                let log_err = log
+                    .try_clone()
+                    .context("Failed to clone serial log handle")?;
+                (Stdio::from(log), Stdio::from(log_err))
+            }
+            None => (Stdio::piped(), Stdio::piped()),
+        };
+
+        let mut child = Command::new("firecracker")
             .arg("--api-sock")
             .arg(&self.socket_path)
-            .stdin(std::process::Stdio::piped())
-            .stdout(std::process::Stdio::piped())
-            .stderr(std::process::Stdio::piped())
+            .stdin(Stdio::piped())
+            .stdout(stdout)
+            .stderr(stderr)
             .spawn()
             .context("Failed to spawn firecracker process")?;
 
+        // Read the console in the background so a full pipe never stalls the guest
+        if let Some(stdout) = child.stdout.take() {
+            let stdout = tokio::process::ChildStdout::from_std(stdout)
+                .context("Failed to watch Firecracker stdout")?;
+            self.console_reader = Some(tokio::spawn(forward_console(
+                BufReader::new(stdout),
+                self.console.clone(),
+            )));
+        }
+
         self.firecracker_process = Some(child);
         debug!("Firecracker process spawned");
 
@@ -154,29 +443,22 @@ impl VmManager {
                 .to_str()
                 .ok_or_else(|| anyhow!("Invalid kernel path"))?
                 .to_string(),
-            boot_args: config.boot_args,
+            boot_args: config.boot_args().to_string(),
         };
         self.client
             .set_boot_source(boot_source)
             .await
             .context("Failed to set boot source")?;
 
-        // Set root drive
-        debug!("Setting root drive: {:?}", config.rootfs_path);
-        let drive = Drive {
-            drive_id: "rootfs".to_string(),
-            path_on_host: config
-                .rootfs_path
-                .to_str()
-                .ok_or_else(|| anyhow!("Invalid rootfs path"))?
-                .to_string(),
-            is_root_device: true,
-            is_read_only: false,
-        };
-        self.client
-            .set_drive(drive)
-            .await
-            .context("Failed to set root drive")?;
+        // Set root drive, then any data drives
+        for drive in drives(&config)? {
+            debug!("Setting drive {}: {}", drive.drive_id, drive.path_on_host);
+            let drive_id = drive.drive_id.clone();
+            self.client
+                .set_drive(drive)
+                .await
+                .with_context(|| format!("Failed to set drive {drive_id}"))?;
+        }
 
         // Set machine config
         debug!(
@@ -199,6 +481,8 @@ impl VmManager {
                 iface_id: "eth0".to_string(),
                 host_dev_name: tap_device.clone(),
                 guest_mac: None,
+                rx_rate_limiter: config.net_rx_rate_limiter.clone(),
+                tx_rate_limiter: config.net_tx_rate_limiter.clone(),
             };
             self.client
                 .set_network_interface(network_interface)
@@ -213,6 +497,15 @@ impl VmManager {
             .await
             .context("Failed to enable entropy device")?;
 
+        // Set balloon device if configured
+        if let Some(balloon) = config.balloon.clone() {
+            debug!("Setting balloon device: {} MiB", balloon.amount_mib);
+            self.client
+                .set_balloon(balloon)
+                .await
+                .context("Failed to set balloon device")?;
+        }
+
         // Set vsock device if configured
         if let (Some(guest_cid), Some(uds_path)) = (config.guest_cid, &config.vsock_uds_path) {
             debug!("Setting vsock device: CID={}, UDS={}", guest_cid, uds_path);
@@ -231,6 +524,92 @@ impl VmManager {
         Ok(())
     }
 
+    /// Pause the running VM
+    #[tracing::instrument(name = "vm.pause", skip(self), fields(socket_path = %self.socket_path.display()))]
+    pub async fn pause(&mut self) -> Result<()> {
+        if self.state() != VmState::Running {
+            return Err(anyhow!("Cannot pause a VM in state {}", self.state()));
+        }
+        self.client.pause().await?;
+        self.mark_paused(true)
+    }
+
+    /// Resume the paused VM
+    #[tracing::instrument(name = "vm.resume", skip(self), fields(socket_path = %self.socket_path.display()))]
+    pub async fn resume(&mut self) -> Result<()> {
+        if self.state() != VmState::Paused {
+            return Err(anyhow!("Cannot resume a VM in state {}", self.state()));
+        }
+        self.client.resume().await?;
+        self.mark_paused(false)
+    }
+
+    /// Record that the VM was paused (or resumed) through [`api_client`](Self::api_client)
+    pub fn mark_paused(&mut self, paused: bool) -> Result<()> {
+        if paused {
+            self.lifecycle
+                .transition_to(VmState::Paused)
+                .context("Failed to transition to Paused state")
+        } else {
+            self.lifecycle
+                .transition_to(VmState::Running)
+                .context("Failed to transition to Running state")
+        }
+    }
+
+    /// A client for the VM's Firecracker API, for calls that shouldn't
+    /// borrow the manager while they wait on Firecracker
+    pub fn api_client(&self) -> FirecrackerClient {
+        self.client.clone()
+    }
+
+    /// Inflate or deflate the VM's memory balloon to `target_mib`
+    #[tracing::instrument(name = "vm.update_balloon", skip(self), fields(socket_path = %self.socket_path.display()))]
+    pub async fn update_balloon(&self, target_mib: u32) -> Result<()> {
+        if !matches!(self.state(), VmState::Running | VmState::Paused) {
+            return Err(anyhow!(
+                "Cannot resize the balloon of a VM in state {}",
+                self.state()
+            ));
+        }
+        self.client.update_balloon(target_mib).await
+    }
+
+    /// Snapshot the VM into `dir` so identical VMs can be restored from it.
+    ///
+    /// The VM is paused while the snapshot is written. With `resume` it is
+    /// resumed afterwards (even if the snapshot failed); otherwise it is left
+    /// in the Paused state.
+    #[tracing::instrument(name = "vm.snapshot", skip(self), fields(socket_path = %self.socket_path.display()))]
+    pub async fn snapshot(&mut self, dir: &Path, resume: bool) -> Result<SnapshotPaths> {
+        if !matches!(self.state(), VmState::Running | VmState::Paused) {
+            return Err(anyhow!("Cannot snapshot a VM in state {}", self.state()));
+        }
+
+        std::fs::create_dir_all(dir)
+            .with_context(|| format!("Failed to create snapshot directory {}", dir.display()))?;
+        let paths = SnapshotPaths::in_dir(dir);
+
+        info!("Creating snapshot in {}", dir.display());
+        let result = self
+            .client
+            .create_snapshot(&paths.snapshot_path, &paths.mem_path, SnapshotType::Full)
+            .await;
+
+        if resume {
+            self.client
+                .resume()
+                .await
+                .context("Failed to resume VM after snapshot")?;
+            self.lifecycle.transition_to(VmState::Running)?;
+        } else {
+            self.lifecycle.transition_to(VmState::Paused)?;
+        }
+        result?;
+
+        Ok(paths)
+    }
+
     /// Stop the VM
     #[tracing::instrument(name = "vm.stop", skip(self), fields(socket_path = %self.socket_path.display()))]
     pub async fn stop(&mut self) -> Result<()> {
@@ -249,6 +628,10 @@ impl VmManager {
         // Give the VM a moment to shut down gracefully
         tokio::time::sleep(Duration::from_secs(2)).await;
 
+        if let Some(reader) = self.console_reader.take() {
+            reader.abort();
+        }
+
         // Kill the Firecracker process
         if let Some(mut child) = self.firecracker_process.take() {
             debug!("Killing Firecracker process");
@@ -279,6 +662,29 @@ impl VmManager {
         Ok(())
     }
 
+    /// Reboot the VM: stop its Firecracker process and boot a fresh one with
+    /// `config` on the same API socket. `config` should be the one the VM was
+    /// started with, so it keeps its TAP device, address and vsock CID.
+    #[tracing::instrument(name = "vm.restart", skip(self, config), fields(socket_path = %self.socket_path.display()))]
+    pub async fn restart(&mut self, config: VmConfig) -> Result<()> {
+        self.stop().await.context("Failed to stop VM for restart")?;
+        // Firecracker refuses to bind a vsock socket that already exists
+        if let Some(path) = &config.vsock_uds_path {
+            let _ = std::fs::remove_file(path);
+        }
+        self.start(config).await
+    }
+
+    /// Instance state as reported by Firecracker ("Not started", "Running" or "Paused")
+    pub async fn get_instance_state(&self) -> Result<String> {
+        let info = self
+            .client
+            .get_instance_info()
+            .await
+            .context("Failed to get instance info")?;
+        Ok(info.state)
+    }
+
     /// Get VM status
     pub async fn status(&self) -> Result<String> {
         let info = self
@@ -296,15 +702,174 @@ impl VmManager {
     }
 }
 
+/// Send each line read from `reader` to `console` until EOF. Lines are
+/// dropped while nobody is subscribed, and split at
+/// [`MAX_CONSOLE_LINE_BYTES`] so a guest that never prints a newline
+/// can't grow the buffer without bound.
+async fn forward_console<R: AsyncBufRead + Unpin>(
+    mut reader: R,
+    console: broadcast::Sender<String>,
+) {
+    let mut buf = Vec::new();
+    loop {
+        buf.clear();
+        let mut line_reader = (&mut reader).take(MAX_CONSOLE_LINE_BYTES);
+        match line_reader.read_until(b'\n', &mut buf).await {
+            Ok(0) => return,
+            Ok(_) => {
+                // The guest may print anything, not just UTF-8
+                let line = String::from_utf8_lossy(&buf);
+                let _ = console.send(line.trim_end_matches(['\r', '\n']).to_string());
+            }
+            Err(e) => {
+                warn!("Failed to read serial console: {}", e);
+                return;
+            }
+        }
+    }
+}
+
 impl Drop for VmManager {
     fn drop(&mut self) {
-        // Best effort cleanup on drop
+        // Best effort cleanup on drop. A manager that never launched (or has
+        // already stopped) Firecracker leaves the socket alone: another
+        // manager may be using the same path.
+        if let Some(reader) = self.console_reader.take() {
+            reader.abort();
+        }
         if let Some(mut child) = self.firecracker_process.take() {
             let _ = child.kill();
+            if self.socket_path.exists() {
+                let _ = std::fs::remove_file(&self.socket_path);
+            }
         }
+    }
+}
 
-        if self.socket_path.exists() {
-            let _ = std::fs::remove_file(&self.socket_path);
-        }
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_drives_are_attached_in_order() {
+        let config = VmConfig::new(PathBuf::from("/tmp/kernel"), PathBuf::from("/tmp/rootfs"))
+            .with_rootfs_mode(RootfsMode::ReadOnly)
+            .with_extra_drive(
+                "scratch".to_string(),
+                PathBuf::from("/tmp/scratch.ext4"),
+                false,
+            )
+            .with_extra_drive(
+                "dataset".to_string(),
+                PathBuf::from("/tmp/dataset.ext4"),
+                true,
+            );
+
+        let drives = drives(&config).unwrap();
+        let summary: Vec<_> = drives
+            .iter()
+            .map(|d| (d.drive_id.as_str(), d.is_root_device, d.is_read_only))
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("rootfs", true, true),
+                ("scratch", false, false),
+                ("dataset", false, true),
+            ]
+        );
+        assert_eq!(drives[2].path_on_host, "/tmp/dataset.ext4");
+    }
+
+    #[tokio::test]
+    async fn test_restart_failed_vm() {
+        let socket = std::env::temp_dir().join(format!("clawpot-test-{}.sock", std::process::id()));
+        let mut manager = VmManager::new(socket);
+        manager.mark_failed();
+
+        // Stopping the failed VM succeeds, so the restart gets as far as
+        // validating the (here invalid) configuration
+        let config =
+            VmConfig::new(PathBuf::from("/tmp/kernel"), PathBuf::from("/tmp/rootfs")).with_vcpus(0);
+        let err = manager.restart(config).await.unwrap_err();
+        assert!(
+            format!("{err:#}").contains("Invalid VM configuration"),
+            "{err:#}"
+        );
+        assert_eq!(manager.state(), VmState::Stopped);
+    }
+
+    #[tokio::test]
+    async fn test_prepare_rootfs() {
+        let key = format!("clawpot-test-{}", std::process::id());
+        let image = std::env::temp_dir().join(format!("{key}-image.ext4"));
+        std::fs::write(&image, b"rootfs").unwrap();
+
+        // Shared images are used in place
+        let shared = prepare_rootfs(&image, RootfsMode::Shared, &key)
+            .await
+            .unwrap();
+        assert_eq!(shared, image);
+
+        let copy = prepare_rootfs(&image, RootfsMode::CopyOnWrite, &key)
+            .await
+            .unwrap();
+        assert_ne!(copy, image);
+        assert_eq!(std::fs::read(&copy).unwrap(), b"rootfs");
+        std::fs::remove_file(&copy).unwrap();
+        std::fs::remove_file(&image).unwrap();
+
+        // A failed copy leaves nothing behind
+        assert!(prepare_rootfs(&image, RootfsMode::CopyOnWrite, &key)
+            .await
+            .is_err());
+        assert!(!copy.exists());
+    }
+
+    #[tokio::test]
+    async fn test_pause_requires_running_vm() {
+        let mut manager = VmManager::new(PathBuf::from("/tmp/clawpot-test-pause.sock"));
+        let err = manager.pause().await.unwrap_err();
+        assert!(err.to_string().contains("Not Started"), "{err}");
+        assert!(manager.resume().await.is_err());
+        assert_eq!(manager.state(), VmState::NotStarted);
+    }
+
+    #[tokio::test]
+    async fn test_forward_console() {
+        let (console, mut rx) = broadcast::channel(CONSOLE_BUFFER_LINES);
+        let output: &[u8] = b"Booting Linux\r\nbad \xff byte\nlogin: ";
+        forward_console(output, console).await;
+
+        assert_eq!(rx.recv().await.unwrap(), "Booting Linux");
+        assert_eq!(rx.recv().await.unwrap(), "bad \u{fffd} byte");
+        assert_eq!(rx.recv().await.unwrap(), "login: ");
+        assert!(matches!(
+            rx.recv().await,
+            Err(broadcast::error::RecvError::Closed)
+        ));
+    }
+
+    #[tokio::test]
+    async fn test_forward_console_splits_long_lines() {
+        let (console, mut rx) = broadcast::channel(CONSOLE_BUFFER_LINES);
+        let limit = usize::try_from(MAX_CONSOLE_LINE_BYTES).unwrap();
+        let mut output = vec![b'x'; limit + 10];
+        output.extend_from_slice(b"\ndone\n");
+        forward_console(output.as_slice(), console).await;
+
+        assert_eq!(rx.recv().await.unwrap(), "x".repeat(limit));
+        assert_eq!(rx.recv().await.unwrap(), "x".repeat(10));
+        assert_eq!(rx.recv().await.unwrap(), "done");
+        assert!(matches!(
+            rx.recv().await,
+            Err(broadcast::error::RecvError::Closed)
+        ));
+    }
+
+    #[test]
+    fn test_no_console_without_process() {
+        let manager = VmManager::new(PathBuf::from("/tmp/fc-console-test.sock"));
+        assert!(manager.console_stream().is_none());
     }
 }
@@ -2,4 +2,4 @@ pub mod lifecycle;
 pub mod manager;
 
 pub use lifecycle::{VmLifecycle, VmState};
-pub use manager::VmManager;
+pub use manager::{RestoreOverrides, SnapshotPaths, VmManager};
@@ -3,17 +3,27 @@
 //! These tests spin up a real tonic server with a mock service implementation
 //! and connect a real gRPC client to verify the full request/response cycle.
 
+use clawpot_common::grpc::{ensure_message_fits, DEFAULT_MAX_MESSAGE_SIZE};
 use clawpot_common::proto::{
     clawpot_service_client::ClawpotServiceClient,
     clawpot_service_server::{ClawpotService, ClawpotServiceServer},
-    CreateVmRequest, CreateVmResponse, DeleteVmRequest, DeleteVmResponse, ExecVmRequest,
-    ExecVmResponse, ExecVmStreamInput, ExecVmStreamOutput, ListVmsRequest, ListVmsResponse, VmInfo,
-    VmState as ProtoVmState,
+    exec_vm_stream_input, exec_vm_stream_output, AttachExecRequest, CleanOrphansRequest,
+    CleanOrphansResponse, ConsoleLine, CreateVmRequest, CreateVmResponse, DeleteVmRequest,
+    DeleteVmResponse, DescribeVmRequest, DescribeVmResponse, ExecVmRequest, ExecVmResponse,
+    ExecVmStreamInput, ExecVmStreamOutput, ExecVmStreamStart, GetServerInfoRequest,
+    GetServerInfoResponse, GetVmStatusRequest, GetVmStatusResponse, IpPoolStatus,
+    ListNetRulesRequest, ListNetRulesResponse, ListOrphansRequest, ListOrphansResponse,
+    ListVmsRequest, ListVmsResponse, PauseVmRequest, PauseVmResponse, RebootVmRequest,
+    RebootVmResponse, ResetVmRequest, ResetVmResponse, RestoreVmRequest, RestoreVmResponse,
+    ResumeVmRequest, ResumeVmResponse, SetDrainRequest, SetDrainResponse, StreamConsoleRequest,
+    UpdateVmMemoryRequest, UpdateVmMemoryResponse, VmEvent, VmInfo, VmState as ProtoVmState,
 };
 use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 use tokio::net::TcpListener;
 use tokio::sync::Mutex;
+use tonic::codec::CompressionEncoding;
 use tonic::{transport::Server, Request, Response, Status};
 
 /// A mock implementation of the ClawpotService that stores VMs in memory.
@@ -21,15 +31,46 @@ use tonic::{transport::Server, Request, Response, Status};
 struct MockClawpotService {
     vms: Arc<Mutex<HashMap<String, VmInfo>>>,
     next_ip: Arc<Mutex<u8>>,
+    max_message_size: usize,
+    draining: AtomicBool,
+    /// Running streaming execs, by session ID
+    exec_sessions: ExecSessions,
 }
 
+type ExecSessions =
+    Arc<std::sync::Mutex<HashMap<String, tokio::sync::broadcast::Sender<ExecVmStreamOutput>>>>;
+
 impl MockClawpotService {
-    fn new() -> Self {
+    fn new(max_message_size: usize) -> Self {
         Self {
             vms: Arc::new(Mutex::new(HashMap::new())),
             next_ip: Arc::new(Mutex::new(2)),
+            max_message_size,
+            draining: AtomicBool::new(false),
+            exec_sessions: Arc::default(),
         }
     }
+
+    /// Move a VM from `from` to `to`, like pausing or resuming it.
+    async fn set_state(
+        &self,
+        vm_id: &str,
+        from: ProtoVmState,
+        to: ProtoVmState,
+    ) -> Result<(), Status> {
+        let mut vms = self.vms.lock().await;
+        let vm = vms
+            .get_mut(vm_id)
+            .ok_or_else(|| Status::not_found(format!("VM {vm_id} not found")))?;
+        if vm.state != from as i32 {
+            return Err(Status::failed_precondition(format!(
+                "VM {vm_id} is not {}",
+                from.as_str_name()
+            )));
+        }
+        vm.state = to as i32;
+        Ok(())
+    }
 }
 
 #[tonic::async_trait]
@@ -38,11 +79,28 @@ impl ClawpotService for MockClawpotService {
         &self,
         request: Request<CreateVmRequest>,
     ) -> Result<Response<CreateVmResponse>, Status> {
+        if self.draining.load(Ordering::Relaxed) {
+            return Err(Status::unavailable("Server is draining"));
+        }
         let req = request.into_inner();
         let vm_id = uuid::Uuid::new_v4().to_string();
-        let mut ip_counter = self.next_ip.lock().await;
-        let ip_address = format!("192.168.100.{}", *ip_counter);
-        *ip_counter += 1;
+        let ip_address = match req.ip_address.filter(|ip| !ip.is_empty()) {
+            Some(ip) => {
+                let vms = self.vms.lock().await;
+                if vms.values().any(|vm| vm.ip_address == ip) {
+                    return Err(Status::already_exists(format!(
+                        "IP address {ip} is already in use"
+                    )));
+                }
+                ip
+            }
+            None => {
+                let mut ip_counter = self.next_ip.lock().await;
+                let ip_address = format!("192.168.100.{}", *ip_counter);
+                *ip_counter += 1;
+                ip_address
+            }
+        };
         let socket_path = format!("/tmp/fc-{}.sock", vm_id);
 
         let vcpu_count = req.vcpu_count.unwrap_or(1);
@@ -56,6 +114,8 @@ impl ClawpotService for MockClawpotService {
             mem_size_mib,
             created_at: 1700000000,
             socket_path: socket_path.clone(),
+            name: req.name.unwrap_or_default(),
+            labels: req.labels,
         };
 
         self.vms.lock().await.insert(vm_id.clone(), info);
@@ -67,6 +127,115 @@ impl ClawpotService for MockClawpotService {
         }))
     }
 
+    async fn restore_vm(
+        &self,
+        request: Request<RestoreVmRequest>,
+    ) -> Result<Response<RestoreVmResponse>, Status> {
+        let req = request.into_inner();
+        if req.snapshot_path.is_empty() || req.mem_path.is_empty() {
+            return Err(Status::invalid_argument("Snapshot paths are required"));
+        }
+        let vm_id = uuid::Uuid::new_v4().to_string();
+        let ip_address = {
+            let mut ip_counter = self.next_ip.lock().await;
+            let ip_address = format!("192.168.100.{}", *ip_counter);
+            *ip_counter += 1;
+            ip_address
+        };
+        let socket_path = format!("/tmp/fc-{}.sock", vm_id);
+
+        let info = VmInfo {
+            vm_id: vm_id.clone(),
+            state: ProtoVmState::Running as i32,
+            ip_address: ip_address.clone(),
+            vcpu_count: 1,
+            mem_size_mib: 256,
+            created_at: 1700000000,
+            socket_path: socket_path.clone(),
+            name: req.name.unwrap_or_default(),
+            ..Default::default()
+        };
+        self.vms.lock().await.insert(vm_id.clone(), info);
+
+        Ok(Response::new(RestoreVmResponse {
+            vm_id,
+            ip_address,
+            socket_path,
+        }))
+    }
+
+    async fn pause_vm(
+        &self,
+        request: Request<PauseVmRequest>,
+    ) -> Result<Response<PauseVmResponse>, Status> {
+        let req = request.into_inner();
+        self.set_state(&req.vm_id, ProtoVmState::Running, ProtoVmState::Paused)
+            .await?;
+        Ok(Response::new(PauseVmResponse {}))
+    }
+
+    async fn resume_vm(
+        &self,
+        request: Request<ResumeVmRequest>,
+    ) -> Result<Response<ResumeVmResponse>, Status> {
+        let req = request.into_inner();
+        self.set_state(&req.vm_id, ProtoVmState::Paused, ProtoVmState::Running)
+            .await?;
+        Ok(Response::new(ResumeVmResponse {}))
+    }
+
+    async fn get_vm_status(
+        &self,
+        request: Request<GetVmStatusRequest>,
+    ) -> Result<Response<GetVmStatusResponse>, Status> {
+        let req = request.into_inner();
+        let vms = self.vms.lock().await;
+        let vm = vms
+            .get(&req.vm_id)
+            .ok_or_else(|| Status::not_found(format!("VM {} not found", req.vm_id)))?;
+        // Mock Firecracker: reports whatever state the VM was last put in
+        let firecracker_state = match ProtoVmState::try_from(vm.state) {
+            Ok(ProtoVmState::Paused) => "Paused",
+            _ => "Running",
+        };
+        Ok(Response::new(GetVmStatusResponse {
+            state: vm.state,
+            lifecycle_state: vm.state,
+            firecracker_state: firecracker_state.to_string(),
+            uptime_secs: 0,
+        }))
+    }
+
+    async fn reboot_vm(
+        &self,
+        request: Request<RebootVmRequest>,
+    ) -> Result<Response<RebootVmResponse>, Status> {
+        let req = request.into_inner();
+        let vms = self.vms.lock().await;
+        // The entry is kept as is, so the VM keeps its address
+        vms.get(&req.vm_id)
+            .ok_or_else(|| Status::not_found(format!("VM {} not found", req.vm_id)))?;
+        Ok(Response::new(RebootVmResponse { agent_ready: true }))
+    }
+
+    async fn update_vm_memory(
+        &self,
+        request: Request<UpdateVmMemoryRequest>,
+    ) -> Result<Response<UpdateVmMemoryResponse>, Status> {
+        let req = request.into_inner();
+        let vms = self.vms.lock().await;
+        let vm = vms
+            .get(&req.vm_id)
+            .ok_or_else(|| Status::not_found(format!("VM {} not found", req.vm_id)))?;
+        if req.balloon_mib > vm.mem_size_mib {
+            return Err(Status::invalid_argument(format!(
+                "Balloon size {} MiB exceeds the VM's {} MiB of memory",
+                req.balloon_mib, vm.mem_size_mib
+            )));
+        }
+        Ok(Response::new(UpdateVmMemoryResponse {}))
+    }
+
     async fn delete_vm(
         &self,
         request: Request<DeleteVmRequest>,
@@ -75,7 +244,10 @@ impl ClawpotService for MockClawpotService {
         let mut vms = self.vms.lock().await;
 
         if vms.remove(&req.vm_id).is_some() {
-            Ok(Response::new(DeleteVmResponse { success: true }))
+            Ok(Response::new(DeleteVmResponse {
+                success: true,
+                leaked: Vec::new(),
+            }))
         } else {
             Err(Status::not_found(format!("VM {} not found", req.vm_id)))
         }
@@ -83,11 +255,62 @@ impl ClawpotService for MockClawpotService {
 
     async fn list_v_ms(
         &self,
-        _request: Request<ListVmsRequest>,
+        request: Request<ListVmsRequest>,
     ) -> Result<Response<ListVmsResponse>, Status> {
+        let selector = request.into_inner().label_selector;
         let vms = self.vms.lock().await;
-        let vm_list: Vec<VmInfo> = vms.values().cloned().collect();
-        Ok(Response::new(ListVmsResponse { vms: vm_list }))
+        let allocated = vms.len() as u32;
+        let vm_list: Vec<VmInfo> = vms
+            .values()
+            .filter(|vm| selector.iter().all(|(k, v)| vm.labels.get(k) == Some(v)))
+            .cloned()
+            .collect();
+        Ok(Response::new(ListVmsResponse {
+            vms: vm_list,
+            ip_pool: Some(IpPoolStatus {
+                total: 253,
+                allocated,
+                available: 253 - allocated,
+            }),
+        }))
+    }
+
+    async fn describe_vm(
+        &self,
+        request: Request<DescribeVmRequest>,
+    ) -> Result<Response<DescribeVmResponse>, Status> {
+        let req = request.into_inner();
+        let vms = self.vms.lock().await;
+        let vm = vms
+            .get(&req.vm_id)
+            .ok_or_else(|| Status::not_found(format!("VM {} not found", req.vm_id)))?;
+        // Mock event log: every VM has only its creation recorded
+        let created = VmEvent {
+            timestamp: "2023-11-14T22:13:20.000Z".to_string(),
+            event_type: "vm.create.completed".to_string(),
+            correlation_id: String::new(),
+            duration_ms: Some(1200),
+            success: Some(true),
+            data: serde_json::json!({ "ip_address": vm.ip_address }).to_string(),
+        };
+        let recent_events: Vec<VmEvent> = std::iter::once(created)
+            .take(req.event_limit.unwrap_or(10) as usize)
+            .collect();
+        Ok(Response::new(DescribeVmResponse {
+            vm: Some(vm.clone()),
+            tap_name: format!("tap-{}", &vm.vm_id[..8]),
+            vsock_path: format!("/tmp/fc-{}-vsock.sock", vm.vm_id),
+            guest_cid: 3,
+            balloon: false,
+            kernel_path: "/var/lib/clawpot/vmlinux".to_string(),
+            rootfs_path: "/var/lib/clawpot/rootfs.ext4".to_string(),
+            boot_args: "console=ttyS0 reboot=k panic=1".to_string(),
+            last_activity: recent_events
+                .first()
+                .map(|e| e.timestamp.clone())
+                .unwrap_or_default(),
+            recent_events,
+        }))
     }
 
     async fn exec_vm(
@@ -95,13 +318,47 @@ impl ClawpotService for MockClawpotService {
         request: Request<ExecVmRequest>,
     ) -> Result<Response<ExecVmResponse>, Status> {
         let req = request.into_inner();
-        // Mock: just echo the command back as stdout
-        let output = format!("mock exec: {} {:?}\n", req.command, req.args);
-        Ok(Response::new(ExecVmResponse {
+        // Mock: `bytes <n>` produces n bytes of stdout, stdin is echoed like
+        // `cat`, and anything else is described back
+        let stdout = if req.command == "bytes" {
+            let len: usize = req.args[0].parse().unwrap();
+            vec![b'x'; len]
+        } else if let Some(stdin) = req.stdin {
+            stdin
+        } else {
+            format!("mock exec: {} {:?}\n", req.command, req.args).into_bytes()
+        };
+        let response = ExecVmResponse {
             exit_code: 0,
-            stdout: output.into_bytes(),
+            stdout,
             stderr: Vec::new(),
-        }))
+        };
+        ensure_message_fits(&response, self.max_message_size, "Exec output")?;
+        Ok(Response::new(response))
+    }
+
+    type StreamConsoleStream = tokio_stream::wrappers::ReceiverStream<Result<ConsoleLine, Status>>;
+
+    async fn stream_console(
+        &self,
+        request: Request<StreamConsoleRequest>,
+    ) -> Result<Response<Self::StreamConsoleStream>, Status> {
+        let req = request.into_inner();
+        if !self.vms.lock().await.contains_key(&req.vm_id) {
+            return Err(Status::not_found(format!("VM {} not found", req.vm_id)));
+        }
+        // Mock console: a short boot log, then the stream ends as if the VM stopped
+        let (tx, rx) = tokio::sync::mpsc::channel(4);
+        for line in ["Booting mock kernel", "login: "] {
+            let _ = tx
+                .send(Ok(ConsoleLine {
+                    line: line.to_string(),
+                }))
+                .await;
+        }
+        Ok(Response::new(tokio_stream::wrappers::ReceiverStream::new(
+            rx,
+        )))
     }
 
     type ExecVMStreamStream =
@@ -109,23 +366,176 @@ impl ClawpotService for MockClawpotService {
 
     async fn exec_vm_stream(
         &self,
-        _request: Request<tonic::Streaming<ExecVmStreamInput>>,
+        request: Request<tonic::Streaming<ExecVmStreamInput>>,
     ) -> Result<Response<Self::ExecVMStreamStream>, Status> {
-        Err(Status::unimplemented("not implemented in mock"))
+        let mut inbound = request.into_inner();
+        let Some(exec_vm_stream_input::Input::Start(start)) =
+            inbound.message().await?.and_then(|msg| msg.input)
+        else {
+            return Err(Status::invalid_argument(
+                "First message must be a start command",
+            ));
+        };
+        if !self.vms.lock().await.contains_key(&start.vm_id) {
+            return Err(Status::not_found(format!("VM {} not found", start.vm_id)));
+        }
+
+        // Mock agent: behaves like `cat`, echoing stdin until it is closed.
+        // Output is copied to attached clients.
+        let session_id = uuid::Uuid::new_v4().to_string();
+        let observers = tokio::sync::broadcast::channel(8).0;
+        self.exec_sessions
+            .lock()
+            .unwrap()
+            .insert(session_id.clone(), observers.clone());
+        let exec_sessions = Arc::clone(&self.exec_sessions);
+        let (tx, rx) = tokio::sync::mpsc::channel(8);
+        tokio::spawn(async move {
+            let _ = tx
+                .send(Ok(ExecVmStreamOutput {
+                    output: Some(exec_vm_stream_output::Output::SessionId(session_id.clone())),
+                }))
+                .await;
+            let send = |output| {
+                let msg = ExecVmStreamOutput {
+                    output: Some(output),
+                };
+                let _ = observers.send(msg.clone());
+                tx.send(Ok(msg))
+            };
+            while let Ok(Some(msg)) = inbound.message().await {
+                match msg.input {
+                    Some(exec_vm_stream_input::Input::StdinData(data)) => {
+                        let _ = send(exec_vm_stream_output::Output::StdoutData(data)).await;
+                    }
+                    Some(exec_vm_stream_input::Input::CloseStdin(_)) => break,
+                    _ => {}
+                }
+            }
+            let _ = send(exec_vm_stream_output::Output::ExitCode(0)).await;
+            exec_sessions.lock().unwrap().remove(&session_id);
+        });
+        Ok(Response::new(tokio_stream::wrappers::ReceiverStream::new(
+            rx,
+        )))
+    }
+
+    type AttachExecStream =
+        tokio_stream::wrappers::ReceiverStream<Result<ExecVmStreamOutput, Status>>;
+
+    async fn attach_exec(
+        &self,
+        request: Request<AttachExecRequest>,
+    ) -> Result<Response<Self::AttachExecStream>, Status> {
+        let req = request.into_inner();
+        let mut output = self
+            .exec_sessions
+            .lock()
+            .unwrap()
+            .get(&req.session_id)
+            .map(tokio::sync::broadcast::Sender::subscribe)
+            .ok_or_else(|| Status::not_found("No running exec with that session ID"))?;
+        let (tx, rx) = tokio::sync::mpsc::channel(8);
+        tokio::spawn(async move {
+            while let Ok(msg) = output.recv().await {
+                if tx.send(Ok(msg)).await.is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Response::new(tokio_stream::wrappers::ReceiverStream::new(
+            rx,
+        )))
+    }
+
+    async fn list_orphans(
+        &self,
+        _request: Request<ListOrphansRequest>,
+    ) -> Result<Response<ListOrphansResponse>, Status> {
+        // The mock never allocates host resources, so nothing can leak
+        Ok(Response::new(ListOrphansResponse::default()))
+    }
+
+    async fn clean_orphans(
+        &self,
+        _request: Request<CleanOrphansRequest>,
+    ) -> Result<Response<CleanOrphansResponse>, Status> {
+        Ok(Response::new(CleanOrphansResponse::default()))
+    }
+
+    async fn list_net_rules(
+        &self,
+        _request: Request<ListNetRulesRequest>,
+    ) -> Result<Response<ListNetRulesResponse>, Status> {
+        Ok(Response::new(ListNetRulesResponse::default()))
+    }
+
+    async fn get_server_info(
+        &self,
+        _request: Request<GetServerInfoRequest>,
+    ) -> Result<Response<GetServerInfoResponse>, Status> {
+        Ok(Response::new(GetServerInfoResponse {
+            version: "0.0.0-mock".to_string(),
+            draining: self.draining.load(Ordering::Relaxed),
+            ..Default::default()
+        }))
+    }
+
+    async fn reset_vm(
+        &self,
+        request: Request<ResetVmRequest>,
+    ) -> Result<Response<ResetVmResponse>, Status> {
+        let vm_id = request.into_inner().vm_id;
+        let mut vms = self.vms.lock().await;
+        let vm = vms
+            .get_mut(&vm_id)
+            .ok_or_else(|| Status::not_found(format!("VM {vm_id} not found")))?;
+        vm.name.clear();
+        vm.labels = HashMap::from([("clawpot.pool".to_string(), "idle".to_string())]);
+        Ok(Response::new(ResetVmResponse {
+            returned_to_pool: true,
+            reason: String::new(),
+        }))
+    }
+
+    async fn set_drain(
+        &self,
+        request: Request<SetDrainRequest>,
+    ) -> Result<Response<SetDrainResponse>, Status> {
+        self.draining
+            .store(request.into_inner().enabled, Ordering::Relaxed);
+        Ok(Response::new(SetDrainResponse {
+            vm_count: self.vms.lock().await.len() as u32,
+        }))
     }
 }
 
 /// Start a mock gRPC server on a random port and return the address.
 async fn start_mock_server() -> String {
+    start_mock_server_with(DEFAULT_MAX_MESSAGE_SIZE, None).await
+}
+
+/// Start a mock gRPC server with a custom max message size and optional compression.
+async fn start_mock_server_with(
+    max_message_size: usize,
+    compression: Option<CompressionEncoding>,
+) -> String {
     let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
     let addr = listener.local_addr().unwrap();
     let addr_str = format!("http://127.0.0.1:{}", addr.port());
 
-    let service = MockClawpotService::new();
+    let mut service = ClawpotServiceServer::new(MockClawpotService::new(max_message_size))
+        .max_decoding_message_size(max_message_size)
+        .max_encoding_message_size(max_message_size);
+    if let Some(encoding) = compression {
+        service = service
+            .send_compressed(encoding)
+            .accept_compressed(encoding);
+    }
 
     tokio::spawn(async move {
         Server::builder()
-            .add_service(ClawpotServiceServer::new(service))
+            .add_service(service)
             .serve_with_incoming(tokio_stream::wrappers::TcpListenerStream::new(listener))
             .await
             .unwrap();
@@ -143,7 +553,7 @@ async fn test_list_vms_empty() {
     let mut client = ClawpotServiceClient::connect(addr).await.unwrap();
 
     let response = client
-        .list_v_ms(ListVmsRequest {})
+        .list_v_ms(ListVmsRequest::default())
         .await
         .unwrap()
         .into_inner();
@@ -160,6 +570,7 @@ async fn test_create_vm_default_params() {
         .create_vm(CreateVmRequest {
             vcpu_count: None,
             mem_size_mib: None,
+            ..Default::default()
         })
         .await
         .unwrap()
@@ -186,6 +597,7 @@ async fn test_create_vm_custom_params() {
         .create_vm(CreateVmRequest {
             vcpu_count: Some(2),
             mem_size_mib: Some(512),
+            ..Default::default()
         })
         .await
         .unwrap()
@@ -195,7 +607,7 @@ async fn test_create_vm_custom_params() {
 
     // Verify the VM shows up in list with correct params
     let list = client
-        .list_v_ms(ListVmsRequest {})
+        .list_v_ms(ListVmsRequest::default())
         .await
         .unwrap()
         .into_inner();
@@ -217,6 +629,7 @@ async fn test_create_and_delete_vm() {
         .create_vm(CreateVmRequest {
             vcpu_count: Some(1),
             mem_size_mib: Some(256),
+            ..Default::default()
         })
         .await
         .unwrap()
@@ -226,7 +639,7 @@ async fn test_create_and_delete_vm() {
 
     // Verify it exists
     let list = client
-        .list_v_ms(ListVmsRequest {})
+        .list_v_ms(ListVmsRequest::default())
         .await
         .unwrap()
         .into_inner();
@@ -244,13 +657,345 @@ async fn test_create_and_delete_vm() {
 
     // Verify it's gone
     let list = client
-        .list_v_ms(ListVmsRequest {})
+        .list_v_ms(ListVmsRequest::default())
         .await
         .unwrap()
         .into_inner();
     assert!(list.vms.is_empty());
 }
 
+#[tokio::test]
+async fn test_pause_and_resume_vm() {
+    let addr = start_mock_server().await;
+    let mut client = ClawpotServiceClient::connect(addr).await.unwrap();
+
+    let vm_id = client
+        .create_vm(CreateVmRequest::default())
+        .await
+        .unwrap()
+        .into_inner()
+        .vm_id;
+
+    client
+        .pause_vm(PauseVmRequest {
+            vm_id: vm_id.clone(),
+        })
+        .await
+        .unwrap();
+    let list = client
+        .list_v_ms(ListVmsRequest::default())
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(list.vms[0].state, ProtoVmState::Paused as i32);
+
+    // Pausing twice is rejected
+    let status = client
+        .pause_vm(PauseVmRequest {
+            vm_id: vm_id.clone(),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::FailedPrecondition);
+
+    client.resume_vm(ResumeVmRequest { vm_id }).await.unwrap();
+    let list = client
+        .list_v_ms(ListVmsRequest::default())
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(list.vms[0].state, ProtoVmState::Running as i32);
+}
+
+#[tokio::test]
+async fn test_get_vm_status() {
+    let addr = start_mock_server().await;
+    let mut client = ClawpotServiceClient::connect(addr).await.unwrap();
+
+    let vm_id = client
+        .create_vm(CreateVmRequest::default())
+        .await
+        .unwrap()
+        .into_inner()
+        .vm_id;
+    client
+        .pause_vm(PauseVmRequest {
+            vm_id: vm_id.clone(),
+        })
+        .await
+        .unwrap();
+
+    let status = client
+        .get_vm_status(GetVmStatusRequest {
+            vm_id: vm_id.clone(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(status.state, ProtoVmState::Paused as i32);
+    assert_eq!(status.lifecycle_state, ProtoVmState::Paused as i32);
+    assert_eq!(status.firecracker_state, "Paused");
+
+    let err = client
+        .get_vm_status(GetVmStatusRequest {
+            vm_id: "missing".to_string(),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(err.code(), tonic::Code::NotFound);
+}
+
+#[tokio::test]
+async fn test_update_vm_memory() {
+    let addr = start_mock_server().await;
+    let mut client = ClawpotServiceClient::connect(addr).await.unwrap();
+
+    let vm_id = client
+        .create_vm(CreateVmRequest {
+            mem_size_mib: Some(512),
+            balloon_mib: Some(0),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_inner()
+        .vm_id;
+
+    client
+        .update_vm_memory(UpdateVmMemoryRequest {
+            vm_id: vm_id.clone(),
+            balloon_mib: 256,
+        })
+        .await
+        .unwrap();
+
+    // The balloon can't be larger than the VM's memory
+    let status = client
+        .update_vm_memory(UpdateVmMemoryRequest {
+            vm_id,
+            balloon_mib: 1024,
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+}
+
+#[tokio::test]
+async fn test_drain_rejects_new_vms() {
+    let addr = start_mock_server().await;
+    let mut client = ClawpotServiceClient::connect(addr).await.unwrap();
+
+    let vm_id = client
+        .create_vm(CreateVmRequest::default())
+        .await
+        .unwrap()
+        .into_inner()
+        .vm_id;
+
+    let drain = client
+        .set_drain(SetDrainRequest { enabled: true })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(drain.vm_count, 1);
+
+    let status = client
+        .create_vm(CreateVmRequest::default())
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::Unavailable);
+    let info = client
+        .get_server_info(GetServerInfoRequest {})
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(info.draining);
+
+    // Existing VMs are untouched
+    client.pause_vm(PauseVmRequest { vm_id }).await.unwrap();
+
+    client
+        .set_drain(SetDrainRequest { enabled: false })
+        .await
+        .unwrap();
+    client.create_vm(CreateVmRequest::default()).await.unwrap();
+}
+
+#[tokio::test]
+async fn test_reset_vm() {
+    let addr = start_mock_server().await;
+    let mut client = ClawpotServiceClient::connect(addr).await.unwrap();
+
+    let vm_id = client
+        .create_vm(CreateVmRequest {
+            name: Some("tenant-a".to_string()),
+            from_pool: Some(true),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_inner()
+        .vm_id;
+
+    let reset = client
+        .reset_vm(ResetVmRequest {
+            vm_id: vm_id.clone(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(reset.returned_to_pool);
+    assert!(reset.reason.is_empty());
+
+    // Back in the pool without the tenant's name
+    let vms = client
+        .list_v_ms(ListVmsRequest::default())
+        .await
+        .unwrap()
+        .into_inner()
+        .vms;
+    assert_eq!(vms.len(), 1);
+    assert!(vms[0].name.is_empty());
+    assert_eq!(vms[0].labels["clawpot.pool"], "idle");
+
+    let status = client
+        .reset_vm(ResetVmRequest {
+            vm_id: "missing".to_string(),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::NotFound);
+}
+
+#[tokio::test]
+async fn test_describe_vm() {
+    let addr = start_mock_server().await;
+    let mut client = ClawpotServiceClient::connect(addr).await.unwrap();
+
+    let created = client
+        .create_vm(CreateVmRequest {
+            name: Some("web-1".to_string()),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_inner();
+
+    let described = client
+        .describe_vm(DescribeVmRequest {
+            vm_id: created.vm_id.clone(),
+            event_limit: None,
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let vm = described.vm.unwrap();
+    assert_eq!(vm.vm_id, created.vm_id);
+    assert_eq!(vm.name, "web-1");
+    assert_eq!(vm.ip_address, created.ip_address);
+    assert!(described.tap_name.starts_with("tap-"));
+    assert_eq!(described.recent_events.len(), 1);
+    assert_eq!(described.recent_events[0].event_type, "vm.create.completed");
+    assert_eq!(
+        described.last_activity,
+        described.recent_events[0].timestamp
+    );
+
+    // Events can be left out
+    let described = client
+        .describe_vm(DescribeVmRequest {
+            vm_id: created.vm_id,
+            event_limit: Some(0),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(described.recent_events.is_empty());
+
+    let status = client
+        .describe_vm(DescribeVmRequest {
+            vm_id: "missing".to_string(),
+            event_limit: None,
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::NotFound);
+}
+
+#[tokio::test]
+async fn test_list_vms_by_label() {
+    let addr = start_mock_server().await;
+    let mut client = ClawpotServiceClient::connect(addr).await.unwrap();
+
+    for (name, tier) in [
+        ("web-1", "frontend"),
+        ("web-2", "frontend"),
+        ("db-1", "backend"),
+    ] {
+        client
+            .create_vm(CreateVmRequest {
+                name: Some(name.to_string()),
+                labels: HashMap::from([("tier".to_string(), tier.to_string())]),
+                ..Default::default()
+            })
+            .await
+            .unwrap();
+    }
+
+    let list = client
+        .list_v_ms(ListVmsRequest {
+            label_selector: HashMap::from([("tier".to_string(), "frontend".to_string())]),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let mut names: Vec<_> = list.vms.iter().map(|vm| vm.name.as_str()).collect();
+    names.sort_unstable();
+    assert_eq!(names, ["web-1", "web-2"]);
+    assert_eq!(list.vms[0].labels["tier"], "frontend");
+    // The pool still counts every VM
+    assert_eq!(list.ip_pool.unwrap().allocated, 3);
+}
+
+#[tokio::test]
+async fn test_reboot_vm_keeps_ip() {
+    let addr = start_mock_server().await;
+    let mut client = ClawpotServiceClient::connect(addr).await.unwrap();
+
+    let created = client
+        .create_vm(CreateVmRequest::default())
+        .await
+        .unwrap()
+        .into_inner();
+
+    let response = client
+        .reboot_vm(RebootVmRequest {
+            vm_id: created.vm_id.clone(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert!(response.agent_ready);
+
+    let list = client
+        .list_v_ms(ListVmsRequest::default())
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(list.vms.len(), 1);
+    assert_eq!(list.vms[0].vm_id, created.vm_id);
+    assert_eq!(list.vms[0].ip_address, created.ip_address);
+    assert_eq!(list.vms[0].state, ProtoVmState::Running as i32);
+
+    let status = client
+        .reboot_vm(RebootVmRequest {
+            vm_id: "missing".to_string(),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::NotFound);
+}
+
 #[tokio::test]
 async fn test_delete_nonexistent_vm() {
     let addr = start_mock_server().await;
@@ -276,6 +1021,7 @@ async fn test_create_multiple_vms_unique_ips() {
         .create_vm(CreateVmRequest {
             vcpu_count: None,
             mem_size_mib: None,
+            ..Default::default()
         })
         .await
         .unwrap()
@@ -285,6 +1031,7 @@ async fn test_create_multiple_vms_unique_ips() {
         .create_vm(CreateVmRequest {
             vcpu_count: None,
             mem_size_mib: None,
+            ..Default::default()
         })
         .await
         .unwrap()
@@ -298,9 +1045,275 @@ async fn test_create_multiple_vms_unique_ips() {
 
     // Both should appear in list
     let list = client
-        .list_v_ms(ListVmsRequest {})
+        .list_v_ms(ListVmsRequest::default())
         .await
         .unwrap()
         .into_inner();
     assert_eq!(list.vms.len(), 2);
 }
+
+fn exec_bytes_request(len: usize) -> ExecVmRequest {
+    ExecVmRequest {
+        vm_id: "vm".to_string(),
+        command: "bytes".to_string(),
+        args: vec![len.to_string()],
+        env: HashMap::new(),
+        working_dir: String::new(),
+        stdin: None,
+    }
+}
+
+#[tokio::test]
+async fn test_exec_with_stdin() {
+    let addr = start_mock_server().await;
+    let mut client = ClawpotServiceClient::connect(addr).await.unwrap();
+
+    let response = client
+        .exec_vm(ExecVmRequest {
+            vm_id: "vm".to_string(),
+            command: "cat".to_string(),
+            stdin: Some(b"piped input\n".to_vec()),
+            ..Default::default()
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(response.exit_code, 0);
+    assert_eq!(response.stdout, b"piped input\n");
+}
+
+#[tokio::test]
+async fn test_exec_output_near_message_limit() {
+    let addr = start_mock_server().await;
+    let mut client = ClawpotServiceClient::connect(addr)
+        .await
+        .unwrap()
+        .max_decoding_message_size(DEFAULT_MAX_MESSAGE_SIZE);
+
+    // Leave a little headroom for the protobuf framing of the other fields
+    let len = DEFAULT_MAX_MESSAGE_SIZE - 16;
+    let response = client
+        .exec_vm(exec_bytes_request(len))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(response.stdout.len(), len);
+}
+
+#[tokio::test]
+async fn test_exec_output_over_message_limit() {
+    let addr = start_mock_server().await;
+    let mut client = ClawpotServiceClient::connect(addr).await.unwrap();
+
+    let status = client
+        .exec_vm(exec_bytes_request(DEFAULT_MAX_MESSAGE_SIZE))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+    assert!(
+        status.message().contains("streaming API"),
+        "Expected a hint about the streaming API, got: {}",
+        status.message()
+    );
+}
+
+#[tokio::test]
+async fn test_exec_output_with_raised_limit() {
+    let limit = DEFAULT_MAX_MESSAGE_SIZE * 2;
+    let addr = start_mock_server_with(limit, None).await;
+    let mut client = ClawpotServiceClient::connect(addr)
+        .await
+        .unwrap()
+        .max_decoding_message_size(limit);
+
+    let response = client
+        .exec_vm(exec_bytes_request(DEFAULT_MAX_MESSAGE_SIZE))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(response.stdout.len(), DEFAULT_MAX_MESSAGE_SIZE);
+}
+
+#[tokio::test]
+async fn test_exec_output_with_gzip_compression() {
+    let addr =
+        start_mock_server_with(DEFAULT_MAX_MESSAGE_SIZE, Some(CompressionEncoding::Gzip)).await;
+    let mut client = ClawpotServiceClient::connect(addr)
+        .await
+        .unwrap()
+        .send_compressed(CompressionEncoding::Gzip)
+        .accept_compressed(CompressionEncoding::Gzip);
+
+    let len = 1024 * 1024;
+    let response = client
+        .exec_vm(exec_bytes_request(len))
+        .await
+        .unwrap()
+        .into_inner();
+    assert_eq!(response.stdout.len(), len);
+}
+
+fn stream_input(input: exec_vm_stream_input::Input) -> ExecVmStreamInput {
+    ExecVmStreamInput { input: Some(input) }
+}
+
+#[tokio::test]
+async fn test_exec_stream_pipes_stdin() {
+    let addr = start_mock_server().await;
+    let mut client = ClawpotServiceClient::connect(addr).await.unwrap();
+    let vm_id = client
+        .create_vm(CreateVmRequest::default())
+        .await
+        .unwrap()
+        .into_inner()
+        .vm_id;
+
+    let mut input = vec![stream_input(exec_vm_stream_input::Input::Start(
+        ExecVmStreamStart {
+            vm_id,
+            command: "cat".to_string(),
+            ..Default::default()
+        },
+    ))];
+    for chunk in ["hello ", "from ", "stdin\n"] {
+        input.push(stream_input(exec_vm_stream_input::Input::StdinData(
+            chunk.as_bytes().to_vec(),
+        )));
+    }
+    input.push(stream_input(exec_vm_stream_input::Input::CloseStdin(true)));
+
+    let mut output = client
+        .exec_vm_stream(tokio_stream::iter(input))
+        .await
+        .unwrap()
+        .into_inner();
+
+    let mut stdout = Vec::new();
+    let mut exit_code = None;
+    while let Some(msg) = output.message().await.unwrap() {
+        match msg.output {
+            Some(exec_vm_stream_output::Output::StdoutData(data)) => stdout.extend(data),
+            Some(exec_vm_stream_output::Output::ExitCode(code)) => exit_code = Some(code),
+            _ => {}
+        }
+    }
+    assert_eq!(stdout, b"hello from stdin\n");
+    assert_eq!(exit_code, Some(0));
+}
+
+#[tokio::test]
+async fn test_attach_exec() {
+    let addr = start_mock_server().await;
+    let mut client = ClawpotServiceClient::connect(addr.clone()).await.unwrap();
+    let vm_id = client
+        .create_vm(CreateVmRequest::default())
+        .await
+        .unwrap()
+        .into_inner()
+        .vm_id;
+
+    let (tx, rx) = tokio::sync::mpsc::channel(4);
+    tx.send(stream_input(exec_vm_stream_input::Input::Start(
+        ExecVmStreamStart {
+            vm_id,
+            command: "cat".to_string(),
+            ..Default::default()
+        },
+    )))
+    .await
+    .unwrap();
+    let mut output = client
+        .exec_vm_stream(tokio_stream::wrappers::ReceiverStream::new(rx))
+        .await
+        .unwrap()
+        .into_inner();
+    let Some(exec_vm_stream_output::Output::SessionId(session_id)) =
+        output.message().await.unwrap().unwrap().output
+    else {
+        panic!("First frame must carry the session ID");
+    };
+
+    // A second client attaches, then sees the output that follows
+    let mut observer_client = ClawpotServiceClient::connect(addr).await.unwrap();
+    let mut observed = observer_client
+        .attach_exec(AttachExecRequest {
+            session_id: session_id.clone(),
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    for input in [
+        exec_vm_stream_input::Input::StdinData(b"shared\n".to_vec()),
+        exec_vm_stream_input::Input::CloseStdin(true),
+    ] {
+        tx.send(stream_input(input)).await.unwrap();
+    }
+    while output.message().await.unwrap().is_some() {}
+
+    let mut stdout = Vec::new();
+    let mut exit_code = None;
+    while let Some(msg) = observed.message().await.unwrap() {
+        match msg.output {
+            Some(exec_vm_stream_output::Output::StdoutData(data)) => stdout.extend(data),
+            Some(exec_vm_stream_output::Output::ExitCode(code)) => exit_code = Some(code),
+            _ => {}
+        }
+    }
+    assert_eq!(stdout, b"shared\n");
+    assert_eq!(exit_code, Some(0));
+
+    // The session is gone once the exec has finished
+    let status = observer_client
+        .attach_exec(AttachExecRequest { session_id })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::NotFound);
+}
+
+#[tokio::test]
+async fn test_exec_stream_requires_start() {
+    let addr = start_mock_server().await;
+    let mut client = ClawpotServiceClient::connect(addr).await.unwrap();
+
+    let input = vec![stream_input(exec_vm_stream_input::Input::StdinData(
+        b"no start".to_vec(),
+    ))];
+    let status = client
+        .exec_vm_stream(tokio_stream::iter(input))
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::InvalidArgument);
+}
+
+#[tokio::test]
+async fn test_stream_console() {
+    let addr = start_mock_server().await;
+    let mut client = ClawpotServiceClient::connect(addr).await.unwrap();
+
+    let created = client
+        .create_vm(CreateVmRequest::default())
+        .await
+        .unwrap()
+        .into_inner();
+
+    let mut stream = client
+        .stream_console(StreamConsoleRequest {
+            vm_id: created.vm_id,
+        })
+        .await
+        .unwrap()
+        .into_inner();
+    let mut lines = Vec::new();
+    while let Some(msg) = stream.message().await.unwrap() {
+        lines.push(msg.line);
+    }
+    assert_eq!(lines, ["Booting mock kernel", "login: "]);
+
+    let status = client
+        .stream_console(StreamConsoleRequest {
+            vm_id: "missing".to_string(),
+        })
+        .await
+        .unwrap_err();
+    assert_eq!(status.code(), tonic::Code::NotFound);
+}
@@ -1,11 +1,13 @@
 use anyhow::{anyhow, Context, Result};
 use clawpot_common::agent_proto::{
-    agent_service_client::AgentServiceClient, ExecRequest, ExecResponse, HealthRequest,
+    agent_service_client::AgentServiceClient, ExecRequest, ExecResponse, ExecStreamInput,
+    ExecStreamOutput, HealthRequest,
 };
 use clawpot_common::AGENT_VSOCK_PORT;
 use std::time::Duration;
 use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
 use tokio::net::UnixStream;
+use tokio_stream::wrappers::ReceiverStream;
 use tonic::transport::{Channel, Endpoint, Uri};
 use tower::service_fn;
 use tracing::debug;
@@ -61,6 +63,13 @@ impl AgentClient {
         })
     }
 
+    /// Raise the maximum response size accepted from the agent
+    #[must_use]
+    pub fn with_max_message_size(mut self, limit: usize) -> Self {
+        self.inner = self.inner.max_decoding_message_size(limit);
+        self
+    }
+
     /// Wait for the agent to become ready, retrying with backoff.
     #[tracing::instrument(name = "agent.wait_ready", skip_all, fields(timeout_secs = timeout.as_secs()))]
     pub async fn wait_ready(vsock_uds_path: &str, timeout: Duration) -> Result<Self> {
@@ -99,13 +108,27 @@ impl AgentClient {
     }
 
     /// Execute a command and return the result
+    ///
+    /// The underlying `tonic::Status` is preserved in the error chain so callers
+    /// can inspect it with `downcast_ref`.
     #[tracing::instrument(name = "agent.exec", skip_all, fields(command = %req.command))]
     pub async fn exec(&mut self, req: ExecRequest) -> Result<ExecResponse> {
+        let response = self.inner.exec(req).await.context("Agent exec failed")?;
+        Ok(response.into_inner())
+    }
+
+    /// Start an interactive exec. The first message on `input` must be the
+    /// start command; the returned stream yields output until the exit code.
+    #[tracing::instrument(name = "agent.exec_stream", skip_all)]
+    pub async fn exec_stream(
+        &mut self,
+        input: ReceiverStream<ExecStreamInput>,
+    ) -> Result<tonic::Streaming<ExecStreamOutput>> {
         let response = self
             .inner
-            .exec(req)
+            .exec_stream(input)
             .await
-            .map_err(|e| anyhow!("Agent exec failed: {e}"))?;
+            .context("Agent exec stream failed")?;
         Ok(response.into_inner())
     }
 }
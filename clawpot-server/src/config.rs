@@ -0,0 +1,592 @@
+use crate::events::retention::MAX_DB_BYTES_ENV;
+use crate::events::{self, PersistMode, INDEXED_HEADERS_ENV};
+use crate::grpc::audit::AUDIT_GRPC_ENV;
+use crate::network::ip_allocator;
+use crate::proxy::body_store::{DEFAULT_HIGH_USAGE_BYTES, HIGH_USAGE_ENV};
+use crate::proxy::dns_allowlist::{DenyRcode, DNS_ALLOWLIST_ENV, DNS_ALLOWLIST_RCODE_ENV};
+use crate::proxy::dns_proxy::{
+    self, DnsMode, DEFAULT_UPSTREAM_DNS, DNS_MODE_ENV, DNS_UPSTREAM_ENV,
+};
+use crate::proxy::doh::{self, DEFAULT_DOH_URL, DOH_URL_ENV};
+use crate::proxy::http_proxy::{
+    DEFAULT_IDLE_TIMEOUT, DEFAULT_MAX_INLINE_BODY, DEFAULT_UPSTREAM_RETRIES,
+    DEFAULT_UPSTREAM_TIMEOUT, MAX_INLINE_BODY_ENV, MAX_UPSTREAM_RETRIES, UPSTREAM_RETRIES_ENV,
+    UPSTREAM_TIMEOUT_ENV,
+};
+use crate::proxy::llm::DEFAULT_MAX_SSE_BYTES;
+use crate::proxy::methods::{MethodPolicy, ALLOWED_METHODS_ENV};
+use crate::proxy::redact::{HeaderRedaction, REDACT_HEADERS_ENV};
+use crate::proxy::splice::{HostList, BYPASS_HOSTS_ENV, SPLICE_HOSTS_ENV};
+use crate::proxy::ssrf::SSRF_ALLOW_ENV;
+use crate::proxy::token_budget::TOKEN_BUDGET_ENV;
+use crate::vm::host::{DEFAULT_MEMORY_MARGIN_MIB, MEMORY_CHECK_ENV, MEMORY_MARGIN_ENV};
+use crate::vm::pool::{POOL_SIZE_ENV, RESET_SCRIPT_ENV};
+use anyhow::{bail, Result};
+use clawpot_common::firecracker::{config::ROOTFS_MODE_ENV, RootfsMode};
+use clawpot_common::grpc::{self, DEFAULT_MAX_MESSAGE_SIZE};
+use hyper::Uri;
+use std::collections::BTreeMap;
+use std::net::{Ipv4Addr, SocketAddr};
+use std::path::PathBuf;
+use std::str::FromStr;
+use std::time::Duration;
+use tonic::codec::CompressionEncoding;
+
+/// Project root used when `CLAWPOT_ROOT` is unset
+const DEFAULT_ROOT: &str = "/workspaces/clawpot";
+
+/// Server settings from the `CLAWPOT_*` environment variables, read and
+/// validated once at startup.
+///
+/// LLM provider keys (`CLAWPOT_ANTHROPIC_API_KEY`, ...) are secrets and are
+/// read by the LLM key store instead, so they never show up in [`Self::summary`].
+#[allow(clippy::struct_excessive_bools)]
+pub struct Config {
+    /// Holds `assets/`, `ca/` and `data/` (`CLAWPOT_ROOT`)
+    pub root: PathBuf,
+    pub events_db: PathBuf,
+    pub events_persist: PersistMode,
+    /// Collapse identical consecutive log events within this window (`None` keeps every one)
+    pub events_coalesce_logs: Option<Duration>,
+    /// Header names copied into the indexed `event_headers` table
+    pub indexed_headers: Vec<String>,
+    /// Prune the oldest finished sessions once the events DB holds more (`None` keeps everything)
+    pub events_max_bytes: Option<u64>,
+    pub auth_addr: Option<String>,
+    /// HTTPS interception; off with `CLAWPOT_DISABLE_MITM`
+    pub mitm_enabled: bool,
+    /// VM subnet as given, e.g. `192.168.100.0/24`
+    pub subnet: String,
+    pub subnet_network: Ipv4Addr,
+    pub subnet_prefix: u8,
+    pub excluded_ips: Vec<Ipv4Addr>,
+    /// Body store usage that triggers an alert (`None` disables it)
+    pub body_store_high_usage_bytes: Option<u64>,
+    pub dns_overrides_file: Option<PathBuf>,
+    /// Domain suffixes VMs may resolve; every name is allowed without it
+    pub dns_allowlist_file: Option<PathBuf>,
+    /// Answer to names outside the allowlist
+    pub dns_allowlist_rcode: DenyRcode,
+    /// Whether the DNS proxy forwards over UDP or DNS-over-HTTPS
+    pub dns_mode: DnsMode,
+    /// Resolvers the DNS proxy forwards to, in order of preference
+    pub dns_upstreams: Vec<SocketAddr>,
+    /// Endpoint the DNS proxy forwards to in DoH mode
+    pub dns_doh_url: Uri,
+    /// Keep VMs that fail to boot for debugging instead of rolling back
+    pub keep_on_failure: bool,
+    pub rootfs_mode: RootfsMode,
+    /// Idle VMs kept booted for `CreateVM` with `from_pool` (0 disables the pool)
+    pub vm_pool_size: usize,
+    /// Script run in a pooled VM by `ResetVM` instead of the built-in cleanup
+    pub vm_reset_script: Option<PathBuf>,
+    /// Refuse new VMs whose memory the host can't spare
+    pub host_memory_check: bool,
+    /// Host memory (MiB) that must stay available on top of a new VM's own
+    pub host_memory_margin_mib: u64,
+    pub grpc_max_message_size: usize,
+    pub grpc_compression: Option<CompressionEncoding>,
+    /// Serve gRPC on TCP port 50051 (off with `CLAWPOT_LISTEN_TCP=0`)
+    pub listen_tcp: bool,
+    /// Also serve gRPC on a Unix socket at this path, for local clients
+    pub listen_uds: Option<PathBuf>,
+    /// Record every gRPC call as a `grpc.call` event
+    pub audit_grpc: bool,
+    pub ready_file: Option<PathBuf>,
+    pub iptables_dry_run: bool,
+    pub proxy: ProxyConfig,
+}
+
+/// HTTP proxy settings
+#[derive(Clone)]
+pub struct ProxyConfig {
+    pub splice_hosts: HostList,
+    pub bypass_hosts: HostList,
+    /// Splice non-HTTP port-80 traffic to its original destination
+    pub splice_non_http: bool,
+    /// Close VM connections idle for this long (`None` disables the timeout)
+    pub idle_timeout: Option<Duration>,
+    /// Redirects the proxy follows itself (0 disables)
+    pub max_redirects: usize,
+    /// Streaming LLM responses larger than this are not reassembled (`None` for no cap)
+    pub llm_max_sse_bytes: Option<usize>,
+    pub llm_estimate_tokens: bool,
+    pub inject_request_id: bool,
+    pub methods: MethodPolicy,
+    /// Internal hosts or IPs VMs may still reach
+    pub ssrf_allow: HostList,
+    /// LLM tokens each VM may spend (`None` for no cap)
+    pub vm_token_budget: Option<u64>,
+    /// Request and response bodies larger than this are streamed, not buffered
+    pub max_inline_body: usize,
+    /// Wait this long for an upstream's response headers (`None` to wait forever)
+    pub upstream_timeout: Option<Duration>,
+    /// Times a GET or HEAD is retried after a connection error or a 502/503/504
+    pub upstream_retries: usize,
+    /// Headers whose values are replaced in recorded HTTP events
+    pub redact_headers: HeaderRedaction,
+    /// Deny traffic from source IPs that aren't registered VMs
+    pub deny_unknown_vm: bool,
+}
+
+impl Config {
+    /// Read the configuration from the process environment.
+    ///
+    /// Every variable is checked before failing, so one error lists all of
+    /// the invalid settings.
+    pub fn from_env() -> Result<Self> {
+        Self::load(|name| std::env::var(name).ok())
+    }
+
+    fn load(get: impl Fn(&str) -> Option<String>) -> Result<Self> {
+        let mut env = Env {
+            get,
+            errors: Vec::new(),
+        };
+
+        let root = env
+            .raw("CLAWPOT_ROOT")
+            .map_or_else(|| PathBuf::from(DEFAULT_ROOT), PathBuf::from);
+        let events_db = env
+            .raw("CLAWPOT_EVENTS_DB")
+            .map_or_else(|| root.join("data/events.db"), PathBuf::from);
+        let events_persist = env.parse("CLAWPOT_EVENTS_PERSIST", PersistMode::All, |raw| {
+            PersistMode::parse(raw).ok_or_else(|| {
+                "expected all, structured, none, or rules such as all,-log,-network.dns.*"
+                    .to_string()
+            })
+        });
+        let events_coalesce_logs = env
+            .number_or_off("CLAWPOT_EVENTS_COALESCE_LOGS_MS", 0)
+            .map(Duration::from_millis);
+        let indexed_headers = env.parse(INDEXED_HEADERS_ENV, Vec::new(), |raw| {
+            Ok(events::parse_header_names(raw))
+        });
+        let events_max_bytes = env.number_or_off(MAX_DB_BYTES_ENV, 0);
+
+        let subnet = env
+            .raw(ip_allocator::SUBNET_ENV)
+            .unwrap_or_else(|| ip_allocator::DEFAULT_SUBNET.to_string());
+        let (subnet_network, subnet_prefix) = match ip_allocator::parse_cidr(&subnet) {
+            Ok(parsed) => parsed,
+            Err(e) => {
+                env.fail(ip_allocator::SUBNET_ENV, &subnet, &format!("{e:#}"));
+                (Ipv4Addr::UNSPECIFIED, 0)
+            }
+        };
+        let excluded_ips = env.parse(ip_allocator::EXCLUDE_ENV, Vec::new(), |raw| {
+            ip_allocator::parse_exclusions(raw).map_err(|e| format!("{e:#}"))
+        });
+
+        let grpc_max_message_size = env.parse(
+            "CLAWPOT_GRPC_MAX_MESSAGE_SIZE",
+            DEFAULT_MAX_MESSAGE_SIZE,
+            |raw| match raw.parse() {
+                Ok(0) | Err(_) => Err("expected a positive number of bytes".to_string()),
+                Ok(size) => Ok(size),
+            },
+        );
+        let grpc_compression = env.parse("CLAWPOT_GRPC_COMPRESSION", None, |raw| {
+            grpc::parse_compression(raw)
+                .map(Some)
+                .ok_or_else(|| "expected gzip".to_string())
+        });
+
+        let listen_tcp = env.flag("CLAWPOT_LISTEN_TCP", true);
+        let listen_uds = env.raw("CLAWPOT_LISTEN_UDS").map(PathBuf::from);
+        if !listen_tcp && listen_uds.is_none() {
+            env.errors.push(
+                "CLAWPOT_LISTEN_TCP=false needs CLAWPOT_LISTEN_UDS, or gRPC is unreachable"
+                    .to_string(),
+            );
+        }
+
+        let proxy = ProxyConfig {
+            splice_hosts: env.hosts(SPLICE_HOSTS_ENV),
+            bypass_hosts: env.hosts(BYPASS_HOSTS_ENV),
+            splice_non_http: env.flag("CLAWPOT_SPLICE_NON_HTTP", false),
+            idle_timeout: env
+                .number_or_off(
+                    "CLAWPOT_PROXY_IDLE_TIMEOUT_SECS",
+                    DEFAULT_IDLE_TIMEOUT.as_secs(),
+                )
+                .map(Duration::from_secs),
+            max_redirects: env.number("CLAWPOT_PROXY_MAX_REDIRECTS", 0),
+            llm_max_sse_bytes: env
+                .number_or_off("CLAWPOT_LLM_MAX_SSE_BYTES", DEFAULT_MAX_SSE_BYTES),
+            llm_estimate_tokens: env.flag("CLAWPOT_LLM_ESTIMATE_TOKENS", false),
+            inject_request_id: env.flag("CLAWPOT_INJECT_REQUEST_ID", false),
+            methods: env.parse(ALLOWED_METHODS_ENV, MethodPolicy::default(), |raw| {
+                MethodPolicy::parse(raw)
+                    .ok_or_else(|| "expected a comma-separated list of methods or *".to_string())
+            }),
+            ssrf_allow: env.hosts(SSRF_ALLOW_ENV),
+            vm_token_budget: env.number_or_off(TOKEN_BUDGET_ENV, 0),
+            max_inline_body: env.number(MAX_INLINE_BODY_ENV, DEFAULT_MAX_INLINE_BODY),
+            upstream_timeout: env
+                .number_or_off(UPSTREAM_TIMEOUT_ENV, DEFAULT_UPSTREAM_TIMEOUT.as_secs())
+                .map(Duration::from_secs),
+            upstream_retries: env.parse(UPSTREAM_RETRIES_ENV, DEFAULT_UPSTREAM_RETRIES, |raw| {
+                raw.parse()
+                    .ok()
+                    .filter(|&retries| retries <= MAX_UPSTREAM_RETRIES)
+                    .ok_or_else(|| format!("expected a whole number up to {MAX_UPSTREAM_RETRIES}"))
+            }),
+            redact_headers: env.parse(REDACT_HEADERS_ENV, HeaderRedaction::default(), |raw| {
+                Ok(HeaderRedaction::parse(raw))
+            }),
+            deny_unknown_vm: env.flag("CLAWPOT_DENY_UNKNOWN_VM", true),
+        };
+
+        let config = Self {
+            events_db,
+            events_persist,
+            events_coalesce_logs,
+            indexed_headers,
+            events_max_bytes,
+            auth_addr: env.raw("CLAWPOT_AUTH_ADDR"),
+            mitm_enabled: !env.flag("CLAWPOT_DISABLE_MITM", false),
+            subnet,
+            subnet_network,
+            subnet_prefix,
+            excluded_ips,
+            body_store_high_usage_bytes: env
+                .number_or_off(HIGH_USAGE_ENV, DEFAULT_HIGH_USAGE_BYTES),
+            dns_overrides_file: env.raw("CLAWPOT_DNS_OVERRIDES").map(PathBuf::from),
+            dns_allowlist_file: env.raw(DNS_ALLOWLIST_ENV).map(PathBuf::from),
+            dns_allowlist_rcode: env.parse(DNS_ALLOWLIST_RCODE_ENV, DenyRcode::default(), |raw| {
+                DenyRcode::parse(raw).ok_or_else(|| "expected refused or nxdomain".to_string())
+            }),
+            dns_mode: env.parse(DNS_MODE_ENV, DnsMode::default(), |raw| {
+                DnsMode::parse(raw).ok_or_else(|| "expected udp or doh".to_string())
+            }),
+            dns_upstreams: env.parse(
+                DNS_UPSTREAM_ENV,
+                vec![DEFAULT_UPSTREAM_DNS.parse().unwrap()],
+                dns_proxy::parse_upstreams,
+            ),
+            dns_doh_url: env.parse(
+                DOH_URL_ENV,
+                Uri::from_static(DEFAULT_DOH_URL),
+                doh::parse_url,
+            ),
+            keep_on_failure: env.flag("CLAWPOT_KEEP_ON_FAILURE", false),
+            rootfs_mode: env.parse(ROOTFS_MODE_ENV, RootfsMode::default(), |raw| {
+                RootfsMode::parse(raw)
+                    .ok_or_else(|| "expected shared, read-only or copy-on-write".to_string())
+            }),
+            vm_pool_size: env.number(POOL_SIZE_ENV, 0),
+            vm_reset_script: env.raw(RESET_SCRIPT_ENV).map(PathBuf::from),
+            host_memory_check: env.flag(MEMORY_CHECK_ENV, true),
+            host_memory_margin_mib: env.number(MEMORY_MARGIN_ENV, DEFAULT_MEMORY_MARGIN_MIB),
+            grpc_max_message_size,
+            grpc_compression,
+            listen_tcp,
+            listen_uds,
+            audit_grpc: env.flag(AUDIT_GRPC_ENV, false),
+            ready_file: env.raw("CLAWPOT_READY_FILE").map(PathBuf::from),
+            iptables_dry_run: env.flag("CLAWPOT_IPTABLES_DRYRUN", false),
+            proxy,
+            root,
+        };
+
+        if !env.errors.is_empty() {
+            bail!("Invalid configuration:\n  {}", env.errors.join("\n  "));
+        }
+        Ok(config)
+    }
+
+    /// Effective value of every setting, keyed by environment variable, for
+    /// logging and `GetServerInfo`.
+    pub fn summary(&self) -> BTreeMap<String, String> {
+        fn off_or<T: ToString>(value: Option<T>) -> String {
+            value.map_or_else(|| "off".to_string(), |v| v.to_string())
+        }
+        fn path(value: Option<&PathBuf>) -> String {
+            value.map_or_else(String::new, |p| p.display().to_string())
+        }
+
+        let proxy = &self.proxy;
+        [
+            ("CLAWPOT_ROOT", self.root.display().to_string()),
+            ("CLAWPOT_EVENTS_DB", self.events_db.display().to_string()),
+            ("CLAWPOT_EVENTS_PERSIST", self.events_persist.to_string()),
+            (
+                "CLAWPOT_EVENTS_COALESCE_LOGS_MS",
+                off_or(self.events_coalesce_logs.map(|w| w.as_millis())),
+            ),
+            (INDEXED_HEADERS_ENV, self.indexed_headers.join(",")),
+            (MAX_DB_BYTES_ENV, off_or(self.events_max_bytes)),
+            (
+                "CLAWPOT_AUTH_ADDR",
+                self.auth_addr.clone().unwrap_or_default(),
+            ),
+            ("CLAWPOT_DISABLE_MITM", (!self.mitm_enabled).to_string()),
+            (ip_allocator::SUBNET_ENV, self.subnet.clone()),
+            (
+                ip_allocator::EXCLUDE_ENV,
+                self.excluded_ips
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(","),
+            ),
+            (HIGH_USAGE_ENV, off_or(self.body_store_high_usage_bytes)),
+            (
+                "CLAWPOT_DNS_OVERRIDES",
+                path(self.dns_overrides_file.as_ref()),
+            ),
+            (DNS_ALLOWLIST_ENV, path(self.dns_allowlist_file.as_ref())),
+            (
+                DNS_ALLOWLIST_RCODE_ENV,
+                self.dns_allowlist_rcode.as_str().to_string(),
+            ),
+            (DNS_MODE_ENV, self.dns_mode.as_str().to_string()),
+            (
+                DNS_UPSTREAM_ENV,
+                self.dns_upstreams
+                    .iter()
+                    .map(ToString::to_string)
+                    .collect::<Vec<_>>()
+                    .join(","),
+            ),
+            (DOH_URL_ENV, self.dns_doh_url.to_string()),
+            ("CLAWPOT_KEEP_ON_FAILURE", self.keep_on_failure.to_string()),
+            (ROOTFS_MODE_ENV, self.rootfs_mode.as_str().to_string()),
+            (
+                POOL_SIZE_ENV,
+                off_or(Some(self.vm_pool_size).filter(|&n| n > 0)),
+            ),
+            (RESET_SCRIPT_ENV, path(self.vm_reset_script.as_ref())),
+            (MEMORY_CHECK_ENV, self.host_memory_check.to_string()),
+            (MEMORY_MARGIN_ENV, self.host_memory_margin_mib.to_string()),
+            (
+                "CLAWPOT_GRPC_MAX_MESSAGE_SIZE",
+                self.grpc_max_message_size.to_string(),
+            ),
+            ("CLAWPOT_GRPC_COMPRESSION", off_or(self.grpc_compression)),
+            ("CLAWPOT_LISTEN_TCP", self.listen_tcp.to_string()),
+            ("CLAWPOT_LISTEN_UDS", path(self.listen_uds.as_ref())),
+            (AUDIT_GRPC_ENV, self.audit_grpc.to_string()),
+            ("CLAWPOT_READY_FILE", path(self.ready_file.as_ref())),
+            ("CLAWPOT_DENY_UNKNOWN_VM", proxy.deny_unknown_vm.to_string()),
+            ("CLAWPOT_IPTABLES_DRYRUN", self.iptables_dry_run.to_string()),
+            (SPLICE_HOSTS_ENV, proxy.splice_hosts.to_string()),
+            (BYPASS_HOSTS_ENV, proxy.bypass_hosts.to_string()),
+            ("CLAWPOT_SPLICE_NON_HTTP", proxy.splice_non_http.to_string()),
+            (
+                "CLAWPOT_PROXY_IDLE_TIMEOUT_SECS",
+                off_or(proxy.idle_timeout.map(|t| t.as_secs())),
+            ),
+            (
+                "CLAWPOT_PROXY_MAX_REDIRECTS",
+                proxy.max_redirects.to_string(),
+            ),
+            ("CLAWPOT_LLM_MAX_SSE_BYTES", off_or(proxy.llm_max_sse_bytes)),
+            (
+                "CLAWPOT_LLM_ESTIMATE_TOKENS",
+                proxy.llm_estimate_tokens.to_string(),
+            ),
+            (
+                "CLAWPOT_INJECT_REQUEST_ID",
+                proxy.inject_request_id.to_string(),
+            ),
+            (ALLOWED_METHODS_ENV, proxy.methods.to_string()),
+            (SSRF_ALLOW_ENV, proxy.ssrf_allow.to_string()),
+            (TOKEN_BUDGET_ENV, off_or(proxy.vm_token_budget)),
+            (MAX_INLINE_BODY_ENV, proxy.max_inline_body.to_string()),
+            (
+                UPSTREAM_TIMEOUT_ENV,
+                off_or(proxy.upstream_timeout.map(|t| t.as_secs())),
+            ),
+            (UPSTREAM_RETRIES_ENV, proxy.upstream_retries.to_string()),
+            (REDACT_HEADERS_ENV, proxy.redact_headers.to_string()),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_string(), value))
+        .collect()
+    }
+}
+
+/// Reads variables through `get`, recording every invalid value instead of
+/// stopping at the first one.
+struct Env<F> {
+    get: F,
+    errors: Vec<String>,
+}
+
+impl<F: Fn(&str) -> Option<String>> Env<F> {
+    /// Trimmed value of `name`, or `None` if it is unset or blank
+    fn raw(&self, name: &str) -> Option<String> {
+        (self.get)(name)
+            .map(|v| v.trim().to_string())
+            .filter(|v| !v.is_empty())
+    }
+
+    fn fail(&mut self, name: &str, raw: &str, reason: &str) {
+        self.errors.push(format!("{name}={raw:?}: {reason}"));
+    }
+
+    /// Parse `name`, using `default` if it is unset or invalid
+    fn parse<T>(
+        &mut self,
+        name: &str,
+        default: T,
+        parse: impl FnOnce(&str) -> Result<T, String>,
+    ) -> T {
+        let Some(raw) = self.raw(name) else {
+            return default;
+        };
+        match parse(&raw) {
+            Ok(value) => value,
+            Err(reason) => {
+                self.fail(name, &raw, &reason);
+                default
+            }
+        }
+    }
+
+    fn flag(&mut self, name: &str, default: bool) -> bool {
+        self.parse(name, default, |raw| {
+            match raw.to_ascii_lowercase().as_str() {
+                "1" | "true" | "yes" | "on" => Ok(true),
+                "0" | "false" | "no" | "off" => Ok(false),
+                _ => Err("expected true or false".to_string()),
+            }
+        })
+    }
+
+    fn number<T: FromStr>(&mut self, name: &str, default: T) -> T {
+        self.parse(name, default, |raw| {
+            raw.parse()
+                .map_err(|_| "expected a non-negative whole number".to_string())
+        })
+    }
+
+    /// A number where 0 turns the feature off (`None`)
+    fn number_or_off<T: FromStr + Default + PartialEq>(
+        &mut self,
+        name: &str,
+        default: T,
+    ) -> Option<T> {
+        Some(self.number(name, default)).filter(|v| *v != T::default())
+    }
+
+    fn hosts(&mut self, name: &str) -> HostList {
+        self.raw(name)
+            .map(|raw| HostList::parse(&raw))
+            .unwrap_or_default()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn load(vars: &[(&str, &str)]) -> Result<Config> {
+        let vars: HashMap<String, String> = vars
+            .iter()
+            .map(|(k, v)| ((*k).to_string(), (*v).to_string()))
+            .collect();
+        Config::load(|name| vars.get(name).cloned())
+    }
+
+    #[test]
+    fn test_defaults() {
+        let config = load(&[]).unwrap();
+        assert_eq!(config.root, PathBuf::from(DEFAULT_ROOT));
+        assert_eq!(
+            config.events_db,
+            PathBuf::from(DEFAULT_ROOT).join("data/events.db")
+        );
+        assert_eq!(config.events_max_bytes, None);
+        assert!(config.mitm_enabled);
+        assert!(config.proxy.deny_unknown_vm);
+        assert!(config.listen_tcp);
+        assert_eq!(config.listen_uds, None);
+        assert!(!config.audit_grpc);
+        assert_eq!(config.rootfs_mode, RootfsMode::Shared);
+        assert_eq!(config.vm_pool_size, 0);
+        assert!(config.host_memory_check);
+        assert_eq!(config.host_memory_margin_mib, DEFAULT_MEMORY_MARGIN_MIB);
+        assert_eq!(config.grpc_max_message_size, DEFAULT_MAX_MESSAGE_SIZE);
+        assert_eq!(config.proxy.idle_timeout, Some(DEFAULT_IDLE_TIMEOUT));
+        assert_eq!(config.proxy.max_redirects, 0);
+        assert_eq!(config.proxy.max_inline_body, DEFAULT_MAX_INLINE_BODY);
+        assert_eq!(
+            config.proxy.upstream_timeout,
+            Some(DEFAULT_UPSTREAM_TIMEOUT)
+        );
+        assert_eq!(config.proxy.upstream_retries, DEFAULT_UPSTREAM_RETRIES);
+        assert_eq!(config.proxy.redact_headers, HeaderRedaction::default());
+        assert_eq!(
+            config.dns_upstreams,
+            vec![DEFAULT_UPSTREAM_DNS.parse::<SocketAddr>().unwrap()]
+        );
+        assert_eq!(config.dns_mode, DnsMode::Udp);
+        assert_eq!(config.dns_doh_url, DEFAULT_DOH_URL);
+        assert_eq!(
+            config.summary()["CLAWPOT_SUBNET"],
+            ip_allocator::DEFAULT_SUBNET
+        );
+    }
+
+    #[test]
+    fn test_values_are_parsed() {
+        let config = load(&[
+            ("CLAWPOT_ROOT", "/srv/clawpot"),
+            ("CLAWPOT_DISABLE_MITM", "yes"),
+            ("CLAWPOT_DENY_UNKNOWN_VM", "0"),
+            ("CLAWPOT_PROXY_IDLE_TIMEOUT_SECS", "0"),
+            ("CLAWPOT_PROXY_MAX_REDIRECTS", " 5 "),
+            ("CLAWPOT_ROOTFS_MODE", "cow"),
+            ("CLAWPOT_SPLICE_HOSTS", "a.example, B.example"),
+        ])
+        .unwrap();
+        assert_eq!(
+            config.events_db,
+            PathBuf::from("/srv/clawpot/data/events.db")
+        );
+        assert!(!config.mitm_enabled);
+        assert!(!config.proxy.deny_unknown_vm);
+        assert_eq!(config.proxy.idle_timeout, None);
+        assert_eq!(config.proxy.max_redirects, 5);
+        assert_eq!(config.rootfs_mode, RootfsMode::CopyOnWrite);
+
+        let summary = config.summary();
+        assert_eq!(summary["CLAWPOT_PROXY_IDLE_TIMEOUT_SECS"], "off");
+        assert_eq!(summary["CLAWPOT_SPLICE_HOSTS"], "a.example,b.example");
+    }
+
+    #[test]
+    fn test_all_errors_are_reported() {
+        let err = load(&[
+            ("CLAWPOT_PROXY_MAX_REDIRECTS", "three"),
+            ("CLAWPOT_KEEP_ON_FAILURE", "maybe"),
+            ("CLAWPOT_SUBNET", "10.0.0.0"),
+            ("CLAWPOT_GRPC_COMPRESSION", "zstd"),
+            ("CLAWPOT_LISTEN_TCP", "no"),
+            ("CLAWPOT_DNS_ALLOWLIST_RCODE", "servfail"),
+            ("CLAWPOT_DNS_DOH_URL", "dns.google"),
+            ("CLAWPOT_PROXY_UPSTREAM_RETRIES", "1000"),
+            ("CLAWPOT_HTTP_ALLOWED_METHODS", " , "),
+        ])
+        .err()
+        .unwrap()
+        .to_string();
+        for name in [
+            "CLAWPOT_PROXY_MAX_REDIRECTS",
+            "CLAWPOT_KEEP_ON_FAILURE",
+            "CLAWPOT_SUBNET",
+            "CLAWPOT_GRPC_COMPRESSION",
+            "CLAWPOT_LISTEN_UDS",
+            "CLAWPOT_DNS_ALLOWLIST_RCODE",
+            "CLAWPOT_DNS_DOH_URL",
+            "CLAWPOT_PROXY_UPSTREAM_RETRIES",
+            "CLAWPOT_HTTP_ALLOWED_METHODS",
+        ] {
+            assert!(err.contains(name), "{name} missing from: {err}");
+        }
+    }
+}
@@ -1,9 +1,10 @@
+pub mod retention;
 mod store;
 mod types;
 
-pub use store::{EventStore, PersistMode};
+pub use store::{parse_header_names, EventStore, PersistMode, INDEXED_HEADERS_ENV};
 #[cfg_attr(not(test), allow(unused_imports))]
-pub use types::{Event, EventFilters, SessionInfo};
+pub use types::{Event, EventFilters, PruneSummary, SessionInfo};
 
 /// Emit a structured event with typed data.
 ///
@@ -0,0 +1,80 @@
+use super::EventStore;
+use crate::proxy::body_store::BodyStore;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::sync::watch;
+use tracing::{info, warn};
+
+/// Environment variable capping the events database, in bytes; the oldest
+/// finished sessions are deleted once it grows past the cap
+pub const MAX_DB_BYTES_ENV: &str = "CLAWPOT_EVENTS_MAX_BYTES";
+
+/// How often the database size is checked against the cap
+pub const PRUNE_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Keep the events database under `max_bytes`, checking every
+/// [`PRUNE_INTERVAL`] until `cancel` fires. Each prune that deletes
+/// anything is summarized in an `events.pruned` event.
+pub async fn run(
+    events: EventStore,
+    body_store: Arc<BodyStore>,
+    max_bytes: u64,
+    mut cancel: watch::Receiver<bool>,
+) {
+    let mut interval = tokio::time::interval(PRUNE_INTERVAL);
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {}
+            _ = cancel.changed() => return,
+        }
+        prune(&events, &body_store, max_bytes).await;
+    }
+}
+
+async fn prune(events: &EventStore, body_store: &BodyStore, max_bytes: u64) {
+    let summary = match events.prune_to_size(max_bytes).await {
+        Ok(summary) => summary,
+        Err(e) => {
+            warn!("Failed to prune events database: {:#}", e);
+            return;
+        }
+    };
+    if summary.sessions.is_empty() {
+        if summary.bytes_after > max_bytes {
+            warn!(
+                "Events database holds {} bytes, over its {} byte cap, but has no finished sessions to prune",
+                summary.bytes_after, max_bytes
+            );
+        }
+        return;
+    }
+
+    let mut bodies_removed = 0;
+    for path in &summary.body_paths {
+        match body_store.remove(path) {
+            Ok(()) => bodies_removed += 1,
+            Err(e) => warn!("Failed to remove pruned body: {:#}", e),
+        }
+    }
+    info!(
+        "Pruned {} sessions ({} events) from the events database, {} -> {} bytes",
+        summary.sessions.len(),
+        summary.events,
+        summary.bytes_before,
+        summary.bytes_after
+    );
+    events.emit(
+        "events.pruned",
+        "server",
+        None,
+        None,
+        &serde_json::json!({
+            "sessions": summary.sessions,
+            "events": summary.events,
+            "bodies_removed": bodies_removed,
+            "bytes_before": summary.bytes_before,
+            "bytes_after": summary.bytes_after,
+            "max_bytes": max_bytes,
+        }),
+    );
+}
@@ -1,19 +1,21 @@
+use std::collections::HashSet;
 use std::fmt::Write as _;
-use std::path::Path;
-use std::sync::atomic::{AtomicI64, Ordering};
-use std::sync::Arc;
-use std::time::Duration;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicI64, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
 
 use anyhow::{Context, Result};
-use rusqlite::Connection;
+use rusqlite::{Connection, OptionalExtension};
 use serde::Serialize;
 use tokio::sync::mpsc;
-use tracing::info;
+use tracing::{info, warn};
 
-use super::types::{Event, EventFilters, SessionInfo};
+use super::types::{Event, EventFilters, PruneSummary, SessionInfo};
+use crate::proxy::UNKNOWN_VM_ID;
 
 /// What to persist to SQLite.
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum PersistMode {
     /// Every event (default).
     All,
@@ -21,21 +23,121 @@ pub enum PersistMode {
     Structured,
     /// Stdout only, no DB writes.
     None,
+    /// Per-type include/exclude rules, e.g. `all,-log,-network.dns.*`.
+    Rules(Vec<PersistRule>),
+}
+
+/// One entry of a [`PersistMode::Rules`] list.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PersistRule {
+    /// Persist matching events (`+` or no prefix) or drop them (`-`)
+    include: bool,
+    /// `all`, an exact event type, or a `prefix.*` pattern
+    pattern: String,
+}
+
+impl PersistRule {
+    fn matches(&self, event_type: &str) -> bool {
+        match self.pattern.strip_suffix('*') {
+            _ if self.pattern == "all" => true,
+            Some(prefix) => event_type.starts_with(prefix),
+            None => event_type == self.pattern,
+        }
+    }
 }
 
 impl PersistMode {
-    pub fn from_env() -> Self {
-        match std::env::var("CLAWPOT_EVENTS_PERSIST")
-            .unwrap_or_default()
-            .as_str()
-        {
-            "structured" => Self::Structured,
-            "none" => Self::None,
-            _ => Self::All,
+    /// Parse `all`, `structured`, `none`, or a comma-separated rule list
+    /// such as `all,-log,-network.dns.*`.
+    ///
+    /// Rules are `all`, an event type, or a `prefix.*` pattern, optionally
+    /// prefixed with `+` or `-`; the last matching rule decides. Events no
+    /// rule matches are persisted only if the list starts with an exclusion,
+    /// so `-log` on its own means "everything but logs".
+    pub fn parse(raw: &str) -> Option<Self> {
+        match raw {
+            "all" => return Some(Self::All),
+            "structured" => return Some(Self::Structured),
+            "none" => return Some(Self::None),
+            _ => {}
+        }
+        let mut rules = Vec::new();
+        for token in raw.split(',').map(str::trim).filter(|t| !t.is_empty()) {
+            let (include, pattern) = match token.strip_prefix('-') {
+                Some(pattern) => (false, pattern),
+                None => (true, token.strip_prefix('+').unwrap_or(token)),
+            };
+            // `*` is only allowed as the final segment, as in `network.dns.*`
+            let stem = pattern.strip_suffix(".*").unwrap_or(pattern);
+            if stem.is_empty() || stem.contains('*') {
+                return None;
+            }
+            rules.push(PersistRule {
+                include,
+                pattern: pattern.to_string(),
+            });
+        }
+        if rules.is_empty() {
+            return None;
+        }
+        Some(Self::Rules(rules))
+    }
+
+    /// Whether events of `event_type` are written to the database.
+    pub fn persists(&self, event_type: &str) -> bool {
+        match self {
+            Self::All => true,
+            Self::Structured => event_type != "log",
+            Self::None => false,
+            Self::Rules(rules) => rules
+                .iter()
+                .rev()
+                .find(|rule| rule.matches(event_type))
+                .map_or(!rules[0].include, |rule| rule.include),
         }
     }
 }
 
+impl std::fmt::Display for PersistMode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::All => f.write_str("all"),
+            Self::Structured => f.write_str("structured"),
+            Self::None => f.write_str("none"),
+            Self::Rules(rules) => {
+                for (i, rule) in rules.iter().enumerate() {
+                    if i > 0 {
+                        f.write_str(",")?;
+                    }
+                    if !rule.include {
+                        f.write_str("-")?;
+                    }
+                    f.write_str(&rule.pattern)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Environment variable listing headers to copy into the indexed `event_headers` table
+pub const INDEXED_HEADERS_ENV: &str = "CLAWPOT_INDEXED_HEADERS";
+
+/// Header names to index from a comma-separated list such as
+/// `user-agent,content-type`. An empty list indexes nothing.
+pub fn parse_header_names(raw: &str) -> Vec<String> {
+    raw.split(',')
+        .map(|h| h.trim().to_ascii_lowercase())
+        .filter(|h| !h.is_empty())
+        .collect()
+}
+
+/// How long `close_session` waits for the writer to flush
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Flushes slower than this are reported, as a sign of a slow or locked database
+const SLOW_FLUSH: Duration = Duration::from_secs(1);
+
 /// Internal record sent through the channel to the background writer.
 struct EventRecord {
     timestamp: String,
@@ -46,13 +148,33 @@ struct EventRecord {
     duration_ms: Option<i64>,
     success: Option<bool>,
     data: String, // JSON string
+    /// Indexed headers as (direction, name, value), written to `event_headers`
+    headers: Vec<(&'static str, String, String)>,
 }
 
 enum WriterMsg {
     Event(EventRecord),
+    /// The last persisted log event was repeated: replace its row's data,
+    /// which now carries a `repeat_count`.
+    Repeat {
+        data: String,
+    },
     Close {
         resp: tokio::sync::oneshot::Sender<()>,
     },
+    /// Write out everything queued before this message, then reply.
+    Flush {
+        resp: tokio::sync::oneshot::Sender<()>,
+    },
+}
+
+/// The last log event sent to the writer, for coalescing repeats.
+struct LastLog {
+    category: String,
+    vm_id: Option<String>,
+    data: String,
+    first_seen: Instant,
+    count: u64,
 }
 
 /// Unified event logging backed by SQLite + tracing stdout.
@@ -62,10 +184,19 @@ enum WriterMsg {
 #[derive(Clone)]
 pub struct EventStore {
     tx: mpsc::UnboundedSender<WriterMsg>,
-    #[cfg_attr(not(test), allow(dead_code))]
+    /// Database file, opened separately for pruning
+    path: Arc<PathBuf>,
     session_id: Arc<String>,
-    persist_mode: PersistMode,
+    persist_mode: Arc<PersistMode>,
     next_id: Arc<AtomicI64>,
+    /// Events queued for the writer but not yet flushed
+    pending: Arc<AtomicUsize>,
+    indexed_headers: Arc<Vec<String>>,
+    /// Window for collapsing identical consecutive log events (`None` keeps every one)
+    log_coalesce_window: Option<Duration>,
+    last_log: Arc<Mutex<Option<LastLog>>>,
+    /// Stamped on events emitted without their own correlation id
+    correlation_id: Option<Arc<str>>,
 }
 
 impl EventStore {
@@ -74,6 +205,7 @@ impl EventStore {
     pub fn new(
         path: &Path,
         session_id: &str,
+        server_id: &str,
         server_version: &str,
         config: &str,
         persist_mode: PersistMode,
@@ -87,19 +219,25 @@ impl EventStore {
         let conn = Connection::open(path)
             .with_context(|| format!("Failed to open events DB at {}", path.display()))?;
 
-        conn.execute_batch("PRAGMA journal_mode=WAL; PRAGMA synchronous=NORMAL;")
-            .context("Failed to set SQLite pragmas")?;
+        // Pruning writes through its own connection, so waits for the lock
+        conn.execute_batch(
+            "PRAGMA journal_mode=WAL; PRAGMA synchronous=NORMAL; PRAGMA busy_timeout=5000;",
+        )
+        .context("Failed to set SQLite pragmas")?;
 
         Self::create_tables(&conn)?;
+        Self::migrate(&conn)?;
 
         // Insert session row
         conn.execute(
-            "INSERT INTO sessions (id, started_at, server_version, config) VALUES (?1, ?2, ?3, ?4)",
+            "INSERT INTO sessions (id, started_at, server_version, config, server_id)
+             VALUES (?1, ?2, ?3, ?4, ?5)",
             rusqlite::params![
                 session_id,
                 chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
                 server_version,
                 config,
+                server_id,
             ],
         )
         .context("Failed to insert session")?;
@@ -108,7 +246,8 @@ impl EventStore {
         let sid = session_id.to_string();
 
         // Spawn background writer
-        tokio::spawn(background_writer(conn, sid.clone(), rx));
+        let pending = Arc::new(AtomicUsize::new(0));
+        tokio::spawn(background_writer(conn, sid.clone(), rx, pending.clone()));
 
         info!(
             "Event store opened at {} (session {})",
@@ -118,12 +257,51 @@ impl EventStore {
 
         Ok(Self {
             tx,
+            path: Arc::new(path.to_path_buf()),
             session_id: Arc::new(sid),
-            persist_mode,
+            persist_mode: Arc::new(persist_mode),
             next_id: Arc::new(AtomicI64::new(1)),
+            pending,
+            indexed_headers: Arc::new(Vec::new()),
+            log_coalesce_window: None,
+            last_log: Arc::new(Mutex::new(None)),
+            correlation_id: None,
         })
     }
 
+    /// Also record the named headers of HTTP request/response events in the
+    /// `event_headers` table, so they can be filtered without parsing every
+    /// event's JSON. The full header map stays in the event data.
+    #[must_use]
+    pub fn with_indexed_headers(mut self, names: Vec<String>) -> Self {
+        self.indexed_headers = Arc::new(names);
+        self
+    }
+
+    /// Collapse identical consecutive `log` events (same category, VM and
+    /// message) emitted within `window` of the first one into a single row,
+    /// whose data gains a `repeat_count`. The row keeps the first timestamp;
+    /// other event types in between don't end a run.
+    #[must_use]
+    pub fn with_log_coalescing(mut self, window: Option<Duration>) -> Self {
+        self.log_coalesce_window = window;
+        self
+    }
+
+    /// Emitter for one logical operation, e.g. a single `create_vm`: events
+    /// emitted through it without a correlation id of their own get `id`.
+    ///
+    /// Re-entrant: an emitter that is already scoped keeps its id, so nested
+    /// steps stay part of the enclosing operation.
+    #[must_use]
+    pub fn with_correlation(&self, id: impl Into<String>) -> Self {
+        let mut scoped = self.clone();
+        if scoped.correlation_id.is_none() {
+            scoped.correlation_id = Some(Arc::from(id.into()));
+        }
+        scoped
+    }
+
     fn create_tables(conn: &Connection) -> Result<()> {
         conn.execute_batch(
             "CREATE TABLE IF NOT EXISTS sessions (
@@ -131,7 +309,8 @@ impl EventStore {
                 started_at     TEXT NOT NULL,
                 stopped_at     TEXT,
                 server_version TEXT NOT NULL,
-                config         TEXT
+                config         TEXT,
+                server_id      TEXT
             );
 
             CREATE TABLE IF NOT EXISTS events (
@@ -151,12 +330,35 @@ impl EventStore {
             CREATE INDEX IF NOT EXISTS idx_events_ts ON events(timestamp);
             CREATE INDEX IF NOT EXISTS idx_events_vm ON events(vm_id);
             CREATE INDEX IF NOT EXISTS idx_events_type ON events(event_type);
-            CREATE INDEX IF NOT EXISTS idx_events_corr ON events(correlation_id);",
+            CREATE INDEX IF NOT EXISTS idx_events_corr ON events(correlation_id);
+
+            CREATE TABLE IF NOT EXISTS event_headers (
+                event_id   INTEGER NOT NULL REFERENCES events(id),
+                direction  TEXT NOT NULL,
+                name       TEXT NOT NULL,
+                value      TEXT NOT NULL
+            );
+
+            CREATE INDEX IF NOT EXISTS idx_event_headers_name_value ON event_headers(name, value);
+            CREATE INDEX IF NOT EXISTS idx_event_headers_event ON event_headers(event_id);",
         )
         .context("Failed to create events tables")?;
         Ok(())
     }
 
+    /// Bring databases created by older server versions up to the current schema.
+    fn migrate(conn: &Connection) -> Result<()> {
+        if !has_column(conn, "sessions", "server_id")? {
+            conn.execute_batch("ALTER TABLE sessions ADD COLUMN server_id TEXT;")
+                .context("Failed to add sessions.server_id column")?;
+        }
+        conn.execute_batch(
+            "CREATE INDEX IF NOT EXISTS idx_sessions_server ON sessions(server_id);",
+        )
+        .context("Failed to create sessions index")?;
+        Ok(())
+    }
+
     /// Core event emission. Writes to SQLite (async) and emits `tracing::info!()`.
     /// Infallible: never panics or returns errors.
     pub fn emit<D: Serialize>(
@@ -224,8 +426,18 @@ impl EventStore {
         data: &D,
     ) -> i64 {
         let local_id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let correlation_id = correlation_id.or(self.correlation_id.as_deref());
 
-        let data_json = serde_json::to_string(data).unwrap_or_else(|_| "{}".to_string());
+        let mut data_json = serde_json::to_string(data).unwrap_or_else(|_| "{}".to_string());
+
+        // Traffic from unregistered sources has no VM: store a NULL vm_id so
+        // it can't pass for a real one, and mark the event instead
+        let vm_id = if vm_id == Some(UNKNOWN_VM_ID) {
+            data_json = mark_unattributed(&data_json);
+            None
+        } else {
+            vm_id
+        };
 
         // Emit to tracing (stdout/OTLP)
         if let Some(vid) = vm_id {
@@ -234,14 +446,28 @@ impl EventStore {
             info!("[{}] {}", event_type, &data_json);
         }
 
-        // Check persist mode
-        let should_persist = match self.persist_mode {
-            PersistMode::All => true,
-            PersistMode::Structured => event_type != "log",
-            PersistMode::None => false,
-        };
+        if self.persist_mode.persists(event_type) {
+            // Held until the event is queued, so the writer sees repeats in
+            // the same order as the decisions made here
+            let mut last_log = match self.log_coalesce_window {
+                Some(window) if event_type == "log" => {
+                    let mut last_log = self.last_log.lock().unwrap_or_else(|e| e.into_inner());
+                    if let Some(data) =
+                        Self::coalesce(&mut last_log, window, category, vm_id, &data_json)
+                    {
+                        if self.tx.send(WriterMsg::Repeat { data }).is_err() {
+                            eprintln!(
+                                "EventStore: failed to send event to writer (channel closed)"
+                            );
+                        }
+                        return local_id;
+                    }
+                    Some(last_log)
+                }
+                _ => None,
+            };
 
-        if should_persist {
+            let headers = self.extract_indexed_headers(event_type, &data_json);
             let record = EventRecord {
                 timestamp: chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true),
                 category: category.to_string(),
@@ -251,9 +477,23 @@ impl EventStore {
                 duration_ms,
                 success,
                 data: data_json,
+                headers,
             };
 
+            if let Some(last_log) = last_log.as_mut() {
+                **last_log = Some(LastLog {
+                    category: record.category.clone(),
+                    vm_id: record.vm_id.clone(),
+                    data: record.data.clone(),
+                    first_seen: Instant::now(),
+                    count: 1,
+                });
+            }
+
+            // Counted before sending, so the writer never decrements past zero
+            self.pending.fetch_add(1, Ordering::Relaxed);
             if self.tx.send(WriterMsg::Event(record)).is_err() {
+                self.pending.fetch_sub(1, Ordering::Relaxed);
                 eprintln!("EventStore: failed to send event to writer (channel closed)");
             }
         }
@@ -261,12 +501,119 @@ impl EventStore {
         local_id
     }
 
+    /// If this log event repeats `last_log` within `window`, count it and
+    /// return the row's new data; otherwise it starts a new row.
+    fn coalesce(
+        last_log: &mut Option<LastLog>,
+        window: Duration,
+        category: &str,
+        vm_id: Option<&str>,
+        data_json: &str,
+    ) -> Option<String> {
+        let last = last_log.as_mut()?;
+        if last.category != category
+            || last.vm_id.as_deref() != vm_id
+            || last.data != data_json
+            || last.first_seen.elapsed() > window
+        {
+            return None;
+        }
+        last.count += 1;
+        let mut data: serde_json::Value = serde_json::from_str(data_json).ok()?;
+        data.as_object_mut()?
+            .insert("repeat_count".to_string(), last.count.into());
+        Some(data.to_string())
+    }
+
+    /// Pull the configured headers out of an HTTP event's header map.
+    ///
+    /// Request events carry the map as a JSON string in `headers` and
+    /// response events in `resp_headers`.
+    fn extract_indexed_headers(
+        &self,
+        event_type: &str,
+        data_json: &str,
+    ) -> Vec<(&'static str, String, String)> {
+        let (direction, field) = match event_type {
+            "network.http.request" => ("request", "headers"),
+            "network.http.response" => ("response", "resp_headers"),
+            _ => return Vec::new(),
+        };
+        if self.indexed_headers.is_empty() {
+            return Vec::new();
+        }
+
+        let Some(map) = serde_json::from_str::<serde_json::Value>(data_json)
+            .ok()
+            .and_then(|data| data.get(field)?.as_str().map(String::from))
+            .and_then(|raw| {
+                serde_json::from_str::<std::collections::HashMap<String, String>>(&raw).ok()
+            })
+        else {
+            return Vec::new();
+        };
+
+        map.into_iter()
+            .map(|(name, value)| (name.to_ascii_lowercase(), value))
+            .filter(|(name, _)| self.indexed_headers.contains(name))
+            .map(|(name, value)| (direction, name, value))
+            .collect()
+    }
+
     /// Close the session (set `stopped_at`), flush pending writes.
+    ///
+    /// Waits up to five seconds for the writer, then logs whether the flush
+    /// completed and how many events may have been lost.
     pub async fn close_session(&self) {
+        let pending = self.pending.load(Ordering::Relaxed);
         let (resp_tx, resp_rx) = tokio::sync::oneshot::channel();
         let _ = self.tx.send(WriterMsg::Close { resp: resp_tx });
         // Wait for flush with a timeout
-        let _ = tokio::time::timeout(Duration::from_secs(5), resp_rx).await;
+        match tokio::time::timeout(CLOSE_TIMEOUT, resp_rx).await {
+            Ok(Ok(())) => info!("Event store closed, flushed {pending} pending events"),
+            Ok(Err(_)) => warn!(
+                "Event writer exited before closing the session; {} of {pending} pending events may be lost",
+                self.pending.load(Ordering::Relaxed)
+            ),
+            Err(_) => warn!(
+                "Timed out after {}s flushing events; {} of {pending} pending events may be lost",
+                CLOSE_TIMEOUT.as_secs(),
+                self.pending.load(Ordering::Relaxed)
+            ),
+        }
+    }
+
+    /// Delete the oldest finished sessions, with their events, until the
+    /// database holds at most `max_bytes`. This session is never deleted,
+    /// and neither are sessions another server may still be writing to.
+    ///
+    /// Size is measured in pages holding data: SQLite keeps freed pages in
+    /// the file and reuses them for new events, so the file stops growing at
+    /// about `max_bytes` rather than shrinking.
+    ///
+    /// Runs on its own connection in a blocking task, so events keep being
+    /// written meanwhile. Events queued before the call are written first,
+    /// so the body files they reference count as in use.
+    pub async fn prune_to_size(&self, max_bytes: u64) -> Result<PruneSummary> {
+        let (resp_tx, resp_rx) = tokio::sync::oneshot::channel();
+        self.tx
+            .send(WriterMsg::Flush { resp: resp_tx })
+            .map_err(|_| anyhow::anyhow!("Event writer has exited"))?;
+        resp_rx
+            .await
+            .map_err(|_| anyhow::anyhow!("Event writer exited before pruning"))?;
+
+        let path = self.path.clone();
+        let session_id = self.session_id.clone();
+        tokio::task::spawn_blocking(move || {
+            let conn = Connection::open(&*path)
+                .with_context(|| format!("Failed to open events DB at {}", path.display()))?;
+            conn.execute_batch("PRAGMA busy_timeout=5000;")
+                .context("Failed to set busy timeout")?;
+            prune_to_size(&conn, &session_id, max_bytes)
+        })
+        .await
+        .context("Pruning task failed")?
     }
 
     /// Returns the session ID.
@@ -297,7 +644,8 @@ impl EventStore {
     pub fn list_sessions(conn: &Connection) -> Result<Vec<SessionInfo>> {
         let mut stmt = conn.prepare(
             "SELECT s.id, s.started_at, s.stopped_at, s.server_version,
-                    (SELECT COUNT(*) FROM events e WHERE e.session_id = s.id) as event_count
+                    (SELECT COUNT(*) FROM events e WHERE e.session_id = s.id) as event_count,
+                    s.server_id
              FROM sessions s
              ORDER BY s.started_at DESC",
         )?;
@@ -309,6 +657,7 @@ impl EventStore {
                 stopped_at: row.get(2)?,
                 server_version: row.get(3)?,
                 event_count: row.get(4)?,
+                server_id: row.get(5)?,
             })
         })?;
 
@@ -345,8 +694,22 @@ impl EventStore {
             let _ = write!(sql, " AND event_type = ?{}", params.len() + 1);
             params.push(Box::new(et.clone()));
         }
+        if let Some((ref name, ref value)) = filters.header {
+            let _ = write!(
+                sql,
+                " AND id IN (SELECT event_id FROM event_headers WHERE name = ?{} AND value = ?{})",
+                params.len() + 1,
+                params.len() + 2
+            );
+            params.push(Box::new(name.to_ascii_lowercase()));
+            params.push(Box::new(value.clone()));
+        }
 
-        sql.push_str(" ORDER BY timestamp ASC, id ASC");
+        if filters.newest_first {
+            sql.push_str(" ORDER BY timestamp DESC, id DESC");
+        } else {
+            sql.push_str(" ORDER BY timestamp ASC, id ASC");
+        }
 
         if let Some(limit) = filters.limit {
             let _ = write!(sql, " LIMIT {limit}");
@@ -381,13 +744,39 @@ impl EventStore {
     }
 }
 
+/// Whether `table` has a column named `column`.
+fn has_column(conn: &Connection, table: &str, column: &str) -> Result<bool> {
+    let mut stmt = conn.prepare(&format!("PRAGMA table_info({table})"))?;
+    let names = stmt.query_map([], |row| row.get::<_, String>(1))?;
+    for name in names {
+        if name? == column {
+            return Ok(true);
+        }
+    }
+    Ok(false)
+}
+
+/// Add `"unattributed": true` to an event's JSON object data.
+fn mark_unattributed(data_json: &str) -> String {
+    match serde_json::from_str::<serde_json::Value>(data_json) {
+        Ok(serde_json::Value::Object(mut data)) => {
+            data.insert("unattributed".to_string(), true.into());
+            serde_json::Value::Object(data).to_string()
+        }
+        _ => data_json.to_string(),
+    }
+}
+
 /// Background task that batches event writes into SQLite transactions.
 async fn background_writer(
     conn: Connection,
     session_id: String,
     mut rx: mpsc::UnboundedReceiver<WriterMsg>,
+    pending: Arc<AtomicUsize>,
 ) {
     let mut batch: Vec<EventRecord> = Vec::with_capacity(64);
+    // Row of the last log event written, which coalesced repeats update
+    let mut last_log_id: Option<i64> = None;
 
     loop {
         // Wait for at least one message
@@ -395,15 +784,27 @@ async fn background_writer(
             Some(WriterMsg::Event(record)) => {
                 batch.push(record);
             }
+            Some(WriterMsg::Repeat { data }) => {
+                apply_repeat(&conn, &mut batch, last_log_id, data);
+            }
+            Some(WriterMsg::Flush { resp }) => {
+                flush_batch(&conn, &session_id, &mut batch, &mut last_log_id, &pending);
+                let _ = resp.send(());
+            }
             Some(WriterMsg::Close { resp }) => {
                 // Drain any remaining events in the channel before flushing
                 while let Ok(msg) = rx.try_recv() {
-                    if let WriterMsg::Event(record) = msg {
-                        batch.push(record);
+                    match msg {
+                        WriterMsg::Event(record) => batch.push(record),
+                        WriterMsg::Repeat { data } => {
+                            apply_repeat(&conn, &mut batch, last_log_id, data);
+                        }
+                        // Shutting down; pruning can wait for the next run
+                        WriterMsg::Close { .. } | WriterMsg::Flush { .. } => {}
                     }
                 }
                 // Flush all events, close session, checkpoint WAL, then respond
-                flush_batch(&conn, &session_id, &mut batch);
+                flush_batch(&conn, &session_id, &mut batch, &mut last_log_id, &pending);
                 close_session_row(&conn, &session_id);
                 checkpoint_wal(&conn);
                 let _ = resp.send(());
@@ -411,7 +812,7 @@ async fn background_writer(
             }
             None => {
                 // Channel closed without explicit close — flush and exit
-                flush_batch(&conn, &session_id, &mut batch);
+                flush_batch(&conn, &session_id, &mut batch, &mut last_log_id, &pending);
                 close_session_row(&conn, &session_id);
                 checkpoint_wal(&conn);
                 return;
@@ -422,8 +823,15 @@ async fn background_writer(
         loop {
             match rx.try_recv() {
                 Ok(WriterMsg::Event(record)) => batch.push(record),
+                Ok(WriterMsg::Repeat { data }) => {
+                    apply_repeat(&conn, &mut batch, last_log_id, data);
+                }
+                Ok(WriterMsg::Flush { resp }) => {
+                    flush_batch(&conn, &session_id, &mut batch, &mut last_log_id, &pending);
+                    let _ = resp.send(());
+                }
                 Ok(WriterMsg::Close { resp }) => {
-                    flush_batch(&conn, &session_id, &mut batch);
+                    flush_batch(&conn, &session_id, &mut batch, &mut last_log_id, &pending);
                     close_session_row(&conn, &session_id);
                     checkpoint_wal(&conn);
                     let _ = resp.send(());
@@ -435,26 +843,70 @@ async fn background_writer(
 
         // Flush the batch if we have events
         if !batch.is_empty() {
-            flush_batch(&conn, &session_id, &mut batch);
+            flush_batch(&conn, &session_id, &mut batch, &mut last_log_id, &pending);
         }
     }
 }
 
-fn flush_batch(conn: &Connection, session_id: &str, batch: &mut Vec<EventRecord>) {
+/// Replace the data of the last log event: still in `batch` if it hasn't
+/// been flushed yet, otherwise already written as row `last_log_id`.
+fn apply_repeat(
+    conn: &Connection,
+    batch: &mut [EventRecord],
+    last_log_id: Option<i64>,
+    data: String,
+) {
+    if let Some(record) = batch.iter_mut().rev().find(|r| r.event_type == "log") {
+        record.data = data;
+        return;
+    }
+    let Some(id) = last_log_id else {
+        return;
+    };
+    if let Err(e) = conn.execute(
+        "UPDATE events SET data = ?1 WHERE id = ?2",
+        rusqlite::params![data, id],
+    ) {
+        eprintln!("EventStore: failed to update repeated log event: {e}");
+    }
+}
+
+fn flush_batch(
+    conn: &Connection,
+    session_id: &str,
+    batch: &mut Vec<EventRecord>,
+    last_log_id: &mut Option<i64>,
+    pending: &AtomicUsize,
+) {
     if batch.is_empty() {
         return;
     }
 
-    if let Err(e) = flush_batch_inner(conn, session_id, batch) {
+    let start = Instant::now();
+    if let Err(e) = flush_batch_inner(conn, session_id, batch, last_log_id) {
         eprintln!(
             "EventStore: failed to flush batch ({} events): {e}",
             batch.len()
         );
     }
+    let elapsed = start.elapsed();
+    if elapsed > SLOW_FLUSH {
+        eprintln!(
+            "EventStore: flushing {} events took {}ms; the database may be slow or locked",
+            batch.len(),
+            elapsed.as_millis()
+        );
+    }
+    pending.fetch_sub(batch.len(), Ordering::Relaxed);
     batch.clear();
 }
 
-fn flush_batch_inner(conn: &Connection, session_id: &str, batch: &[EventRecord]) -> Result<()> {
+fn flush_batch_inner(
+    conn: &Connection,
+    session_id: &str,
+    batch: &[EventRecord],
+    last_log_id: &mut Option<i64>,
+) -> Result<()> {
     let tx = conn.unchecked_transaction()?;
     {
         let mut stmt = tx.prepare_cached(
@@ -462,6 +914,10 @@ fn flush_batch_inner(conn: &Connection, session_id: &str, batch: &[EventRecord])
                                  correlation_id, duration_ms, success, data)
              VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
         )?;
+        let mut header_stmt = tx.prepare_cached(
+            "INSERT INTO event_headers (event_id, direction, name, value)
+             VALUES (?1, ?2, ?3, ?4)",
+        )?;
 
         for record in batch {
             let success_int = record.success.map(i32::from);
@@ -476,6 +932,16 @@ fn flush_batch_inner(conn: &Connection, session_id: &str, batch: &[EventRecord])
                 success_int,
                 record.data,
             ])?;
+
+            if record.event_type == "log" {
+                *last_log_id = Some(tx.last_insert_rowid());
+            }
+            if !record.headers.is_empty() {
+                let event_id = tx.last_insert_rowid();
+                for (direction, name, value) in &record.headers {
+                    header_stmt.execute(rusqlite::params![event_id, direction, name, value])?;
+                }
+            }
         }
     }
     tx.commit()?;
@@ -490,6 +956,93 @@ fn checkpoint_wal(conn: &Connection) {
     }
 }
 
+/// Bytes of the database file holding data, leaving out free pages.
+fn used_bytes(conn: &Connection) -> Result<u64> {
+    let pragma = |name: &str| -> Result<u64> {
+        conn.query_row(&format!("PRAGMA {name}"), [], |row| row.get::<_, i64>(0))
+            .map(|v| v as u64)
+            .with_context(|| format!("Failed to read {name}"))
+    };
+    Ok((pragma("page_count")? - pragma("freelist_count")?) * pragma("page_size")?)
+}
+
+/// Delete the oldest sessions other than `session_id` that have been closed
+/// until the database holds at most `max_bytes`.
+fn prune_to_size(conn: &Connection, session_id: &str, max_bytes: u64) -> Result<PruneSummary> {
+    let bytes_before = used_bytes(conn)?;
+    let mut summary = PruneSummary {
+        bytes_before,
+        bytes_after: bytes_before,
+        ..PruneSummary::default()
+    };
+    let mut body_paths = Vec::new();
+    while summary.bytes_after > max_bytes {
+        // Sessions without `stopped_at` may belong to a server still running
+        let oldest: Option<String> = conn
+            .query_row(
+                "SELECT id FROM sessions
+                 WHERE stopped_at IS NOT NULL AND id != ?1
+                 ORDER BY started_at ASC, rowid ASC LIMIT 1",
+                [session_id],
+                |row| row.get(0),
+            )
+            .optional()?;
+        let Some(oldest) = oldest else {
+            break;
+        };
+
+        let tx = conn.unchecked_transaction()?;
+        {
+            let mut stmt = tx.prepare(
+                "SELECT path FROM (
+                     SELECT json_extract(data, '$.req_body_path') AS path
+                     FROM events WHERE session_id = ?1
+                     UNION
+                     SELECT json_extract(data, '$.resp_body_path')
+                     FROM events WHERE session_id = ?1
+                 ) WHERE path IS NOT NULL",
+            )?;
+            let paths = stmt.query_map([&oldest], |row| row.get::<_, String>(0))?;
+            for path in paths {
+                body_paths.push(path?);
+            }
+        }
+        tx.execute(
+            "DELETE FROM event_headers
+             WHERE event_id IN (SELECT id FROM events WHERE session_id = ?1)",
+            [&oldest],
+        )?;
+        summary.events += tx.execute("DELETE FROM events WHERE session_id = ?1", [&oldest])? as u64;
+        tx.execute("DELETE FROM sessions WHERE id = ?1", [&oldest])?;
+        tx.commit()
+            .with_context(|| format!("Failed to delete session {oldest}"))?;
+
+        summary.sessions.push(oldest);
+        summary.bytes_after = used_bytes(conn)?;
+    }
+
+    // Body files can be shared by events of sessions that are kept; one
+    // pass over the remaining events finds those still referenced
+    let mut unreferenced: HashSet<String> = body_paths.into_iter().collect();
+    if !unreferenced.is_empty() {
+        let mut stmt = conn.prepare(
+            "SELECT path FROM (
+                 SELECT json_extract(data, '$.req_body_path') AS path FROM events
+                 UNION
+                 SELECT json_extract(data, '$.resp_body_path') FROM events
+             ) WHERE path IS NOT NULL",
+        )?;
+        let referenced = stmt.query_map([], |row| row.get::<_, String>(0))?;
+        for path in referenced {
+            unreferenced.remove(&path?);
+        }
+    }
+    let mut unreferenced: Vec<PathBuf> = unreferenced.into_iter().map(PathBuf::from).collect();
+    unreferenced.sort();
+    summary.body_paths = unreferenced;
+    Ok(summary)
+}
+
 fn close_session_row(conn: &Connection, session_id: &str) {
     let now = chrono::Utc::now().to_rfc3339_opts(chrono::SecondsFormat::Millis, true);
     if let Err(e) = conn.execute(
@@ -504,7 +1057,6 @@ fn close_session_row(conn: &Connection, session_id: &str) {
 mod tests {
     use super::*;
     use serde_json::json;
-    use std::path::PathBuf;
     use tempfile::NamedTempFile;
 
     fn temp_db_path() -> PathBuf {
@@ -515,8 +1067,15 @@ mod tests {
     #[tokio::test]
     async fn test_event_store_basic() {
         let path = temp_db_path();
-        let store =
-            EventStore::new(&path, "test-session-1", "0.1.0", "{}", PersistMode::All).unwrap();
+        let store = EventStore::new(
+            &path,
+            "test-session-1",
+            "test-server",
+            "0.1.0",
+            "{}",
+            PersistMode::All,
+        )
+        .unwrap();
 
         // Emit some events
         store.emit(
@@ -544,6 +1103,7 @@ mod tests {
         assert_eq!(sessions.len(), 1);
         assert_eq!(sessions[0].id, "test-session-1");
         assert_eq!(sessions[0].event_count, 3);
+        assert_eq!(sessions[0].server_id.as_deref(), Some("test-server"));
         assert!(sessions[0].stopped_at.is_some());
 
         let events = EventStore::query_events(&conn, &EventFilters::default()).unwrap();
@@ -558,8 +1118,15 @@ mod tests {
     #[tokio::test]
     async fn test_event_store_filters() {
         let path = temp_db_path();
-        let store =
-            EventStore::new(&path, "test-session-2", "0.1.0", "{}", PersistMode::All).unwrap();
+        let store = EventStore::new(
+            &path,
+            "test-session-2",
+            "test-server",
+            "0.1.0",
+            "{}",
+            PersistMode::All,
+        )
+        .unwrap();
 
         store.emit("vm.create.started", "vm", Some("vm-1"), None, &json!({}));
         store.emit(
@@ -608,6 +1175,84 @@ mod tests {
         )
         .unwrap();
         assert_eq!(events.len(), 1);
+
+        // The latest events of a VM, newest first
+        let events = EventStore::query_events(
+            &conn,
+            &EventFilters {
+                vm_id: Some("vm-1".to_string()),
+                limit: Some(1),
+                newest_first: true,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event_type, "network.http.request");
+    }
+
+    #[tokio::test]
+    async fn test_indexed_headers() {
+        let path = temp_db_path();
+        let store = EventStore::new(
+            &path,
+            "test-session-headers",
+            "test-server",
+            "0.1.0",
+            "{}",
+            PersistMode::All,
+        )
+        .unwrap()
+        .with_indexed_headers(parse_header_names(" User-Agent, ,content-type"));
+
+        let headers =
+            |ua: &str| serde_json::to_string(&json!({"user-agent": ua, "accept": "*/*"})).unwrap();
+        store.emit(
+            "network.http.request",
+            "network",
+            Some("vm-1"),
+            Some("corr-1"),
+            &json!({"method": "GET", "headers": headers("curl/8.5")}),
+        );
+        store.emit(
+            "network.http.request",
+            "network",
+            Some("vm-1"),
+            Some("corr-2"),
+            &json!({"method": "GET", "headers": headers("python-requests/2.31")}),
+        );
+        store.emit(
+            "network.http.response",
+            "network",
+            Some("vm-1"),
+            Some("corr-2"),
+            &json!({"status_code": 200, "resp_headers": r#"{"content-type":"text/html"}"#}),
+        );
+
+        store.close_session().await;
+
+        let conn = EventStore::open_readonly(&path).unwrap();
+        let filter = |name: &str, value: &str| EventFilters {
+            header: Some((name.to_string(), value.to_string())),
+            ..Default::default()
+        };
+
+        let events = EventStore::query_events(&conn, &filter("User-Agent", "curl/8.5")).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].correlation_id.as_deref(), Some("corr-1"));
+        // The full header map is still kept in the event data
+        assert!(events[0].data["headers"]
+            .as_str()
+            .unwrap()
+            .contains("accept"));
+
+        let events = EventStore::query_events(&conn, &filter("content-type", "text/html")).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event_type, "network.http.response");
+
+        // Headers that weren't configured are not indexed
+        let events = EventStore::query_events(&conn, &filter("accept", "*/*")).unwrap();
+        assert!(events.is_empty());
     }
 
     #[tokio::test]
@@ -616,6 +1261,7 @@ mod tests {
         let store = EventStore::new(
             &path,
             "test-session-3",
+            "test-server",
             "0.1.0",
             "{}",
             PersistMode::Structured,
@@ -633,11 +1279,261 @@ mod tests {
         assert_eq!(events[0].event_type, "vm.create.started");
     }
 
+    #[test]
+    fn test_persist_rules() {
+        let mode = PersistMode::parse("all,-log,-network.dns.*").unwrap();
+        assert!(mode.persists("vm.create"));
+        assert!(mode.persists("network.http.request"));
+        assert!(mode.persists("network.dns"));
+        assert!(!mode.persists("network.dns.query"));
+        assert!(!mode.persists("log"));
+        assert_eq!(mode.to_string(), "all,-log,-network.dns.*");
+
+        // Unmatched events follow the opposite of the first rule
+        let mode = PersistMode::parse("-log").unwrap();
+        assert!(mode.persists("vm.create"));
+        assert!(!mode.persists("log"));
+        let mode = PersistMode::parse("vm.*, +network.http.request").unwrap();
+        assert!(mode.persists("vm.delete"));
+        assert!(mode.persists("network.http.request"));
+        assert!(!mode.persists("network.http.response"));
+
+        assert_eq!(
+            PersistMode::parse("structured"),
+            Some(PersistMode::Structured)
+        );
+        assert_eq!(PersistMode::parse(""), None);
+        assert_eq!(PersistMode::parse("-"), None);
+        assert_eq!(PersistMode::parse("vm*"), None);
+    }
+
+    #[tokio::test]
+    async fn test_persist_mode_rules() {
+        let path = temp_db_path();
+        let store = EventStore::new(
+            &path,
+            "test-session-rules",
+            "test-server",
+            "0.1.0",
+            "{}",
+            PersistMode::parse("all,-log,-network.dns.*").unwrap(),
+        )
+        .unwrap();
+
+        store.emit("vm.create.started", "vm", Some("vm-1"), None, &json!({}));
+        store.emit(
+            "network.dns.query",
+            "network",
+            Some("vm-1"),
+            None,
+            &json!({}),
+        );
+        store.log("server", None, "this should not be persisted");
+
+        store.close_session().await;
+
+        let conn = EventStore::open_readonly(&path).unwrap();
+        let events = EventStore::query_events(&conn, &EventFilters::default()).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].event_type, "vm.create.started");
+    }
+
+    #[tokio::test]
+    async fn test_log_coalescing() {
+        let path = temp_db_path();
+        let store = EventStore::new(
+            &path,
+            "test-session-coalesce",
+            "test-server",
+            "0.1.0",
+            "{}",
+            PersistMode::All,
+        )
+        .unwrap()
+        .with_log_coalescing(Some(Duration::from_secs(60)));
+
+        for _ in 0..5 {
+            store.log("proxy", Some("vm-1"), "retrying upstream");
+        }
+        store.log("proxy", Some("vm-2"), "retrying upstream");
+        store.emit("vm.create.started", "vm", Some("vm-1"), None, &json!({}));
+        // Let the writer flush, so the repeat updates a written row
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        store.log("proxy", Some("vm-2"), "retrying upstream");
+
+        store.close_session().await;
+
+        let conn = EventStore::open_readonly(&path).unwrap();
+        let events = EventStore::query_events(&conn, &EventFilters::default()).unwrap();
+        assert_eq!(events.len(), 3);
+        assert_eq!(events[0].data["repeat_count"], 5);
+        assert_eq!(events[0].data["message"], "retrying upstream");
+        assert_eq!(events[1].vm_id.as_deref(), Some("vm-2"));
+        assert_eq!(events[1].data["repeat_count"], 2);
+        assert_eq!(events[2].event_type, "vm.create.started");
+    }
+
+    #[tokio::test]
+    async fn test_close_session_flushes_pending() {
+        let path = temp_db_path();
+        let store = EventStore::new(
+            &path,
+            "test-session-pending",
+            "test-server",
+            "0.1.0",
+            "{}",
+            PersistMode::All,
+        )
+        .unwrap();
+
+        for i in 0..3 {
+            store.emit("vm.create.started", "vm", None, None, &json!({ "i": i }));
+        }
+        store.close_session().await;
+        assert_eq!(store.pending.load(Ordering::Relaxed), 0);
+    }
+
+    #[tokio::test]
+    async fn test_prune_to_size() {
+        let path = temp_db_path();
+        let open = |session: &str| {
+            EventStore::new(
+                &path,
+                session,
+                "test-server",
+                "0.1.0",
+                "{}",
+                PersistMode::All,
+            )
+            .unwrap()
+        };
+        let padding = "x".repeat(4096);
+
+        // Two finished sessions; the body the second one shares with the
+        // running session must survive it being pruned
+        for (session, body) in [
+            ("first", "/bodies/first.bin"),
+            ("second", "/bodies/shared.bin"),
+        ] {
+            let store = open(session);
+            for _ in 0..50 {
+                store.emit("vm.exec", "vm", None, None, &json!({ "padding": padding }));
+            }
+            store.emit(
+                "network.http.response",
+                "network",
+                None,
+                None,
+                &json!({ "resp_body_path": body }),
+            );
+            store.close_session().await;
+        }
+        let current = open("current");
+        current.emit(
+            "network.http.request",
+            "network",
+            None,
+            None,
+            &json!({ "req_body_path": "/bodies/shared.bin" }),
+        );
+
+        // Under the cap nothing is pruned
+        let summary = current.prune_to_size(u64::MAX).await.unwrap();
+        assert!(summary.sessions.is_empty());
+        assert_eq!(summary.bytes_after, summary.bytes_before);
+
+        // Over it, finished sessions go oldest first, but never the running one
+        let summary = current.prune_to_size(0).await.unwrap();
+        assert_eq!(summary.sessions, ["first", "second"]);
+        assert_eq!(summary.events, 102);
+        assert!(summary.bytes_after < summary.bytes_before);
+        assert_eq!(summary.body_paths, [PathBuf::from("/bodies/first.bin")]);
+        current.close_session().await;
+
+        let conn = EventStore::open_readonly(&path).unwrap();
+        let sessions = EventStore::list_sessions(&conn).unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0].id, "current");
+        assert_eq!(sessions[0].event_count, 1);
+    }
+
+    #[tokio::test]
+    async fn test_with_correlation() {
+        let path = temp_db_path();
+        let store = EventStore::new(
+            &path,
+            "test-session-correlation",
+            "test-server",
+            "0.1.0",
+            "{}",
+            PersistMode::All,
+        )
+        .unwrap();
+
+        let op = store.with_correlation("op-1");
+        op.emit("vm.create.started", "vm", Some("vm-1"), None, &json!({}));
+        // Nested scopes keep the outer id; explicit ids still win
+        op.with_correlation("op-2")
+            .log("vm", Some("vm-1"), "nested step");
+        op.emit(
+            "network.http.request",
+            "network",
+            None,
+            Some("req-1"),
+            &json!({}),
+        );
+        store.emit("vm.list", "vm", None, None, &json!({}));
+
+        store.close_session().await;
+
+        let conn = EventStore::open_readonly(&path).unwrap();
+        let events = EventStore::query_events(&conn, &EventFilters::default()).unwrap();
+        let ids: Vec<_> = events.iter().map(|e| e.correlation_id.as_deref()).collect();
+        assert_eq!(ids, [Some("op-1"), Some("op-1"), Some("req-1"), None]);
+    }
+
+    #[tokio::test]
+    async fn test_unknown_vm_id_is_stored_as_null() {
+        let path = temp_db_path();
+        let store = EventStore::new(
+            &path,
+            "test-session-unknown",
+            "test-server",
+            "0.1.0",
+            "{}",
+            PersistMode::All,
+        )
+        .unwrap();
+
+        store.emit(
+            "network.http.request",
+            "network",
+            Some(UNKNOWN_VM_ID),
+            None,
+            &json!({ "url": "http://example.com/" }),
+        );
+        store.close_session().await;
+
+        let conn = EventStore::open_readonly(&path).unwrap();
+        let events = EventStore::query_events(&conn, &EventFilters::default()).unwrap();
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].vm_id, None);
+        assert_eq!(events[0].data["unattributed"], true);
+        assert_eq!(events[0].data["url"], "http://example.com/");
+    }
+
     #[tokio::test]
     async fn test_emit_with_duration() {
         let path = temp_db_path();
-        let store =
-            EventStore::new(&path, "test-session-4", "0.1.0", "{}", PersistMode::All).unwrap();
+        let store = EventStore::new(
+            &path,
+            "test-session-4",
+            "test-server",
+            "0.1.0",
+            "{}",
+            PersistMode::All,
+        )
+        .unwrap();
 
         store.emit_with_duration(
             "vm.create.completed",
@@ -657,4 +1553,44 @@ mod tests {
         assert_eq!(events[0].duration_ms, Some(1500));
         assert_eq!(events[0].success, Some(true));
     }
+
+    #[tokio::test]
+    async fn test_migrate_adds_server_id_column() {
+        let path = temp_db_path();
+        {
+            // Schema as written by servers that predate server_id
+            let conn = Connection::open(&path).unwrap();
+            conn.execute_batch(
+                "CREATE TABLE sessions (
+                    id             TEXT PRIMARY KEY,
+                    started_at     TEXT NOT NULL,
+                    stopped_at     TEXT,
+                    server_version TEXT NOT NULL,
+                    config         TEXT
+                );
+                INSERT INTO sessions (id, started_at, server_version)
+                VALUES ('old-session', '2020-01-01T00:00:00.000Z', '0.0.1');",
+            )
+            .unwrap();
+        }
+
+        let store = EventStore::new(
+            &path,
+            "new-session",
+            "server-a",
+            "0.1.0",
+            "{}",
+            PersistMode::All,
+        )
+        .unwrap();
+        store.close_session().await;
+
+        let conn = EventStore::open_readonly(&path).unwrap();
+        let sessions = EventStore::list_sessions(&conn).unwrap();
+        assert_eq!(sessions.len(), 2);
+        assert_eq!(sessions[0].id, "new-session");
+        assert_eq!(sessions[0].server_id.as_deref(), Some("server-a"));
+        assert_eq!(sessions[1].id, "old-session");
+        assert!(sessions[1].server_id.is_none());
+    }
 }
@@ -8,7 +8,11 @@ pub struct EventFilters {
     pub vm_id: Option<String>,
     pub category: Option<String>,
     pub event_type: Option<String>,
+    /// Indexed header (name, value) the event must carry
+    pub header: Option<(String, String)>,
     pub limit: Option<i64>,
+    /// Return the latest events first, so `limit` keeps the most recent ones
+    pub newest_first: bool,
 }
 
 /// Summary of a session returned by list_sessions (used by CLI and tests).
@@ -20,6 +24,7 @@ pub struct SessionInfo {
     pub stopped_at: Option<String>,
     pub server_version: String,
     pub event_count: i64,
+    pub server_id: Option<String>,
 }
 
 /// A single event row returned by queries (used by CLI and tests).
@@ -38,3 +43,17 @@ pub struct Event {
     pub success: Option<bool>,
     pub data: serde_json::Value,
 }
+
+/// What a size-based prune of the database removed.
+#[derive(Debug, Default)]
+pub struct PruneSummary {
+    /// Deleted sessions, oldest first
+    pub sessions: Vec<String>,
+    /// Events deleted with them
+    pub events: u64,
+    /// Bytes of data in the database before and after pruning
+    pub bytes_before: u64,
+    pub bytes_after: u64,
+    /// Externalized bodies that only deleted events referred to
+    pub body_paths: Vec<std::path::PathBuf>,
+}
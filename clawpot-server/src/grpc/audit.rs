@@ -0,0 +1,215 @@
+use crate::events::EventStore;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Instant;
+use tonic::codegen::http::{HeaderMap, Request, Response};
+use tonic::transport::server::{TcpConnectInfo, UdsConnectInfo};
+use tonic::Code;
+use tower::{Layer, Service};
+
+/// Environment variable that enables recording every gRPC call as a `grpc.call` event
+pub const AUDIT_GRPC_ENV: &str = "CLAWPOT_AUDIT_GRPC";
+
+/// Records every gRPC call (method, client, duration and status) as a
+/// `grpc.call` event, independent of the events handlers emit themselves.
+/// Calls rejected before reaching a handler are recorded too.
+///
+/// The status is taken from the response headers, where tonic puts errors
+/// returned by a handler. Calls that start a response stream are recorded
+/// when the stream starts, as OK, with the duration of their setup.
+#[derive(Clone)]
+pub struct AuditLayer {
+    /// `None` when auditing is off, so calls pass straight through
+    events: Option<EventStore>,
+}
+
+impl AuditLayer {
+    pub fn new(events: Option<EventStore>) -> Self {
+        Self { events }
+    }
+}
+
+impl<S> Layer<S> for AuditLayer {
+    type Service = AuditService<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AuditService {
+            inner,
+            events: self.events.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct AuditService<S> {
+    inner: S,
+    events: Option<EventStore>,
+}
+
+impl<S, B, R> Service<Request<B>> for AuditService<S>
+where
+    S: Service<Request<B>, Response = Response<R>> + Clone + Send + 'static,
+    S::Future: Send + 'static,
+    S::Error: Send + 'static,
+    B: Send + 'static,
+    R: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request<B>) -> Self::Future {
+        // Call the instance that was polled ready, leaving a fresh clone behind
+        let clone = self.inner.clone();
+        let mut inner = std::mem::replace(&mut self.inner, clone);
+        let Some(events) = self.events.clone() else {
+            return Box::pin(inner.call(req));
+        };
+
+        let method = req.uri().path().to_string();
+        let peer = client_peer(&req);
+        let start = Instant::now();
+        Box::pin(async move {
+            let result = inner.call(req).await;
+            let duration_ms = start.elapsed().as_millis() as i64;
+            let code = result
+                .as_ref()
+                .ok()
+                .map(|resp| response_code(resp.headers()));
+            let status = code.map_or_else(|| "transport error".to_string(), |c| format!("{c:?}"));
+            events.emit_with_duration(
+                "grpc.call",
+                "server",
+                None,
+                None,
+                duration_ms,
+                Some(code == Some(Code::Ok)),
+                &serde_json::json!({
+                    "method": method,
+                    "peer": peer,
+                    "status_code": code.map(i32::from),
+                    "status": status,
+                }),
+            );
+            result
+        })
+    }
+}
+
+/// The gRPC status in a response's headers. Successful calls carry theirs in
+/// the trailers instead, so a missing status means OK.
+fn response_code(headers: &HeaderMap) -> Code {
+    headers
+        .get("grpc-status")
+        .and_then(|v| v.to_str().ok())
+        .and_then(|v| v.parse::<i32>().ok())
+        .map_or(Code::Ok, Code::from_i32)
+}
+
+/// The calling client: its address for TCP connections, or its user and
+/// process IDs for connections over the Unix socket.
+fn client_peer<B>(req: &Request<B>) -> Option<String> {
+    if let Some(info) = req.extensions().get::<TcpConnectInfo>() {
+        return info.remote_addr().map(|addr| addr.to_string());
+    }
+    let cred = req.extensions().get::<UdsConnectInfo>()?.peer_cred?;
+    Some(match cred.pid() {
+        Some(pid) => format!("unix:uid={},pid={pid}", cred.uid()),
+        None => format!("unix:uid={}", cred.uid()),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{EventFilters, PersistMode};
+    use std::convert::Infallible;
+
+    /// Answers every call with the given status in the response headers
+    #[derive(Clone)]
+    struct Reply(Option<Code>);
+
+    impl Service<Request<()>> for Reply {
+        type Response = Response<()>;
+        type Error = Infallible;
+        type Future = std::future::Ready<Result<Response<()>, Infallible>>;
+
+        fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Infallible>> {
+            Poll::Ready(Ok(()))
+        }
+
+        fn call(&mut self, _req: Request<()>) -> Self::Future {
+            let mut resp = Response::new(());
+            if let Some(code) = self.0 {
+                resp.headers_mut()
+                    .insert("grpc-status", i32::from(code).into());
+            }
+            std::future::ready(Ok(resp))
+        }
+    }
+
+    fn request(path: &str) -> Request<()> {
+        Request::builder().uri(path).body(()).unwrap()
+    }
+
+    #[test]
+    fn test_response_code() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(response_code(&headers), Code::Ok);
+        headers.insert("grpc-status", "7".parse().unwrap());
+        assert_eq!(response_code(&headers), Code::PermissionDenied);
+    }
+
+    #[tokio::test]
+    async fn test_calls_are_recorded() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("events.db");
+        let events = EventStore::new(
+            &path,
+            "test-session",
+            "test-server",
+            "0.1.0",
+            "{}",
+            PersistMode::All,
+        )
+        .unwrap();
+
+        let layer = AuditLayer::new(Some(events.clone()));
+        let mut ok = layer.layer(Reply(None));
+        ok.call(request("/clawpot.ClawpotService/ListVMs"))
+            .await
+            .unwrap();
+        let mut denied = layer.layer(Reply(Some(Code::Unauthenticated)));
+        denied
+            .call(request("/clawpot.ClawpotService/DeleteVM"))
+            .await
+            .unwrap();
+        // Disabled auditing records nothing
+        let mut off = AuditLayer::new(None).layer(Reply(None));
+        off.call(request("/clawpot.ClawpotService/CreateVM"))
+            .await
+            .unwrap();
+        events.close_session().await;
+
+        let conn = EventStore::open_readonly(&path).unwrap();
+        let calls = EventStore::query_events(
+            &conn,
+            &EventFilters {
+                event_type: Some("grpc.call".to_string()),
+                ..EventFilters::default()
+            },
+        )
+        .unwrap();
+        assert_eq!(calls.len(), 2);
+        assert_eq!(calls[0].data["method"], "/clawpot.ClawpotService/ListVMs");
+        assert_eq!(calls[0].success, Some(true));
+        assert_eq!(calls[1].data["status"], "Unauthenticated");
+        assert_eq!(calls[1].data["status_code"], 16);
+        assert_eq!(calls[1].success, Some(false));
+    }
+}
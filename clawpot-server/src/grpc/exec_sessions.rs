@@ -0,0 +1,118 @@
+use crate::vm::registry::VmId;
+use clawpot_common::proto::ExecVmStreamOutput;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tokio::sync::broadcast;
+use uuid::Uuid;
+
+/// Output frames buffered for slow observers before they start missing frames
+const OBSERVER_BUFFER_FRAMES: usize = 256;
+
+/// Streaming execs that other clients can watch with `AttachExec`.
+///
+/// Each `ExecVMStream` opens a session and copies every frame it sends its
+/// own client to the session's broadcast channel. Observers see the frames
+/// sent after they attached, and their stream ends once the exec finishes.
+#[derive(Default)]
+pub struct ExecSessions {
+    sessions: Mutex<HashMap<Uuid, ExecSession>>,
+}
+
+struct ExecSession {
+    vm_id: VmId,
+    command: String,
+    output: broadcast::Sender<ExecVmStreamOutput>,
+}
+
+/// A client attached to a running exec
+pub struct ExecObserver {
+    pub vm_id: VmId,
+    pub command: String,
+    pub output: broadcast::Receiver<ExecVmStreamOutput>,
+}
+
+impl ExecSessions {
+    /// Register a streaming exec, returning its session ID and the sender its
+    /// output frames are copied to.
+    pub fn open(
+        &self,
+        vm_id: VmId,
+        command: &str,
+    ) -> (Uuid, broadcast::Sender<ExecVmStreamOutput>) {
+        let id = Uuid::new_v4();
+        let output = broadcast::channel(OBSERVER_BUFFER_FRAMES).0;
+        self.sessions.lock().unwrap().insert(
+            id,
+            ExecSession {
+                vm_id,
+                command: command.to_string(),
+                output: output.clone(),
+            },
+        );
+        (id, output)
+    }
+
+    /// Subscribe to the output of a running exec.
+    pub fn attach(&self, id: &Uuid) -> Option<ExecObserver> {
+        let sessions = self.sessions.lock().unwrap();
+        let session = sessions.get(id)?;
+        Some(ExecObserver {
+            vm_id: session.vm_id,
+            command: session.command.clone(),
+            output: session.output.subscribe(),
+        })
+    }
+
+    /// Forget a finished exec. Observers' streams end once the exec drops
+    /// the sender returned by [`open`](Self::open).
+    pub fn close(&self, id: &Uuid) {
+        self.sessions.lock().unwrap().remove(id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use clawpot_common::proto::exec_vm_stream_output::Output;
+
+    fn frame(output: Output) -> ExecVmStreamOutput {
+        ExecVmStreamOutput {
+            output: Some(output),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_attach_and_close() {
+        let sessions = ExecSessions::default();
+        let vm_id = Uuid::new_v4();
+        let (id, output) = sessions.open(vm_id, "make");
+
+        // Frames sent before attaching aren't replayed
+        let _ = output.send(frame(Output::StdoutData(b"early".to_vec())));
+        let mut observer = sessions.attach(&id).unwrap();
+        assert_eq!(observer.vm_id, vm_id);
+        assert_eq!(observer.command, "make");
+
+        output
+            .send(frame(Output::StdoutData(b"late".to_vec())))
+            .unwrap();
+        output.send(frame(Output::ExitCode(0))).unwrap();
+        sessions.close(&id);
+        drop(output);
+
+        assert_eq!(
+            observer.output.recv().await.unwrap().output,
+            Some(Output::StdoutData(b"late".to_vec()))
+        );
+        assert_eq!(
+            observer.output.recv().await.unwrap().output,
+            Some(Output::ExitCode(0))
+        );
+        assert!(matches!(
+            observer.output.recv().await,
+            Err(broadcast::error::RecvError::Closed)
+        ));
+        assert!(sessions.attach(&id).is_none());
+        assert!(sessions.attach(&Uuid::new_v4()).is_none());
+    }
+}
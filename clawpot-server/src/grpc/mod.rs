@@ -1,3 +1,6 @@
+pub mod audit;
+pub mod exec_sessions;
 pub mod service;
+pub mod uds;
 
 pub use service::ClawpotServiceImpl;
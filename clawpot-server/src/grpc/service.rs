@@ -1,24 +1,211 @@
 use crate::agent;
 use crate::clawpot_event;
-use crate::events::EventStore;
-use crate::network::{ip_allocator::IpAllocator, NetworkManager};
-use crate::vm::{VmEntry, VmRegistry};
-use clawpot_common::firecracker::VmConfig;
+use crate::config::Config;
+use crate::events::{EventFilters, EventStore};
+use crate::grpc::exec_sessions::ExecSessions;
+use crate::network::{ip_allocator::IpAllocator, iptables, NetworkManager};
+use crate::orphans::{self, Orphan, OrphanKind};
+use crate::proxy::body_store::BodyStore;
+use crate::proxy::token_budget::TokenBudget;
+use crate::vm::host;
+use crate::vm::pool::{
+    DEFAULT_RESET_SCRIPT, POOL_LABEL, POOL_RETRY_DELAY, RESET_SCRIPT_ENV, RESET_TIMEOUT,
+};
+use crate::vm::registry::VmListing;
+use crate::vm::{VmEntry, VmPool, VmRegistry};
+use clawpot_common::agent_proto::{exec_stream_input, exec_stream_output, ExecStreamInput};
+use clawpot_common::firecracker::{
+    config::MIN_GUEST_CID, ConfigError, FullVmConfig, RateLimiter, RootfsMode, VmConfig,
+};
+use clawpot_common::grpc;
 use clawpot_common::proto::{
-    clawpot_service_server::ClawpotService, CreateVmRequest, CreateVmResponse, DeleteVmRequest,
-    DeleteVmResponse, ExecVmRequest, ExecVmResponse, ExecVmStreamInput, ExecVmStreamOutput,
-    ListVmsRequest, ListVmsResponse, VmInfo, VmState as ProtoVmState,
+    clawpot_service_server::ClawpotService, exec_vm_stream_input, exec_vm_stream_output,
+    AttachExecRequest, CleanOrphansRequest, CleanOrphansResponse, ConsoleLine, CreateVmRequest,
+    CreateVmResponse, DeleteVmRequest, DeleteVmResponse, DescribeVmRequest, DescribeVmResponse,
+    ExecVmRequest, ExecVmResponse, ExecVmStreamInput, ExecVmStreamOutput, GetServerInfoRequest,
+    GetServerInfoResponse, GetVmStatusRequest, GetVmStatusResponse, IpPoolStatus,
+    ListNetRulesRequest, ListNetRulesResponse, ListOrphansRequest, ListOrphansResponse,
+    ListVmsRequest, ListVmsResponse, NetRule, OrphanKind as ProtoOrphanKind, OrphanResource,
+    PauseVmRequest, PauseVmResponse, RebootVmRequest, RebootVmResponse, ResetVmRequest,
+    ResetVmResponse, RestoreVmRequest, RestoreVmResponse, ResumeVmRequest, ResumeVmResponse,
+    SetDrainRequest, SetDrainResponse, StreamConsoleRequest, UpdateVmMemoryRequest,
+    UpdateVmMemoryResponse, VmEvent, VmInfo, VmState as ProtoVmState,
 };
-use clawpot_common::vm::VmManager;
-use std::path::PathBuf;
+use clawpot_common::vm::{
+    manager::prepare_rootfs, RestoreOverrides, SnapshotPaths, VmManager, VmState,
+};
+use std::collections::BTreeMap;
+use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
 use std::sync::Arc;
 use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
-use tokio::sync::Mutex;
+use tokio::sync::{broadcast, mpsc, watch, Mutex};
+use tokio_stream::wrappers::ReceiverStream;
 use tonic::{Request, Response, Status};
-use tracing::{error, Span};
+use tracing::{error, warn, Span};
 use uuid::Uuid;
 
-const GUEST_CID: u32 = 3;
+/// Frames buffered in each direction of a streaming exec
+const EXEC_STREAM_BUFFER: usize = 32;
+
+/// Recent events `DescribeVM` includes when the request doesn't say
+const DESCRIBE_EVENTS: u32 = 10;
+
+/// Most recent events `DescribeVM` will return
+const MAX_DESCRIBE_EVENTS: u32 = 1000;
+
+/// A resource that was still present after a VM was deleted
+#[derive(serde::Serialize)]
+struct LeakedResource {
+    resource: &'static str,
+    name: String,
+    critical: bool,
+}
+
+impl From<&Orphan> for OrphanResource {
+    fn from(orphan: &Orphan) -> Self {
+        let kind = match orphan.kind {
+            OrphanKind::TapDevice => ProtoOrphanKind::TapDevice,
+            OrphanKind::Socket => ProtoOrphanKind::Socket,
+        };
+        Self {
+            kind: kind as i32,
+            name: orphan.name.clone(),
+        }
+    }
+}
+
+/// Map a VM lifecycle state to its protobuf representation
+fn proto_state(state: VmState) -> ProtoVmState {
+    match state {
+        VmState::NotStarted => ProtoVmState::Unspecified,
+        VmState::Starting => ProtoVmState::Starting,
+        VmState::Running => ProtoVmState::Running,
+        VmState::Paused => ProtoVmState::Paused,
+        VmState::Stopping => ProtoVmState::Stopping,
+        VmState::Stopped => ProtoVmState::Stopped,
+        VmState::Error => ProtoVmState::Error,
+    }
+}
+
+/// Summary of a registered VM as returned by `ListVMs`
+fn vm_info(listing: VmListing) -> VmInfo {
+    let (id, ip_address, _tap_name, vcpu_count, mem_size_mib, created_at, state, name, labels) =
+        listing;
+    VmInfo {
+        vm_id: id.to_string(),
+        state: proto_state(state) as i32,
+        ip_address: ip_address.to_string(),
+        vcpu_count: u32::from(vcpu_count),
+        mem_size_mib,
+        created_at: created_at
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs() as i64,
+        socket_path: format!("/tmp/fc-{}.sock", id.simple()),
+        name: name.unwrap_or_default(),
+        labels: labels.into_iter().collect(),
+    }
+}
+
+/// Map Firecracker's instance state string to its protobuf representation
+fn firecracker_state(state: &str) -> ProtoVmState {
+    match state {
+        "Not started" => ProtoVmState::Starting,
+        "Running" => ProtoVmState::Running,
+        "Paused" => ProtoVmState::Paused,
+        _ => ProtoVmState::Unspecified,
+    }
+}
+
+/// Check a requested VM name: a DNS label (lowercase letters, digits and
+/// inner hyphens, at most 63 characters) so it reads cleanly in logs.
+/// Names that parse as a UUID are rejected, since requests accept either.
+fn validate_vm_name(name: &str) -> Result<(), Status> {
+    let valid = !name.is_empty()
+        && name.len() <= 63
+        && !name.starts_with('-')
+        && !name.ends_with('-')
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_lowercase() || b.is_ascii_digit() || b == b'-');
+    if !valid {
+        return Err(Status::invalid_argument(format!(
+            "Invalid VM name {name:?}: use 1-63 lowercase letters, digits or hyphens, \
+             not starting or ending with a hyphen"
+        )));
+    }
+    if Uuid::parse_str(name).is_ok() {
+        return Err(Status::invalid_argument(format!(
+            "Invalid VM name {name:?}: names can't look like a VM ID"
+        )));
+    }
+    Ok(())
+}
+
+/// Move a restored guest's `eth0` to `ip_address`, through its agent.
+async fn readdress_guest(
+    vsock_uds_path: &str,
+    ip_address: IpAddr,
+    gateway: IpAddr,
+    prefix: u8,
+) -> anyhow::Result<()> {
+    let mut agent_client =
+        agent::client::AgentClient::wait_ready(vsock_uds_path, Duration::from_secs(30)).await?;
+    let script = format!(
+        "ip addr flush dev eth0 && ip addr add {ip_address}/{prefix} dev eth0 \
+         && ip route replace default via {gateway} dev eth0"
+    );
+    let response = agent_client
+        .exec(clawpot_common::agent_proto::ExecRequest {
+            command: "/bin/sh".to_string(),
+            args: vec!["-c".to_string(), script],
+            ..Default::default()
+        })
+        .await?;
+    if response.exit_code != 0 {
+        anyhow::bail!(
+            "ip exited with {}: {}",
+            response.exit_code,
+            String::from_utf8_lossy(&response.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+/// Labels of an idle VM waiting in the pool
+fn idle_labels() -> BTreeMap<String, String> {
+    BTreeMap::from([(POOL_LABEL.to_string(), "idle".to_string())])
+}
+
+/// Check requested VM labels. Keys are 1-63 lowercase letters, digits, `.`,
+/// `_`, `-` or `/`; values are up to 63 printable characters. Neither may
+/// contain `,` or `=`, which separate pairs in label selectors.
+fn validate_labels<'a>(
+    labels: impl IntoIterator<Item = (&'a String, &'a String)>,
+) -> Result<(), Status> {
+    for (key, value) in labels {
+        let key_valid = !key.is_empty()
+            && key.len() <= 63
+            && key.bytes().all(|b| {
+                b.is_ascii_lowercase()
+                    || b.is_ascii_digit()
+                    || matches!(b, b'.' | b'_' | b'-' | b'/')
+            });
+        let value_valid = value.len() <= 63
+            && value
+                .bytes()
+                .all(|b| b.is_ascii_graphic() && b != b',' && b != b'=');
+        if !key_valid || !value_valid {
+            return Err(Status::invalid_argument(format!(
+                "Invalid VM label {key:?}={value:?}: keys are 1-63 lowercase letters, digits, \
+                 '.', '_', '-' or '/'; values are up to 63 printable characters without ',' or '='"
+            )));
+        }
+    }
+    Ok(())
+}
 
 /// gRPC service implementation for Clawpot
 pub struct ClawpotServiceImpl {
@@ -27,7 +214,20 @@ pub struct ClawpotServiceImpl {
     network_manager: Arc<NetworkManager>,
     kernel_path: PathBuf,
     rootfs_path: PathBuf,
+    config: Arc<Config>,
     event_store: EventStore,
+    next_guest_cid: AtomicU32,
+    /// Set while draining: new VMs are rejected so the server can be replaced
+    draining: AtomicBool,
+    server_id: String,
+    session_id: String,
+    body_store: Arc<BodyStore>,
+    /// The HTTP proxy's LLM token budget, cleared for deleted VMs
+    token_budget: TokenBudget,
+    /// Idle VMs handed out by `CreateVM` with `from_pool`
+    pool: VmPool,
+    /// Running streaming execs, for `AttachExec`
+    exec_sessions: Arc<ExecSessions>,
 }
 
 impl ClawpotServiceImpl {
@@ -37,16 +237,432 @@ impl ClawpotServiceImpl {
         network_manager: Arc<NetworkManager>,
         kernel_path: PathBuf,
         rootfs_path: PathBuf,
+        config: Arc<Config>,
         event_store: EventStore,
+        server_id: String,
+        session_id: String,
+        body_store: Arc<BodyStore>,
+        token_budget: TokenBudget,
     ) -> Self {
+        let pool = VmPool::new(config.vm_pool_size);
         Self {
             vm_registry,
             ip_allocator,
             network_manager,
             kernel_path,
             rootfs_path,
+            config,
             event_store,
+            next_guest_cid: AtomicU32::new(MIN_GUEST_CID),
+            draining: AtomicBool::new(false),
+            server_id,
+            session_id,
+            body_store,
+            token_budget,
+            pool,
+            exec_sessions: Arc::new(ExecSessions::default()),
+        }
+    }
+
+    /// Keep `CLAWPOT_VM_POOL_SIZE` idle VMs booted, replacing each one that
+    /// is handed out or deleted, until `cancel` fires. Nothing is booted
+    /// while the server is draining.
+    pub async fn run_pool(self: Arc<Self>, mut cancel: watch::Receiver<bool>) {
+        loop {
+            while self.pool.needs_vms() && !self.is_draining() && !*cancel.borrow() {
+                match self.boot_pooled_vm().await {
+                    Ok(vm_id) => {
+                        self.pool.push(vm_id);
+                        clawpot_event!(self.event_store, "vm.pool.added", "vm", vm_id = vm_id, {
+                            "pool_idle": self.pool.idle_count(),
+                            "pool_target": self.pool.target()
+                        });
+                    }
+                    // Refused because draining started mid-boot
+                    Err(_) if self.is_draining() => break,
+                    Err(status) => {
+                        warn!("Failed to boot a pooled VM: {}", status.message());
+                        tokio::select! {
+                            () = tokio::time::sleep(POOL_RETRY_DELAY) => {}
+                            _ = cancel.changed() => return,
+                        }
+                    }
+                }
+            }
+            tokio::select! {
+                () = self.pool.wanted() => {}
+                _ = cancel.changed() => return,
+            }
+        }
+    }
+
+    /// Boot an idle VM for the pool, with the default size
+    async fn boot_pooled_vm(&self) -> Result<Uuid, Status> {
+        let request = CreateVmRequest {
+            labels: idle_labels().into_iter().collect(),
+            ..CreateVmRequest::default()
+        };
+        let response = self.create_vm(Request::new(request)).await?.into_inner();
+        Uuid::parse_str(&response.vm_id).map_err(|e| Status::internal(e.to_string()))
+    }
+
+    /// Hand out an idle pooled VM under the caller's name and labels, or
+    /// `None` if the pool is empty.
+    async fn assign_pooled_vm(
+        &self,
+        events: &EventStore,
+        name: Option<&String>,
+        labels: &BTreeMap<String, String>,
+        start: Instant,
+    ) -> Result<Option<CreateVmResponse>, Status> {
+        while let Some(vm_id) = self.pool.take() {
+            let assigned = self
+                .vm_registry
+                .assign(&vm_id, name.cloned(), labels.clone())
+                .await
+                .map_err(|e| {
+                    self.pool.put_back(vm_id);
+                    Status::already_exists(e.to_string())
+                })?;
+            // Skip VMs that were deleted while idle, and delete any that
+            // stopped so they don't hold on to their TAP device and address
+            let Some((ip_address, socket_path)) = assigned else {
+                if self.vm_registry.state(&vm_id).await.is_some() {
+                    warn!("Deleting pooled VM {vm_id}, which stopped while idle");
+                    let _ = self
+                        .delete_vm(Request::new(DeleteVmRequest {
+                            vm_id: vm_id.to_string(),
+                        }))
+                        .await;
+                }
+                continue;
+            };
+
+            let vm_id_str = vm_id.to_string();
+            Span::current().record("vm_id", vm_id_str.as_str());
+            let ip_address = ip_address.to_string();
+            let socket_path = socket_path.to_string_lossy().to_string();
+            events.emit_with_duration(
+                "vm.pool.assigned",
+                "vm",
+                Some(&vm_id_str),
+                None,
+                start.elapsed().as_millis() as i64,
+                Some(true),
+                &serde_json::json!({
+                    "vm_name": name,
+                    "labels": labels,
+                    "ip_address": ip_address,
+                    "pool_idle": self.pool.idle_count(),
+                }),
+            );
+            return Ok(Some(CreateVmResponse {
+                vm_id: vm_id_str,
+                ip_address,
+                socket_path,
+            }));
+        }
+        Ok(None)
+    }
+
+    /// Run the reset script in a VM returned to the pool, then check its
+    /// agent still answers. This is best-effort cleanup, not a restore: see
+    /// [`RESET_SCRIPT_ENV`]. Returns why the VM can't be reused on failure.
+    async fn clean_up_guest(&self, vm_id: &Uuid) -> Result<(), String> {
+        let script = match &self.config.vm_reset_script {
+            Some(path) => tokio::fs::read_to_string(path).await.map_err(|e| {
+                format!("Failed to read {RESET_SCRIPT_ENV} {}: {e}", path.display())
+            })?,
+            None => DEFAULT_RESET_SCRIPT.to_string(),
+        };
+        let vsock_path = self
+            .vm_registry
+            .get_vsock_path(vm_id)
+            .await
+            .map_err(|e| e.to_string())?;
+        let mut agent_client = agent::client::AgentClient::connect(vsock_path)
+            .await
+            .map_err(|e| format!("Failed to connect to agent: {e}"))?;
+
+        let reset = clawpot_common::agent_proto::ExecRequest {
+            command: "/bin/sh".to_string(),
+            args: vec!["-c".to_string(), script],
+            ..Default::default()
+        };
+        let response = tokio::time::timeout(RESET_TIMEOUT, agent_client.exec(reset))
+            .await
+            .map_err(|_| format!("Reset script timed out after {RESET_TIMEOUT:?}"))?
+            .map_err(|e| format!("Reset script failed: {e:#}"))?;
+        if response.exit_code != 0 {
+            return Err(format!(
+                "Reset script exited with {}: {}",
+                response.exit_code,
+                String::from_utf8_lossy(&response.stderr).trim()
+            ));
+        }
+
+        // Only a liveness check: the script mustn't have taken the agent
+        // down with the strays
+        let probe = clawpot_common::agent_proto::ExecRequest {
+            command: "true".to_string(),
+            ..Default::default()
+        };
+        match tokio::time::timeout(RESET_TIMEOUT, agent_client.exec(probe)).await {
+            Ok(Ok(response)) if response.exit_code == 0 => Ok(()),
+            _ => Err("Agent stopped answering after the reset script".to_string()),
+        }
+    }
+
+    /// Look up a VM by ID or, failing that, by name
+    async fn resolve_vm_id(&self, id_or_name: &str) -> Result<Uuid, Status> {
+        if let Ok(id) = Uuid::parse_str(id_or_name) {
+            return Ok(id);
+        }
+        self.vm_registry
+            .find_by_name(id_or_name)
+            .await
+            .ok_or_else(|| Status::not_found(format!("No VM with ID or name {id_or_name:?}")))
+    }
+
+    /// Like [`resolve_vm_id`](Self::resolve_vm_id), for RPCs that act on a
+    /// tenant's VM: idle pooled VMs are hidden until they are handed out.
+    async fn resolve_tenant_vm_id(&self, id_or_name: &str) -> Result<Uuid, Status> {
+        let id = self.resolve_vm_id(id_or_name).await?;
+        if self.pool.is_idle(&id) {
+            return Err(Status::not_found(format!(
+                "No VM with ID or name {id_or_name:?}"
+            )));
+        }
+        Ok(id)
+    }
+
+    fn is_draining(&self) -> bool {
+        self.draining.load(Ordering::Relaxed)
+    }
+
+    /// Reject new VMs while the server is draining
+    fn check_not_draining(&self) -> Result<(), Status> {
+        if self.is_draining() {
+            return Err(Status::unavailable(
+                "Server is draining and not accepting new VMs",
+            ));
+        }
+        Ok(())
+    }
+
+    /// Allocate a vsock guest CID that no other VM from this server has used
+    fn allocate_guest_cid(&self) -> u32 {
+        self.next_guest_cid.fetch_add(1, Ordering::Relaxed)
+    }
+
+    /// Register a VM that failed to boot in the Error state so it can be inspected.
+    ///
+    /// Only used when `CLAWPOT_KEEP_ON_FAILURE` is set. The returned status
+    /// reports the failure and carries the VM ID in the `clawpot-vm-id` metadata.
+    /// `events` is the create's correlation-scoped store.
+    async fn keep_failed_vm(
+        &self,
+        events: &EventStore,
+        mut entry: VmEntry,
+        step: &str,
+        error: &str,
+    ) -> Status {
+        let vm_id = entry.id;
+        let vm_id_str = vm_id.to_string();
+        entry.manager.mark_failed();
+        let serial_log = entry
+            .manager
+            .serial_log_path()
+            .map(|p| p.display().to_string());
+
+        if let Err(e) = self.vm_registry.insert(vm_id, entry).await {
+            error!("Failed to register failed VM {}: {}", vm_id, e);
+        }
+
+        clawpot_event!(events, "vm.create.failed", "vm", vm_id = vm_id_str, {
+            "error": error,
+            "step": step,
+            "kept": true,
+            "serial_log": serial_log
+        });
+
+        let mut status = Status::internal(format!(
+            "Failed to start VM {vm_id_str} ({step}): {error}. \
+             VM kept for debugging; serial log: {}. Delete it when done.",
+            serial_log.as_deref().unwrap_or("(none)")
+        ));
+        if let Ok(value) = vm_id_str.parse() {
+            status.metadata_mut().insert("clawpot-vm-id", value);
+        }
+        status
+    }
+
+    /// Pause or resume a registered VM, reporting the outcome as events.
+    async fn set_paused(&self, vm_id: Uuid, paused: bool) -> Result<(), Status> {
+        let (action, expected) = if paused {
+            ("pause", VmState::Running)
+        } else {
+            ("resume", VmState::Paused)
+        };
+        let vm_id_str = vm_id.to_string();
+
+        match self.vm_registry.state(&vm_id).await {
+            None => return Err(Status::not_found(format!("VM {vm_id_str} not found"))),
+            Some(state) if state != expected => {
+                return Err(Status::failed_precondition(format!(
+                    "Cannot {action} VM {vm_id_str} in state {state}"
+                )));
+            }
+            Some(_) => {}
+        }
+
+        let start = Instant::now();
+        let result = self.vm_registry.set_paused(&vm_id, paused).await;
+        self.event_store.emit_with_duration(
+            &format!("vm.{action}.completed"),
+            "vm",
+            Some(&vm_id_str),
+            None,
+            start.elapsed().as_millis() as i64,
+            Some(result.is_ok()),
+            &serde_json::json!({
+                "error": result.as_ref().err().map(|e| format!("{e:#}")),
+            }),
+        );
+        result.map_err(|e| Status::internal(format!("Failed to {action} VM: {e:#}")))
+    }
+
+    /// Map an agent exec failure to a gRPC status, surfacing oversized output clearly.
+    fn agent_exec_status(&self, err: &anyhow::Error, vm_id: &str, command: &str) -> Status {
+        match err.downcast_ref::<Status>() {
+            Some(status) if grpc::is_message_too_large(status) => {
+                self.emit_exec_too_large(vm_id, command, status.message());
+                Status::resource_exhausted(format!(
+                    "Exec output is too large for a single gRPC message (limit {} bytes); \
+                     use the streaming API or raise CLAWPOT_GRPC_MAX_MESSAGE_SIZE",
+                    self.config.grpc_max_message_size
+                ))
+            }
+            _ => Status::internal(format!("Agent exec failed: {err:#}")),
+        }
+    }
+
+    /// Check that everything a deleted VM owned is actually gone.
+    ///
+    /// TAP devices and IP addresses are critical: leaking them breaks future
+    /// VMs. Leftover socket files are reported but do not fail the delete.
+    async fn verify_cleanup(&self, entry: &VmEntry) -> Vec<LeakedResource> {
+        let mut leaked = Vec::new();
+
+        if self.network_manager.tap_exists(&entry.tap_name).await {
+            leaked.push(LeakedResource {
+                resource: "tap",
+                name: entry.tap_name.clone(),
+                critical: true,
+            });
+        }
+
+        if self
+            .ip_allocator
+            .lock()
+            .await
+            .is_allocated(entry.ip_address)
+        {
+            leaked.push(LeakedResource {
+                resource: "ip",
+                name: entry.ip_address.to_string(),
+                critical: true,
+            });
+        }
+
+        for path in [
+            entry.manager.socket_path(),
+            Path::new(&entry.vsock_uds_path),
+        ] {
+            if path.exists() {
+                leaked.push(LeakedResource {
+                    resource: "socket",
+                    name: path.display().to_string(),
+                    critical: false,
+                });
+            }
         }
+
+        leaked
+    }
+
+    /// Refuse a VM of `mem_size_mib` that the host can't back, rather than
+    /// have it OOM-killed after it starts. `action` (`create` or `restore`)
+    /// names the events; without `CLAWPOT_HOST_MEMORY_CHECK` a shortfall is
+    /// only reported.
+    fn check_host_memory(
+        &self,
+        events: &EventStore,
+        action: &str,
+        vm_id: &str,
+        host_available_mib: Option<u64>,
+        mem_size_mib: u64,
+    ) -> Result<(), Status> {
+        let required_mib = mem_size_mib + self.config.host_memory_margin_mib;
+        let Some(available_mib) = host_available_mib.filter(|&mib| mib < required_mib) else {
+            return Ok(());
+        };
+        let message = format!(
+            "Host has {available_mib} MiB of memory available; a {mem_size_mib} MiB VM \
+             needs {required_mib} MiB including the {} MiB margin",
+            self.config.host_memory_margin_mib
+        );
+        clawpot_event!(events, &format!("vm.{action}.host_memory_low"), "vm", vm_id = vm_id, {
+            "host_available_mib": available_mib,
+            "required_mib": required_mib,
+            "enforced": self.config.host_memory_check
+        });
+        if self.config.host_memory_check {
+            clawpot_event!(events, &format!("vm.{action}.failed"), "vm", vm_id = vm_id, {
+                "error": message,
+                "step": "host_memory"
+            });
+            return Err(Status::resource_exhausted(message));
+        }
+        warn!("{message}");
+        Ok(())
+    }
+
+    /// Release what a create or restore set up on the host for a VM that
+    /// never made it into the registry: its TAP device, IP address, vsock socket and
+    /// private rootfs copy. Firecracker itself is stopped by dropping its
+    /// manager.
+    async fn release_unregistered_vm(
+        &self,
+        vm_id: &Uuid,
+        tap_name: &str,
+        ip_address: IpAddr,
+        vsock_uds_path: &str,
+    ) {
+        let _ = self.network_manager.delete_tap(tap_name, ip_address).await;
+        let _ = self.ip_allocator.lock().await.release(ip_address);
+        let _ = std::fs::remove_file(vsock_uds_path);
+        self.remove_rootfs_copy(vm_id);
+    }
+
+    /// Remove the private rootfs copy of a VM, if it has one.
+    fn remove_rootfs_copy(&self, vm_id: &Uuid) {
+        if self.config.rootfs_mode == RootfsMode::CopyOnWrite {
+            let copy = self
+                .config
+                .rootfs_mode
+                .path_on_host(&self.rootfs_path, &vm_id.simple().to_string());
+            let _ = std::fs::remove_file(copy);
+        }
+    }
+
+    /// Record an exec whose output could not be returned in a single message.
+    fn emit_exec_too_large(&self, vm_id: &str, command: &str, detail: &str) {
+        clawpot_event!(self.event_store, "vm.exec.output_too_large", "vm", vm_id = vm_id, {
+            "command": command,
+            "limit_bytes": self.config.grpc_max_message_size,
+            "error": detail
+        });
     }
 }
 
@@ -60,13 +676,19 @@ impl ClawpotService for ClawpotServiceImpl {
             vcpu_count = tracing::field::Empty,
             mem_size_mib = tracing::field::Empty,
             ip_address = tracing::field::Empty,
+            name = tracing::field::Empty,
         )
     )]
     async fn create_vm(
         &self,
         request: Request<CreateVmRequest>,
     ) -> Result<Response<CreateVmResponse>, Status> {
+        self.check_not_draining()?;
         let start = Instant::now();
+        // Every step of this create shares one correlation id
+        let events = self
+            .event_store
+            .with_correlation(Uuid::new_v4().to_string());
         let req = request.into_inner();
         let span = Span::current();
         let vcpu_count_val = req.vcpu_count.unwrap_or(1);
@@ -74,27 +696,118 @@ impl ClawpotService for ClawpotServiceImpl {
         span.record("vcpu_count", vcpu_count_val);
         span.record("mem_size_mib", mem_size_mib_val);
 
+        let name = req.name.filter(|n| !n.is_empty());
+        if let Some(name) = &name {
+            validate_vm_name(name)?;
+            if self.vm_registry.name_in_use(name).await {
+                return Err(Status::already_exists(format!(
+                    "A VM named {name} already exists"
+                )));
+            }
+            span.record("name", name.as_str());
+        }
+        validate_labels(&req.labels)?;
+        if req.rx_bytes_per_sec == Some(0) || req.tx_bytes_per_sec == Some(0) {
+            return Err(Status::invalid_argument(
+                "Network rate limits must be greater than zero (omit for unlimited)",
+            ));
+        }
+        let labels: BTreeMap<String, String> = req.labels.into_iter().collect();
+
+        if req.from_pool == Some(true) {
+            let sized = req.vcpu_count.is_some()
+                || req.mem_size_mib.is_some()
+                || req.rx_bytes_per_sec.is_some()
+                || req.tx_bytes_per_sec.is_some()
+                || req.ip_address.is_some()
+                || !req.data_drives.is_empty()
+                || req.balloon_mib.is_some();
+            if sized {
+                return Err(Status::invalid_argument(
+                    "Pooled VMs are already booted; only a name and labels can be set",
+                ));
+            }
+            if let Some(response) = self
+                .assign_pooled_vm(&events, name.as_ref(), &labels, start)
+                .await?
+            {
+                return Ok(Response::new(response));
+            }
+            // Pool empty (or disabled): fall back to booting one
+            if self.pool.target() > 0 {
+                clawpot_event!(events, "vm.pool.empty", "vm", {
+                    "pool_target": self.pool.target()
+                });
+            }
+        }
+
+        let requested_ip = req
+            .ip_address
+            .as_deref()
+            .filter(|ip| !ip.is_empty())
+            .map(|ip| {
+                ip.parse::<Ipv4Addr>()
+                    .map_err(|_| Status::invalid_argument(format!("Invalid IPv4 address {ip:?}")))
+            })
+            .transpose()?;
+
         // Generate VM ID
         let vm_id = Uuid::new_v4();
         let vm_id_str = vm_id.to_string();
         span.record("vm_id", vm_id_str.as_str());
 
-        clawpot_event!(self.event_store, "vm.create.started", "vm", vm_id = vm_id_str, {
+        let host_available_mib = host::available_memory_mib()
+            .inspect_err(|e| warn!("Skipping host memory check: {e:#}"))
+            .ok();
+        clawpot_event!(events, "vm.create.started", "vm", vm_id = vm_id_str, {
+            "vm_name": name,
+            "labels": labels,
             "vcpu_count": vcpu_count_val,
-            "mem_size_mib": mem_size_mib_val
+            "mem_size_mib": mem_size_mib_val,
+            "rx_bytes_per_sec": req.rx_bytes_per_sec,
+            "tx_bytes_per_sec": req.tx_bytes_per_sec,
+            "balloon_mib": req.balloon_mib,
+            "requested_ip": requested_ip.map(|ip| ip.to_string()),
+            "host_available_mib": host_available_mib
         });
 
+        self.check_host_memory(
+            &events,
+            "create",
+            &vm_id_str,
+            host_available_mib,
+            u64::from(mem_size_mib_val),
+        )?;
+
         // Allocate IP address
-        let ip_address = self.ip_allocator.lock().await.allocate().map_err(|e| {
-            clawpot_event!(self.event_store, "vm.create.failed", "vm", vm_id = vm_id_str, {
-                "error": e.to_string(),
-                "step": "ip_allocation"
-            });
-            Status::resource_exhausted(format!("No available IP addresses: {e}"))
-        })?;
+        let (ip_address, gateway, netmask) = {
+            let mut allocator = self.ip_allocator.lock().await;
+            let allocated = match requested_ip {
+                Some(ip) if allocator.is_allocated(ip.into()) => Err(Status::already_exists(
+                    format!("IP address {ip} is already in use"),
+                )),
+                Some(ip) => allocator
+                    .allocate_specific(ip)
+                    .map_err(|e| Status::invalid_argument(e.to_string())),
+                None => allocator.allocate().map_err(|e| {
+                    let pool = allocator.stats();
+                    Status::resource_exhausted(format!(
+                        "No available IP addresses ({} of {} in use): {e}",
+                        pool.allocated, pool.total
+                    ))
+                }),
+            };
+            let ip_address = allocated.inspect_err(|status| {
+                clawpot_event!(events, "vm.create.failed", "vm", vm_id = vm_id_str, {
+                    "error": status.message(),
+                    "step": "ip_allocation"
+                });
+            })?;
+            (ip_address, allocator.gateway(), allocator.netmask())
+        };
 
         span.record("ip_address", ip_address.to_string().as_str());
-        clawpot_event!(self.event_store, "vm.create.ip_allocated", "vm", vm_id = vm_id_str, {
+        clawpot_event!(events, "vm.create.ip_allocated", "vm", vm_id = vm_id_str, {
             "ip_address": ip_address.to_string()
         });
 
@@ -105,7 +818,7 @@ impl ClawpotService for ClawpotServiceImpl {
         // Create and configure TAP device
         if let Err(e) = self.network_manager.create_tap(&tap_name, ip_address).await {
             let _ = self.ip_allocator.lock().await.release(ip_address);
-            clawpot_event!(self.event_store, "vm.create.failed", "vm", vm_id = vm_id_str, {
+            clawpot_event!(events, "vm.create.failed", "vm", vm_id = vm_id_str, {
                 "error": e.to_string(),
                 "step": "tap_creation"
             });
@@ -114,7 +827,7 @@ impl ClawpotService for ClawpotServiceImpl {
             )));
         }
 
-        clawpot_event!(self.event_store, "vm.create.tap_created", "vm", vm_id = vm_id_str, {
+        clawpot_event!(events, "vm.create.tap_created", "vm", vm_id = vm_id_str, {
             "tap_name": tap_name
         });
 
@@ -122,34 +835,116 @@ impl ClawpotService for ClawpotServiceImpl {
         let vcpu_count = req.vcpu_count.unwrap_or(1) as u8;
         let mem_size_mib = req.mem_size_mib.unwrap_or(256);
 
-        // Vsock UDS path for this VM
+        // Vsock UDS path and guest CID for this VM
         let vsock_uds_path = format!("/tmp/fc-{}-vsock.sock", vm_id.simple());
+        let guest_cid = self.allocate_guest_cid();
+
+        // Root drive image (a private copy in copy-on-write mode)
+        // Every later failure that doesn't keep the VM goes through
+        // `release_unregistered_vm`, which removes the copy again
+        let rootfs_path = match prepare_rootfs(
+            &self.rootfs_path,
+            self.config.rootfs_mode,
+            &vm_id.simple().to_string(),
+        )
+        .await
+        {
+            Ok(path) => path,
+            Err(e) => {
+                let _ = self.network_manager.delete_tap(&tap_name, ip_address).await;
+                let _ = self.ip_allocator.lock().await.release(ip_address);
+                clawpot_event!(events, "vm.create.failed", "vm", vm_id = vm_id_str, {
+                    "error": e.to_string(),
+                    "step": "rootfs_copy"
+                });
+                return Err(Status::internal(format!("Failed to prepare rootfs: {e:#}")));
+            }
+        };
 
-        let config = VmConfig::new(self.kernel_path.clone(), self.rootfs_path.clone())
+        let mut config = VmConfig::new(self.kernel_path.clone(), rootfs_path)
+            .with_rootfs_mode(self.config.rootfs_mode)
             .with_vcpus(vcpu_count)
             .with_memory(mem_size_mib)
-            .with_network(tap_name.clone(), ip_address.to_string())
-            .with_vsock(GUEST_CID, vsock_uds_path.clone());
+            .with_network(tap_name.clone(), ip_address.to_string(), gateway, netmask)
+            .with_network_rate_limiters(
+                req.rx_bytes_per_sec.map(RateLimiter::bytes_per_second),
+                req.tx_bytes_per_sec.map(RateLimiter::bytes_per_second),
+            )
+            .with_vsock(guest_cid, vsock_uds_path.clone());
+        for (i, drive) in req.data_drives.iter().enumerate() {
+            config = config.with_extra_drive(
+                format!("data{i}"),
+                PathBuf::from(&drive.host_path),
+                drive.read_only,
+            );
+        }
+        if let Some(balloon_mib) = req.balloon_mib {
+            // Deflate under memory pressure rather than let the guest OOM
+            config = config.with_balloon(balloon_mib, true, 0);
+        }
 
         // Create socket path (Firecracker API socket)
         let socket_path = PathBuf::from(format!("/tmp/fc-{}.sock", vm_id.simple()));
 
         // Create and start VM manager
         let mut manager = VmManager::new(socket_path.clone());
+        if self.config.keep_on_failure {
+            manager = manager.with_serial_log(PathBuf::from(format!(
+                "/tmp/fc-{}-serial.log",
+                vm_id.simple()
+            )));
+        }
+
+        // Kept on the entry so the VM can be rebooted
+        let boot_config = config.clone();
+
+        // Entry for a VM that is kept (rather than rolled back) after a failed boot
+        let failed_entry = |manager| VmEntry {
+            id: vm_id,
+            manager,
+            ip_address,
+            tap_name: tap_name.clone(),
+            created_at: SystemTime::now(),
+            vcpu_count,
+            mem_size_mib,
+            vsock_uds_path: vsock_uds_path.clone(),
+            guest_cid,
+            name: name.clone(),
+            balloon: req.balloon_mib.is_some(),
+            labels: labels.clone(),
+            boot_config: Some(boot_config.clone()),
+        };
 
         if let Err(e) = manager.start(config).await {
-            let _ = self.network_manager.delete_tap(&tap_name, ip_address).await;
-            let _ = self.ip_allocator.lock().await.release(ip_address);
-            clawpot_event!(self.event_store, "vm.create.failed", "vm", vm_id = vm_id_str, {
+            if self.config.keep_on_failure && manager.has_process() {
+                return Err(self
+                    .keep_failed_vm(
+                        &events,
+                        failed_entry(manager),
+                        "firecracker_start",
+                        &e.to_string(),
+                    )
+                    .await);
+            }
+            drop(manager);
+            self.release_unregistered_vm(&vm_id, &tap_name, ip_address, &vsock_uds_path)
+                .await;
+            clawpot_event!(events, "vm.create.failed", "vm", vm_id = vm_id_str, {
                 "error": e.to_string(),
                 "step": "firecracker_start"
             });
-            return Err(Status::internal(format!("Failed to start VM: {e}")));
+            return Err(match e.downcast_ref::<ConfigError>() {
+                Some(config_err) if config_err.is_invalid_argument() => {
+                    Status::invalid_argument(format!("Invalid VM configuration: {config_err}"))
+                }
+                _ => Status::internal(format!("Failed to start VM: {e}")),
+            });
         }
 
-        clawpot_event!(self.event_store, "vm.create.firecracker_started", "vm", vm_id = vm_id_str, {
+        clawpot_event!(events, "vm.create.firecracker_started", "vm", vm_id = vm_id_str, {
             "socket_path": socket_path.to_string_lossy().to_string(),
-            "vsock_uds_path": vsock_uds_path
+            "vsock_uds_path": vsock_uds_path,
+            "guest_cid": guest_cid
         });
 
         // Wait for guest agent to become ready (non-fatal)
@@ -157,14 +952,24 @@ impl ClawpotService for ClawpotServiceImpl {
         match agent::client::AgentClient::wait_ready(&vsock_uds_path, Duration::from_secs(30)).await
         {
             Ok(_) => {
-                clawpot_event!(self.event_store, "vm.create.agent_ready", "vm", vm_id = vm_id_str, {
+                clawpot_event!(events, "vm.create.agent_ready", "vm", vm_id = vm_id_str, {
                     "wait_ms": agent_start.elapsed().as_millis() as i64
                 });
             }
             Err(e) => {
-                clawpot_event!(self.event_store, "vm.create.agent_timeout", "vm", vm_id = vm_id_str, {
+                clawpot_event!(events, "vm.create.agent_timeout", "vm", vm_id = vm_id_str, {
                     "error": e.to_string()
                 });
+                if self.config.keep_on_failure {
+                    return Err(self
+                        .keep_failed_vm(
+                            &events,
+                            failed_entry(manager),
+                            "agent_wait",
+                            &e.to_string(),
+                        )
+                        .await);
+                }
             }
         }
 
@@ -173,16 +978,25 @@ impl ClawpotService for ClawpotServiceImpl {
             id: vm_id,
             manager,
             ip_address,
-            tap_name,
+            tap_name: tap_name.clone(),
             created_at: SystemTime::now(),
             vcpu_count,
             mem_size_mib,
-            vsock_uds_path,
+            vsock_uds_path: vsock_uds_path.clone(),
+            guest_cid,
+            name,
+            balloon: req.balloon_mib.is_some(),
+            labels,
+            boot_config: Some(boot_config),
         };
 
-        // Insert into registry
+        // Insert into registry. This can still fail if another create took
+        // the same name while this VM booted; the rejected entry (and with it
+        // Firecracker) is dropped, so only the host resources are left.
         if let Err(e) = self.vm_registry.insert(vm_id, entry).await {
-            clawpot_event!(self.event_store, "vm.create.failed", "vm", vm_id = vm_id_str, {
+            self.release_unregistered_vm(&vm_id, &tap_name, ip_address, &vsock_uds_path)
+                .await;
+            clawpot_event!(events, "vm.create.failed", "vm", vm_id = vm_id_str, {
                 "error": e.to_string(),
                 "step": "registry_insert"
             });
@@ -190,7 +1004,7 @@ impl ClawpotService for ClawpotServiceImpl {
         }
 
         let duration_ms = start.elapsed().as_millis() as i64;
-        self.event_store.emit_with_duration(
+        events.emit_with_duration(
             "vm.create.completed",
             "vm",
             Some(&vm_id_str),
@@ -210,98 +1024,646 @@ impl ClawpotService for ClawpotServiceImpl {
         }))
     }
 
-    #[tracing::instrument(name = "grpc.DeleteVM", skip_all, fields(vm_id = tracing::field::Empty))]
-    async fn delete_vm(
+    #[tracing::instrument(
+        name = "grpc.RestoreVM",
+        skip_all,
+        fields(
+            vm_id = tracing::field::Empty,
+            ip_address = tracing::field::Empty,
+            name = tracing::field::Empty,
+        )
+    )]
+    async fn restore_vm(
         &self,
-        request: Request<DeleteVmRequest>,
-    ) -> Result<Response<DeleteVmResponse>, Status> {
+        request: Request<RestoreVmRequest>,
+    ) -> Result<Response<RestoreVmResponse>, Status> {
+        self.check_not_draining()?;
         let start = Instant::now();
         let req = request.into_inner();
-        Span::current().record("vm_id", req.vm_id.as_str());
+        let span = Span::current();
 
-        let vm_id = Uuid::parse_str(&req.vm_id)
-            .map_err(|e| Status::invalid_argument(format!("Invalid VM ID: {e}")))?;
-        let vm_id_str = vm_id.to_string();
+        let name = req.name.filter(|n| !n.is_empty());
+        if let Some(name) = &name {
+            validate_vm_name(name)?;
+            if self.vm_registry.name_in_use(name).await {
+                return Err(Status::already_exists(format!(
+                    "A VM named {name} already exists"
+                )));
+            }
+            span.record("name", name.as_str());
+        }
 
-        clawpot_event!(
-            self.event_store,
-            "vm.delete.started",
-            "vm",
-            vm_id = vm_id_str,
-            {}
-        );
+        let snapshot = SnapshotPaths {
+            snapshot_path: PathBuf::from(&req.snapshot_path),
+            mem_path: PathBuf::from(&req.mem_path),
+        };
+        for path in [&snapshot.snapshot_path, &snapshot.mem_path] {
+            if !path.is_file() {
+                return Err(Status::invalid_argument(format!(
+                    "Snapshot file {} does not exist on the server",
+                    path.display()
+                )));
+            }
+        }
 
-        let mut entry = self
-            .vm_registry
-            .remove(&vm_id)
+        let vm_id = Uuid::new_v4();
+        let vm_id_str = vm_id.to_string();
+        span.record("vm_id", vm_id_str.as_str());
+
+        // The memory file holds all of guest memory, so its size is the VM's
+        let mem_size_mib = tokio::fs::metadata(&snapshot.mem_path)
             .await
-            .map_err(|e| Status::not_found(format!("VM not found: {e}")))?;
+            .map(|meta| meta.len() / (1024 * 1024))
+            .map_err(|e| {
+                Status::invalid_argument(format!(
+                    "Failed to read snapshot memory file {}: {e}",
+                    snapshot.mem_path.display()
+                ))
+            })?;
+        let host_available_mib = host::available_memory_mib()
+            .inspect_err(|e| warn!("Skipping host memory check: {e:#}"))
+            .ok();
+        clawpot_event!(self.event_store, "vm.restore.started", "vm", vm_id = vm_id_str, {
+            "vm_name": name,
+            "snapshot_path": req.snapshot_path,
+            "mem_path": req.mem_path,
+            "mem_size_mib": mem_size_mib,
+            "host_available_mib": host_available_mib
+        });
+        self.check_host_memory(
+            &self.event_store,
+            "restore",
+            &vm_id_str,
+            host_available_mib,
+            mem_size_mib,
+        )?;
 
-        if let Err(e) = entry.manager.stop().await {
-            error!("Failed to stop VM {}: {}", vm_id, e);
+        let (ip_address, gateway, prefix) = {
+            let mut allocator = self.ip_allocator.lock().await;
+            let ip_address = allocator.allocate().map_err(|e| {
+                clawpot_event!(self.event_store, "vm.restore.failed", "vm", vm_id = vm_id_str, {
+                    "error": e.to_string(),
+                    "step": "ip_allocation"
+                });
+                Status::resource_exhausted(format!("No available IP addresses: {e}"))
+            })?;
+            (ip_address, allocator.gateway(), allocator.prefix())
+        };
+        span.record("ip_address", ip_address.to_string().as_str());
+
+        // Create TAP device name (max 15 chars for Linux interface names)
+        let uuid_short = &vm_id.simple().to_string()[..11];
+        let tap_name = format!("tap-{uuid_short}");
+
+        if let Err(e) = self.network_manager.create_tap(&tap_name, ip_address).await {
+            let _ = self.ip_allocator.lock().await.release(ip_address);
+            clawpot_event!(self.event_store, "vm.restore.failed", "vm", vm_id = vm_id_str, {
+                "error": e.to_string(),
+                "step": "tap_creation"
+            });
+            return Err(Status::internal(format!(
+                "Failed to create TAP device: {e}"
+            )));
         }
 
-        if let Err(e) = self
-            .network_manager
-            .delete_tap(&entry.tap_name, entry.ip_address)
-            .await
-        {
-            error!("Failed to delete TAP device: {}", e);
+        // The restored VM gets its own TAP device, vsock socket and (in
+        // copy-on-write mode) root drive, so it shares nothing writable with
+        // the VM the snapshot was taken from
+        let socket_path = PathBuf::from(format!("/tmp/fc-{}.sock", vm_id.simple()));
+        let vsock_uds_path = format!("/tmp/fc-{}-vsock.sock", vm_id.simple());
+        let overrides = RestoreOverrides {
+            tap_device: Some(tap_name.clone()),
+            vsock_uds_path: Some(vsock_uds_path.clone()),
+            rootfs_copy_key: (self.config.rootfs_mode == RootfsMode::CopyOnWrite)
+                .then(|| vm_id.simple().to_string()),
+        };
+        let mut manager = VmManager::new(socket_path.clone());
+        if let Err(e) = manager.restore(&snapshot, &overrides).await {
+            drop(manager);
+            self.release_unregistered_vm(&vm_id, &tap_name, ip_address, &vsock_uds_path)
+                .await;
+            clawpot_event!(self.event_store, "vm.restore.failed", "vm", vm_id = vm_id_str, {
+                "error": format!("{e:#}"),
+                "step": "snapshot_load"
+            });
+            return Err(Status::internal(format!("Failed to restore VM: {e:#}")));
         }
 
-        if let Err(e) = self.ip_allocator.lock().await.release(entry.ip_address) {
-            error!("Failed to release IP address: {}", e);
+        // The guest still has the snapshot's address, which may belong to
+        // the original VM; move it to the one allocated here
+        if let Err(e) = readdress_guest(&vsock_uds_path, ip_address, gateway, prefix).await {
+            drop(manager);
+            self.release_unregistered_vm(&vm_id, &tap_name, ip_address, &vsock_uds_path)
+                .await;
+            clawpot_event!(self.event_store, "vm.restore.failed", "vm", vm_id = vm_id_str, {
+                "error": format!("{e:#}"),
+                "step": "guest_readdress"
+            });
+            return Err(Status::internal(format!(
+                "Failed to move restored VM to {ip_address}: {e:#}"
+            )));
         }
 
-        // Clean up vsock UDS
-        let _ = std::fs::remove_file(&entry.vsock_uds_path);
+        // Machine size and guest CID are whatever the snapshot recorded. The
+        // CID may repeat another VM's, which is harmless: the host reaches
+        // each guest through its own vsock socket.
+        let restored = match manager.vm_config().await {
+            Ok(config) => config,
+            Err(e) => {
+                error!(
+                    "Failed to read configuration of restored VM {}: {:#}",
+                    vm_id, e
+                );
+                FullVmConfig::default()
+            }
+        };
+        let (vcpu_count, mem_size_mib) = restored
+            .machine_config
+            .map_or((0, 0), |m| (m.vcpu_count, m.mem_size_mib));
+        let guest_cid = restored.vsock.map_or(0, |v| v.guest_cid);
+        let balloon = restored.balloon.is_some();
+
+        let entry = VmEntry {
+            id: vm_id,
+            manager,
+            ip_address,
+            tap_name: tap_name.clone(),
+            created_at: SystemTime::now(),
+            vcpu_count,
+            mem_size_mib,
+            vsock_uds_path: vsock_uds_path.clone(),
+            guest_cid,
+            name,
+            balloon,
+            labels: BTreeMap::new(),
+            boot_config: None,
+        };
+
+        if let Err(e) = self.vm_registry.insert(vm_id, entry).await {
+            self.release_unregistered_vm(&vm_id, &tap_name, ip_address, &vsock_uds_path)
+                .await;
+            clawpot_event!(self.event_store, "vm.restore.failed", "vm", vm_id = vm_id_str, {
+                "error": e.to_string(),
+                "step": "registry_insert"
+            });
+            return Err(Status::internal(format!("Failed to register VM: {e}")));
+        }
 
         let duration_ms = start.elapsed().as_millis() as i64;
         self.event_store.emit_with_duration(
-            "vm.delete.completed",
+            "vm.restore.completed",
             "vm",
             Some(&vm_id_str),
             None,
             duration_ms,
             Some(true),
-            &serde_json::json!({}),
+            &serde_json::json!({
+                "ip_address": ip_address.to_string(),
+                "socket_path": socket_path.to_string_lossy().to_string(),
+                "vcpu_count": vcpu_count,
+                "mem_size_mib": mem_size_mib,
+            }),
         );
 
-        Ok(Response::new(DeleteVmResponse { success: true }))
+        Ok(Response::new(RestoreVmResponse {
+            vm_id: vm_id_str,
+            ip_address: ip_address.to_string(),
+            socket_path: socket_path.to_string_lossy().to_string(),
+        }))
+    }
+
+    #[tracing::instrument(name = "grpc.PauseVM", skip_all, fields(vm_id = tracing::field::Empty))]
+    async fn pause_vm(
+        &self,
+        request: Request<PauseVmRequest>,
+    ) -> Result<Response<PauseVmResponse>, Status> {
+        let req = request.into_inner();
+        Span::current().record("vm_id", req.vm_id.as_str());
+        let vm_id = self.resolve_tenant_vm_id(&req.vm_id).await?;
+
+        self.set_paused(vm_id, true).await?;
+        Ok(Response::new(PauseVmResponse {}))
+    }
+
+    #[tracing::instrument(name = "grpc.ResumeVM", skip_all, fields(vm_id = tracing::field::Empty))]
+    async fn resume_vm(
+        &self,
+        request: Request<ResumeVmRequest>,
+    ) -> Result<Response<ResumeVmResponse>, Status> {
+        let req = request.into_inner();
+        Span::current().record("vm_id", req.vm_id.as_str());
+        let vm_id = self.resolve_tenant_vm_id(&req.vm_id).await?;
+
+        self.set_paused(vm_id, false).await?;
+        Ok(Response::new(ResumeVmResponse {}))
+    }
+
+    #[tracing::instrument(name = "grpc.GetVmStatus", skip_all, fields(vm_id = tracing::field::Empty))]
+    async fn get_vm_status(
+        &self,
+        request: Request<GetVmStatusRequest>,
+    ) -> Result<Response<GetVmStatusResponse>, Status> {
+        let req = request.into_inner();
+        Span::current().record("vm_id", req.vm_id.as_str());
+        let vm_id = self.resolve_vm_id(&req.vm_id).await?;
+
+        let (lifecycle_state, instance_state, created_at) = self
+            .vm_registry
+            .instance_status(&vm_id)
+            .await
+            .ok_or_else(|| Status::not_found(format!("VM {vm_id} not found")))?;
+
+        // A Firecracker process that no longer answers has crashed or been killed
+        let (state, raw_state) = match instance_state {
+            Ok(raw) => (firecracker_state(&raw), raw),
+            Err(e) => {
+                warn!(vm_id = %vm_id, "Firecracker API did not answer: {e:#}");
+                (ProtoVmState::Error, String::new())
+            }
+        };
+
+        Ok(Response::new(GetVmStatusResponse {
+            state: state as i32,
+            lifecycle_state: proto_state(lifecycle_state) as i32,
+            firecracker_state: raw_state,
+            uptime_secs: created_at.elapsed().unwrap_or_default().as_secs(),
+        }))
+    }
+
+    #[tracing::instrument(name = "grpc.RebootVM", skip_all, fields(vm_id = tracing::field::Empty))]
+    async fn reboot_vm(
+        &self,
+        request: Request<RebootVmRequest>,
+    ) -> Result<Response<RebootVmResponse>, Status> {
+        let start = Instant::now();
+        let req = request.into_inner();
+        Span::current().record("vm_id", req.vm_id.as_str());
+        let vm_id = self.resolve_tenant_vm_id(&req.vm_id).await?;
+        let vm_id_str = vm_id.to_string();
+
+        if self.vm_registry.state(&vm_id).await.is_none() {
+            return Err(Status::not_found(format!("VM {vm_id} not found")));
+        }
+        let (mut manager, config) = self
+            .vm_registry
+            .begin_restart(&vm_id)
+            .await
+            .map_err(|e| Status::failed_precondition(format!("Cannot reboot VM: {e:#}")))?;
+
+        clawpot_event!(
+            self.event_store,
+            "vm.reboot.started",
+            "vm",
+            vm_id = vm_id_str,
+            {}
+        );
+
+        let vsock_uds_path = config.vsock_uds_path.clone();
+        let result = manager.restart(config).await;
+        if result.is_err() {
+            manager.mark_failed();
+        }
+        if let Some(mut orphaned) = self.vm_registry.finish_restart(&vm_id, manager).await {
+            // Deleted while rebooting; its TAP and IP are already released
+            let _ = orphaned.stop().await;
+            return Err(Status::aborted("VM was deleted while rebooting"));
+        }
+        if let Err(e) = result {
+            clawpot_event!(self.event_store, "vm.reboot.failed", "vm", vm_id = vm_id_str, {
+                "error": format!("{e:#}")
+            });
+            return Err(Status::internal(format!("Failed to reboot VM: {e:#}")));
+        }
+
+        let agent_ready = match vsock_uds_path {
+            Some(path) => agent::client::AgentClient::wait_ready(&path, Duration::from_secs(30))
+                .await
+                .is_ok(),
+            None => false,
+        };
+
+        self.event_store.emit_with_duration(
+            "vm.reboot",
+            "vm",
+            Some(&vm_id_str),
+            None,
+            start.elapsed().as_millis() as i64,
+            Some(true),
+            &serde_json::json!({ "agent_ready": agent_ready }),
+        );
+
+        Ok(Response::new(RebootVmResponse { agent_ready }))
+    }
+
+    #[tracing::instrument(name = "grpc.UpdateVMMemory", skip_all, fields(vm_id = tracing::field::Empty))]
+    async fn update_vm_memory(
+        &self,
+        request: Request<UpdateVmMemoryRequest>,
+    ) -> Result<Response<UpdateVmMemoryResponse>, Status> {
+        let req = request.into_inner();
+        Span::current().record("vm_id", req.vm_id.as_str());
+        let vm_id = self.resolve_tenant_vm_id(&req.vm_id).await?;
+        let vm_id_str = vm_id.to_string();
+
+        let Some((mem_size_mib, has_balloon)) = self.vm_registry.memory(&vm_id).await else {
+            return Err(Status::not_found(format!("VM {vm_id_str} not found")));
+        };
+        if !has_balloon {
+            return Err(Status::failed_precondition(format!(
+                "VM {vm_id_str} has no memory balloon (create it with a balloon size)"
+            )));
+        }
+        if req.balloon_mib > mem_size_mib {
+            return Err(Status::invalid_argument(format!(
+                "Balloon size {} MiB exceeds the VM's {mem_size_mib} MiB of memory",
+                req.balloon_mib
+            )));
+        }
+        match self.vm_registry.state(&vm_id).await {
+            Some(VmState::Running | VmState::Paused) => {}
+            Some(state) => {
+                return Err(Status::failed_precondition(format!(
+                    "Cannot resize the balloon of VM {vm_id_str} in state {state}"
+                )));
+            }
+            None => return Err(Status::not_found(format!("VM {vm_id_str} not found"))),
+        }
+
+        let start = Instant::now();
+        let result = self
+            .vm_registry
+            .update_balloon(&vm_id, req.balloon_mib)
+            .await;
+        self.event_store.emit_with_duration(
+            "vm.balloon.updated",
+            "vm",
+            Some(&vm_id_str),
+            None,
+            start.elapsed().as_millis() as i64,
+            Some(result.is_ok()),
+            &serde_json::json!({
+                "balloon_mib": req.balloon_mib,
+                "mem_size_mib": mem_size_mib,
+                "error": result.as_ref().err().map(|e| format!("{e:#}")),
+            }),
+        );
+        result.map_err(|e| Status::internal(format!("Failed to resize balloon: {e:#}")))?;
+        Ok(Response::new(UpdateVmMemoryResponse {}))
+    }
+
+    #[tracing::instrument(name = "grpc.DeleteVM", skip_all, fields(vm_id = tracing::field::Empty))]
+    async fn delete_vm(
+        &self,
+        request: Request<DeleteVmRequest>,
+    ) -> Result<Response<DeleteVmResponse>, Status> {
+        let start = Instant::now();
+        let req = request.into_inner();
+        Span::current().record("vm_id", req.vm_id.as_str());
+
+        let vm_id = self.resolve_vm_id(&req.vm_id).await?;
+        let vm_id_str = vm_id.to_string();
+        let events = self
+            .event_store
+            .with_correlation(Uuid::new_v4().to_string());
+
+        clawpot_event!(events, "vm.delete.started", "vm", vm_id = vm_id_str, {});
+
+        let mut entry = self
+            .vm_registry
+            .remove(&vm_id)
+            .await
+            .map_err(|e| Status::not_found(format!("VM not found: {e}")))?;
+        self.pool.remove(&vm_id);
+        self.token_budget.forget(&vm_id_str);
+
+        if let Err(e) = entry.manager.stop().await {
+            error!("Failed to stop VM {}: {}", vm_id, e);
+        }
+
+        if let Err(e) = self
+            .network_manager
+            .delete_tap(&entry.tap_name, entry.ip_address)
+            .await
+        {
+            error!("Failed to delete TAP device: {}", e);
+        }
+
+        if let Err(e) = self.ip_allocator.lock().await.release(entry.ip_address) {
+            error!("Failed to release IP address: {}", e);
+        }
+
+        // Clean up vsock UDS, serial log and any private rootfs copy
+        let _ = std::fs::remove_file(&entry.vsock_uds_path);
+        if let Some(serial_log) = entry.manager.serial_log_path() {
+            let _ = std::fs::remove_file(serial_log);
+        }
+        self.remove_rootfs_copy(&vm_id);
+
+        let leaked = self.verify_cleanup(&entry).await;
+        let success = !leaked.iter().any(|l| l.critical);
+        if !leaked.is_empty() {
+            error!("VM {} left resources behind after delete", vm_id);
+            clawpot_event!(events, "vm.delete.leaked", "vm", vm_id = vm_id_str, {
+                "critical": !success,
+                "leaked": leaked
+            });
+        }
+
+        let duration_ms = start.elapsed().as_millis() as i64;
+        events.emit_with_duration(
+            "vm.delete.completed",
+            "vm",
+            Some(&vm_id_str),
+            None,
+            duration_ms,
+            Some(success),
+            &serde_json::json!({ "leaked_count": leaked.len() }),
+        );
+
+        Ok(Response::new(DeleteVmResponse {
+            success,
+            leaked: leaked
+                .iter()
+                .map(|l| format!("{} {}", l.resource, l.name))
+                .collect(),
+        }))
     }
 
     #[tracing::instrument(name = "grpc.ListVMs", skip_all, fields(vm_count = tracing::field::Empty))]
     async fn list_v_ms(
         &self,
-        _request: Request<ListVmsRequest>,
+        request: Request<ListVmsRequest>,
     ) -> Result<Response<ListVmsResponse>, Status> {
-        let vms_list = self.vm_registry.list().await;
+        let selector: BTreeMap<String, String> =
+            request.into_inner().label_selector.into_iter().collect();
+        let vms_list = self.vm_registry.list_filtered(&selector).await;
 
+        // Idle pooled VMs are only listed when asked for by their label
+        let show_idle = selector.contains_key(POOL_LABEL);
         let vms: Vec<VmInfo> = vms_list
             .into_iter()
-            .map(
-                |(id, ip_address, _tap_name, vcpu_count, mem_size_mib, created_at)| {
-                    let created_timestamp = created_at
-                        .duration_since(UNIX_EPOCH)
-                        .unwrap_or_default()
-                        .as_secs() as i64;
-
-                    VmInfo {
-                        vm_id: id.to_string(),
-                        state: ProtoVmState::Running as i32,
-                        ip_address: ip_address.to_string(),
-                        vcpu_count: u32::from(vcpu_count),
-                        mem_size_mib,
-                        created_at: created_timestamp,
-                        socket_path: format!("/tmp/fc-{}.sock", id.simple()),
-                    }
-                },
-            )
+            .filter(|vm| show_idle || !self.pool.is_idle(&vm.0))
+            .map(vm_info)
             .collect();
 
         Span::current().record("vm_count", vms.len());
 
-        Ok(Response::new(ListVmsResponse { vms }))
+        let pool = self.ip_allocator.lock().await.stats();
+
+        Ok(Response::new(ListVmsResponse {
+            vms,
+            ip_pool: Some(IpPoolStatus {
+                total: pool.total as u32,
+                allocated: pool.allocated as u32,
+                available: pool.available as u32,
+            }),
+        }))
+    }
+
+    #[tracing::instrument(name = "grpc.DescribeVM", skip_all, fields(vm_id = tracing::field::Empty))]
+    async fn describe_vm(
+        &self,
+        request: Request<DescribeVmRequest>,
+    ) -> Result<Response<DescribeVmResponse>, Status> {
+        let req = request.into_inner();
+        Span::current().record("vm_id", req.vm_id.as_str());
+        let vm_id = self.resolve_vm_id(&req.vm_id).await?;
+        let details = self
+            .vm_registry
+            .details(&vm_id)
+            .await
+            .ok_or_else(|| Status::not_found(format!("VM {vm_id} not found")))?;
+
+        // Events are read back from the database, so any still queued for
+        // the writer are missing
+        let limit = req
+            .event_limit
+            .unwrap_or(DESCRIBE_EVENTS)
+            .min(MAX_DESCRIBE_EVENTS);
+        let events_db = self.config.events_db.clone();
+        let filters = EventFilters {
+            vm_id: Some(vm_id.to_string()),
+            limit: Some(i64::from(limit)),
+            newest_first: true,
+            ..EventFilters::default()
+        };
+        let events = tokio::task::spawn_blocking(move || {
+            let conn = EventStore::open_readonly(&events_db)?;
+            EventStore::query_events(&conn, &filters)
+        })
+        .await
+        .map_err(anyhow::Error::from)
+        .and_then(|events| events)
+        .unwrap_or_else(|e| {
+            warn!(vm_id = %vm_id, "Failed to read recent events: {e:#}");
+            Vec::new()
+        });
+
+        let boot_config = details.boot_config.as_ref();
+        let boot_path =
+            |path: Option<&PathBuf>| path.map(|p| p.display().to_string()).unwrap_or_default();
+        Ok(Response::new(DescribeVmResponse {
+            tap_name: details.listing.2.clone(),
+            vm: Some(vm_info(details.listing)),
+            vsock_path: details.vsock_uds_path,
+            guest_cid: details.guest_cid,
+            balloon: details.balloon,
+            kernel_path: boot_path(boot_config.map(|c| &c.kernel_path)),
+            rootfs_path: boot_path(boot_config.map(|c| &c.rootfs_path)),
+            boot_args: boot_config
+                .map(|c| c.boot_args().to_string())
+                .unwrap_or_default(),
+            last_activity: events
+                .first()
+                .map(|e| e.timestamp.clone())
+                .unwrap_or_default(),
+            recent_events: events
+                .into_iter()
+                .map(|e| VmEvent {
+                    timestamp: e.timestamp,
+                    event_type: e.event_type,
+                    correlation_id: e.correlation_id.unwrap_or_default(),
+                    duration_ms: e.duration_ms,
+                    success: e.success,
+                    data: e.data.to_string(),
+                })
+                .collect(),
+        }))
+    }
+
+    #[tracing::instrument(name = "grpc.ResetVM", skip_all, fields(vm_id = tracing::field::Empty))]
+    async fn reset_vm(
+        &self,
+        request: Request<ResetVmRequest>,
+    ) -> Result<Response<ResetVmResponse>, Status> {
+        let start = Instant::now();
+        let req = request.into_inner();
+        Span::current().record("vm_id", req.vm_id.as_str());
+
+        let vm_id = self.resolve_vm_id(&req.vm_id).await?;
+        // Claimed up front so concurrent resets can't both return the VM
+        if !self.pool.begin_reset(vm_id) {
+            return Err(Status::failed_precondition(format!(
+                "VM {} was not handed out from the pool, or is already being reset",
+                req.vm_id
+            )));
+        }
+        let vm_id_str = vm_id.to_string();
+
+        let mut outcome = if self.is_draining() {
+            Err("Server is draining".to_string())
+        } else {
+            self.clean_up_guest(&vm_id).await
+        };
+        if outcome.is_ok() {
+            outcome = match self.vm_registry.assign(&vm_id, None, idle_labels()).await {
+                Ok(Some(_)) => {
+                    if self.pool.give_back(vm_id) {
+                        Ok(())
+                    } else {
+                        Err("The pool is already full".to_string())
+                    }
+                }
+                Ok(None) => Err("VM is no longer running".to_string()),
+                Err(e) => Err(e.to_string()),
+            };
+        }
+        // Anything that can't go back is discarded; the pool boots a fresh
+        // replacement if it is short
+        let discarded = if outcome.is_err() {
+            self.delete_vm(Request::new(DeleteVmRequest {
+                vm_id: vm_id_str.clone(),
+            }))
+            .await
+            .map(|_| ())
+        } else {
+            Ok(())
+        };
+
+        let mut reason = outcome.err().unwrap_or_default();
+        // A failed discard is still recorded before it is returned
+        if let Err(e) = &discarded {
+            reason = format!("{reason}; failed to discard the VM: {}", e.message());
+        }
+        self.event_store.emit_with_duration(
+            "vm.pool.reset",
+            "vm",
+            Some(&vm_id_str),
+            None,
+            start.elapsed().as_millis() as i64,
+            Some(reason.is_empty()),
+            &serde_json::json!({
+                "returned_to_pool": reason.is_empty(),
+                "reason": reason,
+                "pool_idle": self.pool.idle_count(),
+            }),
+        );
+        discarded?;
+
+        Ok(Response::new(ResetVmResponse {
+            returned_to_pool: reason.is_empty(),
+            reason,
+        }))
     }
 
     #[tracing::instrument(
@@ -319,8 +1681,14 @@ impl ClawpotService for ClawpotServiceImpl {
         span.record("vm_id", req.vm_id.as_str());
         span.record("command", req.command.as_str());
 
-        let vm_id = Uuid::parse_str(&req.vm_id)
-            .map_err(|e| Status::invalid_argument(format!("Invalid VM ID: {e}")))?;
+        let vm_id = self.resolve_tenant_vm_id(&req.vm_id).await?;
+
+        // The agent can't answer while the vCPUs are stopped
+        if self.vm_registry.state(&vm_id).await == Some(VmState::Paused) {
+            return Err(Status::failed_precondition(
+                "VM is paused; resume it before running commands",
+            ));
+        }
 
         let vsock_path = self
             .vm_registry
@@ -330,24 +1698,42 @@ impl ClawpotService for ClawpotServiceImpl {
 
         let mut agent_client = agent::client::AgentClient::connect(vsock_path)
             .await
-            .map_err(|e| Status::unavailable(format!("Failed to connect to agent: {e}")))?;
+            .map_err(|e| Status::unavailable(format!("Failed to connect to agent: {e}")))?
+            .with_max_message_size(self.config.grpc_max_message_size);
 
         let agent_req = clawpot_common::agent_proto::ExecRequest {
             command: req.command.clone(),
             args: req.args.clone(),
             env: req.env,
             working_dir: req.working_dir,
+            stdin: req.stdin,
+            max_duration_secs: None,
+            max_output_bytes: None,
         };
+        let stdin_len = agent_req.stdin.as_ref().map(Vec::len);
 
+        let vm_id_str = vm_id.to_string();
         let agent_resp = agent_client
             .exec(agent_req)
             .await
-            .map_err(|e| Status::internal(format!("Agent exec failed: {e}")))?;
+            .map_err(|e| self.agent_exec_status(&e, &vm_id_str, &req.command))?;
 
         span.record("exit_code", agent_resp.exit_code);
 
+        let response = ExecVmResponse {
+            exit_code: agent_resp.exit_code,
+            stdout: agent_resp.stdout,
+            stderr: agent_resp.stderr,
+        };
+
+        if let Err(status) =
+            grpc::ensure_message_fits(&response, self.config.grpc_max_message_size, "Exec output")
+        {
+            self.emit_exec_too_large(&vm_id_str, &req.command, status.message());
+            return Err(status);
+        }
+
         let duration_ms = start.elapsed().as_millis() as i64;
-        let vm_id_str = vm_id.to_string();
         self.event_store.emit_with_duration(
             "vm.exec",
             "vm",
@@ -358,30 +1744,404 @@ impl ClawpotService for ClawpotServiceImpl {
             &serde_json::json!({
                 "command": req.command,
                 "args": req.args,
-                "exit_code": agent_resp.exit_code,
-                "stdout_len": agent_resp.stdout.len(),
-                "stderr_len": agent_resp.stderr.len(),
+                "exit_code": response.exit_code,
+                "stdin_len": stdin_len,
+                "stdout_len": response.stdout.len(),
+                "stderr_len": response.stderr.len(),
             }),
         );
 
-        Ok(Response::new(ExecVmResponse {
-            exit_code: agent_resp.exit_code,
-            stdout: agent_resp.stdout,
-            stderr: agent_resp.stderr,
-        }))
+        Ok(Response::new(response))
     }
 
-    type ExecVMStreamStream =
-        tokio_stream::wrappers::ReceiverStream<Result<ExecVmStreamOutput, Status>>;
+    type ExecVMStreamStream = ReceiverStream<Result<ExecVmStreamOutput, Status>>;
 
-    #[tracing::instrument(name = "grpc.ExecVMStream", skip_all)]
+    #[tracing::instrument(
+        name = "grpc.ExecVMStream",
+        skip_all,
+        fields(vm_id = tracing::field::Empty, command = tracing::field::Empty)
+    )]
     async fn exec_vm_stream(
         &self,
-        _request: Request<tonic::Streaming<ExecVmStreamInput>>,
+        request: Request<tonic::Streaming<ExecVmStreamInput>>,
     ) -> Result<Response<Self::ExecVMStreamStream>, Status> {
-        // Streaming exec is more complex — implement in a follow-up
-        Err(Status::unimplemented(
-            "ExecVMStream not yet implemented. Use ExecVM for now.",
-        ))
+        let start = Instant::now();
+        let mut inbound = request.into_inner();
+        let Some(exec_vm_stream_input::Input::Start(req)) =
+            inbound.message().await?.and_then(|msg| msg.input)
+        else {
+            return Err(Status::invalid_argument(
+                "First message must be a start command with vm_id and command",
+            ));
+        };
+        let span = Span::current();
+        span.record("vm_id", req.vm_id.as_str());
+        span.record("command", req.command.as_str());
+
+        let vm_id = self.resolve_tenant_vm_id(&req.vm_id).await?;
+
+        // The agent can't answer while the vCPUs are stopped
+        if self.vm_registry.state(&vm_id).await == Some(VmState::Paused) {
+            return Err(Status::failed_precondition(
+                "VM is paused; resume it before running commands",
+            ));
+        }
+
+        let vsock_path = self
+            .vm_registry
+            .get_vsock_path(&vm_id)
+            .await
+            .map_err(|e| Status::not_found(format!("VM not found: {e}")))?;
+
+        let mut agent_client = agent::client::AgentClient::connect(vsock_path)
+            .await
+            .map_err(|e| Status::unavailable(format!("Failed to connect to agent: {e}")))?
+            .with_max_message_size(self.config.grpc_max_message_size);
+
+        let (agent_tx, agent_rx) = mpsc::channel(EXEC_STREAM_BUFFER);
+        let agent_start = ExecStreamInput {
+            input: Some(exec_stream_input::Input::Start(
+                clawpot_common::agent_proto::ExecRequest {
+                    command: req.command.clone(),
+                    args: req.args.clone(),
+                    env: req.env,
+                    working_dir: req.working_dir,
+                    stdin: None,
+                    max_duration_secs: req.max_duration_secs,
+                    max_output_bytes: req.max_output_bytes,
+                },
+            )),
+        };
+        // The channel is empty and its receiver alive, so this can't fail
+        let _ = agent_tx.send(agent_start).await;
+
+        let vm_id_str = vm_id.to_string();
+        let mut outbound = agent_client
+            .exec_stream(ReceiverStream::new(agent_rx))
+            .await
+            .map_err(|e| self.agent_exec_status(&e, &vm_id_str, &req.command))?;
+
+        // Client -> agent: stdin frames, until either side goes away
+        tokio::spawn(async move {
+            while let Ok(Some(msg)) = inbound.message().await {
+                let input = match msg.input {
+                    Some(exec_vm_stream_input::Input::StdinData(data)) => {
+                        exec_stream_input::Input::StdinData(data)
+                    }
+                    Some(exec_vm_stream_input::Input::CloseStdin(close)) => {
+                        exec_stream_input::Input::CloseStdin(close)
+                    }
+                    // The command is already running
+                    Some(exec_vm_stream_input::Input::Start(_)) | None => continue,
+                };
+                if agent_tx
+                    .send(ExecStreamInput { input: Some(input) })
+                    .await
+                    .is_err()
+                {
+                    break;
+                }
+            }
+        });
+
+        // Agent -> client: the session ID, output frames, then the exit code.
+        // Output is copied to clients attached with `AttachExec`.
+        let (tx, rx) = mpsc::channel(EXEC_STREAM_BUFFER);
+        let (session_id, observers) = self.exec_sessions.open(vm_id, &req.command);
+        let _ = tx
+            .send(Ok(ExecVmStreamOutput {
+                output: Some(exec_vm_stream_output::Output::SessionId(
+                    session_id.to_string(),
+                )),
+            }))
+            .await;
+        let exec_sessions = Arc::clone(&self.exec_sessions);
+        let event_store = self.event_store.clone();
+        tokio::spawn(async move {
+            let (mut stdout_len, mut stderr_len, mut exit_code) = (0, 0, None);
+            loop {
+                let output = match outbound.message().await {
+                    Ok(Some(msg)) => match msg.output {
+                        Some(output) => output,
+                        None => continue,
+                    },
+                    Ok(None) => break,
+                    Err(status) => {
+                        let _ = tx.send(Err(status)).await;
+                        break;
+                    }
+                };
+                let output = match output {
+                    exec_stream_output::Output::StdoutData(data) => {
+                        stdout_len += data.len();
+                        exec_vm_stream_output::Output::StdoutData(data)
+                    }
+                    exec_stream_output::Output::StderrData(data) => {
+                        stderr_len += data.len();
+                        exec_vm_stream_output::Output::StderrData(data)
+                    }
+                    exec_stream_output::Output::ExitCode(code) => {
+                        exit_code = Some(code);
+                        exec_vm_stream_output::Output::ExitCode(code)
+                    }
+                    exec_stream_output::Output::StdinError(reason) => {
+                        exec_vm_stream_output::Output::StdinError(reason)
+                    }
+                };
+                let msg = ExecVmStreamOutput {
+                    output: Some(output),
+                };
+                // Fails only while nobody is attached
+                let _ = observers.send(msg.clone());
+                if tx.send(Ok(msg)).await.is_err() {
+                    // Client hung up; dropping `outbound` cancels the agent stream
+                    break;
+                }
+            }
+            exec_sessions.close(&session_id);
+            drop(observers);
+
+            event_store.emit_with_duration(
+                "vm.exec",
+                "vm",
+                Some(&vm_id_str),
+                None,
+                start.elapsed().as_millis() as i64,
+                Some(exit_code == Some(0)),
+                &serde_json::json!({
+                    "command": req.command,
+                    "args": req.args,
+                    "exit_code": exit_code,
+                    "stdout_len": stdout_len,
+                    "stderr_len": stderr_len,
+                    "streaming": true,
+                    "session_id": session_id.to_string(),
+                }),
+            );
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    type AttachExecStream = ReceiverStream<Result<ExecVmStreamOutput, Status>>;
+
+    #[tracing::instrument(name = "grpc.AttachExec", skip_all, fields(session_id = tracing::field::Empty))]
+    async fn attach_exec(
+        &self,
+        request: Request<AttachExecRequest>,
+    ) -> Result<Response<Self::AttachExecStream>, Status> {
+        let req = request.into_inner();
+        Span::current().record("session_id", req.session_id.as_str());
+        let session_id = Uuid::parse_str(req.session_id.trim())
+            .map_err(|_| Status::invalid_argument("Invalid exec session ID"))?;
+        let mut observer = self
+            .exec_sessions
+            .attach(&session_id)
+            .ok_or_else(|| Status::not_found("No running exec with that session ID"))?;
+
+        self.event_store.emit(
+            "vm.exec.attached",
+            "vm",
+            Some(&observer.vm_id.to_string()),
+            None,
+            &serde_json::json!({
+                "session_id": session_id.to_string(),
+                "command": observer.command,
+            }),
+        );
+
+        let (tx, rx) = mpsc::channel(EXEC_STREAM_BUFFER);
+        tokio::spawn(async move {
+            loop {
+                let msg = match observer.output.recv().await {
+                    Ok(msg) => msg,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => ExecVmStreamOutput {
+                        output: Some(exec_vm_stream_output::Output::StderrData(
+                            format!("[clawpot: {skipped} output frames dropped]\n").into_bytes(),
+                        )),
+                    },
+                    // The exec finished
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if tx.send(Ok(msg)).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    type StreamConsoleStream = ReceiverStream<Result<ConsoleLine, Status>>;
+
+    #[tracing::instrument(name = "grpc.StreamConsole", skip_all, fields(vm_id = tracing::field::Empty))]
+    async fn stream_console(
+        &self,
+        request: Request<StreamConsoleRequest>,
+    ) -> Result<Response<Self::StreamConsoleStream>, Status> {
+        let req = request.into_inner();
+        Span::current().record("vm_id", req.vm_id.as_str());
+        let vm_id = self.resolve_tenant_vm_id(&req.vm_id).await?;
+
+        let mut console = self
+            .vm_registry
+            .console_stream(&vm_id)
+            .await
+            .map_err(|e| Status::not_found(format!("VM not found: {e}")))?
+            .ok_or_else(|| {
+                Status::failed_precondition(
+                    "VM has no live console (not running, or its output goes to a serial log)",
+                )
+            })?;
+
+        let (tx, rx) = mpsc::channel(EXEC_STREAM_BUFFER);
+        tokio::spawn(async move {
+            loop {
+                let line = match console.recv().await {
+                    Ok(line) => line,
+                    Err(broadcast::error::RecvError::Lagged(skipped)) => {
+                        format!("[clawpot: {skipped} console lines dropped]")
+                    }
+                    // The VM was stopped or deleted
+                    Err(broadcast::error::RecvError::Closed) => break,
+                };
+                if tx.send(Ok(ConsoleLine { line })).await.is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(ReceiverStream::new(rx)))
+    }
+
+    #[tracing::instrument(name = "grpc.ListOrphans", skip_all, fields(orphan_count = tracing::field::Empty))]
+    async fn list_orphans(
+        &self,
+        _request: Request<ListOrphansRequest>,
+    ) -> Result<Response<ListOrphansResponse>, Status> {
+        let found = orphans::find_orphans(&self.vm_registry, &self.network_manager)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to scan for orphans: {e:#}")))?;
+
+        Span::current().record("orphan_count", found.len());
+
+        Ok(Response::new(ListOrphansResponse {
+            orphans: found.iter().map(OrphanResource::from).collect(),
+        }))
+    }
+
+    #[tracing::instrument(name = "grpc.CleanOrphans", skip_all)]
+    async fn clean_orphans(
+        &self,
+        _request: Request<CleanOrphansRequest>,
+    ) -> Result<Response<CleanOrphansResponse>, Status> {
+        let found = orphans::find_orphans(&self.vm_registry, &self.network_manager)
+            .await
+            .map_err(|e| Status::internal(format!("Failed to scan for orphans: {e:#}")))?;
+
+        let mut cleaned = Vec::new();
+        let mut failed = Vec::new();
+        for orphan in &found {
+            match orphans::clean_orphan(&self.network_manager, orphan).await {
+                Ok(()) => {
+                    clawpot_event!(self.event_store, "server.orphan.cleaned", "server", {
+                        "name": orphan.name,
+                        "kind": format!("{:?}", orphan.kind)
+                    });
+                    cleaned.push(OrphanResource::from(orphan));
+                }
+                Err(e) => {
+                    error!("Failed to clean orphan {}: {:#}", orphan.name, e);
+                    clawpot_event!(self.event_store, "server.orphan.clean_failed", "server", {
+                        "name": orphan.name,
+                        "kind": format!("{:?}", orphan.kind),
+                        "error": format!("{e:#}")
+                    });
+                    failed.push(OrphanResource::from(orphan));
+                }
+            }
+        }
+
+        Ok(Response::new(CleanOrphansResponse { cleaned, failed }))
+    }
+
+    #[tracing::instrument(name = "grpc.ListNetRules", skip_all)]
+    async fn list_net_rules(
+        &self,
+        _request: Request<ListNetRulesRequest>,
+    ) -> Result<Response<ListNetRulesResponse>, Status> {
+        let installed = iptables::list_clawpot_rules(self.network_manager.bridge_name())
+            .map_err(|e| Status::internal(format!("Failed to list iptables rules: {e:#}")))?;
+
+        Ok(Response::new(ListNetRulesResponse {
+            rules: installed
+                .into_iter()
+                .map(|r| NetRule {
+                    table: r.table.to_string(),
+                    chain: r.chain.to_string(),
+                    rule: r.rule,
+                })
+                .collect(),
+        }))
+    }
+
+    async fn get_server_info(
+        &self,
+        _request: Request<GetServerInfoRequest>,
+    ) -> Result<Response<GetServerInfoResponse>, Status> {
+        let body_store_error = self.body_store.degraded();
+        let body_store_usage = self.body_store.usage();
+        Ok(Response::new(GetServerInfoResponse {
+            version: env!("CARGO_PKG_VERSION").to_string(),
+            server_id: self.server_id.clone(),
+            session_id: self.session_id.clone(),
+            body_store_degraded: body_store_error.is_some(),
+            body_store_error: body_store_error.unwrap_or_default().to_string(),
+            body_store_bytes: body_store_usage.bytes,
+            body_store_files: body_store_usage.files,
+            config: self.config.summary().into_iter().collect(),
+            draining: self.draining.load(Ordering::Relaxed),
+        }))
+    }
+
+    #[tracing::instrument(name = "grpc.SetDrain", skip_all, fields(enabled = request.get_ref().enabled))]
+    async fn set_drain(
+        &self,
+        request: Request<SetDrainRequest>,
+    ) -> Result<Response<SetDrainResponse>, Status> {
+        let enabled = request.into_inner().enabled;
+        let was_draining = self.draining.swap(enabled, Ordering::Relaxed);
+        if enabled {
+            // Idle pooled VMs have no tenant to wait for
+            for vm_id in self.pool.take_idle() {
+                if let Err(status) = self
+                    .delete_vm(Request::new(DeleteVmRequest {
+                        vm_id: vm_id.to_string(),
+                    }))
+                    .await
+                {
+                    warn!(
+                        "Failed to delete idle pooled VM {vm_id}: {}",
+                        status.message()
+                    );
+                }
+            }
+        } else if was_draining {
+            self.pool.refill();
+        }
+        let vm_count = self.vm_registry.count().await as u32;
+
+        if was_draining != enabled {
+            let event = if enabled {
+                "server.drain.started"
+            } else {
+                "server.drain.stopped"
+            };
+            clawpot_event!(self.event_store, event, "server", {
+                "vm_count": vm_count
+            });
+        }
+
+        Ok(Response::new(SetDrainResponse { vm_count }))
     }
 }
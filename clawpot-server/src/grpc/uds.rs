@@ -0,0 +1,64 @@
+use anyhow::{Context, Result};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
+use tokio::net::UnixListener;
+use tokio_stream::wrappers::UnixListenerStream;
+use tracing::warn;
+
+/// Permissions of the gRPC socket: only root and members of the socket's
+/// group may connect
+const SOCKET_MODE: u32 = 0o660;
+
+/// Bind the gRPC Unix socket at `path`, replacing a stale socket left
+/// behind by a previous run.
+pub fn bind(path: &Path) -> Result<UnixListenerStream> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+    }
+    match std::fs::remove_file(path) {
+        Ok(()) => warn!("Removed stale gRPC socket {}", path.display()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+        Err(e) => {
+            return Err(e)
+                .with_context(|| format!("Failed to remove stale socket {}", path.display()))
+        }
+    }
+    let listener = UnixListener::bind(path)
+        .with_context(|| format!("Failed to bind gRPC socket {}", path.display()))?;
+    std::fs::set_permissions(path, std::fs::Permissions::from_mode(SOCKET_MODE))
+        .with_context(|| format!("Failed to set permissions on {}", path.display()))?;
+    Ok(UnixListenerStream::new(listener))
+}
+
+/// Remove the gRPC socket on shutdown.
+pub fn remove(path: &Path) {
+    if let Err(e) = std::fs::remove_file(path) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            warn!("Failed to remove gRPC socket {}: {}", path.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_bind_replaces_stale_socket() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("run/clawpot.sock");
+
+        drop(bind(&path).unwrap());
+        // The first listener's socket file is still there, as after a crash
+        assert!(path.exists());
+        let _listener = bind(&path).unwrap();
+
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, SOCKET_MODE);
+        tokio::net::UnixStream::connect(&path).await.unwrap();
+
+        remove(&path);
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,63 @@
+use anyhow::{Context, Result};
+use std::path::Path;
+use tracing::info;
+use uuid::Uuid;
+
+/// Load the persistent server ID from `path`, generating and saving one on first run.
+///
+/// Unlike the per-run session ID, the server ID is stable across restarts so a
+/// host's sessions can be grouped together in the events database.
+pub fn load_or_create_server_id(path: &Path) -> Result<String> {
+    if path.exists() {
+        let raw = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read server ID from {}", path.display()))?;
+        let id = raw.trim();
+        if Uuid::parse_str(id).is_ok() {
+            return Ok(id.to_string());
+        }
+        info!(
+            "Server ID file {} is invalid, generating a new one",
+            path.display()
+        );
+    }
+
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)
+            .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+    }
+
+    let id = Uuid::new_v4().to_string();
+    std::fs::write(path, format!("{id}\n"))
+        .with_context(|| format!("Failed to write server ID to {}", path.display()))?;
+    info!("Generated new server ID {} at {}", id, path.display());
+
+    Ok(id)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_server_id_is_stable() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("data/server_id");
+
+        let first = load_or_create_server_id(&path).unwrap();
+        let second = load_or_create_server_id(&path).unwrap();
+
+        assert_eq!(first, second);
+        assert!(Uuid::parse_str(&first).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_server_id_is_replaced() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("server_id");
+        std::fs::write(&path, "not-a-uuid").unwrap();
+
+        let id = load_or_create_server_id(&path).unwrap();
+        assert!(Uuid::parse_str(&id).is_ok());
+        assert_eq!(std::fs::read_to_string(&path).unwrap().trim(), id);
+    }
+}
@@ -1,21 +1,30 @@
 mod agent;
+mod config;
 mod events;
 mod grpc;
+mod identity;
 mod network;
+mod orphans;
 mod proxy;
+mod ready;
 mod telemetry;
 mod vm;
 
 use anyhow::{Context, Result};
+use clawpot_common::firecracker::RootfsMode;
 use clawpot_common::proto::clawpot_service_server::ClawpotServiceServer;
-use events::{EventStore, PersistMode};
+use config::Config;
+use events::EventStore;
 use grpc::ClawpotServiceImpl;
-use network::{ip_allocator::IpAllocator, NetworkManager};
+use network::{
+    ip_allocator::{self, IpAllocator},
+    NetworkManager,
+};
 use proxy::auth_client::AuthClient;
 use proxy::body_store::BodyStore;
 use proxy::ca::CertificateAuthority;
-use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Instant;
 use tokio::signal;
 use tokio::sync::Mutex;
 use tonic::transport::Server;
@@ -25,6 +34,8 @@ use vm::VmRegistry;
 
 #[tokio::main]
 async fn main() -> Result<()> {
+    let startup = Instant::now();
+
     // Install ring as the default CryptoProvider before any TLS usage.
     // Required because both ring and aws-lc-rs features are enabled via rustls defaults.
     rustls::crypto::ring::default_provider()
@@ -40,6 +51,10 @@ async fn main() -> Result<()> {
 
     info!("Starting clawpot-server (session {})...", session_id);
 
+    // Read every CLAWPOT_* setting up front so misconfiguration fails here, all at once
+    let config = Arc::new(Config::from_env()?);
+    network::iptables::set_dry_run(config.iptables_dry_run);
+
     // Check if running as root
     if !nix::unistd::geteuid().is_root() {
         error!("Server must be run as root (use sudo)");
@@ -50,35 +65,55 @@ async fn main() -> Result<()> {
 
     info!("Running as root");
 
-    // Server configuration - resolve paths relative to the binary or use env override
-    let project_root = std::env::var("CLAWPOT_ROOT")
-        .map_or_else(|_| PathBuf::from("/workspaces/clawpot"), PathBuf::from);
+    let project_root = &config.root;
+    let auth_addr = &config.auth_addr;
 
-    // Initialize event store
-    let events_db_path = std::env::var("CLAWPOT_EVENTS_DB")
-        .map_or_else(|_| project_root.join("data/events.db"), PathBuf::from);
-    let persist_mode = PersistMode::from_env();
+    // HTTPS interception needs every guest to trust the CA; some deployments opt out
+    let mitm_enabled = config.mitm_enabled;
 
-    let auth_addr = std::env::var("CLAWPOT_AUTH_ADDR").ok();
+    // Subnet the VMs live on; the bridge takes its first host address
+    let mut ip_allocator = IpAllocator::with_cidr(config.subnet_network, config.subnet_prefix)
+        .with_context(|| format!("Invalid {}", ip_allocator::SUBNET_ENV))?
+        .with_exclusions(config.excluded_ips.clone())
+        .with_state_file(
+            &project_root
+                .join("data")
+                .join(ip_allocator::STATE_FILE_NAME),
+        )
+        .context("Failed to restore IP allocations")?;
+
+    // Stable across restarts, unlike the session ID
+    let server_id = identity::load_or_create_server_id(&project_root.join("data/server_id"))
+        .context("Failed to load server ID")?;
+    info!("Server ID {}", server_id);
 
     let event_store = EventStore::new(
-        &events_db_path,
+        &config.events_db,
         &session_id,
+        &server_id,
         env!("CARGO_PKG_VERSION"),
         &serde_json::json!({
             "root": project_root.to_string_lossy(),
             "auth_addr": auth_addr,
+            "mitm_enabled": mitm_enabled,
         })
         .to_string(),
-        persist_mode,
+        config.events_persist.clone(),
     )
-    .context("Failed to initialize event store")?;
+    .context("Failed to initialize event store")?
+    .with_indexed_headers(config.indexed_headers.clone())
+    .with_log_coalescing(config.events_coalesce_logs);
 
     clawpot_event!(event_store, "server.started", "server", {
         "version": env!("CARGO_PKG_VERSION"),
+        "session_id": session_id,
+        "server_id": server_id,
         "pid": std::process::id(),
         "config_root": project_root.to_string_lossy().to_string(),
-        "auth_addr": auth_addr
+        "auth_addr": auth_addr,
+        "mitm_enabled": mitm_enabled,
+        "subnet": config.subnet,
+        "config": config.summary()
     });
 
     // Initialize networking
@@ -87,12 +122,29 @@ async fn main() -> Result<()> {
 
     clawpot_log!(event_store, "server", "Ensuring network bridge exists...");
     network_manager
-        .ensure_bridge()
+        .ensure_bridge(mitm_enabled, ip_allocator.gateway(), ip_allocator.prefix())
         .await
         .context("Failed to ensure bridge exists")?;
 
     clawpot_log!(event_store, "server", "Network bridge ready");
 
+    // No VM is registered yet, so a restored allocation is only still in use
+    // if a TAP device left over from the previous run is pinned to it
+    match network_manager.leftover_vm_ips().await {
+        Ok(in_use) => {
+            let released = ip_allocator.retain(&in_use);
+            if !released.is_empty() {
+                clawpot_log!(
+                    event_store,
+                    "server",
+                    "Released {} restored IPs no leftover VM holds",
+                    released.len()
+                );
+            }
+        }
+        Err(e) => warn!("Keeping all restored IP allocations: {:#}", e),
+    }
+
     // Initialize CA
     let ca_dir = project_root.join("ca");
     let ca = Arc::new(CertificateAuthority::new(&ca_dir).context("Failed to initialize CA")?);
@@ -100,9 +152,21 @@ async fn main() -> Result<()> {
 
     // Initialize body store
     let body_store_dir = project_root.join("data/bodies");
-    let body_store =
-        Arc::new(BodyStore::new(&body_store_dir).context("Failed to initialize body store")?);
-    clawpot_log!(event_store, "server", "Body store ready");
+    let mut body_store = BodyStore::new_or_inline_only(&body_store_dir);
+    if let Some(threshold) = config.body_store_high_usage_bytes {
+        body_store = body_store.with_high_usage_alert(event_store.clone(), threshold);
+    }
+    let body_store = Arc::new(body_store);
+    if let Some(reason) = body_store.degraded() {
+        clawpot_log!(
+            event_store,
+            "server",
+            "Body store degraded to inline-only, streamed bodies will not be kept: {}",
+            reason
+        );
+    } else {
+        clawpot_log!(event_store, "server", "Body store ready");
+    }
 
     // Initialize authorization client
     let auth = Arc::new(
@@ -113,34 +177,81 @@ async fn main() -> Result<()> {
     clawpot_log!(event_store, "server", "Authorization client ready");
 
     // Initialize LLM key store
-    let llm_keys = Arc::new(proxy::llm::LlmKeyStore::from_env());
+    let llm_keys = Arc::new(proxy::llm::LlmKeyStore::load(
+        &clawpot_common::llm_keys::keys_path(&project_root.join("data")),
+    ));
     clawpot_log!(event_store, "server", "LLM key store initialized");
 
+    // Hostnames answered locally by the DNS proxy instead of upstream
+    let dns_overrides = Arc::new(match &config.dns_overrides_file {
+        Some(path) => proxy::dns_overrides::DnsOverrides::load(path)
+            .context("Failed to load DNS overrides")?,
+        None => proxy::dns_overrides::DnsOverrides::default(),
+    });
+
+    // Names VMs may resolve at all, checked before the authorization service
+    let dns_allowlist = Arc::new(match &config.dns_allowlist_file {
+        Some(path) => proxy::dns_allowlist::DnsAllowlist::load(path)
+            .context("Failed to load DNS allowlist")?
+            .with_deny_rcode(config.dns_allowlist_rcode),
+        None => proxy::dns_allowlist::DnsAllowlist::default(),
+    });
+
     // Create shared cancellation channel
     let (cancel_tx, cancel_rx) = tokio::sync::watch::channel(false);
 
+    if let Some(max_bytes) = config.events_max_bytes {
+        clawpot_log!(
+            event_store,
+            "server",
+            "Pruning the oldest sessions once the events database exceeds {} bytes",
+            max_bytes
+        );
+        tokio::spawn(events::retention::run(
+            event_store.clone(),
+            body_store.clone(),
+            max_bytes,
+            cancel_rx.clone(),
+        ));
+    }
+
     // Initialize IP allocator and VM registry (before proxies so registry is available)
-    let ip_allocator = Arc::new(Mutex::new(IpAllocator::new()));
+    let (first_ip, last_ip) = ip_allocator.allocatable_range();
+    let restored_ips = ip_allocator.allocated_count();
+    let ip_allocator = Arc::new(Mutex::new(ip_allocator));
     clawpot_log!(
         event_store,
         "server",
-        "IP allocator initialized (192.168.100.2-254)"
+        "IP allocator initialized ({}-{}, {} restored from a previous run)",
+        first_ip,
+        last_ip,
+        restored_ips
     );
 
     let vm_registry = Arc::new(VmRegistry::new());
     clawpot_log!(event_store, "server", "VM registry initialized");
 
     // Create oneshot channels for proxy startup verification
+    let proxies_start = Instant::now();
     let (mitm_ready_tx, mitm_ready_rx) = tokio::sync::oneshot::channel();
     let (http_ready_tx, http_ready_rx) = tokio::sync::oneshot::channel();
     let (dns_ready_tx, dns_ready_rx) = tokio::sync::oneshot::channel();
 
     // Start TLS MITM proxy
-    let mitm_ca = ca.clone();
-    let mitm_cancel = cancel_rx.clone();
-    let _mitm_handle = tokio::spawn(async move {
-        proxy::tls_mitm::run(mitm_ca, mitm_cancel, mitm_ready_tx).await;
-    });
+    if mitm_enabled {
+        let mitm_ca = ca.clone();
+        let mitm_cancel = cancel_rx.clone();
+        let _mitm_handle = tokio::spawn(async move {
+            proxy::tls_mitm::run(mitm_ca, mitm_cancel, mitm_ready_tx).await;
+        });
+    } else {
+        // Nothing to wait for: report ready immediately
+        let _ = mitm_ready_tx.send(());
+    }
+
+    // Shared by the HTTP proxy, which charges it, and the gRPC service, which
+    // forgets deleted VMs
+    let token_budget = proxy::token_budget::TokenBudget::new(config.proxy.vm_token_budget);
 
     // Start HTTP proxy
     let http_registry = vm_registry.clone();
@@ -148,6 +259,8 @@ async fn main() -> Result<()> {
     let http_body_store = body_store.clone();
     let http_auth = auth.clone();
     let http_llm_keys = llm_keys.clone();
+    let http_token_budget = token_budget.clone();
+    let http_config = config.proxy.clone();
     let http_cancel = cancel_rx.clone();
     let _http_handle = tokio::spawn(async move {
         if let Err(e) = proxy::http_proxy::run(
@@ -156,6 +269,8 @@ async fn main() -> Result<()> {
             http_body_store,
             http_auth,
             http_llm_keys,
+            http_token_budget,
+            http_config,
             http_cancel,
             http_ready_tx,
         )
@@ -169,23 +284,80 @@ async fn main() -> Result<()> {
     let dns_registry = vm_registry.clone();
     let dns_events = event_store.clone();
     let dns_auth = auth.clone();
+    let deny_unknown_vm = config.proxy.deny_unknown_vm;
+    let dns_upstream = match config.dns_mode {
+        proxy::dns_proxy::DnsMode::Udp => {
+            proxy::dns_proxy::DnsUpstream::Udp(config.dns_upstreams.clone())
+        }
+        proxy::dns_proxy::DnsMode::Doh => proxy::dns_proxy::DnsUpstream::Doh(
+            proxy::doh::DohClient::new(config.dns_doh_url.clone()),
+        ),
+    };
     let dns_cancel = cancel_rx.clone();
     let _dns_handle = tokio::spawn(async move {
-        proxy::dns_proxy::run(dns_registry, dns_events, dns_auth, dns_cancel, dns_ready_tx).await;
+        proxy::dns_proxy::run(
+            dns_registry,
+            dns_events,
+            dns_auth,
+            dns_overrides,
+            dns_allowlist,
+            dns_upstream,
+            deny_unknown_vm,
+            dns_cancel,
+            dns_ready_tx,
+        )
+        .await;
     });
 
-    // Wait for all proxies to be ready before starting gRPC
-    mitm_ready_rx
-        .await
-        .context("TLS MITM proxy failed to start")?;
-    clawpot_log!(event_store, "server", "TLS MITM proxy started");
+    // Wait for all proxies to be ready before starting gRPC, timing each one
+    let timed = move |rx: tokio::sync::oneshot::Receiver<()>| async move {
+        rx.await.map(|()| proxies_start.elapsed())
+    };
+    let (mitm_ready, http_ready, dns_ready) = tokio::join!(
+        timed(mitm_ready_rx),
+        timed(http_ready_rx),
+        timed(dns_ready_rx)
+    );
 
-    http_ready_rx.await.context("HTTP proxy failed to start")?;
+    let mitm_startup = mitm_ready.context("TLS MITM proxy failed to start")?;
+    if mitm_enabled {
+        clawpot_log!(event_store, "server", "TLS MITM proxy started");
+    } else {
+        clawpot_log!(
+            event_store,
+            "server",
+            "CLAWPOT_DISABLE_MITM set: HTTPS interception is OFF, port 443 traffic from VMs is dropped"
+        );
+    }
+
+    let http_startup = http_ready.context("HTTP proxy failed to start")?;
     clawpot_log!(event_store, "server", "HTTP proxy started");
 
-    dns_ready_rx.await.context("DNS proxy failed to start")?;
+    let dns_startup = dns_ready.context("DNS proxy failed to start")?;
     clawpot_log!(event_store, "server", "DNS proxy started");
 
+    let mut components = Vec::new();
+    if mitm_enabled {
+        components.push(ready::Component::new(
+            "tls_mitm",
+            &[proxy::tls_mitm::MITM_LISTEN_ADDR],
+            mitm_startup,
+        ));
+    }
+    components.push(ready::Component::new(
+        "http_proxy",
+        &[
+            proxy::http_proxy::HTTP_LISTEN_ADDR,
+            proxy::http_proxy::HTTPS_LISTEN_ADDR,
+        ],
+        http_startup,
+    ));
+    components.push(ready::Component::new(
+        "dns_proxy",
+        &[proxy::dns_proxy::DNS_LISTEN_ADDR],
+        dns_startup,
+    ));
+
     let kernel_path = project_root.join("assets/kernels/vmlinux");
     let rootfs_path = project_root.join("assets/rootfs/ubuntu.ext4");
 
@@ -206,35 +378,185 @@ async fn main() -> Result<()> {
 
     clawpot_log!(event_store, "server", "VM assets verified");
 
+    // Maximum gRPC message size (applies to exec output returned to clients)
+    let max_message_size = config.grpc_max_message_size;
+
+    // Keep VMs that fail to boot around for debugging instead of rolling back
+    if config.keep_on_failure {
+        clawpot_log!(
+            event_store,
+            "server",
+            "CLAWPOT_KEEP_ON_FAILURE set: failed VMs will be kept for debugging"
+        );
+    }
+
+    // How VMs use the rootfs image (shared, read-only or per-VM copy)
+    let rootfs_mode = config.rootfs_mode;
+    if rootfs_mode != RootfsMode::Shared {
+        clawpot_log!(
+            event_store,
+            "server",
+            "Rootfs mode: {}",
+            rootfs_mode.as_str()
+        );
+    }
+
     // Create gRPC service
-    let service = ClawpotServiceImpl::new(
+    let service = Arc::new(ClawpotServiceImpl::new(
         vm_registry.clone(),
         ip_allocator.clone(),
         network_manager.clone(),
         kernel_path,
         rootfs_path,
+        config.clone(),
         event_store.clone(),
-    );
+        server_id.clone(),
+        session_id.clone(),
+        body_store.clone(),
+        token_budget,
+    ));
+
+    if config.vm_pool_size > 0 {
+        clawpot_log!(
+            event_store,
+            "server",
+            "Keeping {} idle VMs booted in the pool",
+            config.vm_pool_size
+        );
+        tokio::spawn(service.clone().run_pool(cancel_rx.clone()));
+    }
 
     // Bind address
-    let addr = "0.0.0.0:50051".parse()?;
-    clawpot_log!(event_store, "server", "Starting gRPC server on {}", addr);
-
-    // Start gRPC server with graceful shutdown
-    Server::builder()
-        .add_service(ClawpotServiceServer::new(service))
-        .serve_with_shutdown(
-            addr,
-            shutdown_signal(
-                vm_registry,
-                network_manager,
-                ip_allocator,
-                cancel_tx,
-                event_store.clone(),
-            ),
+    let addr: std::net::SocketAddr = "0.0.0.0:50051".parse()?;
+
+    let mut grpc_service = ClawpotServiceServer::from_arc(service)
+        .max_decoding_message_size(max_message_size)
+        .max_encoding_message_size(max_message_size);
+    if let Some(encoding) = config.grpc_compression {
+        clawpot_log!(
+            event_store,
+            "server",
+            "gRPC compression enabled ({})",
+            encoding
+        );
+        grpc_service = grpc_service
+            .send_compressed(encoding)
+            .accept_compressed(encoding);
+    }
+
+    let audit = grpc::audit::AuditLayer::new(config.audit_grpc.then(|| event_store.clone()));
+    if config.audit_grpc {
+        clawpot_log!(event_store, "server", "gRPC call auditing enabled");
+    }
+
+    // Bind before reporting ready so the addresses are actually accepting connections
+    let grpc_start = Instant::now();
+    let mut grpc_addrs = Vec::new();
+    let tcp_incoming = if config.listen_tcp {
+        let grpc_listener = tokio::net::TcpListener::bind(addr)
+            .await
+            .with_context(|| format!("Failed to bind gRPC server on {addr}"))?;
+        grpc_addrs.push(addr.to_string());
+        Some(
+            tonic::transport::server::TcpIncoming::from_listener(grpc_listener, true, None)
+                .map_err(|e| anyhow::anyhow!("Failed to set up gRPC listener: {e}"))?,
         )
-        .await
-        .context("gRPC server failed")?;
+    } else {
+        None
+    };
+    let uds_incoming = match &config.listen_uds {
+        Some(path) => {
+            let incoming = grpc::uds::bind(path)?;
+            grpc_addrs.push(format!("unix://{}", path.display()));
+            Some(incoming)
+        }
+        None => None,
+    };
+    clawpot_log!(
+        event_store,
+        "server",
+        "gRPC server listening on {} (max message size {} bytes)",
+        grpc_addrs.join(", "),
+        max_message_size
+    );
+    components.push(ready::Component::new(
+        "grpc",
+        &grpc_addrs.iter().map(String::as_str).collect::<Vec<_>>(),
+        grpc_start.elapsed(),
+    ));
+
+    let report = ready::ReadyReport {
+        session_id: session_id.clone(),
+        total_startup_ms: startup.elapsed().as_millis() as u64,
+        components,
+    };
+    event_store.emit("server.ready", "server", None, None, &report);
+    let ready_file = config.ready_file.as_deref().filter(|path| {
+        report
+            .write_ready_file(path)
+            .inspect_err(|e| warn!("Failed to write readiness file: {:#}", e))
+            .is_ok()
+    });
+    info!("clawpot ready in {} ms", report.total_startup_ms);
+
+    // Serve every listener until the shutdown handler has cleaned up, then
+    // stop them together
+    let (stop_tx, stop_rx) = tokio::sync::watch::channel(false);
+    let stopped = || {
+        let mut stop_rx = stop_rx.clone();
+        async move {
+            let _ = stop_rx.wait_for(|stop| *stop).await;
+        }
+    };
+    let tcp_server = async {
+        match tcp_incoming {
+            Some(incoming) => {
+                Server::builder()
+                    .layer(audit.clone())
+                    .add_service(grpc_service.clone())
+                    .serve_with_incoming_shutdown(incoming, stopped())
+                    .await
+            }
+            None => Ok(()),
+        }
+    };
+    let uds_server = async {
+        match uds_incoming {
+            Some(incoming) => {
+                Server::builder()
+                    .layer(audit.clone())
+                    .add_service(grpc_service.clone())
+                    .serve_with_incoming_shutdown(incoming, stopped())
+                    .await
+            }
+            None => Ok(()),
+        }
+    };
+    let servers = async { tokio::try_join!(tcp_server, uds_server).map(|_| ()) };
+    let shutdown = async {
+        shutdown_signal(
+            vm_registry,
+            network_manager,
+            ip_allocator,
+            cancel_tx,
+            event_store.clone(),
+        )
+        .await;
+        let _ = stop_tx.send(true);
+    };
+    tokio::pin!(servers, shutdown);
+    let served = tokio::select! {
+        served = &mut servers => served,
+        () = &mut shutdown => servers.await,
+    };
+
+    if let Some(path) = &ready_file {
+        ready::remove_ready_file(path);
+    }
+    if let Some(path) = &config.listen_uds {
+        grpc::uds::remove(path);
+    }
+    served.context("gRPC server failed")?;
 
     clawpot_event!(event_store, "server.stopped", "server", {
         "reason": "shutdown"
@@ -291,7 +613,7 @@ async fn shutdown_signal(
         vms_list.len()
     );
 
-    for (vm_id, ip_address, tap_name, _, _, _) in vms_list {
+    for (vm_id, ip_address, tap_name, ..) in vms_list {
         let _cleanup_span = tracing::info_span!("shutdown.cleanup_vm", vm_id = %vm_id).entered();
         clawpot_log!(event_store, "server", vm_id = vm_id, "Cleaning up VM");
 
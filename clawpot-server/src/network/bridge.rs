@@ -6,7 +6,13 @@ use tracing::info;
 
 /// Ensure a bridge device exists, create if missing
 /// Assigns the gateway IP and brings it up
-pub async fn ensure_bridge(handle: &Handle, name: &str, gateway_ip: IpAddr) -> Result<()> {
+pub async fn ensure_bridge(
+    handle: &Handle,
+    name: &str,
+    gateway_ip: IpAddr,
+    prefix: u8,
+    intercept_https: bool,
+) -> Result<()> {
     // Check if bridge already exists
     let mut links = handle.link().get().match_name(name.to_string()).execute();
 
@@ -15,20 +21,26 @@ pub async fn ensure_bridge(handle: &Handle, name: &str, gateway_ip: IpAddr) -> R
     } else {
         // Bridge doesn't exist, create it
         info!("Bridge {} does not exist, creating...", name);
-        create_bridge(handle, name, gateway_ip).await?;
+        create_bridge(handle, name, gateway_ip, prefix, intercept_https).await?;
     }
 
     // Always ensure iptables rules and IP forwarding are set up,
     // even if the bridge already existed (rules may have been flushed).
     enable_ip_forwarding()?;
-    super::iptables::ensure_proxy_redirect_rules(name)?;
+    super::iptables::ensure_proxy_redirect_rules(name, intercept_https)?;
     super::iptables::ensure_egress_filter_rules(name)?;
 
     Ok(())
 }
 
 /// Create a new bridge device
-async fn create_bridge(handle: &Handle, name: &str, gateway_ip: IpAddr) -> Result<()> {
+async fn create_bridge(
+    handle: &Handle,
+    name: &str,
+    gateway_ip: IpAddr,
+    prefix: u8,
+    intercept_https: bool,
+) -> Result<()> {
     // Create bridge
     handle
         .link()
@@ -47,14 +59,14 @@ async fn create_bridge(handle: &Handle, name: &str, gateway_ip: IpAddr) -> Resul
     // Assign IP to bridge
     handle
         .address()
-        .add(index, gateway_ip, 24)
+        .add(index, gateway_ip, prefix)
         .execute()
         .await
         .context(format!(
-            "Failed to assign IP {gateway_ip}/24 to bridge {name}"
+            "Failed to assign IP {gateway_ip}/{prefix} to bridge {name}"
         ))?;
 
-    info!("Assigned IP {}/24 to bridge {}", gateway_ip, name);
+    info!("Assigned IP {}/{} to bridge {}", gateway_ip, prefix, name);
 
     // Bring bridge up
     handle
@@ -70,7 +82,7 @@ async fn create_bridge(handle: &Handle, name: &str, gateway_ip: IpAddr) -> Resul
     enable_ip_forwarding()?;
 
     // Set up proxy redirect and egress filter rules
-    super::iptables::add_proxy_redirect_rules(name)?;
+    super::iptables::add_proxy_redirect_rules(name, intercept_https)?;
     super::iptables::add_egress_filter_rules(name)?;
 
     Ok(())
@@ -152,7 +164,7 @@ mod tests {
         tokio::spawn(connection);
 
         let gateway = IpAddr::V4(Ipv4Addr::new(192, 168, 100, 1));
-        ensure_bridge(&handle, "test-br0", gateway)
+        ensure_bridge(&handle, "test-br0", gateway, 24, true)
             .await
             .expect("Failed to ensure bridge");
 
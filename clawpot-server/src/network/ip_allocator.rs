@@ -1,35 +1,147 @@
-use anyhow::{anyhow, Result};
+use anyhow::{anyhow, bail, Context, Result};
 use bitvec::prelude::*;
+use std::collections::HashSet;
 use std::net::{IpAddr, Ipv4Addr};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+/// Environment variable overriding the VM subnet, e.g. `10.200.0.0/24`
+pub const SUBNET_ENV: &str = "CLAWPOT_SUBNET";
+
+/// Subnet used when `CLAWPOT_SUBNET` is unset
+pub const DEFAULT_SUBNET: &str = "192.168.100.0/24";
+
+/// File inside the data directory recording which IPs are handed out
+pub const STATE_FILE_NAME: &str = "ip_allocations.json";
+
+/// Environment variable listing addresses the allocator must never hand out
+pub const EXCLUDE_ENV: &str = "CLAWPOT_IP_EXCLUDE";
+
+/// Parse an IPv4 subnet in CIDR notation (`10.200.0.0/24`).
+pub fn parse_cidr(cidr: &str) -> Result<(Ipv4Addr, u8)> {
+    let (network, prefix) = cidr
+        .trim()
+        .split_once('/')
+        .ok_or_else(|| anyhow!("Subnet '{cidr}' must be in CIDR notation (e.g. 10.200.0.0/24)"))?;
+    let network = network
+        .parse()
+        .with_context(|| format!("Invalid network address in subnet '{cidr}'"))?;
+    let prefix = prefix
+        .parse()
+        .with_context(|| format!("Invalid prefix length in subnet '{cidr}'"))?;
+    Ok((network, prefix))
+}
+
+/// Parse a comma-separated list of IPv4 addresses (`10.200.0.5,10.200.0.10`).
+pub fn parse_exclusions(raw: &str) -> Result<Vec<Ipv4Addr>> {
+    raw.split(',')
+        .map(str::trim)
+        .filter(|ip| !ip.is_empty())
+        .map(|ip| {
+            ip.parse()
+                .with_context(|| format!("Invalid IPv4 address '{ip}'"))
+        })
+        .collect()
+}
+
+/// Snapshot of how full the address pool is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PoolStats {
+    pub total: usize,
+    pub allocated: usize,
+    pub available: usize,
+}
 
-/// IP address allocator for the 192.168.100.0/24 network
-/// Gateway is at 192.168.100.1
-/// Allocatable range: 192.168.100.2-254 (253 addresses)
+/// IP address allocator for the VM subnet (192.168.100.0/24 by default)
+/// Gateway is the first host address (e.g. 192.168.100.1)
+/// Allocatable range: second host through the last address before broadcast
 #[allow(dead_code)]
 pub struct IpAllocator {
-    network_base: u32, // 192.168.100.0 as u32
-    gateway: Ipv4Addr, // 192.168.100.1
-    allocated: BitVec, // Bitmap of allocated IPs
-    next_index: usize, // Hint for next allocation (round-robin)
+    network_base: u32,           // Network address as u32
+    prefix: u8,                  // Prefix length of the subnet
+    gateway: Ipv4Addr,           // First host address
+    allocated: BitVec,           // Bitmap of allocated IPs
+    excluded: BitVec,            // Subset of `allocated` that is never handed out
+    next_index: usize,           // Hint for next allocation (round-robin)
+    state_file: Option<PathBuf>, // Where allocations are persisted, if anywhere
 }
 
 impl IpAllocator {
     /// Create a new IP allocator for 192.168.100.0/24
     /// Gateway is 192.168.100.1, allocatable range is .2-.254
     pub fn new() -> Self {
-        // 192.168.100.0 = 3232261120
-        let network_base = u32::from(Ipv4Addr::new(192, 168, 100, 0));
-        let gateway = Ipv4Addr::new(192, 168, 100, 1);
+        Self::with_cidr(Ipv4Addr::new(192, 168, 100, 0), 24).expect("default subnet is valid")
+    }
+
+    /// Create an allocator for `network/prefix`
+    /// The gateway is the first host; the network, gateway and broadcast
+    /// addresses are never handed out.
+    pub fn with_cidr(network: Ipv4Addr, prefix: u8) -> Result<Self> {
+        if !(8..=30).contains(&prefix) {
+            bail!("Subnet prefix /{prefix} is not supported (expected /8 to /30)");
+        }
+
+        let network_base = u32::from(network);
+        let host_bits = 32 - u32::from(prefix);
+        if network_base & ((1 << host_bits) - 1) != 0 {
+            bail!("{network}/{prefix} is not a network address (host bits are set)");
+        }
 
-        // 253 allocatable IPs (.2 through .254)
-        let allocated = bitvec![0; 253];
+        let gateway = Ipv4Addr::from(network_base + 1);
 
-        Self {
+        // Every address except network, gateway and broadcast
+        let allocated = bitvec![0; (1usize << host_bits) - 3];
+        let excluded = allocated.clone();
+
+        Ok(Self {
             network_base,
+            prefix,
             gateway,
             allocated,
+            excluded,
             next_index: 0,
+            state_file: None,
+        })
+    }
+
+    /// Never hand out the addresses in `excluded`, e.g. ones used by other
+    /// infrastructure on the subnet. Addresses outside the pool are ignored.
+    #[must_use]
+    pub fn with_exclusions(mut self, excluded: Vec<Ipv4Addr>) -> Self {
+        for ip in excluded {
+            match self.index_of(ip) {
+                Some(index) => {
+                    self.allocated.set(index, true);
+                    self.excluded.set(index, true);
+                }
+                None => warn!("Ignoring excluded IP {} outside the allocatable range", ip),
+            }
+        }
+        self
+    }
+
+    /// Persist allocations to `path` after every change, first restoring
+    /// any allocations recorded there by a previous run.
+    ///
+    /// VMs can outlive the server (e.g. after a crash), so without this a
+    /// restarted server would hand out addresses still used by live TAPs.
+    pub fn with_state_file(mut self, path: &Path) -> Result<Self> {
+        match std::fs::read(path) {
+            Ok(data) => {
+                let ips: Vec<IpAddr> = serde_json::from_slice(&data).with_context(|| {
+                    format!("Failed to parse IP allocations from {}", path.display())
+                })?;
+                self.restore(ips);
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(e).with_context(|| {
+                    format!("Failed to read IP allocations from {}", path.display())
+                })
+            }
         }
+        self.state_file = Some(path.to_path_buf());
+        Ok(self)
     }
 
     /// Allocate the next available IP address
@@ -45,19 +157,133 @@ impl IpAllocator {
 
                 // Update next_index for next allocation
                 self.next_index = (index + 1) % self.allocated.len();
+                self.persist();
 
-                // Convert index to IP address
-                // Index 0 = 192.168.100.2, Index 1 = 192.168.100.3, etc.
-                let ip_u32 = self.network_base + 2 + index as u32;
-                let ip = Ipv4Addr::from(ip_u32);
-
-                return Ok(IpAddr::V4(ip));
+                return Ok(IpAddr::V4(self.ip_at(index)));
             }
         }
 
         Err(anyhow!("No available IP addresses in the pool"))
     }
 
+    /// Allocate a specific IP address
+    /// Returns error if it is outside the allocatable range or already taken
+    pub fn allocate_specific(&mut self, ip: Ipv4Addr) -> Result<IpAddr> {
+        let Some(index) = self.index_of(ip) else {
+            let (first, last) = self.allocatable_range();
+            return Err(anyhow!(
+                "IP address {ip} is not in the allocatable range ({first}-{last})"
+            ));
+        };
+
+        if self.excluded[index] {
+            return Err(anyhow!("IP address {ip} is excluded from the pool"));
+        }
+        if self.allocated[index] {
+            return Err(anyhow!("IP address {ip} is already allocated"));
+        }
+
+        self.allocated.set(index, true);
+
+        // Continue round-robin after the pinned address so the next
+        // allocate() doesn't have to skip over it
+        if index == self.next_index {
+            self.next_index = (index + 1) % self.allocated.len();
+        }
+        self.persist();
+
+        Ok(IpAddr::V4(ip))
+    }
+
+    /// Mark an IP address as in use, e.g. one held by a VM that survived a
+    /// restart. Already-allocated addresses are left as they are.
+    pub fn mark_allocated(&mut self, ip: IpAddr) -> Result<()> {
+        let IpAddr::V4(ipv4) = ip else {
+            return Err(anyhow!("IPv6 addresses are not supported"));
+        };
+        let Some(index) = self.index_of(ipv4) else {
+            let (first, last) = self.allocatable_range();
+            return Err(anyhow!(
+                "IP address {ipv4} is not in the allocatable range ({first}-{last})"
+            ));
+        };
+
+        if !self.allocated[index] {
+            self.allocated.set(index, true);
+            self.persist();
+        }
+        Ok(())
+    }
+
+    /// All currently allocated addresses, in ascending order. Excluded
+    /// addresses aren't included; they come from configuration instead.
+    pub fn snapshot(&self) -> Vec<IpAddr> {
+        self.allocated
+            .iter_ones()
+            .filter(|&index| !self.excluded[index])
+            .map(|index| IpAddr::V4(self.ip_at(index)))
+            .collect()
+    }
+
+    /// Mark every address in `ips` as allocated. Addresses outside the
+    /// current subnet (e.g. after `CLAWPOT_SUBNET` changed) are skipped.
+    /// Returns how many were restored.
+    pub fn restore(&mut self, ips: Vec<IpAddr>) -> usize {
+        ips.into_iter()
+            .filter(|&ip| match self.mark_allocated(ip) {
+                Ok(()) => true,
+                Err(e) => {
+                    warn!("Ignoring restored allocation: {:#}", e);
+                    false
+                }
+            })
+            .count()
+    }
+
+    /// Release every allocated address not in `keep`, returning the
+    /// released ones. Excluded addresses are left alone.
+    pub fn retain(&mut self, keep: &HashSet<IpAddr>) -> Vec<IpAddr> {
+        let released: Vec<IpAddr> = self
+            .snapshot()
+            .into_iter()
+            .filter(|ip| !keep.contains(ip))
+            .collect();
+        for ip in &released {
+            if let IpAddr::V4(ipv4) = ip {
+                if let Some(index) = self.index_of(*ipv4) {
+                    self.allocated.set(index, false);
+                }
+            }
+        }
+        if !released.is_empty() {
+            self.persist();
+        }
+        released
+    }
+
+    /// Write the current allocations to the state file, if there is one.
+    /// Best-effort: a failed write is logged, not returned.
+    fn persist(&self) {
+        let Some(path) = &self.state_file else {
+            return;
+        };
+        let tmp = path.with_extension("json.tmp");
+        let result = serde_json::to_vec(&self.snapshot())
+            .map_err(anyhow::Error::from)
+            .and_then(|data| {
+                std::fs::write(&tmp, data)?;
+                std::fs::rename(&tmp, path)?;
+                Ok(())
+            });
+        if let Err(e) = result {
+            warn!(
+                "Failed to persist IP allocations to {}: {:#}",
+                path.display(),
+                e
+            );
+        }
+    }
+
     /// Release an IP address back to the pool
     pub fn release(&mut self, ip: IpAddr) -> Result<()> {
         let ipv4 = match ip {
@@ -65,44 +291,84 @@ impl IpAllocator {
             IpAddr::V6(_) => return Err(anyhow!("IPv6 addresses are not supported")),
         };
 
-        let ip_u32 = u32::from(ipv4);
-
         // Check if IP is in our network range
-        if ip_u32 < self.network_base + 2 || ip_u32 > self.network_base + 254 {
+        let Some(index) = self.index_of(ipv4) else {
+            let (first, last) = self.allocatable_range();
             return Err(anyhow!(
-                "IP address {ipv4} is not in the allocatable range (192.168.100.2-254)"
+                "IP address {ipv4} is not in the allocatable range ({first}-{last})"
             ));
-        }
-
-        // Calculate index in the bitmap
-        let index = (ip_u32 - self.network_base - 2) as usize;
+        };
 
-        if index >= self.allocated.len() {
-            return Err(anyhow!("IP address {ipv4} is out of range"));
+        // Excluded addresses must never re-enter the pool
+        if self.excluded[index] {
+            return Err(anyhow!("IP address {ipv4} is excluded from the pool"));
         }
 
         // Mark as unallocated
         self.allocated.set(index, false);
+        self.persist();
 
         Ok(())
     }
 
+    /// Whether an IP address is currently allocated to a VM
+    pub fn is_allocated(&self, ip: IpAddr) -> bool {
+        let IpAddr::V4(ipv4) = ip else {
+            return false;
+        };
+
+        self.index_of(ipv4)
+            .is_some_and(|index| self.allocated[index] && !self.excluded[index])
+    }
+
+    /// First and last addresses handed out by `allocate`
+    pub fn allocatable_range(&self) -> (Ipv4Addr, Ipv4Addr) {
+        (self.ip_at(0), self.ip_at(self.allocated.len() - 1))
+    }
+
+    /// Bitmap index 0 is the address after the gateway
+    fn ip_at(&self, index: usize) -> Ipv4Addr {
+        Ipv4Addr::from(self.network_base + 2 + index as u32)
+    }
+
+    /// Bitmap index of `ip`, or `None` if it isn't an allocatable address
+    fn index_of(&self, ip: Ipv4Addr) -> Option<usize> {
+        let offset = u32::from(ip).checked_sub(self.network_base + 2)? as usize;
+        (offset < self.allocated.len()).then_some(offset)
+    }
+
     /// Get the gateway IP address
-    #[allow(dead_code)]
     pub fn gateway(&self) -> IpAddr {
         IpAddr::V4(self.gateway)
     }
 
-    /// Get the number of allocated IPs
-    #[allow(dead_code)]
+    /// Get the subnet prefix length
+    pub fn prefix(&self) -> u8 {
+        self.prefix
+    }
+
+    /// Get the subnet mask (e.g. 255.255.255.0 for a /24)
+    pub fn netmask(&self) -> IpAddr {
+        IpAddr::V4(Ipv4Addr::from(u32::MAX << (32 - u32::from(self.prefix))))
+    }
+
+    /// Get the number of allocated IPs (not counting excluded ones)
     pub fn allocated_count(&self) -> usize {
-        self.allocated.count_ones()
+        self.allocated.count_ones() - self.excluded.count_ones()
     }
 
     /// Get the number of available IPs
-    #[allow(dead_code)]
     pub fn available_count(&self) -> usize {
-        self.allocated.len() - self.allocated_count()
+        self.allocated.count_zeros()
+    }
+
+    /// Get the pool size alongside allocated and available counts
+    pub fn stats(&self) -> PoolStats {
+        PoolStats {
+            total: self.allocated.len() - self.excluded.count_ones(),
+            allocated: self.allocated_count(),
+            available: self.available_count(),
+        }
     }
 }
 
@@ -146,12 +412,205 @@ mod tests {
 
         // Should have 2 available (253 - 1 allocated)
         assert_eq!(allocator.available_count(), 252);
+        assert_eq!(
+            allocator.stats(),
+            PoolStats {
+                total: 253,
+                allocated: 1,
+                available: 252,
+            }
+        );
 
         // Allocate again - should get a different IP (round-robin)
         let ip3 = allocator.allocate().unwrap();
         assert_ne!(ip3, ip1); // Round-robin, so we get the next available
     }
 
+    #[test]
+    fn test_allocate_specific() {
+        let mut allocator = IpAllocator::new();
+        let pinned = Ipv4Addr::new(192, 168, 100, 2);
+        assert_eq!(
+            allocator.allocate_specific(pinned).unwrap(),
+            IpAddr::V4(pinned)
+        );
+
+        // Round-robin continues past the pinned address
+        assert_eq!(
+            allocator.allocate().unwrap(),
+            IpAddr::V4(Ipv4Addr::new(192, 168, 100, 3))
+        );
+
+        // Pinning ahead of the cursor is skipped over by later allocations
+        let ahead = Ipv4Addr::new(192, 168, 100, 5);
+        allocator.allocate_specific(ahead).unwrap();
+        assert_eq!(
+            allocator.allocate().unwrap(),
+            IpAddr::V4(Ipv4Addr::new(192, 168, 100, 4))
+        );
+        assert_eq!(
+            allocator.allocate().unwrap(),
+            IpAddr::V4(Ipv4Addr::new(192, 168, 100, 6))
+        );
+
+        let err = allocator.allocate_specific(ahead).unwrap_err();
+        assert!(err.to_string().contains("already allocated"));
+        assert!(allocator
+            .allocate_specific(Ipv4Addr::new(192, 168, 100, 1))
+            .is_err());
+        assert!(allocator
+            .allocate_specific(Ipv4Addr::new(10, 0, 0, 2))
+            .is_err());
+
+        allocator.release(IpAddr::V4(ahead)).unwrap();
+        allocator.allocate_specific(ahead).unwrap();
+    }
+
+    #[test]
+    fn test_snapshot_and_restore() {
+        let mut allocator = IpAllocator::new();
+        let pinned: Vec<IpAddr> = [7, 3, 200]
+            .into_iter()
+            .map(|octet| IpAddr::V4(Ipv4Addr::new(192, 168, 100, octet)))
+            .collect();
+        for &ip in &pinned {
+            allocator.mark_allocated(ip).unwrap();
+        }
+        assert!(allocator
+            .mark_allocated(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 3)))
+            .is_err());
+
+        let snapshot = allocator.snapshot();
+        assert_eq!(snapshot.len(), 3);
+        assert_eq!(snapshot[0], pinned[1]);
+
+        let mut fresh = IpAllocator::new();
+        assert_eq!(fresh.restore(snapshot), 3);
+        for &ip in &pinned {
+            assert!(fresh.is_allocated(ip));
+        }
+        assert_eq!(fresh.allocated_count(), 3);
+
+        // New allocations skip the restored slots
+        assert_eq!(
+            fresh.allocate().unwrap(),
+            IpAddr::V4(Ipv4Addr::new(192, 168, 100, 2))
+        );
+        assert_eq!(
+            fresh.allocate().unwrap(),
+            IpAddr::V4(Ipv4Addr::new(192, 168, 100, 4))
+        );
+    }
+
+    #[test]
+    fn test_state_file() {
+        let dir = std::env::temp_dir().join(format!("clawpot-ips-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(STATE_FILE_NAME);
+
+        let mut allocator = IpAllocator::new().with_state_file(&path).unwrap();
+        let first = allocator.allocate().unwrap();
+        let second = allocator.allocate().unwrap();
+        allocator.release(first).unwrap();
+
+        // A restarted server picks up where the last one left off
+        let restarted = IpAllocator::new().with_state_file(&path).unwrap();
+        assert!(!restarted.is_allocated(first));
+        assert!(restarted.is_allocated(second));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_retain() {
+        let dir = std::env::temp_dir().join(format!("clawpot-ips-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(STATE_FILE_NAME);
+
+        let excluded = Ipv4Addr::new(192, 168, 100, 5);
+        let mut allocator = IpAllocator::new()
+            .with_exclusions(vec![excluded])
+            .with_state_file(&path)
+            .unwrap();
+        let kept = allocator.allocate().unwrap();
+        let dropped = allocator.allocate().unwrap();
+
+        let released = allocator.retain(&HashSet::from([kept]));
+        assert_eq!(released, vec![dropped]);
+        assert!(allocator.is_allocated(kept));
+        assert!(!allocator.is_allocated(dropped));
+        let IpAddr::V4(dropped_v4) = dropped else {
+            unreachable!()
+        };
+        allocator.allocate_specific(dropped_v4).unwrap();
+        allocator.release(dropped).unwrap();
+
+        // Excluded addresses stay out of the pool; the release is persisted
+        assert_eq!(allocator.retain(&HashSet::new()), vec![kept]);
+        let restarted = IpAllocator::new()
+            .with_exclusions(vec![excluded])
+            .with_state_file(&path)
+            .unwrap();
+        assert_eq!(restarted.allocated_count(), 0);
+        assert_eq!(restarted.available_count(), 251);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_exclusions() {
+        let excluded = [
+            Ipv4Addr::new(192, 168, 100, 5),
+            Ipv4Addr::new(192, 168, 100, 10),
+        ];
+        let mut allocator = IpAllocator::new().with_exclusions(excluded.to_vec());
+        assert_eq!(allocator.available_count(), 251);
+        assert_eq!(allocator.allocated_count(), 0);
+        assert_eq!(allocator.stats().total, 251);
+
+        // Allocate everything: the excluded addresses are never handed out
+        let ips: Vec<IpAddr> = (0..251).map(|_| allocator.allocate().unwrap()).collect();
+        assert!(allocator.allocate().is_err());
+        for ip in excluded {
+            assert!(!ips.contains(&IpAddr::V4(ip)));
+            assert!(!allocator.is_allocated(IpAddr::V4(ip)));
+        }
+        assert_eq!(allocator.available_count(), 0);
+
+        // Releasing an excluded address is refused and changes nothing
+        let err = allocator.release(IpAddr::V4(excluded[0])).unwrap_err();
+        assert!(err.to_string().contains("excluded"));
+        assert_eq!(allocator.available_count(), 0);
+        assert!(allocator.allocate().is_err());
+
+        allocator.release(ips[0]).unwrap();
+        assert!(allocator.allocate_specific(excluded[1]).is_err());
+        assert_eq!(allocator.snapshot().len(), 250);
+    }
+
+    #[test]
+    fn test_parse_exclusions() {
+        assert_eq!(
+            parse_exclusions(" 10.200.0.5, ,10.200.0.10").unwrap(),
+            vec![Ipv4Addr::new(10, 200, 0, 5), Ipv4Addr::new(10, 200, 0, 10)]
+        );
+        assert!(parse_exclusions("").unwrap().is_empty());
+        assert!(parse_exclusions("10.200.0.5,dns").is_err());
+    }
+
+    #[test]
+    fn test_is_allocated() {
+        let mut allocator = IpAllocator::new();
+        let ip = allocator.allocate().unwrap();
+        assert!(allocator.is_allocated(ip));
+
+        allocator.release(ip).unwrap();
+        assert!(!allocator.is_allocated(ip));
+
+        // Addresses outside the pool are never allocated
+        assert!(!allocator.is_allocated(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))));
+    }
+
     #[test]
     fn test_allocate_all_ips() {
         let mut allocator = IpAllocator::new();
@@ -190,5 +649,86 @@ mod tests {
             allocator.gateway(),
             IpAddr::V4(Ipv4Addr::new(192, 168, 100, 1))
         );
+        assert_eq!(
+            allocator.netmask(),
+            IpAddr::V4(Ipv4Addr::new(255, 255, 255, 0))
+        );
+    }
+
+    #[test]
+    fn test_slash_28() {
+        let mut allocator = IpAllocator::with_cidr(Ipv4Addr::new(10, 200, 0, 16), 28).unwrap();
+        assert_eq!(
+            allocator.gateway(),
+            IpAddr::V4(Ipv4Addr::new(10, 200, 0, 17))
+        );
+        assert_eq!(
+            allocator.netmask(),
+            IpAddr::V4(Ipv4Addr::new(255, 255, 255, 240))
+        );
+        assert_eq!(
+            allocator.allocatable_range(),
+            (Ipv4Addr::new(10, 200, 0, 18), Ipv4Addr::new(10, 200, 0, 30))
+        );
+
+        // .18 through .30: 13 addresses, skipping network, gateway and broadcast
+        let ips: Vec<IpAddr> = (0..13).map(|_| allocator.allocate().unwrap()).collect();
+        assert_eq!(ips[0], IpAddr::V4(Ipv4Addr::new(10, 200, 0, 18)));
+        assert_eq!(ips[12], IpAddr::V4(Ipv4Addr::new(10, 200, 0, 30)));
+        assert!(allocator.allocate().is_err());
+
+        for last_octet in [16, 17, 31, 32] {
+            let ip = IpAddr::V4(Ipv4Addr::new(10, 200, 0, last_octet));
+            assert!(allocator.release(ip).is_err(), "released {ip}");
+        }
+        allocator.release(ips[5]).unwrap();
+        assert_eq!(allocator.allocate().unwrap(), ips[5]);
+    }
+
+    #[test]
+    fn test_slash_22() {
+        let mut allocator = IpAllocator::with_cidr(Ipv4Addr::new(10, 200, 4, 0), 22).unwrap();
+        assert_eq!(
+            allocator.gateway(),
+            IpAddr::V4(Ipv4Addr::new(10, 200, 4, 1))
+        );
+        assert_eq!(
+            allocator.netmask(),
+            IpAddr::V4(Ipv4Addr::new(255, 255, 252, 0))
+        );
+        assert_eq!(allocator.available_count(), 1021);
+
+        // Allocation crosses the /24 boundaries inside the /22
+        let ips: Vec<IpAddr> = (0..1021).map(|_| allocator.allocate().unwrap()).collect();
+        assert_eq!(ips[0], IpAddr::V4(Ipv4Addr::new(10, 200, 4, 2)));
+        assert_eq!(ips[254], IpAddr::V4(Ipv4Addr::new(10, 200, 5, 0)));
+        assert_eq!(ips[1020], IpAddr::V4(Ipv4Addr::new(10, 200, 7, 254)));
+        assert!(allocator.allocate().is_err());
+
+        assert!(allocator
+            .release(IpAddr::V4(Ipv4Addr::new(10, 200, 7, 255)))
+            .is_err());
+        allocator
+            .release(IpAddr::V4(Ipv4Addr::new(10, 200, 5, 255)))
+            .unwrap();
+        assert!(!allocator.is_allocated(IpAddr::V4(Ipv4Addr::new(10, 200, 5, 255))));
+    }
+
+    #[test]
+    fn test_with_cidr_rejects_invalid() {
+        assert!(IpAllocator::with_cidr(Ipv4Addr::new(10, 200, 0, 5), 24).is_err());
+        assert!(IpAllocator::with_cidr(Ipv4Addr::new(10, 200, 0, 0), 31).is_err());
+        assert!(IpAllocator::with_cidr(Ipv4Addr::new(10, 0, 0, 0), 4).is_err());
+    }
+
+    #[test]
+    fn test_parse_cidr() {
+        assert_eq!(
+            parse_cidr("10.200.0.0/24").unwrap(),
+            (Ipv4Addr::new(10, 200, 0, 0), 24)
+        );
+        assert!(parse_cidr("10.200.0.0").is_err());
+        assert!(parse_cidr("10.200.0.0/abc").is_err());
+        assert!(parse_cidr("fd00::/64").is_err());
     }
 }
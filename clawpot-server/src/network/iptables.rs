@@ -1,20 +1,90 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
 use std::net::IpAddr;
+use std::sync::atomic::{AtomicBool, Ordering};
 use tracing::{info, warn};
 
-/// Helper to convert iptables Box<dyn Error> results into anyhow errors
-fn ipt_new() -> Result<iptables::IPTables> {
-    iptables::new(false).map_err(|e| anyhow::anyhow!("Failed to initialize iptables: {e}"))
+/// iptables handle, or a stand-in that only logs what would be run.
+enum Ipt {
+    Real(iptables::IPTables),
+    DryRun,
+}
+
+impl Ipt {
+    fn append(&self, table: &str, chain: &str, rule: &str) -> Result<()> {
+        match self {
+            Self::Real(ipt) => ipt
+                .append(table, chain, rule)
+                .map_err(|e| anyhow::anyhow!("{e}")),
+            Self::DryRun => {
+                info!("iptables (dry run): iptables -t {table} -A {chain} {rule}");
+                Ok(())
+            }
+        }
+    }
+
+    fn exists(&self, table: &str, chain: &str, rule: &str) -> Result<bool> {
+        match self {
+            Self::Real(ipt) => ipt
+                .exists(table, chain, rule)
+                .map_err(|e| anyhow::anyhow!("{e}")),
+            // Report missing so callers log the rule they would add
+            Self::DryRun => Ok(false),
+        }
+    }
+
+    fn list(&self, table: &str, chain: &str) -> Result<Vec<String>> {
+        match self {
+            Self::Real(ipt) => ipt.list(table, chain).map_err(|e| anyhow::anyhow!("{e}")),
+            Self::DryRun => Ok(Vec::new()),
+        }
+    }
+
+    fn delete(&self, table: &str, chain: &str, rule: &str) -> Result<()> {
+        match self {
+            Self::Real(ipt) => ipt
+                .delete(table, chain, rule)
+                .map_err(|e| anyhow::anyhow!("{e}")),
+            Self::DryRun => {
+                info!("iptables (dry run): iptables -t {table} -D {chain} {rule}");
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Set once at startup from `CLAWPOT_IPTABLES_DRYRUN`
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+/// Log iptables changes instead of applying them.
+///
+/// Dry-run is for exercising the networking code on non-root dev boxes; it is
+/// ignored when running as root so a stray env var can't disable the
+/// firewall on a real deployment.
+pub fn set_dry_run(requested: bool) {
+    if requested && nix::unistd::geteuid().is_root() {
+        warn!("CLAWPOT_IPTABLES_DRYRUN is ignored when running as root");
+        return;
+    }
+    DRY_RUN.store(requested, Ordering::Relaxed);
+}
+
+fn dry_run() -> bool {
+    DRY_RUN.load(Ordering::Relaxed)
+}
+
+/// Create an iptables handle (or a dry-run stand-in), converting errors into anyhow
+fn ipt_new() -> Result<Ipt> {
+    if dry_run() {
+        return Ok(Ipt::DryRun);
+    }
+    iptables::new(false)
+        .map(Ipt::Real)
+        .map_err(|e| anyhow::anyhow!("Failed to initialize iptables: {e}"))
 }
 
 /// Helper to run an iptables operation with proper error conversion
-fn ipt_append(
-    ipt: &iptables::IPTables,
-    table: &str,
-    chain: &str,
-    rule: &str,
-    description: &str,
-) -> Result<()> {
+fn ipt_append(ipt: &Ipt, table: &str, chain: &str, rule: &str, description: &str) -> Result<()> {
     ipt.append(table, chain, rule)
         .map_err(|e| anyhow::anyhow!("iptables rule '{description}' failed: {e}"))?;
     info!("iptables: {}", description);
@@ -45,7 +115,7 @@ pub fn add_source_ip_rule(tap: &str, ip: IpAddr) -> Result<()> {
 /// Remove an iptables rule for a TAP device
 /// Best-effort removal - doesn't fail if rule doesn't exist
 pub fn remove_source_ip_rule(tap: &str, ip: IpAddr) -> Result<()> {
-    let ipt = match iptables::new(false) {
+    let ipt = match ipt_new() {
         Ok(ipt) => ipt,
         Err(e) => {
             warn!("Failed to initialize iptables for rule removal: {}", e);
@@ -73,7 +143,10 @@ pub fn remove_source_ip_rule(tap: &str, ip: IpAddr) -> Result<()> {
 
 /// Add iptables rules to redirect HTTP/HTTPS traffic from the bridge to the proxy.
 /// Called once at bridge setup time, not per-VM.
-pub fn add_proxy_redirect_rules(bridge: &str) -> Result<()> {
+///
+/// With `intercept_https` off, port 443 is not redirected and falls through to
+/// the egress DROP rule.
+pub fn add_proxy_redirect_rules(bridge: &str, intercept_https: bool) -> Result<()> {
     let ipt = ipt_new()?;
 
     // Redirect HTTP (port 80) to Envoy transparent proxy
@@ -87,19 +160,24 @@ pub fn add_proxy_redirect_rules(bridge: &str) -> Result<()> {
     )?;
 
     // Redirect HTTPS (port 443) to TLS MITM proxy
-    let rule = format!("-i {bridge} -p tcp --dport 443 -j REDIRECT --to-port 10443");
-    ipt_append(
-        &ipt,
-        "nat",
-        "PREROUTING",
-        &rule,
-        "REDIRECT port 443 -> 10443",
-    )?;
+    if intercept_https {
+        ipt_append(
+            &ipt,
+            "nat",
+            "PREROUTING",
+            &https_redirect_rule(bridge),
+            "REDIRECT port 443 -> 10443",
+        )?;
+    }
 
     info!("Proxy redirect rules added for bridge {}", bridge);
     Ok(())
 }
 
+fn https_redirect_rule(bridge: &str) -> String {
+    format!("-i {bridge} -p tcp --dport 443 -j REDIRECT --to-port 10443")
+}
+
 /// Add iptables rules to redirect DNS to the proxy and block all other egress.
 /// Called once at bridge setup time.
 pub fn add_egress_filter_rules(bridge: &str) -> Result<()> {
@@ -129,26 +207,35 @@ pub fn add_egress_filter_rules(bridge: &str) -> Result<()> {
 
 /// Idempotently ensure proxy redirect rules exist.
 /// Uses iptables crate's `exists` check before appending to avoid duplicates.
-pub fn ensure_proxy_redirect_rules(bridge: &str) -> Result<()> {
+///
+/// With `intercept_https` off, a 443 redirect left by an earlier run is removed.
+pub fn ensure_proxy_redirect_rules(bridge: &str, intercept_https: bool) -> Result<()> {
     let ipt = ipt_new()?;
 
-    let rules: &[(&str, &str, String, &str)] = &[
-        (
-            "nat",
-            "PREROUTING",
-            format!("-i {bridge} -p tcp --dport 80 -j REDIRECT --to-port 10080"),
-            "REDIRECT port 80 → 10080",
-        ),
-        (
+    ensure_iptables_rule(
+        &ipt,
+        "nat",
+        "PREROUTING",
+        &format!("-i {bridge} -p tcp --dport 80 -j REDIRECT --to-port 10080"),
+        "REDIRECT port 80 → 10080",
+    )?;
+
+    let https_rule = https_redirect_rule(bridge);
+    if intercept_https {
+        ensure_iptables_rule(
+            &ipt,
             "nat",
             "PREROUTING",
-            format!("-i {bridge} -p tcp --dport 443 -j REDIRECT --to-port 10443"),
+            &https_rule,
             "REDIRECT port 443 → 10443",
-        ),
-    ];
-
-    for (table, chain, rule, desc) in rules {
-        ensure_iptables_rule(&ipt, table, chain, rule, desc)?;
+        )?;
+    } else if ipt
+        .exists("nat", "PREROUTING", &https_rule)
+        .context("iptables exists check for 443 redirect failed")?
+    {
+        ipt.delete("nat", "PREROUTING", &https_rule)
+            .context("Failed to remove 443 redirect")?;
+        info!("iptables: removed REDIRECT port 443 → 10443 (HTTPS interception disabled)");
     }
     Ok(())
 }
@@ -186,7 +273,7 @@ pub fn ensure_egress_filter_rules(bridge: &str) -> Result<()> {
 
 /// Check if an iptables rule exists, and add it if not.
 fn ensure_iptables_rule(
-    ipt: &iptables::IPTables,
+    ipt: &Ipt,
     table: &str,
     chain: &str,
     rule: &str,
@@ -206,7 +293,7 @@ fn ensure_iptables_rule(
 
 /// Remove proxy redirect and egress filter rules (best-effort, for cleanup).
 pub fn remove_proxy_rules(bridge: &str) {
-    let ipt = match iptables::new(false) {
+    let ipt = match ipt_new() {
         Ok(ipt) => ipt,
         Err(e) => {
             warn!("Failed to initialize iptables for cleanup: {}", e);
@@ -220,11 +307,7 @@ pub fn remove_proxy_rules(bridge: &str) {
             "PREROUTING",
             format!("-i {bridge} -p tcp --dport 80 -j REDIRECT --to-port 10080"),
         ),
-        (
-            "nat",
-            "PREROUTING",
-            format!("-i {bridge} -p tcp --dport 443 -j REDIRECT --to-port 10443"),
-        ),
+        ("nat", "PREROUTING", https_redirect_rule(bridge)),
         (
             "nat",
             "PREROUTING",
@@ -250,6 +333,64 @@ pub fn remove_proxy_rules(bridge: &str) {
     );
 }
 
+/// An installed iptables rule, as printed by `iptables -t <table> -S <chain>`
+pub struct InstalledRule {
+    pub table: &'static str,
+    pub chain: &'static str,
+    pub rule: String,
+}
+
+/// List the rules clawpot has installed: those matching the bridge or a VM TAP device.
+pub fn list_clawpot_rules(bridge: &str) -> Result<Vec<InstalledRule>> {
+    let ipt = ipt_new()?;
+    let mut installed = Vec::new();
+
+    for (table, chain) in [("nat", "PREROUTING"), ("filter", "FORWARD")] {
+        let rules = ipt
+            .list(table, chain)
+            .map_err(|e| anyhow::anyhow!("Failed to list {table}/{chain} rules: {e}"))?;
+        installed.extend(
+            rules
+                .into_iter()
+                .filter(|rule| is_clawpot_rule(rule, bridge))
+                .map(|rule| InstalledRule { table, chain, rule }),
+        );
+    }
+
+    Ok(installed)
+}
+
+/// Source addresses pinned to VM TAP devices by [`add_source_ip_rule`], keyed by TAP name
+pub fn tap_source_ips() -> Result<HashMap<String, IpAddr>> {
+    let ipt = ipt_new()?;
+    let rules = ipt
+        .list("filter", "FORWARD")
+        .map_err(|e| anyhow::anyhow!("Failed to list filter/FORWARD rules: {e}"))?;
+    Ok(rules
+        .iter()
+        .filter_map(|rule| parse_source_ip_rule(rule))
+        .collect())
+}
+
+/// Parse `-A FORWARD -i <tap> ! -s <ip>/32 -j DROP` into the TAP name and address
+fn parse_source_ip_rule(rule: &str) -> Option<(String, IpAddr)> {
+    let tokens: Vec<&str> = rule.split_whitespace().collect();
+    let tap = tokens
+        .windows(2)
+        .find(|w| w[0] == "-i" && w[1].starts_with("tap-"))?[1];
+    let source = tokens.windows(3).find(|w| w[0] == "!" && w[1] == "-s")?[2];
+    let ip = source.split('/').next()?.parse().ok()?;
+    Some((tap.to_string(), ip))
+}
+
+/// Whether a rule's input interface is the clawpot bridge or a VM TAP device
+fn is_clawpot_rule(rule: &str, bridge: &str) -> bool {
+    let tokens: Vec<&str> = rule.split_whitespace().collect();
+    tokens
+        .windows(2)
+        .any(|w| w[0] == "-i" && (w[1] == bridge || w[1].starts_with("tap-")))
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -267,4 +408,56 @@ mod tests {
         // Remove rule
         remove_source_ip_rule(tap, ip).expect("Failed to remove iptables rule");
     }
+
+    #[test]
+    fn test_is_clawpot_rule() {
+        assert!(is_clawpot_rule(
+            "-A PREROUTING -i br0 -p tcp -m tcp --dport 80 -j REDIRECT --to-ports 10080",
+            "br0"
+        ));
+        assert!(is_clawpot_rule(
+            "-A FORWARD -i tap-0123456789a ! -s 192.168.100.2/32 -j DROP",
+            "br0"
+        ));
+        assert!(!is_clawpot_rule("-A FORWARD -i docker0 -j ACCEPT", "br0"));
+        assert!(!is_clawpot_rule("-A FORWARD -i br01 -j ACCEPT", "br0"));
+        assert!(!is_clawpot_rule("-P FORWARD ACCEPT", "br0"));
+    }
+
+    #[test]
+    fn test_parse_source_ip_rule() {
+        assert_eq!(
+            parse_source_ip_rule("-A FORWARD -i tap-0123456789a ! -s 192.168.100.2/32 -j DROP"),
+            Some((
+                "tap-0123456789a".to_string(),
+                IpAddr::V4(Ipv4Addr::new(192, 168, 100, 2))
+            ))
+        );
+        assert_eq!(
+            parse_source_ip_rule("-A FORWARD -i br0 ! -s 192.168.100.0/24 -j DROP"),
+            None
+        );
+        assert_eq!(
+            parse_source_ip_rule("-A FORWARD -i tap-0123456789a -j ACCEPT"),
+            None
+        );
+    }
+
+    #[test]
+    fn test_dry_run_applies_nothing() {
+        if nix::unistd::geteuid().is_root() {
+            // Dry-run is ignored as root; don't touch the real firewall
+            return;
+        }
+        set_dry_run(true);
+
+        let tap = "test-tap0";
+        let ip = IpAddr::V4(Ipv4Addr::new(192, 168, 100, 2));
+        add_source_ip_rule(tap, ip).expect("dry-run add should succeed");
+        remove_source_ip_rule(tap, ip).expect("dry-run remove should succeed");
+        ensure_proxy_redirect_rules("test-br0", true).expect("dry-run ensure should succeed");
+        ensure_proxy_redirect_rules("test-br0", false).expect("dry-run ensure should succeed");
+        ensure_egress_filter_rules("test-br0").expect("dry-run ensure should succeed");
+        remove_proxy_rules("test-br0");
+    }
 }
@@ -5,6 +5,7 @@ pub mod tap;
 
 use anyhow::{Context, Result};
 use rtnetlink::Handle;
+use std::collections::HashSet;
 use std::net::IpAddr;
 use tracing::info;
 
@@ -30,10 +31,22 @@ impl NetworkManager {
     }
 
     /// Ensure the bridge exists at server startup
-    /// Creates bridge with gateway IP 192.168.100.1/24 if it doesn't exist
-    pub async fn ensure_bridge(&self) -> Result<()> {
-        let gateway_ip: IpAddr = "192.168.100.1".parse().unwrap();
-        bridge::ensure_bridge(&self.handle, &self.bridge_name, gateway_ip).await?;
+    /// Creates bridge with gateway IP `gateway_ip/prefix` if it doesn't exist.
+    /// `intercept_https` controls whether port 443 is redirected to the TLS MITM proxy.
+    pub async fn ensure_bridge(
+        &self,
+        intercept_https: bool,
+        gateway_ip: IpAddr,
+        prefix: u8,
+    ) -> Result<()> {
+        bridge::ensure_bridge(
+            &self.handle,
+            &self.bridge_name,
+            gateway_ip,
+            prefix,
+            intercept_https,
+        )
+        .await?;
         info!("Network bridge {} is ready", self.bridge_name);
         Ok(())
     }
@@ -79,6 +92,31 @@ impl NetworkManager {
         Ok(())
     }
 
+    /// Whether a TAP device with the given name currently exists
+    pub async fn tap_exists(&self, tap_name: &str) -> bool {
+        bridge::get_link_index(&self.handle, tap_name).await.is_ok()
+    }
+
+    /// List VM TAP devices present on the host (registered or not)
+    pub async fn list_vm_taps(&self) -> Result<Vec<String>> {
+        tap::list_taps(&self.handle, "tap-").await
+    }
+
+    /// Addresses pinned to VM TAP devices that still exist on the host
+    pub async fn leftover_vm_ips(&self) -> Result<HashSet<IpAddr>> {
+        let taps: HashSet<String> = self.list_vm_taps().await?.into_iter().collect();
+        Ok(iptables::tap_source_ips()?
+            .into_iter()
+            .filter(|(tap, _)| taps.contains(tap))
+            .map(|(_, ip)| ip)
+            .collect())
+    }
+
+    /// Delete a TAP device whose owning VM (and IP) is no longer known
+    pub async fn delete_orphan_tap(&self, tap_name: &str) -> Result<()> {
+        tap::delete_tap(&self.handle, tap_name).await
+    }
+
     /// Get the bridge name
     pub fn bridge_name(&self) -> &str {
         &self.bridge_name
@@ -1,5 +1,7 @@
 use anyhow::{Context, Result};
+use futures_util::stream::TryStreamExt;
 use nix::libc;
+use rtnetlink::packet_route::link::LinkAttribute;
 use rtnetlink::{Handle, LinkUnspec};
 use std::fs::OpenOptions;
 use std::os::unix::io::AsRawFd;
@@ -120,6 +122,24 @@ pub async fn delete_tap(handle: &Handle, name: &str) -> Result<()> {
     Ok(())
 }
 
+/// List the names of all network devices whose name starts with `prefix`
+pub async fn list_taps(handle: &Handle, prefix: &str) -> Result<Vec<String>> {
+    let mut links = handle.link().get().execute();
+    let mut names = Vec::new();
+
+    while let Some(link) = links.try_next().await.context("Failed to list links")? {
+        for attr in link.attributes {
+            if let LinkAttribute::IfName(name) = attr {
+                if name.starts_with(prefix) {
+                    names.push(name);
+                }
+            }
+        }
+    }
+
+    Ok(names)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
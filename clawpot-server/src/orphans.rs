@@ -0,0 +1,124 @@
+use crate::network::NetworkManager;
+use crate::vm::VmRegistry;
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use tracing::info;
+
+/// Directory where Firecracker API and vsock sockets are created
+const SOCKET_DIR: &str = "/tmp";
+
+/// Kind of host resource left behind by a VM that no longer exists
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrphanKind {
+    TapDevice,
+    Socket,
+}
+
+/// A host resource that no registered VM owns
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Orphan {
+    pub kind: OrphanKind,
+    pub name: String,
+}
+
+/// Find TAP devices and Firecracker sockets not owned by any registered VM.
+///
+/// These are typically left behind by a server crash or a failed delete.
+pub async fn find_orphans(
+    registry: &VmRegistry,
+    network_manager: &NetworkManager,
+) -> Result<Vec<Orphan>> {
+    let (owned_taps, owned_sockets) = registry.owned_resources().await;
+
+    let mut orphans: Vec<Orphan> = network_manager
+        .list_vm_taps()
+        .await?
+        .into_iter()
+        .filter(|name| !owned_taps.contains(name))
+        .map(|name| Orphan {
+            kind: OrphanKind::TapDevice,
+            name,
+        })
+        .collect();
+
+    orphans.extend(
+        find_orphan_sockets(Path::new(SOCKET_DIR), &owned_sockets)?
+            .into_iter()
+            .map(|path| Orphan {
+                kind: OrphanKind::Socket,
+                name: path.display().to_string(),
+            }),
+    );
+
+    Ok(orphans)
+}
+
+/// Remove a single orphaned resource
+pub async fn clean_orphan(network_manager: &NetworkManager, orphan: &Orphan) -> Result<()> {
+    match orphan.kind {
+        OrphanKind::TapDevice => network_manager.delete_orphan_tap(&orphan.name).await?,
+        OrphanKind::Socket => std::fs::remove_file(&orphan.name)
+            .with_context(|| format!("Failed to remove socket {}", orphan.name))?,
+    }
+    info!("Cleaned orphaned resource {}", orphan.name);
+    Ok(())
+}
+
+/// Find `fc-*.sock` files in `dir` that are neither owned nor accepting connections.
+fn find_orphan_sockets(dir: &Path, owned: &HashSet<PathBuf>) -> Result<Vec<PathBuf>> {
+    let mut orphans = Vec::new();
+
+    let entries = std::fs::read_dir(dir)
+        .with_context(|| format!("Failed to read directory {}", dir.display()))?;
+    for entry in entries {
+        let path = entry?.path();
+        let is_fc_socket = path
+            .file_name()
+            .and_then(|n| n.to_str())
+            .is_some_and(|n| n.starts_with("fc-") && n.ends_with(".sock"));
+        if !is_fc_socket || owned.contains(&path) {
+            continue;
+        }
+
+        // A live Firecracker process still accepts connections on its socket
+        if UnixStream::connect(&path).is_err() {
+            orphans.push(path);
+        }
+    }
+
+    orphans.sort();
+    Ok(orphans)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::net::UnixListener;
+
+    #[test]
+    fn test_find_orphan_sockets() {
+        let dir = tempfile::tempdir().unwrap();
+
+        // Stale socket: the listener is gone but the file remains
+        let stale = dir.path().join("fc-stale.sock");
+        drop(UnixListener::bind(&stale).unwrap());
+
+        // Live socket: still has a listener
+        let live = dir.path().join("fc-live.sock");
+        let _listener = UnixListener::bind(&live).unwrap();
+
+        // Owned socket: belongs to a registered VM even if nothing listens
+        let owned = dir.path().join("fc-owned-vsock.sock");
+        drop(UnixListener::bind(&owned).unwrap());
+
+        // Unrelated file
+        std::fs::write(dir.path().join("other.sock"), "").unwrap();
+
+        let owned_set = HashSet::from([owned]);
+        let orphans = find_orphan_sockets(dir.path(), &owned_set).unwrap();
+
+        assert_eq!(orphans, vec![stale]);
+    }
+}
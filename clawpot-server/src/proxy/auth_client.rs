@@ -1,14 +1,111 @@
+use super::throttle::warn_throttled;
 use anyhow::{Context, Result};
 use clawpot_common::network_auth_proto::{
     network_authorization_request,
     network_authorization_service_client::NetworkAuthorizationServiceClient, DnsRequest,
-    HttpRequest, NetworkAuthorizationRequest,
+    HttpRequest, NetworkAuthorizationRequest, NetworkAuthorizationResponse,
 };
 use tonic::transport::Channel;
-use tracing::{info, warn};
+use tonic::{Code, Status};
+use tracing::info;
 
 const MAX_BODY_FOR_GRPC: usize = 1024 * 1024; // 1MB
 
+/// Why traffic was denied, recorded as `deny_code` alongside the free-form
+/// reason so denials can be grouped.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DenyCode {
+    /// The authorization service said no
+    PolicyDenied,
+    /// The authorization service could not be reached or errored
+    AuthUnreachable,
+    /// The authorization service is shedding load
+    RateLimited,
+    /// The source IP does not belong to a registered VM
+    UnknownVm,
+    /// The authorization service did not answer in time
+    Timeout,
+    /// The DNS name is outside `CLAWPOT_DNS_ALLOWLIST`
+    NotAllowlisted,
+}
+
+impl DenyCode {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            DenyCode::PolicyDenied => "policy_denied",
+            DenyCode::AuthUnreachable => "auth_unreachable",
+            DenyCode::RateLimited => "rate_limited",
+            DenyCode::UnknownVm => "unknown_vm",
+            DenyCode::Timeout => "timeout",
+            DenyCode::NotAllowlisted => "not_allowlisted",
+        }
+    }
+
+    /// Classify a failed call to the authorization service.
+    fn from_status(status: &Status) -> Self {
+        match status.code() {
+            Code::DeadlineExceeded => DenyCode::Timeout,
+            Code::ResourceExhausted => DenyCode::RateLimited,
+            _ => DenyCode::AuthUnreachable,
+        }
+    }
+}
+
+/// Outcome of an authorization check.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuthDecision {
+    pub allowed: bool,
+    pub reason: String,
+    /// Set whenever `allowed` is false
+    pub deny_code: Option<DenyCode>,
+}
+
+impl AuthDecision {
+    pub fn allow(reason: impl Into<String>) -> Self {
+        Self {
+            allowed: true,
+            reason: reason.into(),
+            deny_code: None,
+        }
+    }
+
+    pub fn deny(code: DenyCode, reason: impl Into<String>) -> Self {
+        Self {
+            allowed: false,
+            reason: reason.into(),
+            deny_code: Some(code),
+        }
+    }
+
+    /// `deny_code` as recorded in events (null when allowed)
+    pub fn deny_code_str(&self) -> Option<&'static str> {
+        self.deny_code.map(DenyCode::as_str)
+    }
+
+    /// Interpret the authorization service's answer, or the error calling it.
+    fn from_response(
+        result: Result<tonic::Response<NetworkAuthorizationResponse>, Status>,
+    ) -> Self {
+        match result {
+            Ok(resp) => {
+                let resp = resp.into_inner();
+                if resp.allow {
+                    Self::allow(resp.reason)
+                } else {
+                    Self::deny(DenyCode::PolicyDenied, resp.reason)
+                }
+            }
+            Err(e) => {
+                warn_throttled("Auth service call failed (denying)", "auth service", &e);
+                Self::deny(
+                    DenyCode::from_status(&e),
+                    format!("auth service unreachable: {e}"),
+                )
+            }
+        }
+    }
+}
+
 /// Client for the external Python authorization service.
 /// If no address is configured, all requests are allowed.
 pub enum AuthClient {
@@ -31,7 +128,7 @@ impl AuthClient {
         }
     }
 
-    /// Authorize an HTTP request. Returns (allowed, reason).
+    /// Authorize an HTTP request. Returns the decision.
     pub async fn authorize_http(
         &self,
         request_id: i64,
@@ -40,9 +137,9 @@ impl AuthClient {
         url: &str,
         headers: &std::collections::HashMap<String, String>,
         body: &[u8],
-    ) -> Result<(bool, String)> {
+    ) -> Result<AuthDecision> {
         match self {
-            AuthClient::Disabled => Ok((true, "authorization disabled".to_string())),
+            AuthClient::Disabled => Ok(AuthDecision::allow("authorization disabled")),
             AuthClient::Connected(client) => {
                 let truncated = body.len() > MAX_BODY_FOR_GRPC;
                 let body_bytes = if truncated {
@@ -65,30 +162,21 @@ impl AuthClient {
                 };
 
                 let mut client = client.clone();
-                match client.authorize(request).await {
-                    Ok(resp) => {
-                        let resp = resp.into_inner();
-                        Ok((resp.allow, resp.reason))
-                    }
-                    Err(e) => {
-                        warn!("Auth service call failed (denying): {}", e);
-                        Ok((false, format!("auth service unreachable: {e}")))
-                    }
-                }
+                Ok(AuthDecision::from_response(client.authorize(request).await))
             }
         }
     }
 
-    /// Authorize a DNS request. Returns (allowed, reason).
+    /// Authorize a DNS request. Returns the decision.
     pub async fn authorize_dns(
         &self,
         request_id: i64,
         vm_id: &str,
         query_name: &str,
         query_type: &str,
-    ) -> Result<(bool, String)> {
+    ) -> Result<AuthDecision> {
         match self {
-            AuthClient::Disabled => Ok((true, "authorization disabled".to_string())),
+            AuthClient::Disabled => Ok(AuthDecision::allow("authorization disabled")),
             AuthClient::Connected(client) => {
                 let request = NetworkAuthorizationRequest {
                     request_id: request_id.to_string(),
@@ -101,17 +189,44 @@ impl AuthClient {
                 };
 
                 let mut client = client.clone();
-                match client.authorize(request).await {
-                    Ok(resp) => {
-                        let resp = resp.into_inner();
-                        Ok((resp.allow, resp.reason))
-                    }
-                    Err(e) => {
-                        warn!("Auth service call failed (denying): {}", e);
-                        Ok((false, format!("auth service unreachable: {e}")))
-                    }
-                }
+                Ok(AuthDecision::from_response(client.authorize(request).await))
             }
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decision_from_response() {
+        let denied =
+            AuthDecision::from_response(Ok(tonic::Response::new(NetworkAuthorizationResponse {
+                allow: false,
+                reason: "blocked by policy".to_string(),
+            })));
+        assert_eq!(
+            denied,
+            AuthDecision::deny(DenyCode::PolicyDenied, "blocked by policy")
+        );
+
+        let allowed =
+            AuthDecision::from_response(Ok(tonic::Response::new(NetworkAuthorizationResponse {
+                allow: true,
+                ..Default::default()
+            })));
+        assert!(allowed.allowed);
+        assert_eq!(allowed.deny_code_str(), None);
+
+        for (status, code) in [
+            (Status::deadline_exceeded("slow"), DenyCode::Timeout),
+            (Status::resource_exhausted("busy"), DenyCode::RateLimited),
+            (Status::unavailable("down"), DenyCode::AuthUnreachable),
+        ] {
+            let decision = AuthDecision::from_response(Err(status));
+            assert!(!decision.allowed);
+            assert_eq!(decision.deny_code, Some(code));
+        }
+    }
+}
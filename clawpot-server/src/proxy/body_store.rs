@@ -1,8 +1,20 @@
-use anyhow::{Context, Result};
+use crate::events::EventStore;
+use anyhow::{bail, Context, Result};
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use tokio::fs::File;
+use tokio::io::{AsyncWriteExt, BufWriter};
+use tracing::warn;
 
 const DEFAULT_INLINE_THRESHOLD: usize = 64 * 1024; // 64KB
 
+/// Environment variable setting the disk usage, in bytes, at which a
+/// `body_store.high_usage` event is emitted. 0 disables the alert.
+pub const HIGH_USAGE_ENV: &str = "CLAWPOT_BODY_STORE_HIGH_USAGE_BYTES";
+
+/// Alert threshold used when `CLAWPOT_BODY_STORE_HIGH_USAGE_BYTES` is unset
+pub const DEFAULT_HIGH_USAGE_BYTES: u64 = 1024 * 1024 * 1024; // 1GB
+
 pub enum StoredBody {
     Inline(Vec<u8>),
     External(PathBuf),
@@ -25,9 +37,52 @@ impl StoredBody {
     }
 }
 
+/// A body written to disk as it streams through the proxy, for bodies too
+/// large to hold in memory. Finish it with [`BodyStore::finish_spool`].
+pub struct BodySpool {
+    path: PathBuf,
+    file: BufWriter<File>,
+    written: u64,
+    /// First write error; later writes are skipped
+    error: Option<std::io::Error>,
+}
+
+impl BodySpool {
+    pub async fn write(&mut self, data: &[u8]) {
+        if self.error.is_some() {
+            return;
+        }
+        match self.file.write_all(data).await {
+            Ok(()) => self.written += data.len() as u64,
+            Err(e) => self.error = Some(e),
+        }
+    }
+}
+
 pub struct BodyStore {
     storage_dir: PathBuf,
     inline_threshold: usize,
+    /// Why bodies can't be written to disk; set in inline-only mode
+    degraded: Option<String>,
+    /// Bytes of externalized bodies on disk
+    bytes: AtomicU64,
+    /// Number of externalized body files on disk
+    files: AtomicU64,
+    high_usage: Option<HighUsageAlert>,
+}
+
+/// Emits `body_store.high_usage` the first time usage reaches `threshold`.
+struct HighUsageAlert {
+    events: EventStore,
+    threshold: u64,
+    fired: AtomicBool,
+}
+
+/// Disk used by externalized bodies
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BodyStoreUsage {
+    pub bytes: u64,
+    pub files: u64,
 }
 
 impl BodyStore {
@@ -38,22 +93,285 @@ impl BodyStore {
                 storage_dir.display()
             )
         })?;
+
+        // An existing directory may still be read-only
+        let probe = storage_dir.join(".write-test");
+        std::fs::write(&probe, b"")
+            .and_then(|()| std::fs::remove_file(&probe))
+            .with_context(|| {
+                format!(
+                    "Body storage dir is not writable: {}",
+                    storage_dir.display()
+                )
+            })?;
+
+        // Count bodies left by previous runs so usage reflects the disk
+        let (bytes, files) = std::fs::read_dir(storage_dir)
+            .with_context(|| format!("Failed to read {}", storage_dir.display()))?
+            .filter_map(|entry| entry.ok()?.metadata().ok())
+            .filter(std::fs::Metadata::is_file)
+            .fold((0, 0), |(bytes, files), meta| {
+                (bytes + meta.len(), files + 1)
+            });
+
         Ok(Self {
             storage_dir: storage_dir.to_path_buf(),
             inline_threshold: DEFAULT_INLINE_THRESHOLD,
+            degraded: None,
+            bytes: AtomicU64::new(bytes),
+            files: AtomicU64::new(files),
+            high_usage: None,
         })
     }
 
+    /// Emit a `body_store.high_usage` event once disk usage reaches
+    /// `threshold` bytes.
+    #[must_use]
+    pub fn with_high_usage_alert(mut self, events: EventStore, threshold: u64) -> Self {
+        self.high_usage = Some(HighUsageAlert {
+            events,
+            threshold,
+            fired: AtomicBool::new(false),
+        });
+        self.check_high_usage();
+        self
+    }
+
+    /// Like [`BodyStore::new`], but if the directory can't be used, fall back
+    /// to inline-only mode instead of failing: buffered bodies are then kept
+    /// inline in the event database whatever their size, and streamed bodies
+    /// (which [`BodyStore::spool`] refuses) aren't recorded.
+    pub fn new_or_inline_only(storage_dir: &Path) -> Self {
+        Self::new(storage_dir).unwrap_or_else(|e| {
+            warn!(
+                "Body storage degraded to inline-only, storing all bodies in the event database: {:#}",
+                e
+            );
+            Self {
+                storage_dir: storage_dir.to_path_buf(),
+                inline_threshold: DEFAULT_INLINE_THRESHOLD,
+                degraded: Some(format!("{e:#}")),
+                bytes: AtomicU64::new(0),
+                files: AtomicU64::new(0),
+                high_usage: None,
+            }
+        })
+    }
+
+    /// Why the store is in inline-only mode, or `None` if it is fully working.
+    pub fn degraded(&self) -> Option<&str> {
+        self.degraded.as_deref()
+    }
+
+    /// Disk currently used by externalized bodies.
+    pub fn usage(&self) -> BodyStoreUsage {
+        BodyStoreUsage {
+            bytes: self.bytes.load(Ordering::Relaxed),
+            files: self.files.load(Ordering::Relaxed),
+        }
+    }
+
     /// Store a body, either inline or externalized to disk.
     /// `suffix` should be "req" or "resp".
     pub fn store(&self, request_id: i64, suffix: &str, body: &[u8]) -> Result<StoredBody> {
-        if body.len() <= self.inline_threshold {
+        if body.len() <= self.inline_threshold || self.degraded.is_some() {
             return Ok(StoredBody::Inline(body.to_vec()));
         }
 
         let path = self.storage_dir.join(format!("{request_id}_{suffix}.bin"));
+        let replaced = std::fs::metadata(&path).ok().map(|meta| meta.len());
         std::fs::write(&path, body)
             .with_context(|| format!("Failed to write body to {}", path.display()))?;
+
+        // Bodies can overwrite an earlier file with the same name
+        match replaced {
+            Some(old) => {
+                self.bytes.fetch_sub(old, Ordering::Relaxed);
+            }
+            None => {
+                self.files.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        self.bytes.fetch_add(body.len() as u64, Ordering::Relaxed);
+        self.check_high_usage();
+
         Ok(StoredBody::External(path))
     }
+
+    /// Start writing a body straight to disk as `{name}_{suffix}.bin`, for
+    /// bodies streamed through instead of buffered. Fails in inline-only mode.
+    pub fn spool(&self, name: &str, suffix: &str) -> Result<BodySpool> {
+        if let Some(reason) = &self.degraded {
+            bail!("Body store is inline-only: {reason}");
+        }
+        let path = self.storage_dir.join(format!("{name}_{suffix}.bin"));
+        let file = std::fs::File::create(&path)
+            .with_context(|| format!("Failed to create body file {}", path.display()))?;
+        self.files.fetch_add(1, Ordering::Relaxed);
+        Ok(BodySpool {
+            path,
+            file: BufWriter::new(File::from_std(file)),
+            written: 0,
+            error: None,
+        })
+    }
+
+    /// Flush a spooled body and return where it was written. What was
+    /// written counts towards usage even if a write failed.
+    pub async fn finish_spool(&self, mut spool: BodySpool) -> Result<PathBuf> {
+        let flushed = spool.file.flush().await;
+        self.bytes.fetch_add(spool.written, Ordering::Relaxed);
+        self.check_high_usage();
+        if let Some(e) = spool.error {
+            return Err(e).with_context(|| format!("Failed to write {}", spool.path.display()));
+        }
+        flushed.with_context(|| format!("Failed to write {}", spool.path.display()))?;
+        Ok(spool.path)
+    }
+
+    /// Delete a body file, e.g. once the events referring to it are pruned.
+    /// Only files in this store's directory are deleted.
+    pub fn remove(&self, path: &Path) -> Result<()> {
+        if path.parent() != Some(self.storage_dir.as_path()) {
+            bail!("{} is not in the body store", path.display());
+        }
+        let size = std::fs::metadata(path)
+            .with_context(|| format!("Failed to stat {}", path.display()))?
+            .len();
+        std::fs::remove_file(path)
+            .with_context(|| format!("Failed to remove {}", path.display()))?;
+        self.bytes.fetch_sub(size, Ordering::Relaxed);
+        self.files.fetch_sub(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    fn check_high_usage(&self) {
+        let Some(alert) = &self.high_usage else {
+            return;
+        };
+        let usage = self.usage();
+        if usage.bytes < alert.threshold || alert.fired.swap(true, Ordering::Relaxed) {
+            return;
+        }
+        warn!(
+            "Body store {} holds {} bytes in {} files (threshold {})",
+            self.storage_dir.display(),
+            usage.bytes,
+            usage.files,
+            alert.threshold
+        );
+        alert.events.emit(
+            "body_store.high_usage",
+            "server",
+            None,
+            None,
+            &serde_json::json!({
+                "dir": self.storage_dir.to_string_lossy(),
+                "bytes": usage.bytes,
+                "files": usage.files,
+                "threshold_bytes": alert.threshold,
+            }),
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_inline_only_when_dir_unusable() {
+        let dir = std::env::temp_dir().join(format!("clawpot-bodies-{}", uuid::Uuid::new_v4()));
+        std::fs::create_dir_all(&dir).unwrap();
+
+        let store = BodyStore::new_or_inline_only(&dir.join("bodies"));
+        assert!(store.degraded().is_none());
+        let large = vec![b'x'; DEFAULT_INLINE_THRESHOLD + 1];
+        assert!(store
+            .store(1, "req", &large)
+            .unwrap()
+            .external_path()
+            .is_some());
+
+        // A regular file where the directory should be can't be created
+        let blocked = dir.join("file");
+        std::fs::write(&blocked, b"").unwrap();
+        let store = BodyStore::new_or_inline_only(&blocked.join("bodies"));
+        assert!(store.degraded().is_some());
+        assert!(store
+            .store(1, "req", &large)
+            .unwrap()
+            .inline_bytes()
+            .is_some());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_usage_tracking() {
+        let dir = std::env::temp_dir().join(format!("clawpot-bodies-{}", uuid::Uuid::new_v4()));
+        let store = BodyStore::new(&dir).unwrap();
+        let large = vec![b'x'; DEFAULT_INLINE_THRESHOLD + 1];
+        let size = large.len() as u64;
+
+        store.store(1, "req", &large).unwrap();
+        store.store(1, "resp", &large).unwrap();
+        store
+            .store(1, "req", &large[..DEFAULT_INLINE_THRESHOLD])
+            .unwrap(); // inline
+        assert_eq!(
+            store.usage(),
+            BodyStoreUsage {
+                bytes: 2 * size,
+                files: 2
+            }
+        );
+
+        // Overwriting a file replaces its size rather than adding to it
+        let larger = vec![b'y'; large.len() + 10];
+        store.store(1, "req", &larger).unwrap();
+        assert_eq!(store.usage().bytes, 2 * size + 10);
+        assert_eq!(store.usage().files, 2);
+
+        // A new store picks up what is already on disk
+        assert_eq!(BodyStore::new(&dir).unwrap().usage(), store.usage());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_spool() {
+        let dir = std::env::temp_dir().join(format!("clawpot-bodies-{}", uuid::Uuid::new_v4()));
+        let store = BodyStore::new(&dir).unwrap();
+
+        let mut spool = store.spool("corr-1", "resp").unwrap();
+        for chunk in [&b"hello "[..], b"spooled ", b"world"] {
+            spool.write(chunk).await;
+        }
+        let path = store.finish_spool(spool).await.unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), b"hello spooled world");
+        assert_eq!(
+            store.usage(),
+            BodyStoreUsage {
+                bytes: 19,
+                files: 1
+            }
+        );
+
+        // Inline-only stores can't spool
+        let blocked = dir.join("corr-1_resp.bin");
+        assert!(BodyStore::new_or_inline_only(&blocked.join("bodies"))
+            .spool("corr-2", "req")
+            .is_err());
+
+        // Removing a body gives its space back; files elsewhere are left alone
+        assert!(store
+            .remove(&std::env::temp_dir().join("outside.bin"))
+            .is_err());
+        store.remove(&path).unwrap();
+        assert!(!path.exists());
+        assert_eq!(store.usage(), BodyStoreUsage { bytes: 0, files: 0 });
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
 }
@@ -4,18 +4,41 @@ use std::collections::HashMap;
 use std::path::{Path, PathBuf};
 use std::sync::Arc;
 use tokio::sync::Mutex;
-use tracing::info;
+use tracing::{info, warn};
+
+/// Subdirectory of the CA directory holding previous CAs that guests may
+/// still trust while images are re-provisioned after a rotation
+const TRUSTED_DIR: &str = "trusted";
+
+/// File in the CA directory that all trusted CA certificates are exported to
+pub const BUNDLE_FILE: &str = "trust-bundle.crt";
 
 /// Certificate authority that generates per-domain leaf certificates for TLS MITM.
+///
+/// Leaves are always signed by the primary CA (`ca.crt`/`ca.key`). To rotate
+/// it, move both files into `trusted/` (keeping their names' stems paired,
+/// e.g. `trusted/2024.crt` and `trusted/2024.key`) and restart: a new
+/// primary is generated, and the old CA cross-signs it so guests that only
+/// trust the old CA still validate the served chain. Once every image has
+/// been re-provisioned, delete the old files from `trusted/`.
 #[allow(dead_code)]
 pub struct CertificateAuthority {
     ca_cert: rcgen::Certificate,
     ca_key: KeyPair,
     ca_cert_pem: String,
+    additional: Vec<AdditionalCa>,
     cache: Arc<Mutex<HashMap<String, CachedCert>>>,
     ca_dir: PathBuf,
 }
 
+/// A previous CA that guests may still trust.
+struct AdditionalCa {
+    cert_pem: String,
+    /// The primary CA certificate cross-signed by this CA, if its key is
+    /// available. Served after the primary so either trust anchor works.
+    cross_cert_pem: Option<String>,
+}
+
 /// A cached leaf certificate and its private key.
 #[derive(Clone)]
 pub struct CachedCert {
@@ -23,9 +46,21 @@ pub struct CachedCert {
     pub key_pem: String,
 }
 
+/// Parameters shared by every clawpot CA certificate.
+fn ca_params() -> CertificateParams {
+    let mut params = CertificateParams::default();
+    params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let mut dn = DistinguishedName::new();
+    dn.push(DnType::CommonName, "Clawpot MITM CA");
+    dn.push(DnType::OrganizationName, "Clawpot");
+    params.distinguished_name = dn;
+    params
+}
+
 impl CertificateAuthority {
     /// Create or load a CA from the given directory.
     /// If ca.crt and ca.key exist, loads them; otherwise generates new ones.
+    /// Previous CAs in `trusted/` are loaded as additional trust anchors.
     pub fn new(ca_dir: &Path) -> Result<Self> {
         std::fs::create_dir_all(ca_dir)
             .with_context(|| format!("Failed to create CA directory: {}", ca_dir.display()))?;
@@ -45,14 +80,7 @@ impl CertificateAuthority {
             // loading existing certs, but the key is what matters for signing).
             // We keep the original on-disk cert for the TLS chain so it matches
             // the cert injected into VM trust stores by setup-rootfs.sh.
-            let mut params = CertificateParams::default();
-            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
-            let mut dn = DistinguishedName::new();
-            dn.push(DnType::CommonName, "Clawpot MITM CA");
-            dn.push(DnType::OrganizationName, "Clawpot");
-            params.distinguished_name = dn;
-
-            let ca_cert = params
+            let ca_cert = ca_params()
                 .self_signed(&ca_key)
                 .context("Failed to self-sign CA cert")?;
 
@@ -61,14 +89,7 @@ impl CertificateAuthority {
             info!("Generating new CA certificate in {}", ca_dir.display());
             let ca_key = KeyPair::generate().context("Failed to generate CA key pair")?;
 
-            let mut params = CertificateParams::default();
-            params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
-            let mut dn = DistinguishedName::new();
-            dn.push(DnType::CommonName, "Clawpot MITM CA");
-            dn.push(DnType::OrganizationName, "Clawpot");
-            params.distinguished_name = dn;
-
-            let ca_cert = params
+            let ca_cert = ca_params()
                 .self_signed(&ca_key)
                 .context("Failed to self-sign CA cert")?;
 
@@ -82,13 +103,22 @@ impl CertificateAuthority {
             (ca_cert, ca_key, ca_cert_pem)
         };
 
-        Ok(Self {
+        let additional = load_additional(&ca_dir.join(TRUSTED_DIR), &ca_key)?;
+
+        let ca = Self {
             ca_cert,
             ca_key,
             ca_cert_pem,
+            additional,
             cache: Arc::new(Mutex::new(HashMap::new())),
             ca_dir: ca_dir.to_path_buf(),
-        })
+        };
+
+        let bundle_path = ca_dir.join(BUNDLE_FILE);
+        std::fs::write(&bundle_path, ca.trust_bundle_pem())
+            .with_context(|| format!("Failed to write {}", bundle_path.display()))?;
+
+        Ok(ca)
     }
 
     /// Get the CA certificate PEM string (for injecting into rootfs trust store).
@@ -96,6 +126,36 @@ impl CertificateAuthority {
         &self.ca_cert_pem
     }
 
+    /// Every CA guests may trust, primary first, as one PEM bundle. Written
+    /// to `trust-bundle.crt` for injecting into rootfs trust stores.
+    pub fn trust_bundle_pem(&self) -> String {
+        let mut bundle = self.ca_cert_pem.clone();
+        for ca in &self.additional {
+            if !bundle.ends_with('\n') {
+                bundle.push('\n');
+            }
+            bundle.push_str(&ca.cert_pem);
+        }
+        bundle
+    }
+
+    /// CA certificates served after the leaf: the primary, then the primary
+    /// cross-signed by each previous CA so guests trusting either validate.
+    pub fn chain_pem(&self) -> String {
+        let mut chain = self.ca_cert_pem.clone();
+        for cross in self
+            .additional
+            .iter()
+            .filter_map(|ca| ca.cross_cert_pem.as_ref())
+        {
+            if !chain.ends_with('\n') {
+                chain.push('\n');
+            }
+            chain.push_str(cross);
+        }
+        chain
+    }
+
     /// Get the CA directory path.
     #[allow(dead_code)]
     pub fn ca_dir(&self) -> &Path {
@@ -117,6 +177,8 @@ impl CertificateAuthority {
         let mut dn = DistinguishedName::new();
         dn.push(DnType::CommonName, domain);
         params.distinguished_name = dn;
+        // Lets clients pick the right issuer when old and new CAs share a name
+        params.use_authority_key_identifier_extension = true;
 
         let leaf_cert = params
             .signed_by(&leaf_key, &self.ca_cert, &self.ca_key)
@@ -131,3 +193,121 @@ impl CertificateAuthority {
         Ok(cached)
     }
 }
+
+/// Load previous CAs from `dir`. Each `<name>.crt` is trusted; if a matching
+/// `<name>.key` exists, it is used to cross-sign the primary CA.
+fn load_additional(dir: &Path, primary_key: &KeyPair) -> Result<Vec<AdditionalCa>> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e).with_context(|| format!("Failed to read {}", dir.display())),
+    };
+    let mut cert_paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().is_some_and(|ext| ext == "crt"))
+        .collect();
+    cert_paths.sort();
+
+    let mut additional = Vec::new();
+    for cert_path in cert_paths {
+        let cert_pem = std::fs::read_to_string(&cert_path)
+            .with_context(|| format!("Failed to read {}", cert_path.display()))?;
+
+        let key_path = cert_path.with_extension("key");
+        let cross_cert_pem = if key_path.exists() {
+            let key_pem = std::fs::read_to_string(&key_path)
+                .with_context(|| format!("Failed to read {}", key_path.display()))?;
+            Some(
+                cross_sign(primary_key, &key_pem)
+                    .with_context(|| format!("Failed to cross-sign with {}", key_path.display()))?,
+            )
+        } else {
+            warn!(
+                "No key for trusted CA {}; guests trusting only it will reject the served chain",
+                cert_path.display()
+            );
+            None
+        };
+
+        info!("Loaded additional trusted CA {}", cert_path.display());
+        additional.push(AdditionalCa {
+            cert_pem,
+            cross_cert_pem,
+        });
+    }
+    Ok(additional)
+}
+
+/// Issue a CA certificate for `primary_key` signed by the CA whose key is
+/// `issuer_key_pem`.
+fn cross_sign(primary_key: &KeyPair, issuer_key_pem: &str) -> Result<String> {
+    let issuer_key = KeyPair::from_pem(issuer_key_pem).context("Failed to parse CA key")?;
+    let issuer = ca_params()
+        .self_signed(&issuer_key)
+        .context("Failed to self-sign CA cert")?;
+
+    let mut params = ca_params();
+    params.use_authority_key_identifier_extension = true;
+    let cross = params
+        .signed_by(primary_key, &issuer, &issuer_key)
+        .context("Failed to sign cross certificate")?;
+    Ok(cross.pem())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_dir() -> PathBuf {
+        std::env::temp_dir().join(format!("clawpot-ca-{}", uuid::Uuid::new_v4()))
+    }
+
+    fn count_certs(pem: &str) -> usize {
+        pem.matches("-----BEGIN CERTIFICATE-----").count()
+    }
+
+    #[test]
+    fn test_rotation_keeps_old_ca_trusted() {
+        let dir = temp_dir();
+        let old = CertificateAuthority::new(&dir).unwrap();
+        let old_pem = old.ca_cert_pem().to_string();
+        assert_eq!(count_certs(&old.chain_pem()), 1);
+
+        // Rotate: retire the current CA into trusted/ and start a new one
+        std::fs::create_dir_all(dir.join(TRUSTED_DIR)).unwrap();
+        for file in ["crt", "key"] {
+            std::fs::rename(
+                dir.join(format!("ca.{file}")),
+                dir.join(TRUSTED_DIR).join(format!("old.{file}")),
+            )
+            .unwrap();
+        }
+        let new = CertificateAuthority::new(&dir).unwrap();
+        assert_ne!(new.ca_cert_pem(), old_pem);
+
+        // Both CAs are exported; the chain carries the cross-signed primary
+        let bundle = std::fs::read_to_string(dir.join(BUNDLE_FILE)).unwrap();
+        assert_eq!(bundle, new.trust_bundle_pem());
+        assert_eq!(count_certs(&bundle), 2);
+        assert!(bundle.contains(old_pem.trim()));
+        assert_eq!(count_certs(&new.chain_pem()), 2);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn test_trusted_ca_without_key() {
+        let dir = temp_dir();
+        let other_dir = temp_dir();
+        let other = CertificateAuthority::new(&other_dir).unwrap();
+        std::fs::create_dir_all(dir.join(TRUSTED_DIR)).unwrap();
+        std::fs::write(dir.join(TRUSTED_DIR).join("other.crt"), other.ca_cert_pem()).unwrap();
+
+        let ca = CertificateAuthority::new(&dir).unwrap();
+        assert_eq!(count_certs(&ca.trust_bundle_pem()), 2);
+        assert_eq!(count_certs(&ca.chain_pem()), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+        std::fs::remove_dir_all(&other_dir).unwrap();
+    }
+}
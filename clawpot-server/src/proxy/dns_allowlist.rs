@@ -0,0 +1,164 @@
+use anyhow::{Context, Result};
+use std::collections::HashSet;
+use std::path::Path;
+use tracing::info;
+
+/// Environment variable naming the DNS allowlist file
+pub const DNS_ALLOWLIST_ENV: &str = "CLAWPOT_DNS_ALLOWLIST";
+
+/// Environment variable choosing how names outside the allowlist are answered
+pub const DNS_ALLOWLIST_RCODE_ENV: &str = "CLAWPOT_DNS_ALLOWLIST_RCODE";
+
+/// Answer given to queries for names outside the allowlist
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DenyRcode {
+    /// REFUSED (default): the resolver won't answer
+    #[default]
+    Refused,
+    /// NXDOMAIN: the name doesn't exist, which some clients give up on faster
+    NxDomain,
+}
+
+impl DenyRcode {
+    pub fn parse(raw: &str) -> Option<Self> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "refused" => Some(Self::Refused),
+            "nxdomain" => Some(Self::NxDomain),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Refused => "refused",
+            Self::NxDomain => "nxdomain",
+        }
+    }
+
+    /// RCODE of the response
+    pub fn code(self) -> u8 {
+        match self {
+            Self::Refused => 5,
+            Self::NxDomain => 3,
+        }
+    }
+}
+
+/// Domain suffixes VMs may resolve, checked before the authorization
+/// service is asked. Names outside the list are answered locally and never
+/// reach the upstream resolvers.
+///
+/// Loaded from the file named by `CLAWPOT_DNS_ALLOWLIST`, one domain per
+/// line; blank lines and `#` comments are ignored. An entry allows the
+/// domain itself and every name under it, so `example.com` also allows
+/// `api.example.com` (but not `badexample.com`). Without the file every
+/// name is allowed.
+#[derive(Debug, Default)]
+pub struct DnsAllowlist {
+    /// Allowed suffixes, or `None` to allow every name
+    suffixes: Option<HashSet<String>>,
+    deny_rcode: DenyRcode,
+}
+
+impl DnsAllowlist {
+    /// Load the allowlist from the file at `path` (`CLAWPOT_DNS_ALLOWLIST`).
+    pub fn load(path: &Path) -> Result<Self> {
+        let raw = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read DNS allowlist from {}", path.display()))?;
+        let allowlist = Self::parse(&raw);
+        info!(
+            "Loaded {} DNS allowlist entries from {}",
+            allowlist.suffixes.as_ref().map_or(0, HashSet::len),
+            path.display()
+        );
+        Ok(allowlist)
+    }
+
+    fn parse(raw: &str) -> Self {
+        let suffixes = raw
+            .lines()
+            .map(|line| line.split('#').next().unwrap_or_default())
+            .map(normalize_name)
+            .filter(|name| !name.is_empty())
+            .collect();
+        Self {
+            suffixes: Some(suffixes),
+            deny_rcode: DenyRcode::default(),
+        }
+    }
+
+    /// Answer names outside the list with `rcode` (`CLAWPOT_DNS_ALLOWLIST_RCODE`).
+    #[must_use]
+    pub fn with_deny_rcode(mut self, rcode: DenyRcode) -> Self {
+        self.deny_rcode = rcode;
+        self
+    }
+
+    pub fn deny_rcode(&self) -> DenyRcode {
+        self.deny_rcode
+    }
+
+    /// Whether `name` is listed or falls under a listed domain.
+    pub fn allows(&self, name: &str) -> bool {
+        let Some(suffixes) = &self.suffixes else {
+            return true;
+        };
+        let name = normalize_name(name);
+        let mut candidate = name.as_str();
+        loop {
+            if suffixes.contains(candidate) {
+                return true;
+            }
+            match candidate.split_once('.') {
+                Some((_, parent)) => candidate = parent,
+                None => return false,
+            }
+        }
+    }
+}
+
+fn normalize_name(name: &str) -> String {
+    name.trim()
+        .trim_start_matches("*.")
+        .trim_end_matches('.')
+        .to_ascii_lowercase()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_allows_suffixes() {
+        let allowlist = DnsAllowlist::parse(
+            "# Package mirrors\nexample.com\n\n  PyPI.org.  # trailing comment\n*.internal\n",
+        );
+        // Exact matches, ignoring case and a trailing dot
+        assert!(allowlist.allows("example.com"));
+        assert!(allowlist.allows("pypi.org."));
+        // Subdomains
+        assert!(allowlist.allows("api.example.com"));
+        assert!(allowlist.allows("files.PyPI.org"));
+        assert!(allowlist.allows("db.internal"));
+        // Non-matches
+        assert!(!allowlist.allows("badexample.com"));
+        assert!(!allowlist.allows("com"));
+        assert!(!allowlist.allows("example.org"));
+        assert!(!allowlist.allows("unknown"));
+    }
+
+    #[test]
+    fn test_default_allows_everything() {
+        assert!(DnsAllowlist::default().allows("anything.example"));
+        // A file without entries denies everything
+        assert!(!DnsAllowlist::parse("# nothing yet\n").allows("example.com"));
+    }
+
+    #[test]
+    fn test_parse_rcode() {
+        assert_eq!(DenyRcode::parse("NXDOMAIN"), Some(DenyRcode::NxDomain));
+        assert_eq!(DenyRcode::parse(" refused "), Some(DenyRcode::Refused));
+        assert_eq!(DenyRcode::parse("servfail"), None);
+        assert_eq!(DenyRcode::NxDomain.code(), 3);
+    }
+}
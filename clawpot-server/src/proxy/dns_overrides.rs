@@ -0,0 +1,211 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::path::Path;
+use tracing::info;
+
+/// TTL for synthesized answers; short so edits to the overrides file take effect quickly
+const OVERRIDE_TTL: u32 = 60;
+
+/// Hostname → IP overrides answered by the DNS proxy instead of upstream.
+///
+/// Loaded from the JSON file named by `CLAWPOT_DNS_OVERRIDES`:
+///
+/// ```json
+/// {
+///   "global": { "api.internal": "10.0.0.5" },
+///   "vms": { "192.168.100.2": { "api.internal": "10.0.0.6" } }
+/// }
+/// ```
+///
+/// Per-VM maps are keyed by vm_id or guest IP and take precedence over `global`.
+#[derive(Debug, Default, Deserialize)]
+pub struct DnsOverrides {
+    #[serde(default)]
+    global: HashMap<String, IpAddr>,
+    #[serde(default)]
+    vms: HashMap<String, HashMap<String, IpAddr>>,
+}
+
+impl DnsOverrides {
+    /// Load overrides from the JSON file at `path` (`CLAWPOT_DNS_OVERRIDES`).
+    pub fn load(path: &Path) -> Result<Self> {
+        let raw = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read DNS overrides from {}", path.display()))?;
+        let overrides = Self::parse(&raw)
+            .with_context(|| format!("Invalid DNS overrides file {}", path.display()))?;
+        info!(
+            "Loaded {} global and {} per-VM DNS override(s) from {}",
+            overrides.global.len(),
+            overrides.vms.values().map(HashMap::len).sum::<usize>(),
+            path.display()
+        );
+        Ok(overrides)
+    }
+
+    fn parse(raw: &str) -> Result<Self> {
+        let parsed: Self = serde_json::from_str(raw)?;
+        // Normalize names so lookups are case-insensitive and ignore a trailing dot
+        Ok(Self {
+            global: normalize(parsed.global),
+            vms: parsed
+                .vms
+                .into_iter()
+                .map(|(vm, map)| (vm, normalize(map)))
+                .collect(),
+        })
+    }
+
+    /// Find the override for `name` as seen by the VM with `vm_id` at `vm_ip`.
+    pub fn lookup(&self, vm_id: &str, vm_ip: IpAddr, name: &str) -> Option<IpAddr> {
+        let name = normalize_name(name);
+        [vm_id.to_string(), vm_ip.to_string()]
+            .iter()
+            .filter_map(|key| self.vms.get(key))
+            .find_map(|map| map.get(&name))
+            .or_else(|| self.global.get(&name))
+            .copied()
+    }
+}
+
+fn normalize(map: HashMap<String, IpAddr>) -> HashMap<String, IpAddr> {
+    map.into_iter()
+        .map(|(name, ip)| (normalize_name(&name), ip))
+        .collect()
+}
+
+fn normalize_name(name: &str) -> String {
+    name.trim_end_matches('.').to_ascii_lowercase()
+}
+
+/// Build a response to `query` answering its question with `ip`.
+///
+/// A queries get an A record for an IPv4 override and AAAA queries an AAAA
+/// record for IPv6; any other combination gets an empty NOERROR answer, so
+/// the real address is never looked up upstream. Returns `None` if the query
+/// can't be parsed.
+pub fn build_override_response(query: &[u8], ip: IpAddr) -> Option<Vec<u8>> {
+    let question_end = question_end(query)?;
+    let qtype = u16::from_be_bytes([query[question_end - 4], query[question_end - 3]]);
+
+    let rdata: Option<(u16, Vec<u8>)> = match (qtype, ip) {
+        (1, IpAddr::V4(v4)) => Some((1, v4.octets().to_vec())),
+        (28, IpAddr::V6(v6)) => Some((28, v6.octets().to_vec())),
+        _ => None,
+    };
+
+    // Header and question only, dropping any EDNS records from the query
+    let mut resp = query[..question_end].to_vec();
+    resp[2] = (resp[2] | 0x84) & 0xFD; // QR=1, AA=1, TC=0 (keep opcode and RD)
+    resp[3] = 0x80; // RA=1, RCODE=0
+    resp[4..6].copy_from_slice(&1u16.to_be_bytes()); // QDCOUNT
+    resp[6..8].copy_from_slice(&u16::from(rdata.is_some()).to_be_bytes()); // ANCOUNT
+    resp[8..12].copy_from_slice(&[0, 0, 0, 0]); // NSCOUNT, ARCOUNT
+
+    if let Some((rtype, data)) = rdata {
+        resp.extend_from_slice(&[0xC0, 0x0C]); // Name: pointer to the question
+        resp.extend_from_slice(&rtype.to_be_bytes());
+        resp.extend_from_slice(&1u16.to_be_bytes()); // Class IN
+        resp.extend_from_slice(&OVERRIDE_TTL.to_be_bytes());
+        resp.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        resp.extend_from_slice(&data);
+    }
+    Some(resp)
+}
+
+/// Offset just past the first question (name, QTYPE and QCLASS).
+fn question_end(packet: &[u8]) -> Option<usize> {
+    if packet.len() < 12 || u16::from_be_bytes([packet[4], packet[5]]) == 0 {
+        return None;
+    }
+    let mut pos = 12;
+    loop {
+        let label_len = *packet.get(pos)? as usize;
+        pos += 1;
+        if label_len == 0 {
+            break;
+        }
+        pos += label_len;
+    }
+    let end = pos + 4;
+    (end <= packet.len()).then_some(end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn query(name: &str, qtype: u16) -> Vec<u8> {
+        let mut packet = vec![
+            0x12, 0x34, // ID
+            0x01, 0x00, // Flags (standard query, RD)
+            0x00, 0x01, // QDCOUNT=1
+            0x00, 0x00, 0x00, 0x00, // ANCOUNT, NSCOUNT
+            0x00, 0x01, // ARCOUNT=1 (EDNS)
+        ];
+        for label in name.split('.') {
+            packet.push(label.len() as u8);
+            packet.extend_from_slice(label.as_bytes());
+        }
+        packet.push(0);
+        packet.extend_from_slice(&qtype.to_be_bytes());
+        packet.extend_from_slice(&[0x00, 0x01]); // QCLASS=IN
+        packet.extend_from_slice(&[0x00, 0x00, 0x29, 0x10, 0x00, 0, 0, 0, 0, 0, 0]); // OPT
+        packet
+    }
+
+    #[test]
+    fn test_lookup_precedence() {
+        let overrides = DnsOverrides::parse(
+            r#"{
+                "global": { "API.internal.": "10.0.0.5", "db.internal": "10.0.0.7" },
+                "vms": { "192.168.100.2": { "api.internal": "10.0.0.6" } }
+            }"#,
+        )
+        .unwrap();
+
+        let vm_ip: IpAddr = "192.168.100.2".parse().unwrap();
+        let other_ip: IpAddr = "192.168.100.3".parse().unwrap();
+        assert_eq!(
+            overrides.lookup("vm-1", vm_ip, "api.internal"),
+            Some("10.0.0.6".parse().unwrap())
+        );
+        assert_eq!(
+            overrides.lookup("vm-2", other_ip, "Api.Internal."),
+            Some("10.0.0.5".parse().unwrap())
+        );
+        assert_eq!(
+            overrides.lookup("vm-1", vm_ip, "db.internal"),
+            Some("10.0.0.7".parse().unwrap())
+        );
+        assert_eq!(overrides.lookup("vm-1", vm_ip, "example.com"), None);
+    }
+
+    #[test]
+    fn test_build_a_response() {
+        let q = query("api.internal", 1);
+        let resp = build_override_response(&q, "10.0.0.5".parse().unwrap()).unwrap();
+
+        assert_eq!(resp[0..2], [0x12, 0x34]); // ID preserved
+        assert!(resp[2] & 0x80 != 0); // QR=1
+        assert_eq!(resp[3] & 0x0F, 0); // NOERROR
+        assert_eq!(resp[6..8], [0, 1]); // ANCOUNT=1
+        assert_eq!(resp[10..12], [0, 0]); // EDNS record dropped
+        assert_eq!(resp[resp.len() - 4..], [10, 0, 0, 5]);
+    }
+
+    #[test]
+    fn test_build_nodata_for_mismatched_type() {
+        let q = query("api.internal", 28);
+        let resp = build_override_response(&q, "10.0.0.5".parse().unwrap()).unwrap();
+        assert_eq!(resp[6..8], [0, 0]); // No answers
+        assert_eq!(resp[3] & 0x0F, 0); // but still NOERROR
+    }
+
+    #[test]
+    fn test_build_rejects_truncated_query() {
+        let q = query("api.internal", 1);
+        assert!(build_override_response(&q[..14], "10.0.0.5".parse().unwrap()).is_none());
+    }
+}
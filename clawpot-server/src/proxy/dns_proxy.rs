@@ -1,28 +1,141 @@
-use anyhow::{Context, Result};
-use std::net::SocketAddr;
+use anyhow::{bail, Context, Result};
+use serde::Serialize;
+use std::fmt;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::sync::Arc;
-use std::time::Instant;
+use std::time::{Duration, Instant};
 use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream, UdpSocket};
-use tracing::{error, info, warn};
+use tracing::{error, info};
 use uuid::Uuid;
 
-use super::auth_client::AuthClient;
+use super::auth_client::{AuthClient, AuthDecision, DenyCode};
+use super::dns_allowlist::DnsAllowlist;
+use super::dns_overrides::{self, DnsOverrides};
+use super::doh::DohClient;
+use super::throttle::warn_throttled;
 use crate::events::EventStore;
 use crate::vm::VmRegistry;
 
-const DNS_LISTEN_ADDR: &str = "0.0.0.0:10053";
-const UPSTREAM_DNS: &str = "8.8.8.8:53";
+pub const DNS_LISTEN_ADDR: &str = "0.0.0.0:10053";
 
-/// Start the DNS proxy. Runs until cancel is triggered.
+/// Environment variable listing the upstream resolvers, tried in order
+pub const DNS_UPSTREAM_ENV: &str = "CLAWPOT_DNS_UPSTREAM";
+
+/// Resolver used when `CLAWPOT_DNS_UPSTREAM` is unset
+pub const DEFAULT_UPSTREAM_DNS: &str = "8.8.8.8:53";
+
+/// Environment variable choosing how queries reach the upstream: `udp`
+/// (default) or `doh`
+pub const DNS_MODE_ENV: &str = "CLAWPOT_DNS_MODE";
+
+/// How long each upstream gets to answer before the next one is tried
+const UPSTREAM_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// RCODE of a FORMERR response, given to queries that can't be parsed
+const RCODE_FORMERR: u8 = 1;
+
+/// RCODE of a SERVFAIL response, which moves on to the next upstream
+const RCODE_SERVFAIL: u8 = 2;
+
+/// RCODE of a REFUSED response, given to denied queries
+const RCODE_REFUSED: u8 = 5;
+
+/// Compression pointers followed while reading one name, so a pointer loop
+/// in a malformed response can't spin forever
+const MAX_NAME_POINTERS: usize = 32;
+
+/// Parse a comma-separated list of `ip:port` resolvers.
+pub fn parse_upstreams(raw: &str) -> Result<Vec<SocketAddr>, String> {
+    let upstreams = raw
+        .split(',')
+        .map(str::trim)
+        .filter(|s| !s.is_empty())
+        .map(|s| {
+            s.parse()
+                .map_err(|_| format!("'{s}' is not an ip:port address"))
+        })
+        .collect::<Result<Vec<SocketAddr>, String>>()?;
+    if upstreams.is_empty() {
+        return Err("expected at least one ip:port address".to_string());
+    }
+    Ok(upstreams)
+}
+
+/// Transport used to forward allowed queries
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum DnsMode {
+    /// Plain DNS over UDP to `CLAWPOT_DNS_UPSTREAM` (default)
+    #[default]
+    Udp,
+    /// DNS-over-HTTPS to `CLAWPOT_DNS_DOH_URL`, for hosts that only allow
+    /// HTTPS egress
+    Doh,
+}
+
+impl DnsMode {
+    pub fn parse(raw: &str) -> Option<Self> {
+        match raw.trim().to_ascii_lowercase().as_str() {
+            "udp" => Some(Self::Udp),
+            "doh" => Some(Self::Doh),
+            _ => None,
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Udp => "udp",
+            Self::Doh => "doh",
+        }
+    }
+}
+
+/// Where allowed queries are forwarded
+pub enum DnsUpstream {
+    /// Resolvers tried over UDP in order of preference
+    Udp(Vec<SocketAddr>),
+    /// A DNS-over-HTTPS endpoint
+    Doh(DohClient),
+}
+
+impl fmt::Display for DnsUpstream {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Udp(upstreams) => {
+                let addrs: Vec<_> = upstreams.iter().map(ToString::to_string).collect();
+                f.write_str(&addrs.join(", "))
+            }
+            Self::Doh(doh) => write!(f, "{} (DoH)", doh.url()),
+        }
+    }
+}
+
+/// Start the DNS proxy, forwarding to `upstream`. Runs until cancel is
+/// triggered.
 pub async fn run(
     registry: Arc<VmRegistry>,
     events: EventStore,
     auth: Arc<AuthClient>,
+    overrides: Arc<DnsOverrides>,
+    allowlist: Arc<DnsAllowlist>,
+    upstream: DnsUpstream,
+    deny_unknown_vm: bool,
     mut cancel: tokio::sync::watch::Receiver<bool>,
     ready: tokio::sync::oneshot::Sender<()>,
 ) {
-    match run_inner(registry, events, auth, &mut cancel, ready).await {
+    match run_inner(
+        registry,
+        events,
+        auth,
+        overrides,
+        allowlist,
+        Arc::new(upstream),
+        deny_unknown_vm,
+        &mut cancel,
+        ready,
+    )
+    .await
+    {
         Ok(()) => info!("DNS proxy shut down"),
         Err(e) => error!("DNS proxy failed: {:#}", e),
     }
@@ -32,6 +145,10 @@ async fn run_inner(
     registry: Arc<VmRegistry>,
     events: EventStore,
     auth: Arc<AuthClient>,
+    overrides: Arc<DnsOverrides>,
+    allowlist: Arc<DnsAllowlist>,
+    upstream: Arc<DnsUpstream>,
+    deny_unknown_vm: bool,
     cancel: &mut tokio::sync::watch::Receiver<bool>,
     ready: tokio::sync::oneshot::Sender<()>,
 ) -> Result<()> {
@@ -43,7 +160,10 @@ async fn run_inner(
         .await
         .with_context(|| format!("Failed to bind DNS proxy TCP on {DNS_LISTEN_ADDR}"))?;
 
-    info!("DNS proxy listening on {} (UDP+TCP)", DNS_LISTEN_ADDR);
+    info!(
+        "DNS proxy listening on {} (UDP+TCP), upstream {}",
+        DNS_LISTEN_ADDR, upstream
+    );
 
     // Signal readiness now that both sockets are bound
     let _ = ready.send(());
@@ -60,24 +180,24 @@ async fn run_inner(
                 let registry = registry.clone();
                 let events = events.clone();
                 let auth = auth.clone();
+                let overrides = overrides.clone();
+                let allowlist = allowlist.clone();
+                let upstream = upstream.clone();
                 let reply_socket = udp_socket.clone();
 
                 // Spawn handler so we don't block the listener
-                let upstream_socket = UdpSocket::bind("0.0.0.0:0").await;
-                if let Ok(upstream_socket) = upstream_socket {
-                    tokio::spawn(async move {
-                        match process_dns_query(&packet, peer_addr, &registry, &events, &auth, &upstream_socket).await {
-                            Ok(response) => {
-                                if let Err(e) = reply_socket.send_to(&response, peer_addr).await {
-                                    warn!("Failed to send DNS response to {}: {}", peer_addr, e);
-                                }
-                            }
-                            Err(e) => {
-                                warn!("DNS query from {} failed: {:#}", peer_addr, e);
+                tokio::spawn(async move {
+                    match process_dns_query(&packet, peer_addr, &registry, &events, &auth, &overrides, &allowlist, &upstream, deny_unknown_vm).await {
+                        Ok(response) => {
+                            if let Err(e) = reply_socket.send_to(&response, peer_addr).await {
+                                warn_throttled("Failed to send DNS response", peer_addr.ip(), format!("to {peer_addr}: {e}"));
                             }
                         }
-                    });
-                }
+                        Err(e) => {
+                            warn_throttled("DNS query failed", peer_addr.ip(), format!("from {peer_addr}: {e:#}"));
+                        }
+                    }
+                });
             }
             result = tcp_listener.accept() => {
                 let (stream, peer_addr) = result.context("Failed to accept TCP DNS connection")?;
@@ -85,10 +205,13 @@ async fn run_inner(
                 let registry = registry.clone();
                 let events = events.clone();
                 let auth = auth.clone();
+                let overrides = overrides.clone();
+                let allowlist = allowlist.clone();
+                let upstream = upstream.clone();
 
                 tokio::spawn(async move {
-                    if let Err(e) = handle_tcp_dns_connection(stream, peer_addr, &registry, &events, &auth).await {
-                        warn!("TCP DNS connection from {} failed: {:#}", peer_addr, e);
+                    if let Err(e) = handle_tcp_dns_connection(stream, peer_addr, &registry, &events, &auth, &overrides, &allowlist, &upstream, deny_unknown_vm).await {
+                        warn_throttled("TCP DNS connection failed", peer_addr.ip(), format!("from {peer_addr}: {e:#}"));
                     }
                 });
             }
@@ -110,61 +233,102 @@ async fn process_dns_query(
     registry: &VmRegistry,
     events: &EventStore,
     auth: &AuthClient,
-    upstream_socket: &UdpSocket,
+    overrides: &DnsOverrides,
+    allowlist: &DnsAllowlist,
+    upstream: &DnsUpstream,
+    deny_unknown_vm: bool,
 ) -> Result<Vec<u8>> {
     let start = Instant::now();
     let corr_id = Uuid::new_v4().to_string();
 
-    // 1. Resolve vm_id — block unknown sources
-    let vm_id = if let Some(id) = registry.find_by_ip(peer_addr.ip()).await {
-        id.to_string()
-    } else {
-        warn!(
-            "Blocking DNS request from unknown source IP: {}",
-            peer_addr.ip()
-        );
-        return Ok(build_refused_response(packet));
+    // 1. Resolve vm_id and name, applying the unknown-source policy
+    let Some((vm_id, vm_name)) =
+        super::resolve_vm(registry, events, peer_addr.ip(), "DNS", deny_unknown_vm).await
+    else {
+        return Ok(build_error_response(packet, RCODE_REFUSED));
     };
 
-    // 2. Parse DNS query
-    let (query_name, query_type) =
-        parse_dns_question(packet).unwrap_or(("unknown".to_string(), "unknown".to_string()));
+    // 2. Parse DNS query. Every question has to be authorized before the
+    //    query is forwarded, so one that can't be parsed is never forwarded.
+    let Some(questions) = parse_dns_questions(packet) else {
+        let duration_ms = start.elapsed().as_millis() as i64;
+        events.emit_with_duration(
+            "network.dns.response",
+            "network",
+            Some(&vm_id),
+            Some(&corr_id),
+            duration_ms,
+            Some(false),
+            &serde_json::json!({
+                "rcode": RCODE_FORMERR,
+                "answers": "FORMERR",
+                "error": "malformed question section",
+                "duration_ms": duration_ms,
+            }),
+        );
+        return Ok(build_error_response(packet, RCODE_FORMERR));
+    };
+    let query_names: Vec<&str> = questions.iter().map(|(name, _)| name.as_str()).collect();
+    let query_types: Vec<&str> = questions.iter().map(|(_, qtype)| qtype.as_str()).collect();
 
-    // 3. Log request event
+    // 3. Log request event; multi-question queries list every name and type
     events.emit(
         "network.dns.request",
         "network",
         Some(&vm_id),
         Some(&corr_id),
         &serde_json::json!({
-            "query_name": query_name,
-            "query_type": query_type,
+            "query_name": query_names.join(","),
+            "query_type": query_types.join(","),
+            "vm_name": vm_name,
         }),
     );
 
-    // 4. Authorize
-    let auth_start = Instant::now();
-    let (allowed, reason) = auth
-        .authorize_dns(0, &vm_id, &query_name, &query_type)
-        .await
-        .unwrap_or((false, "auth error".to_string()));
-    let auth_latency = auth_start.elapsed().as_millis() as i64;
+    // 4. Authorize every question; the query is forwarded whole, so one
+    //    denied name refuses all of it. Names outside the local allowlist
+    //    are denied without asking the authorization service.
+    let mut denied_rcode = None;
+    for (query_name, query_type) in &questions {
+        let auth_start = Instant::now();
+        let (decision, rcode) = if allowlist.allows(query_name) {
+            let decision = auth
+                .authorize_dns(0, &vm_id, query_name, query_type)
+                .await
+                .unwrap_or_else(|_| AuthDecision::deny(DenyCode::AuthUnreachable, "auth error"));
+            (decision, RCODE_REFUSED)
+        } else {
+            let decision = AuthDecision::deny(DenyCode::NotAllowlisted, "not in the DNS allowlist");
+            (decision, allowlist.deny_rcode().code())
+        };
+        let auth_latency = auth_start.elapsed().as_millis() as i64;
 
-    events.emit(
-        "network.dns.authorized",
-        "network",
-        Some(&vm_id),
-        Some(&corr_id),
-        &serde_json::json!({
-            "allowed": allowed,
-            "reason": reason,
-            "latency_ms": auth_latency,
-        }),
-    );
+        events.emit(
+            "network.dns.authorized",
+            "network",
+            Some(&vm_id),
+            Some(&corr_id),
+            &serde_json::json!({
+                "query_name": query_name,
+                "allowed": decision.allowed,
+                "reason": decision.reason,
+                "deny_code": decision.deny_code_str(),
+                "latency_ms": auth_latency,
+            }),
+        );
+        if !decision.allowed {
+            denied_rcode = Some(rcode);
+            break;
+        }
+    }
 
-    // 5. If denied, respond with REFUSED
-    if !allowed {
-        let refused = build_refused_response(packet);
+    // 5. If denied, respond with REFUSED (or NXDOMAIN for names outside the allowlist)
+    if let Some(rcode) = denied_rcode {
+        let refused = build_error_response(packet, rcode);
+        let answers = if rcode == RCODE_REFUSED {
+            "REFUSED"
+        } else {
+            "NXDOMAIN"
+        };
         let duration_ms = start.elapsed().as_millis() as i64;
         events.emit_with_duration(
             "network.dns.response",
@@ -174,38 +338,49 @@ async fn process_dns_query(
             duration_ms,
             Some(false),
             &serde_json::json!({
-                "rcode": 5,
-                "answers": "REFUSED",
+                "rcode": rcode,
+                "answers": answers,
                 "duration_ms": duration_ms,
             }),
         );
         return Ok(refused);
     }
 
-    // 6. Forward to upstream via UDP
-    upstream_socket
-        .send_to(packet, UPSTREAM_DNS)
-        .await
-        .context("Failed to send to upstream DNS")?;
-
-    let mut resp_buf = vec![0u8; 4096];
-    let resp_len = tokio::time::timeout(
-        std::time::Duration::from_secs(5),
-        upstream_socket.recv(&mut resp_buf),
-    )
-    .await
-    .context("DNS upstream timeout")?
-    .context("Failed to receive DNS response")?;
+    // 5b. Answer locally if the name is overridden for this VM. Overrides
+    //     answer a single question, so multi-question queries go upstream.
+    let single_name = match questions.as_slice() {
+        [(name, _)] => Some(name.as_str()),
+        _ => None,
+    };
+    if let Some(ip) = single_name.and_then(|name| overrides.lookup(&vm_id, peer_addr.ip(), name)) {
+        if let Some(response) = dns_overrides::build_override_response(packet, ip) {
+            let duration_ms = start.elapsed().as_millis() as i64;
+            events.emit_with_duration(
+                "network.dns.response",
+                "network",
+                Some(&vm_id),
+                Some(&corr_id),
+                duration_ms,
+                Some(true),
+                &serde_json::json!({
+                    "rcode": 0,
+                    "synthesized": true,
+                    "answers": ip.to_string(),
+                    "resp_size": response.len(),
+                    "duration_ms": duration_ms,
+                }),
+            );
+            return Ok(response);
+        }
+    }
 
-    let response = resp_buf[..resp_len].to_vec();
+    // 6. Forward upstream
+    let (response, upstream) = forward_query(packet, upstream).await?;
+    let resp_len = response.len();
 
     // 7. Log response
     let duration_ms = start.elapsed().as_millis() as i64;
-    let rcode = if resp_len >= 4 {
-        Some(i32::from(resp_buf[3] & 0x0F))
-    } else {
-        None
-    };
+    let rcode = rcode(&response).map(i32::from);
 
     events.emit_with_duration(
         "network.dns.response",
@@ -216,7 +391,9 @@ async fn process_dns_query(
         Some(rcode == Some(0)),
         &serde_json::json!({
             "rcode": rcode,
+            "records": parse_dns_answers(&response),
             "resp_size": resp_len,
+            "upstream": upstream,
             "duration_ms": duration_ms,
         }),
     );
@@ -224,6 +401,107 @@ async fn process_dns_query(
     Ok(response)
 }
 
+/// RCODE of a DNS message, if it is long enough to have one.
+fn rcode(message: &[u8]) -> Option<u8> {
+    message.get(3).map(|flags| flags & 0x0F)
+}
+
+/// Forward `packet` upstream, returning the answer and who gave it. Over
+/// UDP the resolvers are tried in order; a DoH endpoint that fails is
+/// answered for with SERVFAIL, since there is nothing to fail over to.
+async fn forward_query(packet: &[u8], upstream: &DnsUpstream) -> Result<(Vec<u8>, String)> {
+    match upstream {
+        DnsUpstream::Udp(upstreams) => {
+            let (response, from) = query_upstreams(packet, upstreams, UPSTREAM_TIMEOUT).await?;
+            Ok((response, from.to_string()))
+        }
+        DnsUpstream::Doh(doh) => {
+            let response = match doh.query(packet, UPSTREAM_TIMEOUT).await {
+                Ok(response) => response,
+                Err(e) => {
+                    warn_throttled(
+                        "DoH upstream failed",
+                        doh.url(),
+                        format!("{}: {e:#}", doh.url()),
+                    );
+                    build_error_response(packet, RCODE_SERVFAIL)
+                }
+            };
+            Ok((response, doh.url().to_string()))
+        }
+    }
+}
+
+/// Send `packet` to each upstream in turn, moving on when one times out,
+/// fails or answers SERVFAIL. Returns the first other answer and the
+/// upstream that gave it; if none did, the last SERVFAIL is returned, or
+/// an error if no upstream answered at all.
+async fn query_upstreams(
+    packet: &[u8],
+    upstreams: &[SocketAddr],
+    timeout: Duration,
+) -> Result<(Vec<u8>, SocketAddr)> {
+    let mut servfail = None;
+    let mut last_error = None;
+    for &upstream in upstreams {
+        match query_upstream(packet, upstream, timeout).await {
+            Ok(response) if rcode(&response) == Some(RCODE_SERVFAIL) => {
+                warn_throttled("DNS upstream returned SERVFAIL", upstream, upstream);
+                servfail = Some((response, upstream));
+            }
+            Ok(response) => return Ok((response, upstream)),
+            Err(e) => {
+                warn_throttled(
+                    "DNS upstream failed",
+                    upstream,
+                    format!("{upstream}: {e:#}"),
+                );
+                last_error = Some(e);
+            }
+        }
+    }
+    if let Some(answer) = servfail {
+        return Ok(answer);
+    }
+    match last_error {
+        Some(e) => Err(e.context("All DNS upstreams failed")),
+        None => bail!("No DNS upstreams configured"),
+    }
+}
+
+/// Send `packet` to `upstream` from a fresh socket of the same address
+/// family and wait up to `timeout` for its answer. Datagrams from anywhere
+/// else are ignored.
+async fn query_upstream(packet: &[u8], upstream: SocketAddr, timeout: Duration) -> Result<Vec<u8>> {
+    let local: SocketAddr = if upstream.is_ipv6() {
+        (Ipv6Addr::UNSPECIFIED, 0).into()
+    } else {
+        (Ipv4Addr::UNSPECIFIED, 0).into()
+    };
+    let socket = UdpSocket::bind(local)
+        .await
+        .context("Failed to bind upstream UDP socket")?;
+    socket
+        .send_to(packet, upstream)
+        .await
+        .context("Failed to send to upstream DNS")?;
+
+    let mut resp_buf = vec![0u8; 4096];
+    tokio::time::timeout(timeout, async {
+        loop {
+            let (len, from) = socket
+                .recv_from(&mut resp_buf)
+                .await
+                .context("Failed to receive DNS response")?;
+            if from == upstream {
+                return Ok::<_, anyhow::Error>(resp_buf[..len].to_vec());
+            }
+        }
+    })
+    .await
+    .context("DNS upstream timeout")?
+}
+
 /// Handle a single TCP DNS connection. Reads length-prefixed messages in a loop.
 async fn handle_tcp_dns_connection(
     mut stream: TcpStream,
@@ -231,6 +509,10 @@ async fn handle_tcp_dns_connection(
     registry: &VmRegistry,
     events: &EventStore,
     auth: &AuthClient,
+    overrides: &DnsOverrides,
+    allowlist: &DnsAllowlist,
+    upstream: &DnsUpstream,
+    deny_unknown_vm: bool,
 ) -> Result<()> {
     loop {
         // Read 2-byte length prefix
@@ -258,18 +540,16 @@ async fn handle_tcp_dns_connection(
             .await
             .context("Failed to read TCP DNS message")?;
 
-        // Process via UDP upstream (standard for DNS proxies)
-        let upstream_socket = UdpSocket::bind("0.0.0.0:0")
-            .await
-            .context("Failed to bind upstream UDP socket for TCP DNS")?;
-
         let response = process_dns_query(
             &msg_buf,
             peer_addr,
             registry,
             events,
             auth,
-            &upstream_socket,
+            overrides,
+            allowlist,
+            upstream,
+            deny_unknown_vm,
         )
         .await?;
 
@@ -308,8 +588,10 @@ async fn write_dns_message<W: AsyncWrite + Unpin>(writer: &mut W, message: &[u8]
     Ok(())
 }
 
-/// Parse the question section of a DNS query to extract name and type.
-fn parse_dns_question(packet: &[u8]) -> Option<(String, String)> {
+/// Parse the question section of a DNS query to extract the name and type
+/// of each of its `QDCOUNT` questions. Returns `None` if any question is
+/// malformed.
+fn parse_dns_questions(packet: &[u8]) -> Option<Vec<(String, String)>> {
     if packet.len() < 12 {
         return None; // Too short for DNS header
     }
@@ -320,8 +602,21 @@ fn parse_dns_question(packet: &[u8]) -> Option<(String, String)> {
         return None;
     }
 
-    // Parse question name starting at byte 12
+    // Questions start at byte 12
     let mut pos = 12;
+    let mut questions = Vec::new();
+    for _ in 0..qdcount {
+        let (name, qtype, name_end) = parse_dns_question(packet, pos)?;
+        questions.push((name, qtype));
+        // Skip QTYPE and QCLASS; a truncated question can only be the last
+        pos = name_end + 4;
+    }
+    Some(questions)
+}
+
+/// Parse the question starting at `pos`, returning its name, type and the
+/// offset just past its name.
+fn parse_dns_question(packet: &[u8], mut pos: usize) -> Option<(String, String, usize)> {
     let mut labels = Vec::new();
 
     loop {
@@ -345,7 +640,7 @@ fn parse_dns_question(packet: &[u8]) -> Option<(String, String)> {
 
     // Query type (2 bytes after name)
     if pos + 2 > packet.len() {
-        return Some((name, "unknown".to_string()));
+        return Some((name, "unknown".to_string(), pos));
     }
     let qtype = u16::from_be_bytes([packet[pos], packet[pos + 1]]);
     let qtype_str = match qtype {
@@ -361,19 +656,116 @@ fn parse_dns_question(packet: &[u8]) -> Option<(String, String)> {
         _ => "OTHER",
     };
 
-    Some((name, qtype_str.to_string()))
+    Some((name, qtype_str.to_string(), pos))
+}
+
+/// An A, AAAA or CNAME record from the answer section of a response
+#[derive(Debug, PartialEq, Serialize)]
+struct DnsAnswer {
+    name: String,
+    #[serde(rename = "type")]
+    record_type: &'static str,
+    ttl: u32,
+    /// Address for A and AAAA records, target name for CNAME records
+    data: String,
 }
 
-/// Build a REFUSED DNS response from a query packet.
-fn build_refused_response(query: &[u8]) -> Vec<u8> {
+/// Parse the A, AAAA and CNAME records in the answer section of a DNS
+/// response, following name compression pointers. Other record types are
+/// skipped; parsing stops at the first malformed record.
+fn parse_dns_answers(packet: &[u8]) -> Vec<DnsAnswer> {
+    let mut answers = Vec::new();
+    if packet.len() < 12 {
+        return answers;
+    }
+    let qdcount = u16::from_be_bytes([packet[4], packet[5]]);
+    let ancount = u16::from_be_bytes([packet[6], packet[7]]);
+
+    let mut pos = 12;
+    for _ in 0..qdcount {
+        let Some((_, name_end)) = read_name(packet, pos) else {
+            return answers;
+        };
+        pos = name_end + 4; // QTYPE and QCLASS
+    }
+
+    for _ in 0..ancount {
+        let Some((name, name_end)) = read_name(packet, pos) else {
+            break;
+        };
+        // TYPE, CLASS, TTL and RDLENGTH
+        let Some(fixed) = packet.get(name_end..name_end + 10) else {
+            break;
+        };
+        let rtype = u16::from_be_bytes([fixed[0], fixed[1]]);
+        let ttl = u32::from_be_bytes([fixed[4], fixed[5], fixed[6], fixed[7]]);
+        let rdata_start = name_end + 10;
+        let rdata_end = rdata_start + usize::from(u16::from_be_bytes([fixed[8], fixed[9]]));
+        let Some(rdata) = packet.get(rdata_start..rdata_end) else {
+            break;
+        };
+        pos = rdata_end;
+
+        let record = match rtype {
+            1 => <[u8; 4]>::try_from(rdata)
+                .ok()
+                .map(|octets| ("A", Ipv4Addr::from(octets).to_string())),
+            28 => <[u8; 16]>::try_from(rdata)
+                .ok()
+                .map(|octets| ("AAAA", Ipv6Addr::from(octets).to_string())),
+            5 => read_name(packet, rdata_start).map(|(target, _)| ("CNAME", target)),
+            _ => None,
+        };
+        if let Some((record_type, data)) = record {
+            answers.push(DnsAnswer {
+                name,
+                record_type,
+                ttl,
+                data,
+            });
+        }
+    }
+    answers
+}
+
+/// Read the possibly compressed name at `pos`, returning it and the offset
+/// just past where it is stored (not past any labels a pointer led to).
+fn read_name(packet: &[u8], mut pos: usize) -> Option<(String, usize)> {
+    let mut labels = Vec::new();
+    let mut end = None;
+    let mut pointers = 0;
+    loop {
+        let len = usize::from(*packet.get(pos)?);
+        match len & 0xC0 {
+            0x00 if len == 0 => return Some((labels.join("."), end.unwrap_or(pos + 1))),
+            0x00 => {
+                let label = packet.get(pos + 1..pos + 1 + len)?;
+                labels.push(String::from_utf8_lossy(label).into_owned());
+                pos += 1 + len;
+            }
+            0xC0 => {
+                pointers += 1;
+                if pointers > MAX_NAME_POINTERS {
+                    return None;
+                }
+                end.get_or_insert(pos + 2);
+                pos = ((len & 0x3F) << 8) | usize::from(*packet.get(pos + 1)?);
+            }
+            _ => return None,
+        }
+    }
+}
+
+/// Build an answerless DNS response from a query packet, e.g. REFUSED or NXDOMAIN.
+fn build_error_response(query: &[u8], rcode: u8) -> Vec<u8> {
     if query.len() < 12 {
         return vec![];
     }
     let mut resp = query.to_vec();
-    // Set QR bit (response) and RCODE=5 (REFUSED)
+    // Set QR bit (response) and the RCODE
     resp[2] = (resp[2] | 0x80) & 0xFD; // Set QR=1, clear AA
-    resp[3] = (resp[3] & 0xF0) | 0x05; // RCODE=5
-                                       // Zero out answer/authority/additional counts
+    resp[3] = (resp[3] & 0xF0) | (rcode & 0x0F);
+    // Zero out answer/authority/additional counts
     resp[6..12].copy_from_slice(&[0, 0, 0, 0, 0, 0]);
     resp
 }
@@ -381,6 +773,10 @@ fn build_refused_response(query: &[u8]) -> Vec<u8> {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::events::PersistMode;
+    use crate::proxy::dns_allowlist::DenyRcode;
+    use crate::vm::VmEntry;
+    use clawpot_common::vm::VmManager;
 
     #[test]
     fn test_parse_dns_question() {
@@ -402,9 +798,183 @@ mod tests {
         packet.extend_from_slice(&[0x00, 0x01]); // QTYPE=A
         packet.extend_from_slice(&[0x00, 0x01]); // QCLASS=IN
 
-        let (name, qtype) = parse_dns_question(&packet).unwrap();
-        assert_eq!(name, "example.com");
-        assert_eq!(qtype, "A");
+        let questions = parse_dns_questions(&packet).unwrap();
+        assert_eq!(questions, [("example.com".to_string(), "A".to_string())]);
+    }
+
+    #[test]
+    fn test_parse_multiple_dns_questions() {
+        let mut packet = vec![
+            0x00, 0x01, // ID
+            0x01, 0x00, // Flags (standard query)
+            0x00, 0x02, // QDCOUNT=2
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
+        ];
+        for (label, qtype) in [(&b"allowed"[..], 1u8), (&b"denied"[..], 28u8)] {
+            packet.push(label.len() as u8);
+            packet.extend_from_slice(label);
+            packet.extend_from_slice(&[3]);
+            packet.extend_from_slice(b"com");
+            packet.push(0); // Root label
+            packet.extend_from_slice(&[0x00, qtype]); // QTYPE
+            packet.extend_from_slice(&[0x00, 0x01]); // QCLASS=IN
+        }
+
+        let questions = parse_dns_questions(&packet).unwrap();
+        assert_eq!(
+            questions,
+            [
+                ("allowed.com".to_string(), "A".to_string()),
+                ("denied.com".to_string(), "AAAA".to_string()),
+            ]
+        );
+
+        // Claiming a question that isn't there makes the packet malformed
+        packet[5] = 3;
+        assert!(parse_dns_questions(&packet).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_malformed_query_is_not_forwarded() {
+        let dir = tempfile::tempdir().unwrap();
+        let events = EventStore::new(
+            &dir.path().join("events.db"),
+            "test-session",
+            "test-server",
+            "0.1.0",
+            "{}",
+            PersistMode::All,
+        )
+        .unwrap();
+        let registry = VmRegistry::new();
+        let id = Uuid::new_v4();
+        registry
+            .insert(
+                id,
+                VmEntry {
+                    id,
+                    manager: VmManager::new(std::path::PathBuf::from("/tmp/test.sock")),
+                    ip_address: "127.0.0.1".parse().unwrap(),
+                    tap_name: "tap-test".to_string(),
+                    created_at: std::time::SystemTime::now(),
+                    vcpu_count: 1,
+                    mem_size_mib: 256,
+                    vsock_uds_path: "/tmp/test-vsock.sock".to_string(),
+                    guest_cid: 3,
+                    name: None,
+                    balloon: false,
+                    labels: std::collections::BTreeMap::new(),
+                    boot_config: None,
+                },
+            )
+            .await
+            .unwrap();
+        let resolver = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let upstream = DnsUpstream::Udp(vec![resolver.local_addr().unwrap()]);
+
+        // One well-formed question, then a second that is cut off
+        let mut packet = vec![0xAB, 0xCD, 0x01, 0x00, 0x00, 0x02, 0, 0, 0, 0, 0, 0];
+        packet.extend_from_slice(&[7]);
+        packet.extend_from_slice(b"example");
+        packet.extend_from_slice(&[3]);
+        packet.extend_from_slice(b"com");
+        packet.extend_from_slice(&[0, 0x00, 0x01, 0x00, 0x01]);
+        packet.extend_from_slice(&[8]);
+        packet.extend_from_slice(b"internal");
+
+        let response = process_dns_query(
+            &packet,
+            "127.0.0.1:5353".parse().unwrap(),
+            &registry,
+            &events,
+            &AuthClient::Disabled,
+            &DnsOverrides::default(),
+            &DnsAllowlist::default(),
+            &upstream,
+            true,
+        )
+        .await
+        .unwrap();
+        assert_eq!(response[..2], [0xAB, 0xCD]);
+        assert_eq!(rcode(&response), Some(RCODE_FORMERR));
+
+        let mut buf = [0u8; 512];
+        let forwarded =
+            tokio::time::timeout(Duration::from_millis(100), resolver.recv_from(&mut buf)).await;
+        assert!(forwarded.is_err(), "malformed query reached the upstream");
+    }
+
+    /// Header and `example.com A` question of a response with `ancount` answers
+    fn response_header(ancount: u8) -> Vec<u8> {
+        let mut packet = vec![
+            0x00, 0x01, // ID
+            0x81, 0x80, // Flags (response, no error)
+            0x00, 0x01, // QDCOUNT=1
+            0x00, ancount, // ANCOUNT
+            0x00, 0x00, 0x00, 0x00,
+        ];
+        packet.extend_from_slice(&[7]);
+        packet.extend_from_slice(b"example");
+        packet.extend_from_slice(&[3]);
+        packet.extend_from_slice(b"com");
+        packet.push(0);
+        packet.extend_from_slice(&[0x00, 0x01, 0x00, 0x01]); // QTYPE=A, QCLASS=IN
+        packet
+    }
+
+    #[test]
+    fn test_parse_dns_answers_a_record() {
+        let mut packet = response_header(1);
+        packet.extend_from_slice(&[0xC0, 0x0C]); // Name: pointer to the question
+        packet.extend_from_slice(&[0x00, 0x01, 0x00, 0x01]); // TYPE=A, CLASS=IN
+        packet.extend_from_slice(&300u32.to_be_bytes()); // TTL
+        packet.extend_from_slice(&[0x00, 0x04, 93, 184, 216, 34]);
+
+        assert_eq!(
+            parse_dns_answers(&packet),
+            [DnsAnswer {
+                name: "example.com".to_string(),
+                record_type: "A",
+                ttl: 300,
+                data: "93.184.216.34".to_string(),
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_dns_answers_cname_chain() {
+        let mut packet = response_header(2);
+        // example.com CNAME cdn.example.com, with "example.com" compressed
+        packet.extend_from_slice(&[0xC0, 0x0C]);
+        packet.extend_from_slice(&[0x00, 0x05, 0x00, 0x01]); // TYPE=CNAME, CLASS=IN
+        packet.extend_from_slice(&60u32.to_be_bytes());
+        let cname_at = packet.len() + 2;
+        packet.extend_from_slice(&[0x00, 0x06, 3]);
+        packet.extend_from_slice(b"cdn");
+        packet.extend_from_slice(&[0xC0, 0x0C]);
+        // cdn.example.com A 10.0.0.1, its name a pointer to the CNAME target
+        packet.extend_from_slice(&[0xC0, cname_at as u8]);
+        packet.extend_from_slice(&[0x00, 0x01, 0x00, 0x01]);
+        packet.extend_from_slice(&30u32.to_be_bytes());
+        packet.extend_from_slice(&[0x00, 0x04, 10, 0, 0, 1]);
+
+        let answers = parse_dns_answers(&packet);
+        assert_eq!(answers.len(), 2);
+        assert_eq!(answers[0].record_type, "CNAME");
+        assert_eq!(answers[0].name, "example.com");
+        assert_eq!(answers[0].data, "cdn.example.com");
+        assert_eq!(answers[1].record_type, "A");
+        assert_eq!(answers[1].name, "cdn.example.com");
+        assert_eq!(answers[1].data, "10.0.0.1");
+        assert_eq!(answers[1].ttl, 30);
+    }
+
+    #[test]
+    fn test_read_name_rejects_pointer_loops() {
+        let mut packet = response_header(0);
+        let at = packet.len();
+        packet.extend_from_slice(&[0xC0, at as u8]); // Points at itself
+        assert!(read_name(&packet, at).is_none());
     }
 
     #[test]
@@ -415,10 +985,104 @@ mod tests {
             0x00, 0x01, // QDCOUNT
             0x00, 0x00, 0x00, 0x00, 0x00, 0x00,
         ];
-        let resp = build_refused_response(&query);
+        let resp = build_error_response(&query, RCODE_REFUSED);
         assert_eq!(resp[0..2], [0xAB, 0xCD]); // ID preserved
         assert!(resp[2] & 0x80 != 0); // QR=1
         assert_eq!(resp[3] & 0x0F, 5); // RCODE=5
+
+        let resp = build_error_response(&query, DenyRcode::NxDomain.code());
+        assert_eq!(resp[3] & 0x0F, 3); // RCODE=3 (NXDOMAIN)
+        assert_eq!(resp[6..12], [0, 0, 0, 0, 0, 0]);
+    }
+
+    #[test]
+    fn test_parse_upstreams() {
+        assert_eq!(
+            parse_upstreams("10.0.0.1:53, [2001:db8::1]:5353,").unwrap(),
+            vec![
+                "10.0.0.1:53".parse::<SocketAddr>().unwrap(),
+                "[2001:db8::1]:5353".parse().unwrap(),
+            ]
+        );
+        assert!(parse_upstreams("10.0.0.1").is_err());
+        assert!(parse_upstreams(" , ").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_upstream_failover() {
+        // The first upstream never answers
+        let silent = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        // The second echoes the query back with the QR bit set
+        let answering = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let upstreams = [
+            silent.local_addr().unwrap(),
+            answering.local_addr().unwrap(),
+        ];
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            let (len, from) = answering.recv_from(&mut buf).await.unwrap();
+            buf[2] |= 0x80;
+            answering.send_to(&buf[..len], from).await.unwrap();
+        });
+
+        let query = [0xAB, 0xCD, 0x01, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
+        let (response, upstream) = query_upstreams(&query, &upstreams, Duration::from_millis(200))
+            .await
+            .unwrap();
+        assert_eq!(upstream, upstreams[1]);
+        assert_eq!(response[..2], [0xAB, 0xCD]);
+        assert_ne!(response[2] & 0x80, 0);
+
+        // With every upstream silent the query fails
+        let err = query_upstreams(&query, &upstreams[..1], Duration::from_millis(50))
+            .await
+            .unwrap_err();
+        assert!(format!("{err:#}").contains("timeout"));
+        drop(silent);
+    }
+
+    #[tokio::test]
+    async fn test_ipv6_upstream() {
+        let Ok(answering) = UdpSocket::bind("[::1]:0").await else {
+            // No IPv6 loopback on this host
+            return;
+        };
+        let upstream = answering.local_addr().unwrap();
+        tokio::spawn(async move {
+            let mut buf = [0u8; 512];
+            let (len, from) = answering.recv_from(&mut buf).await.unwrap();
+            buf[2] |= 0x80;
+            answering.send_to(&buf[..len], from).await.unwrap();
+        });
+
+        let query = [0xAB, 0xCD, 0x01, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
+        let (response, from) = query_upstreams(&query, &[upstream], Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(from, upstream);
+        assert_eq!(response[..2], [0xAB, 0xCD]);
+    }
+
+    #[tokio::test]
+    async fn test_doh_failure_answers_servfail() {
+        // Nothing listens on the endpoint once the listener is dropped
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}/dns-query", listener.local_addr().unwrap());
+        drop(listener);
+        let upstream = DnsUpstream::Doh(DohClient::new(url.parse().unwrap()));
+
+        let query = [0xAB, 0xCD, 0x01, 0x00, 0x00, 0x01, 0, 0, 0, 0, 0, 0];
+        let (response, from) = forward_query(&query, &upstream).await.unwrap();
+        assert_eq!(from, url);
+        assert_eq!(response[..2], [0xAB, 0xCD]);
+        assert_eq!(rcode(&response), Some(RCODE_SERVFAIL));
+    }
+
+    #[test]
+    fn test_parse_dns_mode() {
+        assert_eq!(DnsMode::parse("DoH"), Some(DnsMode::Doh));
+        assert_eq!(DnsMode::parse("udp"), Some(DnsMode::Udp));
+        assert_eq!(DnsMode::parse("dot"), None);
     }
 
     #[tokio::test]
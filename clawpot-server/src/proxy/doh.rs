@@ -0,0 +1,182 @@
+use anyhow::{anyhow, bail, Context, Result};
+use http_body_util::{BodyExt, Full, Limited};
+use hyper::body::Bytes;
+use hyper::header::{ACCEPT, CONTENT_TYPE};
+use hyper::{Request, StatusCode, Uri};
+use hyper_util::client::legacy::connect::HttpConnector;
+use hyper_util::client::legacy::Client;
+use hyper_util::rt::TokioExecutor;
+use std::time::Duration;
+
+use super::http_proxy::upstream_tls_config;
+
+/// Environment variable naming the endpoint used with `CLAWPOT_DNS_MODE=doh`
+pub const DOH_URL_ENV: &str = "CLAWPOT_DNS_DOH_URL";
+
+/// Endpoint used when `CLAWPOT_DNS_DOH_URL` is unset
+pub const DEFAULT_DOH_URL: &str = "https://dns.google/dns-query";
+
+/// Media type of a wire-format DNS message (RFC 8484)
+const DNS_MESSAGE: &str = "application/dns-message";
+
+/// Largest answer read from the endpoint, the most a DNS message can hold
+const MAX_RESPONSE_BYTES: usize = 65_535;
+
+/// Parse a DoH endpoint URL; it must be `http` or `https` with a host.
+pub fn parse_url(raw: &str) -> Result<Uri, String> {
+    let uri: Uri = raw
+        .trim()
+        .parse()
+        .map_err(|_| format!("'{raw}' is not a URL"))?;
+    match (uri.scheme_str(), uri.host()) {
+        (Some("http" | "https"), Some(_)) => Ok(uri),
+        _ => Err(format!("'{raw}' is not an http(s) URL")),
+    }
+}
+
+/// Forwards DNS queries to a DNS-over-HTTPS endpoint (RFC 8484), POSTing
+/// each wire-format query and returning the binary answer.
+pub struct DohClient {
+    client: Client<hyper_rustls::HttpsConnector<HttpConnector>, Full<Bytes>>,
+    url: Uri,
+}
+
+impl DohClient {
+    pub fn new(url: Uri) -> Self {
+        let mut http_connector = HttpConnector::new();
+        http_connector.enforce_http(false);
+        let https_connector = hyper_rustls::HttpsConnectorBuilder::new()
+            .with_tls_config(upstream_tls_config())
+            .https_or_http()
+            .enable_http1()
+            .wrap_connector(http_connector);
+        Self {
+            client: Client::builder(TokioExecutor::new()).build(https_connector),
+            url,
+        }
+    }
+
+    pub fn url(&self) -> &Uri {
+        &self.url
+    }
+
+    /// Send `query` to the endpoint and return its answer. Fails if it takes
+    /// longer than `timeout`, or the endpoint answers with anything but a
+    /// 200 carrying a DNS message.
+    pub async fn query(&self, query: &[u8], timeout: Duration) -> Result<Vec<u8>> {
+        tokio::time::timeout(timeout, self.post(query))
+            .await
+            .context("DoH upstream timeout")?
+    }
+
+    async fn post(&self, query: &[u8]) -> Result<Vec<u8>> {
+        let request = Request::post(self.url.clone())
+            .header(CONTENT_TYPE, DNS_MESSAGE)
+            .header(ACCEPT, DNS_MESSAGE)
+            .body(Full::new(Bytes::copy_from_slice(query)))
+            .context("Failed to build DoH request")?;
+        let response = self
+            .client
+            .request(request)
+            .await
+            .context("DoH request failed")?;
+
+        let status = response.status();
+        if status != StatusCode::OK {
+            bail!("DoH upstream answered {status}");
+        }
+        let content_type = response
+            .headers()
+            .get(CONTENT_TYPE)
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or_default();
+        // Allow parameters, e.g. `application/dns-message; charset=binary`
+        let media_type = content_type.split(';').next().unwrap_or_default().trim();
+        if !media_type.eq_ignore_ascii_case(DNS_MESSAGE) {
+            bail!("DoH upstream answered with content type {content_type:?}");
+        }
+
+        let body = Limited::new(response.into_body(), MAX_RESPONSE_BYTES)
+            .collect()
+            .await
+            .map_err(|e| anyhow!("Failed to read DoH answer: {e}"))?;
+        Ok(body.to_bytes().to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hyper::body::Incoming;
+    use hyper::server::conn::http1;
+    use hyper::service::service_fn;
+    use hyper::Response;
+    use hyper_util::rt::TokioIo;
+    use std::net::SocketAddr;
+    use tokio::net::TcpListener;
+
+    const ANSWER: &[u8] = &[0x12, 0x34, 0x81, 0x80, 0, 1, 0, 0, 0, 0, 0, 0];
+
+    /// Endpoint that answers `/dns-query` POSTs of a DNS message with a
+    /// canned answer, `/html` with a web page and anything else with a 404.
+    async fn spawn_endpoint() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let service = service_fn(|req: Request<Incoming>| async move {
+                        let is_query = req
+                            .headers()
+                            .get(CONTENT_TYPE)
+                            .is_some_and(|v| v == DNS_MESSAGE);
+                        let (status, content_type, body) = match req.uri().path() {
+                            "/dns-query" if is_query => (StatusCode::OK, DNS_MESSAGE, ANSWER),
+                            "/html" => (StatusCode::OK, "text/html", &b"<html>"[..]),
+                            _ => (StatusCode::NOT_FOUND, "text/plain", &b"not found"[..]),
+                        };
+                        Response::builder()
+                            .status(status)
+                            .header(CONTENT_TYPE, content_type)
+                            .body(Full::new(Bytes::from_static(body)))
+                    });
+                    let _ = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+        addr
+    }
+
+    fn client(addr: SocketAddr, path: &str) -> DohClient {
+        DohClient::new(parse_url(&format!("http://{addr}{path}")).unwrap())
+    }
+
+    #[tokio::test]
+    async fn test_query() {
+        let addr = spawn_endpoint().await;
+        let timeout = Duration::from_secs(5);
+        let query = [0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+
+        let answer = client(addr, "/dns-query")
+            .query(&query, timeout)
+            .await
+            .unwrap();
+        assert_eq!(answer, ANSWER);
+
+        let err = client(addr, "/html").query(&query, timeout).await;
+        assert!(format!("{:#}", err.unwrap_err()).contains("text/html"));
+        let err = client(addr, "/missing").query(&query, timeout).await;
+        assert!(format!("{:#}", err.unwrap_err()).contains("404"));
+    }
+
+    #[test]
+    fn test_parse_url() {
+        assert!(parse_url("https://dns.google/dns-query").is_ok());
+        assert!(parse_url("http://127.0.0.1:8053/dns-query").is_ok());
+        assert!(parse_url("dns.google").is_err());
+        assert!(parse_url("ftp://dns.google/").is_err());
+    }
+}
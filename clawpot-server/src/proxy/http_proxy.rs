@@ -1,27 +1,86 @@
-use anyhow::{Context, Result};
-use http_body_util::{BodyExt, Full};
-use hyper::body::{Bytes, Incoming};
+use anyhow::{anyhow, Context, Result};
+use http_body_util::combinators::BoxBody;
+use http_body_util::{BodyExt, Full, StreamBody};
+use hyper::body::{Bytes, Frame, Incoming};
+use hyper::http::response::Builder;
 use hyper::server::conn::http1;
 use hyper::service::service_fn;
+use hyper::upgrade::OnUpgrade;
 use hyper::{Request, Response, StatusCode};
+use hyper_util::client::legacy::connect::HttpConnector;
 use hyper_util::client::legacy::Client;
-use hyper_util::rt::TokioExecutor;
+use hyper_util::rt::{TokioExecutor, TokioIo};
 use std::collections::HashMap;
-use std::net::SocketAddr;
-use std::sync::Arc;
-use std::time::Instant;
-use tokio::net::TcpListener;
+use std::fmt::Write as _;
+use std::future::Future;
+use std::net::{IpAddr, SocketAddr};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncRead, AsyncWrite};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+use tokio_stream::wrappers::ReceiverStream;
 use tracing::{error, info, warn};
 use uuid::Uuid;
 
-use super::auth_client::AuthClient;
+use super::auth_client::{AuthClient, AuthDecision, DenyCode};
 use super::body_store::BodyStore;
 use super::llm::{self, LlmKeyStore};
+use super::methods::MethodPolicy;
+use super::redact::{self, HeaderRedaction};
+use super::splice::{self, HostList};
+use super::ssrf::{self, SsrfGuard, SsrfResolver};
+use super::throttle::warn_throttled;
+use super::token_budget::{Reservation, TokenBudget};
+use crate::config::ProxyConfig;
 use crate::events::EventStore;
 use crate::vm::VmRegistry;
 
-const HTTP_LISTEN_ADDR: &str = "0.0.0.0:10080";
-const HTTPS_LISTEN_ADDR: &str = "0.0.0.0:10081";
+pub const HTTP_LISTEN_ADDR: &str = "0.0.0.0:10080";
+pub const HTTPS_LISTEN_ADDR: &str = "0.0.0.0:10081";
+
+/// Default time a VM connection may sit with no request in flight before it is closed
+pub const DEFAULT_IDLE_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Header carrying the event correlation id to upstreams when enabled
+const REQUEST_ID_HEADER: &str = "x-clawpot-request-id";
+
+/// Frames of a streamed response buffered between the upstream and the VM
+const STREAM_BUFFER_FRAMES: usize = 16;
+
+/// Environment variable setting the largest request or response body, in
+/// bytes, held in memory; larger bodies are streamed through
+pub const MAX_INLINE_BODY_ENV: &str = "CLAWPOT_PROXY_MAX_INLINE_BODY";
+
+/// Bodies buffered when `CLAWPOT_PROXY_MAX_INLINE_BODY` is unset
+pub const DEFAULT_MAX_INLINE_BODY: usize = 8 * 1024 * 1024; // 8MB
+
+/// Environment variable setting how long, in seconds, to wait for an
+/// upstream's response headers before answering the VM with a 504
+pub const UPSTREAM_TIMEOUT_ENV: &str = "CLAWPOT_PROXY_UPSTREAM_TIMEOUT";
+
+/// Upstream timeout used when `CLAWPOT_PROXY_UPSTREAM_TIMEOUT` is unset
+pub const DEFAULT_UPSTREAM_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Environment variable setting how many times (up to
+/// [`MAX_UPSTREAM_RETRIES`]) a GET or HEAD is retried after a connection
+/// error or a 502, 503 or 504 from the upstream
+pub const UPSTREAM_RETRIES_ENV: &str = "CLAWPOT_PROXY_UPSTREAM_RETRIES";
+
+/// Retries used when `CLAWPOT_PROXY_UPSTREAM_RETRIES` is unset
+pub const DEFAULT_UPSTREAM_RETRIES: usize = 2;
+
+/// Most retries `CLAWPOT_PROXY_UPSTREAM_RETRIES` may ask for
+pub const MAX_UPSTREAM_RETRIES: usize = 10;
+
+/// Wait before the first retry of an upstream request, doubled for each
+/// retry after it up to [`MAX_RETRY_BACKOFF`]
+const RETRY_BACKOFF: Duration = Duration::from_millis(200);
+
+/// Longest wait between retries of an upstream request
+const MAX_RETRY_BACKOFF: Duration = Duration::from_secs(5);
 
 /// Shared context for the HTTP proxy handlers.
 struct ProxyCtx {
@@ -30,11 +89,50 @@ struct ProxyCtx {
     body_store: Arc<BodyStore>,
     auth: Arc<AuthClient>,
     llm_keys: Arc<LlmKeyStore>,
+    splice_hosts: Arc<HostList>,
+    bypass_hosts: Arc<HostList>,
+    /// Splice non-HTTP port-80 traffic to its original destination instead of resetting it
+    splice_non_http: bool,
     use_tls_upstream: bool,
-    http_client: Client<
-        hyper_rustls::HttpsConnector<hyper_util::client::legacy::connect::HttpConnector>,
-        Full<Bytes>,
-    >,
+    http_client: HttpClient,
+    /// Close VM connections idle for this long (`None` disables the timeout)
+    idle_timeout: Option<Duration>,
+    /// Streaming LLM responses larger than this are not reassembled (`None` for no cap)
+    llm_max_sse_bytes: Option<usize>,
+    /// Estimate LLM token counts from text length when the provider omits usage
+    llm_estimate_tokens: bool,
+    /// Send each request's correlation id upstream in `X-Clawpot-Request-Id`
+    inject_request_id: bool,
+    /// Methods VMs may use, enforced before authorization
+    methods: Arc<MethodPolicy>,
+    /// Redirects the proxy follows itself, re-authorizing each hop (0 disables)
+    max_redirects: usize,
+    /// Blocks upstreams that resolve to internal addresses
+    ssrf: Arc<SsrfGuard>,
+    /// LLM tokens left per VM, shared by both listeners
+    token_budget: TokenBudget,
+    /// Bodies larger than this are streamed through and spooled to disk
+    max_inline_body: usize,
+    /// Give up on upstreams that send no response headers for this long (`None` to wait forever)
+    upstream_timeout: Option<Duration>,
+    /// Times a GET or HEAD is retried after a connection error or a 502/503/504
+    upstream_retries: usize,
+    /// Headers whose values are left out of recorded events
+    redact_headers: Arc<HeaderRedaction>,
+    /// Deny traffic from source IPs that aren't registered VMs
+    deny_unknown_vm: bool,
+}
+
+type HttpClient = Client<hyper_rustls::HttpsConnector<HttpConnector<SsrfResolver>>, ProxyBody>;
+
+/// Body of a request or response: buffered, or streamed from the other side
+type ProxyBody = BoxBody<Bytes, hyper::Error>;
+
+/// A buffered response body.
+fn full(body: impl Into<Bytes>) -> ProxyBody {
+    Full::new(body.into())
+        .map_err(|never| match never {})
+        .boxed()
 }
 
 /// Start both HTTP proxy listeners (plain HTTP + TLS upstream).
@@ -44,33 +142,36 @@ pub async fn run(
     body_store: Arc<BodyStore>,
     auth: Arc<AuthClient>,
     llm_keys: Arc<LlmKeyStore>,
+    token_budget: TokenBudget,
+    config: ProxyConfig,
     mut cancel: tokio::sync::watch::Receiver<bool>,
     ready: tokio::sync::oneshot::Sender<()>,
 ) -> Result<()> {
-    // Build TLS root store: start with webpki roots, then add native roots for wider coverage
-    let mut roots = rustls::RootCertStore::empty();
-    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
-    let native = rustls_native_certs::load_native_certs();
-    if !native.errors.is_empty() {
-        warn!("Errors loading native TLS roots: {:?}", native.errors);
-    }
-    if !native.certs.is_empty() {
-        let (added, ignored) = roots.add_parsable_certificates(native.certs);
-        info!(
-            "Loaded {} native TLS root certificates ({} ignored)",
-            added, ignored
-        );
-    }
-    let tls_config = rustls::ClientConfig::builder()
-        .with_root_certificates(roots)
-        .with_no_client_auth();
-    let https_connector = hyper_rustls::HttpsConnectorBuilder::new()
-        .with_tls_config(tls_config)
-        .https_or_http()
-        .enable_http1()
-        .build();
-
-    let http_client = Client::builder(TokioExecutor::new()).build(https_connector);
+    log_config(&config);
+    let ProxyConfig {
+        splice_hosts,
+        bypass_hosts,
+        splice_non_http,
+        idle_timeout,
+        max_redirects,
+        llm_max_sse_bytes,
+        llm_estimate_tokens,
+        inject_request_id,
+        methods,
+        ssrf_allow,
+        vm_token_budget: _,
+        max_inline_body,
+        upstream_timeout,
+        upstream_retries,
+        redact_headers,
+        deny_unknown_vm,
+    } = config;
+    let splice_hosts = Arc::new(splice_hosts);
+    let redact_headers = Arc::new(redact_headers);
+    let bypass_hosts = Arc::new(bypass_hosts);
+    let methods = Arc::new(methods);
+    let ssrf = Arc::new(SsrfGuard::new(ssrf_allow));
+    let http_client = build_http_client(ssrf.clone());
 
     // Pre-bind both listeners before spawning tasks
     let http_listener = TcpListener::bind(HTTP_LISTEN_ADDR)
@@ -94,8 +195,24 @@ pub async fn run(
         body_store: body_store.clone(),
         auth: auth.clone(),
         llm_keys: llm_keys.clone(),
+        splice_hosts: splice_hosts.clone(),
+        bypass_hosts: bypass_hosts.clone(),
+        splice_non_http,
         use_tls_upstream: false,
         http_client: http_client.clone(),
+        idle_timeout,
+        llm_max_sse_bytes,
+        llm_estimate_tokens,
+        inject_request_id,
+        methods: methods.clone(),
+        max_redirects,
+        ssrf: ssrf.clone(),
+        token_budget: token_budget.clone(),
+        max_inline_body,
+        upstream_timeout,
+        upstream_retries,
+        redact_headers: redact_headers.clone(),
+        deny_unknown_vm,
     });
 
     let https_ctx = Arc::new(ProxyCtx {
@@ -104,8 +221,24 @@ pub async fn run(
         body_store,
         auth,
         llm_keys,
+        splice_hosts,
+        bypass_hosts,
+        splice_non_http,
         use_tls_upstream: true,
         http_client,
+        idle_timeout,
+        llm_max_sse_bytes,
+        llm_estimate_tokens,
+        inject_request_id,
+        methods,
+        max_redirects,
+        ssrf,
+        token_budget,
+        max_inline_body,
+        upstream_timeout,
+        upstream_retries,
+        redact_headers,
+        deny_unknown_vm,
     });
 
     let mut cancel2 = cancel.clone();
@@ -127,6 +260,149 @@ pub async fn run(
     Ok(())
 }
 
+/// Log the proxy settings that differ from plain forwarding.
+fn log_config(config: &ProxyConfig) {
+    for (var, hosts) in [
+        (splice::SPLICE_HOSTS_ENV, &config.splice_hosts),
+        (splice::BYPASS_HOSTS_ENV, &config.bypass_hosts),
+    ] {
+        if !hosts.is_empty() {
+            info!("{} lists {} host(s)", var, hosts.len());
+        }
+    }
+    if config.splice_non_http {
+        info!("Non-HTTP traffic on port 80 will be spliced to its original destination");
+    }
+    match config.idle_timeout {
+        Some(t) => info!("Closing idle proxy connections after {}s", t.as_secs()),
+        None => info!("Proxy idle connection timeout disabled"),
+    }
+    if config.max_redirects > 0 {
+        info!(
+            "Following up to {} upstream redirects per request",
+            config.max_redirects
+        );
+    }
+    if config.llm_estimate_tokens {
+        info!("Missing LLM token counts will be estimated from content length");
+    }
+    if config.inject_request_id {
+        info!("Forwarded requests will carry an X-Clawpot-Request-Id header");
+    }
+    if !config.deny_unknown_vm {
+        info!("CLAWPOT_DENY_UNKNOWN_VM disabled: unknown sources will be authorized");
+    }
+    if let Some(budget) = config.vm_token_budget {
+        info!("Each VM may spend {} LLM tokens", budget);
+    }
+    info!(
+        "Bodies over {} bytes will be streamed instead of buffered",
+        config.max_inline_body
+    );
+    match config.upstream_timeout {
+        Some(t) => info!("Upstreams must respond within {}s", t.as_secs()),
+        None => info!("Upstream timeout disabled"),
+    }
+    if config.upstream_retries > 0 {
+        info!(
+            "Retrying GET and HEAD requests up to {} times after upstream failures",
+            config.upstream_retries
+        );
+    }
+    info!(
+        "Header values left out of recorded events: {}",
+        config.redact_headers
+    );
+    if config.methods.allows_all() {
+        info!("All HTTP methods allowed");
+    } else {
+        info!("HTTP methods allowed: {}", config.methods.allow_header());
+    }
+}
+
+/// Tracks whether a VM connection has a request in flight and when it last did.
+struct ConnActivity {
+    in_flight: AtomicUsize,
+    last_active: Mutex<Instant>,
+}
+
+impl ConnActivity {
+    fn new() -> Self {
+        Self {
+            in_flight: AtomicUsize::new(0),
+            last_active: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Mark a request as started; it counts as in flight until the guard drops.
+    fn begin(self: &Arc<Self>) -> ActiveRequest {
+        self.in_flight.fetch_add(1, Ordering::SeqCst);
+        ActiveRequest(self.clone())
+    }
+
+    /// When the connection will have been idle for `timeout`, assuming nothing
+    /// changes. Connections with a request in flight are re-checked a full
+    /// timeout from now, so long-polls are never cut off.
+    fn idle_deadline(&self, timeout: Duration) -> Instant {
+        if self.in_flight.load(Ordering::SeqCst) > 0 {
+            Instant::now() + timeout
+        } else {
+            *self.last_active.lock().unwrap() + timeout
+        }
+    }
+
+    fn is_idle_for(&self, timeout: Duration) -> bool {
+        self.in_flight.load(Ordering::SeqCst) == 0
+            && self.last_active.lock().unwrap().elapsed() >= timeout
+    }
+}
+
+struct ActiveRequest(Arc<ConnActivity>);
+
+impl Drop for ActiveRequest {
+    fn drop(&mut self) {
+        *self.0.last_active.lock().unwrap() = Instant::now();
+        self.0.in_flight.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+/// TLS settings for upstream connections, trusting both webpki and native
+/// root certificates.
+pub fn upstream_tls_config() -> rustls::ClientConfig {
+    // Build TLS root store: start with webpki roots, then add native roots for wider coverage
+    let mut roots = rustls::RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    let native = rustls_native_certs::load_native_certs();
+    if !native.errors.is_empty() {
+        warn!("Errors loading native TLS roots: {:?}", native.errors);
+    }
+    if !native.certs.is_empty() {
+        let (added, ignored) = roots.add_parsable_certificates(native.certs);
+        info!(
+            "Loaded {} native TLS root certificates ({} ignored)",
+            added, ignored
+        );
+    }
+    rustls::ClientConfig::builder()
+        .with_root_certificates(roots)
+        .with_no_client_auth()
+}
+
+/// Build the upstream client. Upstream names are resolved through `ssrf`,
+/// so internal addresses are refused at connect time.
+fn build_http_client(ssrf: Arc<SsrfGuard>) -> HttpClient {
+    let tls_config = upstream_tls_config();
+    let mut http_connector = HttpConnector::new_with_resolver(SsrfResolver::new(ssrf));
+    http_connector.enforce_http(false);
+    let https_connector = hyper_rustls::HttpsConnectorBuilder::new()
+        .with_tls_config(tls_config)
+        .https_or_http()
+        .enable_http1()
+        .wrap_connector(http_connector);
+
+    Client::builder(TokioExecutor::new()).build(https_connector)
+}
+
 async fn run_listener(
     listener: TcpListener,
     ctx: Arc<ProxyCtx>,
@@ -164,7 +440,11 @@ async fn serve_connection(
         match super::proxy_protocol::read_proxy_header(&mut stream).await {
             Ok(ip) => SocketAddr::new(ip, peer_addr.port()),
             Err(e) => {
-                warn!("Failed to read PROXY header from {}: {:#}", peer_addr, e);
+                warn_throttled(
+                    "Failed to read PROXY header",
+                    peer_addr.ip(),
+                    format!("from {peer_addr}: {e:#}"),
+                );
                 return;
             }
         }
@@ -172,19 +452,378 @@ async fn serve_connection(
         peer_addr
     };
 
-    let io = hyper_util::rt::TokioIo::new(stream);
-    let service = service_fn(move |req| {
-        let ctx = ctx.clone();
-        async move { handle_request(req, effective_addr, ctx).await }
+    // Arbitrary TCP on port 80 would only produce a hyper parse error, so deal
+    // with it explicitly
+    if !ctx.use_tls_upstream {
+        if let splice::Sniffed::NotHttp(preview) = splice::sniff_http(&stream).await {
+            handle_non_http(stream, effective_addr, &ctx, &preview).await;
+            return;
+        }
+    }
+
+    // Bypassed destinations are passed through without authorization or parsing
+    if !ctx.use_tls_upstream && !ctx.bypass_hosts.is_empty() {
+        stream = match try_bypass(stream, effective_addr, &ctx).await {
+            Some(stream) => stream,
+            None => return,
+        };
+    }
+
+    // Plain HTTP to an allowlisted host bypasses hyper entirely once authorized
+    if !ctx.use_tls_upstream && !ctx.splice_hosts.is_empty() {
+        stream = match try_splice(stream, effective_addr, &ctx).await {
+            Some(stream) => stream,
+            None => return,
+        };
+    }
+
+    let idle_timeout = ctx.idle_timeout;
+    let activity = Arc::new(ConnActivity::new());
+    let io = TokioIo::new(stream);
+    let service = service_fn({
+        let activity = activity.clone();
+        move |req| {
+            let ctx = ctx.clone();
+            let active = activity.begin();
+            async move {
+                let resp = handle_request(req, effective_addr, ctx).await;
+                drop(active);
+                resp
+            }
+        }
     });
-    if let Err(e) = http1::Builder::new()
+    // Keep-alive lets chatty guests send many requests over one connection.
+    // Upgrades hand the connection over to a tunnel (CONNECT and WebSocket)
+    let conn = http1::Builder::new()
         .preserve_header_case(true)
-        .keep_alive(false)
+        .keep_alive(true)
         .serve_connection(io, service)
-        .await
-    {
+        .with_upgrades();
+    let mut conn = std::pin::pin!(conn);
+
+    let result = loop {
+        let Some(timeout) = idle_timeout else {
+            break conn.as_mut().await;
+        };
+        let deadline = activity.idle_deadline(timeout);
+        tokio::select! {
+            result = conn.as_mut() => break result,
+            () = tokio::time::sleep_until(deadline.into()) => {
+                if activity.is_idle_for(timeout) {
+                    info!(
+                        "Closing HTTP connection from {} after {}s idle",
+                        effective_addr,
+                        timeout.as_secs()
+                    );
+                    conn.as_mut().graceful_shutdown();
+                    break conn.as_mut().await;
+                }
+            }
+        }
+    };
+    if let Err(e) = result {
         if !e.to_string().contains("connection closed") {
-            warn!("HTTP connection from {} error: {}", effective_addr, e);
+            warn_throttled(
+                "HTTP connection error",
+                effective_addr.ip(),
+                format!("from {effective_addr}: {e}"),
+            );
+        }
+    }
+}
+
+/// Splice the connection to its upstream if its first request targets an
+/// allowlisted host and is authorized. Returns the untouched stream when the
+/// request should instead go through the buffered path, or `None` once spliced.
+async fn try_splice(
+    stream: tokio::net::TcpStream,
+    peer_addr: SocketAddr,
+    ctx: &ProxyCtx,
+) -> Option<tokio::net::TcpStream> {
+    let Some(head) = splice::peek_request_head(&stream).await else {
+        return Some(stream);
+    };
+    // Disallowed methods and internal upstreams are rejected by the buffered
+    // path, which also handles upgrades
+    if !ctx.splice_hosts.contains(&head.host)
+        || !ctx.methods.allows(&head.method)
+        || head.headers.contains_key("upgrade")
+    {
+        return Some(stream);
+    }
+    // Connect to the address that was checked, not whatever the name resolves to later
+    let Some(upstream) = ctx
+        .ssrf
+        .resolve(&head.host)
+        .await
+        .ok()
+        .and_then(|addrs| addrs.first().copied())
+    else {
+        return Some(stream);
+    };
+    // Unknown sources are rejected by the buffered path
+    let Some(vm_id) = ctx.registry.find_by_ip(peer_addr.ip()).await else {
+        return Some(stream);
+    };
+    let vm_id = vm_id.to_string();
+    let url = format!("http://{}{}", head.host, head.path);
+
+    // Bodies are never buffered on this path, so authorize on the head alone
+    let decision = ctx
+        .auth
+        .authorize_http(0, &vm_id, &head.method, &url, &head.headers, &[])
+        .await
+        .unwrap_or_else(|_| AuthDecision::deny(DenyCode::AuthUnreachable, "auth error"));
+    if !decision.allowed {
+        // Let the buffered path record and answer the denial
+        return Some(stream);
+    }
+
+    // Only this request was authorized, so the upstream is asked to close
+    // the connection after answering it
+    let outcome = SpliceOutcome::run(stream, &upstream.to_string(), Some(head.len)).await;
+    ctx.events.emit_with_duration(
+        "network.http.spliced",
+        "network",
+        Some(&vm_id),
+        Some(&Uuid::new_v4().to_string()),
+        outcome.duration_ms,
+        Some(outcome.error.is_none()),
+        &serde_json::json!({
+            "method": head.method,
+            "url": url,
+            "reason": decision.reason,
+            "bytes_up": outcome.bytes_up,
+            "bytes_down": outcome.bytes_down,
+            "duration_ms": outcome.duration_ms,
+            "error": outcome.error,
+        }),
+    );
+    None
+}
+
+/// Pass the connection straight through if its original destination IP, or
+/// the `Host` of its first request, is on the bypass list. Nothing is
+/// authorized or parsed beyond that; only the connection itself is logged.
+/// A `Host` match only counts when the connection goes to an address that
+/// host resolves to, since the guest picks both independently.
+/// Returns the untouched stream if it isn't bypassed, or `None` once handled.
+async fn try_bypass(
+    stream: tokio::net::TcpStream,
+    peer_addr: SocketAddr,
+    ctx: &ProxyCtx,
+) -> Option<tokio::net::TcpStream> {
+    let original_dst = splice::original_dst(&stream);
+
+    // Match on the destination IP first so non-HTTP traffic can be bypassed too
+    let (host, upstream) = match original_dst {
+        Some(dst) if ctx.bypass_hosts.contains_ip(dst.ip()) => (None, dst),
+        _ => {
+            let Some(head) = splice::peek_request_head(&stream).await else {
+                return Some(stream);
+            };
+            if !ctx.bypass_hosts.contains(&head.host) {
+                return Some(stream);
+            }
+            // Names that don't resolve, or resolve internally, are answered
+            // by the buffered path
+            let Ok(resolved) = ctx.ssrf.resolve(&head.host).await else {
+                return Some(stream);
+            };
+            let upstream = match original_dst {
+                Some(dst) if resolved.iter().any(|addr| addr.ip() == dst.ip()) => dst,
+                // Dialled somewhere else with a bypassed Host header
+                Some(_) => return Some(stream),
+                None => match resolved.first() {
+                    Some(addr) => *addr,
+                    None => return Some(stream),
+                },
+            };
+            (Some(head.host), upstream)
+        }
+    };
+
+    let vm_id = super::resolve_vm_id(
+        &ctx.registry,
+        &ctx.events,
+        peer_addr.ip(),
+        "HTTP bypass",
+        ctx.deny_unknown_vm,
+    )
+    .await?;
+
+    // Bypassed IPs still need CLAWPOT_SSRF_ALLOW to reach internal ranges
+    if ctx.ssrf.blocks_ip(upstream.ip()) {
+        splice_blocked(ctx, stream, &vm_id, "bypass", upstream);
+        return None;
+    }
+
+    let upstream = upstream.to_string();
+    let outcome = SpliceOutcome::run(stream, &upstream, None).await;
+    ctx.events.emit_with_duration(
+        "network.http.bypassed",
+        "network",
+        Some(&vm_id),
+        Some(&Uuid::new_v4().to_string()),
+        outcome.duration_ms,
+        Some(outcome.error.is_none()),
+        &serde_json::json!({
+            "host": host,
+            "upstream": upstream,
+            "bytes_up": outcome.bytes_up,
+            "bytes_down": outcome.bytes_down,
+            "duration_ms": outcome.duration_ms,
+            "error": outcome.error,
+        }),
+    );
+    None
+}
+
+/// Handle a port-80 connection whose first bytes aren't an HTTP request.
+///
+/// If its original destination is on the bypass list, or non-HTTP splicing
+/// is enabled, the connection is spliced there. Otherwise it is reset so the
+/// guest sees a clear failure.
+async fn handle_non_http(
+    stream: tokio::net::TcpStream,
+    peer_addr: SocketAddr,
+    ctx: &ProxyCtx,
+    preview: &[u8],
+) {
+    let original_dst = splice::original_dst(&stream);
+    let preview_hex = preview.iter().fold(String::new(), |mut hex, b| {
+        let _ = write!(hex, "{b:02x}");
+        hex
+    });
+
+    let Some(vm_id) = super::resolve_vm_id(
+        &ctx.registry,
+        &ctx.events,
+        peer_addr.ip(),
+        "non-HTTP",
+        ctx.deny_unknown_vm,
+    )
+    .await
+    else {
+        splice::reset(stream);
+        return;
+    };
+
+    let splice_to =
+        original_dst.filter(|dst| ctx.splice_non_http || ctx.bypass_hosts.contains_ip(dst.ip()));
+    let Some(dst) = splice_to else {
+        warn!(
+            "Resetting non-HTTP connection on port 80 from {} to {} (first bytes: {})",
+            peer_addr,
+            original_dst.map_or_else(|| "unknown".to_string(), |d| d.to_string()),
+            preview_hex
+        );
+        ctx.events.emit(
+            "network.tcp.non_http",
+            "network",
+            Some(&vm_id),
+            None,
+            &serde_json::json!({
+                "action": "reset",
+                "original_dst": original_dst.map(|d| d.to_string()),
+                "preview": preview_hex,
+            }),
+        );
+        splice::reset(stream);
+        return;
+    };
+    if ctx.ssrf.blocks_ip(dst.ip()) {
+        splice_blocked(ctx, stream, &vm_id, "non_http", dst);
+        return;
+    }
+
+    let outcome = SpliceOutcome::run(stream, &dst.to_string(), None).await;
+    ctx.events.emit_with_duration(
+        "network.tcp.non_http",
+        "network",
+        Some(&vm_id),
+        Some(&Uuid::new_v4().to_string()),
+        outcome.duration_ms,
+        Some(outcome.error.is_none()),
+        &serde_json::json!({
+            "action": "spliced",
+            "original_dst": dst.to_string(),
+            "preview": preview_hex,
+            "bytes_up": outcome.bytes_up,
+            "bytes_down": outcome.bytes_down,
+            "duration_ms": outcome.duration_ms,
+            "error": outcome.error,
+        }),
+    );
+}
+
+/// Record and reset a connection that would be spliced to an internal address.
+fn splice_blocked(
+    ctx: &ProxyCtx,
+    stream: tokio::net::TcpStream,
+    vm_id: &str,
+    kind: &str,
+    upstream: SocketAddr,
+) {
+    warn_throttled(
+        "Blocked splice to internal address",
+        vm_id,
+        format!("{kind} connection to {upstream} from {vm_id}"),
+    );
+    ctx.events.emit(
+        "security.ssrf_blocked",
+        "security",
+        Some(vm_id),
+        None,
+        &serde_json::json!({
+            "splice": kind,
+            "upstream": upstream.to_string(),
+            "resolved_ip": upstream.ip().to_string(),
+        }),
+    );
+    splice::reset(stream);
+}
+
+/// Result of splicing a VM connection to an upstream, for logging.
+struct SpliceOutcome {
+    duration_ms: i64,
+    bytes_up: u64,
+    bytes_down: u64,
+    error: Option<String>,
+}
+
+impl SpliceOutcome {
+    /// Splice `stream` to `upstream`. With `request_head`, the length of the
+    /// connection's first request head, only that request is passed through
+    /// (see [`splice::splice_one_request`]).
+    async fn run(
+        stream: tokio::net::TcpStream,
+        upstream: &str,
+        request_head: Option<usize>,
+    ) -> Self {
+        let start = Instant::now();
+        let result = match request_head {
+            Some(head_len) => splice::splice_one_request(stream, upstream, head_len).await,
+            None => splice::splice(stream, upstream).await,
+        };
+        let duration_ms = start.elapsed().as_millis() as i64;
+
+        match result {
+            Ok((bytes_up, bytes_down)) => Self {
+                duration_ms,
+                bytes_up,
+                bytes_down,
+                error: None,
+            },
+            Err(e) => {
+                warn_throttled("Splice failed", upstream, format!("to {upstream}: {e:#}"));
+                Self {
+                    duration_ms,
+                    bytes_up: 0,
+                    bytes_down: 0,
+                    error: Some(format!("{e:#}")),
+                }
+            }
         }
     }
 }
@@ -193,49 +832,140 @@ async fn handle_request(
     req: Request<Incoming>,
     peer_addr: SocketAddr,
     ctx: Arc<ProxyCtx>,
-) -> Result<Response<Full<Bytes>>, hyper::Error> {
+) -> Result<Response<ProxyBody>, hyper::Error> {
     match handle_request_inner(req, peer_addr, ctx).await {
         Ok(resp) => Ok(resp),
         Err(e) => {
-            warn!("Proxy request failed: {:#}", e);
+            warn_throttled("Proxy request failed", peer_addr.ip(), format!("{e:#}"));
             Ok(Response::builder()
                 .status(StatusCode::BAD_GATEWAY)
-                .body(Full::new(Bytes::from(format!("Proxy error: {e}"))))
+                .body(full(format!("Proxy error: {e}")))
                 .unwrap())
         }
     }
 }
 
+/// Record and answer an LLM request from a VM with no token budget left.
+fn budget_exceeded(
+    ctx: &ProxyCtx,
+    vm_id: &str,
+    corr_id: &str,
+    det: &llm::LlmDetection,
+    start: Instant,
+) -> Response<ProxyBody> {
+    warn_throttled(
+        "LLM token budget exceeded",
+        vm_id,
+        format!("{} request from {vm_id}", det.provider),
+    );
+    ctx.events.emit(
+        "llm.budget_exceeded",
+        "llm",
+        Some(vm_id),
+        Some(corr_id),
+        &serde_json::json!({
+            "provider": det.provider,
+            "endpoint": det.endpoint,
+        }),
+    );
+    let duration_ms = start.elapsed().as_millis() as i64;
+    ctx.events.emit_with_duration(
+        "network.http.response",
+        "network",
+        Some(vm_id),
+        Some(corr_id),
+        duration_ms,
+        Some(false),
+        &serde_json::json!({
+            "status_code": 429,
+            "resp_body_size": 0,
+            "duration_ms": duration_ms,
+        }),
+    );
+    let body = serde_json::json!({
+        "error": {
+            "type": "token_budget_exceeded",
+            "message": "This VM has used its LLM token budget",
+        }
+    });
+    Response::builder()
+        .status(StatusCode::TOO_MANY_REQUESTS)
+        .header(hyper::header::CONTENT_TYPE, "application/json")
+        .body(full(body.to_string()))
+        .unwrap()
+}
+
+/// Record and answer a request whose upstream resolves to an internal address.
+fn ssrf_blocked(
+    ctx: &ProxyCtx,
+    vm_id: &str,
+    corr_id: &str,
+    method: &str,
+    url: &str,
+    host: &str,
+    ip: IpAddr,
+) -> Response<ProxyBody> {
+    warn_throttled(
+        "Blocked request to internal address",
+        vm_id,
+        format!("{method} {url} ({ip}) from {vm_id}"),
+    );
+    ctx.events.emit(
+        "security.ssrf_blocked",
+        "security",
+        Some(vm_id),
+        Some(corr_id),
+        &serde_json::json!({
+            "method": method,
+            "url": url,
+            "resolved_ip": ip.to_string(),
+        }),
+    );
+    Response::builder()
+        .status(StatusCode::FORBIDDEN)
+        .body(full(format!(
+            "Denied: {host} resolves to internal address {ip}"
+        )))
+        .unwrap()
+}
+
 async fn handle_request_inner(
-    req: Request<Incoming>,
+    mut req: Request<Incoming>,
     peer_addr: SocketAddr,
     ctx: Arc<ProxyCtx>,
-) -> Result<Response<Full<Bytes>>> {
+) -> Result<Response<ProxyBody>> {
     let start = Instant::now();
     let corr_id = Uuid::new_v4().to_string();
 
-    // 1. Resolve vm_id from source IP — block unknown sources
-    let vm_id = if let Some(id) = ctx.registry.find_by_ip(peer_addr.ip()).await {
-        id.to_string()
-    } else {
-        warn!(
-            "Blocking HTTP request from unknown source IP: {}",
-            peer_addr.ip()
-        );
+    // 1. Resolve vm_id from source IP, applying the unknown-source policy
+    let Some(vm_id) = super::resolve_vm_id(
+        &ctx.registry,
+        &ctx.events,
+        peer_addr.ip(),
+        "HTTP",
+        ctx.deny_unknown_vm,
+    )
+    .await
+    else {
         return Ok(Response::builder()
             .status(StatusCode::FORBIDDEN)
-            .body(Full::new(Bytes::from("Unknown VM")))
+            .body(full("Unknown VM"))
             .unwrap());
     };
 
     // 2. Extract request metadata
     let method = req.method().to_string();
-    let host = req
-        .headers()
-        .get("host")
-        .and_then(|v| v.to_str().ok())
-        .unwrap_or("unknown")
-        .to_string();
+    let connect = req.method() == hyper::Method::CONNECT;
+    let host = match req.uri().authority() {
+        // CONNECT names its target in the request line
+        Some(authority) if connect => authority.to_string(),
+        _ => req
+            .headers()
+            .get("host")
+            .and_then(|v| v.to_str().ok())
+            .unwrap_or("unknown")
+            .to_string(),
+    };
 
     let path = req
         .uri()
@@ -247,25 +977,72 @@ async fn handle_request_inner(
     } else {
         "http"
     };
-    let url = format!("{scheme}://{host}{path}");
+    // A CONNECT target is tunnelled to, not fetched, so it has no URL
+    let url = if connect {
+        host.clone()
+    } else {
+        format!("{scheme}://{host}{path}")
+    };
+
+    // 2b. Reject disallowed methods locally, before asking the auth service
+    if !ctx.methods.allows(&method) {
+        warn_throttled(
+            "HTTP method blocked",
+            &vm_id,
+            format!("{method} {url} from {vm_id}"),
+        );
+        ctx.events.emit(
+            "network.http.method_blocked",
+            "network",
+            Some(&vm_id),
+            Some(&corr_id),
+            &serde_json::json!({
+                "method": method,
+                "url": url,
+            }),
+        );
+        return Ok(Response::builder()
+            .status(StatusCode::METHOD_NOT_ALLOWED)
+            .header(hyper::header::ALLOW, ctx.methods.allow_header())
+            .body(full(format!("Method {method} not allowed")))
+            .unwrap());
+    }
+
+    // 2c. Never forward to private, loopback or link-local addresses. The
+    // connector checks again when it connects, in case the name rebinds
+    if let Some(ip) = ctx.ssrf.check(&host).await {
+        return Ok(ssrf_blocked(
+            &ctx, &vm_id, &corr_id, &method, &url, &host, ip,
+        ));
+    }
 
     let headers_map: HashMap<String, String> = req
         .headers()
         .iter()
         .map(|(k, v)| (k.to_string(), v.to_str().unwrap_or("").to_string()))
         .collect();
-    let headers_json = serde_json::to_string(&headers_map).unwrap_or_default();
+    // The recorded copy leaves out credentials; the forwarded request keeps them
+    let headers_json = ctx.redact_headers.to_json(&headers_map);
+
+    // Tunnels take over the VM's connection once the response has been sent
+    let connect_upgrade = connect.then(|| hyper::upgrade::on(&mut req));
+    let websocket_upgrade =
+        (!connect && is_websocket_upgrade(req.headers())).then(|| hyper::upgrade::on(&mut req));
 
-    // Collect request body
+    // Collect small request bodies. Larger ones are streamed upstream once
+    // authorized; only the part read so far is authorized and logged
     let (parts, body) = req.into_parts();
-    let req_body = body
-        .collect()
-        .await
-        .map(http_body_util::Collected::to_bytes)
-        .unwrap_or_default();
+    let (req_body, mut streamed_req) = match read_body(body, ctx.max_inline_body).await {
+        ReadBody::Buffered(body) => (body, None),
+        ReadBody::Streaming { prefix, rest } => (prefix.clone(), Some((prefix, rest))),
+    };
 
-    // 3. Store body and log request event
-    let stored_body = ctx.body_store.store(0, "req", &req_body).ok();
+    // 3. Store body and log request event (streamed bodies are spooled as they go)
+    let stored_body = if streamed_req.is_some() {
+        None
+    } else {
+        ctx.body_store.store(0, "req", &req_body).ok()
+    };
     let req_body_path = match &stored_body {
         Some(super::body_store::StoredBody::External(p)) => Some(p.to_string_lossy().to_string()),
         _ => None,
@@ -282,16 +1059,17 @@ async fn handle_request_inner(
             "headers": headers_json,
             "req_body_size": req_body.len(),
             "req_body_path": req_body_path,
+            "req_body_streamed": streamed_req.is_some(),
         }),
     );
 
     // 4. Authorize
     let auth_start = Instant::now();
-    let (allowed, reason) = ctx
+    let decision = ctx
         .auth
         .authorize_http(0, &vm_id, &method, &url, &headers_map, &req_body)
         .await
-        .unwrap_or((false, "auth error".to_string()));
+        .unwrap_or_else(|_| AuthDecision::deny(DenyCode::AuthUnreachable, "auth error"));
     let auth_latency = auth_start.elapsed().as_millis() as i64;
 
     ctx.events.emit(
@@ -300,14 +1078,15 @@ async fn handle_request_inner(
         Some(&vm_id),
         Some(&corr_id),
         &serde_json::json!({
-            "allowed": allowed,
-            "reason": reason,
+            "allowed": decision.allowed,
+            "reason": decision.reason,
+            "deny_code": decision.deny_code_str(),
             "latency_ms": auth_latency,
         }),
     );
 
     // 5. If denied, return 403
-    if !allowed {
+    if !decision.allowed {
         let duration_ms = start.elapsed().as_millis() as i64;
         ctx.events.emit_with_duration(
             "network.http.response",
@@ -324,18 +1103,34 @@ async fn handle_request_inner(
         );
         return Ok(Response::builder()
             .status(StatusCode::FORBIDDEN)
-            .body(Full::new(Bytes::from(format!("Denied: {reason}"))))
+            .body(full(format!("Denied: {}", decision.reason)))
             .unwrap());
     }
 
+    // 5a. CONNECT tunnels raw bytes to the authorized target
+    if let Some(vm_upgrade) = connect_upgrade {
+        return Ok(open_connect_tunnel(ctx, vm_id, corr_id, host, start, vm_upgrade).await);
+    }
+
     // 5b. Detect LLM API request
     let llm_detection = llm::detect_llm_request(&host, &path, &headers_map, &ctx.llm_keys);
 
+    let mut budget_reservation = None;
     if let Some(ref det) = llm_detection {
-        let req_body_json: serde_json::Value =
-            serde_json::from_slice(&req_body).unwrap_or(serde_json::Value::Null);
-        let (model, message_count, streaming) =
-            llm::extract_request_summary(&det.endpoint, &req_body);
+        // 5c. Refuse LLM requests from VMs that have spent their token budget
+        let Some(reservation) = ctx.token_budget.reserve(&vm_id) else {
+            return Ok(budget_exceeded(&ctx, &vm_id, &corr_id, det, start));
+        };
+        budget_reservation = Some(reservation);
+
+        let ((model, message_count, streaming), summary_truncated) =
+            llm::summarize_request(&det.endpoint, &req_body);
+        // Large bodies are only kept in the body store, not parsed into the event
+        let req_body_json: serde_json::Value = if summary_truncated {
+            serde_json::Value::Null
+        } else {
+            serde_json::from_slice(&req_body).unwrap_or(serde_json::Value::Null)
+        };
 
         ctx.events.emit(
             "llm.request",
@@ -348,48 +1143,199 @@ async fn handle_request_inner(
                 "model": model,
                 "message_count": message_count,
                 "streaming": streaming,
+                "summary_truncated": summary_truncated,
                 "url": url,
                 "body": req_body_json,
             }),
         );
     }
 
-    // 6. Forward to upstream
-    let upstream_uri: hyper::Uri = url
+    // 6. Forward to upstream, following redirects if enabled
+    let original_uri: hyper::Uri = url
         .parse()
         .with_context(|| format!("Invalid upstream URL: {url}"))?;
+    let mut upstream_uri = original_uri.clone();
+    let mut upstream_method = parts.method.clone();
+    let mut upstream_body = req_body.clone();
+    let mut redirects = 0;
+    // Set once a redirect has switched to a bodyless GET
+    let mut body_dropped = false;
+    // A streamed body can only be sent once, so it is never resent to a redirect
+    // target; a WebSocket handshake must reach the upstream the VM asked for
+    let max_redirects = if streamed_req.is_some() || websocket_upgrade.is_some() {
+        0
+    } else {
+        ctx.max_redirects
+    };
 
-    let mut upstream_req = Request::builder().method(parts.method).uri(&upstream_uri);
-
-    for (key, value) in &parts.headers {
-        let key_str = key.as_str().to_lowercase();
-        // Strip VM-provided auth header if we're injecting a server-managed key
-        if let Some(ref det) = llm_detection {
-            if let Some(ref strip) = det.strip_header {
-                if key_str == strip.to_lowercase() {
+    let upstream_resp = loop {
+        // Repeating a GET or HEAD has no side effects, but a streamed body
+        // can't be sent twice
+        let retries = if streamed_req.is_none() && is_idempotent(&upstream_method) {
+            ctx.upstream_retries
+        } else {
+            0
+        };
+        let mut attempt = 0;
+        let resp = loop {
+            let body = match streamed_req.take() {
+                Some((prefix, rest)) => {
+                    let events = ctx.events.clone();
+                    let (event_vm_id, event_corr_id) = (vm_id.clone(), corr_id.clone());
+                    spool_body(&ctx, &corr_id, "req", prefix, rest, move |spooled| {
+                        events.emit(
+                            "network.http.request_body",
+                            "network",
+                            Some(&event_vm_id),
+                            Some(&event_corr_id),
+                            &serde_json::json!({
+                                "req_body_size": spooled.size,
+                                "req_body_path": spooled.path,
+                                "complete": spooled.complete,
+                            }),
+                        );
+                    })
+                }
+                None => full(upstream_body.clone()),
+            };
+            // Server-managed keys only go to the target the VM asked for
+            let upstream_req = build_upstream_request(
+                &ctx,
+                &upstream_method,
+                &upstream_uri,
+                &parts.headers,
+                body,
+                body_dropped,
+                llm_detection.as_ref().filter(|_| redirects == 0),
+                &corr_id,
+                (redirects > 0).then_some(&original_uri),
+            )?;
+            let outcome = send_upstream(&ctx, upstream_req).await;
+            if attempt < retries {
+                if let Some(reason) = retry_reason(&outcome) {
+                    attempt += 1;
+                    ctx.events.emit(
+                        "network.http.retry",
+                        "network",
+                        Some(&vm_id),
+                        Some(&corr_id),
+                        &serde_json::json!({
+                            "method": upstream_method.as_str(),
+                            "url": upstream_uri.to_string(),
+                            "attempt": attempt,
+                            "reason": reason,
+                        }),
+                    );
+                    tokio::time::sleep(retry_backoff(attempt)).await;
                     continue;
                 }
             }
-        }
-        upstream_req = upstream_req.header(key, value);
-    }
+            match outcome {
+                Ok(resp) => break resp,
+                Err(UpstreamFailure::TimedOut) => {
+                    return Ok(upstream_timeout(
+                        &ctx,
+                        &vm_id,
+                        &corr_id,
+                        upstream_method.as_str(),
+                        &upstream_uri,
+                        start,
+                    ));
+                }
+                Err(UpstreamFailure::Failed { err, .. }) => {
+                    // The name resolved to an internal address by the time we connected
+                    if let Some(ip) = ssrf::blocked_ip(&err) {
+                        let url = upstream_uri.to_string();
+                        let host = upstream_uri.authority().map_or("", |a| a.as_str());
+                        return Ok(ssrf_blocked(
+                            &ctx,
+                            &vm_id,
+                            &corr_id,
+                            upstream_method.as_str(),
+                            &url,
+                            host,
+                            ip,
+                        ));
+                    }
+                    return Err(err);
+                }
+            }
+        };
 
-    // Inject server-managed API key
-    if let Some(ref det) = llm_detection {
-        if let Some((ref header_name, ref header_value)) = det.inject_header {
-            upstream_req = upstream_req.header(header_name.as_str(), header_value.as_str());
+        if redirects >= max_redirects {
+            break resp;
         }
-    }
+        let Some(next_uri) = redirect_target(resp.status(), resp.headers(), &upstream_uri) else {
+            break resp;
+        };
 
-    let upstream_req = upstream_req
-        .body(Full::new(req_body.clone()))
-        .context("Failed to build upstream request")?;
+        // 303, and 301/302 after a POST, switch to a bodyless GET like browsers do
+        let (next_method, next_body, drops_body) = match resp.status() {
+            StatusCode::SEE_OTHER => (hyper::Method::GET, Bytes::new(), true),
+            StatusCode::MOVED_PERMANENTLY | StatusCode::FOUND
+                if upstream_method == hyper::Method::POST =>
+            {
+                (hyper::Method::GET, Bytes::new(), true)
+            }
+            _ => (upstream_method.clone(), upstream_body.clone(), false),
+        };
 
-    let upstream_resp = ctx
-        .http_client
-        .request(upstream_req)
-        .await
-        .context("Upstream request failed")?;
+        // Policy applies to every hop, not just the URL the VM asked for
+        let next_url = next_uri.to_string();
+        let next_host = next_uri.authority().map_or("", |a| a.as_str());
+        if let Some(ip) = ctx.ssrf.check(next_host).await {
+            ctx.events.emit(
+                "security.ssrf_blocked",
+                "security",
+                Some(&vm_id),
+                Some(&corr_id),
+                &serde_json::json!({
+                    "method": next_method.as_str(),
+                    "url": next_url,
+                    "resolved_ip": ip.to_string(),
+                    "redirect_from": upstream_uri.to_string(),
+                }),
+            );
+            break resp;
+        }
+        let decision = ctx
+            .auth
+            .authorize_http(
+                0,
+                &vm_id,
+                next_method.as_str(),
+                &next_url,
+                &headers_map,
+                &next_body,
+            )
+            .await
+            .unwrap_or_else(|_| AuthDecision::deny(DenyCode::AuthUnreachable, "auth error"));
+        ctx.events.emit(
+            "network.http.redirect",
+            "network",
+            Some(&vm_id),
+            Some(&corr_id),
+            &serde_json::json!({
+                "from": upstream_uri.to_string(),
+                "to": next_url,
+                "status_code": resp.status().as_u16(),
+                "hop": redirects + 1,
+                "followed": decision.allowed,
+                "reason": decision.reason,
+                "deny_code": decision.deny_code_str(),
+            }),
+        );
+        if !decision.allowed {
+            // Hand the redirect back; if the VM follows it, it is authorized again
+            break resp;
+        }
+
+        redirects += 1;
+        upstream_uri = next_uri;
+        upstream_method = next_method;
+        upstream_body = next_body;
+        body_dropped |= drops_body;
+    };
 
     let status = upstream_resp.status();
     let resp_headers: HashMap<String, String> = upstream_resp
@@ -397,75 +1343,1739 @@ async fn handle_request_inner(
         .iter()
         .map(|(k, v)| (k.to_string(), v.to_str().unwrap_or("").to_string()))
         .collect();
-    let resp_headers_json = serde_json::to_string(&resp_headers).unwrap_or_default();
 
-    // Collect response body
-    let resp_body = upstream_resp
-        .into_body()
-        .collect()
-        .await
-        .map(http_body_util::Collected::to_bytes)
-        .unwrap_or_default();
+    // 6b. A WebSocket handshake the upstream accepted turns into a tunnel
+    if let Some(vm_upgrade) = websocket_upgrade {
+        if status == StatusCode::SWITCHING_PROTOCOLS {
+            log_tunnel_response(&ctx, &vm_id, &corr_id, start, status, "websocket");
+            let tunnel = Tunnel {
+                ctx,
+                vm_id,
+                corr_id,
+                kind: "websocket",
+                target: url,
+            };
+            let upstream = async move {
+                hyper::upgrade::on(upstream_resp)
+                    .await
+                    .map(TokioIo::new)
+                    .context("Upstream connection upgrade failed")
+            };
+            tokio::spawn(tunnel.run(vm_upgrade, upstream));
+            // The upstream's Upgrade header is kept; Connection is re-added
+            // since it is otherwise dropped as hop-by-hop
+            return Ok(response_head(status, &resp_headers)
+                .header(hyper::header::CONNECTION, "upgrade")
+                .body(full(Bytes::new()))
+                .unwrap());
+        }
+    }
+
+    let exchange = Exchange {
+        vm_id,
+        corr_id,
+        llm_detection,
+        budget_reservation,
+        req_body,
+        start,
+        redirects,
+    };
+
+    // 7. Server-sent events go to the VM as they arrive and are logged at the end
+    let is_event_stream = resp_headers
+        .get("content-type")
+        .is_some_and(|ct| ct.contains("text/event-stream"));
+    if is_event_stream {
+        let response = response_head(status, &resp_headers);
+        let body = stream_response(
+            ctx,
+            exchange,
+            status,
+            resp_headers,
+            upstream_resp.into_body(),
+        );
+        return Ok(response.body(body).unwrap());
+    }
+
+    // Collect small response bodies; larger ones are streamed to the VM and
+    // logged once they end
+    let resp_body = match read_body(upstream_resp.into_body(), ctx.max_inline_body).await {
+        ReadBody::Buffered(body) => body,
+        ReadBody::Streaming { prefix, rest } => {
+            let response = response_head(status, &resp_headers);
+            let logger = ctx.clone();
+            let corr_id = exchange.corr_id.clone();
+            let body = spool_body(
+                &ctx,
+                &corr_id,
+                "resp",
+                prefix.clone(),
+                rest,
+                move |spooled| {
+                    log_response(
+                        &logger,
+                        &exchange,
+                        status,
+                        &resp_headers,
+                        &prefix,
+                        spooled.size,
+                        true,
+                        spooled.path.as_deref(),
+                    );
+                },
+            );
+            return Ok(response.body(body).unwrap());
+        }
+    };
+
+    // 8. Log response events
+    log_response(
+        &ctx,
+        &exchange,
+        status,
+        &resp_headers,
+        &resp_body,
+        resp_body.len(),
+        false,
+        None,
+    );
+
+    // 9. Return response to VM
+    Ok(response_head(status, &resp_headers)
+        .body(full(resp_body))
+        .unwrap())
+}
+
+/// Why an upstream request produced no response
+enum UpstreamFailure {
+    /// No response headers arrived within the upstream timeout
+    TimedOut,
+    Failed {
+        err: anyhow::Error,
+        /// The connection couldn't be made, so the request never reached the upstream
+        connect: bool,
+    },
+}
 
-    // 7. Log LLM response event (before generic network event)
+/// Send a request upstream, giving up if its response headers take longer
+/// than the upstream timeout.
+async fn send_upstream(
+    ctx: &ProxyCtx,
+    req: Request<ProxyBody>,
+) -> Result<Response<Incoming>, UpstreamFailure> {
+    let request = ctx.http_client.request(req);
+    let result = match ctx.upstream_timeout {
+        Some(timeout) => tokio::time::timeout(timeout, request)
+            .await
+            .map_err(|_| UpstreamFailure::TimedOut)?,
+        None => request.await,
+    };
+    result.map_err(|e| UpstreamFailure::Failed {
+        connect: e.is_connect(),
+        err: anyhow::Error::new(e).context("Upstream request failed"),
+    })
+}
+
+fn is_idempotent(method: &hyper::Method) -> bool {
+    matches!(*method, hyper::Method::GET | hyper::Method::HEAD)
+}
+
+/// How long to wait before retry number `attempt` (counting from 1)
+fn retry_backoff(attempt: usize) -> Duration {
+    u32::try_from(attempt.saturating_sub(1))
+        .ok()
+        .and_then(|doublings| 2u32.checked_pow(doublings))
+        .and_then(|factor| RETRY_BACKOFF.checked_mul(factor))
+        .map_or(MAX_RETRY_BACKOFF, |backoff| backoff.min(MAX_RETRY_BACKOFF))
+}
+
+/// Why an attempt is worth repeating, if it is: it couldn't connect, or
+/// the upstream answered 502, 503 or 504. Timeouts aren't retried, since
+/// an upstream that hangs once is likely to hang again.
+fn retry_reason(outcome: &Result<Response<Incoming>, UpstreamFailure>) -> Option<String> {
+    match outcome {
+        Ok(resp) => matches!(
+            resp.status(),
+            StatusCode::BAD_GATEWAY | StatusCode::SERVICE_UNAVAILABLE | StatusCode::GATEWAY_TIMEOUT
+        )
+        .then(|| format!("status {}", resp.status().as_u16())),
+        // Connecting to an internal address fails the same way every time
+        Err(UpstreamFailure::Failed { err, connect: true }) if ssrf::blocked_ip(err).is_none() => {
+            Some(format!("{err:#}"))
+        }
+        Err(_) => None,
+    }
+}
+
+/// Record and answer a request whose upstream sent no response in time.
+fn upstream_timeout(
+    ctx: &ProxyCtx,
+    vm_id: &str,
+    corr_id: &str,
+    method: &str,
+    uri: &hyper::Uri,
+    start: Instant,
+) -> Response<ProxyBody> {
+    warn_throttled(
+        "Upstream timed out",
+        vm_id,
+        format!("{method} {uri} from {vm_id}"),
+    );
     let duration_ms = start.elapsed().as_millis() as i64;
-    if let Some(ref det) = llm_detection {
+    ctx.events.emit_with_duration(
+        "network.http.timeout",
+        "network",
+        Some(vm_id),
+        Some(corr_id),
+        duration_ms,
+        Some(false),
+        &serde_json::json!({
+            "method": method,
+            "url": uri.to_string(),
+            "status_code": 504,
+            "resp_body_size": 0,
+            "timeout_ms": ctx.upstream_timeout.map(|t| t.as_millis() as u64),
+            "duration_ms": duration_ms,
+        }),
+    );
+    Response::builder()
+        .status(StatusCode::GATEWAY_TIMEOUT)
+        .body(full("Upstream timed out"))
+        .unwrap()
+}
+
+/// What logging a response needs to know about the request it answers, kept
+/// until a streamed response ends.
+struct Exchange {
+    vm_id: String,
+    corr_id: String,
+    llm_detection: Option<llm::LlmDetection>,
+    /// Token budget held for an LLM request until its response is logged
+    budget_reservation: Option<Reservation>,
+    req_body: Bytes,
+    start: Instant,
+    redirects: usize,
+}
+
+/// Whether a request asks to switch its connection to the WebSocket protocol.
+fn is_websocket_upgrade(headers: &hyper::HeaderMap) -> bool {
+    let has_token = |name, token: &str| {
+        headers
+            .get_all(name)
+            .iter()
+            .filter_map(|v| v.to_str().ok())
+            .flat_map(|v| v.split(','))
+            .any(|t| t.trim().eq_ignore_ascii_case(token))
+    };
+    has_token(hyper::header::CONNECTION, "upgrade")
+        && has_token(hyper::header::UPGRADE, "websocket")
+}
+
+/// Connect to the target of an authorized `CONNECT` request and answer 200,
+/// after which raw bytes are copied between the VM and the target until
+/// either side closes. Answers 502 if the target can't be reached.
+async fn open_connect_tunnel(
+    ctx: Arc<ProxyCtx>,
+    vm_id: String,
+    corr_id: String,
+    target: String,
+    start: Instant,
+    vm_upgrade: OnUpgrade,
+) -> Response<ProxyBody> {
+    // Connect to the addresses that were checked, not whatever the name resolves to later
+    let connected = match ctx.ssrf.resolve(&target).await {
+        Ok(addrs) => TcpStream::connect(&addrs[..])
+            .await
+            .with_context(|| format!("Failed to connect to {target}")),
+        Err(e) => Err(anyhow!("Failed to resolve {target}: {e}")),
+    };
+    let upstream = match connected {
+        Ok(upstream) => upstream,
+        Err(e) => {
+            warn_throttled("CONNECT tunnel failed", &vm_id, format!("{e:#}"));
+            log_tunnel_response(
+                &ctx,
+                &vm_id,
+                &corr_id,
+                start,
+                StatusCode::BAD_GATEWAY,
+                "connect",
+            );
+            return Response::builder()
+                .status(StatusCode::BAD_GATEWAY)
+                .body(full(format!("Proxy error: {e:#}")))
+                .unwrap();
+        }
+    };
+    upstream.set_nodelay(true).ok();
+
+    log_tunnel_response(&ctx, &vm_id, &corr_id, start, StatusCode::OK, "connect");
+    let tunnel = Tunnel {
+        ctx,
+        vm_id,
+        corr_id,
+        kind: "connect",
+        target,
+    };
+    tokio::spawn(tunnel.run(vm_upgrade, std::future::ready(Ok(upstream))));
+    Response::new(full(Bytes::new()))
+}
+
+/// Log the `network.http.response` event for a request answered by opening
+/// (or failing to open) a tunnel.
+fn log_tunnel_response(
+    ctx: &ProxyCtx,
+    vm_id: &str,
+    corr_id: &str,
+    start: Instant,
+    status: StatusCode,
+    kind: &str,
+) {
+    let duration_ms = start.elapsed().as_millis() as i64;
+    ctx.events.emit_with_duration(
+        "network.http.response",
+        "network",
+        Some(vm_id),
+        Some(corr_id),
+        duration_ms,
+        Some(status.is_success() || status.is_informational()),
+        &serde_json::json!({
+            "status_code": status.as_u16(),
+            "resp_body_size": 0,
+            "duration_ms": duration_ms,
+            "tunnel": kind,
+        }),
+    );
+}
+
+/// A VM connection handed over from HTTP to raw bytes, by `CONNECT` or a
+/// WebSocket upgrade.
+struct Tunnel {
+    ctx: Arc<ProxyCtx>,
+    vm_id: String,
+    corr_id: String,
+    /// `connect` or `websocket`
+    kind: &'static str,
+    /// `host:port` for `CONNECT`, the handshake URL for WebSocket
+    target: String,
+}
+
+impl Tunnel {
+    /// Wait for both connections to be handed over, then copy bytes between
+    /// them until either side closes. Logs `network.tunnel.opened` once both
+    /// are ready and `network.tunnel.closed` at the end.
+    async fn run<U>(self, vm: OnUpgrade, upstream: impl Future<Output = Result<U>>)
+    where
+        U: AsyncRead + AsyncWrite + Unpin,
+    {
+        let start = Instant::now();
+        let result = async {
+            let (vm, mut upstream) = tokio::try_join!(
+                async { vm.await.context("VM connection upgrade failed") },
+                upstream
+            )?;
+            self.ctx.events.emit(
+                "network.tunnel.opened",
+                "network",
+                Some(&self.vm_id),
+                Some(&self.corr_id),
+                &serde_json::json!({
+                    "kind": self.kind,
+                    "target": self.target,
+                }),
+            );
+            tokio::io::copy_bidirectional(&mut TokioIo::new(vm), &mut upstream)
+                .await
+                .context("Tunnel copy failed")
+        }
+        .await;
+        let duration_ms = start.elapsed().as_millis() as i64;
+
+        let (bytes_up, bytes_down, error) = match result {
+            Ok((up, down)) => (up, down, None),
+            Err(e) => {
+                warn_throttled(
+                    "Tunnel failed",
+                    &self.vm_id,
+                    format!("{} to {}: {e:#}", self.kind, self.target),
+                );
+                (0, 0, Some(format!("{e:#}")))
+            }
+        };
+        self.ctx.events.emit_with_duration(
+            "network.tunnel.closed",
+            "network",
+            Some(&self.vm_id),
+            Some(&self.corr_id),
+            duration_ms,
+            Some(error.is_none()),
+            &serde_json::json!({
+                "kind": self.kind,
+                "target": self.target,
+                "bytes_up": bytes_up,
+                "bytes_down": bytes_down,
+                "duration_ms": duration_ms,
+                "error": error,
+            }),
+        );
+    }
+}
+
+/// Response to the VM with the upstream's status and headers, minus hop-by-hop ones.
+fn response_head(status: StatusCode, resp_headers: &HashMap<String, String>) -> Builder {
+    let mut response = Response::builder().status(status);
+    for (key, value) in resp_headers {
+        // Skip hop-by-hop headers
+        let lower = key.to_lowercase();
+        if lower == "transfer-encoding" || lower == "connection" {
+            continue;
+        }
+        response = response.header(key.as_str(), value.as_str());
+    }
+    response
+}
+
+/// A body read into memory, or, once it outgrew the inline limit, the part
+/// read so far and the rest still to come.
+enum ReadBody {
+    Buffered(Bytes),
+    Streaming { prefix: Bytes, rest: Incoming },
+}
+
+/// Read `body` into memory, stopping as soon as more than `limit` bytes have
+/// arrived. A body that fails partway is kept as read so far.
+async fn read_body(mut body: Incoming, limit: usize) -> ReadBody {
+    let mut buf = Vec::new();
+    while let Some(Ok(frame)) = body.frame().await {
+        if let Some(data) = frame.data_ref() {
+            buf.extend_from_slice(data);
+            if buf.len() > limit {
+                return ReadBody::Streaming {
+                    prefix: buf.into(),
+                    rest: body,
+                };
+            }
+        }
+    }
+    ReadBody::Buffered(buf.into())
+}
+
+/// What passed through [`spool_body`]
+struct Spooled {
+    size: usize,
+    /// Copy in the body store, unless it couldn't be written
+    path: Option<PathBuf>,
+    /// Whether the body ended normally, rather than failing or being dropped by the reader
+    complete: bool,
+}
+
+/// Stream a body too large to buffer: `prefix` (already read) and then the
+/// rest of `body` are forwarded frame by frame while being copied to the body
+/// store as `{corr_id}_{suffix}.bin`. `done` is called once the body ends.
+fn spool_body(
+    ctx: &ProxyCtx,
+    corr_id: &str,
+    suffix: &str,
+    prefix: Bytes,
+    mut body: Incoming,
+    done: impl FnOnce(Spooled) + Send + 'static,
+) -> ProxyBody {
+    let (tx, rx) = mpsc::channel(STREAM_BUFFER_FRAMES);
+    let body_store = ctx.body_store.clone();
+    let mut spool = body_store
+        .spool(corr_id, suffix)
+        .inspect_err(|e| warn_throttled("Failed to spool body", "body store", format!("{e:#}")))
+        .ok();
+    tokio::spawn(async move {
+        let mut size = prefix.len();
+        if let Some(spool) = &mut spool {
+            spool.write(&prefix).await;
+        }
+        let mut complete = tx.send(Ok(Frame::data(prefix))).await.is_ok();
+        while complete {
+            let Some(frame) = body.frame().await else {
+                break;
+            };
+            match frame {
+                Ok(frame) => {
+                    if let Some(data) = frame.data_ref() {
+                        size += data.len();
+                        if let Some(spool) = &mut spool {
+                            spool.write(data).await;
+                        }
+                    }
+                    complete = tx.send(Ok(frame)).await.is_ok();
+                }
+                Err(e) => {
+                    // Passed on so the reader sees the body fail rather than end
+                    let _ = tx.send(Err(e)).await;
+                    complete = false;
+                }
+            }
+        }
+        let path = match spool {
+            Some(spool) => body_store
+                .finish_spool(spool)
+                .await
+                .inspect_err(|e| {
+                    warn_throttled("Failed to spool body", "body store", format!("{e:#}"))
+                })
+                .ok(),
+            None => None,
+        };
+        done(Spooled {
+            size,
+            path,
+            complete,
+        });
+    });
+    StreamBody::new(ReceiverStream::new(rx)).boxed()
+}
+
+/// Forward an upstream body to the VM frame by frame, keeping a bounded copy
+/// that is logged once the body ends (or the VM stops reading it).
+fn stream_response(
+    ctx: Arc<ProxyCtx>,
+    exchange: Exchange,
+    status: StatusCode,
+    resp_headers: HashMap<String, String>,
+    mut upstream: Incoming,
+) -> ProxyBody {
+    let (tx, rx) = mpsc::channel(STREAM_BUFFER_FRAMES);
+    tokio::spawn(async move {
+        let mut capture = llm::StreamCapture::new(ctx.llm_max_sse_bytes);
+        while let Some(frame) = upstream.frame().await {
+            let frame = match frame {
+                Ok(frame) => frame,
+                Err(e) => {
+                    // Passed on so the VM sees the stream fail rather than end
+                    let _ = tx.send(Err(e)).await;
+                    break;
+                }
+            };
+            if let Some(data) = frame.data_ref() {
+                capture.push(data);
+            }
+            if tx.send(Ok(frame)).await.is_err() {
+                break;
+            }
+        }
+        let total = capture.total();
+        let (body, truncated) = capture.into_body();
+        log_response(
+            &ctx,
+            &exchange,
+            status,
+            &resp_headers,
+            &body,
+            total,
+            truncated,
+            None,
+        );
+    });
+    StreamBody::new(ReceiverStream::new(rx)).boxed()
+}
+
+/// Log the `llm.response` (for LLM requests) and `network.http.response`
+/// events for a response. `truncated` means `resp_body` only holds part of a
+/// larger streamed body of `resp_body_size` bytes, which `spooled` points to
+/// if it was copied to disk.
+fn log_response(
+    ctx: &ProxyCtx,
+    exchange: &Exchange,
+    status: StatusCode,
+    resp_headers: &HashMap<String, String>,
+    resp_body: &[u8],
+    resp_body_size: usize,
+    truncated: bool,
+    spooled: Option<&Path>,
+) {
+    let vm_id = exchange.vm_id.as_str();
+    let corr_id = exchange.corr_id.as_str();
+    let duration_ms = exchange.start.elapsed().as_millis() as i64;
+
+    // LLM response event goes before the generic network event
+    if let Some(det) = &exchange.llm_detection {
         let resp_content_type = resp_headers.get("content-type").map(String::as_str);
-        let (body_json, model, input_tokens, output_tokens) =
-            llm::process_response(&det.endpoint, resp_content_type, &resp_body);
+        // A cap of 0 makes process_response parse only the edges it was given
+        let max_sse_bytes = if truncated {
+            Some(0)
+        } else {
+            ctx.llm_max_sse_bytes
+        };
+        let mut llm_resp =
+            llm::process_response(&det.endpoint, resp_content_type, resp_body, max_sse_bytes);
+        if ctx.llm_estimate_tokens && llm::is_text_endpoint(&det.endpoint) && !truncated {
+            llm_resp.estimate_missing_tokens(&exchange.req_body, resp_body);
+        }
+        // Failed requests use no tokens; a success without readable usage
+        // is charged the reservation
+        let tokens = if status.is_success() {
+            (llm_resp.input_tokens.is_some() || llm_resp.output_tokens.is_some())
+                .then(|| llm_resp.input_tokens.unwrap_or(0) + llm_resp.output_tokens.unwrap_or(0))
+        } else {
+            Some(0)
+        };
+        let budget_remaining = exchange
+            .budget_reservation
+            .as_ref()
+            .and_then(|reservation| reservation.settle(tokens));
 
         ctx.events.emit_with_duration(
             "llm.response",
             "llm",
-            Some(&vm_id),
-            Some(&corr_id),
+            Some(vm_id),
+            Some(corr_id),
             duration_ms,
             Some(status.is_success()),
-            &serde_json::json!({
-                "provider": det.provider,
-                "endpoint": det.endpoint,
-                "model": model,
-                "input_tokens": input_tokens,
-                "output_tokens": output_tokens,
-                "status_code": status.as_u16(),
-                "body": body_json,
-            }),
+            &llm_response_data(det, &llm_resp, status, budget_remaining),
         );
     }
 
-    // 8. Log response event
-    let stored_resp = ctx.body_store.store(0, "resp", &resp_body).ok();
+    // A partial copy of a streamed body isn't worth storing
+    let stored_resp = if truncated {
+        None
+    } else {
+        ctx.body_store.store(0, "resp", resp_body).ok()
+    };
     let resp_body_path = match &stored_resp {
         Some(super::body_store::StoredBody::External(p)) => Some(p.to_string_lossy().to_string()),
-        _ => None,
+        _ => spooled.map(|p| p.to_string_lossy().to_string()),
     };
+    let resp_headers_json = ctx.redact_headers.to_json(resp_headers);
 
     ctx.events.emit_with_duration(
         "network.http.response",
         "network",
-        Some(&vm_id),
-        Some(&corr_id),
+        Some(vm_id),
+        Some(corr_id),
         duration_ms,
         Some(status.is_success()),
         &serde_json::json!({
             "status_code": status.as_u16(),
-            "resp_body_size": resp_body.len(),
+            "resp_body_size": resp_body_size,
             "resp_body_path": resp_body_path,
             "resp_headers": resp_headers_json,
+            "redirects": exchange.redirects,
             "duration_ms": duration_ms,
         }),
     );
+}
 
-    // 9. Return response to VM
-    let mut response = Response::builder().status(status);
-    for (key, value) in &resp_headers {
-        // Skip hop-by-hop headers
-        let lower = key.to_lowercase();
-        if lower == "transfer-encoding" || lower == "connection" {
+/// Data of the `llm.response` event for a processed upstream response.
+/// `budget_remaining` is the VM's token budget left after this response.
+fn llm_response_data(
+    det: &llm::LlmDetection,
+    llm_resp: &llm::LlmResponse,
+    status: StatusCode,
+    budget_remaining: Option<u64>,
+) -> serde_json::Value {
+    serde_json::json!({
+        "provider": det.provider,
+        "endpoint": det.endpoint,
+        "model": llm_resp.model,
+        "input_tokens": llm_resp.input_tokens,
+        "output_tokens": llm_resp.output_tokens,
+        "status_code": status.as_u16(),
+        "body": llm_resp.body,
+        "reassembly_skipped": llm_resp.reassembly_skipped,
+        "input_tokens_estimated": llm_resp.input_tokens_estimated,
+        "output_tokens_estimated": llm_resp.output_tokens_estimated,
+        "audio_seconds": llm_resp.audio_seconds,
+        "token_budget_remaining": budget_remaining,
+    })
+}
+
+/// Build the request forwarded upstream from the VM's request parts.
+///
+/// `original` is the VM's target URL when following a redirect: the VM's
+/// `Host` header is then dropped so the client sets one for the new target,
+/// and credentials are dropped if the redirect leaves the original host.
+fn build_upstream_request(
+    ctx: &ProxyCtx,
+    method: &hyper::Method,
+    uri: &hyper::Uri,
+    headers: &hyper::HeaderMap,
+    body: ProxyBody,
+    body_dropped: bool,
+    llm_detection: Option<&llm::LlmDetection>,
+    corr_id: &str,
+    original: Option<&hyper::Uri>,
+) -> Result<Request<ProxyBody>> {
+    let cross_origin = original.is_some_and(|o| o.authority() != uri.authority());
+    let mut upstream_req = Request::builder().method(method).uri(uri);
+
+    for (key, value) in headers {
+        let key_str = key.as_str().to_lowercase();
+        // Don't let the VM supply its own request id
+        if ctx.inject_request_id && key_str == REQUEST_ID_HEADER {
             continue;
         }
-        response = response.header(key.as_str(), value.as_str());
+        if original.is_some() && key_str == "host" {
+            continue;
+        }
+        // The VM's credentials are for the host it asked for, not wherever
+        // that host redirects to
+        if cross_origin && redact::is_credential_header(&key_str) {
+            continue;
+        }
+        // The VM's body isn't sent once a redirect has switched to GET
+        if body_dropped
+            && matches!(
+                key_str.as_str(),
+                "content-length" | "content-type" | "transfer-encoding"
+            )
+        {
+            continue;
+        }
+        // Strip VM-provided auth header if we're injecting a server-managed key
+        if let Some(det) = llm_detection {
+            if let Some(ref strip) = det.strip_header {
+                if key_str == strip.to_lowercase() {
+                    continue;
+                }
+            }
+        }
+        upstream_req = upstream_req.header(key, value);
+    }
+
+    // Inject server-managed API key
+    if let Some(det) = llm_detection {
+        if let Some((ref header_name, ref header_value)) = det.inject_header {
+            upstream_req = upstream_req.header(header_name.as_str(), header_value.as_str());
+        }
+    }
+
+    // Tag the request with its correlation id so upstream logs can be matched to ours
+    if ctx.inject_request_id {
+        upstream_req = upstream_req.header(REQUEST_ID_HEADER, corr_id);
+    }
+
+    upstream_req
+        .body(body)
+        .context("Failed to build upstream request")
+}
+
+/// Where a redirect response points, resolved against the URL that produced
+/// it. Returns `None` for other responses and for targets that aren't
+/// absolute http(s) URLs or absolute paths.
+fn redirect_target(
+    status: StatusCode,
+    headers: &hyper::HeaderMap,
+    current: &hyper::Uri,
+) -> Option<hyper::Uri> {
+    if !matches!(
+        status,
+        StatusCode::MOVED_PERMANENTLY
+            | StatusCode::FOUND
+            | StatusCode::SEE_OTHER
+            | StatusCode::TEMPORARY_REDIRECT
+            | StatusCode::PERMANENT_REDIRECT
+    ) {
+        return None;
+    }
+    let location = headers.get(hyper::header::LOCATION)?.to_str().ok()?;
+
+    let target: hyper::Uri = if let Some(rest) = location.strip_prefix("//") {
+        format!("{}://{rest}", current.scheme_str()?).parse().ok()?
+    } else if location.starts_with('/') {
+        format!(
+            "{}://{}{location}",
+            current.scheme_str()?,
+            current.authority()?
+        )
+        .parse()
+        .ok()?
+    } else {
+        location.parse().ok()?
+    };
+
+    if !matches!(target.scheme_str(), Some("http" | "https")) || target.authority().is_none() {
+        return None;
+    }
+    Some(target)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::{Event, EventFilters, PersistMode};
+    use crate::vm::VmEntry;
+    use clawpot_common::vm::VmManager;
+    use hyper::upgrade::Upgraded;
+    use std::time::SystemTime;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+    /// Upstream server that echoes the request path on a keep-alive connection.
+    /// Requests to `/slow` are answered after a delay, requests to
+    /// `/redirect` with a 302 to `/target`, and requests to `/v1/messages`
+    /// with the API key headers they carried.
+    async fn spawn_upstream() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let service = service_fn(|req: Request<Incoming>| async move {
+                        if req.uri().path() == "/slow" {
+                            tokio::time::sleep(Duration::from_millis(500)).await;
+                        }
+                        if req.uri().path() == "/v1/messages" {
+                            let keys: Vec<_> = req
+                                .headers()
+                                .get_all("x-api-key")
+                                .iter()
+                                .map(|v| v.to_str().unwrap().to_string())
+                                .collect();
+                            return Ok::<_, hyper::Error>(Response::new(Full::new(Bytes::from(
+                                keys.join(","),
+                            ))));
+                        }
+                        if req.uri().path() == "/inspect" {
+                            // The method and the headers that must not survive some redirects
+                            let mut seen = vec![req.method().to_string()];
+                            for name in ["content-length", "content-type", "x-api-key"] {
+                                let value =
+                                    req.headers().get(name).map_or("-", |v| v.to_str().unwrap());
+                                seen.push(value.to_string());
+                            }
+                            return Ok::<_, hyper::Error>(Response::new(Full::new(Bytes::from(
+                                seen.join(" "),
+                            ))));
+                        }
+                        if req.uri().path() == "/redirect" {
+                            // `?to=<url>` redirects there instead of to /target
+                            let location = req
+                                .uri()
+                                .query()
+                                .and_then(|q| q.strip_prefix("to="))
+                                .unwrap_or("/target")
+                                .to_string();
+                            return Ok::<_, hyper::Error>(
+                                Response::builder()
+                                    .status(StatusCode::FOUND)
+                                    .header("location", location)
+                                    .body(Full::new(Bytes::new()))
+                                    .unwrap(),
+                            );
+                        }
+                        Ok::<_, hyper::Error>(Response::new(Full::new(Bytes::from(
+                            req.uri().path().to_string(),
+                        ))))
+                    });
+                    let _ = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+        addr
+    }
+
+    /// Upstream server that answers with a server-sent event stream: one
+    /// frame right away and a second after a delay.
+    async fn spawn_sse_upstream() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let service = service_fn(|_req: Request<Incoming>| async move {
+                        let (tx, rx) = mpsc::channel(2);
+                        tokio::spawn(async move {
+                            let _ = tx.send(Ok(Frame::data(Bytes::from("data: one\n\n")))).await;
+                            tokio::time::sleep(Duration::from_millis(500)).await;
+                            let _ = tx.send(Ok(Frame::data(Bytes::from("data: two\n\n")))).await;
+                        });
+                        let body: StreamBody<ReceiverStream<Result<Frame<Bytes>, hyper::Error>>> =
+                            StreamBody::new(ReceiverStream::new(rx));
+                        Ok::<_, hyper::Error>(
+                            Response::builder()
+                                .header("content-type", "text/event-stream")
+                                .body(body)
+                                .unwrap(),
+                        )
+                    });
+                    let _ = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .await;
+                });
+            }
+        });
+        addr
+    }
+
+    /// Proxy context with a VM registered at the loopback address.
+    async fn proxy_ctx(
+        dir: &std::path::Path,
+        idle_timeout: Option<Duration>,
+        max_redirects: usize,
+    ) -> ProxyCtx {
+        let events = EventStore::new(
+            &dir.join("events.db"),
+            "test-session",
+            "test-server",
+            "0.1.0",
+            "{}",
+            PersistMode::All,
+        )
+        .unwrap();
+
+        // Register a VM at the loopback address so the proxy accepts our requests
+        let registry = Arc::new(VmRegistry::new());
+        let id = Uuid::new_v4();
+        registry
+            .insert(
+                id,
+                VmEntry {
+                    id,
+                    manager: VmManager::new(PathBuf::from("/tmp/test.sock")),
+                    ip_address: "127.0.0.1".parse().unwrap(),
+                    tap_name: "tap-test".to_string(),
+                    created_at: SystemTime::now(),
+                    vcpu_count: 1,
+                    mem_size_mib: 256,
+                    vsock_uds_path: "/tmp/test-vsock.sock".to_string(),
+                    guest_cid: 3,
+                    name: None,
+                    balloon: false,
+                    labels: std::collections::BTreeMap::new(),
+                    boot_config: None,
+                },
+            )
+            .await
+            .unwrap();
+
+        // The test upstream listens on loopback
+        let ssrf = Arc::new(SsrfGuard::allowing("127.0.0.1"));
+        ProxyCtx {
+            registry,
+            events,
+            body_store: Arc::new(BodyStore::new(&dir.join("bodies")).unwrap()),
+            auth: Arc::new(AuthClient::Disabled),
+            llm_keys: Arc::new(LlmKeyStore::from_env()),
+            splice_hosts: Arc::new(HostList::default()),
+            bypass_hosts: Arc::new(HostList::default()),
+            splice_non_http: false,
+            use_tls_upstream: false,
+            http_client: build_http_client(ssrf.clone()),
+            idle_timeout,
+            llm_max_sse_bytes: None,
+            llm_estimate_tokens: false,
+            inject_request_id: false,
+            methods: Arc::new(MethodPolicy::default()),
+            max_redirects,
+            ssrf,
+            token_budget: TokenBudget::default(),
+            max_inline_body: DEFAULT_MAX_INLINE_BODY,
+            upstream_timeout: Some(DEFAULT_UPSTREAM_TIMEOUT),
+            upstream_retries: DEFAULT_UPSTREAM_RETRIES,
+            redact_headers: Arc::new(HeaderRedaction::default()),
+            deny_unknown_vm: true,
+        }
     }
 
-    Ok(response.body(Full::new(resp_body)).unwrap())
+    /// Start a proxy listener on loopback, registered as a VM, and return its address.
+    async fn spawn_proxy(
+        dir: &std::path::Path,
+        idle_timeout: Option<Duration>,
+        max_redirects: usize,
+    ) -> SocketAddr {
+        serve(proxy_ctx(dir, idle_timeout, max_redirects).await).await
+    }
+
+    /// Start a proxy listener on loopback with `ctx` and return its address.
+    async fn serve(ctx: ProxyCtx) -> SocketAddr {
+        let ctx = Arc::new(ctx);
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let proxy_addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            // Keep the sender alive for as long as the listener runs
+            let (_cancel_tx, mut cancel_rx) = tokio::sync::watch::channel(false);
+            run_listener(listener, ctx, &mut cancel_rx, false).await
+        });
+        proxy_addr
+    }
+
+    /// Wait for the first event of `event_type` to be written by the proxy.
+    async fn wait_for_event(dir: &std::path::Path, event_type: &str) -> Event {
+        for _ in 0..50 {
+            let conn = EventStore::open_readonly(&dir.join("events.db")).unwrap();
+            let events = EventStore::query_events(
+                &conn,
+                &EventFilters {
+                    event_type: Some(event_type.to_string()),
+                    ..EventFilters::default()
+                },
+            )
+            .unwrap();
+            if let Some(event) = events.into_iter().next() {
+                return event;
+            }
+            tokio::time::sleep(Duration::from_millis(100)).await;
+        }
+        panic!("no {event_type} event was logged");
+    }
+
+    /// Upstream server that accepts WebSocket handshakes, then answers
+    /// everything written on the upgraded connection with `echo: ` and the data.
+    async fn spawn_websocket_upstream() -> SocketAddr {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                tokio::spawn(async move {
+                    let service = service_fn(|mut req: Request<Incoming>| async move {
+                        let on_upgrade = hyper::upgrade::on(&mut req);
+                        tokio::spawn(async move {
+                            let mut io = TokioIo::new(on_upgrade.await.unwrap());
+                            let mut buf = [0u8; 64];
+                            loop {
+                                let n = io.read(&mut buf).await.unwrap();
+                                if n == 0 {
+                                    break;
+                                }
+                                let reply = [&b"echo: "[..], &buf[..n]].concat();
+                                io.write_all(&reply).await.unwrap();
+                            }
+                        });
+                        Ok::<_, hyper::Error>(
+                            Response::builder()
+                                .status(StatusCode::SWITCHING_PROTOCOLS)
+                                .header("connection", "upgrade")
+                                .header("upgrade", "websocket")
+                                .body(Full::new(Bytes::new()))
+                                .unwrap(),
+                        )
+                    });
+                    let _ = http1::Builder::new()
+                        .serve_connection(TokioIo::new(stream), service)
+                        .with_upgrades()
+                        .await;
+                });
+            }
+        });
+        addr
+    }
+
+    /// Send `req` through the proxy on a new connection and return the
+    /// response status along with the connection it was upgraded to.
+    async fn upgrade(
+        proxy_addr: SocketAddr,
+        req: Request<Full<Bytes>>,
+    ) -> (StatusCode, TokioIo<Upgraded>) {
+        let stream = tokio::net::TcpStream::connect(proxy_addr).await.unwrap();
+        let (mut sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .unwrap();
+        tokio::spawn(conn.with_upgrades());
+        let resp = sender.send_request(req).await.unwrap();
+        let status = resp.status();
+        let upgraded = hyper::upgrade::on(resp).await.unwrap();
+        (status, TokioIo::new(upgraded))
+    }
+
+    async fn connect(
+        proxy_addr: SocketAddr,
+    ) -> hyper::client::conn::http1::SendRequest<Full<Bytes>> {
+        let stream = tokio::net::TcpStream::connect(proxy_addr).await.unwrap();
+        let (sender, conn) = hyper::client::conn::http1::handshake(TokioIo::new(stream))
+            .await
+            .unwrap();
+        tokio::spawn(conn);
+        sender
+    }
+
+    async fn get(
+        sender: &mut hyper::client::conn::http1::SendRequest<Full<Bytes>>,
+        upstream: SocketAddr,
+        path: &str,
+    ) -> Bytes {
+        sender.ready().await.unwrap();
+        let req = Request::builder()
+            .uri(path)
+            .header("host", upstream.to_string())
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        let resp = sender.send_request(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        resp.into_body().collect().await.unwrap().to_bytes()
+    }
+
+    #[tokio::test]
+    async fn test_keep_alive_serves_multiple_requests() {
+        let dir = tempfile::tempdir().unwrap();
+        let proxy_addr = spawn_proxy(dir.path(), None, 0).await;
+        let upstream = spawn_upstream().await;
+
+        // Both requests share a single client connection to the proxy
+        let mut sender = connect(proxy_addr).await;
+        for path in ["/first", "/second"] {
+            assert_eq!(get(&mut sender, upstream, path).await, path.as_bytes());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_disallowed_method_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let proxy_addr = spawn_proxy(dir.path(), None, 0).await;
+        let upstream = spawn_upstream().await;
+
+        let mut sender = connect(proxy_addr).await;
+        sender.ready().await.unwrap();
+        let req = Request::builder()
+            .method("TRACE")
+            .uri("/")
+            .header("host", upstream.to_string())
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        let resp = sender.send_request(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+        assert!(resp.headers()["allow"].to_str().unwrap().contains("GET"));
+
+        // Allowed methods still go through on the same connection
+        assert_eq!(get(&mut sender, upstream, "/ok").await, "/ok".as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_websocket_is_tunnelled() {
+        let dir = tempfile::tempdir().unwrap();
+        let proxy_addr = spawn_proxy(dir.path(), None, 0).await;
+        let upstream = spawn_websocket_upstream().await;
+
+        let req = Request::builder()
+            .uri("/socket")
+            .header("host", upstream.to_string())
+            .header("connection", "Upgrade")
+            .header("upgrade", "websocket")
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        let (status, mut io) = upgrade(proxy_addr, req).await;
+        assert_eq!(status, StatusCode::SWITCHING_PROTOCOLS);
+
+        // Bytes flow both ways over the upgraded connection
+        io.write_all(b"hello").await.unwrap();
+        let mut reply = [0u8; 11];
+        io.read_exact(&mut reply).await.unwrap();
+        assert_eq!(&reply, b"echo: hello");
+        drop(io);
+
+        let closed = wait_for_event(dir.path(), "network.tunnel.closed").await;
+        assert_eq!(closed.data["kind"], "websocket");
+        assert_eq!(closed.data["bytes_up"], 5);
+        assert_eq!(closed.data["bytes_down"], 11);
+    }
+
+    #[tokio::test]
+    async fn test_connect_is_tunnelled_when_allowed() {
+        let dir = tempfile::tempdir().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let target = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let (mut read, mut write) = stream.split();
+            let _ = tokio::io::copy(&mut read, &mut write).await;
+        });
+        let connect_req = || {
+            Request::builder()
+                .method("CONNECT")
+                .uri(target.to_string())
+                .body(Full::new(Bytes::new()))
+                .unwrap()
+        };
+
+        // CONNECT is not in the default method allowlist
+        let mut sender = connect(spawn_proxy(dir.path(), None, 0).await).await;
+        sender.ready().await.unwrap();
+        let resp = sender.send_request(connect_req()).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::METHOD_NOT_ALLOWED);
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut ctx = proxy_ctx(dir.path(), None, 0).await;
+        ctx.methods = Arc::new(MethodPolicy::parse("GET, CONNECT").unwrap());
+        let (status, mut io) = upgrade(serve(ctx).await, connect_req()).await;
+        assert_eq!(status, StatusCode::OK);
+
+        io.write_all(b"raw bytes").await.unwrap();
+        let mut echoed = [0u8; 9];
+        io.read_exact(&mut echoed).await.unwrap();
+        assert_eq!(&echoed, b"raw bytes");
+
+        let opened = wait_for_event(dir.path(), "network.tunnel.opened").await;
+        assert_eq!(opened.data["kind"], "connect");
+        assert_eq!(opened.data["target"], target.to_string());
+    }
+
+    #[test]
+    fn test_is_websocket_upgrade() {
+        let mut headers = hyper::HeaderMap::new();
+        headers.insert("upgrade", "WebSocket".parse().unwrap());
+        assert!(!is_websocket_upgrade(&headers));
+        headers.insert("connection", "keep-alive, Upgrade".parse().unwrap());
+        assert!(is_websocket_upgrade(&headers));
+        headers.insert("upgrade", "h2c".parse().unwrap());
+        assert!(!is_websocket_upgrade(&headers));
+    }
+
+    #[test]
+    fn test_retry_backoff() {
+        assert_eq!(retry_backoff(1), RETRY_BACKOFF);
+        assert_eq!(retry_backoff(3), RETRY_BACKOFF * 4);
+        // Capped, including where doubling would overflow
+        assert_eq!(retry_backoff(10), MAX_RETRY_BACKOFF);
+        assert_eq!(retry_backoff(40), MAX_RETRY_BACKOFF);
+        assert_eq!(retry_backoff(usize::MAX), MAX_RETRY_BACKOFF);
+    }
+
+    #[tokio::test]
+    async fn test_internal_upstream_is_blocked() {
+        let dir = tempfile::tempdir().unwrap();
+        let proxy_addr = spawn_proxy(dir.path(), None, 0).await;
+
+        let mut sender = connect(proxy_addr).await;
+        sender.ready().await.unwrap();
+        let req = Request::builder()
+            .uri("/latest/meta-data/")
+            .header("host", "169.254.169.254")
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        let resp = sender.send_request(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_spliced_connection_serves_one_request() {
+        let upstream = spawn_upstream().await;
+        let dir = tempfile::tempdir().unwrap();
+        let mut ctx = proxy_ctx(dir.path(), None, 0).await;
+        ctx.splice_hosts = Arc::new(HostList::parse("127.0.0.1"));
+
+        let mut sender = connect(serve(ctx).await).await;
+        assert_eq!(
+            get(&mut sender, upstream, "/first").await,
+            "/first".as_bytes()
+        );
+        let spliced = wait_for_event(dir.path(), "network.http.spliced").await;
+        assert_eq!(spliced.data["url"], format!("http://{upstream}/first"));
+
+        // Later requests can't ride along unauthorized on the spliced connection
+        assert!(sender.ready().await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_bypass_host_must_pass_ssrf_check() {
+        let upstream = spawn_upstream().await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut ctx = proxy_ctx(dir.path(), None, 0).await;
+        ctx.bypass_hosts = Arc::new(HostList::parse("127.0.0.1"));
+        let mut sender = connect(serve(ctx).await).await;
+        assert_eq!(get(&mut sender, upstream, "/raw").await, "/raw".as_bytes());
+        let bypassed = wait_for_event(dir.path(), "network.http.bypassed").await;
+        assert_eq!(bypassed.data["upstream"], upstream.to_string());
+
+        // A bypassed Host that resolves internally isn't spliced blindly
+        let dir = tempfile::tempdir().unwrap();
+        let mut ctx = proxy_ctx(dir.path(), None, 0).await;
+        ctx.bypass_hosts = Arc::new(HostList::parse("127.0.0.1"));
+        ctx.ssrf = Arc::new(SsrfGuard::default());
+        let mut sender = connect(serve(ctx).await).await;
+        sender.ready().await.unwrap();
+        let req = Request::builder()
+            .uri("/raw")
+            .header("host", upstream.to_string())
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        let resp = sender.send_request(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::FORBIDDEN);
+    }
+
+    #[tokio::test]
+    async fn test_redirects_are_followed_only_when_enabled() {
+        let upstream = spawn_upstream().await;
+
+        let dir = tempfile::tempdir().unwrap();
+        let proxy_addr = spawn_proxy(dir.path(), None, 0).await;
+        let mut sender = connect(proxy_addr).await;
+        sender.ready().await.unwrap();
+        let req = Request::builder()
+            .uri("/redirect")
+            .header("host", upstream.to_string())
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        let resp = sender.send_request(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::FOUND);
+
+        let dir = tempfile::tempdir().unwrap();
+        let proxy_addr = spawn_proxy(dir.path(), None, 3).await;
+        let mut sender = connect(proxy_addr).await;
+        assert_eq!(
+            get(&mut sender, upstream, "/redirect").await,
+            "/target".as_bytes()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_post_redirect_drops_body_headers() {
+        let upstream = spawn_upstream().await;
+        let dir = tempfile::tempdir().unwrap();
+        let mut sender = connect(spawn_proxy(dir.path(), None, 3).await).await;
+        sender.ready().await.unwrap();
+        let req = Request::post("/redirect?to=/inspect")
+            .header("host", upstream.to_string())
+            .header("content-type", "text/plain")
+            .header("x-api-key", "sk-from-vm")
+            .body(Full::new(Bytes::from("hello")))
+            .unwrap();
+        let resp = tokio::time::timeout(Duration::from_secs(5), sender.send_request(req))
+            .await
+            .expect("redirect target waited for a body")
+            .unwrap();
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        // A same-origin hop keeps the credentials
+        assert_eq!(body, "GET - - sk-from-vm".as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_cross_origin_redirect_drops_credentials() {
+        let (upstream, other) = (spawn_upstream().await, spawn_upstream().await);
+        let dir = tempfile::tempdir().unwrap();
+        let mut sender = connect(spawn_proxy(dir.path(), None, 3).await).await;
+        sender.ready().await.unwrap();
+        let req = Request::builder()
+            .uri(format!("/redirect?to=http://{other}/inspect"))
+            .header("host", upstream.to_string())
+            .header("x-api-key", "sk-from-vm")
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        let resp = sender.send_request(req).await.unwrap();
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "GET - - -".as_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_event_stream_is_forwarded_incrementally() {
+        let dir = tempfile::tempdir().unwrap();
+        let proxy_addr = spawn_proxy(dir.path(), None, 0).await;
+        let upstream = spawn_sse_upstream().await;
+
+        let mut sender = connect(proxy_addr).await;
+        sender.ready().await.unwrap();
+        let req = Request::builder()
+            .uri("/stream")
+            .header("host", upstream.to_string())
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        let started = Instant::now();
+        let mut body = sender.send_request(req).await.unwrap().into_body();
+
+        // The first frame arrives while the upstream still holds back the second
+        let first = body.frame().await.unwrap().unwrap().into_data().unwrap();
+        assert_eq!(first, "data: one\n\n");
+        assert!(started.elapsed() < Duration::from_millis(400));
+
+        let rest = body.collect().await.unwrap().to_bytes();
+        assert_eq!(rest, "data: two\n\n");
+    }
+
+    #[tokio::test]
+    async fn test_large_download_is_streamed() {
+        const CHUNK: usize = 1024 * 1024;
+        let dir = tempfile::tempdir().unwrap();
+        let mut ctx = proxy_ctx(dir.path(), None, 0).await;
+        ctx.max_inline_body = 64 * 1024;
+        let proxy_addr = serve(ctx).await;
+
+        // The upstream holds back the last of ten chunks until told to send it,
+        // so the client can only see the first nine if the proxy streams them
+        let release = Arc::new(tokio::sync::Notify::new());
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream = listener.local_addr().unwrap();
+        let upstream_release = release.clone();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let service = service_fn(move |_req: Request<Incoming>| {
+                let release = upstream_release.clone();
+                async move {
+                    let (tx, rx) = mpsc::channel(2);
+                    tokio::spawn(async move {
+                        for _ in 0..9 {
+                            let _ = tx
+                                .send(Ok(Frame::data(Bytes::from(vec![b'x'; CHUNK]))))
+                                .await;
+                        }
+                        release.notified().await;
+                        let _ = tx
+                            .send(Ok(Frame::data(Bytes::from(vec![b'x'; CHUNK]))))
+                            .await;
+                    });
+                    let body: StreamBody<ReceiverStream<Result<Frame<Bytes>, hyper::Error>>> =
+                        StreamBody::new(ReceiverStream::new(rx));
+                    Ok::<_, hyper::Error>(Response::new(body))
+                }
+            });
+            let _ = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await;
+        });
+
+        let mut sender = connect(proxy_addr).await;
+        sender.ready().await.unwrap();
+        let req = Request::builder()
+            .uri("/large")
+            .header("host", upstream.to_string())
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        let resp = sender.send_request(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let mut body = resp.into_body();
+
+        // More than the inline limit arrives before the upstream finishes
+        let mut received = 0;
+        while received <= CHUNK {
+            let frame = tokio::time::timeout(Duration::from_secs(5), body.frame())
+                .await
+                .expect("body was buffered instead of streamed")
+                .unwrap()
+                .unwrap();
+            received += frame.data_ref().map_or(0, Bytes::len);
+        }
+        release.notify_one();
+        received += body.collect().await.unwrap().to_bytes().len();
+        assert_eq!(received, 10 * CHUNK);
+
+        // The response is logged, with its full size, once it has ended
+        let logged = wait_for_event(dir.path(), "network.http.response").await;
+        assert_eq!(logged.data["resp_body_size"], 10 * CHUNK);
+        assert!(logged.data["resp_body_path"].is_string());
+    }
+
+    #[tokio::test]
+    async fn test_large_upload_is_streamed() {
+        const SIZE: usize = 4 * 1024 * 1024;
+        let dir = tempfile::tempdir().unwrap();
+        let mut ctx = proxy_ctx(dir.path(), None, 0).await;
+        ctx.max_inline_body = 64 * 1024;
+        let proxy_addr = serve(ctx).await;
+
+        // The upstream answers with the size of the body it received
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream = listener.local_addr().unwrap();
+        tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let service = service_fn(|req: Request<Incoming>| async move {
+                let body = req.into_body().collect().await?.to_bytes();
+                Ok::<_, hyper::Error>(Response::new(Full::new(Bytes::from(
+                    body.len().to_string(),
+                ))))
+            });
+            let _ = http1::Builder::new()
+                .serve_connection(TokioIo::new(stream), service)
+                .await;
+        });
+
+        let mut sender = connect(proxy_addr).await;
+        sender.ready().await.unwrap();
+        let req = Request::post("/upload")
+            .header("host", upstream.to_string())
+            .body(Full::new(Bytes::from(vec![b'x'; SIZE])))
+            .unwrap();
+        let resp = sender.send_request(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::OK);
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, SIZE.to_string().as_bytes());
+
+        // The whole body is copied to the body store and logged once it has ended
+        let logged = wait_for_event(dir.path(), "network.http.request_body").await;
+        assert_eq!(logged.data["req_body_size"], SIZE);
+        assert_eq!(logged.data["complete"], true);
+        let path = logged.data["req_body_path"].as_str().unwrap();
+        assert_eq!(std::fs::read(path).unwrap().len(), SIZE);
+    }
+
+    #[tokio::test]
+    async fn test_recorded_headers_are_redacted() {
+        let dir = tempfile::tempdir().unwrap();
+        let proxy_addr = spawn_proxy(dir.path(), None, 0).await;
+        let upstream = spawn_upstream().await;
+
+        let mut sender = connect(proxy_addr).await;
+        sender.ready().await.unwrap();
+        let req = Request::post("/v1/messages")
+            .header("host", upstream.to_string())
+            .header("x-api-key", "sk-from-vm")
+            .header("user-agent", "clawpot-test")
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        let resp = sender.send_request(req).await.unwrap();
+        // The upstream still gets the real key
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "sk-from-vm".as_bytes());
+
+        let logged = wait_for_event(dir.path(), "network.http.request").await;
+        let headers = logged.data["headers"].as_str().unwrap();
+        assert!(!headers.contains("sk-from-vm"));
+        let headers: HashMap<String, String> = serde_json::from_str(headers).unwrap();
+        assert_eq!(headers["x-api-key"], crate::proxy::redact::REDACTED);
+        assert_eq!(headers["user-agent"], "clawpot-test");
+    }
+
+    #[tokio::test]
+    async fn test_upstream_timeout() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut ctx = proxy_ctx(dir.path(), None, 0).await;
+        ctx.upstream_timeout = Some(Duration::from_millis(100));
+        let proxy_addr = serve(ctx).await;
+        let upstream = spawn_upstream().await;
+
+        let mut sender = connect(proxy_addr).await;
+        sender.ready().await.unwrap();
+        let req = Request::builder()
+            .uri("/slow")
+            .header("host", upstream.to_string())
+            .body(Full::new(Bytes::new()))
+            .unwrap();
+        let resp = sender.send_request(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::GATEWAY_TIMEOUT);
+
+        let logged = wait_for_event(dir.path(), "network.http.timeout").await;
+        assert_eq!(logged.data["status_code"], 504);
+        assert_eq!(logged.data["timeout_ms"], 100);
+        assert_eq!(logged.success, Some(false));
+    }
+
+    #[tokio::test]
+    async fn test_failed_get_is_retried() {
+        let dir = tempfile::tempdir().unwrap();
+        let proxy_addr = spawn_proxy(dir.path(), None, 0).await;
+
+        // The upstream is unavailable for the first request only
+        let requests = Arc::new(AtomicUsize::new(0));
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let upstream = listener.local_addr().unwrap();
+        let counter = requests.clone();
+        tokio::spawn(async move {
+            loop {
+                let (stream, _) = listener.accept().await.unwrap();
+                let counter = counter.clone();
+                let service = service_fn(move |_req: Request<Incoming>| {
+                    let status = if counter.fetch_add(1, Ordering::SeqCst) == 0 {
+                        StatusCode::SERVICE_UNAVAILABLE
+                    } else {
+                        StatusCode::OK
+                    };
+                    async move {
+                        Ok::<_, hyper::Error>(
+                            Response::builder()
+                                .status(status)
+                                .body(Full::new(Bytes::from(status.as_str().to_string())))
+                                .unwrap(),
+                        )
+                    }
+                });
+                tokio::spawn(http1::Builder::new().serve_connection(TokioIo::new(stream), service));
+            }
+        });
+
+        let mut sender = connect(proxy_addr).await;
+        assert_eq!(get(&mut sender, upstream, "/flaky").await, "200");
+        assert_eq!(requests.load(Ordering::SeqCst), 2);
+
+        let retry = wait_for_event(dir.path(), "network.http.retry").await;
+        assert_eq!(retry.data["attempt"], 1);
+        assert_eq!(retry.data["reason"], "status 503");
+
+        // Requests with side effects are never repeated
+        sender.ready().await.unwrap();
+        requests.store(0, Ordering::SeqCst);
+        let req = Request::post("/flaky")
+            .header("host", upstream.to_string())
+            .body(Full::new(Bytes::from("payload")))
+            .unwrap();
+        let resp = sender.send_request(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::SERVICE_UNAVAILABLE);
+        assert_eq!(requests.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_llm_request_over_budget_is_rejected() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut ctx = proxy_ctx(dir.path(), None, 0).await;
+        // Allowed so the SSRF check doesn't need DNS; nothing is forwarded
+        ctx.ssrf = Arc::new(SsrfGuard::allowing("api.anthropic.com"));
+        ctx.token_budget = TokenBudget::new(Some(100));
+        let vm_id = crate::proxy::resolve_vm_id(
+            &ctx.registry,
+            &ctx.events,
+            "127.0.0.1".parse().unwrap(),
+            "HTTP",
+            true,
+        )
+        .await
+        .unwrap();
+        let spent = ctx.token_budget.reserve(&vm_id).unwrap();
+        assert_eq!(spent.settle(Some(150)), Some(0));
+        let proxy_addr = serve(ctx).await;
+
+        let mut sender = connect(proxy_addr).await;
+        sender.ready().await.unwrap();
+        let req = Request::builder()
+            .method("POST")
+            .uri("/v1/messages")
+            .header("host", "api.anthropic.com")
+            .body(Full::new(Bytes::from("{}")))
+            .unwrap();
+        let resp = sender.send_request(req).await.unwrap();
+        assert_eq!(resp.status(), StatusCode::TOO_MANY_REQUESTS);
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        let body: serde_json::Value = serde_json::from_slice(&body).unwrap();
+        assert_eq!(body["error"]["type"], "token_budget_exceeded");
+    }
+
+    #[tokio::test]
+    async fn test_llm_key_is_injected() {
+        let upstream = spawn_upstream().await;
+        let dir = tempfile::tempdir().unwrap();
+        let mut ctx = proxy_ctx(dir.path(), None, 0).await;
+        ctx.llm_keys = Arc::new(LlmKeyStore::with_keys(&[("anthropic", "sk-server")]));
+
+        // Detection goes by the VM's Host header; the stub stands in for the provider
+        let detection = llm::detect_llm_request(
+            "api.anthropic.com",
+            "/v1/messages",
+            &HashMap::new(),
+            &ctx.llm_keys,
+        );
+        assert!(detection.is_some());
+
+        let mut headers = hyper::HeaderMap::new();
+        headers.insert("x-api-key", "sk-from-vm".parse().unwrap());
+        let uri: hyper::Uri = format!("http://{upstream}/v1/messages").parse().unwrap();
+        let req = build_upstream_request(
+            &ctx,
+            &hyper::Method::POST,
+            &uri,
+            &headers,
+            full(Bytes::new()),
+            false,
+            detection.as_ref(),
+            "corr-1",
+            None,
+        )
+        .unwrap();
+        let resp = ctx.http_client.request(req).await.unwrap();
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "sk-server".as_bytes());
+
+        // Without a server-managed key the VM's own key passes through
+        ctx.llm_keys = Arc::new(LlmKeyStore::with_keys(&[]));
+        let detection = llm::detect_llm_request(
+            "api.anthropic.com",
+            "/v1/messages",
+            &HashMap::new(),
+            &ctx.llm_keys,
+        );
+        let req = build_upstream_request(
+            &ctx,
+            &hyper::Method::POST,
+            &uri,
+            &headers,
+            full(Bytes::new()),
+            false,
+            detection.as_ref(),
+            "corr-2",
+            None,
+        )
+        .unwrap();
+        let resp = ctx.http_client.request(req).await.unwrap();
+        let body = resp.into_body().collect().await.unwrap().to_bytes();
+        assert_eq!(body, "sk-from-vm".as_bytes());
+    }
+
+    #[test]
+    fn test_llm_response_event_from_sse() {
+        let det = llm::detect_llm_request(
+            "api.anthropic.com",
+            "/v1/messages",
+            &HashMap::new(),
+            &LlmKeyStore::with_keys(&[]),
+        )
+        .unwrap();
+        // Captured from a streaming Messages API response
+        let body = concat!(
+            "event: message_start\n",
+            "data: {\"type\":\"message_start\",\"message\":{\"model\":\"claude-sonnet-4-20250514\",",
+            "\"usage\":{\"input_tokens\":150}}}\n\n",
+            "event: content_block_delta\n",
+            "data: {\"type\":\"content_block_delta\",\"delta\":{\"type\":\"text_delta\",\"text\":\"Hi\"}}\n\n",
+            "event: message_delta\n",
+            "data: {\"type\":\"message_delta\",\"delta\":{\"stop_reason\":\"end_turn\"},",
+            "\"usage\":{\"output_tokens\":85}}\n\n",
+        );
+        let resp = llm::process_response(
+            &det.endpoint,
+            Some("text/event-stream"),
+            body.as_bytes(),
+            None,
+        );
+
+        let data = llm_response_data(&det, &resp, StatusCode::OK, None);
+        assert_eq!(data["provider"], "anthropic");
+        assert_eq!(data["model"], "claude-sonnet-4-20250514");
+        assert_eq!(data["input_tokens"], 150);
+        assert_eq!(data["output_tokens"], 85);
+        assert_eq!(data["status_code"], 200);
+    }
+
+    #[test]
+    fn test_redirect_target() {
+        let current: hyper::Uri = "http://example.com/a/b?q=1".parse().unwrap();
+        let target = |status, location: &str| {
+            let mut headers = hyper::HeaderMap::new();
+            headers.insert(hyper::header::LOCATION, location.parse().unwrap());
+            redirect_target(status, &headers, &current).map(|u| u.to_string())
+        };
+
+        assert_eq!(
+            target(StatusCode::FOUND, "/login").as_deref(),
+            Some("http://example.com/login")
+        );
+        assert_eq!(
+            target(StatusCode::PERMANENT_REDIRECT, "https://other.example/x").as_deref(),
+            Some("https://other.example/x")
+        );
+        assert_eq!(
+            target(StatusCode::MOVED_PERMANENTLY, "//cdn.example/y").as_deref(),
+            Some("http://cdn.example/y")
+        );
+        assert_eq!(target(StatusCode::FOUND, "ftp://example.com/f"), None);
+        assert_eq!(target(StatusCode::OK, "/login"), None);
+        assert_eq!(target(StatusCode::NOT_MODIFIED, "/login"), None);
+    }
+
+    #[tokio::test]
+    async fn test_idle_connection_is_closed() {
+        let dir = tempfile::tempdir().unwrap();
+        let proxy_addr = spawn_proxy(dir.path(), Some(Duration::from_millis(200)), 0).await;
+        let upstream = spawn_upstream().await;
+
+        // A request slower than the idle timeout is in flight, so it survives
+        let mut sender = connect(proxy_addr).await;
+        assert_eq!(
+            get(&mut sender, upstream, "/slow").await,
+            "/slow".as_bytes()
+        );
+
+        // Once nothing is in flight, the proxy closes the connection
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while !sender.is_closed() {
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        })
+        .await
+        .expect("idle connection was not closed");
+    }
 }
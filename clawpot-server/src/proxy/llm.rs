@@ -1,11 +1,52 @@
-use std::collections::HashMap;
+use clawpot_common::llm_keys;
+use std::collections::{BTreeMap, HashMap, VecDeque};
 use std::env;
-use tracing::info;
+use std::path::Path;
+use tracing::{info, warn};
+
+/// Default size above which streaming responses are not reassembled in full
+pub const DEFAULT_MAX_SSE_BYTES: usize = 16 * 1024 * 1024;
+
+/// How much of the start and end of an oversized stream is still parsed for usage
+const SSE_EDGE_BYTES: usize = 64 * 1024;
+
+/// Most of a streamed response body kept for processing, whatever the
+/// configured reassembly cap, so a runaway upstream can't exhaust memory
+pub const MAX_STREAM_CAPTURE_BYTES: usize = 256 * 1024 * 1024;
+
+/// Request bodies larger than this are summarized from their first bytes only
+const SUMMARY_MAX_BYTES: usize = 64 * 1024;
+
+/// Rough characters-per-token ratio used when a provider doesn't report usage
+const CHARS_PER_TOKEN: u64 = 4;
+
+/// How a provider's API host is recognized.
+enum HostMatch {
+    /// A single fixed host, e.g. `api.openai.com`
+    Exact(&'static str),
+    /// Any host ending in this suffix, e.g. `.openai.azure.com` for
+    /// per-resource hosts like `myresource.openai.azure.com`
+    Suffix(&'static str),
+}
+
+impl HostMatch {
+    fn matches(&self, host: &str) -> bool {
+        match self {
+            Self::Exact(expected) => host.eq_ignore_ascii_case(expected),
+            Self::Suffix(suffix) => {
+                host.len() > suffix.len()
+                    && host
+                        .get(host.len() - suffix.len()..)
+                        .is_some_and(|end| end.eq_ignore_ascii_case(suffix))
+            }
+        }
+    }
+}
 
 /// An LLM API provider (e.g. Anthropic, OpenAI).
 struct LlmProvider {
     name: &'static str,
-    host: &'static str,
+    host: HostMatch,
     env_var: &'static str,
     auth_header: &'static str,
     bearer_format: bool,
@@ -14,25 +55,50 @@ struct LlmProvider {
 
 /// A specific API endpoint within a provider.
 struct LlmEndpoint {
+    /// Endpoint kind, which selects how requests and responses are parsed
     name: &'static str,
+    /// Path prefix; a `*` segment matches any one path segment, e.g. an
+    /// Azure deployment name
     path_prefix: &'static str,
 }
 
+impl LlmEndpoint {
+    fn matches(&self, path: &str) -> bool {
+        if !self.path_prefix.contains('*') {
+            return path.starts_with(self.path_prefix);
+        }
+        let path = path.split('?').next().unwrap_or(path);
+        let mut segments = path.split('/');
+        self.path_prefix.split('/').all(|expected| {
+            segments.next().is_some_and(|segment| match expected {
+                "*" => !segment.is_empty(),
+                _ => segment == expected,
+            })
+        })
+    }
+}
+
 static PROVIDERS: &[LlmProvider] = &[
     LlmProvider {
         name: "anthropic",
-        host: "api.anthropic.com",
+        host: HostMatch::Exact("api.anthropic.com"),
         env_var: "CLAWPOT_ANTHROPIC_API_KEY",
         auth_header: "x-api-key",
         bearer_format: false,
-        endpoints: &[LlmEndpoint {
-            name: "messages",
-            path_prefix: "/v1/messages",
-        }],
+        endpoints: &[
+            LlmEndpoint {
+                name: "messages",
+                path_prefix: "/v1/messages",
+            },
+            LlmEndpoint {
+                name: "complete",
+                path_prefix: "/v1/complete",
+            },
+        ],
     },
     LlmProvider {
         name: "openai",
-        host: "api.openai.com",
+        host: HostMatch::Exact("api.openai.com"),
         env_var: "CLAWPOT_OPENAI_API_KEY",
         auth_header: "authorization",
         bearer_format: true,
@@ -45,11 +111,46 @@ static PROVIDERS: &[LlmProvider] = &[
                 name: "responses",
                 path_prefix: "/v1/responses",
             },
+            LlmEndpoint {
+                name: "embeddings",
+                path_prefix: "/v1/embeddings",
+            },
+            LlmEndpoint {
+                name: "audio_transcriptions",
+                path_prefix: "/v1/audio/transcriptions",
+            },
+            LlmEndpoint {
+                name: "audio_translations",
+                path_prefix: "/v1/audio/translations",
+            },
+            LlmEndpoint {
+                name: "audio_speech",
+                path_prefix: "/v1/audio/speech",
+            },
+        ],
+    },
+    // Azure OpenAI speaks the OpenAI API under per-deployment paths, e.g.
+    // /openai/deployments/<name>/chat/completions?api-version=...
+    LlmProvider {
+        name: "azure_openai",
+        host: HostMatch::Suffix(".openai.azure.com"),
+        env_var: "CLAWPOT_AZURE_OPENAI_API_KEY",
+        auth_header: "api-key",
+        bearer_format: false,
+        endpoints: &[
+            LlmEndpoint {
+                name: "chat_completions",
+                path_prefix: "/openai/deployments/*/chat/completions",
+            },
+            LlmEndpoint {
+                name: "embeddings",
+                path_prefix: "/openai/deployments/*/embeddings",
+            },
         ],
     },
 ];
 
-/// Holds server-managed API keys loaded from environment variables.
+/// Holds server-managed API keys loaded from the key file and environment variables.
 pub struct LlmKeyStore {
     keys: HashMap<String, String>,
 }
@@ -57,7 +158,42 @@ pub struct LlmKeyStore {
 impl LlmKeyStore {
     /// Load API keys from environment variables defined in the provider registry.
     pub fn from_env() -> Self {
+        Self::with_env(HashMap::new())
+    }
+
+    /// Load API keys from the key file managed by `clawpot llm keys`, then
+    /// from environment variables, which take precedence.
+    pub fn load(keys_file: &Path) -> Self {
+        let file_keys = match llm_keys::load(keys_file) {
+            Ok(keys) => keys,
+            Err(e) => {
+                warn!("Ignoring LLM key file: {:#}", e);
+                BTreeMap::new()
+            }
+        };
+
         let mut keys = HashMap::new();
+        for (provider, key) in file_keys {
+            let key = key.trim().to_string();
+            if !PROVIDERS.iter().any(|p| p.name == provider) {
+                warn!(
+                    "Ignoring key for unknown LLM provider {} in key file",
+                    provider
+                );
+            } else if !key.is_empty() {
+                info!(
+                    "Loaded API key for {} from {} ({} chars)",
+                    provider,
+                    keys_file.display(),
+                    key.len()
+                );
+                keys.insert(provider, key);
+            }
+        }
+        Self::with_env(keys)
+    }
+
+    fn with_env(mut keys: HashMap<String, String>) -> Self {
         for provider in PROVIDERS {
             if let Ok(raw_key) = env::var(provider.env_var) {
                 let key = raw_key.trim().to_string();
@@ -78,6 +214,16 @@ impl LlmKeyStore {
         Self { keys }
     }
 
+    #[cfg(test)]
+    pub fn with_keys(keys: &[(&str, &str)]) -> Self {
+        Self {
+            keys: keys
+                .iter()
+                .map(|(provider, key)| ((*provider).to_string(), (*key).to_string()))
+                .collect(),
+        }
+    }
+
     fn get(&self, provider_name: &str) -> Option<&str> {
         self.keys.get(provider_name).map(String::as_str)
     }
@@ -104,7 +250,7 @@ pub fn detect_llm_request(
     let host_bare = host.split(':').next().unwrap_or(host);
 
     for provider in PROVIDERS {
-        if !host_bare.eq_ignore_ascii_case(provider.host) {
+        if !provider.host.matches(host_bare) {
             continue;
         }
 
@@ -112,7 +258,7 @@ pub fn detect_llm_request(
         let endpoint_name = provider
             .endpoints
             .iter()
-            .find(|ep| path.starts_with(ep.path_prefix))
+            .find(|ep| ep.matches(path))
             .map_or("unknown", |ep| ep.name);
 
         // Build key injection
@@ -143,50 +289,174 @@ pub fn detect_llm_request(
 }
 
 /// A parsed SSE event.
+#[derive(Debug, PartialEq, Eq)]
 struct SseEvent {
     event_type: Option<String>,
     data: String,
 }
 
-/// Parse raw SSE bytes into a sequence of events.
-fn parse_sse(body: &[u8]) -> Vec<SseEvent> {
-    let text = String::from_utf8_lossy(body);
-    let mut events = Vec::new();
+/// Incremental SSE parser.
+///
+/// Feed it response bytes as they arrive and it yields each event once the
+/// frame's blank-line delimiter has been seen. Partial frames are buffered as
+/// raw bytes, so a multi-byte character split across chunks still decodes.
+#[derive(Default)]
+struct SseParser {
+    buf: Vec<u8>,
+    /// Length of the prefix of `buf` already searched for a delimiter
+    scanned: usize,
+}
 
-    // Split by double newline (frame delimiter)
-    for frame in text.split("\n\n") {
-        let frame = frame.trim();
-        if frame.is_empty() {
-            continue;
+impl SseParser {
+    /// Add a chunk of the stream, returning any events it completes.
+    fn push(&mut self, chunk: &[u8]) -> Vec<SseEvent> {
+        self.buf.extend_from_slice(chunk);
+
+        let mut events = Vec::new();
+        let mut frame_start = 0;
+        // Back up one byte in case the previous chunk ended with the first '\n'
+        let mut search_from = self.scanned.saturating_sub(1);
+        while let Some(pos) = self.buf[search_from..]
+            .windows(2)
+            .position(|w| w == b"\n\n")
+        {
+            let frame_end = search_from + pos;
+            events.extend(parse_sse_frame(&self.buf[frame_start..frame_end]));
+            frame_start = frame_end + 2;
+            search_from = frame_start;
         }
 
-        let mut event_type = None;
-        let mut data_parts: Vec<&str> = Vec::new();
+        self.buf.drain(..frame_start);
+        self.scanned = self.buf.len();
+        events
+    }
 
-        for line in frame.lines() {
-            if line.starts_with(':') {
-                // Comment / keepalive — skip
-            } else if let Some(rest) = line.strip_prefix("event:") {
-                event_type = Some(rest.trim().to_string());
-            } else if let Some(rest) = line.strip_prefix("data:") {
-                let data = rest.trim();
-                if data != "[DONE]" {
-                    data_parts.push(data);
-                }
+    /// End of stream: parse whatever is left as a final, undelimited frame.
+    fn finish(self) -> Option<SseEvent> {
+        parse_sse_frame(&self.buf)
+    }
+}
+
+/// Parse a single SSE frame (the text between blank-line delimiters).
+/// Returns `None` for frames without data, such as keepalive comments.
+fn parse_sse_frame(frame: &[u8]) -> Option<SseEvent> {
+    let text = String::from_utf8_lossy(frame);
+    let mut event_type = None;
+    let mut data_parts: Vec<&str> = Vec::new();
+
+    for line in text.trim().lines() {
+        if line.starts_with(':') {
+            // Comment / keepalive — skip
+        } else if let Some(rest) = line.strip_prefix("event:") {
+            event_type = Some(rest.trim().to_string());
+        } else if let Some(rest) = line.strip_prefix("data:") {
+            let data = rest.trim();
+            if data != "[DONE]" {
+                data_parts.push(data);
             }
         }
+    }
 
-        if data_parts.is_empty() {
-            continue;
+    if data_parts.is_empty() {
+        return None;
+    }
+
+    Some(SseEvent {
+        event_type,
+        data: data_parts.join("\n"),
+    })
+}
+
+/// Parse a complete SSE body into a sequence of events.
+fn parse_sse(body: &[u8]) -> Vec<SseEvent> {
+    let mut parser = SseParser::default();
+    let mut events = parser.push(body);
+    events.extend(parser.finish());
+    events
+}
+
+/// Parse only the frames near the start and end of an oversized SSE body.
+///
+/// Usage stats live at the edges: Anthropic reports input tokens in
+/// `message_start`, and every provider reports output tokens in a final event.
+fn parse_sse_edges(body: &[u8]) -> Vec<SseEvent> {
+    let head = &body[..body.len().min(SSE_EDGE_BYTES)];
+    let head_end = head
+        .windows(2)
+        .rposition(|w| w == b"\n\n")
+        .map_or(0, |i| i + 2);
+
+    let tail_from = body.len().saturating_sub(SSE_EDGE_BYTES).max(head_end);
+    let tail_start = if tail_from == head_end {
+        head_end
+    } else {
+        body[tail_from..]
+            .windows(2)
+            .position(|w| w == b"\n\n")
+            .map_or(body.len(), |i| tail_from + i + 2)
+    };
+
+    let mut events = parse_sse(&body[..head_end]);
+    events.extend(parse_sse(&body[tail_start..]));
+    events
+}
+
+/// Copy of a response body captured while it is streamed to the VM.
+///
+/// The whole body is kept up to `limit` bytes. Past that only the first and
+/// last `SSE_EDGE_BYTES` are kept, which is all [`process_response`] parses
+/// of an oversized stream anyway.
+pub struct StreamCapture {
+    limit: usize,
+    head: Vec<u8>,
+    tail: VecDeque<u8>,
+    total: usize,
+}
+
+impl StreamCapture {
+    /// Capture up to `limit` bytes in full (`None` for the
+    /// [`MAX_STREAM_CAPTURE_BYTES`] ceiling).
+    pub fn new(limit: Option<usize>) -> Self {
+        Self {
+            limit: limit.map_or(MAX_STREAM_CAPTURE_BYTES, |limit| {
+                limit.min(MAX_STREAM_CAPTURE_BYTES)
+            }),
+            head: Vec::new(),
+            tail: VecDeque::new(),
+            total: 0,
         }
+    }
 
-        events.push(SseEvent {
-            event_type,
-            data: data_parts.join("\n"),
-        });
+    pub fn push(&mut self, chunk: &[u8]) {
+        self.total += chunk.len();
+        if self.total <= self.limit {
+            self.head.extend_from_slice(chunk);
+            return;
+        }
+        // Past the limit only the first and last SSE_EDGE_BYTES are kept
+        if self.head.len() > SSE_EDGE_BYTES {
+            self.tail.extend(self.head.drain(SSE_EDGE_BYTES..));
+        }
+        let fill = (SSE_EDGE_BYTES - self.head.len()).min(chunk.len());
+        let (to_head, to_tail) = chunk.split_at(fill);
+        self.head.extend_from_slice(to_head);
+        self.tail.extend(to_tail);
+        let excess = self.tail.len().saturating_sub(SSE_EDGE_BYTES);
+        self.tail.drain(..excess);
     }
 
-    events
+    /// Bytes seen, including any that weren't kept.
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    /// The captured body, and whether it was cut down to its edges.
+    pub fn into_body(self) -> (Vec<u8>, bool) {
+        let truncated = self.total > self.limit;
+        let mut body = self.head;
+        body.extend(self.tail);
+        (body, truncated)
+    }
 }
 
 /// Given SSE events from a streaming response, reassemble into a single
@@ -198,12 +468,81 @@ fn reassemble_stream(
 ) -> (serde_json::Value, Option<String>, Option<u64>, Option<u64>) {
     match endpoint {
         "messages" => reassemble_anthropic_messages(events),
+        "complete" => reassemble_anthropic_complete(events),
         "chat_completions" => reassemble_openai_chat(events),
         "responses" => reassemble_openai_responses(events),
-        _ => (serde_json::Value::Null, None, None, None),
+        _ => reassemble_generic(events),
     }
 }
 
+/// Best-effort accounting for endpoints we don't recognize: take the model
+/// from any event and usage from the last event that reports it, keeping
+/// that final event as the body.
+fn reassemble_generic(
+    events: &[SseEvent],
+) -> (serde_json::Value, Option<String>, Option<u64>, Option<u64>) {
+    let parsed: Vec<serde_json::Value> = events
+        .iter()
+        .filter_map(|e| serde_json::from_str(&e.data).ok())
+        .collect();
+
+    let model = parsed.iter().find_map(find_model);
+    let last = parsed
+        .iter()
+        .rev()
+        .find(|json| find_usage(json).is_some())
+        .or_else(|| parsed.last());
+    let Some(last) = last else {
+        return (serde_json::Value::Null, model, None, None);
+    };
+
+    let (input_tokens, output_tokens) = generic_usage(last);
+    (last.clone(), model, input_tokens, output_tokens)
+}
+
+/// Model name at the top level or inside a wrapping `response`/`message` object.
+fn find_model(json: &serde_json::Value) -> Option<String> {
+    ["/model", "/response/model", "/message/model"]
+        .iter()
+        .find_map(|p| json.pointer(p)?.as_str())
+        .map(String::from)
+}
+
+/// Usage object at the top level or inside a wrapping `response`/`message` object.
+fn find_usage(json: &serde_json::Value) -> Option<&serde_json::Map<String, serde_json::Value>> {
+    ["/usage", "/response/usage", "/message/usage"]
+        .iter()
+        .find_map(|p| json.pointer(p)?.as_object())
+}
+
+/// Extract (input, output) token counts from any `usage.*token*` fields.
+///
+/// The usual names are preferred; otherwise any token field mentioning
+/// input/prompt or output/completion is used.
+fn generic_usage(json: &serde_json::Value) -> (Option<u64>, Option<u64>) {
+    let Some(usage) = find_usage(json) else {
+        return (None, None);
+    };
+    let tokens = |exact: &[&str], hints: &[&str]| {
+        exact
+            .iter()
+            .find_map(|k| usage.get(*k)?.as_u64())
+            .or_else(|| {
+                usage
+                    .iter()
+                    .filter(|(k, _)| k.contains("token") && hints.iter().any(|h| k.contains(h)))
+                    .find_map(|(_, v)| v.as_u64())
+            })
+    };
+    (
+        tokens(&["input_tokens", "prompt_tokens"], &["input", "prompt"]),
+        tokens(
+            &["output_tokens", "completion_tokens"],
+            &["output", "completion"],
+        ),
+    )
+}
+
 fn reassemble_anthropic_messages(
     events: &[SseEvent],
 ) -> (serde_json::Value, Option<String>, Option<u64>, Option<u64>) {
@@ -270,6 +609,53 @@ fn reassemble_anthropic_messages(
     (reassembled, model, input_tokens, output_tokens)
 }
 
+/// Reassemble a legacy Anthropic Text Completions stream. Each `completion`
+/// event carries a text fragment; the API does not report token usage.
+fn reassemble_anthropic_complete(
+    events: &[SseEvent],
+) -> (serde_json::Value, Option<String>, Option<u64>, Option<u64>) {
+    let mut id = serde_json::Value::Null;
+    let mut model = None;
+    let mut stop_reason = serde_json::Value::Null;
+    let mut completion = String::new();
+
+    for event in events {
+        if event.event_type.as_deref() != Some("completion") {
+            continue;
+        }
+        let Ok(json) = serde_json::from_str::<serde_json::Value>(&event.data) else {
+            continue;
+        };
+
+        if id.is_null() {
+            if let Some(i) = json.get("id").or_else(|| json.get("log_id")) {
+                id = i.clone();
+            }
+        }
+        if let Some(m) = json.get("model").and_then(serde_json::Value::as_str) {
+            model = Some(m.to_string());
+        }
+        if let Some(text) = json.get("completion").and_then(serde_json::Value::as_str) {
+            completion.push_str(text);
+        }
+        if let Some(sr) = json.get("stop_reason") {
+            if !sr.is_null() {
+                stop_reason = sr.clone();
+            }
+        }
+    }
+
+    let reassembled = serde_json::json!({
+        "id": id,
+        "type": "completion",
+        "model": model,
+        "completion": completion,
+        "stop_reason": stop_reason,
+    });
+
+    (reassembled, model, None, None)
+}
+
 fn reassemble_openai_chat(
     events: &[SseEvent],
 ) -> (serde_json::Value, Option<String>, Option<u64>, Option<u64>) {
@@ -377,27 +763,125 @@ fn reassemble_openai_responses(
     (serde_json::Value::Null, None, None, None)
 }
 
-/// Process an LLM response body. Detects streaming (from content-type header),
-/// parses SSE if streaming, returns (body_json, model, input_tokens, output_tokens).
+/// An LLM response summarized for the `llm.response` event.
+pub struct LlmResponse {
+    pub body: serde_json::Value,
+    pub model: Option<String>,
+    pub input_tokens: Option<u64>,
+    pub output_tokens: Option<u64>,
+    /// The stream exceeded the size cap, so only usage was extracted and `body` is null
+    pub reassembly_skipped: bool,
+    /// `input_tokens` is a length-based estimate rather than a reported count
+    pub input_tokens_estimated: bool,
+    /// `output_tokens` is a length-based estimate rather than a reported count
+    pub output_tokens_estimated: bool,
+    /// Billed audio duration for audio endpoints that report usage in seconds
+    pub audio_seconds: Option<f64>,
+}
+
+impl LlmResponse {
+    fn from_parts(
+        (body, model, input_tokens, output_tokens): (
+            serde_json::Value,
+            Option<String>,
+            Option<u64>,
+            Option<u64>,
+        ),
+    ) -> Self {
+        Self {
+            body,
+            model,
+            input_tokens,
+            output_tokens,
+            reassembly_skipped: false,
+            input_tokens_estimated: false,
+            output_tokens_estimated: false,
+            audio_seconds: None,
+        }
+    }
+
+    /// Fill in token counts the provider didn't report with a rough estimate
+    /// from the length of the request and response text.
+    pub fn estimate_missing_tokens(&mut self, request_body: &[u8], response_body: &[u8]) {
+        if self.input_tokens.is_none() {
+            self.input_tokens = Some(estimate_tokens(request_body));
+            self.input_tokens_estimated = true;
+        }
+        if self.output_tokens.is_none() {
+            // Prefer the reassembled body so SSE framing isn't counted
+            self.output_tokens = Some(if self.body.is_null() {
+                estimate_tokens(response_body)
+            } else {
+                text_len(&self.body).div_ceil(CHARS_PER_TOKEN)
+            });
+            self.output_tokens_estimated = true;
+        }
+    }
+}
+
+/// Estimate the token count of a request or response body from the length of
+/// the text it contains. Bodies that aren't JSON are counted in full.
+fn estimate_tokens(body: &[u8]) -> u64 {
+    let len = match serde_json::from_slice::<serde_json::Value>(body) {
+        Ok(json) => text_len(&json),
+        Err(_) => String::from_utf8_lossy(body).chars().count() as u64,
+    };
+    len.div_ceil(CHARS_PER_TOKEN)
+}
+
+/// Total number of characters in all strings inside a JSON value.
+fn text_len(value: &serde_json::Value) -> u64 {
+    match value {
+        serde_json::Value::String(s) => s.chars().count() as u64,
+        serde_json::Value::Array(items) => items.iter().map(text_len).sum(),
+        serde_json::Value::Object(map) => map.values().map(text_len).sum(),
+        _ => 0,
+    }
+}
+
+/// Process an LLM response body. Detects streaming (from content-type header)
+/// and parses SSE if streaming. Streams larger than `max_sse_bytes` are not
+/// reassembled; only their first and last events are parsed for usage.
 pub fn process_response(
     endpoint: &str,
     content_type: Option<&str>,
     body: &[u8],
-) -> (serde_json::Value, Option<String>, Option<u64>, Option<u64>) {
+    max_sse_bytes: Option<usize>,
+) -> LlmResponse {
     let is_streaming = content_type.is_some_and(|ct| ct.contains("text/event-stream"));
 
     if is_streaming {
+        if max_sse_bytes.is_some_and(|max| body.len() > max) {
+            let events = parse_sse_edges(body);
+            let (_, model, input_tokens, output_tokens) = reassemble_stream(endpoint, &events);
+            return LlmResponse {
+                body: serde_json::Value::Null,
+                model,
+                input_tokens,
+                output_tokens,
+                reassembly_skipped: true,
+                input_tokens_estimated: false,
+                output_tokens_estimated: false,
+                audio_seconds: None,
+            };
+        }
         let events = parse_sse(body);
-        reassemble_stream(endpoint, &events)
+        LlmResponse::from_parts(reassemble_stream(endpoint, &events))
     } else {
-        // Non-streaming JSON response
-        let json: serde_json::Value =
+        // Non-streaming JSON response (binary for audio_speech, which parses as null)
+        let mut json: serde_json::Value =
             serde_json::from_slice(body).unwrap_or(serde_json::Value::Null);
         let model = json
             .get("model")
             .and_then(serde_json::Value::as_str)
             .map(String::from);
 
+        if endpoint == "unknown" {
+            let model = find_model(&json);
+            let (input_tokens, output_tokens) = generic_usage(&json);
+            return LlmResponse::from_parts((json, model, input_tokens, output_tokens));
+        }
+
         // Anthropic uses input_tokens/output_tokens, OpenAI uses prompt_tokens/completion_tokens
         let input_tokens = json
             .pointer("/usage/input_tokens")
@@ -408,10 +892,37 @@ pub fn process_response(
             .or_else(|| json.pointer("/usage/completion_tokens"))
             .and_then(serde_json::Value::as_u64);
 
-        (json, model, input_tokens, output_tokens)
+        // Whisper bills by duration: `usage.seconds`, or `duration` in verbose_json
+        let audio_seconds = if endpoint.starts_with("audio_") {
+            json.pointer("/usage/seconds")
+                .or_else(|| json.get("duration"))
+                .and_then(serde_json::Value::as_f64)
+        } else {
+            None
+        };
+
+        // Embedding vectors are bulky and not useful in the event log
+        if endpoint == "embeddings" {
+            if let Some(obj) = json.as_object_mut() {
+                if let Some(serde_json::Value::Array(data)) = obj.remove("data") {
+                    obj.insert("embedding_count".to_string(), data.len().into());
+                }
+            }
+        }
+
+        LlmResponse {
+            audio_seconds,
+            ..LlmResponse::from_parts((json, model, input_tokens, output_tokens))
+        }
     }
 }
 
+/// Whether an endpoint exchanges text, so token counts can be estimated from
+/// content length. Embeddings have no output and audio is billed by duration.
+pub fn is_text_endpoint(endpoint: &str) -> bool {
+    endpoint != "embeddings" && !endpoint.starts_with("audio_")
+}
+
 /// Extract a summary from the request body for the llm.request event.
 /// Returns (model, message_count, streaming).
 pub fn extract_request_summary(
@@ -433,17 +944,84 @@ pub fn extract_request_summary(
             .get("messages")
             .and_then(serde_json::Value::as_array)
             .map(Vec::len),
-        "responses" => match json.get("input") {
+        "responses" | "embeddings" | "audio_speech" => match json.get("input") {
             Some(serde_json::Value::Array(arr)) => Some(arr.len()),
             Some(serde_json::Value::String(_)) => Some(1),
             _ => None,
         },
+        "complete" => json
+            .get("prompt")
+            .and_then(serde_json::Value::as_str)
+            .map(count_prompt_turns),
         _ => None,
     };
 
     (model, message_count, streaming)
 }
 
+/// Summarize a request body for the llm.request event without parsing more
+/// than [`SUMMARY_MAX_BYTES`] of it.
+///
+/// Larger bodies only yield `model` and `stream` (which clients send before
+/// the messages); the message count needs the whole body and is left out.
+/// Returns the summary and whether it came from a truncated body.
+pub fn summarize_request(
+    endpoint: &str,
+    body: &[u8],
+) -> ((Option<String>, Option<usize>, Option<bool>), bool) {
+    if body.len() <= SUMMARY_MAX_BYTES {
+        return (extract_request_summary(endpoint, body), false);
+    }
+
+    let mut fields = TopLevelFields::default();
+    let mut de = serde_json::Deserializer::from_slice(&body[..SUMMARY_MAX_BYTES]);
+    // The prefix always ends mid-document, so the error is expected; whatever
+    // was read before it is kept in `fields`
+    let _ = serde::Deserializer::deserialize_map(&mut de, &mut fields);
+    ((fields.model, None, fields.stream), true)
+}
+
+/// The top-level request fields readable from a truncated JSON object.
+#[derive(Default)]
+struct TopLevelFields {
+    model: Option<String>,
+    stream: Option<bool>,
+}
+
+impl<'de> serde::de::Visitor<'de> for &mut TopLevelFields {
+    type Value = ();
+
+    fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str("a JSON object")
+    }
+
+    fn visit_map<A: serde::de::MapAccess<'de>>(self, mut map: A) -> Result<(), A::Error> {
+        while let Some(key) = map.next_key::<String>()? {
+            match key.as_str() {
+                "model" => self.model = map.next_value()?,
+                "stream" => self.stream = map.next_value()?,
+                _ => {
+                    map.next_value::<serde::de::IgnoredAny>()?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Count the turns in a legacy `\n\nHuman: ...\n\nAssistant:` prompt,
+/// ignoring the trailing empty `Assistant:` turn the model is asked to complete.
+fn count_prompt_turns(prompt: &str) -> usize {
+    prompt
+        .split("\n\n")
+        .filter_map(|part| {
+            part.strip_prefix("Human:")
+                .or_else(|| part.strip_prefix("Assistant:"))
+        })
+        .filter(|text| !text.trim().is_empty())
+        .count()
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -458,6 +1036,27 @@ mod tests {
 
     // --- Detection tests ---
 
+    #[test]
+    fn provider_names_match_key_file_providers() {
+        let names: Vec<&str> = PROVIDERS.iter().map(|p| p.name).collect();
+        assert_eq!(names, llm_keys::PROVIDER_NAMES);
+    }
+
+    #[test]
+    fn key_store_loads_key_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = llm_keys::keys_path(dir.path());
+        let keys = BTreeMap::from([
+            ("openai".to_string(), " sk-file ".to_string()),
+            ("gemini".to_string(), "ignored".to_string()),
+        ]);
+        llm_keys::save(&path, &keys).unwrap();
+
+        let ks = LlmKeyStore::load(&path);
+        assert_eq!(ks.get("openai"), Some("sk-file"));
+        assert_eq!(ks.get("gemini"), None);
+    }
+
     #[test]
     fn detect_anthropic_messages() {
         let ks = make_key_store(vec![("anthropic", "sk-ant-test")]);
@@ -471,6 +1070,18 @@ mod tests {
         assert_eq!(v, "sk-ant-test");
     }
 
+    #[test]
+    fn detect_anthropic_complete() {
+        let ks = make_key_store(vec![("anthropic", "sk-ant-test")]);
+        let headers = HashMap::new();
+        let det = detect_llm_request("api.anthropic.com", "/v1/complete", &headers, &ks).unwrap();
+        assert_eq!(det.provider, "anthropic");
+        assert_eq!(det.endpoint, "complete");
+        let (h, v) = det.inject_header.unwrap();
+        assert_eq!(h, "x-api-key");
+        assert_eq!(v, "sk-ant-test");
+    }
+
     #[test]
     fn detect_anthropic_with_port() {
         let ks = make_key_store(vec![("anthropic", "sk-ant-test")]);
@@ -503,6 +1114,73 @@ mod tests {
         assert_eq!(det.endpoint, "responses");
     }
 
+    #[test]
+    fn detect_openai_embeddings_and_audio() {
+        let ks = make_key_store(vec![("openai", "sk-openai-test")]);
+        let headers = HashMap::new();
+        for (path, endpoint) in [
+            ("/v1/embeddings", "embeddings"),
+            ("/v1/audio/transcriptions", "audio_transcriptions"),
+            ("/v1/audio/translations", "audio_translations"),
+            ("/v1/audio/speech", "audio_speech"),
+        ] {
+            let det = detect_llm_request("api.openai.com", path, &headers, &ks).unwrap();
+            assert_eq!(det.endpoint, endpoint, "{path}");
+        }
+    }
+
+    #[test]
+    fn detect_azure_openai_deployment() {
+        let store = make_key_store(vec![("azure_openai", "azure-key")]);
+        let det = detect_llm_request(
+            "myresource.openai.azure.com:443",
+            "/openai/deployments/gpt-4o/chat/completions?api-version=2024-06-01",
+            &HashMap::new(),
+            &store,
+        )
+        .unwrap();
+        assert_eq!(det.provider, "azure_openai");
+        assert_eq!(det.endpoint, "chat_completions");
+        assert_eq!(det.strip_header.as_deref(), Some("api-key"));
+        assert_eq!(
+            det.inject_header,
+            Some(("api-key".to_string(), "azure-key".to_string()))
+        );
+
+        let det = detect_llm_request(
+            "MyResource.OpenAI.Azure.com",
+            "/openai/deployments/ada/embeddings?api-version=2024-06-01",
+            &HashMap::new(),
+            &store,
+        )
+        .unwrap();
+        assert_eq!(det.endpoint, "embeddings");
+
+        // The deployment segment can't be empty, and the bare suffix isn't a resource
+        let det = detect_llm_request(
+            "myresource.openai.azure.com",
+            "/openai/deployments//chat/completions",
+            &HashMap::new(),
+            &store,
+        )
+        .unwrap();
+        assert_eq!(det.endpoint, "unknown");
+        assert!(detect_llm_request(
+            "openai.azure.com",
+            "/openai/deployments/gpt-4o/chat/completions",
+            &HashMap::new(),
+            &store,
+        )
+        .is_none());
+        assert!(detect_llm_request(
+            "evil-openai.azure.com.example",
+            "/openai/deployments/gpt-4o/chat/completions",
+            &HashMap::new(),
+            &store,
+        )
+        .is_none());
+    }
+
     #[test]
     fn detect_unknown_endpoint() {
         let ks = make_key_store(vec![("anthropic", "sk-ant-test")]);
@@ -512,6 +1190,33 @@ mod tests {
         assert_eq!(det.endpoint, "unknown");
     }
 
+    #[test]
+    fn process_unknown_endpoint_json() {
+        let body =
+            br#"{"model":"claude-x","usage":{"cache_read_input_tokens":7,"output_tokens":3}}"#;
+        let resp = process_response("unknown", Some("application/json"), body, None);
+        assert_eq!(resp.model.as_deref(), Some("claude-x"));
+        assert_eq!(resp.input_tokens, Some(7));
+        assert_eq!(resp.output_tokens, Some(3));
+        assert_eq!(resp.body["usage"]["output_tokens"], 3);
+    }
+
+    #[test]
+    fn process_unknown_endpoint_stream() {
+        let body = b"data: {\"model\":\"gpt-x\",\"delta\":\"a\"}\n\ndata: {\"response\":{\"usage\":{\"prompt_tokens\":12,\"completion_tokens\":4}}}\n\ndata: {\"done\":true}\n\ndata: [DONE]\n\n";
+        let resp = process_response("unknown", Some("text/event-stream"), body, None);
+        assert_eq!(resp.model.as_deref(), Some("gpt-x"));
+        assert_eq!(resp.input_tokens, Some(12));
+        assert_eq!(resp.output_tokens, Some(4));
+        assert!(resp.body.pointer("/response/usage").is_some());
+
+        // No usage anywhere: keep the final event, without counts
+        let body = b"data: {\"delta\":\"a\"}\n\ndata: {\"delta\":\"b\"}\n\n";
+        let resp = process_response("unknown", Some("text/event-stream"), body, None);
+        assert_eq!(resp.body["delta"], "b");
+        assert!(resp.input_tokens.is_none());
+    }
+
     #[test]
     fn detect_no_key_passthrough() {
         let ks = make_key_store(vec![]);
@@ -557,6 +1262,39 @@ mod tests {
         assert_eq!(events[0].data, "{\"test\": true}");
     }
 
+    #[test]
+    fn sse_parser_handles_frames_split_across_chunks() {
+        let body = "event: message_start\ndata: {\"a\":1}\n\n: keepalive\n\ndata: {\"text\":\"héllo\"}\n\ndata: [DONE]\n\n".as_bytes();
+        let expected = parse_sse(body);
+        assert_eq!(expected.len(), 2);
+
+        // Every possible split point, including inside the delimiter and the 'é'
+        for split in 0..=body.len() {
+            let mut parser = SseParser::default();
+            let mut events = parser.push(&body[..split]);
+            events.extend(parser.push(&body[split..]));
+            events.extend(parser.finish());
+            assert_eq!(events, expected, "split at {split}");
+        }
+
+        // One byte at a time
+        let mut parser = SseParser::default();
+        let mut events: Vec<SseEvent> = body.iter().flat_map(|b| parser.push(&[*b])).collect();
+        events.extend(parser.finish());
+        assert_eq!(events, expected);
+    }
+
+    #[test]
+    fn sse_parser_yields_events_as_delimiters_arrive() {
+        let mut parser = SseParser::default();
+        assert!(parser.push(b"data: {\"n\":1}\n").is_empty());
+        let events = parser.push(b"\ndata: {\"n\":2}");
+        assert_eq!(events.len(), 1);
+        assert_eq!(events[0].data, "{\"n\":1}");
+        // The trailing frame has no delimiter, so only finish() returns it
+        assert_eq!(parser.finish().unwrap().data, "{\"n\":2}");
+    }
+
     // --- Stream reassembly tests ---
 
     #[test]
@@ -591,6 +1329,20 @@ mod tests {
         );
     }
 
+    #[test]
+    fn reassemble_anthropic_complete_stream() {
+        let body = b"event: completion\ndata: {\"type\":\"completion\",\"completion\":\" Hello\",\"stop_reason\":null,\"model\":\"claude-2.1\",\"log_id\":\"compl_01\"}\n\nevent: ping\ndata: {\"type\":\"ping\"}\n\nevent: completion\ndata: {\"type\":\"completion\",\"completion\":\" world\",\"stop_reason\":\"stop_sequence\",\"model\":\"claude-2.1\",\"log_id\":\"compl_01\"}\n\n";
+        let events = parse_sse(body);
+
+        let (json, model, input, output) = reassemble_stream("complete", &events);
+        assert_eq!(model.as_deref(), Some("claude-2.1"));
+        assert!(input.is_none());
+        assert!(output.is_none());
+        assert_eq!(json["id"], "compl_01");
+        assert_eq!(json["completion"], " Hello world");
+        assert_eq!(json["stop_reason"], "stop_sequence");
+    }
+
     #[test]
     fn reassemble_openai_chat_stream() {
         let events = vec![
@@ -654,6 +1406,34 @@ mod tests {
         assert_eq!(streaming, Some(true));
     }
 
+    #[test]
+    fn extract_summary_anthropic_complete() {
+        let body = br#"{"model":"claude-2.1","prompt":"\n\nHuman: hi\n\nAssistant: hello\n\nHuman: bye\n\nAssistant:","max_tokens_to_sample":256,"stream":true}"#;
+        let (model, count, streaming) = extract_request_summary("complete", body);
+        assert_eq!(model.as_deref(), Some("claude-2.1"));
+        assert_eq!(count, Some(3));
+        assert_eq!(streaming, Some(true));
+    }
+
+    #[test]
+    fn summarize_large_request_reads_prefix_only() {
+        let big = "x".repeat(SUMMARY_MAX_BYTES);
+        let body = format!(
+            r#"{{"model":"claude-sonnet-4-20250514","stream":true,"messages":[{{"role":"user","content":"{big}"}}]}}"#
+        );
+        let ((model, count, streaming), truncated) = summarize_request("messages", body.as_bytes());
+        assert!(truncated);
+        assert_eq!(model.as_deref(), Some("claude-sonnet-4-20250514"));
+        assert_eq!(streaming, Some(true));
+        assert!(count.is_none());
+
+        // Small bodies get the full summary
+        let body = br#"{"model":"gpt-4o","messages":[{"role":"user","content":"hi"}]}"#;
+        let ((_, count, _), truncated) = summarize_request("chat_completions", body);
+        assert!(!truncated);
+        assert_eq!(count, Some(1));
+    }
+
     #[test]
     fn extract_summary_openai_chat() {
         let body =
@@ -685,22 +1465,151 @@ mod tests {
     #[test]
     fn process_anthropic_non_streaming() {
         let body = br#"{"id":"msg_01","model":"claude-sonnet-4-20250514","content":[{"type":"text","text":"Hello"}],"usage":{"input_tokens":100,"output_tokens":50}}"#;
-        let (json, model, input, output) =
-            process_response("messages", Some("application/json"), body);
-        assert_eq!(model.as_deref(), Some("claude-sonnet-4-20250514"));
-        assert_eq!(input, Some(100));
-        assert_eq!(output, Some(50));
-        assert!(json.get("content").is_some());
+        let resp = process_response("messages", Some("application/json"), body, None);
+        assert_eq!(resp.model.as_deref(), Some("claude-sonnet-4-20250514"));
+        assert_eq!(resp.input_tokens, Some(100));
+        assert_eq!(resp.output_tokens, Some(50));
+        assert!(resp.body.get("content").is_some());
+    }
+
+    #[test]
+    fn process_anthropic_complete_non_streaming() {
+        let body = br#"{"type":"completion","id":"compl_01","completion":" Hello","stop_reason":"stop_sequence","model":"claude-2.1"}"#;
+        let resp = process_response("complete", Some("application/json"), body, None);
+        assert_eq!(resp.model.as_deref(), Some("claude-2.1"));
+        assert!(resp.input_tokens.is_none());
+        assert!(resp.output_tokens.is_none());
+        assert_eq!(resp.body["completion"], " Hello");
     }
 
     #[test]
     fn process_openai_non_streaming() {
         let body = br#"{"id":"chatcmpl-01","model":"gpt-4o","choices":[{"message":{"role":"assistant","content":"Hi"}}],"usage":{"prompt_tokens":10,"completion_tokens":5}}"#;
-        let (json, model, input, output) =
-            process_response("chat_completions", Some("application/json"), body);
-        assert_eq!(model.as_deref(), Some("gpt-4o"));
-        assert_eq!(input, Some(10));
-        assert_eq!(output, Some(5));
-        assert!(json.get("choices").is_some());
+        let resp = process_response("chat_completions", Some("application/json"), body, None);
+        assert_eq!(resp.model.as_deref(), Some("gpt-4o"));
+        assert_eq!(resp.input_tokens, Some(10));
+        assert_eq!(resp.output_tokens, Some(5));
+        assert!(resp.body.get("choices").is_some());
+    }
+
+    #[test]
+    fn process_openai_embeddings() {
+        let body = br#"{"object":"list","data":[{"object":"embedding","index":0,"embedding":[0.1,0.2]},{"object":"embedding","index":1,"embedding":[0.3,0.4]}],"model":"text-embedding-3-small","usage":{"prompt_tokens":8,"total_tokens":8}}"#;
+        let resp = process_response("embeddings", Some("application/json"), body, None);
+        assert_eq!(resp.model.as_deref(), Some("text-embedding-3-small"));
+        assert_eq!(resp.input_tokens, Some(8));
+        assert!(resp.output_tokens.is_none());
+        assert!(resp.body.get("data").is_none());
+        assert_eq!(resp.body["embedding_count"], 2);
+    }
+
+    #[test]
+    fn process_openai_audio_usage() {
+        let body = br#"{"text":"Hello there.","usage":{"type":"duration","seconds":4}}"#;
+        let resp = process_response("audio_transcriptions", Some("application/json"), body, None);
+        assert_eq!(resp.audio_seconds, Some(4.0));
+
+        let body = br#"{"task":"transcribe","language":"english","duration":8.47,"text":"Hello."}"#;
+        let resp = process_response("audio_translations", Some("application/json"), body, None);
+        assert_eq!(resp.audio_seconds, Some(8.47));
+
+        let body = br#"{"text":"Hi","usage":{"type":"tokens","input_tokens":14,"output_tokens":45,"total_tokens":59}}"#;
+        let resp = process_response("audio_transcriptions", Some("application/json"), body, None);
+        assert_eq!(resp.input_tokens, Some(14));
+        assert_eq!(resp.output_tokens, Some(45));
+        assert!(resp.audio_seconds.is_none());
+
+        // Speech responses are raw audio
+        let resp = process_response("audio_speech", Some("audio/mpeg"), b"\xff\xfb\x90", None);
+        assert!(resp.body.is_null());
+    }
+
+    // --- Token estimates ---
+
+    #[test]
+    fn estimate_fills_only_missing_counts() {
+        let body = br#"{"model":"m","content":[{"type":"text","text":"Hello world!"}],"usage":{"input_tokens":100}}"#;
+        let mut resp = process_response("messages", Some("application/json"), body, None);
+        let request = br#"{"messages":[{"role":"user","content":"abcdefgh"}]}"#;
+        resp.estimate_missing_tokens(request, body);
+
+        assert_eq!(resp.input_tokens, Some(100));
+        assert!(!resp.input_tokens_estimated);
+        // "m" + "text" + "Hello world!" = 17 chars
+        assert_eq!(resp.output_tokens, Some(5));
+        assert!(resp.output_tokens_estimated);
+    }
+
+    #[test]
+    fn estimate_tokens_from_text() {
+        // "user" + "abcdefgh" = 12 chars
+        assert_eq!(
+            estimate_tokens(br#"{"messages":[{"role":"user","content":"abcdefgh"}]}"#),
+            3
+        );
+        assert_eq!(estimate_tokens(b"upstream error"), 4);
+        assert_eq!(estimate_tokens(b""), 0);
+    }
+
+    // --- Oversized streams ---
+
+    fn large_anthropic_stream(deltas: usize) -> Vec<u8> {
+        let mut body = String::from(
+            "event: message_start\ndata: {\"message\":{\"id\":\"msg_01\",\"model\":\"claude-sonnet-4-20250514\",\"usage\":{\"input_tokens\":150}}}\n\n",
+        );
+        for _ in 0..deltas {
+            body.push_str("event: content_block_delta\ndata: {\"delta\":{\"type\":\"text_delta\",\"text\":\"lorem ipsum dolor sit amet \"}}\n\n");
+        }
+        body.push_str("event: message_delta\ndata: {\"delta\":{\"stop_reason\":\"end_turn\"},\"usage\":{\"output_tokens\":85}}\n\n");
+        body.into_bytes()
+    }
+
+    #[test]
+    fn process_oversized_stream_skips_reassembly() {
+        let body = large_anthropic_stream(10_000);
+        let resp = process_response("messages", Some("text/event-stream"), &body, Some(1024));
+        assert!(resp.reassembly_skipped);
+        assert!(resp.body.is_null());
+        assert_eq!(resp.model.as_deref(), Some("claude-sonnet-4-20250514"));
+        assert_eq!(resp.input_tokens, Some(150));
+        assert_eq!(resp.output_tokens, Some(85));
+    }
+
+    #[test]
+    fn process_stream_under_cap_is_reassembled() {
+        let body = large_anthropic_stream(3);
+        let resp = process_response("messages", Some("text/event-stream"), &body, Some(1 << 20));
+        assert!(!resp.reassembly_skipped);
+        assert_eq!(resp.output_tokens, Some(85));
+        assert!(resp.body.pointer("/content/0/text").is_some());
+    }
+
+    #[test]
+    fn stream_capture_keeps_edges_past_limit() {
+        let body = large_anthropic_stream(10_000);
+        let mut capture = StreamCapture::new(Some(1024));
+        for chunk in body.chunks(1000) {
+            capture.push(chunk);
+        }
+        assert_eq!(capture.total(), body.len());
+        let (captured, truncated) = capture.into_body();
+        assert!(truncated);
+        assert_eq!(captured.len(), 2 * SSE_EDGE_BYTES);
+
+        // Edges parse to the same usage as the full body
+        let resp = process_response("messages", Some("text/event-stream"), &captured, Some(0));
+        assert_eq!(resp.input_tokens, Some(150));
+        assert_eq!(resp.output_tokens, Some(85));
+
+        let mut capture = StreamCapture::new(None);
+        capture.push(&body);
+        assert_eq!(capture.into_body(), (body, false));
+    }
+
+    #[test]
+    fn parse_sse_edges_small_body() {
+        // Bodies smaller than both edge windows are parsed once, without duplicates
+        let body = large_anthropic_stream(2);
+        assert_eq!(parse_sse_edges(&body).len(), parse_sse(&body).len());
     }
 }
@@ -0,0 +1,113 @@
+use std::collections::BTreeSet;
+
+/// Environment variable listing the HTTP methods VMs may use
+pub const ALLOWED_METHODS_ENV: &str = "CLAWPOT_HTTP_ALLOWED_METHODS";
+
+/// Methods allowed when `CLAWPOT_HTTP_ALLOWED_METHODS` is unset. TRACE and
+/// CONNECT are left out: CONNECT tunnels raw bytes the proxy can't inspect,
+/// so it has to be allowed explicitly, and TRACE only reflects requests back.
+const DEFAULT_ALLOWED_METHODS: &[&str] =
+    &["GET", "HEAD", "POST", "PUT", "PATCH", "DELETE", "OPTIONS"];
+
+/// Local HTTP method allowlist, checked before the auth service is asked.
+///
+/// This gives baseline protection even when no external authorizer is
+/// configured and every request would otherwise be allowed.
+#[derive(Clone)]
+pub struct MethodPolicy {
+    /// Allowed methods (upper case), or `None` to allow every method
+    allowed: Option<BTreeSet<String>>,
+}
+
+impl Default for MethodPolicy {
+    fn default() -> Self {
+        Self {
+            allowed: Some(
+                DEFAULT_ALLOWED_METHODS
+                    .iter()
+                    .map(|m| (*m).to_string())
+                    .collect(),
+            ),
+        }
+    }
+}
+
+impl MethodPolicy {
+    /// Parse a comma-separated method list, or `*` to allow every method.
+    /// Returns `None` if no methods are listed.
+    pub fn parse(raw: &str) -> Option<Self> {
+        let methods: BTreeSet<String> = raw
+            .split(',')
+            .map(|m| m.trim().to_ascii_uppercase())
+            .filter(|m| !m.is_empty())
+            .collect();
+        if methods.is_empty() {
+            return None;
+        }
+        if methods.contains("*") {
+            return Some(Self { allowed: None });
+        }
+        Some(Self {
+            allowed: Some(methods),
+        })
+    }
+
+    pub fn allows_all(&self) -> bool {
+        self.allowed.is_none()
+    }
+
+    pub fn allows(&self, method: &str) -> bool {
+        self.allowed
+            .as_ref()
+            .is_none_or(|allowed| allowed.contains(&method.to_ascii_uppercase()))
+    }
+
+    /// Value for the `Allow` header of a 405 response.
+    pub fn allow_header(&self) -> String {
+        self.allowed
+            .as_ref()
+            .map(|allowed| {
+                allowed
+                    .iter()
+                    .map(String::as_str)
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            })
+            .unwrap_or_default()
+    }
+}
+
+impl std::fmt::Display for MethodPolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.allows_all() {
+            f.write_str("*")
+        } else {
+            f.write_str(&self.allow_header())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_denies_trace_and_connect() {
+        let policy = MethodPolicy::default();
+        assert!(policy.allows("GET"));
+        assert!(policy.allows("post"));
+        assert!(!policy.allows("TRACE"));
+        assert!(!policy.allows("CONNECT"));
+    }
+
+    #[test]
+    fn test_parse() {
+        let policy = MethodPolicy::parse(" get, POST ,").unwrap();
+        assert!(policy.allows("GET"));
+        assert!(!policy.allows("PUT"));
+        assert_eq!(policy.allow_header(), "GET, POST");
+
+        assert!(MethodPolicy::parse("*").unwrap().allows("TRACE"));
+        assert!(MethodPolicy::parse(" , ").is_none());
+    }
+}
@@ -1,8 +1,114 @@
 pub mod auth_client;
 pub mod body_store;
 pub mod ca;
+pub mod dns_allowlist;
+pub mod dns_overrides;
 pub mod dns_proxy;
+pub mod doh;
 pub mod http_proxy;
 pub mod llm;
+pub mod methods;
 pub mod proxy_protocol;
+pub mod redact;
+pub mod splice;
+pub mod ssrf;
+pub mod throttle;
 pub mod tls_mitm;
+pub mod token_budget;
+
+use self::throttle::warn_throttled;
+use crate::events::EventStore;
+use crate::vm::VmRegistry;
+use std::net::IpAddr;
+
+/// vm_id recorded for traffic from sources that aren't registered VMs
+pub const UNKNOWN_VM_ID: &str = "unknown";
+
+/// Resolve the VM that sent traffic from `ip`.
+///
+/// Unknown sources (spoofed, or a VM that was just deleted) always produce a
+/// `network.unknown_source` event. They are denied if `deny_unknown`
+/// (`CLAWPOT_DENY_UNKNOWN_VM`) is set, and otherwise go through the normal
+/// authorization path labelled "unknown". Returns `None` if the request
+/// should be denied, otherwise the vm_id to attribute it to.
+pub async fn resolve_vm_id(
+    registry: &VmRegistry,
+    events: &EventStore,
+    ip: IpAddr,
+    protocol: &str,
+    deny_unknown: bool,
+) -> Option<String> {
+    resolve_vm(registry, events, ip, protocol, deny_unknown)
+        .await
+        .map(|(id, _)| id)
+}
+
+/// Like [`resolve_vm_id`], but also returns the VM's friendly name (if it
+/// has one) so events can be labelled with something readable.
+pub async fn resolve_vm(
+    registry: &VmRegistry,
+    events: &EventStore,
+    ip: IpAddr,
+    protocol: &str,
+    denied: bool,
+) -> Option<(String, Option<String>)> {
+    if let Some((id, name)) = registry.find_named_by_ip(ip).await {
+        return Some((id.to_string(), name));
+    }
+
+    // A spoofing or stale VM can send a flood of these, so they're
+    // throttled per source IP
+    let key = if denied {
+        "Blocking request from unknown source IP"
+    } else {
+        "Allowing request from unknown source IP"
+    };
+    warn_throttled(key, ip, format!("{protocol} request from {ip}"));
+    events.emit(
+        "network.unknown_source",
+        "network",
+        None,
+        None,
+        &serde_json::json!({
+            "source_ip": ip.to_string(),
+            "protocol": protocol,
+            "denied": denied,
+            "deny_code": denied.then_some(auth_client::DenyCode::UnknownVm.as_str()),
+        }),
+    );
+
+    (!denied).then(|| (UNKNOWN_VM_ID.to_string(), None))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::events::PersistMode;
+
+    #[tokio::test]
+    async fn test_unknown_source_policy() {
+        let dir = tempfile::tempdir().unwrap();
+        let events = EventStore::new(
+            &dir.path().join("events.db"),
+            "test-session",
+            "test-server",
+            "0.1.0",
+            "{}",
+            PersistMode::All,
+        )
+        .unwrap();
+        let registry = VmRegistry::new();
+        let ip = "192.168.100.9".parse().unwrap();
+
+        assert_eq!(
+            resolve_vm_id(&registry, &events, ip, "HTTP", true).await,
+            None
+        );
+        assert_eq!(
+            resolve_vm_id(&registry, &events, ip, "HTTP", false)
+                .await
+                .as_deref(),
+            Some(UNKNOWN_VM_ID)
+        );
+    }
+}
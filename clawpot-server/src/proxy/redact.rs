@@ -0,0 +1,143 @@
+use std::collections::{BTreeSet, HashMap};
+
+/// Environment variable listing the headers whose values are kept out of
+/// recorded HTTP events
+pub const REDACT_HEADERS_ENV: &str = "CLAWPOT_REDACT_HEADERS";
+
+/// Headers that usually carry credentials. They are redacted when
+/// `CLAWPOT_REDACT_HEADERS` is unset, and never forwarded to another origin
+/// when the proxy follows a redirect.
+const CREDENTIAL_HEADERS: &[&str] = &[
+    "api-key",
+    "authorization",
+    "cookie",
+    "proxy-authorization",
+    "set-cookie",
+    "x-api-key",
+    "x-auth-token",
+    "x-goog-api-key",
+];
+
+/// Whether `name` is one of the [`CREDENTIAL_HEADERS`]
+pub fn is_credential_header(name: &str) -> bool {
+    CREDENTIAL_HEADERS
+        .iter()
+        .any(|h| h.eq_ignore_ascii_case(name))
+}
+
+/// Recorded in place of a redacted header's value
+pub const REDACTED: &str = "<redacted>";
+
+/// Headers whose values are replaced with [`REDACTED`] in the header maps
+/// stored with `network.http.request` and `network.http.response` events.
+/// Only the recorded copy changes; requests and responses are forwarded
+/// with their real headers.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeaderRedaction {
+    /// Lower-case header names
+    names: BTreeSet<String>,
+}
+
+impl Default for HeaderRedaction {
+    fn default() -> Self {
+        Self {
+            names: CREDENTIAL_HEADERS
+                .iter()
+                .map(|h| (*h).to_string())
+                .collect(),
+        }
+    }
+}
+
+impl HeaderRedaction {
+    /// Parse a comma-separated header list, or `none` to record every
+    /// header as sent.
+    pub fn parse(raw: &str) -> Self {
+        if raw.trim().eq_ignore_ascii_case("none") {
+            return Self {
+                names: BTreeSet::new(),
+            };
+        }
+        Self {
+            names: raw
+                .split(',')
+                .map(|h| h.trim().to_ascii_lowercase())
+                .filter(|h| !h.is_empty())
+                .collect(),
+        }
+    }
+
+    pub fn is_redacted(&self, name: &str) -> bool {
+        self.names.contains(&name.to_ascii_lowercase())
+    }
+
+    /// `headers` as the JSON object stored in an event, with redacted values replaced.
+    pub fn to_json(&self, headers: &HashMap<String, String>) -> String {
+        let recorded: HashMap<&str, &str> = headers
+            .iter()
+            .map(|(name, value)| {
+                let value = if self.is_redacted(name) {
+                    REDACTED
+                } else {
+                    value.as_str()
+                };
+                (name.as_str(), value)
+            })
+            .collect();
+        serde_json::to_string(&recorded).unwrap_or_default()
+    }
+}
+
+impl std::fmt::Display for HeaderRedaction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.names.is_empty() {
+            return f.write_str("none");
+        }
+        let names: Vec<&str> = self.names.iter().map(String::as_str).collect();
+        f.write_str(&names.join(","))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_to_json_redacts_credentials() {
+        let headers: HashMap<String, String> = [
+            ("Authorization", "Bearer sk-secret"),
+            ("x-api-key", "key-secret"),
+            ("cookie", "session=cookie-secret"),
+            ("content-type", "application/json"),
+            ("user-agent", "curl/8.0"),
+        ]
+        .into_iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+
+        let json = HeaderRedaction::default().to_json(&headers);
+        for secret in ["sk-secret", "key-secret", "cookie-secret"] {
+            assert!(!json.contains(secret), "{secret} leaked into {json}");
+        }
+        let recorded: HashMap<String, String> = serde_json::from_str(&json).unwrap();
+        assert_eq!(recorded["Authorization"], REDACTED);
+        assert_eq!(recorded["content-type"], "application/json");
+        assert_eq!(recorded["user-agent"], "curl/8.0");
+    }
+
+    #[test]
+    fn test_parse() {
+        let redaction = HeaderRedaction::parse(" X-Session-Token, authorization ,");
+        assert!(redaction.is_redacted("x-session-token"));
+        assert!(redaction.is_redacted("Authorization"));
+        assert!(!redaction.is_redacted("cookie"));
+        assert_eq!(redaction.to_string(), "authorization,x-session-token");
+
+        assert!(is_credential_header("API-Key"));
+        assert!(!is_credential_header("content-type"));
+
+        let none = HeaderRedaction::parse("None");
+        assert!(!none.is_redacted("authorization"));
+        assert_eq!(none.to_string(), "none");
+    }
+}
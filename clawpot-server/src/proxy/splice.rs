@@ -0,0 +1,381 @@
+use anyhow::{Context, Result};
+use std::collections::{HashMap, HashSet};
+use std::net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+
+/// Environment variable listing hosts whose traffic is spliced after authorization
+pub const SPLICE_HOSTS_ENV: &str = "CLAWPOT_SPLICE_HOSTS";
+
+/// Environment variable listing hosts or IPs whose port-80 traffic bypasses the proxy
+pub const BYPASS_HOSTS_ENV: &str = "CLAWPOT_HTTP_BYPASS_HOSTS";
+
+/// Largest request head we will peek at when deciding whether to splice
+const MAX_HEAD_SIZE: usize = 8192;
+
+/// How long to wait for a complete request head before falling back to hyper
+const HEAD_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// A set of hostnames (or IP addresses) whose plain HTTP connections skip
+/// the buffered proxy path.
+///
+/// Spliced connections are joined to a direct upstream TCP connection, so
+/// bodies are neither buffered nor recorded and LLM key injection does not
+/// apply.
+#[derive(Clone, Default)]
+pub struct HostList {
+    hosts: HashSet<String>,
+}
+
+impl HostList {
+    /// Parse a comma-separated host list.
+    pub fn parse(raw: &str) -> Self {
+        let hosts = raw
+            .split(',')
+            .map(|h| h.trim().to_ascii_lowercase())
+            .filter(|h| !h.is_empty())
+            .collect();
+        Self { hosts }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hosts.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.hosts.len()
+    }
+
+    /// Check a `Host` header value (with or without a port) against the list.
+    pub fn contains(&self, host: &str) -> bool {
+        let (name, _) = split_host_port(host);
+        self.hosts.contains(&name.to_ascii_lowercase())
+    }
+
+    /// Check whether an IP address is listed.
+    pub fn contains_ip(&self, ip: IpAddr) -> bool {
+        self.hosts.contains(&ip.to_string())
+    }
+}
+
+impl std::fmt::Display for HostList {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let mut hosts: Vec<&str> = self.hosts.iter().map(String::as_str).collect();
+        hosts.sort_unstable();
+        f.write_str(&hosts.join(","))
+    }
+}
+
+/// The parts of an HTTP/1.x request head needed to authorize a spliced request.
+#[derive(Debug, PartialEq, Eq)]
+pub struct RequestHead {
+    pub method: String,
+    pub path: String,
+    pub host: String,
+    pub headers: HashMap<String, String>,
+    /// Length of the head in bytes, including the blank line ending it
+    pub len: usize,
+}
+
+/// Peek at the first request on `stream` without consuming it.
+///
+/// Returns `None` if no complete, parseable request head arrives in time, in
+/// which case the connection should be handed to hyper untouched.
+pub async fn peek_request_head(stream: &TcpStream) -> Option<RequestHead> {
+    let deadline = Instant::now() + HEAD_TIMEOUT;
+    let mut buf = vec![0u8; MAX_HEAD_SIZE];
+    let mut last_len = 0;
+
+    loop {
+        let n = tokio::time::timeout_at(deadline.into(), stream.peek(&mut buf))
+            .await
+            .ok()?
+            .ok()?;
+        if n == 0 {
+            return None;
+        }
+        if let Some(end) = find_head_end(&buf[..n]) {
+            return parse_request_head(&buf[..end]);
+        }
+        if n == buf.len() {
+            return None;
+        }
+        // peek returns immediately while data is buffered, so back off until more arrives
+        if n == last_len {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        last_len = n;
+    }
+}
+
+/// What the first bytes of a port-80 connection look like.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Sniffed {
+    /// Starts with an HTTP method token
+    Http,
+    /// Definitely not HTTP; holds the bytes seen so far
+    NotHttp(Vec<u8>),
+    /// No data arrived in time (e.g. a protocol where the server speaks first)
+    Unknown,
+}
+
+/// Peek at the first bytes of `stream` to decide whether it is speaking HTTP.
+pub async fn sniff_http(stream: &TcpStream) -> Sniffed {
+    let deadline = Instant::now() + HEAD_TIMEOUT;
+    let mut buf = [0u8; 32];
+    let mut last_len = 0;
+
+    loop {
+        let n = match tokio::time::timeout_at(deadline.into(), stream.peek(&mut buf)).await {
+            Ok(Ok(n)) if n > 0 => n,
+            _ if last_len == 0 => return Sniffed::Unknown,
+            // Timed out or closed partway through what could be a method token
+            _ => return Sniffed::NotHttp(buf[..last_len].to_vec()),
+        };
+        match looks_like_http(&buf[..n]) {
+            Some(true) => return Sniffed::Http,
+            Some(false) => return Sniffed::NotHttp(buf[..n].to_vec()),
+            None if n == buf.len() => return Sniffed::NotHttp(buf[..n].to_vec()),
+            None => {}
+        }
+        if n == last_len {
+            tokio::time::sleep(Duration::from_millis(5)).await;
+        }
+        last_len = n;
+    }
+}
+
+/// Whether `data` starts with an HTTP method token followed by a space.
+/// Returns `None` if more bytes are needed to tell.
+fn looks_like_http(data: &[u8]) -> Option<bool> {
+    const MAX_METHOD_LEN: usize = 20;
+    for (i, &b) in data.iter().enumerate() {
+        if b == b' ' {
+            return Some(i > 0);
+        }
+        if !(b.is_ascii_uppercase() || b == b'-') || i >= MAX_METHOD_LEN {
+            return Some(false);
+        }
+    }
+    None
+}
+
+/// Close `stream` with a TCP RST instead of a normal FIN, so the client sees
+/// an immediate connection reset rather than a confusing empty response.
+pub fn reset(stream: TcpStream) {
+    let linger = nix::libc::linger {
+        l_onoff: 1,
+        l_linger: 0,
+    };
+    // Best-effort: if this fails the drop still closes the socket normally
+    let _ = nix::sys::socket::setsockopt(&stream, nix::sys::socket::sockopt::Linger, &linger);
+    drop(stream);
+}
+
+/// Connect to `upstream` (a `host:port` or `ip:port`) and copy bytes in both
+/// directions until either side closes. Returns (bytes from VM, bytes from upstream).
+pub async fn splice(mut vm: TcpStream, upstream: &str) -> Result<(u64, u64)> {
+    let mut conn = TcpStream::connect(upstream)
+        .await
+        .with_context(|| format!("Failed to connect to upstream {upstream}"))?;
+    conn.set_nodelay(true).ok();
+
+    tokio::io::copy_bidirectional(&mut vm, &mut conn)
+        .await
+        .with_context(|| format!("Splice to {upstream} failed"))
+}
+
+/// Splice a plain HTTP connection after rewriting its first request head,
+/// `head_len` bytes long, to carry `Connection: close`. The upstream then
+/// closes the connection after answering that one request, so the VM has to
+/// send any further request on a new connection, where it is authorized
+/// again. Returns (bytes from VM, bytes from upstream).
+pub async fn splice_one_request(
+    mut vm: TcpStream,
+    upstream: &str,
+    head_len: usize,
+) -> Result<(u64, u64)> {
+    let mut head = vec![0u8; head_len];
+    vm.read_exact(&mut head)
+        .await
+        .context("Failed to read request head")?;
+    let mut conn = TcpStream::connect(upstream)
+        .await
+        .with_context(|| format!("Failed to connect to upstream {upstream}"))?;
+    conn.set_nodelay(true).ok();
+    conn.write_all(&with_connection_close(&head))
+        .await
+        .with_context(|| format!("Failed to send request head to {upstream}"))?;
+
+    let (up, down) = tokio::io::copy_bidirectional(&mut vm, &mut conn)
+        .await
+        .with_context(|| format!("Splice to {upstream} failed"))?;
+    Ok((up + head_len as u64, down))
+}
+
+/// `head` with its `Connection`, `Keep-Alive` and `Proxy-Connection` headers
+/// replaced by `Connection: close`.
+fn with_connection_close(head: &[u8]) -> Vec<u8> {
+    let text = String::from_utf8_lossy(head);
+    let mut out = String::with_capacity(text.len() + 19);
+    for line in text.split_terminator("\r\n").filter(|l| !l.is_empty()) {
+        let name = line.split_once(':').map(|(name, _)| name.trim());
+        if name.is_some_and(|name| {
+            ["connection", "keep-alive", "proxy-connection"]
+                .iter()
+                .any(|hop| name.eq_ignore_ascii_case(hop))
+        }) {
+            continue;
+        }
+        out.push_str(line);
+        out.push_str("\r\n");
+    }
+    out.push_str("Connection: close\r\n\r\n");
+    out.into_bytes()
+}
+
+/// Format a `Host` header value as a connectable `host:port`, defaulting to port 80.
+pub fn upstream_addr(host: &str) -> String {
+    let (name, port) = split_host_port(host);
+    format!("{name}:{}", port.unwrap_or(80))
+}
+
+/// Destination the VM originally connected to, before iptables redirected it
+/// to the proxy. Returns `None` if the connection wasn't redirected.
+pub fn original_dst(stream: &TcpStream) -> Option<SocketAddr> {
+    let raw = nix::sys::socket::getsockopt(stream, nix::sys::socket::sockopt::OriginalDst).ok()?;
+    let addr = SocketAddr::V4(SocketAddrV4::new(
+        Ipv4Addr::from(u32::from_be(raw.sin_addr.s_addr)),
+        u16::from_be(raw.sin_port),
+    ));
+    // Connections made straight to the listener report the listener itself
+    (stream.local_addr().ok() != Some(addr)).then_some(addr)
+}
+
+fn find_head_end(buf: &[u8]) -> Option<usize> {
+    buf.windows(4).position(|w| w == b"\r\n\r\n").map(|i| i + 4)
+}
+
+fn parse_request_head(head: &[u8]) -> Option<RequestHead> {
+    let text = std::str::from_utf8(head).ok()?;
+    let mut lines = text.split("\r\n");
+
+    let mut request_line = lines.next()?.split(' ');
+    let method = request_line.next()?.to_string();
+    let path = request_line.next()?.to_string();
+    if !request_line.next()?.starts_with("HTTP/1.") {
+        return None;
+    }
+
+    let mut headers = HashMap::new();
+    for line in lines.filter(|l| !l.is_empty()) {
+        let (name, value) = line.split_once(':')?;
+        headers.insert(name.trim().to_ascii_lowercase(), value.trim().to_string());
+    }
+    let host = headers.get("host")?.clone();
+
+    Some(RequestHead {
+        method,
+        path,
+        host,
+        headers,
+        len: head.len(),
+    })
+}
+
+fn split_host_port(host: &str) -> (&str, Option<u16>) {
+    match host.rsplit_once(':') {
+        Some((name, port)) if !name.contains(':') => (name, port.parse().ok()),
+        _ => (host, None),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_host_list() {
+        let hosts = HostList::parse(" Example.com, ,downloads.example.org,10.0.0.5 ");
+        assert!(hosts.contains("example.com"));
+        assert!(hosts.contains("EXAMPLE.COM:8080"));
+        assert!(hosts.contains("downloads.example.org"));
+        assert!(!hosts.contains("api.example.com"));
+        assert!(hosts.contains_ip("10.0.0.5".parse().unwrap()));
+        assert!(!hosts.contains_ip("10.0.0.6".parse().unwrap()));
+        assert!(HostList::parse("").is_empty());
+    }
+
+    #[test]
+    fn test_parse_request_head() {
+        let raw = b"GET /file.tar.gz HTTP/1.1\r\nHost: example.com\r\nUser-Agent: curl\r\n\r\n";
+        let end = find_head_end(raw).unwrap();
+        let head = parse_request_head(&raw[..end]).unwrap();
+        assert_eq!(head.method, "GET");
+        assert_eq!(head.path, "/file.tar.gz");
+        assert_eq!(head.host, "example.com");
+        assert_eq!(head.headers.get("user-agent").unwrap(), "curl");
+        assert_eq!(head.len, raw.len());
+    }
+
+    #[test]
+    fn test_with_connection_close() {
+        let raw = b"GET / HTTP/1.1\r\nHost: example.com\r\nConnection: keep-alive\r\nKeep-Alive: timeout=5\r\nAccept: */*\r\n\r\n";
+        assert_eq!(
+            with_connection_close(raw),
+            b"GET / HTTP/1.1\r\nHost: example.com\r\nAccept: */*\r\nConnection: close\r\n\r\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_request_head_rejects_invalid() {
+        assert!(find_head_end(b"GET / HTTP/1.1\r\nHost: example.com\r\n").is_none());
+        assert!(parse_request_head(b"GET / HTTP/1.1\r\n\r\n").is_none());
+        assert!(parse_request_head(b"\x16\x03\x01\x02\x00\r\n\r\n").is_none());
+    }
+
+    #[test]
+    fn test_looks_like_http() {
+        assert_eq!(looks_like_http(b"GET / HTTP/1.1\r\n"), Some(true));
+        assert_eq!(looks_like_http(b"M-SEARCH * HTTP/1.1"), Some(true));
+        assert_eq!(looks_like_http(b"GE"), None);
+        assert_eq!(looks_like_http(b"\x16\x03\x01\x02\x00"), Some(false));
+        assert_eq!(looks_like_http(b"SSH-2.0-OpenSSH_9.6\r\n"), Some(false));
+        assert_eq!(looks_like_http(b" GET"), Some(false));
+    }
+
+    #[tokio::test]
+    async fn test_sniff_http() {
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+
+        for (payload, expected) in [
+            (&b"GET / HTTP/1.1\r\n"[..], Sniffed::Http),
+            (
+                &b"\x16\x03\x01"[..],
+                Sniffed::NotHttp(b"\x16\x03\x01".to_vec()),
+            ),
+        ] {
+            let mut client = TcpStream::connect(addr).await.unwrap();
+            let (server, _) = listener.accept().await.unwrap();
+            client.write_all(payload).await.unwrap();
+            assert_eq!(sniff_http(&server).await, expected);
+        }
+    }
+
+    #[test]
+    fn test_upstream_addr() {
+        assert_eq!(upstream_addr("example.com"), "example.com:80");
+        assert_eq!(upstream_addr("example.com:8080"), "example.com:8080");
+    }
+
+    #[test]
+    fn test_split_host_port() {
+        assert_eq!(split_host_port("example.com"), ("example.com", None));
+        assert_eq!(
+            split_host_port("example.com:8080"),
+            ("example.com", Some(8080))
+        );
+    }
+}
@@ -0,0 +1,281 @@
+use super::splice::{self, HostList};
+use hyper_util::client::legacy::connect::dns::Name;
+use std::future::Future;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll};
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// Environment variable listing internal hosts or IPs VMs may still reach
+pub const SSRF_ALLOW_ENV: &str = "CLAWPOT_SSRF_ALLOW";
+
+/// Blocks proxied requests whose upstream resolves to a private, loopback or
+/// link-local address, e.g. cloud metadata at 169.254.169.254 or services
+/// listening on the host itself.
+///
+/// Hosts and IPs listed in `CLAWPOT_SSRF_ALLOW` are exempt.
+#[derive(Default)]
+pub struct SsrfGuard {
+    allow: HostList,
+}
+
+impl SsrfGuard {
+    pub fn new(allow: HostList) -> Self {
+        Self { allow }
+    }
+
+    #[cfg(test)]
+    pub fn allowing(hosts: &str) -> Self {
+        Self {
+            allow: HostList::parse(hosts),
+        }
+    }
+
+    /// Resolve `host` (a `Host` header value, with or without a port) and
+    /// return the internal address it points at, if any. Hosts that fail to
+    /// resolve are not blocked here; forwarding them fails anyway.
+    pub async fn check(&self, host: &str) -> Option<IpAddr> {
+        if self.allow.contains(host) {
+            return None;
+        }
+        match self.resolve(host).await {
+            Ok(_) => None,
+            Err(e) => e.downcast_ref::<SsrfBlocked>().map(|blocked| blocked.ip),
+        }
+    }
+
+    /// Resolve `host` to the addresses to connect to, failing with
+    /// [`SsrfBlocked`] if any of them is internal. Connecting to exactly
+    /// these addresses pins the upstream, so a DNS answer that changes after
+    /// the check (DNS rebinding) can't redirect the connection.
+    pub async fn resolve(&self, host: &str) -> Result<Vec<SocketAddr>, BoxError> {
+        let addrs: Vec<SocketAddr> = tokio::net::lookup_host(splice::upstream_addr(host))
+            .await?
+            .collect();
+        if self.allow.contains(host) {
+            return Ok(addrs);
+        }
+        if let Some(ip) = addrs
+            .iter()
+            .map(SocketAddr::ip)
+            .find(|&ip| self.blocks_ip(ip))
+        {
+            return Err(Box::new(SsrfBlocked {
+                host: host.to_string(),
+                ip,
+            }));
+        }
+        Ok(addrs)
+    }
+
+    /// Whether a connection straight to `ip`, with no name to check, should
+    /// be blocked. Used for spliced connections to the guest's original
+    /// destination.
+    pub fn blocks_ip(&self, ip: IpAddr) -> bool {
+        is_internal(ip) && !self.allow.contains_ip(ip)
+    }
+}
+
+/// An upstream that resolved to an internal address
+#[derive(Debug)]
+pub struct SsrfBlocked {
+    pub host: String,
+    pub ip: IpAddr,
+}
+
+impl std::fmt::Display for SsrfBlocked {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} resolves to internal address {}", self.host, self.ip)
+    }
+}
+
+impl std::error::Error for SsrfBlocked {}
+
+/// The internal address behind `err`, if it (or anything in its source
+/// chain) is an [`SsrfBlocked`] from the upstream connector.
+pub fn blocked_ip(err: &anyhow::Error) -> Option<IpAddr> {
+    err.chain()
+        .find_map(|cause| cause.downcast_ref::<SsrfBlocked>())
+        .map(|blocked| blocked.ip)
+}
+
+/// DNS resolver for the upstream HTTP connector that applies the SSRF
+/// checks to the addresses actually connected to, not just to an earlier
+/// lookup of the same name.
+#[derive(Clone)]
+pub struct SsrfResolver {
+    guard: Arc<SsrfGuard>,
+}
+
+impl SsrfResolver {
+    pub fn new(guard: Arc<SsrfGuard>) -> Self {
+        Self { guard }
+    }
+}
+
+impl tower::Service<Name> for SsrfResolver {
+    type Response = std::vec::IntoIter<SocketAddr>;
+    type Error = BoxError;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(&mut self, name: Name) -> Self::Future {
+        let guard = self.guard.clone();
+        // The connector applies the URI's port, so the default used here doesn't matter
+        Box::pin(async move { Ok(guard.resolve(name.as_str()).await?.into_iter()) })
+    }
+}
+
+/// Whether `ip` is in a private, loopback, link-local or otherwise
+/// non-public range.
+pub fn is_internal(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(v4) => is_internal_v4(v4),
+        IpAddr::V6(v6) => match v6.to_ipv4_mapped().or_else(|| embedded_v4(v6)) {
+            Some(v4) => is_internal_v4(v4),
+            None => is_internal_v6(v6),
+        },
+    }
+}
+
+/// The IPv4 address carried by a NAT64 (`64:ff9b::/96`) or 6to4
+/// (`2002::/16`) address, which reaches that IPv4 host.
+fn embedded_v4(ip: Ipv6Addr) -> Option<Ipv4Addr> {
+    let segments = ip.segments();
+    let octets = ip.octets();
+    if segments[..6] == [0x64, 0xff9b, 0, 0, 0, 0] {
+        return Some(Ipv4Addr::new(
+            octets[12], octets[13], octets[14], octets[15],
+        ));
+    }
+    if segments[0] == 0x2002 {
+        return Some(Ipv4Addr::new(octets[2], octets[3], octets[4], octets[5]));
+    }
+    None
+}
+
+fn is_internal_v4(ip: Ipv4Addr) -> bool {
+    let [first, second, ..] = ip.octets();
+    // 100.64.0.0/10 (carrier-grade NAT) is commonly used for internal networks
+    let shared = first == 100 && (second & 0xc0) == 64;
+    // 198.18.0.0/15 is reserved for benchmarking
+    let benchmarking = first == 198 && (second & 0xfe) == 18;
+    // 240.0.0.0/4 is reserved, and includes the broadcast address
+    let reserved = first >= 240;
+    ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_multicast()
+        || shared
+        || benchmarking
+        || reserved
+}
+
+fn is_internal_v6(ip: Ipv6Addr) -> bool {
+    let first = ip.segments()[0];
+    ip.is_loopback()
+        || ip.is_unspecified()
+        // ff00::/8
+        || ip.is_multicast()
+        // fc00::/7 unique local
+        || (first & 0xfe00) == 0xfc00
+        // fe80::/10 link-local
+        || (first & 0xffc0) == 0xfe80
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_is_internal() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.100.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "224.0.0.251",
+            "240.0.0.1",
+            "255.255.255.255",
+            "198.18.0.1",
+            "198.19.255.255",
+            "::1",
+            "fd00::1",
+            "fe80::1",
+            "ff02::1",
+            "::ffff:127.0.0.1",
+            // NAT64 and 6to4 addresses of 169.254.169.254 and 10.0.0.1
+            "64:ff9b::a9fe:a9fe",
+            "2002:a00:1::1",
+        ] {
+            assert!(is_internal(ip.parse().unwrap()), "{ip} should be internal");
+        }
+        for ip in [
+            "93.184.216.34",
+            "100.128.0.1",
+            "198.20.0.1",
+            "2606:4700::1111",
+            "64:ff9b::5db8:d822",
+            "2002:5db8:d822::1",
+        ] {
+            assert!(!is_internal(ip.parse().unwrap()), "{ip} should be public");
+        }
+    }
+
+    #[tokio::test]
+    async fn test_check() {
+        let guard = SsrfGuard::default();
+        assert_eq!(
+            guard.check("169.254.169.254").await,
+            Some("169.254.169.254".parse().unwrap())
+        );
+        assert_eq!(
+            guard.check("127.0.0.1:8080").await,
+            Some("127.0.0.1".parse().unwrap())
+        );
+        assert_eq!(guard.check("93.184.216.34").await, None);
+
+        let guard = SsrfGuard::allowing("127.0.0.1, internal.example");
+        assert_eq!(guard.check("127.0.0.1:8080").await, None);
+        assert_eq!(guard.check("internal.example").await, None);
+    }
+
+    #[test]
+    fn test_blocks_ip() {
+        let guard = SsrfGuard::default();
+        assert!(guard.blocks_ip("169.254.169.254".parse().unwrap()));
+        assert!(guard.blocks_ip("10.0.0.5".parse().unwrap()));
+        assert!(!guard.blocks_ip("93.184.216.34".parse().unwrap()));
+
+        let guard = SsrfGuard::allowing("10.0.0.5");
+        assert!(!guard.blocks_ip("10.0.0.5".parse().unwrap()));
+        assert!(guard.blocks_ip("10.0.0.6".parse().unwrap()));
+    }
+
+    #[tokio::test]
+    async fn test_resolver_blocks_internal_names() {
+        let name: Name = "localhost".parse().unwrap();
+        let mut resolver = SsrfResolver::new(Arc::new(SsrfGuard::default()));
+        let err = tower::Service::call(&mut resolver, name.clone())
+            .await
+            .unwrap_err();
+        let err = anyhow::anyhow!(err).context("Upstream request failed");
+        assert!(blocked_ip(&err).is_some_and(|ip| ip.is_loopback()));
+
+        let mut resolver = SsrfResolver::new(Arc::new(SsrfGuard::allowing("localhost")));
+        let addrs: Vec<_> = tower::Service::call(&mut resolver, name)
+            .await
+            .unwrap()
+            .collect();
+        assert!(addrs.iter().all(|addr| addr.ip().is_loopback()));
+    }
+}
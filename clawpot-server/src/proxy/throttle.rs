@@ -0,0 +1,212 @@
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// Window within which repeats of the same warning are collapsed
+const WINDOW: Duration = Duration::from_secs(10);
+
+/// Minimum time between sweeps of closed windows
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Collapses repeated warnings so a systemic failure (e.g. the auth service
+/// being down) produces one line per window instead of one per request.
+///
+/// Warnings are throttled per key and source (the VM, peer or upstream
+/// they concern), so one noisy VM doesn't hide another's warnings. The
+/// first occurrence is logged immediately and later ones within the window
+/// are counted. The count is logged with the next warning after the window
+/// closes, e.g. `Auth service call failed (denying) (auth service): 427
+/// similar warnings suppressed in the last 10s`.
+pub struct WarnThrottle {
+    window: Duration,
+    keys: Mutex<HashMap<(&'static str, String), Window>>,
+    /// When closed windows were last swept
+    swept: Mutex<Instant>,
+}
+
+struct Window {
+    started: Instant,
+    suppressed: u64,
+}
+
+impl WarnThrottle {
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window,
+            keys: Mutex::new(HashMap::new()),
+            swept: Mutex::new(Instant::now()),
+        }
+    }
+
+    /// Log `{key}: {detail}` unless `key` was already logged for `source`
+    /// within the window.
+    ///
+    /// Any windows that have closed since the last sweep are reported
+    /// first, so sources that went quiet don't keep their entry forever.
+    pub fn warn(&self, key: &'static str, source: impl Display, detail: impl Display) {
+        let now = Instant::now();
+        for (closed_key, closed_source, suppressed) in self.sweep(now) {
+            self.report(closed_key, &closed_source, suppressed);
+        }
+        let source = source.to_string();
+        if let Some(suppressed) = self.record(key, &source, now) {
+            self.report(key, &source, suppressed);
+            warn!("{}: {}", key, detail);
+        }
+    }
+
+    /// [`Self::close_windows`], at most once per [`SWEEP_INTERVAL`] so a
+    /// burst of warnings doesn't scan every entry each time.
+    fn sweep(&self, now: Instant) -> Vec<(&'static str, String, u64)> {
+        {
+            let mut swept = self.swept.lock().unwrap();
+            if now.duration_since(*swept) < SWEEP_INTERVAL {
+                return Vec::new();
+            }
+            *swept = now;
+        }
+        self.close_windows(now)
+    }
+
+    fn report(&self, key: &str, source: &str, suppressed: u64) {
+        if suppressed > 0 {
+            warn!(
+                "{} ({}): {} similar warnings suppressed in the last {}s",
+                key,
+                source,
+                suppressed,
+                self.window.as_secs()
+            );
+        }
+    }
+
+    /// Count an occurrence of `key` from `source` at `now`. Returns `None`
+    /// if it should be suppressed. Otherwise it starts a new window and
+    /// the count suppressed in the previous one (0 if there was none or it
+    /// was already reported) is returned.
+    fn record(&self, key: &'static str, source: &str, now: Instant) -> Option<u64> {
+        let mut keys = self.keys.lock().unwrap();
+        match keys.entry((key, source.to_string())) {
+            Entry::Occupied(mut entry) => {
+                let window = entry.get_mut();
+                if now.duration_since(window.started) < self.window {
+                    window.suppressed += 1;
+                    return None;
+                }
+                let suppressed = window.suppressed;
+                *window = Window {
+                    started: now,
+                    suppressed: 0,
+                };
+                Some(suppressed)
+            }
+            Entry::Vacant(entry) => {
+                entry.insert(Window {
+                    started: now,
+                    suppressed: 0,
+                });
+                Some(0)
+            }
+        }
+    }
+
+    /// Forget the windows that have closed by `now`, returning the key,
+    /// source and suppressed count of those that suppressed anything.
+    fn close_windows(&self, now: Instant) -> Vec<(&'static str, String, u64)> {
+        let mut closed = Vec::new();
+        self.keys.lock().unwrap().retain(|(key, source), window| {
+            if now.duration_since(window.started) < self.window {
+                return true;
+            }
+            if window.suppressed > 0 {
+                closed.push((*key, source.clone(), window.suppressed));
+            }
+            false
+        });
+        closed
+    }
+}
+
+/// Log a warning through the shared proxy throttle. `source` is what the
+/// warning concerns (a VM, peer address or upstream); each is throttled
+/// separately.
+pub fn warn_throttled(key: &'static str, source: impl Display, detail: impl Display) {
+    static THROTTLE: OnceLock<WarnThrottle> = OnceLock::new();
+    THROTTLE
+        .get_or_init(|| WarnThrottle::new(WINDOW))
+        .warn(key, source, detail);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_repeats_are_collapsed() {
+        let throttle = WarnThrottle::new(Duration::from_secs(10));
+        let start = Instant::now();
+
+        assert_eq!(throttle.record("a", "vm-1", start), Some(0));
+        for i in 1..=427 {
+            assert_eq!(
+                throttle.record("a", "vm-1", start + Duration::from_millis(i)),
+                None
+            );
+        }
+        // Other sources and keys are throttled independently
+        let second = start + Duration::from_secs(1);
+        assert_eq!(throttle.record("a", "vm-2", second), Some(0));
+        assert_eq!(throttle.record("b", "vm-1", second), Some(0));
+        assert_eq!(throttle.record("b", "vm-1", second), None);
+
+        // Closed windows are reported once, and only if they suppressed anything
+        assert!(throttle
+            .close_windows(start + Duration::from_secs(5))
+            .is_empty());
+        assert_eq!(
+            throttle.close_windows(start + Duration::from_secs(10)),
+            vec![("a", "vm-1".to_string(), 427)]
+        );
+        assert_eq!(
+            throttle.close_windows(second + Duration::from_secs(10)),
+            vec![("b", "vm-1".to_string(), 1)]
+        );
+
+        // A warning after its window closed starts a new one
+        let later = start + Duration::from_secs(30);
+        assert_eq!(throttle.record("a", "vm-1", later), Some(0));
+        assert_eq!(
+            throttle.record("a", "vm-1", later + Duration::from_secs(1)),
+            None
+        );
+        // A window that closed without being swept reports with the next warning
+        assert_eq!(
+            throttle.record("a", "vm-1", later + Duration::from_secs(10)),
+            Some(1)
+        );
+    }
+
+    #[test]
+    fn test_sweeps_are_rate_limited() {
+        let throttle = WarnThrottle::new(Duration::from_secs(10));
+        let start = *throttle.swept.lock().unwrap();
+
+        let second = start + Duration::from_millis(500);
+        for _ in 0..2 {
+            throttle.record("a", "vm-1", start);
+            throttle.record("a", "vm-2", second);
+        }
+
+        let closed = start + Duration::from_secs(10);
+        assert_eq!(throttle.sweep(closed), vec![("a", "vm-1".to_string(), 1)]);
+        // vm-2's window has closed too, but the last sweep was too recent
+        assert!(throttle.sweep(second + Duration::from_secs(10)).is_empty());
+        assert_eq!(
+            throttle.sweep(closed + SWEEP_INTERVAL),
+            vec![("a", "vm-2".to_string(), 1)]
+        );
+    }
+}
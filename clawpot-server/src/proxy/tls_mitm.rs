@@ -6,11 +6,12 @@ use std::sync::Arc;
 use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
 use tokio::net::{TcpListener, TcpStream};
 use tokio_rustls::TlsAcceptor;
-use tracing::{error, info, warn};
+use tracing::{error, info};
 
 use super::ca::CertificateAuthority;
+use super::throttle::warn_throttled;
 
-const MITM_LISTEN_ADDR: &str = "0.0.0.0:10443";
+pub const MITM_LISTEN_ADDR: &str = "0.0.0.0:10443";
 const HTTP_PROXY_TLS_ADDR: &str = "127.0.0.1:10081";
 
 /// Start the TLS MITM proxy. Runs until the cancellation token is triggered.
@@ -46,7 +47,7 @@ async fn run_inner(
                 let ca = ca.clone();
                 tokio::spawn(async move {
                     if let Err(e) = handle_connection(stream, addr, ca).await {
-                        warn!("MITM connection from {} failed: {:#}", addr, e);
+                        warn_throttled("MITM connection failed", addr.ip(), format!("from {addr}: {e:#}"));
                     }
                 });
             }
@@ -84,7 +85,7 @@ async fn handle_connection(
         .with_context(|| format!("Failed to generate cert for {sni}"))?;
 
     // Build rustls server config with the leaf cert
-    let tls_config = build_server_config(&leaf.cert_pem, &leaf.key_pem, ca.ca_cert_pem())
+    let tls_config = build_server_config(&leaf.cert_pem, &leaf.key_pem, &ca.chain_pem())
         .with_context(|| format!("Failed to build TLS config for {sni}"))?;
 
     let acceptor = TlsAcceptor::from(Arc::new(tls_config));
@@ -130,7 +131,7 @@ fn build_server_config(cert_pem: &str, key_pem: &str, ca_pem: &str) -> Result<Se
         .collect::<std::result::Result<Vec<_>, _>>()
         .context("Failed to parse leaf cert PEM")?;
 
-    // Append CA cert so the chain is complete
+    // Append CA certs so the chain is complete
     let mut ca_reader = Cursor::new(ca_pem);
     let ca_certs: Vec<_> = rustls_pemfile::certs(&mut ca_reader)
         .collect::<std::result::Result<Vec<_>, _>>()
@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+/// Environment variable setting how many LLM tokens each VM may spend
+pub const TOKEN_BUDGET_ENV: &str = "CLAWPOT_VM_TOKEN_BUDGET";
+
+/// Tokens held back for each LLM request while it is in flight, and charged
+/// for a successful response whose token usage can't be read
+pub const REQUEST_RESERVATION_TOKENS: u64 = 4096;
+
+/// Per-VM cap on LLM tokens, charged with the input and output tokens of
+/// each LLM response.
+///
+/// A VM starts with the full budget on its first LLM request. Each request
+/// reserves [`REQUEST_RESERVATION_TOKENS`] (or whatever is left) up front,
+/// so concurrent requests can't all start on the same remaining budget, and
+/// settles to its actual usage once the response is in. Once a VM has none
+/// left, further LLM requests are rejected; the response that crosses the
+/// limit is still delivered, since its size isn't known in advance.
+#[derive(Clone, Default)]
+pub struct TokenBudget {
+    /// Tokens each VM starts with, or `None` for no cap
+    limit: Option<u64>,
+    /// Tokens left, keyed by vm_id, for VMs that have been charged
+    remaining: Arc<Mutex<HashMap<String, u64>>>,
+}
+
+impl TokenBudget {
+    pub fn new(limit: Option<u64>) -> Self {
+        Self {
+            limit,
+            remaining: Arc::default(),
+        }
+    }
+
+    /// Tokens `vm_id` has left, or `None` if budgets are off.
+    pub fn remaining(&self, vm_id: &str) -> Option<u64> {
+        let limit = self.limit?;
+        let remaining = self.remaining.lock().unwrap();
+        Some(remaining.get(vm_id).copied().unwrap_or(limit))
+    }
+
+    /// Reserve tokens for an LLM request from `vm_id`, or `None` if it has
+    /// spent its whole budget.
+    pub fn reserve(&self, vm_id: &str) -> Option<Reservation> {
+        let held = match self.limit {
+            Some(limit) => {
+                let mut remaining = self.remaining.lock().unwrap();
+                let left = remaining.entry(vm_id.to_string()).or_insert(limit);
+                if *left == 0 {
+                    return None;
+                }
+                let held = REQUEST_RESERVATION_TOKENS.min(*left);
+                *left -= held;
+                held
+            }
+            None => 0,
+        };
+        Some(Reservation {
+            budget: self.clone(),
+            vm_id: vm_id.to_string(),
+            held,
+            settled: AtomicBool::new(false),
+        })
+    }
+
+    /// Forget `vm_id`'s spending, once the VM is deleted.
+    pub fn forget(&self, vm_id: &str) {
+        self.remaining.lock().unwrap().remove(vm_id);
+    }
+
+    /// Return `held` reserved tokens to `vm_id`, charge it `used` instead
+    /// and return what it has left. A forgotten VM isn't charged.
+    fn settle(&self, vm_id: &str, held: u64, used: u64) -> Option<u64> {
+        let limit = self.limit?;
+        let mut remaining = self.remaining.lock().unwrap();
+        let Some(left) = remaining.get_mut(vm_id) else {
+            return Some(limit);
+        };
+        *left = (*left + held).saturating_sub(used);
+        Some(*left)
+    }
+}
+
+/// Tokens reserved for one in-flight LLM request by [`TokenBudget::reserve`].
+/// Dropping it without [`settle`](Self::settle) (e.g. when the upstream
+/// can't be reached) gives the tokens back.
+pub struct Reservation {
+    budget: TokenBudget,
+    vm_id: String,
+    held: u64,
+    settled: AtomicBool,
+}
+
+impl Reservation {
+    /// Charge the request's actual usage, [`REQUEST_RESERVATION_TOKENS`] if
+    /// it is unknown, and return what the VM has left, or `None` if budgets
+    /// are off. Only the first call charges anything.
+    pub fn settle(&self, used: Option<u64>) -> Option<u64> {
+        if self.settled.swap(true, Ordering::SeqCst) {
+            return self.budget.remaining(&self.vm_id);
+        }
+        let used = used.unwrap_or(REQUEST_RESERVATION_TOKENS);
+        self.budget.settle(&self.vm_id, self.held, used)
+    }
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if !self.settled.load(Ordering::SeqCst) {
+            self.budget.settle(&self.vm_id, self.held, 0);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_settle() {
+        let budget = TokenBudget::new(Some(1000));
+        assert_eq!(budget.remaining("vm-a"), Some(1000));
+        assert_eq!(budget.reserve("vm-a").unwrap().settle(Some(600)), Some(400));
+        assert!(budget.reserve("vm-a").is_some());
+
+        // Overspending stops at zero, and other VMs keep their own budget
+        assert_eq!(budget.reserve("vm-a").unwrap().settle(Some(500)), Some(0));
+        assert!(budget.reserve("vm-a").is_none());
+        assert_eq!(budget.remaining("vm-b"), Some(1000));
+
+        // Clones share the same accounting
+        assert!(budget.clone().reserve("vm-a").is_none());
+    }
+
+    #[test]
+    fn test_no_limit() {
+        let budget = TokenBudget::default();
+        assert_eq!(budget.remaining("vm-a"), None);
+        let reservation = budget.reserve("vm-a").unwrap();
+        assert_eq!(reservation.settle(Some(u64::MAX)), None);
+    }
+
+    #[test]
+    fn test_reservations() {
+        let budget = TokenBudget::new(Some(REQUEST_RESERVATION_TOKENS + 100));
+
+        // In-flight requests hold their reservation, so the next one only
+        // gets what is left and the one after is refused
+        let first = budget.reserve("vm-a").unwrap();
+        let second = budget.reserve("vm-a").unwrap();
+        assert_eq!(budget.remaining("vm-a"), Some(0));
+        assert!(budget.reserve("vm-a").is_none());
+
+        // Settling charges the actual usage; settling twice charges once
+        assert_eq!(
+            first.settle(Some(10)),
+            Some(REQUEST_RESERVATION_TOKENS - 10)
+        );
+        assert_eq!(
+            first.settle(Some(10)),
+            Some(REQUEST_RESERVATION_TOKENS - 10)
+        );
+
+        // A dropped reservation is returned in full
+        drop(second);
+        assert_eq!(
+            budget.remaining("vm-a"),
+            Some(REQUEST_RESERVATION_TOKENS + 90)
+        );
+
+        // Unknown usage is charged the reservation
+        let third = budget.reserve("vm-a").unwrap();
+        assert_eq!(third.settle(None), Some(90));
+    }
+
+    #[test]
+    fn test_forget() {
+        let budget = TokenBudget::new(Some(1000));
+        let reservation = budget.reserve("vm-a").unwrap();
+        budget.forget("vm-a");
+        assert_eq!(budget.remaining("vm-a"), Some(1000));
+
+        // A request still in flight doesn't bring the entry back
+        assert_eq!(reservation.settle(Some(500)), Some(1000));
+        assert_eq!(budget.remaining("vm-a"), Some(1000));
+    }
+}
@@ -0,0 +1,61 @@
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::path::Path;
+use std::time::Duration;
+use tracing::{info, warn};
+
+/// A server component that must be up before the server is ready.
+#[derive(Debug, Serialize)]
+pub struct Component {
+    pub name: &'static str,
+    pub addrs: Vec<String>,
+    pub startup_ms: u64,
+}
+
+impl Component {
+    pub fn new(name: &'static str, addrs: &[&str], startup: Duration) -> Self {
+        Self {
+            name,
+            addrs: addrs.iter().map(ToString::to_string).collect(),
+            startup_ms: startup.as_millis() as u64,
+        }
+    }
+}
+
+/// Consolidated readiness report, emitted as the `server.ready` event and
+/// optionally written to the file named by `CLAWPOT_READY_FILE`.
+#[derive(Debug, Serialize)]
+pub struct ReadyReport {
+    pub session_id: String,
+    pub total_startup_ms: u64,
+    pub components: Vec<Component>,
+}
+
+impl ReadyReport {
+    /// Write the report to `path` (`CLAWPOT_READY_FILE`).
+    ///
+    /// The file is written to a temporary path and renamed into place, so
+    /// anything polling for it never sees a partial report.
+    pub fn write_ready_file(&self, path: &Path) -> Result<()> {
+        if let Some(parent) = path.parent() {
+            std::fs::create_dir_all(parent)
+                .with_context(|| format!("Failed to create directory: {}", parent.display()))?;
+        }
+        let tmp = path.with_extension("tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(self)?)
+            .with_context(|| format!("Failed to write {}", tmp.display()))?;
+        std::fs::rename(&tmp, path)
+            .with_context(|| format!("Failed to write readiness file {}", path.display()))?;
+        info!("Wrote readiness file {}", path.display());
+        Ok(())
+    }
+}
+
+/// Remove the readiness file on shutdown so stale files don't signal a dead server.
+pub fn remove_ready_file(path: &Path) {
+    if let Err(e) = std::fs::remove_file(path) {
+        if e.kind() != std::io::ErrorKind::NotFound {
+            warn!("Failed to remove readiness file {}: {}", path.display(), e);
+        }
+    }
+}
@@ -0,0 +1,43 @@
+use anyhow::{Context, Result};
+
+/// Environment variable that turns off refusing VMs the host has no memory for
+pub const MEMORY_CHECK_ENV: &str = "CLAWPOT_HOST_MEMORY_CHECK";
+
+/// Environment variable setting how much memory, in MiB, must stay
+/// available on the host after a new VM's memory is accounted for
+pub const MEMORY_MARGIN_ENV: &str = "CLAWPOT_HOST_MEMORY_MARGIN_MIB";
+
+/// Margin used when `CLAWPOT_HOST_MEMORY_MARGIN_MIB` is unset
+pub const DEFAULT_MEMORY_MARGIN_MIB: u64 = 512;
+
+/// Memory the host can give to new processes without swapping, in MiB
+/// (`MemAvailable` in `/proc/meminfo`).
+pub fn available_memory_mib() -> Result<u64> {
+    let meminfo =
+        std::fs::read_to_string("/proc/meminfo").context("Failed to read /proc/meminfo")?;
+    parse_mem_available(&meminfo).context("No MemAvailable in /proc/meminfo")
+}
+
+fn parse_mem_available(meminfo: &str) -> Option<u64> {
+    let line = meminfo
+        .lines()
+        .find_map(|line| line.strip_prefix("MemAvailable:"))?;
+    let kib: u64 = line.trim().trim_end_matches("kB").trim().parse().ok()?;
+    Some(kib / 1024)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_mem_available() {
+        let meminfo = "MemTotal:       16318412 kB\n\
+                       MemFree:         1203380 kB\n\
+                       MemAvailable:    9437184 kB\n\
+                       Buffers:          402584 kB\n";
+        assert_eq!(parse_mem_available(meminfo), Some(9216));
+        assert_eq!(parse_mem_available("MemTotal: 1024 kB\n"), None);
+        assert_eq!(parse_mem_available("MemAvailable: lots\n"), None);
+    }
+}
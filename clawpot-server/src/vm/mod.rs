@@ -1,3 +1,6 @@
+pub mod host;
+pub mod pool;
 pub mod registry;
 
+pub use pool::VmPool;
 pub use registry::{VmEntry, VmRegistry};
@@ -0,0 +1,286 @@
+use super::registry::VmId;
+use std::collections::{HashSet, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// Environment variable setting how many idle VMs are kept booted
+pub const POOL_SIZE_ENV: &str = "CLAWPOT_VM_POOL_SIZE";
+
+/// Label carried by idle pooled VMs; replaced by the caller's labels when
+/// one is handed out
+pub const POOL_LABEL: &str = "clawpot.pool";
+
+/// How long to wait before booting another pooled VM after one failed
+pub const POOL_RETRY_DELAY: Duration = Duration::from_secs(10);
+
+/// Environment variable naming a shell script run in a pooled VM (via the
+/// agent) to clean it up before it goes back into the pool.
+///
+/// The cleanup is best-effort: the guest's root filesystem and kernel state
+/// are not restored, so anything the script doesn't undo is seen by the next
+/// tenant. Don't recycle VMs between tenants that must be isolated.
+pub const RESET_SCRIPT_ENV: &str = "CLAWPOT_VM_RESET_SCRIPT";
+
+/// How long the reset script may run before the VM is discarded instead
+pub const RESET_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Reset script used without `CLAWPOT_VM_RESET_SCRIPT`. Commands run by the
+/// agent share its cgroup, so everything in it except the agent and this
+/// script was left behind by earlier commands. Besides those processes it
+/// only clears the temporary directories and the neighbour cache; files
+/// written anywhere else, users, mounts and sysctls are left as they are.
+pub const DEFAULT_RESET_SCRIPT: &str = r#"
+cgroup=$(cut -d: -f3 /proc/$$/cgroup)
+for pid in $(cat "/sys/fs/cgroup$cgroup/cgroup.procs"); do
+    [ "$pid" = "$$" ] || [ "$pid" = "$PPID" ] || kill -9 "$pid" 2>/dev/null
+done
+rm -rf /tmp/* /tmp/.[!.]* /var/tmp/*
+ip neigh flush all 2>/dev/null
+exit 0
+"#;
+
+/// Idle, already booted VMs handed out by `CreateVM` with `from_pool`.
+///
+/// The pool only tracks which VMs are idle; they are ordinary registered
+/// VMs, booted and replaced by the service whenever the pool drops below
+/// its target size.
+pub struct VmPool {
+    target: usize,
+    /// Idle VMs, oldest first
+    idle: Mutex<VecDeque<VmId>>,
+    /// VMs handed out that can be reset and returned with `ResetVM`
+    lent: Mutex<HashSet<VmId>>,
+    /// Lent VMs claimed by a `ResetVM` that is still running
+    resetting: Mutex<HashSet<VmId>>,
+    /// Signalled when the pool drops below its target
+    wanted: Notify,
+}
+
+impl VmPool {
+    pub fn new(target: usize) -> Self {
+        Self {
+            target,
+            idle: Mutex::new(VecDeque::new()),
+            lent: Mutex::new(HashSet::new()),
+            resetting: Mutex::new(HashSet::new()),
+            wanted: Notify::new(),
+        }
+    }
+
+    /// Number of idle VMs the pool is kept at (0 disables it)
+    pub fn target(&self) -> usize {
+        self.target
+    }
+
+    pub fn idle_count(&self) -> usize {
+        self.idle.lock().unwrap().len()
+    }
+
+    /// Whether fewer than `target` VMs are idle
+    pub fn needs_vms(&self) -> bool {
+        self.idle_count() < self.target
+    }
+
+    /// Add a freshly booted idle VM.
+    pub fn push(&self, id: VmId) {
+        self.idle.lock().unwrap().push_back(id);
+    }
+
+    /// Take the longest-idle VM, asking for a replacement.
+    pub fn take(&self) -> Option<VmId> {
+        let id = self.idle.lock().unwrap().pop_front()?;
+        self.lent.lock().unwrap().insert(id);
+        self.wanted.notify_one();
+        Some(id)
+    }
+
+    /// Put back a VM taken with [`take`](Self::take) that couldn't be handed out.
+    pub fn put_back(&self, id: VmId) {
+        self.lent.lock().unwrap().remove(&id);
+        self.idle.lock().unwrap().push_front(id);
+    }
+
+    /// Whether `id` is idle in the pool, waiting to be handed out
+    pub fn is_idle(&self, id: &VmId) -> bool {
+        self.idle.lock().unwrap().contains(id)
+    }
+
+    /// Take every idle VM out of the pool, e.g. to delete them when the
+    /// server starts draining. No replacements are asked for.
+    pub fn take_idle(&self) -> Vec<VmId> {
+        self.idle.lock().unwrap().drain(..).collect()
+    }
+
+    /// Ask for the pool to be refilled, e.g. when draining stops.
+    pub fn refill(&self) {
+        self.wanted.notify_one();
+    }
+
+    /// Whether `id` was handed out by the pool and not yet returned
+    #[cfg(test)]
+    pub fn is_lent(&self, id: &VmId) -> bool {
+        self.lent.lock().unwrap().contains(id)
+    }
+
+    /// Claim a lent VM for resetting. Returns `false` if it wasn't lent,
+    /// including when another reset has already claimed it.
+    pub fn begin_reset(&self, id: VmId) -> bool {
+        if !self.lent.lock().unwrap().remove(&id) {
+            return false;
+        }
+        self.resetting.lock().unwrap().insert(id);
+        true
+    }
+
+    /// Return a VM claimed with [`begin_reset`](Self::begin_reset). Returns
+    /// `false`, leaving the VM to be deleted, if it wasn't being reset or
+    /// replacements have already filled the pool.
+    pub fn give_back(&self, id: VmId) -> bool {
+        if !self.resetting.lock().unwrap().remove(&id) {
+            return false;
+        }
+        let mut idle = self.idle.lock().unwrap();
+        if idle.len() >= self.target {
+            return false;
+        }
+        idle.push_back(id);
+        true
+    }
+
+    /// Forget a VM that is being deleted. Returns whether it was idle.
+    pub fn remove(&self, id: &VmId) -> bool {
+        self.lent.lock().unwrap().remove(id);
+        self.resetting.lock().unwrap().remove(id);
+        let mut idle = self.idle.lock().unwrap();
+        let before = idle.len();
+        idle.retain(|pooled| pooled != id);
+        let removed = idle.len() != before;
+        drop(idle);
+        if removed {
+            self.wanted.notify_one();
+        }
+        removed
+    }
+
+    /// Wait until a VM is taken from or removed out of the pool.
+    pub async fn wanted(&self) {
+        self.wanted.notified().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use uuid::Uuid;
+
+    #[tokio::test]
+    async fn test_take_and_replenish() {
+        let pool = VmPool::new(2);
+        assert!(pool.needs_vms());
+        assert_eq!(pool.take(), None);
+
+        let (first, second) = (Uuid::new_v4(), Uuid::new_v4());
+        pool.push(first);
+        pool.push(second);
+        assert!(!pool.needs_vms());
+
+        // Oldest first, and taking one asks for a replacement
+        assert_eq!(pool.take(), Some(first));
+        assert!(pool.needs_vms());
+        tokio::time::timeout(Duration::from_secs(1), pool.wanted())
+            .await
+            .unwrap();
+
+        pool.put_back(first);
+        assert_eq!(pool.take(), Some(first));
+    }
+
+    #[test]
+    fn test_remove() {
+        let pool = VmPool::new(1);
+        let id = Uuid::new_v4();
+        pool.push(id);
+        assert!(pool.remove(&id));
+        assert!(!pool.remove(&id));
+        assert_eq!(pool.idle_count(), 0);
+    }
+
+    #[test]
+    fn test_take_idle() {
+        let pool = VmPool::new(3);
+        let (first, second, lent) = (Uuid::new_v4(), Uuid::new_v4(), Uuid::new_v4());
+        pool.push(lent);
+        assert_eq!(pool.take(), Some(lent));
+        pool.push(first);
+        pool.push(second);
+        assert!(pool.is_idle(&first));
+        assert!(!pool.is_idle(&lent));
+
+        // Lent VMs stay with their tenants
+        assert_eq!(pool.take_idle(), [first, second]);
+        assert!(!pool.is_idle(&first));
+        assert!(pool.is_lent(&lent));
+        assert_eq!(pool.idle_count(), 0);
+    }
+
+    #[test]
+    fn test_give_back() {
+        let pool = VmPool::new(1);
+        let (lent, replacement) = (Uuid::new_v4(), Uuid::new_v4());
+        pool.push(lent);
+        assert_eq!(pool.take(), Some(lent));
+        assert!(pool.is_lent(&lent));
+
+        // Only VMs claimed for a reset can be given back
+        assert!(!pool.give_back(lent));
+        assert!(pool.begin_reset(lent));
+        assert!(!pool.is_lent(&lent));
+
+        // Returned while the pool has room
+        assert!(pool.give_back(lent));
+        assert!(!pool.is_lent(&lent));
+        assert_eq!(pool.take(), Some(lent));
+
+        // Not once a replacement has filled it
+        pool.push(replacement);
+        assert!(pool.begin_reset(lent));
+        assert!(!pool.give_back(lent));
+        assert!(!pool.is_lent(&lent));
+        assert_eq!(pool.idle_count(), 1);
+    }
+
+    #[test]
+    fn test_double_reset() {
+        let pool = VmPool::new(2);
+        let id = Uuid::new_v4();
+        pool.push(id);
+        assert_eq!(pool.take(), Some(id));
+
+        // A second reset can't claim the VM while the first is running
+        assert!(pool.begin_reset(id));
+        assert!(!pool.begin_reset(id));
+        assert!(pool.give_back(id));
+        assert!(!pool.give_back(id));
+        assert_eq!(pool.idle_count(), 1);
+
+        // Nor once it is idle again
+        assert!(!pool.begin_reset(id));
+        assert_eq!(pool.take(), Some(id));
+        assert_eq!(pool.take(), None);
+    }
+
+    #[test]
+    fn test_remove_during_reset() {
+        let pool = VmPool::new(1);
+        let id = Uuid::new_v4();
+        pool.push(id);
+        assert_eq!(pool.take(), Some(id));
+        assert!(pool.begin_reset(id));
+
+        // Deleted while the reset ran, so it can't come back
+        assert!(!pool.remove(&id));
+        assert!(!pool.give_back(id));
+        assert_eq!(pool.idle_count(), 0);
+    }
+}
@@ -1,14 +1,57 @@
 use anyhow::{anyhow, Result};
-use clawpot_common::vm::VmManager;
-use std::collections::HashMap;
+use clawpot_common::firecracker::VmConfig;
+use clawpot_common::vm::{VmManager, VmState};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::future::Future;
 use std::net::IpAddr;
+use std::path::PathBuf;
 use std::sync::Arc;
-use std::time::SystemTime;
-use tokio::sync::RwLock;
+use std::time::{Duration, SystemTime};
+use tokio::sync::{broadcast, RwLock};
 use uuid::Uuid;
 
 pub type VmId = Uuid;
 
+/// How long a Firecracker API call may take before the VM is treated as
+/// unresponsive. Calls are made without holding the registry lock.
+const FIRECRACKER_CALL_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Run a Firecracker API call, giving up after [`FIRECRACKER_CALL_TIMEOUT`]
+async fn firecracker_call<T>(call: impl Future<Output = Result<T>>) -> Result<T> {
+    tokio::time::timeout(FIRECRACKER_CALL_TIMEOUT, call)
+        .await
+        .map_err(|_| {
+            anyhow!(
+                "Firecracker API did not respond within {}s",
+                FIRECRACKER_CALL_TIMEOUT.as_secs()
+            )
+        })?
+}
+
+/// A VM as returned by [`VmRegistry::list`]:
+/// (id, ip, tap_name, vcpus, memory, created_at, state, name, labels)
+pub type VmListing = (
+    VmId,
+    IpAddr,
+    String,
+    u8,
+    u32,
+    SystemTime,
+    VmState,
+    Option<String>,
+    BTreeMap<String, String>,
+);
+
+/// Everything the registry holds about one VM, as returned by [`VmRegistry::details`]
+pub struct VmDetails {
+    pub listing: VmListing,
+    pub vsock_uds_path: String,
+    pub guest_cid: u32,
+    pub balloon: bool,
+    /// `None` for VMs restored from a snapshot
+    pub boot_config: Option<VmConfig>,
+}
+
 /// Entry in the VM registry containing VM metadata and manager
 #[allow(dead_code)]
 pub struct VmEntry {
@@ -20,6 +63,16 @@ pub struct VmEntry {
     pub vcpu_count: u8,
     pub mem_size_mib: u32,
     pub vsock_uds_path: String,
+    pub guest_cid: u32,
+    /// Friendly name given at creation, shown alongside the id in logs
+    pub name: Option<String>,
+    /// Whether the VM has a memory balloon that can be resized
+    pub balloon: bool,
+    /// Key/value labels for selecting groups of VMs, e.g. `tier=frontend`
+    pub labels: BTreeMap<String, String>,
+    /// Configuration the VM was booted with, reused to reboot it. `None` for
+    /// VMs restored from a snapshot
+    pub boot_config: Option<VmConfig>,
 }
 
 /// Thread-safe VM registry for managing multiple VMs
@@ -36,13 +89,18 @@ impl VmRegistry {
     }
 
     /// Insert a new VM into the registry
-    /// Returns error if VM ID already exists
+    /// Returns error if VM ID or name already exists
     pub async fn insert(&self, id: VmId, entry: VmEntry) -> Result<()> {
         let mut vms = self.vms.write().await;
 
         if vms.contains_key(&id) {
             return Err(anyhow!("VM with ID {id} already exists"));
         }
+        if let Some(name) = &entry.name {
+            if vms.values().any(|e| e.name.as_ref() == Some(name)) {
+                return Err(anyhow!("VM with name {name} already exists"));
+            }
+        }
 
         vms.insert(id, entry);
         Ok(())
@@ -72,24 +130,37 @@ impl VmRegistry {
     }
 
     /// List all VM IDs and their metadata
-    /// Returns a vector of tuples (id, ip, tap_name, vcpus, memory, created_at)
-    pub async fn list(&self) -> Vec<(VmId, IpAddr, String, u8, u32, SystemTime)> {
+    pub async fn list(&self) -> Vec<VmListing> {
+        self.list_filtered(&BTreeMap::new()).await
+    }
+
+    /// List the VMs carrying every label in `selector`
+    pub async fn list_filtered(&self, selector: &BTreeMap<String, String>) -> Vec<VmListing> {
         let vms = self.vms.read().await;
 
         vms.iter()
-            .map(|(id, entry)| {
-                (
-                    *id,
-                    entry.ip_address,
-                    entry.tap_name.clone(),
-                    entry.vcpu_count,
-                    entry.mem_size_mib,
-                    entry.created_at,
-                )
+            .filter(|(_, entry)| {
+                selector
+                    .iter()
+                    .all(|(key, value)| entry.labels.get(key) == Some(value))
             })
+            .map(|(id, entry)| listing(*id, entry))
             .collect()
     }
 
+    /// Everything known about a VM, or `None` if it isn't registered
+    pub async fn details(&self, id: &VmId) -> Option<VmDetails> {
+        let vms = self.vms.read().await;
+        let entry = vms.get(id)?;
+        Some(VmDetails {
+            listing: listing(*id, entry),
+            vsock_uds_path: entry.vsock_uds_path.clone(),
+            guest_cid: entry.guest_cid,
+            balloon: entry.balloon,
+            boot_config: entry.boot_config.clone(),
+        })
+    }
+
     /// Get the count of registered VMs
     #[allow(dead_code)]
     pub async fn count(&self) -> usize {
@@ -123,6 +194,211 @@ impl VmRegistry {
             .map(|(id, _)| *id)
     }
 
+    /// Find a VM and its friendly name by IP address, for labelling proxy events
+    pub async fn find_named_by_ip(&self, ip: IpAddr) -> Option<(VmId, Option<String>)> {
+        let vms = self.vms.read().await;
+        vms.iter()
+            .find(|(_, entry)| entry.ip_address == ip)
+            .map(|(id, entry)| (*id, entry.name.clone()))
+    }
+
+    /// Find a VM by its friendly name
+    pub async fn find_by_name(&self, name: &str) -> Option<VmId> {
+        let vms = self.vms.read().await;
+        vms.iter()
+            .find(|(_, entry)| entry.name.as_deref() == Some(name))
+            .map(|(id, _)| *id)
+    }
+
+    /// Check whether a registered VM already uses `name`
+    pub async fn name_in_use(&self, name: &str) -> bool {
+        let vms = self.vms.read().await;
+        vms.values()
+            .any(|entry| entry.name.as_deref() == Some(name))
+    }
+
+    /// Give a running VM a new name and labels, e.g. when handing out a
+    /// pooled VM. Returns its IP and API socket, `None` if it is no longer
+    /// registered or running, or an error if another VM has `name`.
+    pub async fn assign(
+        &self,
+        id: &VmId,
+        name: Option<String>,
+        labels: BTreeMap<String, String>,
+    ) -> Result<Option<(IpAddr, PathBuf)>> {
+        let mut vms = self.vms.write().await;
+        if let Some(name) = &name {
+            if vms
+                .iter()
+                .any(|(other, entry)| other != id && entry.name.as_ref() == Some(name))
+            {
+                return Err(anyhow!("VM with name {name} already exists"));
+            }
+        }
+        let Some(entry) = vms.get_mut(id) else {
+            return Ok(None);
+        };
+        if entry.manager.state() != VmState::Running {
+            return Ok(None);
+        }
+        entry.name = name;
+        entry.labels = labels;
+        Ok(Some((
+            entry.ip_address,
+            entry.manager.socket_path().to_path_buf(),
+        )))
+    }
+
+    /// TAP device names and socket paths owned by registered VMs
+    pub async fn owned_resources(&self) -> (HashSet<String>, HashSet<PathBuf>) {
+        let vms = self.vms.read().await;
+        let taps = vms.values().map(|entry| entry.tap_name.clone()).collect();
+        let sockets = vms
+            .values()
+            .flat_map(|entry| {
+                [
+                    entry.manager.socket_path().to_path_buf(),
+                    PathBuf::from(&entry.vsock_uds_path),
+                ]
+            })
+            .collect();
+        (taps, sockets)
+    }
+
+    /// Current lifecycle state of a VM, or `None` if it isn't registered
+    pub async fn state(&self, id: &VmId) -> Option<VmState> {
+        let vms = self.vms.read().await;
+        vms.get(id).map(|entry| entry.manager.state())
+    }
+
+    /// Lifecycle state, Firecracker's own instance state and creation time of
+    /// a VM, or `None` if it isn't registered
+    pub async fn instance_status(
+        &self,
+        id: &VmId,
+    ) -> Option<(VmState, Result<String>, SystemTime)> {
+        let (state, client, created_at) = {
+            let vms = self.vms.read().await;
+            let entry = vms.get(id)?;
+            (
+                entry.manager.state(),
+                entry.manager.api_client(),
+                entry.created_at,
+            )
+        };
+        let instance_state = firecracker_call(client.get_instance_info())
+            .await
+            .map(|info| info.state);
+        Some((state, instance_state, created_at))
+    }
+
+    /// Pause (`paused = true`) or resume a VM
+    pub async fn set_paused(&self, id: &VmId, paused: bool) -> Result<()> {
+        let (action, expected) = if paused {
+            ("pause", VmState::Running)
+        } else {
+            ("resume", VmState::Paused)
+        };
+        let client = {
+            let vms = self.vms.read().await;
+            let entry = vms
+                .get(id)
+                .ok_or_else(|| anyhow!("VM with ID {id} not found"))?;
+            let state = entry.manager.state();
+            if state != expected {
+                return Err(anyhow!("Cannot {action} a VM in state {state}"));
+            }
+            entry.manager.api_client()
+        };
+        firecracker_call(async {
+            if paused {
+                client.pause().await
+            } else {
+                client.resume().await
+            }
+        })
+        .await?;
+
+        let mut vms = self.vms.write().await;
+        let entry = vms
+            .get_mut(id)
+            .ok_or_else(|| anyhow!("VM with ID {id} was removed during {action}"))?;
+        entry.manager.mark_paused(paused)
+    }
+
+    /// Memory size of a VM and whether it has a balloon, or `None` if it isn't registered
+    pub async fn memory(&self, id: &VmId) -> Option<(u32, bool)> {
+        let vms = self.vms.read().await;
+        vms.get(id).map(|entry| (entry.mem_size_mib, entry.balloon))
+    }
+
+    /// Resize a VM's memory balloon
+    pub async fn update_balloon(&self, id: &VmId, target_mib: u32) -> Result<()> {
+        let client = {
+            let vms = self.vms.read().await;
+            let entry = vms
+                .get(id)
+                .ok_or_else(|| anyhow!("VM with ID {id} not found"))?;
+            let state = entry.manager.state();
+            if !matches!(state, VmState::Running | VmState::Paused) {
+                return Err(anyhow!(
+                    "Cannot resize the balloon of a VM in state {state}"
+                ));
+            }
+            entry.manager.api_client()
+        };
+        firecracker_call(client.update_balloon(target_mib)).await
+    }
+
+    /// Take a VM's manager and boot configuration out for a restart.
+    ///
+    /// An idle manager on the same socket is left in its place, so the entry
+    /// (and proxy lookups by its IP) stay valid while the VM reboots. Hand
+    /// the manager back with [`finish_restart`](Self::finish_restart).
+    pub async fn begin_restart(&self, id: &VmId) -> Result<(VmManager, VmConfig)> {
+        let mut vms = self.vms.write().await;
+        let entry = vms
+            .get_mut(id)
+            .ok_or_else(|| anyhow!("VM with ID {id} not found"))?;
+        if !matches!(
+            entry.manager.state(),
+            VmState::Running | VmState::Paused | VmState::Error
+        ) {
+            return Err(anyhow!(
+                "Cannot restart a VM in state {}",
+                entry.manager.state()
+            ));
+        }
+        let config = entry.boot_config.clone().ok_or_else(|| {
+            anyhow!("VM was restored from a snapshot and has no boot configuration")
+        })?;
+        let idle = VmManager::new(entry.manager.socket_path().to_path_buf());
+        Ok((std::mem::replace(&mut entry.manager, idle), config))
+    }
+
+    /// Put a manager taken by [`begin_restart`](Self::begin_restart) back.
+    /// Returns it again if the VM was deleted in the meantime.
+    pub async fn finish_restart(&self, id: &VmId, manager: VmManager) -> Option<VmManager> {
+        let mut vms = self.vms.write().await;
+        match vms.get_mut(id) {
+            Some(entry) => {
+                entry.manager = manager;
+                None
+            }
+            None => Some(manager),
+        }
+    }
+
+    /// Subscribe to a VM's serial console, or `None` if it has no live
+    /// console (its output goes to a serial log file, or it isn't running)
+    pub async fn console_stream(&self, id: &VmId) -> Result<Option<broadcast::Receiver<String>>> {
+        let vms = self.vms.read().await;
+        let entry = vms
+            .get(id)
+            .ok_or_else(|| anyhow!("VM with ID {id} not found"))?;
+        Ok(entry.manager.console_stream())
+    }
+
     /// Get the vsock UDS path for a VM
     pub async fn get_vsock_path(&self, id: &VmId) -> Result<String> {
         let vms = self.vms.read().await;
@@ -139,10 +415,23 @@ impl Default for VmRegistry {
     }
 }
 
+fn listing(id: VmId, entry: &VmEntry) -> VmListing {
+    (
+        id,
+        entry.ip_address,
+        entry.tap_name.clone(),
+        entry.vcpu_count,
+        entry.mem_size_mib,
+        entry.created_at,
+        entry.manager.state(),
+        entry.name.clone(),
+        entry.labels.clone(),
+    )
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use std::path::PathBuf;
 
     #[tokio::test]
     async fn test_insert_and_get() {
@@ -158,12 +447,23 @@ mod tests {
             vcpu_count: 2,
             mem_size_mib: 512,
             vsock_uds_path: "/tmp/test-vsock.sock".to_string(),
+            guest_cid: 3,
+            name: None,
+            balloon: false,
+            labels: BTreeMap::new(),
+            boot_config: None,
         };
 
         registry.insert(id, entry).await.unwrap();
 
         assert_eq!(registry.count().await, 1);
         assert!(registry.get(&id).await.is_ok());
+
+        let details = registry.details(&id).await.unwrap();
+        assert_eq!(details.listing.2, "tap-test");
+        assert_eq!(details.vsock_uds_path, "/tmp/test-vsock.sock");
+        assert_eq!(details.guest_cid, 3);
+        assert!(registry.details(&Uuid::new_v4()).await.is_none());
     }
 
     #[tokio::test]
@@ -180,6 +480,11 @@ mod tests {
             vcpu_count: 2,
             mem_size_mib: 512,
             vsock_uds_path: "/tmp/test-vsock.sock".to_string(),
+            guest_cid: 3,
+            name: None,
+            balloon: false,
+            labels: BTreeMap::new(),
+            boot_config: None,
         };
 
         registry.insert(id, entry).await.unwrap();
@@ -204,6 +509,11 @@ mod tests {
                 vcpu_count: 1,
                 mem_size_mib: 256,
                 vsock_uds_path: format!("/tmp/test-{}-vsock.sock", i),
+                guest_cid: 3 + i,
+                name: None,
+                balloon: false,
+                labels: BTreeMap::new(),
+                boot_config: None,
             };
             registry.insert(id, entry).await.unwrap();
         }
@@ -211,4 +521,245 @@ mod tests {
         let list = registry.list().await;
         assert_eq!(list.len(), 3);
     }
+
+    #[tokio::test]
+    async fn test_owned_resources() {
+        let registry = VmRegistry::new();
+        let id = Uuid::new_v4();
+
+        let entry = VmEntry {
+            id,
+            manager: VmManager::new(PathBuf::from("/tmp/test.sock")),
+            ip_address: "192.168.100.2".parse().unwrap(),
+            tap_name: "tap-test".to_string(),
+            created_at: SystemTime::now(),
+            vcpu_count: 1,
+            mem_size_mib: 256,
+            vsock_uds_path: "/tmp/test-vsock.sock".to_string(),
+            guest_cid: 3,
+            name: None,
+            balloon: false,
+            labels: BTreeMap::new(),
+            boot_config: None,
+        };
+        registry.insert(id, entry).await.unwrap();
+
+        let (taps, sockets) = registry.owned_resources().await;
+        assert!(taps.contains("tap-test"));
+        assert!(sockets.contains(&PathBuf::from("/tmp/test.sock")));
+        assert!(sockets.contains(&PathBuf::from("/tmp/test-vsock.sock")));
+    }
+
+    #[tokio::test]
+    async fn test_names() {
+        let registry = VmRegistry::new();
+        let entry = |id, ip: &str, name: &str| VmEntry {
+            id,
+            manager: VmManager::new(PathBuf::from(format!("/tmp/test-{name}.sock"))),
+            ip_address: ip.parse().unwrap(),
+            tap_name: format!("tap-{name}"),
+            created_at: SystemTime::now(),
+            vcpu_count: 1,
+            mem_size_mib: 256,
+            vsock_uds_path: format!("/tmp/test-{name}-vsock.sock"),
+            guest_cid: 3,
+            name: Some(name.to_string()),
+            balloon: false,
+            labels: BTreeMap::new(),
+            boot_config: None,
+        };
+
+        let id = Uuid::new_v4();
+        registry
+            .insert(id, entry(id, "192.168.100.2", "build-vm-3"))
+            .await
+            .unwrap();
+        assert!(registry.name_in_use("build-vm-3").await);
+        assert_eq!(registry.find_by_name("build-vm-3").await, Some(id));
+        assert_eq!(registry.find_by_name("build-vm-4").await, None);
+        assert_eq!(
+            registry
+                .find_named_by_ip("192.168.100.2".parse().unwrap())
+                .await,
+            Some((id, Some("build-vm-3".to_string())))
+        );
+
+        let dup = Uuid::new_v4();
+        assert!(registry
+            .insert(dup, entry(dup, "192.168.100.3", "build-vm-3"))
+            .await
+            .is_err());
+
+        // Assigning checks the name against other VMs, and only hands out
+        // running VMs (this one was never started)
+        let other = Uuid::new_v4();
+        registry
+            .insert(other, entry(other, "192.168.100.4", "pooled"))
+            .await
+            .unwrap();
+        let labels = BTreeMap::from([("tier".to_string(), "frontend".to_string())]);
+        assert!(registry
+            .assign(&other, Some("build-vm-3".to_string()), labels.clone())
+            .await
+            .is_err());
+        assert!(registry
+            .assign(&other, Some("pooled".to_string()), labels.clone())
+            .await
+            .unwrap()
+            .is_none());
+        assert!(registry
+            .assign(&Uuid::new_v4(), None, labels)
+            .await
+            .unwrap()
+            .is_none());
+    }
+
+    #[tokio::test]
+    async fn test_list_filtered_by_labels() {
+        let registry = VmRegistry::new();
+        for (i, labels) in [
+            &[("tier", "frontend"), ("env", "prod")][..],
+            &[("tier", "frontend"), ("env", "staging")][..],
+            &[("tier", "backend")][..],
+        ]
+        .into_iter()
+        .enumerate()
+        {
+            let id = Uuid::new_v4();
+            let entry = VmEntry {
+                id,
+                manager: VmManager::new(PathBuf::from(format!("/tmp/test-label-{i}.sock"))),
+                ip_address: format!("192.168.100.{}", i + 2).parse().unwrap(),
+                tap_name: format!("tap-label-{i}"),
+                created_at: SystemTime::now(),
+                vcpu_count: 1,
+                mem_size_mib: 256,
+                vsock_uds_path: format!("/tmp/test-label-{i}-vsock.sock"),
+                guest_cid: 3,
+                name: None,
+                balloon: false,
+                labels: labels
+                    .iter()
+                    .map(|(k, v)| ((*k).to_string(), (*v).to_string()))
+                    .collect(),
+                boot_config: None,
+            };
+            registry.insert(id, entry).await.unwrap();
+        }
+
+        let selector = |pairs: &[(&str, &str)]| -> BTreeMap<String, String> {
+            pairs
+                .iter()
+                .map(|(k, v)| ((*k).to_string(), (*v).to_string()))
+                .collect()
+        };
+        assert_eq!(registry.list_filtered(&selector(&[])).await.len(), 3);
+        assert_eq!(
+            registry
+                .list_filtered(&selector(&[("tier", "frontend")]))
+                .await
+                .len(),
+            2
+        );
+        let prod = registry
+            .list_filtered(&selector(&[("tier", "frontend"), ("env", "prod")]))
+            .await;
+        assert_eq!(prod.len(), 1);
+        assert_eq!(prod[0].8.get("env").map(String::as_str), Some("prod"));
+        assert!(registry
+            .list_filtered(&selector(&[("tier", "db")]))
+            .await
+            .is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_firecracker_calls_do_not_hold_the_lock() {
+        let registry = Arc::new(VmRegistry::new());
+        let id = Uuid::new_v4();
+        let socket = std::env::temp_dir().join(format!("clawpot-test-{id}.sock"));
+        // Accepts connections but never answers, like a wedged Firecracker
+        let _listener = tokio::net::UnixListener::bind(&socket).unwrap();
+        let entry = VmEntry {
+            id,
+            manager: VmManager::new(socket.clone()),
+            ip_address: "192.168.100.2".parse().unwrap(),
+            tap_name: "tap-wedged".to_string(),
+            created_at: SystemTime::now(),
+            vcpu_count: 1,
+            mem_size_mib: 256,
+            vsock_uds_path: "/tmp/test-wedged-vsock.sock".to_string(),
+            guest_cid: 3,
+            name: None,
+            balloon: false,
+            labels: BTreeMap::new(),
+            boot_config: None,
+        };
+        registry.insert(id, entry).await.unwrap();
+
+        let status = tokio::spawn({
+            let registry = registry.clone();
+            async move { registry.instance_status(&id).await }
+        });
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        // Writers get through while the call waits on Firecracker
+        tokio::time::timeout(Duration::from_secs(1), registry.remove(&id))
+            .await
+            .unwrap()
+            .unwrap();
+        status.abort();
+        std::fs::remove_file(&socket).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_restart_keeps_entry() {
+        let registry = VmRegistry::new();
+        let id = Uuid::new_v4();
+        let ip: IpAddr = "192.168.100.2".parse().unwrap();
+        let mut manager = VmManager::new(PathBuf::from("/tmp/test-restart.sock"));
+        manager.mark_failed();
+        let entry = VmEntry {
+            id,
+            manager,
+            ip_address: ip,
+            tap_name: "tap-restart".to_string(),
+            created_at: SystemTime::now(),
+            vcpu_count: 1,
+            mem_size_mib: 256,
+            vsock_uds_path: "/tmp/test-restart-vsock.sock".to_string(),
+            guest_cid: 3,
+            name: None,
+            balloon: false,
+            labels: BTreeMap::new(),
+            boot_config: Some(VmConfig::new(
+                PathBuf::from("/tmp/kernel"),
+                PathBuf::from("/tmp/rootfs"),
+            )),
+        };
+        registry.insert(id, entry).await.unwrap();
+
+        let (mut manager, config) = registry.begin_restart(&id).await.unwrap();
+        // Still resolvable while rebooting, but can't be restarted twice
+        assert_eq!(registry.find_by_ip(ip).await, Some(id));
+        assert_eq!(registry.state(&id).await, Some(VmState::NotStarted));
+        assert!(registry.begin_restart(&id).await.is_err());
+
+        // The failed VM stops; the boot then fails on the invalid config
+        assert!(manager.restart(config.with_vcpus(0)).await.is_err());
+        assert!(registry.finish_restart(&id, manager).await.is_none());
+        assert_eq!(registry.state(&id).await, Some(VmState::Stopped));
+        assert_eq!(registry.find_by_ip(ip).await, Some(id));
+
+        // A VM deleted mid-restart hands the manager back
+        registry
+            .vms
+            .write()
+            .await
+            .get_mut(&id)
+            .unwrap()
+            .manager
+            .mark_failed();
+        let (manager, _config) = registry.begin_restart(&id).await.unwrap();
+        registry.remove(&id).await.unwrap();
+        assert!(registry.finish_restart(&id, manager).await.is_some());
+    }
 }
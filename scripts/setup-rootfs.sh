@@ -14,6 +14,10 @@ PROJECT_ROOT="$(dirname "$SCRIPT_DIR")"
 ROOTFS_PATH="$PROJECT_ROOT/assets/rootfs/ubuntu.ext4"
 AGENT_BINARY="$PROJECT_ROOT/target/x86_64-unknown-linux-musl/release/clawpot-agent"
 CA_CERT="$PROJECT_ROOT/ca/ca.crt"
+# Written by the server; also holds CAs kept trusted during a rotation
+if [ -f "$PROJECT_ROOT/ca/trust-bundle.crt" ]; then
+    CA_CERT="$PROJECT_ROOT/ca/trust-bundle.crt"
+fi
 MOUNT_POINT="/tmp/clawpot-rootfs-mount"
 
 # Verify prerequisites